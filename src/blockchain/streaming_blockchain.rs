@@ -8,9 +8,11 @@
 //! 4. Streams vector data to remote vector databases via btrfs send/receive
 
 use crate::blockchain::PluginFootprint;
+use crate::cache::BtrfsCache;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::process::Command;
 use tokio::sync::RwLock;
@@ -173,6 +175,8 @@ pub struct StreamingBlockchain {
     snapshot_interval: SnapshotInterval,
     retention_policy: RetentionPolicy,
     last_snapshot_time: Arc<RwLock<Instant>>,
+    height: Arc<AtomicU64>,
+    block_cache: Option<Arc<BtrfsCache>>, // Indexed copy of every block for lookups
 }
 
 impl StreamingBlockchain {
@@ -194,6 +198,10 @@ impl StreamingBlockchain {
         Self::create_subvolume(&vector_subvol).await?;
         Self::create_subvolume(&state_subvol).await?;
 
+        let height = Self::count_blocks(&timing_subvol).await;
+        crate::metrics::record_blockchain_height(height);
+        let block_cache = Self::open_block_cache().await;
+
         Ok(Self {
             base_path,
             timing_subvol,
@@ -202,9 +210,50 @@ impl StreamingBlockchain {
             snapshot_interval,
             retention_policy: RetentionPolicy::from_env(),
             last_snapshot_time: Arc::new(RwLock::new(Instant::now())),
+            height: Arc::new(AtomicU64::new(height)),
+            block_cache,
         })
     }
 
+    /// Use the block cache at the default location when it has been set up
+    async fn open_block_cache() -> Option<Arc<BtrfsCache>> {
+        let cache_dir = BtrfsCache::default_dir();
+        if !cache_dir.exists() {
+            return None;
+        }
+        match BtrfsCache::new(cache_dir).await {
+            Ok(cache) => Some(Arc::new(cache)),
+            Err(e) => {
+                warn!("Block cache unavailable: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Index new blocks in `cache` instead of the default one
+    pub fn with_block_cache(mut self, cache: Arc<BtrfsCache>) -> Self {
+        self.block_cache = Some(cache);
+        self
+    }
+
+    /// Count blocks already written to the timing subvolume
+    async fn count_blocks(timing_subvol: &Path) -> u64 {
+        let mut count = 0;
+        if let Ok(mut entries) = tokio::fs::read_dir(timing_subvol).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                if entry.path().extension().is_some_and(|ext| ext == "json") {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /// Number of blocks in the chain
+    pub fn height(&self) -> u64 {
+        self.height.load(Ordering::Relaxed)
+    }
+
     async fn create_subvolume(path: &Path) -> Result<()> {
         if !path.exists() {
             let output = Command::new("btrfs")
@@ -248,7 +297,8 @@ impl StreamingBlockchain {
             "data": event.data,
             "plugin_footprint": true
         });
-        tokio::fs::write(&timing_file, serde_json::to_string_pretty(&timing_data)?).await?;
+        let block = serde_json::to_string_pretty(&timing_data)?;
+        tokio::fs::write(&timing_file, &block).await?;
 
         let vector_file = self.vector_subvol.join(format!("{}.vec", event.hash));
         let vector_data = serde_json::json!({
@@ -264,6 +314,16 @@ impl StreamingBlockchain {
         });
         tokio::fs::write(&vector_file, serde_json::to_string(&vector_data)?).await?;
 
+        let height = self.height.fetch_add(1, Ordering::Relaxed) + 1;
+        crate::metrics::record_blockchain_height(height);
+
+        if let Some(cache) = &self.block_cache {
+            // The timing file is the block of record; a failed cache write only costs lookups
+            if let Err(e) = cache.put_block(&event.hash, Some(height), block.as_bytes()) {
+                warn!("Failed to cache block {}: {}", event.hash, e);
+            }
+        }
+
         // Only create snapshot if interval requires it
         self.create_snapshot_if_needed(&event.hash).await?;
         info!("Plugin footprint added with hash: {}", event.hash);
@@ -6,6 +6,7 @@
 //! - Linux page cache for hot data
 //! - Automatic snapshot management
 //! - NUMA-aware memory allocation and CPU affinity
+//! - Size-bounded LRU/LFU eviction with per-namespace quotas
 
 use anyhow::{Context, Result};
 use rusqlite::OptionalExtension;
@@ -16,8 +17,11 @@ use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Mutex,
 };
+use std::time::Instant;
 use tracing::{debug, info, warn};
 
+use super::eviction::{self, CacheBudget, CacheNamespace, EvictionStats};
+use super::numa::NumaStats;
 use super::snapshot_manager::{SnapshotConfig, SnapshotManager};
 
 /// NUMA node information and CPU mapping
@@ -59,11 +63,13 @@ pub struct BtrfsCache {
     cache_dir: PathBuf,
     index: Mutex<rusqlite::Connection>,
     snapshot_manager: SnapshotManager,
+    budget: CacheBudget,
     numa_nodes: Vec<NumaNode>,
     placement_strategy: CachePlacementStrategy,
     memory_policy: MemoryPolicy,
     cpu_affinity: Vec<u32>, // CPU cores for affinity binding
     current_node_index: AtomicUsize,
+    numa_stats: Mutex<NumaStats>,
 }
 
 #[allow(dead_code)]
@@ -113,6 +119,13 @@ impl BtrfsCache {
         Ok(())
     }
 
+    /// Cache directory from `OPDBUS_CACHE_DIR`, defaulting to `/var/lib/op-dbus/@cache`
+    pub fn default_dir() -> PathBuf {
+        std::env::var("OPDBUS_CACHE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("/var/lib/op-dbus/@cache"))
+    }
+
     /// Create new BTRFS cache with proper subvolumes
     pub async fn new(cache_dir: PathBuf) -> Result<Self> {
         // Ensure parent directory exists (not as subvolume)
@@ -164,6 +177,9 @@ impl BtrfsCache {
             [],
         )?;
 
+        // Block tracking and eviction counters
+        eviction::init_tables(&index)?;
+
         // Initialize snapshot manager
         let snapshot_config = SnapshotConfig {
             snapshot_dir: cache_dir
@@ -206,14 +222,27 @@ impl BtrfsCache {
             cache_dir,
             index: Mutex::new(index),
             snapshot_manager,
+            budget: CacheBudget::from_env(),
             numa_nodes,
             placement_strategy,
             memory_policy,
             cpu_affinity,
             current_node_index: AtomicUsize::new(0),
+            numa_stats: Mutex::new(NumaStats::new()),
         })
     }
 
+    /// Replace the size budget (defaults to `CacheBudget::from_env()`)
+    pub fn with_budget(mut self, budget: CacheBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Current size budget
+    pub fn budget(&self) -> &CacheBudget {
+        &self.budget
+    }
+
     fn determine_placement_strategy(numa_nodes: &[NumaNode]) -> CachePlacementStrategy {
         let default_choice = if numa_nodes.is_empty() {
             "disabled".to_string()
@@ -404,6 +433,7 @@ impl BtrfsCache {
     }
 
     fn load_embedding(&self, text_hash: &str) -> Result<Option<Vec<f32>>> {
+        let started = Instant::now();
         let index = self.index.lock().unwrap();
 
         // Lookup in SQLite index
@@ -417,6 +447,10 @@ impl BtrfsCache {
 
         drop(index); // Release lock before file I/O
 
+        crate::metrics::record_cache_lookup(
+            CacheNamespace::Embeddings.as_str(),
+            vector_file.is_some(),
+        );
         if let Some(file) = vector_file {
             let path = self.cache_dir.join("embeddings/vectors").join(&file);
 
@@ -427,6 +461,7 @@ impl BtrfsCache {
             let vector: Vec<f32> =
                 bincode::deserialize(&data).context("Failed to deserialize cached embedding")?;
 
+            self.record_numa_access("cache_lookup", started);
             return Ok(Some(vector));
         }
 
@@ -455,10 +490,145 @@ impl BtrfsCache {
                 access_count = access_count + 1",
             rusqlite::params![text_hash, text, vector_file, now, now, vector.len()],
         )?;
+        drop(index);
+
+        self.enforce_budget()?;
 
         Ok(())
     }
 
+    /// Store a block under its hash (and optionally its number)
+    pub fn put_block(
+        &self,
+        block_hash: &str,
+        block_number: Option<u64>,
+        data: &[u8],
+    ) -> Result<()> {
+        validate_block_hash(block_hash)?;
+        let by_hash_dir = self.cache_dir.join("blocks/by-hash");
+        std::fs::create_dir_all(&by_hash_dir)?;
+
+        let block_file = format!("by-hash/{}.blk", block_hash);
+        std::fs::write(self.cache_dir.join("blocks").join(&block_file), data)?;
+
+        let index = self.index.lock().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        index.execute(
+            "INSERT INTO blocks (block_hash, block_number, block_file, created_at, accessed_at, size_bytes)
+             VALUES (?1, ?2, ?3, ?4, ?4, ?5)
+             ON CONFLICT(block_hash) DO UPDATE SET
+                block_number = COALESCE(?2, block_number),
+                accessed_at = ?4,
+                access_count = access_count + 1,
+                size_bytes = ?5",
+            rusqlite::params![
+                block_hash,
+                block_number.map(|n| n as i64),
+                block_file,
+                now,
+                data.len() as i64
+            ],
+        )?;
+        drop(index);
+
+        self.enforce_budget()?;
+
+        Ok(())
+    }
+
+    /// Load a cached block by hash
+    pub fn get_block(&self, block_hash: &str) -> Result<Option<Vec<u8>>> {
+        validate_block_hash(block_hash)?;
+        self.load_block("block_hash = ?1", rusqlite::params![block_hash])
+    }
+
+    /// Load a cached block by number
+    pub fn get_block_by_number(&self, block_number: u64) -> Result<Option<Vec<u8>>> {
+        self.load_block("block_number = ?1", rusqlite::params![block_number as i64])
+    }
+
+    fn load_block(&self, filter: &str, params: impl rusqlite::Params) -> Result<Option<Vec<u8>>> {
+        let started = Instant::now();
+        let index = self.index.lock().unwrap();
+        let row: Option<(String, String)> = index
+            .query_row(
+                &format!("SELECT block_hash, block_file FROM blocks WHERE {}", filter),
+                params,
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        crate::metrics::record_cache_lookup(CacheNamespace::Blocks.as_str(), row.is_some());
+        let Some((block_hash, block_file)) = row else {
+            return Ok(None);
+        };
+
+        index.execute(
+            "UPDATE blocks
+             SET accessed_at = ?1, access_count = access_count + 1
+             WHERE block_hash = ?2",
+            rusqlite::params![chrono::Utc::now().timestamp(), block_hash],
+        )?;
+        drop(index); // Release lock before file I/O
+
+        let path = self.cache_dir.join("blocks").join(&block_file);
+        let data =
+            std::fs::read(&path).context(format!("Failed to read cached block: {:?}", path))?;
+        self.record_numa_access("cache_lookup", started);
+        Ok(Some(data))
+    }
+
+    /// Count a cache read as local when it ran on the node cache work is pinned to
+    fn record_numa_access(&self, operation: &str, started: Instant) {
+        let local = match (self.select_numa_node(operation), self.numa_nodes.first()) {
+            (Some(node), Some(primary)) => node.node_id == primary.node_id,
+            _ => true,
+        };
+        let latency_ns = started.elapsed().as_nanos() as u64;
+
+        let mut stats = self.numa_stats.lock().unwrap();
+        if local {
+            stats.record_local_access(latency_ns);
+        } else {
+            stats.record_remote_access(latency_ns);
+        }
+    }
+
+    /// Evict entries until the cache fits its budget, returning the number removed
+    pub fn enforce_budget(&self) -> Result<usize> {
+        if self.budget.is_unlimited() {
+            return Ok(0);
+        }
+
+        let index = self.index.lock().unwrap();
+        let victims = eviction::select_victims(&index, &self.budget)?;
+        if victims.is_empty() {
+            return Ok(0);
+        }
+        eviction::record_evictions(&index, &victims)?;
+        drop(index); // Release lock before file I/O
+
+        for victim in &victims {
+            let path = self
+                .cache_dir
+                .join(victim.namespace.data_dir())
+                .join(&victim.file);
+            if let Err(e) = std::fs::remove_file(&path) {
+                debug!("Evicted file already gone {}: {}", path.display(), e);
+            }
+        }
+
+        let freed: u64 = victims.iter().map(|v| v.size_bytes).sum();
+        info!(
+            "Evicted {} cache entries ({} bytes, policy={:?})",
+            victims.len(),
+            freed,
+            self.budget.policy
+        );
+
+        Ok(victims.len())
+    }
+
     fn update_access(&self, text_hash: &str) -> Result<()> {
         let index = self.index.lock().unwrap();
         let now = chrono::Utc::now().timestamp();
@@ -485,10 +655,17 @@ impl BtrfsCache {
             |row| row.get(0),
         )?;
 
-        let total_accesses: i64 =
-            index.query_row("SELECT SUM(access_count) FROM embeddings", [], |row| {
-                row.get(0)
-            })?;
+        let total_accesses: i64 = index.query_row(
+            "SELECT COALESCE(SUM(access_count), 0) FROM embeddings",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let block_entries: i64 =
+            index.query_row("SELECT COUNT(*) FROM blocks", [], |row| row.get(0))?;
+
+        let embedding_evictions = eviction::load_stats(&index, CacheNamespace::Embeddings)?;
+        let block_evictions = eviction::load_stats(&index, CacheNamespace::Blocks)?;
 
         drop(index); // Release lock before file I/O
 
@@ -504,6 +681,10 @@ impl BtrfsCache {
             disk_usage_bytes: total_size,
             embeddings_size_bytes: embeddings_size,
             blocks_size_bytes: blocks_size,
+            block_entries: block_entries as usize,
+            embedding_evictions,
+            block_evictions,
+            numa: self.numa_stats.lock().unwrap().clone(),
         })
     }
 
@@ -588,6 +769,7 @@ impl BtrfsCache {
         // Clear index
         let index = self.index.lock().unwrap();
         index.execute("DELETE FROM embeddings", [])?;
+        index.execute("DELETE FROM blocks", [])?;
 
         log::info!("Cache cleared");
 
@@ -626,6 +808,9 @@ impl BtrfsCache {
             std::fs::create_dir_all(blocks_dir.join("by-hash"))?;
         }
 
+        let index = self.index.lock().unwrap();
+        index.execute("DELETE FROM blocks", [])?;
+
         log::info!("Blocks cache cleared");
 
         Ok(())
@@ -831,6 +1016,11 @@ pub struct CacheStats {
     pub disk_usage_bytes: u64,
     pub embeddings_size_bytes: u64,
     pub blocks_size_bytes: u64,
+    pub block_entries: usize,
+    pub embedding_evictions: EvictionStats,
+    pub block_evictions: EvictionStats,
+    /// Lookups made by this process, split by NUMA locality
+    pub numa: NumaStats,
 }
 
 impl CacheStats {
@@ -850,6 +1040,14 @@ impl CacheStats {
         }
     }
 }
+/// Block hashes become file names, so only accept hex digests
+fn validate_block_hash(block_hash: &str) -> Result<()> {
+    if block_hash.is_empty() || !block_hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        anyhow::bail!("Invalid block hash {:?}: expected a hex digest", block_hash);
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 /// NUMA configuration information
 pub struct NumaInfo {
@@ -874,4 +1072,12 @@ mod tests {
         assert_ne!(hash1, hash3);
         assert_eq!(hash1.len(), 64); // SHA256 hex length
     }
+
+    #[test]
+    fn test_block_hash_validation() {
+        assert!(validate_block_hash("a1b2c3D4").is_ok());
+        assert!(validate_block_hash("").is_err());
+        assert!(validate_block_hash("../../etc/passwd").is_err());
+        assert!(validate_block_hash("abc/def").is_err());
+    }
 }
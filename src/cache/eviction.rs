@@ -0,0 +1,637 @@
+//! Size-bounded eviction for the BTRFS cache
+//!
+//! The SQLite index already records `accessed_at` and `access_count` for every
+//! entry, so eviction is a matter of ordering those rows and deleting from the
+//! coldest end until the cache fits its budget again.
+//!
+//! Budgets can be set globally (bytes and entries across all namespaces) and
+//! per namespace (embeddings vs. blocks). Eviction totals are persisted in the
+//! index so `op-dbus cache stats` can report them across process restarts.
+
+use anyhow::Result;
+use std::fmt;
+use tracing::warn;
+
+/// Cache namespaces that are tracked in the SQLite index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheNamespace {
+    Embeddings,
+    Blocks,
+}
+
+impl CacheNamespace {
+    pub const ALL: [CacheNamespace; 2] = [CacheNamespace::Embeddings, CacheNamespace::Blocks];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheNamespace::Embeddings => "embeddings",
+            CacheNamespace::Blocks => "blocks",
+        }
+    }
+
+    /// Directory (relative to the cache root) that entry files live in
+    pub fn data_dir(&self) -> &'static str {
+        match self {
+            CacheNamespace::Embeddings => "embeddings/vectors",
+            CacheNamespace::Blocks => "blocks",
+        }
+    }
+
+    /// SELECT returning (namespace, key, file, size_bytes, accessed_at, access_count)
+    ///
+    /// Embedding sizes are derived from the vector length: bincode stores a
+    /// `Vec<f32>` as a u64 length prefix followed by 4 bytes per element.
+    fn select_sql(&self) -> &'static str {
+        match self {
+            CacheNamespace::Embeddings => {
+                "SELECT 'embeddings' AS namespace, text_hash AS key, vector_file AS file,
+                        vector_size * 4 + 8 AS size_bytes, accessed_at, access_count
+                 FROM embeddings"
+            }
+            CacheNamespace::Blocks => {
+                "SELECT 'blocks' AS namespace, block_hash AS key, block_file AS file,
+                        size_bytes, accessed_at, access_count
+                 FROM blocks"
+            }
+        }
+    }
+
+    fn delete_sql(&self) -> &'static str {
+        match self {
+            CacheNamespace::Embeddings => "DELETE FROM embeddings WHERE text_hash = ?1",
+            CacheNamespace::Blocks => "DELETE FROM blocks WHERE block_hash = ?1",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "embeddings" => Some(CacheNamespace::Embeddings),
+            "blocks" => Some(CacheNamespace::Blocks),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CacheNamespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Which entries are evicted first when the cache is over budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Least recently used (oldest `accessed_at` first)
+    #[default]
+    Lru,
+    /// Least frequently used (lowest `access_count` first, ties broken by age)
+    Lfu,
+}
+
+impl EvictionPolicy {
+    fn order_by(&self) -> &'static str {
+        match self {
+            EvictionPolicy::Lru => "accessed_at ASC, access_count ASC",
+            EvictionPolicy::Lfu => "access_count ASC, accessed_at ASC",
+        }
+    }
+}
+
+impl std::str::FromStr for EvictionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "lru" => Ok(EvictionPolicy::Lru),
+            "lfu" => Ok(EvictionPolicy::Lfu),
+            other => anyhow::bail!("Unknown eviction policy '{}' (expected lru or lfu)", other),
+        }
+    }
+}
+
+/// Byte and entry limits; `None` means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_entries: Option<usize>,
+}
+
+impl Quota {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes.is_none() && self.max_entries.is_none()
+    }
+
+    /// How far `usage` exceeds this quota, as (bytes, entries)
+    fn excess(&self, usage: &NamespaceUsage) -> (u64, usize) {
+        let bytes = self
+            .max_bytes
+            .map(|max| usage.bytes.saturating_sub(max))
+            .unwrap_or(0);
+        let entries = self
+            .max_entries
+            .map(|max| usage.entries.saturating_sub(max))
+            .unwrap_or(0);
+        (bytes, entries)
+    }
+}
+
+/// Complete cache budget: a global quota plus per-namespace quotas
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheBudget {
+    pub total: Quota,
+    pub embeddings: Quota,
+    pub blocks: Quota,
+    pub policy: EvictionPolicy,
+}
+
+impl CacheBudget {
+    /// Build a budget from environment variables
+    ///
+    /// - `OPDBUS_CACHE_MAX_BYTES` / `OPDBUS_CACHE_MAX_ENTRIES`
+    /// - `OPDBUS_CACHE_EMBEDDINGS_MAX_BYTES` / `OPDBUS_CACHE_EMBEDDINGS_MAX_ENTRIES`
+    /// - `OPDBUS_CACHE_BLOCKS_MAX_BYTES` / `OPDBUS_CACHE_BLOCKS_MAX_ENTRIES`
+    /// - `OPDBUS_CACHE_EVICTION` (`lru` or `lfu`)
+    ///
+    /// Byte values accept K/M/G/T suffixes (e.g. `512M`, `20G`).
+    pub fn from_env() -> Self {
+        let policy = match std::env::var("OPDBUS_CACHE_EVICTION") {
+            Ok(value) => value.parse().unwrap_or_else(|e| {
+                warn!("{}, using lru", e);
+                EvictionPolicy::Lru
+            }),
+            Err(_) => EvictionPolicy::Lru,
+        };
+
+        Self {
+            total: Self::quota_from_env("OPDBUS_CACHE"),
+            embeddings: Self::quota_from_env("OPDBUS_CACHE_EMBEDDINGS"),
+            blocks: Self::quota_from_env("OPDBUS_CACHE_BLOCKS"),
+            policy,
+        }
+    }
+
+    fn quota_from_env(prefix: &str) -> Quota {
+        let max_bytes = std::env::var(format!("{}_MAX_BYTES", prefix))
+            .ok()
+            .and_then(|value| match parse_byte_size(&value) {
+                Some(bytes) => Some(bytes),
+                None => {
+                    warn!("Invalid {}_MAX_BYTES value '{}', ignoring", prefix, value);
+                    None
+                }
+            });
+        let max_entries = std::env::var(format!("{}_MAX_ENTRIES", prefix))
+            .ok()
+            .and_then(|value| match value.trim().parse() {
+                Ok(entries) => Some(entries),
+                Err(_) => {
+                    warn!("Invalid {}_MAX_ENTRIES value '{}', ignoring", prefix, value);
+                    None
+                }
+            });
+        Quota {
+            max_bytes,
+            max_entries,
+        }
+    }
+
+    pub fn quota(&self, namespace: CacheNamespace) -> &Quota {
+        match namespace {
+            CacheNamespace::Embeddings => &self.embeddings,
+            CacheNamespace::Blocks => &self.blocks,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.total.is_unlimited() && self.embeddings.is_unlimited() && self.blocks.is_unlimited()
+    }
+}
+
+/// Parse a byte size such as `1048576`, `512K`, `64M`, `20G` or `1T`
+pub fn parse_byte_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last()? {
+        (idx, 'k' | 'K') => (&value[..idx], 1024u64),
+        (idx, 'm' | 'M') => (&value[..idx], 1024 * 1024),
+        (idx, 'g' | 'G') => (&value[..idx], 1024 * 1024 * 1024),
+        (idx, 't' | 'T') => (&value[..idx], 1024 * 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Current usage of one namespace
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NamespaceUsage {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+impl std::ops::Add for NamespaceUsage {
+    type Output = NamespaceUsage;
+
+    fn add(self, other: NamespaceUsage) -> NamespaceUsage {
+        NamespaceUsage {
+            entries: self.entries + other.entries,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
+/// Cumulative eviction counters for one namespace
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvictionStats {
+    pub evicted_entries: u64,
+    pub evicted_bytes: u64,
+    /// Unix timestamp of the last eviction run that removed something
+    pub last_eviction_at: Option<i64>,
+}
+
+/// An index row chosen for eviction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvictionCandidate {
+    pub namespace: CacheNamespace,
+    pub key: String,
+    pub file: String,
+    pub size_bytes: u64,
+}
+
+/// Create the tables used for block tracking and eviction accounting
+pub(crate) fn init_tables(index: &rusqlite::Connection) -> Result<()> {
+    index.execute(
+        "CREATE TABLE IF NOT EXISTS blocks (
+            block_hash TEXT PRIMARY KEY,
+            block_number INTEGER,
+            block_file TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            accessed_at INTEGER NOT NULL,
+            access_count INTEGER NOT NULL DEFAULT 1,
+            size_bytes INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    index.execute(
+        "CREATE INDEX IF NOT EXISTS idx_blocks_number
+         ON blocks(block_number)",
+        [],
+    )?;
+
+    index.execute(
+        "CREATE TABLE IF NOT EXISTS eviction_stats (
+            namespace TEXT PRIMARY KEY,
+            evicted_entries INTEGER NOT NULL DEFAULT 0,
+            evicted_bytes INTEGER NOT NULL DEFAULT 0,
+            last_eviction_at INTEGER
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Entry count and byte usage for a namespace
+pub(crate) fn usage(
+    index: &rusqlite::Connection,
+    namespace: CacheNamespace,
+) -> Result<NamespaceUsage> {
+    let sql = format!(
+        "SELECT COUNT(*), COALESCE(SUM(size_bytes), 0) FROM ({})",
+        namespace.select_sql()
+    );
+    let (entries, bytes): (i64, i64) =
+        index.query_row(&sql, [], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(NamespaceUsage {
+        entries: entries as usize,
+        bytes: bytes as u64,
+    })
+}
+
+/// Pick the entries that must go to bring the cache back within `budget`
+///
+/// Namespace quotas are satisfied first, then the global quota is applied to
+/// whatever remains, ordering victims across all namespaces by the policy.
+pub(crate) fn select_victims(
+    index: &rusqlite::Connection,
+    budget: &CacheBudget,
+) -> Result<Vec<EvictionCandidate>> {
+    let mut victims = Vec::new();
+    let mut total = NamespaceUsage::default();
+
+    for namespace in CacheNamespace::ALL {
+        let mut ns_usage = usage(index, namespace)?;
+        let (excess_bytes, excess_entries) = budget.quota(namespace).excess(&ns_usage);
+
+        if excess_bytes > 0 || excess_entries > 0 {
+            let chosen = coldest(
+                index,
+                namespace.select_sql(),
+                budget.policy,
+                excess_bytes,
+                excess_entries,
+                &victims,
+            )?;
+            for victim in &chosen {
+                ns_usage.entries -= 1;
+                ns_usage.bytes = ns_usage.bytes.saturating_sub(victim.size_bytes);
+            }
+            victims.extend(chosen);
+        }
+
+        total = total + ns_usage;
+    }
+
+    let (excess_bytes, excess_entries) = budget.total.excess(&total);
+    if excess_bytes > 0 || excess_entries > 0 {
+        let union = CacheNamespace::ALL
+            .iter()
+            .map(|ns| ns.select_sql())
+            .collect::<Vec<_>>()
+            .join(" UNION ALL ");
+        let chosen = coldest(
+            index,
+            &union,
+            budget.policy,
+            excess_bytes,
+            excess_entries,
+            &victims,
+        )?;
+        victims.extend(chosen);
+    }
+
+    Ok(victims)
+}
+
+/// Walk rows from the cold end until enough bytes and entries are freed
+fn coldest(
+    index: &rusqlite::Connection,
+    select: &str,
+    policy: EvictionPolicy,
+    bytes_needed: u64,
+    entries_needed: usize,
+    already_chosen: &[EvictionCandidate],
+) -> Result<Vec<EvictionCandidate>> {
+    let sql = format!("{} ORDER BY {}", select, policy.order_by());
+    let mut stmt = index.prepare(&sql)?;
+    let mut rows = stmt.query([])?;
+
+    let mut chosen = Vec::new();
+    let mut freed_bytes = 0u64;
+
+    while freed_bytes < bytes_needed || chosen.len() < entries_needed {
+        let Some(row) = rows.next()? else {
+            break;
+        };
+        let namespace: String = row.get(0)?;
+        let Some(namespace) = CacheNamespace::parse(&namespace) else {
+            continue;
+        };
+        let candidate = EvictionCandidate {
+            namespace,
+            key: row.get(1)?,
+            file: row.get(2)?,
+            size_bytes: row.get::<_, i64>(3)? as u64,
+        };
+
+        if already_chosen
+            .iter()
+            .any(|c| c.namespace == candidate.namespace && c.key == candidate.key)
+        {
+            continue;
+        }
+
+        freed_bytes += candidate.size_bytes;
+        chosen.push(candidate);
+    }
+
+    Ok(chosen)
+}
+
+/// Remove evicted rows from the index and add them to the eviction counters
+pub(crate) fn record_evictions(
+    index: &rusqlite::Connection,
+    victims: &[EvictionCandidate],
+) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+
+    for victim in victims {
+        index.execute(victim.namespace.delete_sql(), [&victim.key])?;
+    }
+
+    for namespace in CacheNamespace::ALL {
+        let (count, bytes) = victims
+            .iter()
+            .filter(|v| v.namespace == namespace)
+            .fold((0i64, 0i64), |(c, b), v| (c + 1, b + v.size_bytes as i64));
+        if count == 0 {
+            continue;
+        }
+        index.execute(
+            "INSERT INTO eviction_stats (namespace, evicted_entries, evicted_bytes, last_eviction_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(namespace) DO UPDATE SET
+                evicted_entries = evicted_entries + ?2,
+                evicted_bytes = evicted_bytes + ?3,
+                last_eviction_at = ?4",
+            rusqlite::params![namespace.as_str(), count, bytes, now],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Load the persisted eviction counters for a namespace
+pub(crate) fn load_stats(
+    index: &rusqlite::Connection,
+    namespace: CacheNamespace,
+) -> Result<EvictionStats> {
+    use rusqlite::OptionalExtension;
+
+    let stats = index
+        .query_row(
+            "SELECT evicted_entries, evicted_bytes, last_eviction_at
+             FROM eviction_stats WHERE namespace = ?1",
+            [namespace.as_str()],
+            |row| {
+                Ok(EvictionStats {
+                    evicted_entries: row.get::<_, i64>(0)? as u64,
+                    evicted_bytes: row.get::<_, i64>(1)? as u64,
+                    last_eviction_at: row.get(2)?,
+                })
+            },
+        )
+        .optional()?;
+    Ok(stats.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_index() -> rusqlite::Connection {
+        let index = rusqlite::Connection::open_in_memory().unwrap();
+        index
+            .execute(
+                "CREATE TABLE embeddings (
+                    text_hash TEXT PRIMARY KEY,
+                    text TEXT NOT NULL,
+                    vector_file TEXT NOT NULL,
+                    created_at INTEGER NOT NULL,
+                    accessed_at INTEGER NOT NULL,
+                    access_count INTEGER NOT NULL DEFAULT 1,
+                    vector_size INTEGER NOT NULL
+                )",
+                [],
+            )
+            .unwrap();
+        init_tables(&index).unwrap();
+        index
+    }
+
+    fn add_embedding(index: &rusqlite::Connection, key: &str, accessed: i64, count: i64) {
+        // 254 floats -> 1024 bytes on disk
+        index
+            .execute(
+                "INSERT INTO embeddings VALUES (?1, ?1, ?1, 0, ?2, ?3, 254)",
+                rusqlite::params![key, accessed, count],
+            )
+            .unwrap();
+    }
+
+    fn add_block(index: &rusqlite::Connection, key: &str, accessed: i64, count: i64, size: i64) {
+        index
+            .execute(
+                "INSERT INTO blocks VALUES (?1, NULL, ?1, 0, ?2, ?3, ?4)",
+                rusqlite::params![key, accessed, count, size],
+            )
+            .unwrap();
+    }
+
+    fn keys(victims: &[EvictionCandidate]) -> Vec<&str> {
+        victims.iter().map(|v| v.key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("4096"), Some(4096));
+        assert_eq!(parse_byte_size("2K"), Some(2048));
+        assert_eq!(parse_byte_size("64M"), Some(64 * 1024 * 1024));
+        assert_eq!(parse_byte_size(" 1g "), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_byte_size("lots"), None);
+        assert_eq!(parse_byte_size(""), None);
+    }
+
+    #[test]
+    fn test_within_budget_evicts_nothing() {
+        let index = test_index();
+        add_embedding(&index, "a", 10, 1);
+        let budget = CacheBudget {
+            total: Quota {
+                max_bytes: Some(4096),
+                max_entries: Some(10),
+            },
+            ..Default::default()
+        };
+        assert!(select_victims(&index, &budget).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lru_vs_lfu_ordering() {
+        let index = test_index();
+        add_embedding(&index, "old-popular", 10, 50);
+        add_embedding(&index, "new-rare", 20, 1);
+        add_embedding(&index, "newest", 30, 5);
+
+        let mut budget = CacheBudget {
+            total: Quota {
+                max_bytes: None,
+                max_entries: Some(2),
+            },
+            ..Default::default()
+        };
+
+        budget.policy = EvictionPolicy::Lru;
+        assert_eq!(
+            keys(&select_victims(&index, &budget).unwrap()),
+            vec!["old-popular"]
+        );
+
+        budget.policy = EvictionPolicy::Lfu;
+        assert_eq!(
+            keys(&select_victims(&index, &budget).unwrap()),
+            vec!["new-rare"]
+        );
+    }
+
+    #[test]
+    fn test_namespace_quota_only_touches_its_namespace() {
+        let index = test_index();
+        add_embedding(&index, "emb", 1, 1);
+        add_block(&index, "blk-1", 5, 1, 600);
+        add_block(&index, "blk-2", 6, 1, 600);
+
+        let budget = CacheBudget {
+            blocks: Quota {
+                max_bytes: Some(1000),
+                max_entries: None,
+            },
+            ..Default::default()
+        };
+
+        let victims = select_victims(&index, &budget).unwrap();
+        assert_eq!(keys(&victims), vec!["blk-1"]);
+        assert_eq!(victims[0].namespace, CacheNamespace::Blocks);
+    }
+
+    #[test]
+    fn test_global_quota_after_namespace_quota() {
+        let index = test_index();
+        add_embedding(&index, "emb-old", 1, 1);
+        add_embedding(&index, "emb-new", 9, 1);
+        add_block(&index, "blk-old", 2, 1, 1024);
+        add_block(&index, "blk-new", 8, 1, 1024);
+
+        let budget = CacheBudget {
+            total: Quota {
+                max_bytes: None,
+                max_entries: Some(2),
+            },
+            embeddings: Quota {
+                max_bytes: None,
+                max_entries: Some(1),
+            },
+            ..Default::default()
+        };
+
+        // Namespace quota removes emb-old, global quota then removes blk-old
+        assert_eq!(
+            keys(&select_victims(&index, &budget).unwrap()),
+            vec!["emb-old", "blk-old"]
+        );
+    }
+
+    #[test]
+    fn test_record_evictions_updates_stats() {
+        let index = test_index();
+        add_block(&index, "blk", 1, 1, 300);
+        let victims = vec![EvictionCandidate {
+            namespace: CacheNamespace::Blocks,
+            key: "blk".to_string(),
+            file: "blk".to_string(),
+            size_bytes: 300,
+        }];
+
+        record_evictions(&index, &victims).unwrap();
+        record_evictions(&index, &[]).unwrap();
+
+        let stats = load_stats(&index, CacheNamespace::Blocks).unwrap();
+        assert_eq!(stats.evicted_entries, 1);
+        assert_eq!(stats.evicted_bytes, 300);
+        assert!(stats.last_eviction_at.is_some());
+        assert_eq!(usage(&index, CacheNamespace::Blocks).unwrap().entries, 0);
+        assert_eq!(
+            load_stats(&index, CacheNamespace::Embeddings).unwrap(),
+            EvictionStats::default()
+        );
+    }
+}
@@ -7,9 +7,12 @@
 //! - NUMA-aware CPU affinity for L3 cache optimization
 //! - Automatic NUMA topology detection
 //! - Per-node statistics and monitoring
+//! - Byte/entry budgets with LRU or LFU eviction
 
 pub mod btrfs_cache;
+pub mod eviction;
 pub mod numa;
 pub mod snapshot_manager;
 
 pub use btrfs_cache::BtrfsCache;
+pub use eviction::{CacheBudget, Quota};
@@ -347,6 +347,68 @@ define_event!(ErrorOccurred {
     severity: String
 });
 
+// Network events published by the rtnetlink monitor
+define_event!(LinkAdded {
+    ifname: String,
+    index: u32,
+    kind: Option<String>
+});
+
+define_event!(LinkChanged {
+    ifname: String,
+    index: u32,
+    up: bool,
+    mtu: Option<u32>,
+    master: Option<String>
+});
+
+define_event!(LinkRemoved {
+    ifname: String,
+    index: u32
+});
+
+define_event!(AddressAdded {
+    ifname: String,
+    index: u32,
+    address: String
+});
+
+define_event!(AddressRemoved {
+    ifname: String,
+    index: u32,
+    address: String
+});
+
+define_event!(RouteAdded {
+    destination: String,
+    table: u32,
+    dev: Option<String>,
+    gateway: Option<String>,
+    protocol: u8
+});
+
+define_event!(RouteRemoved {
+    destination: String,
+    table: u32,
+    dev: Option<String>,
+    gateway: Option<String>,
+    protocol: u8
+});
+
+define_event!(RuleAdded {
+    priority: Option<u32>,
+    table: u32,
+    ipv6: bool,
+    protocol: u8
+});
+
+define_event!(RuleRemoved {
+    priority: Option<u32>,
+    table: u32,
+    ipv6: bool,
+    protocol: u8
+});
+
 /// Example logging interceptor
 pub struct LoggingInterceptor;
 
@@ -399,6 +461,7 @@ impl EventInterceptor for MetricsInterceptor {
     async fn after_publish(&self, event: &dyn Event) {
         let mut counts = self.event_counts.write().await;
         *counts.entry(event.event_type().to_string()).or_insert(0) += 1;
+        crate::metrics::record_event(event.event_type());
     }
 }
 
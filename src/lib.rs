@@ -8,9 +8,11 @@ pub mod cache;
 pub mod introspection;
 pub mod isp_migration;
 pub mod isp_support;
+pub mod metrics;
 pub mod native;
 pub mod nonnet_db;
 pub mod snapshot;
+pub mod snapshot_diff;
 pub mod state;
 
 // Loose coupling modules
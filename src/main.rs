@@ -7,6 +7,7 @@ mod cache;
 mod ml;
 mod native;
 mod nonnet_db;
+mod snapshot_diff;
 mod state;
 mod webui;
 
@@ -18,6 +19,13 @@ use std::sync::Arc;
 use tokio::fs;
 use tracing::info;
 
+// Shared with the library so MCP tool metrics land in the same registry
+use op_dbus::metrics;
+// Shared with the library so netlink events reach library-side subscribers
+use op_dbus::event_bus;
+// Shared with the library so container checkpoints follow the same snapshot targets
+use op_dbus::snapshot;
+
 #[cfg(any(feature = "mcp", feature = "web"))]
 use op_dbus::mcp::dbus_indexer::{DbusIndexer, DbusQueryEngine};
 #[cfg(any(feature = "mcp", feature = "web"))]
@@ -34,6 +42,7 @@ struct Cli {
     #[arg(short, long)]
     state_file: Option<PathBuf>,
 
+    /// Serve DHCP on ovsbr0 with the old built-in defaults (deprecated, use the dhcp plugin)
     #[arg(short = 't', long)]
     enable_dhcp_server: bool,
 
@@ -57,6 +66,10 @@ enum Commands {
         /// Only apply to specific plugin (e.g., lxc, net, systemd)
         #[arg(short, long)]
         plugin: Option<String>,
+        /// Snapshot /etc and the op-dbus config subvolume before applying, and
+        /// restore them if a plugin fails and cannot roll back on its own
+        #[arg(long)]
+        host_snapshot: bool,
     },
 
     /// Query current system state
@@ -127,6 +140,15 @@ enum Commands {
     #[command(subcommand)]
     Cache(CacheCommands),
 
+    /// Snapshot management for every managed subvolume
+    #[command(subcommand)]
+    Snapshot(SnapshotCommands),
+
+    /// OpenFlow tools that work without a switch
+    #[cfg(feature = "openflow")]
+    #[command(subcommand)]
+    Openflow(OpenflowCommands),
+
     /// Start web UI server
     Serve {
         /// Bind address
@@ -165,6 +187,19 @@ enum CacheCommands {
         older_than_days: i64,
     },
 
+    /// Evict entries until the cache fits its size budget
+    Evict {
+        /// Total byte budget (e.g. 512M, 20G); overrides OPDBUS_CACHE_MAX_BYTES
+        #[arg(long)]
+        max_bytes: Option<String>,
+        /// Total entry budget; overrides OPDBUS_CACHE_MAX_ENTRIES
+        #[arg(long)]
+        max_entries: Option<usize>,
+        /// Eviction policy: lru or lfu; overrides OPDBUS_CACHE_EVICTION
+        #[arg(long)]
+        policy: Option<String>,
+    },
+
     /// Create cache snapshot
     Snapshot,
 
@@ -175,6 +210,93 @@ enum CacheCommands {
     DeleteSnapshots,
 }
 
+#[cfg(feature = "openflow")]
+#[derive(Subcommand)]
+enum OpenflowCommands {
+    /// Trace a packet through a bridge's desired flows (generated flows included)
+    Trace {
+        /// State file with an openflow plugin section, or a bare openflow config
+        state_file: PathBuf,
+
+        /// Packet in ovs-ofctl match syntax, e.g. "in_port=vi100,tcp,nw_dst=10.0.0.2,tp_dst=443"
+        packet: String,
+
+        /// Bridge to trace on (required when the config has several)
+        #[arg(short, long)]
+        bridge: Option<String>,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommands {
+    /// Show snapshot targets and their retention policies
+    Targets,
+
+    /// List snapshots (all enabled targets by default)
+    List {
+        #[arg(short, long)]
+        target: Option<String>,
+    },
+
+    /// Create a snapshot of a target's subvolume
+    Create {
+        /// Target name (e.g. dbus-index, cache, config)
+        target: String,
+        /// Snapshot name (defaults to timestamp)
+        #[arg(short, long)]
+        name: Option<String>,
+        /// Tag the new snapshot
+        #[arg(long)]
+        tag: Option<String>,
+    },
+
+    /// Tag (or untag) a snapshot
+    Tag {
+        target: String,
+        name: String,
+        /// Tag value; omit together with --remove to drop the tag
+        tag: Option<String>,
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Apply retention policies and delete expired snapshots
+    Prune {
+        #[arg(short, long)]
+        target: Option<String>,
+        /// Only show what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Replace a target's subvolume with a snapshot (current data is kept aside)
+    Restore {
+        target: String,
+        name: String,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Show what changed between two snapshots (paths, target/name or names)
+    Diff {
+        /// Older snapshot
+        from: String,
+        /// Newer snapshot
+        to: String,
+        /// Print the diff as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
 #[cfg(any(feature = "mcp", feature = "web"))]
 #[derive(Subcommand)]
 enum IndexCommands {
@@ -360,60 +482,109 @@ async fn apply_state_from_file_single_plugin(
     Ok(())
 }
 
-async fn setup_dhcp_server() -> Result<()> {
-    info!("Setting up DHCP server...");
+/// Quiet period after a netlink event before the daemon reacts, so bursts coalesce
+const NETWORK_SETTLE: std::time::Duration = std::time::Duration::from_secs(2);
 
-    // Install dnsmasq (lightweight DHCP and DNS server)
-    let output = tokio::process::Command::new("apt")
-        .args(["update"])
-        .output()
-        .await?;
+/// React to netlink monitor events instead of polling: re-check the net plugin for
+/// drift, and re-apply OpenFlow policies when container ports come or go
+async fn watch_network_events(state_manager: Arc<state::StateManager>, state_file: PathBuf) {
+    use futures::StreamExt;
 
-    if !output.status.success() {
-        return Err(anyhow::anyhow!("Failed to update package list"));
-    }
-
-    let output = tokio::process::Command::new("apt")
-        .args(["install", "-y", "dnsmasq"])
-        .output()
-        .await?;
+    let mut events = Box::pin(native::rtnetlink_monitor::events().await);
+    while let Some(event) = events.next().await {
+        let mut containers_changed = is_container_link_event(&**event);
+        // A container start or an apply produces a burst; react once it settles
+        while let Ok(Some(event)) = tokio::time::timeout(NETWORK_SETTLE, events.next()).await {
+            containers_changed |= is_container_link_event(&**event);
+        }
 
-    if !output.status.success() {
-        return Err(anyhow::anyhow!("Failed to install dnsmasq"));
+        let desired = match state_manager.load_desired_state(&state_file).await {
+            Ok(desired) => desired,
+            Err(e) => {
+                log::warn!("Failed to load {}: {}", state_file.display(), e);
+                continue;
+            }
+        };
+        if desired.plugins.contains_key("net") {
+            match state_manager.check_drift(&desired, "net").await {
+                Ok(0) => {}
+                Ok(pending) => log::warn!("Network state drifted: {} pending change(s)", pending),
+                Err(e) => log::warn!("Failed to check network drift: {}", e),
+            }
+        }
+        if containers_changed && desired.plugins.contains_key("openflow") {
+            if let Err(e) = state_manager
+                .apply_state_single_plugin(desired, "openflow")
+                .await
+            {
+                log::warn!("Failed to re-apply OpenFlow policies: {}", e);
+            }
+        }
     }
+}
 
-    // Create basic dnsmasq configuration for DHCP server
-    let dhcp_config = r#"# DHCP server configuration
-interface=ovsbr0
-dhcp-range=192.168.1.50,192.168.1.150,12h
-dhcp-option=option:router,192.168.1.1
-dhcp-option=option:dns-server,8.8.8.8,8.8.4.4
-dhcp-authoritative
-"#;
-
-    fs::write("/etc/dnsmasq.d/op-dbus-dhcp.conf", dhcp_config).await?;
-
-    // Enable and start dnsmasq
-    let output = tokio::process::Command::new("systemctl")
-        .args(["enable", "dnsmasq"])
-        .output()
-        .await?;
+/// Whether an event is a container port appearing on or leaving the host
+#[cfg(feature = "openflow")]
+fn is_container_link_event(event: &dyn event_bus::Event) -> bool {
+    matches!(event.event_type(), "LinkAdded" | "LinkRemoved")
+        && event.to_json()["ifname"]
+            .as_str()
+            .is_some_and(state::plugins::openflow::OpenFlowPlugin::is_container_port)
+}
 
-    if !output.status.success() {
-        return Err(anyhow::anyhow!("Failed to enable dnsmasq"));
-    }
+#[cfg(not(feature = "openflow"))]
+fn is_container_link_event(_event: &dyn event_bus::Event) -> bool {
+    false
+}
 
-    let output = tokio::process::Command::new("systemctl")
-        .args(["restart", "dnsmasq"])
-        .output()
-        .await?;
+/// Desired state for `--enable-dhcp-server`: the DHCP setup op-dbus used to
+/// hard-code, now applied through the dhcp plugin
+fn legacy_dhcp_state() -> state::manager::DesiredState {
+    let dhcp = serde_json::json!({
+        "bridges": [{
+            "bridge": "ovsbr0",
+            "ranges": [{"start": "192.168.1.50", "end": "192.168.1.150", "lease_time": "12h"}],
+            "gateway": "192.168.1.1",
+            "dns_servers": ["8.8.8.8", "8.8.4.4"]
+        }]
+    });
+    state::manager::DesiredState {
+        version: 1,
+        plugins: [("dhcp".to_string(), dhcp)].into_iter().collect(),
+    }
+}
 
-    if !output.status.success() {
-        return Err(anyhow::anyhow!("Failed to start dnsmasq"));
+/// Address for the daemon's standalone `/metrics` listener.
+///
+/// `OPDBUS_METRICS_ADDR` overrides it ("off" disables it). The web UI's `/metrics` only
+/// exists in a separate `serve` process, so `run` always starts its own listener.
+fn metrics_addr() -> Option<String> {
+    match std::env::var("OPDBUS_METRICS_ADDR") {
+        Ok(addr) if addr == "off" || addr.is_empty() => None,
+        Ok(addr) => Some(addr),
+        Err(_) => Some(metrics::DEFAULT_METRICS_ADDR.to_string()),
     }
+}
 
-    info!("DHCP server setup complete");
-    Ok(())
+/// Periodically export the persistent cache statistics, which the CLI commands
+/// that fill the cache cannot serve themselves
+async fn export_cache_stats(cache_dir: PathBuf) {
+    // The library's cache, so its stats types match the shared metrics module
+    let cache = match op_dbus::cache::BtrfsCache::new(cache_dir).await {
+        Ok(cache) => cache,
+        Err(e) => {
+            log::warn!("Cache statistics unavailable: {}", e);
+            return;
+        }
+    };
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        match cache.stats() {
+            Ok(stats) => metrics::record_cache_stats(&stats),
+            Err(e) => log::debug!("Failed to read cache statistics: {}", e),
+        }
+    }
 }
 
 #[tokio::main]
@@ -421,7 +592,14 @@ async fn main() -> Result<()> {
     init_logging()?;
     let args = Cli::parse();
 
-    let state_manager = Arc::new(state::StateManager::new());
+    let mut state_manager = state::StateManager::new();
+    let host_snapshot = matches!(args.command, Some(Commands::Apply { host_snapshot: true, .. }))
+        || std::env::var("OPDBUS_HOST_SNAPSHOT").is_ok_and(|v| v == "1" || v == "true");
+    if host_snapshot {
+        state_manager =
+            state_manager.with_host_snapshots(state::host_snapshot::HostSnapshotConfig::default());
+    }
+    let state_manager = Arc::new(state_manager);
 
     // Register core plugins manually
     let plugins = vec![
@@ -431,6 +609,10 @@ async fn main() -> Result<()> {
         ("lxc", Arc::new(state::plugins::LxcPlugin::new())),
         ("sessdecl", Arc::new(state::plugins::SessDeclPlugin::new())),
         ("dns", Arc::new(state::plugins::DnsResolverPlugin::new())),
+        ("dhcp", Arc::new(state::plugins::DhcpPlugin::new())),
+        ("nft", Arc::new(state::plugins::NftPlugin::new())),
+        ("sysctl", Arc::new(state::plugins::SysctlPlugin::new())),
+        ("wireguard", Arc::new(state::plugins::WireGuardPlugin::new())),
         ("pcidecl", Arc::new(state::plugins::PciDeclPlugin::new())),
         ("packagekit", Arc::new(state::plugins::PackageKitPlugin::new())),
         #[cfg(feature = "openflow")]
@@ -482,9 +664,30 @@ async fn main() -> Result<()> {
 
     match args.command.unwrap_or(Commands::Run { oneshot: false }) {
         Commands::Run { oneshot } => {
+            if let Some(addr) = metrics_addr() {
+                tokio::spawn(async move {
+                    if let Err(e) = metrics::serve(&addr).await {
+                        log::warn!("Metrics listener exited: {}", e);
+                    }
+                });
+
+                let cache_dir = crate::cache::BtrfsCache::default_dir();
+                if cache_dir.exists() {
+                    tokio::spawn(export_cache_stats(cache_dir));
+                }
+            }
+
+            // Live link/address/route view; keeps net queries cached and publishes events
+            native::rtnetlink_monitor::start();
+
             // Set up DHCP server if requested
             if args.enable_dhcp_server {
-                setup_dhcp_server().await?;
+                log::warn!(
+                    "--enable-dhcp-server is deprecated, declare a \"dhcp\" section in the state file instead"
+                );
+                state_manager
+                    .apply_state_single_plugin(legacy_dhcp_state(), "dhcp")
+                    .await?;
             }
 
             let state_file = args
@@ -499,6 +702,10 @@ async fn main() -> Result<()> {
                 return Ok(());
             }
 
+            if state_file.exists() {
+                tokio::spawn(watch_network_events(Arc::clone(&state_manager), state_file));
+            }
+
             info!("Daemon running, press Ctrl+C to stop");
             tokio::signal::ctrl_c().await?;
             Ok(())
@@ -508,6 +715,7 @@ async fn main() -> Result<()> {
             state_file,
             dry_run,
             plugin,
+            ..
         } => {
             if dry_run {
                 info!("DRY RUN: Showing what would be applied");
@@ -905,11 +1113,17 @@ async fn main() -> Result<()> {
 
         Commands::Cache(cmd) => handle_cache_command(cmd).await,
 
+        Commands::Snapshot(cmd) => handle_snapshot_command(cmd).await,
+
+        #[cfg(feature = "openflow")]
+        Commands::Openflow(cmd) => handle_openflow_command(cmd),
+
         #[cfg(any(feature = "mcp", feature = "web"))]
         Commands::Index(cmd) => handle_index_command(cmd).await,
 
         Commands::Serve { bind, port } => {
             info!("Starting web UI server on {}:{}", bind, port);
+            native::rtnetlink_monitor::start();
 
             let config = crate::webui::WebConfig {
                 bind_addr: bind,
@@ -923,9 +1137,7 @@ async fn main() -> Result<()> {
 }
 
 async fn handle_cache_command(cmd: CacheCommands) -> Result<()> {
-    let cache_dir = PathBuf::from(
-        std::env::var("OPDBUS_CACHE_DIR").unwrap_or_else(|_| "/var/lib/op-dbus/@cache".to_string()),
-    );
+    let cache_dir = crate::cache::BtrfsCache::default_dir();
 
     match cmd {
         CacheCommands::Stats => {
@@ -953,12 +1165,39 @@ async fn handle_cache_command(cmd: CacheCommands) -> Result<()> {
                 stats.blocks_size_bytes as f64 / 1024.0 / 1024.0
             );
 
+            println!("  Tracked entries:  {}", stats.block_entries);
+
             println!("\nTotal:");
             println!(
                 "  Disk usage:       {:.2} MB (compressed)",
                 stats.disk_usage_bytes as f64 / 1024.0 / 1024.0
             );
 
+            let budget = cache.budget();
+            println!("\nBudget ({:?}):", budget.policy);
+            println!("  Total:            {}", format_quota(&budget.total));
+            println!("  Embeddings:       {}", format_quota(&budget.embeddings));
+            println!("  Blocks:           {}", format_quota(&budget.blocks));
+
+            println!("\nEvictions:");
+            for (name, evictions) in [
+                ("Embeddings", &stats.embedding_evictions),
+                ("Blocks", &stats.block_evictions),
+            ] {
+                let last = evictions
+                    .last_eviction_at
+                    .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+                    .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_else(|| "never".to_string());
+                println!(
+                    "  {:<18}{} entries, {:.2} MB (last: {})",
+                    format!("{}:", name),
+                    evictions.evicted_entries,
+                    evictions.evicted_bytes as f64 / 1024.0 / 1024.0,
+                    last
+                );
+            }
+
             // Show snapshots
             let snapshots = cache.list_snapshots().await?;
             println!("\nSnapshots:          {}", snapshots.len());
@@ -1019,6 +1258,38 @@ async fn handle_cache_command(cmd: CacheCommands) -> Result<()> {
             Ok(())
         }
 
+        CacheCommands::Evict {
+            max_bytes,
+            max_entries,
+            policy,
+        } => {
+            let mut budget = crate::cache::CacheBudget::from_env();
+            if let Some(max_bytes) = max_bytes {
+                budget.total.max_bytes = Some(
+                    crate::cache::eviction::parse_byte_size(&max_bytes)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid byte size: {}", max_bytes))?,
+                );
+            }
+            if max_entries.is_some() {
+                budget.total.max_entries = max_entries;
+            }
+            if let Some(policy) = policy {
+                budget.policy = policy.parse()?;
+            }
+
+            if budget.is_unlimited() {
+                println!("No cache budget configured - nothing to evict");
+                return Ok(());
+            }
+
+            let cache = crate::cache::BtrfsCache::new(cache_dir)
+                .await?
+                .with_budget(budget);
+            let removed = cache.enforce_budget()?;
+            println!("? Evicted {} entries", removed);
+            Ok(())
+        }
+
         CacheCommands::Snapshot => {
             println!("Creating cache snapshot...");
             let cache = crate::cache::BtrfsCache::new(cache_dir).await?;
@@ -1053,6 +1324,270 @@ async fn handle_cache_command(cmd: CacheCommands) -> Result<()> {
     }
 }
 
+fn confirm(prompt: &str) -> Result<bool> {
+    use std::io::{self, Write};
+
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().eq_ignore_ascii_case("y"))
+}
+
+#[cfg(feature = "openflow")]
+fn handle_openflow_command(cmd: OpenflowCommands) -> Result<()> {
+    use state::plugins::openflow::{trace, OpenFlowConfig};
+
+    match cmd {
+        OpenflowCommands::Trace {
+            state_file,
+            packet,
+            bridge,
+            json,
+        } => {
+            let content = std::fs::read_to_string(&state_file)
+                .with_context(|| format!("Failed to read {}", state_file.display()))?;
+            let value: serde_json::Value = serde_json::from_str(&content)?;
+            let section = value
+                .get("plugins")
+                .and_then(|plugins| plugins.get("openflow"))
+                .cloned()
+                .unwrap_or(value);
+            let config: OpenFlowConfig = serde_json::from_value(section)
+                .context("No openflow config in the state file")?;
+
+            let report = trace::trace_config(&config, bridge.as_deref(), &packet)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{}", report);
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn handle_snapshot_command(cmd: SnapshotCommands) -> Result<()> {
+    let targets = snapshot::SnapshotTargets::load()?;
+
+    match cmd {
+        SnapshotCommands::Targets => {
+            println!("=== Snapshot Targets ===\n");
+            for (name, config) in targets.iter() {
+                println!(
+                    "  {:<12} {:<8} {:?}",
+                    name,
+                    if config.enabled { "enabled" } else { "disabled" },
+                    config.policy
+                );
+                println!("               source:    {}", config.subvolume.display());
+                println!("               snapshots: {}", config.snapshots_dir.display());
+            }
+            Ok(())
+        }
+
+        SnapshotCommands::List { target } => {
+            for (name, config) in targets.select(target.as_deref())? {
+                let snapshots = config.manager().list_snapshots()?;
+                println!("=== {} ({} snapshots, {:?}) ===", name, snapshots.len(), config.policy);
+                let mut snapshots = snapshots;
+                snapshots.sort_by_key(|s| s.created);
+                for snapshot in &snapshots {
+                    let created = chrono::DateTime::from_timestamp(snapshot.created, 0)
+                        .map(|d| d.to_rfc3339())
+                        .unwrap_or_else(|| "Unknown".to_string());
+                    match &snapshot.tag {
+                        Some(tag) => println!("  {}  {}  🏷️  {}", snapshot.name, created, tag),
+                        None => println!("  {}  {}", snapshot.name, created),
+                    }
+                }
+                println!();
+            }
+            Ok(())
+        }
+
+        SnapshotCommands::Create { target, name, tag } => {
+            let config = targets.get(&target)?;
+            let manager = config.manager();
+            let snapshot_path = manager.create_tagged_snapshot(
+                &config.subvolume,
+                name.as_deref(),
+                tag.as_deref(),
+            )?;
+            println!("✅ Created: {}", snapshot_path.display());
+            if let Some(tag) = tag {
+                println!("   Tagged as: {}", tag);
+            }
+            Ok(())
+        }
+
+        SnapshotCommands::Tag {
+            target,
+            name,
+            tag,
+            remove,
+        } => {
+            let manager = targets.get(&target)?.manager();
+            match (tag, remove) {
+                (_, true) => {
+                    manager.untag_snapshot(&name)?;
+                    println!("✅ Removed tag from {}", name);
+                }
+                (Some(tag), false) => {
+                    manager.tag_snapshot(&name, &tag)?;
+                    println!("✅ Tagged {} as {}", name, tag);
+                }
+                (None, false) => anyhow::bail!("Specify a tag or --remove"),
+            }
+            Ok(())
+        }
+
+        SnapshotCommands::Prune {
+            target,
+            dry_run,
+            force,
+        } => {
+            let mut plans = Vec::new();
+            for (name, config) in targets.select(target.as_deref())? {
+                let manager = config.manager();
+                let victims = manager.plan_retention()?;
+                println!("{} ({:?}): {} to delete", name, config.policy, victims.len());
+                for victim in &victims {
+                    println!("  - {}", victim.name);
+                }
+                plans.push((manager, victims));
+            }
+
+            let total: usize = plans.iter().map(|(_, v)| v.len()).sum();
+            if dry_run || total == 0 {
+                if dry_run {
+                    println!("\nDry run - nothing deleted");
+                }
+                return Ok(());
+            }
+            if !force && !confirm(&format!("\nDelete {} snapshot(s)?", total))? {
+                println!("Cancelled");
+                return Ok(());
+            }
+
+            for (manager, victims) in plans {
+                for victim in victims {
+                    manager.delete_snapshot(&victim.path)?;
+                }
+            }
+            println!("✅ Deleted {} snapshot(s)", total);
+            Ok(())
+        }
+
+        SnapshotCommands::Restore {
+            target,
+            name,
+            force,
+        } => {
+            let config = targets.get(&target)?;
+            if !force
+                && !confirm(&format!(
+                    "Replace {} with snapshot {}?",
+                    config.subvolume.display(),
+                    name
+                ))?
+            {
+                println!("Cancelled");
+                return Ok(());
+            }
+
+            let backup = config.manager().restore_snapshot(&name, &config.subvolume)?;
+            println!("✅ Restored {} from {}", config.subvolume.display(), name);
+            if let Some(backup) = backup {
+                println!("   Previous contents kept at {}", backup.display());
+            }
+            Ok(())
+        }
+
+        SnapshotCommands::Diff { from, to, json } => {
+            let from = snapshot::resolve_snapshot(&from)?;
+            let to = snapshot::resolve_snapshot(&to)?;
+
+            // Two plain JSON files (index or state dumps) get a structured diff only
+            if from.is_file() && to.is_file() {
+                let changes = crate::snapshot_diff::diff_json_files(&from, &to)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&changes)?);
+                } else if changes.is_empty() {
+                    println!("No differences found");
+                } else {
+                    for change in &changes {
+                        println!("  {:?} {}", change.kind, change.pointer);
+                    }
+                }
+                return Ok(());
+            }
+
+            let diff = tokio::task::spawn_blocking(move || {
+                crate::snapshot_diff::diff_snapshots(&from, &to)
+            })
+            .await??;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+                return Ok(());
+            }
+
+            println!("=== Snapshot Diff ({:?}) ===\n", diff.method);
+            println!("From: {}", diff.from.display());
+            println!("To:   {}\n", diff.to.display());
+
+            if diff.is_empty() {
+                println!("No differences found");
+                return Ok(());
+            }
+
+            for change in diff.added() {
+                println!("  + {} ({} bytes)", change.path.display(), change.new_size.unwrap_or(0));
+            }
+            for change in diff.removed() {
+                println!("  - {} ({} bytes)", change.path.display(), change.old_size.unwrap_or(0));
+            }
+            for change in diff.modified() {
+                println!(
+                    "  ~ {} ({} -> {} bytes)",
+                    change.path.display(),
+                    change.old_size.unwrap_or(0),
+                    change.new_size.unwrap_or(0)
+                );
+            }
+
+            for (file, changes) in &diff.json {
+                println!("\n{} ({} changes):", file, changes.len());
+                for change in changes {
+                    println!("  {:?} {}", change.kind, change.pointer);
+                }
+            }
+
+            println!(
+                "\nAdded: {}, removed: {}, modified: {}, size delta: {} bytes",
+                diff.added().count(),
+                diff.removed().count(),
+                diff.modified().count(),
+                diff.size_delta()
+            );
+            Ok(())
+        }
+    }
+}
+
+fn format_quota(quota: &crate::cache::Quota) -> String {
+    let bytes = quota
+        .max_bytes
+        .map(|b| format!("{:.2} MB", b as f64 / 1024.0 / 1024.0))
+        .unwrap_or_else(|| "unlimited".to_string());
+    let entries = quota
+        .max_entries
+        .map(|e| e.to_string())
+        .unwrap_or_else(|| "unlimited".to_string());
+    format!("{} / {} entries", bytes, entries)
+}
+
 #[cfg(any(feature = "mcp", feature = "web"))]
 async fn handle_index_command(cmd: IndexCommands) -> Result<()> {
 
@@ -1677,6 +2212,9 @@ async fn handle_container_command(
                 veth: format!("vi{}", container_id),
                 bridge: "vmbr0".to_string(), // default bridge, may be changed by plugin
                 running: None,
+                config: Default::default(),
+                provision: None,
+                provisioned: None,
                 properties: Some(properties),
             };
 
@@ -92,6 +92,28 @@ pub struct IndexStatistics {
     pub scan_duration_seconds: f64,
 }
 
+impl IndexStatistics {
+    /// Publish these statistics as `opdbus_index_*` gauges
+    pub fn record_metrics(&self) {
+        let registry = crate::metrics::global();
+        for (name, help, value) in [
+            ("opdbus_index_services", "D-Bus services in the index", self.total_services),
+            ("opdbus_index_objects", "D-Bus objects in the index", self.total_objects),
+            ("opdbus_index_interfaces", "D-Bus interfaces in the index", self.total_interfaces),
+            ("opdbus_index_methods", "D-Bus methods in the index", self.total_methods),
+            ("opdbus_index_properties", "D-Bus properties in the index", self.total_properties),
+        ] {
+            registry.set(name, help, &[], value as f64);
+        }
+        registry.set(
+            "opdbus_index_scan_duration_seconds",
+            "Duration of the most recent D-Bus index scan",
+            &[],
+            self.scan_duration_seconds,
+        );
+    }
+}
+
 /// D-Bus indexer that builds complete system index
 pub struct DbusIndexer {
     index_root: PathBuf,
@@ -205,6 +227,7 @@ impl DbusIndexer {
         };
 
         log::info!("✅ Index complete in {:.2}s", duration);
+        index.statistics.record_metrics();
         log::info!("   Services: {}", index.statistics.total_services);
         log::info!("   Objects: {}", index.statistics.total_objects);
         log::info!("   Methods: {}", index.statistics.total_methods);
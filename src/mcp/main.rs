@@ -8,6 +8,8 @@ mod resources;
 mod native;
 
 use anyhow::{Context, Result};
+// tool_registry records into the shared metrics registry, served from main()
+use op_dbus::metrics;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::io::{self, BufRead, Write};
@@ -657,11 +659,32 @@ impl McpServer {
     }
 }
 
+/// Address for this server's own `/metrics` listener, which carries the tool
+/// call counters recorded by the registry.
+///
+/// `OPDBUS_MCP_METRICS_ADDR` overrides it ("off" disables it).
+fn metrics_addr() -> Option<String> {
+    match std::env::var("OPDBUS_MCP_METRICS_ADDR") {
+        Ok(addr) if addr == "off" || addr.is_empty() => None,
+        Ok(addr) => Some(addr),
+        Err(_) => Some(metrics::DEFAULT_MCP_METRICS_ADDR.to_string()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize logging
     env_logger::init();
 
+    if let Some(addr) = metrics_addr() {
+        tokio::spawn(async move {
+            // Another dbus-mcp instance may already hold the port
+            if let Err(e) = metrics::serve(&addr).await {
+                eprintln!("Metrics listener exited: {}", e);
+            }
+        });
+    }
+
     eprintln!("Starting refactored MCP server with tool registry...");
 
     let server = McpServer::new().await?;
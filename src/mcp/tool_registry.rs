@@ -78,6 +78,11 @@ impl ToolResult {
         }
     }
 
+    /// Whether any content item is an error
+    pub fn is_error(&self) -> bool {
+        self.content.iter().any(|c| c.content_type == "error")
+    }
+
     /// Add metadata to the result
     pub fn with_metadata(mut self, metadata: Value) -> Self {
         self.metadata = Some(metadata);
@@ -248,6 +253,7 @@ impl ToolRegistry {
 
         // Execute tool
         let result = tool.execute(params.clone()).await;
+        crate::metrics::record_tool_call(name, matches!(&result, Ok(r) if !r.is_error()));
 
         // Call after middleware
         for mw in middlewares.iter() {
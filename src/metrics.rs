@@ -0,0 +1,594 @@
+//! Prometheus metrics for the op-dbus daemon
+//!
+//! Components record into the process-wide [`global()`] registry; the web server
+//! exposes it at `/metrics`, and [`serve`] provides the standalone listener the
+//! `run` daemon and the `dbus-mcp` server each start, since every process has
+//! its own registry.
+//!
+//! Exported families:
+//! - `opdbus_apply_total` / `opdbus_apply_duration_seconds` (per plugin)
+//! - `opdbus_drift_actions` / `opdbus_drift_detected_total` (per plugin)
+//! - `opdbus_query_duration_seconds` (per plugin)
+//! - `opdbus_cache_requests_total` / `opdbus_cache_hit_ratio` (per namespace)
+//! - `opdbus_cache_entries` / `opdbus_cache_disk_bytes` / `opdbus_cache_evicted_*` (per
+//!   namespace) and `opdbus_cache_hot_entries` / `opdbus_cache_accesses`, from [`CacheStats`]
+//! - `opdbus_numa_accesses` (per locality) / `opdbus_numa_avg_latency_seconds` /
+//!   `opdbus_numa_local_hit_ratio`, from [`NumaStats`]
+//! - `opdbus_blockchain_height`
+//! - `opdbus_mcp_tool_calls_total` (per tool, served by `dbus-mcp` itself)
+//! - `opdbus_events_total` (per event type, via `MetricsInterceptor`)
+//! - `opdbus_index_*` (D-Bus index statistics)
+
+use crate::cache::btrfs_cache::CacheStats;
+use crate::cache::numa::NumaStats;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Content type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Default standalone listen address (web UI port + 1)
+pub const DEFAULT_METRICS_ADDR: &str = "127.0.0.1:9574";
+
+/// Default listen address of the `dbus-mcp` server's own listener
+pub const DEFAULT_MCP_METRICS_ADDR: &str = "127.0.0.1:9575";
+
+/// Histogram buckets in seconds, tuned for plugin queries and applies
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Series {
+    Value(f64),
+    Histogram {
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+#[derive(Debug)]
+struct Family {
+    help: &'static str,
+    kind: Kind,
+    /// Keyed by the rendered label set, e.g. `plugin="net",result="success"`
+    series: BTreeMap<String, Series>,
+}
+
+/// Registry of metric families
+#[derive(Debug, Default)]
+pub struct Registry {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Increment a counter by one
+    pub fn inc(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)]) {
+        self.add(name, help, labels, 1.0);
+    }
+
+    /// Increment a counter by `value`
+    pub fn add(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+        self.update(name, help, Kind::Counter, labels, |series| {
+            if let Series::Value(v) = series {
+                *v += value;
+            }
+        });
+    }
+
+    /// Set a gauge
+    pub fn set(&self, name: &'static str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+        self.update(name, help, Kind::Gauge, labels, |series| {
+            if let Series::Value(v) = series {
+                *v = value;
+            }
+        });
+    }
+
+    /// Record an observation (in seconds) into a histogram
+    pub fn observe(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.update(name, help, Kind::Histogram, labels, |series| {
+            if let Series::Histogram {
+                buckets,
+                sum,
+                count,
+            } = series
+            {
+                for (bucket, bound) in buckets.iter_mut().zip(DURATION_BUCKETS) {
+                    if value <= *bound {
+                        *bucket += 1;
+                    }
+                }
+                *sum += value;
+                *count += 1;
+            }
+        });
+    }
+
+    /// Current value of a counter or gauge (mainly for tests and CLI output)
+    pub fn value(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let families = self.families.lock().unwrap();
+        match families.get(name)?.series.get(&render_labels(labels))? {
+            Series::Value(v) => Some(*v),
+            Series::Histogram { count, .. } => Some(*count as f64),
+        }
+    }
+
+    fn update(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        kind: Kind,
+        labels: &[(&str, &str)],
+        f: impl FnOnce(&mut Series),
+    ) {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            kind,
+            series: BTreeMap::new(),
+        });
+        if family.kind != kind {
+            log::warn!(
+                "Metric {} registered as {} but used as {}",
+                name,
+                family.kind.as_str(),
+                kind.as_str()
+            );
+            return;
+        }
+
+        let series = family
+            .series
+            .entry(render_labels(labels))
+            .or_insert_with(|| match kind {
+                Kind::Histogram => Series::Histogram {
+                    buckets: vec![0; DURATION_BUCKETS.len()],
+                    sum: 0.0,
+                    count: 0,
+                },
+                _ => Series::Value(0.0),
+            });
+        f(series);
+    }
+
+    /// Render all families in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+
+        for (name, family) in families.iter() {
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, family.kind.as_str());
+
+            for (labels, series) in &family.series {
+                match series {
+                    Series::Value(v) => {
+                        let _ = writeln!(out, "{}{} {}", name, braced(labels), format_value(*v));
+                    }
+                    Series::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        for (bucket, bound) in buckets.iter().zip(DURATION_BUCKETS) {
+                            let le = with_label(labels, "le", &bound.to_string());
+                            let _ = writeln!(out, "{}_bucket{{{}}} {}", name, le, bucket);
+                        }
+                        let le = with_label(labels, "le", "+Inf");
+                        let _ = writeln!(out, "{}_bucket{{{}}} {}", name, le, count);
+                        let _ =
+                            writeln!(out, "{}_sum{} {}", name, braced(labels), format_value(*sum));
+                        let _ = writeln!(out, "{}_count{} {}", name, braced(labels), count);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn braced(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+fn with_label(labels: &str, key: &str, value: &str) -> String {
+    if labels.is_empty() {
+        format!("{}=\"{}\"", key, value)
+    } else {
+        format!("{},{}=\"{}\"", labels, key, value)
+    }
+}
+
+fn format_value(v: f64) -> String {
+    if v.is_finite() && v.fract() == 0.0 && v.abs() < 1e15 {
+        format!("{}", v as i64)
+    } else {
+        format!("{}", v)
+    }
+}
+
+/// Process-wide registry
+static GLOBAL_REGISTRY: once_cell::sync::Lazy<Registry> = once_cell::sync::Lazy::new(Registry::new);
+
+/// Get the global metrics registry
+pub fn global() -> &'static Registry {
+    &GLOBAL_REGISTRY
+}
+
+/// Record a plugin apply outcome and duration
+pub fn record_apply(plugin: &str, success: bool, started: Instant) {
+    let result = if success { "success" } else { "failure" };
+    global().inc(
+        "opdbus_apply_total",
+        "Plugin apply operations by result",
+        &[("plugin", plugin), ("result", result)],
+    );
+    global().observe(
+        "opdbus_apply_duration_seconds",
+        "Time spent applying desired state per plugin",
+        &[("plugin", plugin)],
+        started.elapsed().as_secs_f64(),
+    );
+}
+
+/// Record the number of pending (non-NoOp) actions found for a plugin
+pub fn record_drift(plugin: &str, actions: usize) {
+    global().set(
+        "opdbus_drift_actions",
+        "Pending actions in the most recent diff per plugin",
+        &[("plugin", plugin)],
+        actions as f64,
+    );
+    if actions > 0 {
+        global().inc(
+            "opdbus_drift_detected_total",
+            "Diffs that found the plugin out of its desired state",
+            &[("plugin", plugin)],
+        );
+    }
+}
+
+/// Record the latency of a plugin state query
+pub fn record_query(plugin: &str, started: Instant) {
+    global().observe(
+        "opdbus_query_duration_seconds",
+        "Time spent querying current state per plugin",
+        &[("plugin", plugin)],
+        started.elapsed().as_secs_f64(),
+    );
+}
+
+/// Record a cache lookup and refresh the namespace hit ratio
+pub fn record_cache_lookup(namespace: &str, hit: bool) {
+    let registry = global();
+    registry.inc(
+        "opdbus_cache_requests_total",
+        "Cache lookups by namespace and result",
+        &[
+            ("namespace", namespace),
+            ("result", if hit { "hit" } else { "miss" }),
+        ],
+    );
+
+    let hits = registry
+        .value(
+            "opdbus_cache_requests_total",
+            &[("namespace", namespace), ("result", "hit")],
+        )
+        .unwrap_or(0.0);
+    let misses = registry
+        .value(
+            "opdbus_cache_requests_total",
+            &[("namespace", namespace), ("result", "miss")],
+        )
+        .unwrap_or(0.0);
+    registry.set(
+        "opdbus_cache_hit_ratio",
+        "Fraction of cache lookups served from cache",
+        &[("namespace", namespace)],
+        hits / (hits + misses),
+    );
+}
+
+/// Record a snapshot of the persistent cache statistics
+pub fn record_cache_stats(stats: &CacheStats) {
+    let registry = global();
+    let namespaces = [
+        (
+            "embeddings",
+            stats.total_entries,
+            stats.embeddings_size_bytes,
+            &stats.embedding_evictions,
+        ),
+        (
+            "blocks",
+            stats.block_entries,
+            stats.blocks_size_bytes,
+            &stats.block_evictions,
+        ),
+    ];
+    for (namespace, entries, bytes, evictions) in namespaces {
+        let labels = [("namespace", namespace)];
+        registry.set(
+            "opdbus_cache_entries",
+            "Entries in the cache index",
+            &labels,
+            entries as f64,
+        );
+        registry.set(
+            "opdbus_cache_disk_bytes",
+            "Bytes the cache uses on disk",
+            &labels,
+            bytes as f64,
+        );
+        registry.set(
+            "opdbus_cache_evicted_entries",
+            "Entries evicted since the cache was created",
+            &labels,
+            evictions.evicted_entries as f64,
+        );
+        registry.set(
+            "opdbus_cache_evicted_bytes",
+            "Bytes evicted since the cache was created",
+            &labels,
+            evictions.evicted_bytes as f64,
+        );
+    }
+    registry.set(
+        "opdbus_cache_hot_entries",
+        "Embeddings accessed within the last hour",
+        &[],
+        stats.hot_entries as f64,
+    );
+    registry.set(
+        "opdbus_cache_accesses",
+        "Recorded embedding accesses",
+        &[],
+        stats.total_accesses as f64,
+    );
+    record_numa_stats(&stats.numa);
+}
+
+/// Record NUMA locality of cache accesses
+pub fn record_numa_stats(stats: &NumaStats) {
+    let registry = global();
+    registry.set(
+        "opdbus_numa_accesses",
+        "Cache accesses by NUMA locality",
+        &[("locality", "local")],
+        stats.local_accesses as f64,
+    );
+    registry.set(
+        "opdbus_numa_accesses",
+        "Cache accesses by NUMA locality",
+        &[("locality", "remote")],
+        stats.remote_accesses as f64,
+    );
+    registry.set(
+        "opdbus_numa_avg_latency_seconds",
+        "Average cache access latency",
+        &[],
+        stats.avg_latency_ns() as f64 / 1e9,
+    );
+    registry.set(
+        "opdbus_numa_local_hit_ratio",
+        "Fraction of cache accesses served from the local NUMA node",
+        &[],
+        stats.local_hit_rate(),
+    );
+}
+
+/// Record the current blockchain height (number of blocks written)
+pub fn record_blockchain_height(height: u64) {
+    global().set(
+        "opdbus_blockchain_height",
+        "Number of blocks in the streaming blockchain",
+        &[],
+        height as f64,
+    );
+}
+
+/// Record an MCP tool invocation
+pub fn record_tool_call(tool: &str, success: bool) {
+    global().inc(
+        "opdbus_mcp_tool_calls_total",
+        "MCP tool invocations by result",
+        &[
+            ("tool", tool),
+            ("result", if success { "success" } else { "error" }),
+        ],
+    );
+}
+
+/// Record a published event-bus event
+pub fn record_event(event_type: &str) {
+    global().inc(
+        "opdbus_events_total",
+        "Events published on the event bus",
+        &[("event", event_type)],
+    );
+}
+
+/// Serve `/metrics` on a standalone TCP listener (used when the web UI is not running)
+pub async fn serve(addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind metrics listener on {}", addr))?;
+    log::info!("Metrics available at http://{}/metrics", addr);
+
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let n = match stream.read(&mut buf).await {
+                Ok(n) => n,
+                Err(_) => return,
+            };
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let path = request
+                .lines()
+                .next()
+                .and_then(|line| line.split_whitespace().nth(1))
+                .unwrap_or("");
+
+            let response = if path == "/metrics" {
+                let body = global().render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    CONTENT_TYPE,
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters_and_gauges() {
+        let registry = Registry::new();
+        registry.inc("opdbus_test_total", "Test counter", &[("plugin", "net")]);
+        registry.inc("opdbus_test_total", "Test counter", &[("plugin", "net")]);
+        registry.set("opdbus_test_gauge", "Test gauge", &[], 2.5);
+
+        let out = registry.render();
+        assert!(out.contains("# TYPE opdbus_test_total counter"));
+        assert!(out.contains("opdbus_test_total{plugin=\"net\"} 2"));
+        assert!(out.contains("opdbus_test_gauge 2.5"));
+    }
+
+    #[test]
+    fn test_render_histogram() {
+        let registry = Registry::new();
+        registry.observe(
+            "opdbus_test_seconds",
+            "Test histogram",
+            &[("plugin", "lxc")],
+            0.02,
+        );
+        registry.observe(
+            "opdbus_test_seconds",
+            "Test histogram",
+            &[("plugin", "lxc")],
+            3.0,
+        );
+
+        let out = registry.render();
+        assert!(out.contains("opdbus_test_seconds_bucket{plugin=\"lxc\",le=\"0.01\"} 0"));
+        assert!(out.contains("opdbus_test_seconds_bucket{plugin=\"lxc\",le=\"0.025\"} 1"));
+        assert!(out.contains("opdbus_test_seconds_bucket{plugin=\"lxc\",le=\"5\"} 2"));
+        assert!(out.contains("opdbus_test_seconds_bucket{plugin=\"lxc\",le=\"+Inf\"} 2"));
+        assert!(out.contains("opdbus_test_seconds_count{plugin=\"lxc\"} 2"));
+    }
+
+    #[test]
+    fn test_cache_stats_export() {
+        let mut numa = NumaStats::new();
+        numa.record_local_access(3_000);
+        numa.record_remote_access(1_000);
+        let stats = CacheStats {
+            total_entries: 4,
+            hot_entries: 1,
+            total_accesses: 9,
+            disk_usage_bytes: 300,
+            embeddings_size_bytes: 100,
+            blocks_size_bytes: 200,
+            block_entries: 2,
+            embedding_evictions: Default::default(),
+            block_evictions: crate::cache::eviction::EvictionStats {
+                evicted_entries: 5,
+                evicted_bytes: 500,
+                last_eviction_at: None,
+            },
+            numa,
+        };
+        record_cache_stats(&stats);
+
+        let registry = global();
+        assert_eq!(
+            registry.value("opdbus_cache_entries", &[("namespace", "blocks")]),
+            Some(2.0)
+        );
+        assert_eq!(
+            registry.value("opdbus_cache_evicted_bytes", &[("namespace", "blocks")]),
+            Some(500.0)
+        );
+        assert_eq!(
+            registry.value("opdbus_numa_accesses", &[("locality", "remote")]),
+            Some(1.0)
+        );
+        assert_eq!(
+            registry.value("opdbus_numa_local_hit_ratio", &[]),
+            Some(0.5)
+        );
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        let registry = Registry::new();
+        registry.inc("opdbus_test_total", "Test counter", &[("tool", "a\"b")]);
+        assert!(registry.render().contains("tool=\"a\\\"b\""));
+    }
+}
@@ -1,7 +1,16 @@
 //! Native protocol implementations - no wrappers
+pub mod nftables;
 pub mod openflow;
+pub mod openflow_msg;
+pub mod openflow_trace;
 pub mod ovsdb_jsonrpc;
+pub mod ovsdb_monitor;
+pub mod ovsdb_txn;
 pub mod rtnetlink_helpers;
+pub mod rtnetlink_links;
+pub mod rtnetlink_monitor;
+pub mod rtnetlink_routes;
+pub mod wireguard;
 
 pub use ovsdb_jsonrpc::OvsdbClient;
 // rtnetlink_helpers functions accessed via rtnetlink_helpers::function_name
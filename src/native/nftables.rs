@@ -0,0 +1,1353 @@
+//! Native nftables client - nf_tables over nfnetlink, no nft binary
+//!
+//! Dumps tables, chains, sets, set elements and rules, and commits changes as
+//! one nfnetlink batch, which the kernel applies as a single transaction:
+//! either every message in the batch takes effect or none does. Rule
+//! expressions the plugin compiles to are decoded into [`Expr`]; anything else
+//! is kept raw so rules written by other tools survive a round trip.
+
+use anyhow::{anyhow, bail, Context, Result};
+use netlink_sys::{protocols::NETLINK_NETFILTER, Socket, SocketAddr};
+use std::collections::HashMap;
+use std::io;
+
+const NLMSG_HDRLEN: usize = 16;
+const NFGENMSG_LEN: usize = 4;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+pub const NLM_F_CREATE: u16 = 0x400;
+pub const NLM_F_APPEND: u16 = 0x800;
+
+const NLA_F_NESTED: u16 = 0x8000;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const NFNL_SUBSYS_NFTABLES: u16 = 10;
+const NFNL_MSG_BATCH_BEGIN: u16 = 16;
+const NFNL_MSG_BATCH_END: u16 = 17;
+
+/// nf_tables message types (low byte of the netlink message type)
+pub mod msg {
+    pub const NEWTABLE: u16 = 0;
+    pub const GETTABLE: u16 = 1;
+    pub const DELTABLE: u16 = 2;
+    pub const NEWCHAIN: u16 = 3;
+    pub const GETCHAIN: u16 = 4;
+    pub const NEWRULE: u16 = 6;
+    pub const GETRULE: u16 = 7;
+    pub const NEWSET: u16 = 9;
+    pub const GETSET: u16 = 10;
+    pub const NEWSETELEM: u16 = 12;
+    pub const GETSETELEM: u16 = 13;
+
+    pub fn name(kind: u16) -> &'static str {
+        match kind {
+            NEWTABLE => "NEWTABLE",
+            DELTABLE => "DELTABLE",
+            NEWCHAIN => "NEWCHAIN",
+            NEWRULE => "NEWRULE",
+            NEWSET => "NEWSET",
+            NEWSETELEM => "NEWSETELEM",
+            _ => "message",
+        }
+    }
+}
+
+mod table_attr {
+    pub const NAME: u16 = 1;
+    pub const FLAGS: u16 = 2;
+    pub const USE: u16 = 3;
+    pub const HANDLE: u16 = 4;
+    pub const PAD: u16 = 5;
+    pub const USERDATA: u16 = 6;
+    pub const OWNER: u16 = 7;
+    /// NFT_TABLE_F_OWNER: the table dies with the socket that created it
+    pub const F_OWNER: u32 = 0x2;
+}
+
+mod chain_attr {
+    pub const TABLE: u16 = 1;
+    pub const HANDLE: u16 = 2;
+    pub const NAME: u16 = 3;
+    pub const HOOK: u16 = 4;
+    pub const POLICY: u16 = 5;
+    pub const USE: u16 = 6;
+    pub const TYPE: u16 = 7;
+    pub const PAD: u16 = 9;
+    pub const ID: u16 = 11;
+    pub const HOOK_NUM: u16 = 1;
+    pub const HOOK_PRIORITY: u16 = 2;
+    pub const HOOK_DEV: u16 = 3;
+}
+
+mod rule_attr {
+    pub const TABLE: u16 = 1;
+    pub const CHAIN: u16 = 2;
+    pub const HANDLE: u16 = 3;
+    pub const EXPRESSIONS: u16 = 4;
+    pub const POSITION: u16 = 6;
+    pub const USERDATA: u16 = 7;
+    pub const PAD: u16 = 8;
+    pub const ID: u16 = 9;
+}
+
+mod set_attr {
+    pub const TABLE: u16 = 1;
+    pub const NAME: u16 = 2;
+    pub const FLAGS: u16 = 3;
+    pub const KEY_TYPE: u16 = 4;
+    pub const KEY_LEN: u16 = 5;
+    pub const ID: u16 = 10;
+    pub const PAD: u16 = 14;
+    pub const HANDLE: u16 = 16;
+}
+
+mod elem_attr {
+    pub const LIST_TABLE: u16 = 1;
+    pub const LIST_SET: u16 = 2;
+    pub const LIST_ELEMENTS: u16 = 3;
+    pub const KEY: u16 = 1;
+}
+
+const NFTA_LIST_ELEM: u16 = 1;
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+const NFTA_DATA_VALUE: u16 = 1;
+const NFTA_DATA_VERDICT: u16 = 2;
+const NFTA_VERDICT_CODE: u16 = 1;
+const NFTA_VERDICT_CHAIN: u16 = 2;
+/// NFTNL_UDATA_*_COMMENT, the TLV nft uses for comments in userdata
+const UDATA_COMMENT: u8 = 0;
+
+/// Verdict register; data registers start at 1
+pub const REG_VERDICT: u32 = 0;
+pub const REG_1: u32 = 1;
+
+pub mod meta_key {
+    pub const IIFNAME: u32 = 6;
+    pub const OIFNAME: u32 = 7;
+    pub const NFPROTO: u32 = 15;
+    pub const L4PROTO: u32 = 16;
+}
+
+pub mod payload_base {
+    pub const NETWORK: u32 = 1;
+    pub const TRANSPORT: u32 = 2;
+}
+
+pub mod cmp_op {
+    pub const EQ: u32 = 0;
+    pub const NEQ: u32 = 1;
+}
+
+pub const CT_KEY_STATE: u32 = 0;
+pub const NFT_LOOKUP_F_INV: u32 = 1;
+
+/// Set flags
+pub const NFT_SET_CONSTANT: u32 = 0x2;
+pub const NFT_SET_INTERVAL: u32 = 0x4;
+pub const NFT_SET_MAP: u32 = 0x8;
+
+/// nftables address families
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Family {
+    Inet,
+    Ip,
+    Arp,
+    Netdev,
+    Bridge,
+    Ip6,
+}
+
+impl Family {
+    pub fn code(self) -> u8 {
+        match self {
+            Family::Inet => 1,
+            Family::Ip => 2,
+            Family::Arp => 3,
+            Family::Netdev => 5,
+            Family::Bridge => 7,
+            Family::Ip6 => 10,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            1 => Family::Inet,
+            2 => Family::Ip,
+            3 => Family::Arp,
+            5 => Family::Netdev,
+            7 => Family::Bridge,
+            10 => Family::Ip6,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Family::Inet => "inet",
+            Family::Ip => "ip",
+            Family::Arp => "arp",
+            Family::Netdev => "netdev",
+            Family::Bridge => "bridge",
+            Family::Ip6 => "ip6",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "inet" => Family::Inet,
+            "ip" => Family::Ip,
+            "arp" => Family::Arp,
+            "netdev" => Family::Netdev,
+            "bridge" => Family::Bridge,
+            "ip6" => Family::Ip6,
+            other => bail!("Unknown nftables family '{}'", other),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub family: Family,
+    pub name: String,
+    pub flags: u32,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    pub num: u32,
+    pub priority: i32,
+    pub device: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chain {
+    pub family: Family,
+    pub table: String,
+    pub name: String,
+    /// Base chains are attached to a hook; regular chains are only jumped to
+    pub hook: Option<Hook>,
+    pub chain_type: Option<String>,
+    /// Base chain policy as a verdict code (NF_ACCEPT or NF_DROP)
+    pub policy: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Set {
+    pub family: Family,
+    pub table: String,
+    pub name: String,
+    pub flags: u32,
+    pub key_type: u32,
+    pub key_len: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub family: Family,
+    pub table: String,
+    pub chain: String,
+    pub expressions: Vec<Expr>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    Continue,
+    Return,
+    Jump(String),
+    Goto(String),
+}
+
+impl Verdict {
+    pub const NF_DROP: u32 = 0;
+    pub const NF_ACCEPT: u32 = 1;
+    const CONTINUE: i32 = -1;
+    const JUMP: i32 = -3;
+    const GOTO: i32 = -4;
+    const RETURN: i32 = -5;
+
+    fn code(&self) -> i32 {
+        match self {
+            Verdict::Accept => Self::NF_ACCEPT as i32,
+            Verdict::Drop => Self::NF_DROP as i32,
+            Verdict::Continue => Self::CONTINUE,
+            Verdict::Return => Self::RETURN,
+            Verdict::Jump(_) => Self::JUMP,
+            Verdict::Goto(_) => Self::GOTO,
+        }
+    }
+
+    fn from_code(code: i32, chain: Option<String>) -> Option<Self> {
+        Some(match (code, chain) {
+            (0, _) => Verdict::Drop,
+            (1, _) => Verdict::Accept,
+            (Self::CONTINUE, _) => Verdict::Continue,
+            (Self::RETURN, _) => Verdict::Return,
+            (Self::JUMP, Some(chain)) => Verdict::Jump(chain),
+            (Self::GOTO, Some(chain)) => Verdict::Goto(chain),
+            _ => return None,
+        })
+    }
+}
+
+/// A rule expression; only the forms the nft plugin generates are typed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// Load a meta key into `dreg`
+    Meta {
+        key: u32,
+        dreg: u32,
+    },
+    /// Load `len` bytes at `offset` from a header into `dreg`
+    Payload {
+        base: u32,
+        offset: u32,
+        len: u32,
+        dreg: u32,
+    },
+    Cmp {
+        sreg: u32,
+        op: u32,
+        data: Vec<u8>,
+    },
+    /// `dreg = (sreg & mask) ^ xor`
+    Bitwise {
+        sreg: u32,
+        dreg: u32,
+        mask: Vec<u8>,
+        xor: Vec<u8>,
+    },
+    /// Load a conntrack key into `dreg`
+    Ct {
+        key: u32,
+        dreg: u32,
+    },
+    Lookup {
+        set: String,
+        sreg: u32,
+        invert: bool,
+    },
+    Counter {
+        packets: u64,
+        bytes: u64,
+    },
+    /// Immediate verdict
+    Verdict(Verdict),
+    /// Anything else, kept as the expression's name and encoded data
+    Raw {
+        name: String,
+        data: Vec<u8>,
+    },
+}
+
+impl Expr {
+    pub fn name(&self) -> &str {
+        match self {
+            Expr::Meta { .. } => "meta",
+            Expr::Payload { .. } => "payload",
+            Expr::Cmp { .. } => "cmp",
+            Expr::Bitwise { .. } => "bitwise",
+            Expr::Ct { .. } => "ct",
+            Expr::Lookup { .. } => "lookup",
+            Expr::Counter { .. } => "counter",
+            Expr::Verdict(_) => "immediate",
+            Expr::Raw { name, .. } => name,
+        }
+    }
+
+    fn encode(&self, out: &mut Attrs) {
+        let mut data = Attrs::default();
+        match self {
+            Expr::Meta { key, dreg } => {
+                data.u32(1, *dreg).u32(2, *key);
+            }
+            Expr::Payload {
+                base,
+                offset,
+                len,
+                dreg,
+            } => {
+                data.u32(1, *dreg)
+                    .u32(2, *base)
+                    .u32(3, *offset)
+                    .u32(4, *len);
+            }
+            Expr::Cmp {
+                sreg,
+                op,
+                data: value,
+            } => {
+                data.u32(1, *sreg).u32(2, *op).data(3, value);
+            }
+            Expr::Bitwise {
+                sreg,
+                dreg,
+                mask,
+                xor,
+            } => {
+                data.u32(1, *sreg)
+                    .u32(2, *dreg)
+                    .u32(3, mask.len() as u32)
+                    .data(4, mask)
+                    .data(5, xor);
+            }
+            Expr::Ct { key, dreg } => {
+                data.u32(1, *dreg).u32(2, *key);
+            }
+            Expr::Lookup { set, sreg, invert } => {
+                data.str(1, set).u32(2, *sreg);
+                if *invert {
+                    data.u32(5, NFT_LOOKUP_F_INV);
+                }
+            }
+            Expr::Counter { packets, bytes } => {
+                if *packets != 0 || *bytes != 0 {
+                    data.u64(1, *bytes).u64(2, *packets);
+                }
+            }
+            Expr::Verdict(verdict) => {
+                data.u32(1, REG_VERDICT).nested(2, |value| {
+                    value.nested(NFTA_DATA_VERDICT, |v| {
+                        v.u32(NFTA_VERDICT_CODE, verdict.code() as u32);
+                        if let Verdict::Jump(chain) | Verdict::Goto(chain) = verdict {
+                            v.str(NFTA_VERDICT_CHAIN, chain);
+                        }
+                    });
+                });
+            }
+            Expr::Raw { data: raw, .. } => data.0.extend_from_slice(raw),
+        }
+        out.nested(NFTA_LIST_ELEM, |elem| {
+            elem.str(NFTA_EXPR_NAME, self.name());
+            elem.bytes(NFTA_EXPR_DATA | NLA_F_NESTED, &data.0);
+        });
+    }
+
+    fn decode(elem: &[u8]) -> Option<Expr> {
+        let attrs = AttrSet::parse(elem);
+        let name = attrs.str(NFTA_EXPR_NAME)?;
+        let raw = attrs.get(NFTA_EXPR_DATA).unwrap_or_default();
+        let data = AttrSet::parse(raw);
+        let typed = match name.as_str() {
+            "meta" if data.get(3).is_none() => Some(Expr::Meta {
+                dreg: data.u32(1)?,
+                key: data.u32(2)?,
+            }),
+            "payload" if data.get(1).is_some() => Some(Expr::Payload {
+                dreg: data.u32(1)?,
+                base: data.u32(2)?,
+                offset: data.u32(3)?,
+                len: data.u32(4)?,
+            }),
+            "cmp" => Some(Expr::Cmp {
+                sreg: data.u32(1)?,
+                op: data.u32(2)?,
+                data: data.value(3)?,
+            }),
+            // Only mask-and-xor bitwise ops; shifts carry an NFTA_BITWISE_OP
+            "bitwise" if data.u32(6).unwrap_or(0) == 0 => Some(Expr::Bitwise {
+                sreg: data.u32(1)?,
+                dreg: data.u32(2)?,
+                mask: data.value(4)?,
+                xor: data.value(5)?,
+            }),
+            "ct" if data.get(1).is_some() && data.get(3).is_none() => Some(Expr::Ct {
+                dreg: data.u32(1)?,
+                key: data.u32(2)?,
+            }),
+            "lookup" if data.get(3).is_none() => Some(Expr::Lookup {
+                set: data.str(1)?,
+                sreg: data.u32(2)?,
+                invert: data.u32(5).unwrap_or(0) & NFT_LOOKUP_F_INV != 0,
+            }),
+            "counter" => Some(Expr::Counter {
+                bytes: data.u64(1).unwrap_or(0),
+                packets: data.u64(2).unwrap_or(0),
+            }),
+            "immediate" if data.u32(1) == Some(REG_VERDICT) => {
+                let value = AttrSet::parse(data.get(2)?);
+                let verdict = AttrSet::parse(value.get(NFTA_DATA_VERDICT)?);
+                Verdict::from_code(
+                    verdict.u32(NFTA_VERDICT_CODE)? as i32,
+                    verdict.str(NFTA_VERDICT_CHAIN),
+                )
+                .map(Expr::Verdict)
+            }
+            _ => None,
+        };
+        Some(typed.unwrap_or(Expr::Raw {
+            name,
+            data: raw.to_vec(),
+        }))
+    }
+}
+
+/// One nf_tables message, as dumped or as queued in a [`Batch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// `msg::*` type
+    pub kind: u16,
+    pub family: u8,
+    /// Extra netlink flags (NLM_F_CREATE, ...)
+    pub flags: u16,
+    pub attrs: Vec<u8>,
+}
+
+impl Message {
+    /// Turn a dumped object into a message that recreates it: dump-only
+    /// attributes (handles, use counts) are dropped and table ownership is
+    /// cleared so a restored table doesn't die with our socket
+    pub fn to_replay(&self) -> Option<Message> {
+        let (kind, strip): (u16, &[u16]) = match self.kind {
+            msg::NEWTABLE => (
+                msg::NEWTABLE,
+                &[
+                    table_attr::USE,
+                    table_attr::HANDLE,
+                    table_attr::PAD,
+                    table_attr::OWNER,
+                ],
+            ),
+            msg::NEWCHAIN => (
+                msg::NEWCHAIN,
+                &[
+                    chain_attr::HANDLE,
+                    chain_attr::USE,
+                    chain_attr::PAD,
+                    chain_attr::ID,
+                ],
+            ),
+            msg::NEWSET => (msg::NEWSET, &[set_attr::HANDLE, set_attr::PAD]),
+            msg::NEWSETELEM => (msg::NEWSETELEM, &[]),
+            msg::NEWRULE => (
+                msg::NEWRULE,
+                &[
+                    rule_attr::HANDLE,
+                    rule_attr::POSITION,
+                    rule_attr::PAD,
+                    rule_attr::ID,
+                ],
+            ),
+            _ => return None,
+        };
+
+        let mut attrs = Attrs::default();
+        for (attr_kind, nested, value) in AttrSet::parse(&self.attrs).raw() {
+            if strip.contains(&attr_kind) {
+                continue;
+            }
+            let kind = if nested {
+                attr_kind | NLA_F_NESTED
+            } else {
+                attr_kind
+            };
+            if self.kind == msg::NEWTABLE && attr_kind == table_attr::FLAGS && value.len() == 4 {
+                let flags = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+                attrs.u32(kind, flags & !table_attr::F_OWNER);
+            } else {
+                attrs.bytes(kind, value);
+            }
+        }
+
+        let flags = match kind {
+            msg::NEWRULE => NLM_F_CREATE | NLM_F_APPEND,
+            _ => NLM_F_CREATE,
+        };
+        Some(Message {
+            kind,
+            family: self.family,
+            flags,
+            attrs: attrs.0,
+        })
+    }
+
+    /// (family, table name) the message belongs to
+    pub fn table(&self) -> Option<(u8, String)> {
+        // Every object names its table in attribute 1, tables name themselves
+        AttrSet::parse(&self.attrs)
+            .str(1)
+            .map(|name| (self.family, name))
+    }
+}
+
+/// Messages committed together as one kernel transaction
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    messages: Vec<Message>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    pub fn push(&mut self, message: Message) -> &mut Self {
+        self.messages.push(message);
+        self
+    }
+
+    fn message(&mut self, kind: u16, family: Family, flags: u16, attrs: Attrs) -> &mut Self {
+        self.push(Message {
+            kind,
+            family: family.code(),
+            flags,
+            attrs: attrs.0,
+        })
+    }
+
+    pub fn add_table(&mut self, table: &Table) -> &mut Self {
+        let mut attrs = Attrs::default();
+        attrs.str(table_attr::NAME, &table.name);
+        attrs.u32(table_attr::FLAGS, table.flags);
+        if let Some(comment) = &table.comment {
+            attrs.bytes(table_attr::USERDATA, &udata_comment(comment));
+        }
+        self.message(msg::NEWTABLE, table.family, NLM_F_CREATE, attrs)
+    }
+
+    pub fn delete_table(&mut self, family: Family, name: &str) -> &mut Self {
+        let mut attrs = Attrs::default();
+        attrs.str(table_attr::NAME, name);
+        self.message(msg::DELTABLE, family, 0, attrs)
+    }
+
+    pub fn add_chain(&mut self, chain: &Chain) -> &mut Self {
+        let mut attrs = Attrs::default();
+        attrs.str(chain_attr::TABLE, &chain.table);
+        attrs.str(chain_attr::NAME, &chain.name);
+        if let Some(hook) = &chain.hook {
+            attrs.nested(chain_attr::HOOK, |h| {
+                h.u32(chain_attr::HOOK_NUM, hook.num);
+                h.u32(chain_attr::HOOK_PRIORITY, hook.priority as u32);
+                if let Some(device) = &hook.device {
+                    h.str(chain_attr::HOOK_DEV, device);
+                }
+            });
+        }
+        if let Some(policy) = chain.policy {
+            attrs.u32(chain_attr::POLICY, policy);
+        }
+        if let Some(chain_type) = &chain.chain_type {
+            attrs.str(chain_attr::TYPE, chain_type);
+        }
+        self.message(msg::NEWCHAIN, chain.family, NLM_F_CREATE, attrs)
+    }
+
+    pub fn add_set(&mut self, set: &Set) -> &mut Self {
+        let id = self.messages.len() as u32 + 1;
+        let mut attrs = Attrs::default();
+        attrs.str(set_attr::TABLE, &set.table);
+        attrs.str(set_attr::NAME, &set.name);
+        attrs.u32(set_attr::FLAGS, set.flags);
+        attrs.u32(set_attr::KEY_TYPE, set.key_type);
+        attrs.u32(set_attr::KEY_LEN, set.key_len);
+        attrs.u32(set_attr::ID, id);
+        self.message(msg::NEWSET, set.family, NLM_F_CREATE, attrs)
+    }
+
+    pub fn add_elements(
+        &mut self,
+        family: Family,
+        table: &str,
+        set: &str,
+        keys: &[Vec<u8>],
+    ) -> &mut Self {
+        if keys.is_empty() {
+            return self;
+        }
+        let mut attrs = Attrs::default();
+        attrs.str(elem_attr::LIST_TABLE, table);
+        attrs.str(elem_attr::LIST_SET, set);
+        attrs.nested(elem_attr::LIST_ELEMENTS, |list| {
+            for key in keys {
+                list.nested(NFTA_LIST_ELEM, |elem| {
+                    elem.data(elem_attr::KEY, key);
+                });
+            }
+        });
+        self.message(msg::NEWSETELEM, family, NLM_F_CREATE, attrs)
+    }
+
+    pub fn add_rule(&mut self, rule: &Rule) -> &mut Self {
+        let mut attrs = Attrs::default();
+        attrs.str(rule_attr::TABLE, &rule.table);
+        attrs.str(rule_attr::CHAIN, &rule.chain);
+        attrs.nested(rule_attr::EXPRESSIONS, |list| {
+            for expr in &rule.expressions {
+                expr.encode(list);
+            }
+        });
+        if let Some(comment) = &rule.comment {
+            attrs.bytes(rule_attr::USERDATA, &udata_comment(comment));
+        }
+        self.message(
+            msg::NEWRULE,
+            rule.family,
+            NLM_F_CREATE | NLM_F_APPEND,
+            attrs,
+        )
+    }
+
+    /// Wire encoding: BATCH_BEGIN, the messages with sequence numbers from
+    /// `seq + 1`, BATCH_END
+    fn encode(&self, seq: u32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push_message(
+            &mut buf,
+            NFNL_MSG_BATCH_BEGIN,
+            NLM_F_REQUEST,
+            seq,
+            0,
+            NFNL_SUBSYS_NFTABLES,
+            &[],
+        );
+        for (i, message) in self.messages.iter().enumerate() {
+            push_message(
+                &mut buf,
+                NFNL_SUBSYS_NFTABLES << 8 | message.kind,
+                NLM_F_REQUEST | NLM_F_ACK | message.flags,
+                seq + 1 + i as u32,
+                message.family,
+                0,
+                &message.attrs,
+            );
+        }
+        push_message(
+            &mut buf,
+            NFNL_MSG_BATCH_END,
+            NLM_F_REQUEST,
+            seq + 1 + self.messages.len() as u32,
+            0,
+            NFNL_SUBSYS_NFTABLES,
+            &[],
+        );
+        buf
+    }
+}
+
+fn push_message(
+    buf: &mut Vec<u8>,
+    message_type: u16,
+    flags: u16,
+    seq: u32,
+    family: u8,
+    res_id: u16,
+    attrs: &[u8],
+) {
+    let len = NLMSG_HDRLEN + NFGENMSG_LEN + attrs.len();
+    buf.extend_from_slice(&(len as u32).to_ne_bytes());
+    buf.extend_from_slice(&message_type.to_ne_bytes());
+    buf.extend_from_slice(&flags.to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    // nfgenmsg: family, NFNETLINK_V0, resource id (big endian)
+    buf.push(family);
+    buf.push(0);
+    buf.extend_from_slice(&res_id.to_be_bytes());
+    buf.extend_from_slice(attrs);
+}
+
+/// A received netlink message
+struct Received<'a> {
+    message_type: u16,
+    seq: u32,
+    payload: &'a [u8],
+}
+
+fn split_messages(mut buf: &[u8]) -> Vec<Received<'_>> {
+    let mut messages = Vec::new();
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            break;
+        }
+        messages.push(Received {
+            message_type: u16::from_ne_bytes([buf[4], buf[5]]),
+            seq: u32::from_ne_bytes([buf[8], buf[9], buf[10], buf[11]]),
+            payload: &buf[NLMSG_HDRLEN..len],
+        });
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+    messages
+}
+
+/// Errno carried by an NLMSG_ERROR payload (0 is an ack)
+fn error_code(payload: &[u8]) -> i32 {
+    payload
+        .get(..4)
+        .map_or(0, |b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Netlink attribute builder
+#[derive(Debug, Default)]
+struct Attrs(Vec<u8>);
+
+impl Attrs {
+    fn bytes(&mut self, kind: u16, value: &[u8]) -> &mut Self {
+        let len = 4 + value.len();
+        self.0.extend_from_slice(&(len as u16).to_ne_bytes());
+        self.0.extend_from_slice(&kind.to_ne_bytes());
+        self.0.extend_from_slice(value);
+        while !self.0.len().is_multiple_of(4) {
+            self.0.push(0);
+        }
+        self
+    }
+
+    fn str(&mut self, kind: u16, value: &str) -> &mut Self {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.bytes(kind, &bytes)
+    }
+
+    fn u32(&mut self, kind: u16, value: u32) -> &mut Self {
+        self.bytes(kind, &value.to_be_bytes())
+    }
+
+    fn u64(&mut self, kind: u16, value: u64) -> &mut Self {
+        self.bytes(kind, &value.to_be_bytes())
+    }
+
+    fn nested(&mut self, kind: u16, build: impl FnOnce(&mut Attrs)) -> &mut Self {
+        let mut inner = Attrs::default();
+        build(&mut inner);
+        self.bytes(kind | NLA_F_NESTED, &inner.0)
+    }
+
+    /// nft_data value: a nested NFTA_DATA_VALUE
+    fn data(&mut self, kind: u16, value: &[u8]) -> &mut Self {
+        self.nested(kind, |d| {
+            d.bytes(NFTA_DATA_VALUE, value);
+        })
+    }
+}
+
+/// Parsed attributes of one nesting level
+struct AttrSet<'a>(Vec<(u16, bool, &'a [u8])>);
+
+impl<'a> AttrSet<'a> {
+    fn parse(mut buf: &'a [u8]) -> Self {
+        let mut attrs = Vec::new();
+        while buf.len() >= 4 {
+            let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+            let kind = u16::from_ne_bytes([buf[2], buf[3]]);
+            if len < 4 || len > buf.len() {
+                break;
+            }
+            attrs.push((kind & NLA_TYPE_MASK, kind & NLA_F_NESTED != 0, &buf[4..len]));
+            buf = &buf[((len + 3) & !3).min(buf.len())..];
+        }
+        Self(attrs)
+    }
+
+    fn raw(&self) -> impl Iterator<Item = (u16, bool, &'a [u8])> + '_ {
+        self.0.iter().copied()
+    }
+
+    fn get(&self, kind: u16) -> Option<&'a [u8]> {
+        self.0
+            .iter()
+            .find(|(k, _, _)| *k == kind)
+            .map(|(_, _, v)| *v)
+    }
+
+    fn all(&self, kind: u16) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.0
+            .iter()
+            .filter(move |(k, _, _)| *k == kind)
+            .map(|(_, _, v)| *v)
+    }
+
+    fn str(&self, kind: u16) -> Option<String> {
+        let value = self.get(kind)?;
+        let end = value.iter().position(|b| *b == 0).unwrap_or(value.len());
+        Some(String::from_utf8_lossy(&value[..end]).into_owned())
+    }
+
+    fn u32(&self, kind: u16) -> Option<u32> {
+        let v = self.get(kind)?;
+        Some(u32::from_be_bytes(v.get(..4)?.try_into().ok()?))
+    }
+
+    fn u64(&self, kind: u16) -> Option<u64> {
+        let v = self.get(kind)?;
+        Some(u64::from_be_bytes(v.get(..8)?.try_into().ok()?))
+    }
+
+    fn value(&self, kind: u16) -> Option<Vec<u8>> {
+        AttrSet::parse(self.get(kind)?)
+            .get(NFTA_DATA_VALUE)
+            .map(<[u8]>::to_vec)
+    }
+}
+
+fn udata_comment(comment: &str) -> Vec<u8> {
+    // The TLV length is one byte, NUL included
+    let text = &comment.as_bytes()[..comment.len().min(254)];
+    let mut tlv = vec![UDATA_COMMENT, text.len() as u8 + 1];
+    tlv.extend_from_slice(text);
+    tlv.push(0);
+    tlv
+}
+
+fn udata_comment_of(udata: &[u8]) -> Option<String> {
+    let mut rest = udata;
+    while rest.len() >= 2 {
+        let (kind, len) = (rest[0], rest[1] as usize);
+        let value = rest.get(2..2 + len)?;
+        if kind == UDATA_COMMENT {
+            let end = value.iter().position(|b| *b == 0).unwrap_or(value.len());
+            return Some(String::from_utf8_lossy(&value[..end]).into_owned());
+        }
+        rest = &rest[2 + len..];
+    }
+    None
+}
+
+impl Table {
+    pub fn decode(message: &Message) -> Option<Self> {
+        let attrs = AttrSet::parse(&message.attrs);
+        Some(Table {
+            family: Family::from_code(message.family)?,
+            name: attrs.str(table_attr::NAME)?,
+            flags: attrs.u32(table_attr::FLAGS).unwrap_or(0),
+            comment: attrs.get(table_attr::USERDATA).and_then(udata_comment_of),
+        })
+    }
+}
+
+impl Chain {
+    pub fn decode(message: &Message) -> Option<Self> {
+        let attrs = AttrSet::parse(&message.attrs);
+        let hook = attrs.get(chain_attr::HOOK).map(AttrSet::parse);
+        Some(Chain {
+            family: Family::from_code(message.family)?,
+            table: attrs.str(chain_attr::TABLE)?,
+            name: attrs.str(chain_attr::NAME)?,
+            hook: match hook {
+                Some(hook) => Some(Hook {
+                    num: hook.u32(chain_attr::HOOK_NUM)?,
+                    priority: hook.u32(chain_attr::HOOK_PRIORITY).unwrap_or(0) as i32,
+                    device: hook.str(chain_attr::HOOK_DEV),
+                }),
+                None => None,
+            },
+            chain_type: attrs.str(chain_attr::TYPE),
+            policy: attrs.u32(chain_attr::POLICY),
+        })
+    }
+}
+
+impl Set {
+    pub fn decode(message: &Message) -> Option<Self> {
+        let attrs = AttrSet::parse(&message.attrs);
+        Some(Set {
+            family: Family::from_code(message.family)?,
+            table: attrs.str(set_attr::TABLE)?,
+            name: attrs.str(set_attr::NAME)?,
+            flags: attrs.u32(set_attr::FLAGS).unwrap_or(0),
+            key_type: attrs.u32(set_attr::KEY_TYPE).unwrap_or(0),
+            key_len: attrs.u32(set_attr::KEY_LEN).unwrap_or(0),
+        })
+    }
+}
+
+impl Rule {
+    pub fn decode(message: &Message) -> Option<Self> {
+        let attrs = AttrSet::parse(&message.attrs);
+        let expressions = attrs
+            .get(rule_attr::EXPRESSIONS)
+            .map(|list| {
+                AttrSet::parse(list)
+                    .all(NFTA_LIST_ELEM)
+                    .filter_map(Expr::decode)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Rule {
+            family: Family::from_code(message.family)?,
+            table: attrs.str(rule_attr::TABLE)?,
+            chain: attrs.str(rule_attr::CHAIN)?,
+            expressions,
+            comment: attrs.get(rule_attr::USERDATA).and_then(udata_comment_of),
+        })
+    }
+}
+
+/// Keys of the elements in a NEWSETELEM message
+fn element_keys(message: &Message) -> Vec<Vec<u8>> {
+    let attrs = AttrSet::parse(&message.attrs);
+    let Some(list) = attrs.get(elem_attr::LIST_ELEMENTS) else {
+        return Vec::new();
+    };
+    AttrSet::parse(list)
+        .all(NFTA_LIST_ELEM)
+        .filter_map(|elem| AttrSet::parse(elem).value(elem_attr::KEY))
+        .collect()
+}
+
+/// Everything in the kernel's nf_tables ruleset
+#[derive(Debug, Clone, Default)]
+pub struct Ruleset {
+    pub tables: Vec<Table>,
+    pub chains: Vec<Chain>,
+    pub sets: Vec<Set>,
+    /// Element keys per (family, table, set)
+    pub elements: HashMap<(Family, String, String), Vec<Vec<u8>>>,
+    /// Rules in chain order
+    pub rules: Vec<Rule>,
+}
+
+/// Synchronous nfnetlink socket; callers on the runtime wrap it in spawn_blocking
+pub struct NftClient {
+    socket: Socket,
+    seq: u32,
+}
+
+impl NftClient {
+    pub fn connect() -> Result<Self> {
+        let mut socket =
+            Socket::new(NETLINK_NETFILTER).context("Failed to open nfnetlink socket")?;
+        socket.bind_auto()?;
+        socket.connect(&SocketAddr::new(0, 0))?;
+        Ok(Self {
+            socket,
+            seq: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(1, |d| d.as_secs() as u32),
+        })
+    }
+
+    /// Dump every object of one kind
+    fn dump(&mut self, kind: u16, family: u8, attrs: &[u8]) -> Result<Vec<Message>> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        let mut request = Vec::new();
+        push_message(
+            &mut request,
+            NFNL_SUBSYS_NFTABLES << 8 | kind,
+            NLM_F_REQUEST | NLM_F_DUMP,
+            seq,
+            family,
+            0,
+            attrs,
+        );
+        self.socket.send(&request, 0)?;
+
+        let mut messages = Vec::new();
+        loop {
+            let (buf, _) = self.socket.recv_from_full()?;
+            for received in split_messages(&buf) {
+                if received.seq != seq {
+                    continue;
+                }
+                match received.message_type {
+                    NLMSG_DONE => return Ok(messages),
+                    NLMSG_ERROR => match error_code(received.payload) {
+                        0 => {}
+                        code => {
+                            return Err(io::Error::from_raw_os_error(-code))
+                                .with_context(|| format!("nftables dump of type {} failed", kind))
+                        }
+                    },
+                    message_type => {
+                        let Some(body) = received.payload.get(NFGENMSG_LEN..) else {
+                            continue;
+                        };
+                        messages.push(Message {
+                            kind: message_type & 0xff,
+                            family: received.payload[0],
+                            flags: 0,
+                            attrs: body.to_vec(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn dump_elements(&mut self, family: u8, table: &str, set: &str) -> Result<Vec<Message>> {
+        let mut attrs = Attrs::default();
+        attrs.str(elem_attr::LIST_TABLE, table);
+        attrs.str(elem_attr::LIST_SET, set);
+        self.dump(msg::GETSETELEM, family, &attrs.0)
+    }
+
+    /// Decoded view of the whole ruleset
+    pub fn ruleset(&mut self) -> Result<Ruleset> {
+        let mut ruleset = Ruleset {
+            tables: self
+                .dump(msg::GETTABLE, 0, &[])?
+                .iter()
+                .filter_map(Table::decode)
+                .collect(),
+            chains: self
+                .dump(msg::GETCHAIN, 0, &[])?
+                .iter()
+                .filter_map(Chain::decode)
+                .collect(),
+            sets: self
+                .dump(msg::GETSET, 0, &[])?
+                .iter()
+                .filter_map(Set::decode)
+                .collect(),
+            rules: self
+                .dump(msg::GETRULE, 0, &[])?
+                .iter()
+                .filter_map(Rule::decode)
+                .collect(),
+            ..Default::default()
+        };
+        for set in ruleset.sets.clone() {
+            let keys = self
+                .dump_elements(set.family.code(), &set.table, &set.name)?
+                .iter()
+                .flat_map(element_keys)
+                .collect();
+            ruleset
+                .elements
+                .insert((set.family, set.table.clone(), set.name.clone()), keys);
+        }
+        Ok(ruleset)
+    }
+
+    /// The whole ruleset as messages that recreate it, in dependency order
+    /// (tables, chains, sets, elements, rules)
+    pub fn capture(&mut self) -> Result<Vec<Message>> {
+        let mut messages = self.dump(msg::GETTABLE, 0, &[])?;
+        messages.extend(self.dump(msg::GETCHAIN, 0, &[])?);
+        let sets = self.dump(msg::GETSET, 0, &[])?;
+        messages.extend(sets.iter().cloned());
+        for set in sets.iter().filter_map(Set::decode) {
+            messages.extend(self.dump_elements(set.family.code(), &set.table, &set.name)?);
+        }
+        messages.extend(self.dump(msg::GETRULE, 0, &[])?);
+        Ok(messages.iter().filter_map(Message::to_replay).collect())
+    }
+
+    /// Commit a batch as one transaction; on error nothing in it is applied
+    pub fn commit(&mut self, batch: &Batch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let base = self.seq.wrapping_add(1);
+        self.seq = base.wrapping_add(batch.len() as u32 + 1);
+        self.socket.send(&batch.encode(base), 0)?;
+
+        // The kernel processes the batch inside send(), so every ack and
+        // error is already queued; drain without blocking
+        self.socket.set_non_blocking(true)?;
+        let mut errors = Vec::new();
+        let drained = loop {
+            let buf = match self.socket.recv_from_full() {
+                Ok((buf, _)) => buf,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            for received in split_messages(&buf) {
+                if received.message_type != NLMSG_ERROR {
+                    continue;
+                }
+                let code = error_code(received.payload);
+                if code == 0 {
+                    continue;
+                }
+                let error = io::Error::from_raw_os_error(-code);
+                let index = received.seq.wrapping_sub(base);
+                match batch.messages.get(index.wrapping_sub(1) as usize) {
+                    Some(message) if index > 0 => errors.push(format!(
+                        "{} {}: {}",
+                        msg::name(message.kind),
+                        describe(message),
+                        error
+                    )),
+                    _ => errors.push(format!("batch: {}", error)),
+                }
+            }
+        };
+        self.socket.set_non_blocking(false)?;
+        drained?;
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "nftables transaction rejected, nothing applied: {}",
+                errors.join("; ")
+            ))
+        }
+    }
+}
+
+/// "family table/object" for error messages
+fn describe(message: &Message) -> String {
+    let attrs = AttrSet::parse(&message.attrs);
+    let family = Family::from_code(message.family).map_or("?", Family::as_str);
+    match (attrs.str(1), attrs.str(2).or_else(|| attrs.str(3))) {
+        (Some(table), Some(object)) if message.kind != msg::NEWTABLE => {
+            format!("{} {}/{}", family, table, object)
+        }
+        (Some(table), _) => format!("{} {}", family, table),
+        _ => family.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept_ssh() -> Rule {
+        Rule {
+            family: Family::Inet,
+            table: "filter".to_string(),
+            chain: "input".to_string(),
+            expressions: vec![
+                Expr::Meta {
+                    key: meta_key::L4PROTO,
+                    dreg: REG_1,
+                },
+                Expr::Cmp {
+                    sreg: REG_1,
+                    op: cmp_op::EQ,
+                    data: vec![6],
+                },
+                Expr::Payload {
+                    base: payload_base::TRANSPORT,
+                    offset: 2,
+                    len: 2,
+                    dreg: REG_1,
+                },
+                Expr::Lookup {
+                    set: "ssh_ports".to_string(),
+                    sreg: REG_1,
+                    invert: true,
+                },
+                Expr::Counter {
+                    packets: 0,
+                    bytes: 0,
+                },
+                Expr::Verdict(Verdict::Jump("allowed".to_string())),
+            ],
+            comment: Some("ssh".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_rule_round_trip() {
+        let rule = accept_ssh();
+        let mut batch = Batch::new();
+        batch.add_rule(&rule);
+        let message = &batch.messages()[0];
+        assert_eq!(message.flags, NLM_F_CREATE | NLM_F_APPEND);
+        assert_eq!(Rule::decode(message), Some(rule));
+
+        // Unknown expressions survive as raw data
+        let mut raw = Attrs::default();
+        Expr::Raw {
+            name: "limit".to_string(),
+            data: vec![8, 0, 1, 0, 0, 0, 0, 10],
+        }
+        .encode(&mut raw);
+        let decoded = AttrSet::parse(&raw.0)
+            .all(NFTA_LIST_ELEM)
+            .filter_map(Expr::decode)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decoded,
+            vec![Expr::Raw {
+                name: "limit".to_string(),
+                data: vec![8, 0, 1, 0, 0, 0, 0, 10]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_batch_framing() {
+        let mut batch = Batch::new();
+        batch
+            .add_table(&Table {
+                family: Family::Inet,
+                name: "filter".to_string(),
+                flags: 0,
+                comment: Some("op-dbus".to_string()),
+            })
+            .add_set(&Set {
+                family: Family::Inet,
+                table: "filter".to_string(),
+                name: "ct".to_string(),
+                flags: 0,
+                key_type: 7,
+                key_len: 4,
+            })
+            .add_elements(Family::Inet, "filter", "ct", &[vec![10, 0, 0, 1]])
+            .add_elements(Family::Inet, "filter", "ct", &[]);
+        assert_eq!(batch.len(), 3);
+
+        let wire = batch.encode(100);
+        let messages = split_messages(&wire);
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[0].message_type, NFNL_MSG_BATCH_BEGIN);
+        assert_eq!(
+            messages[0].payload[2..4],
+            NFNL_SUBSYS_NFTABLES.to_be_bytes()
+        );
+        assert_eq!(messages[1].message_type, 10 << 8 | msg::NEWTABLE);
+        assert_eq!(messages[1].payload[0], Family::Inet.code());
+        assert_eq!(
+            messages.iter().map(|m| m.seq).collect::<Vec<_>>(),
+            vec![100, 101, 102, 103, 104]
+        );
+        assert_eq!(messages[4].message_type, NFNL_MSG_BATCH_END);
+
+        let table = Table::decode(&batch.messages()[0]).unwrap();
+        assert_eq!(table.comment.as_deref(), Some("op-dbus"));
+        assert_eq!(element_keys(&batch.messages()[2]), vec![vec![10, 0, 0, 1]]);
+    }
+
+    #[test]
+    fn test_replay_strips_dump_only_attributes() {
+        let mut attrs = Attrs::default();
+        attrs
+            .str(table_attr::NAME, "fw")
+            .u32(table_attr::FLAGS, table_attr::F_OWNER)
+            .u32(table_attr::USE, 3)
+            .u64(table_attr::HANDLE, 7)
+            .u32(table_attr::OWNER, 1234);
+        let dumped = Message {
+            kind: msg::NEWTABLE,
+            family: Family::Ip.code(),
+            flags: 0,
+            attrs: attrs.0,
+        };
+
+        let replay = dumped.to_replay().unwrap();
+        assert_eq!(replay.flags, NLM_F_CREATE);
+        let parsed = AttrSet::parse(&replay.attrs);
+        assert_eq!(parsed.str(table_attr::NAME).as_deref(), Some("fw"));
+        assert_eq!(parsed.u32(table_attr::FLAGS), Some(0));
+        assert!(parsed.get(table_attr::USE).is_none());
+        assert!(parsed.get(table_attr::HANDLE).is_none());
+        assert!(parsed.get(table_attr::OWNER).is_none());
+        assert_eq!(replay.table(), Some((Family::Ip.code(), "fw".to_string())));
+    }
+}
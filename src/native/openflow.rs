@@ -1,461 +1,639 @@
-//! Native OpenFlow protocol implementation
-//! Talks directly to OpenFlow switches without CLI tools
-#![allow(dead_code)]
-
-use anyhow::{Context, Result};
-use std::net::{Ipv4Addr, SocketAddr};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-
-/// Flow match field
-#[derive(Debug, Clone)]
-pub struct FlowMatch {
-    pub in_port: Option<u32>,
-    pub dl_src: Option<[u8; 6]>,
-    pub dl_dst: Option<[u8; 6]>,
-    pub dl_type: Option<u16>,
-    pub nw_src: Option<Ipv4Addr>,
-    pub nw_dst: Option<Ipv4Addr>,
-    pub nw_proto: Option<u8>,
-    pub tp_src: Option<u16>,
-    pub tp_dst: Option<u16>,
+//! Native OpenFlow 1.3 client
+//! Talks directly to OpenFlow switches without CLI tools - OVS bridges through
+//! their management socket (/var/run/openvswitch/<bridge>.mgmt), anything else over TCP
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+pub use super::openflow_msg::*;
+
+const OVS_RUNDIR: &str = "/var/run/openvswitch";
+const ECHO_INTERVAL: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Any byte stream an OpenFlow connection can run over
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Transport for T {}
+
+type Stream = Box<dyn Transport>;
+
+/// State shared between the client, its reader task and the keepalive task
+struct Shared {
+    writer: Mutex<WriteHalf<Stream>>,
+    /// Replies are routed to whoever is waiting on their xid
+    pending: std::sync::Mutex<HashMap<u32, mpsc::UnboundedSender<Message>>>,
+    next_xid: AtomicU32,
+    closed: AtomicBool,
 }
 
-/// Flow action
-#[derive(Debug, Clone)]
-pub enum FlowAction {
-    Output { port: u32 },
-    Drop,
-}
-
-/// Basic flow entry for OpenFlow operations
-#[derive(Debug, Clone)]
-pub struct FlowEntry {
-    pub priority: u16,
-    pub match_fields: FlowMatch,
-    pub actions: Vec<FlowAction>,
-    pub idle_timeout: u16,
-    pub hard_timeout: u16,
-    pub cookie: u64,
-}
-
-/// OpenFlow protocol versions
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum OpenFlowVersion {
-    V1_0 = 0x01,
-    V1_3 = 0x04,
-}
-
-impl OpenFlowVersion {
-    pub fn as_u8(self) -> u8 {
-        self as u8
+impl Shared {
+    fn next_xid(&self) -> u32 {
+        self.next_xid.fetch_add(1, Ordering::Relaxed)
     }
-}
-
-/// OpenFlow message types (core ones we need)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum OpenFlowMessageType {
-    Hello = 0,
-    Error = 1,
-    EchoRequest = 2,
-    EchoReply = 3,
-    FeaturesRequest = 5,
-    FeaturesReply = 6,
-    FlowMod = 14,
-    FlowRemoved = 11,
-    PacketIn = 10,
-    PacketOut = 13,
-    FlowStatsRequest = 16,
-    FlowStatsReply = 17,
-}
 
-impl OpenFlowMessageType {
-    pub fn as_u8(self) -> u8 {
-        self as u8
+    fn register(&self, xid: u32) -> mpsc::UnboundedReceiver<Message> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.pending.lock().unwrap().insert(xid, tx);
+        rx
     }
-}
-
-/// OpenFlow header (8 bytes)
-#[derive(Debug, Clone)]
-pub struct OpenFlowHeader {
-    pub version: u8,
-    pub message_type: u8,
-    pub length: u16,
-    pub xid: u32,
-}
 
-impl OpenFlowHeader {
-    pub fn new(message_type: OpenFlowMessageType, length: u16, xid: u32) -> Self {
-        Self {
-            version: OpenFlowVersion::V1_3.as_u8(),
-            message_type: message_type.as_u8(),
-            length,
-            xid,
-        }
+    fn unregister(&self, xid: u32) {
+        self.pending.lock().unwrap().remove(&xid);
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(8);
-        buf.push(self.version);
-        buf.push(self.message_type);
-        buf.extend_from_slice(&self.length.to_be_bytes());
-        buf.extend_from_slice(&self.xid.to_be_bytes());
-        buf
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // Dropping the senders wakes every waiter with "connection closed"
+        self.pending.lock().unwrap().clear();
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        if bytes.len() < 8 {
-            return Err(anyhow::anyhow!("Header too short"));
+    async fn write(&self, message: &Message, xid: u32) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            bail!("OpenFlow connection closed");
         }
+        let mut writer = self.writer.lock().await;
+        writer
+            .write_all(&message.encode(xid))
+            .await
+            .context("Failed to send OpenFlow message")?;
+        writer.flush().await?;
+        Ok(())
+    }
 
-        Ok(Self {
-            version: bytes[0],
-            message_type: bytes[1],
-            length: u16::from_be_bytes([bytes[2], bytes[3]]),
-            xid: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
-        })
+    /// Send a request and collect its reply; multipart replies are reassembled
+    async fn request(&self, message: &Message) -> Result<Vec<Message>> {
+        let xid = self.next_xid();
+        let mut rx = self.register(xid);
+
+        let exchange = async {
+            self.write(message, xid).await?;
+            let mut replies = Vec::new();
+            loop {
+                let reply = rx
+                    .recv()
+                    .await
+                    .ok_or_else(|| anyhow!("OpenFlow connection closed"))?;
+                match reply {
+                    Message::Error(error) => return Err(anyhow::Error::new(error)),
+                    Message::MultipartReply(part) if part.has_more() => {
+                        replies.push(Message::MultipartReply(part))
+                    }
+                    other => {
+                        replies.push(other);
+                        return Ok(replies);
+                    }
+                }
+            }
+        };
+        let result = tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+            .await
+            .unwrap_or_else(|_| {
+                Err(anyhow!(
+                    "Timed out waiting for reply to OpenFlow message type {}",
+                    message.message_type()
+                ))
+            });
+        self.unregister(xid);
+        result
     }
 }
 
-/// OpenFlow client
+/// OpenFlow 1.3 controller connection
+///
+/// A background task reads everything the switch sends: replies go to the
+/// request waiting on their xid, echo requests are answered, and asynchronous
+/// messages (packet-in, flow-removed, port-status) are broadcast to `events()`.
 pub struct OpenFlowClient {
-    stream: TcpStream,
-    next_xid: u32,
+    shared: Arc<Shared>,
+    events: broadcast::Sender<Message>,
+    features: FeaturesReply,
+    tasks: Vec<JoinHandle<()>>,
 }
 
 impl OpenFlowClient {
-    /// Connect to an OpenFlow switch
+    /// Connect to a switch over TCP
     pub async fn connect(addr: SocketAddr) -> Result<Self> {
         let stream = TcpStream::connect(addr)
             .await
-            .context("Failed to connect to OpenFlow switch")?;
-
-        let mut client = Self {
-            stream,
-            next_xid: 1,
-        };
-
-        // Perform OpenFlow handshake
-        client.handshake().await?;
-
-        Ok(client)
-    }
-
-    /// Perform OpenFlow handshake (Hello exchange)
-    async fn handshake(&mut self) -> Result<()> {
-        // Send Hello message
-        let hello_msg = OpenFlowHello::new();
-        self.send_message(&hello_msg).await?;
-
-        // Receive Hello reply (and possibly Features reply)
-        // This is simplified - real implementation would handle version negotiation
-        let _reply = self.receive_message().await?;
-
-        Ok(())
+            .context(format!("Failed to connect to OpenFlow switch at {}", addr))?;
+        Self::from_stream(stream).await
     }
 
-    /// Get next transaction ID
-    fn next_xid(&mut self) -> u32 {
-        let xid = self.next_xid;
-        self.next_xid += 1;
-        xid
+    /// Connect through a unix socket
+    pub async fn connect_unix(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let stream = UnixStream::connect(path).await.context(format!(
+            "Failed to connect to OpenFlow socket {}",
+            path.display()
+        ))?;
+        Self::from_stream(stream).await
     }
 
-    /// Send an OpenFlow message
-    async fn send_message(&mut self, message: &dyn OpenFlowMessage) -> Result<()> {
-        let payload = message.to_bytes();
-        let header = OpenFlowHeader::new(
-            message.message_type(),
-            (payload.len() + 8) as u16,
-            message.xid(),
+    /// Connect to an OVS bridge through its management socket
+    pub async fn connect_bridge(bridge: &str) -> Result<Self> {
+        Self::connect_unix(format!("{}/{}.mgmt", OVS_RUNDIR, bridge))
+            .await
+            .context(format!(
+                "Failed to open OpenFlow connection to bridge {}",
+                bridge
+            ))
+    }
+
+    /// Run the OpenFlow handshake over an already connected stream
+    pub async fn from_stream(stream: impl Transport) -> Result<Self> {
+        let mut stream: Stream = Box::new(stream);
+        let features = Self::handshake(&mut stream).await?;
+
+        let (reader, writer) = tokio::io::split(stream);
+        let shared = Arc::new(Shared {
+            writer: Mutex::new(writer),
+            pending: std::sync::Mutex::new(HashMap::new()),
+            next_xid: AtomicU32::new(2),
+            closed: AtomicBool::new(false),
+        });
+        let (events, _) = broadcast::channel(256);
+
+        let tasks = vec![
+            tokio::spawn(read_loop(reader, shared.clone(), events.clone())),
+            tokio::spawn(keepalive(shared.clone())),
+        ];
+
+        log::debug!(
+            "OpenFlow 1.3 session established with datapath {:016x}",
+            features.datapath_id
         );
-
-        let mut data = header.to_bytes();
-        data.extend_from_slice(&payload);
-
-        self.stream.write_all(&data).await?;
-        Ok(())
+        Ok(Self {
+            shared,
+            events,
+            features,
+            tasks,
+        })
     }
 
-    /// Receive an OpenFlow message
-    async fn receive_message(&mut self) -> Result<Box<dyn OpenFlowMessage>> {
-        // Read header first
-        let mut header_buf = [0u8; 8];
-        self.stream.read_exact(&mut header_buf).await?;
-        let header = OpenFlowHeader::from_bytes(&header_buf)?;
-
-        // Read payload
-        let payload_len = header.length as usize - 8;
-        let mut payload_buf = vec![0u8; payload_len];
-        if payload_len > 0 {
-            self.stream.read_exact(&mut payload_buf).await?;
+    /// Exchange hellos, negotiate OpenFlow 1.3 and fetch the switch features
+    async fn handshake(stream: &mut Stream) -> Result<FeaturesReply> {
+        stream.write_all(&Message::hello().encode(0)).await?;
+
+        let (_, hello) = read_message(stream).await?;
+        match hello {
+            Message::Hello { versions } if versions & (1 << OFP_VERSION) != 0 => {}
+            Message::Hello { versions } => {
+                let error = OpenFlowError {
+                    error_type: OpenFlowError::HELLO_FAILED,
+                    code: 0,
+                    data: b"OpenFlow 1.3 required".to_vec(),
+                };
+                stream.write_all(&Message::Error(error).encode(0)).await?;
+                bail!(
+                    "Switch does not support OpenFlow 1.3 (version bitmap {:#x})",
+                    versions
+                );
+            }
+            Message::Error(error) => return Err(anyhow::Error::new(error)),
+            other => bail!(
+                "Expected OpenFlow hello, got message type {}",
+                other.message_type()
+            ),
         }
 
-        // Parse message based on type
-        match header.message_type {
-            0 => Ok(Box::new(OpenFlowHello::from_bytes(header.xid, &payload_buf)?)),
-            6 => Ok(Box::new(OpenFlowFeaturesReply::from_bytes(header.xid, &payload_buf)?)),
-            _ => {
-                // For now, return a generic message
-                Ok(Box::new(OpenFlowGenericMessage {
-                    header,
-                    payload: payload_buf,
-                }))
+        stream
+            .write_all(&Message::FeaturesRequest.encode(1))
+            .await?;
+        loop {
+            let (header, message) = read_message(stream).await?;
+            match message {
+                Message::FeaturesReply(features) => return Ok(features),
+                Message::EchoRequest(data) => {
+                    stream
+                        .write_all(&Message::EchoReply(data).encode(header.xid))
+                        .await?
+                }
+                Message::Error(error) => return Err(anyhow::Error::new(error)),
+                _ => {}
             }
         }
     }
 
-    /// Add a flow entry
-    pub async fn add_flow(&mut self, flow: &FlowEntry) -> Result<()> {
-        let flow_mod = OpenFlowFlowMod::from_flow_entry(flow, self.next_xid());
-        self.send_message(&flow_mod).await?;
-        Ok(())
-    }
-
-    /// Add a flow rule from string (ovs-ofctl format)
-    pub async fn add_flow_rule(&mut self, rule: &str) -> Result<()> {
-        // For now, this is a placeholder - we'd need to parse ovs-ofctl format
-        // TODO: Implement ovs-ofctl format parsing
-        log::warn!("String-based flow rules not yet implemented: {}", rule);
-        Ok(())
+    /// Features reported during the handshake
+    pub fn features(&self) -> &FeaturesReply {
+        &self.features
     }
 
-    /// Delete all flows
-    pub async fn delete_all_flows(&mut self) -> Result<()> {
-        let flow_mod = OpenFlowFlowMod::delete_all(self.next_xid());
-        self.send_message(&flow_mod).await?;
-        Ok(())
+    pub fn is_connected(&self) -> bool {
+        !self.shared.closed.load(Ordering::SeqCst)
     }
 
-    /// Request switch features
-    pub async fn request_features(&mut self) -> Result<OpenFlowFeaturesReply> {
-        let request = OpenFlowFeaturesRequest::new(self.next_xid());
-        self.send_message(&request).await?;
-
-        // Wait for reply
-        let reply = self.receive_message().await?;
-        if let Some(features) = reply.as_any().downcast_ref::<OpenFlowFeaturesReply>() {
-            Ok(features.clone())
-        } else {
-            Err(anyhow::anyhow!("Expected FeaturesReply, got different message type"))
-        }
+    /// Subscribe to asynchronous switch messages
+    pub fn events(&self) -> broadcast::Receiver<Message> {
+        self.events.subscribe()
     }
 
-    /// Query flow statistics (basic implementation)
-    pub async fn query_flows(&mut self) -> Result<Vec<String>> {
-        // For now, return empty list - full flow stats parsing would be complex
-        // TODO: Implement proper flow statistics parsing
-        Ok(Vec::new())
+    /// Send a request and wait for its reply
+    pub async fn request(&self, message: &Message) -> Result<Vec<Message>> {
+        self.shared.request(message).await
     }
-}
-
-/// OpenFlow message trait
-trait OpenFlowMessage {
-    fn message_type(&self) -> OpenFlowMessageType;
-    fn xid(&self) -> u32;
-    fn to_bytes(&self) -> Vec<u8>;
-    fn as_any(&self) -> &dyn std::any::Any;
-}
 
-// Hello message
-struct OpenFlowHello {
-    xid: u32,
-}
+    /// Send messages followed by a barrier; fails with the first error the switch reported
+    pub async fn send_batch(&self, messages: &[Message]) -> Result<()> {
+        let mut receivers = Vec::with_capacity(messages.len());
+        let mut sent = Ok(());
+        for message in messages {
+            let xid = self.shared.next_xid();
+            receivers.push((xid, self.shared.register(xid)));
+            sent = self.shared.write(message, xid).await;
+            if sent.is_err() {
+                break;
+            }
+        }
+        let barrier = match sent {
+            Ok(()) => self.barrier().await,
+            Err(e) => Err(e),
+        };
 
-impl OpenFlowHello {
-    fn new() -> Self {
-        Self { xid: 0 }
-    }
+        // The switch processes messages in order, so every error for the batch
+        // has been routed by the time the barrier reply arrives
+        let mut failure = None;
+        for (index, (xid, mut rx)) in receivers.into_iter().enumerate() {
+            if let Ok(Message::Error(error)) = rx.try_recv() {
+                failure.get_or_insert((index, error));
+            }
+            self.shared.unregister(xid);
+        }
 
-    fn from_bytes(xid: u32, _payload: &[u8]) -> Result<Self> {
-        Ok(Self { xid })
+        if let Some((index, error)) = failure {
+            return Err(anyhow::Error::new(error).context(format!(
+                "OpenFlow message {} of {} rejected",
+                index + 1,
+                messages.len()
+            )));
+        }
+        barrier
     }
-}
 
-impl OpenFlowMessage for OpenFlowHello {
-    fn message_type(&self) -> OpenFlowMessageType {
-        OpenFlowMessageType::Hello
+    pub async fn barrier(&self) -> Result<()> {
+        match self.request(&Message::BarrierRequest).await?.pop() {
+            Some(Message::BarrierReply) => Ok(()),
+            other => bail!("Unexpected reply to barrier request: {:?}", other),
+        }
     }
 
-    fn xid(&self) -> u32 {
-        self.xid
+    /// Round-trip an echo request
+    pub async fn echo(&self) -> Result<Duration> {
+        let started = Instant::now();
+        match self.request(&Message::EchoRequest(Vec::new())).await?.pop() {
+            Some(Message::EchoReply(_)) => Ok(started.elapsed()),
+            other => bail!("Unexpected reply to echo request: {:?}", other),
+        }
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        // Hello message has no payload in basic form
-        Vec::new()
+    pub async fn request_features(&self) -> Result<FeaturesReply> {
+        match self.request(&Message::FeaturesRequest).await?.pop() {
+            Some(Message::FeaturesReply(features)) => Ok(features),
+            other => bail!("Unexpected reply to features request: {:?}", other),
+        }
     }
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+    pub async fn add_flow(&self, flow: &FlowMod) -> Result<()> {
+        self.flow_mods(std::slice::from_ref(flow)).await
     }
-}
-
-// Features request
-struct OpenFlowFeaturesRequest {
-    xid: u32,
-}
 
-impl OpenFlowFeaturesRequest {
-    fn new(xid: u32) -> Self {
-        Self { xid }
+    /// Apply several flow mods, confirmed by a single barrier
+    pub async fn flow_mods(&self, flows: &[FlowMod]) -> Result<()> {
+        let messages: Vec<Message> = flows.iter().cloned().map(Message::FlowMod).collect();
+        self.send_batch(&messages).await
     }
-}
 
-impl OpenFlowMessage for OpenFlowFeaturesRequest {
-    fn message_type(&self) -> OpenFlowMessageType {
-        OpenFlowMessageType::FeaturesRequest
+    /// Delete flows in a table (OFPTT_ALL for all) whose cookie matches under `cookie_mask`
+    pub async fn delete_flows(&self, table_id: u8, cookie: u64, cookie_mask: u64) -> Result<()> {
+        self.add_flow(&FlowMod::delete(table_id, Match::new()).cookie(cookie, cookie_mask))
+            .await
     }
 
-    fn xid(&self) -> u32 {
-        self.xid
+    pub async fn delete_all_flows(&self) -> Result<()> {
+        self.delete_flows(OFPTT_ALL, 0, 0).await
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        Vec::new()
+    pub async fn flow_stats(&self, request: FlowStatsRequest) -> Result<Vec<FlowStats>> {
+        let replies = self
+            .request(&Message::MultipartRequest(MultipartRequest::Flow(request)))
+            .await?;
+        let mut flows = Vec::new();
+        for reply in replies {
+            match reply {
+                Message::MultipartReply(MultipartReply {
+                    body: MultipartReplyBody::Flow(part),
+                    ..
+                }) => flows.extend(part),
+                other => bail!("Unexpected reply to flow stats request: {:?}", other),
+            }
+        }
+        Ok(flows)
     }
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+    /// Every flow on the switch
+    pub async fn query_flows(&self) -> Result<Vec<FlowStats>> {
+        self.flow_stats(FlowStatsRequest::all()).await
     }
-}
-
-// Features reply
-#[derive(Clone)]
-pub struct OpenFlowFeaturesReply {
-    xid: u32,
-    datapath_id: u64,
-    n_buffers: u32,
-    n_tables: u8,
-    capabilities: u32,
-}
 
-impl OpenFlowFeaturesReply {
-    fn from_bytes(xid: u32, payload: &[u8]) -> Result<Self> {
-        if payload.len() < 24 {
-            return Err(anyhow::anyhow!("Features reply payload too short"));
+    /// Counters for one port, or all ports when `port_no` is None
+    pub async fn port_stats(&self, port_no: Option<u32>) -> Result<Vec<PortStats>> {
+        let request = MultipartRequest::PortStats {
+            port_no: port_no.unwrap_or(ofp_port::ANY),
+        };
+        let replies = self.request(&Message::MultipartRequest(request)).await?;
+        let mut ports = Vec::new();
+        for reply in replies {
+            match reply {
+                Message::MultipartReply(MultipartReply {
+                    body: MultipartReplyBody::PortStats(part),
+                    ..
+                }) => ports.extend(part),
+                other => bail!("Unexpected reply to port stats request: {:?}", other),
+            }
         }
-
-        Ok(Self {
-            xid,
-            datapath_id: u64::from_be_bytes(payload[0..8].try_into()?),
-            n_buffers: u32::from_be_bytes(payload[8..12].try_into()?),
-            n_tables: payload[12],
-            capabilities: u32::from_be_bytes(payload[16..20].try_into()?),
-        })
+        Ok(ports)
     }
-}
 
-impl OpenFlowMessage for OpenFlowFeaturesReply {
-    fn message_type(&self) -> OpenFlowMessageType {
-        OpenFlowMessageType::FeaturesReply
+    pub async fn group_mod(&self, group: &GroupMod) -> Result<()> {
+        self.send_batch(&[Message::GroupMod(group.clone())]).await
     }
 
-    fn xid(&self) -> u32 {
-        self.xid
+    pub async fn meter_mod(&self, meter: &MeterMod) -> Result<()> {
+        self.send_batch(&[Message::MeterMod(meter.clone())]).await
     }
+}
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(24);
-        buf.extend_from_slice(&self.datapath_id.to_be_bytes());
-        buf.extend_from_slice(&self.n_buffers.to_be_bytes());
-        buf.push(self.n_tables);
-        buf.push(0); // pad
-        buf.extend_from_slice(&0u16.to_be_bytes()); // pad
-        buf.extend_from_slice(&self.capabilities.to_be_bytes());
-        buf
+impl Drop for OpenFlowClient {
+    fn drop(&mut self) {
+        self.shared.close();
+        for task in &self.tasks {
+            task.abort();
+        }
     }
+}
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
-    }
+/// Read one complete frame
+async fn read_frame<R: AsyncRead + Unpin + ?Sized>(
+    reader: &mut R,
+) -> Result<(OpenFlowHeader, Vec<u8>)> {
+    let mut header_bytes = [0u8; OFP_HEADER_LEN];
+    reader.read_exact(&mut header_bytes).await?;
+    let header = OpenFlowHeader::from_bytes(&header_bytes)?;
+    let len = usize::from(header.length);
+    if len < OFP_HEADER_LEN {
+        bail!("OpenFlow message length {} is shorter than its header", len);
+    }
+    let mut body = vec![0u8; len - OFP_HEADER_LEN];
+    reader.read_exact(&mut body).await?;
+    Ok((header, body))
 }
 
-// Flow mod message
-struct OpenFlowFlowMod {
-    xid: u32,
-    command: u16,
-    match_fields: Vec<u8>, // Simplified - would need proper encoding
-    instructions: Vec<u8>, // Simplified - would need proper encoding
+async fn read_message<R: AsyncRead + Unpin + ?Sized>(
+    reader: &mut R,
+) -> Result<(OpenFlowHeader, Message)> {
+    let (header, body) = read_frame(reader).await?;
+    let message = Message::decode(&header, &body)?;
+    Ok((header, message))
 }
 
-impl OpenFlowFlowMod {
-    fn from_flow_entry(_flow: &FlowEntry, xid: u32) -> Self {
-        // This is a placeholder - real implementation would encode the flow properly
-        Self {
-            xid,
-            command: 0, // OFPFC_ADD
-            match_fields: Vec::new(),
-            instructions: Vec::new(),
+async fn read_loop(
+    mut reader: ReadHalf<Stream>,
+    shared: Arc<Shared>,
+    events: broadcast::Sender<Message>,
+) {
+    loop {
+        let (header, body) = match read_frame(&mut reader).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::debug!("OpenFlow connection ended: {}", e);
+                break;
+            }
+        };
+        let message = match Message::decode(&header, &body) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!(
+                    "Dropping undecodable OpenFlow message type {}: {}",
+                    header.message_type,
+                    e
+                );
+                continue;
+            }
+        };
+
+        if let Message::EchoRequest(data) = message {
+            if let Err(e) = shared.write(&Message::EchoReply(data), header.xid).await {
+                log::debug!("Failed to answer OpenFlow echo request: {}", e);
+                break;
+            }
+            continue;
         }
-    }
 
-    fn delete_all(xid: u32) -> Self {
-        Self {
-            xid,
-            command: 3, // OFPFC_DELETE
-            match_fields: Vec::new(),
-            instructions: Vec::new(),
+        let waiter = shared.pending.lock().unwrap().get(&header.xid).cloned();
+        match waiter {
+            Some(tx) => {
+                let _ = tx.send(message);
+            }
+            None => {
+                let _ = events.send(message);
+            }
         }
     }
+    shared.close();
 }
 
-impl OpenFlowMessage for OpenFlowFlowMod {
-    fn message_type(&self) -> OpenFlowMessageType {
-        OpenFlowMessageType::FlowMod
-    }
-
-    fn xid(&self) -> u32 {
-        self.xid
-    }
-
-    fn to_bytes(&self) -> Vec<u8> {
-        // Simplified implementation
-        let mut buf = Vec::new();
-        buf.extend_from_slice(&0u64.to_be_bytes()); // cookie
-        buf.extend_from_slice(&0u64.to_be_bytes()); // cookie_mask
-        buf.push(0); // table_id
-        buf.push(self.command as u8); // command
-        buf.extend_from_slice(&0u16.to_be_bytes()); // idle_timeout
-        buf.extend_from_slice(&0u16.to_be_bytes()); // hard_timeout
-        buf.extend_from_slice(&0u16.to_be_bytes()); // priority
-        buf.extend_from_slice(&0u32.to_be_bytes()); // buffer_id
-        buf.extend_from_slice(&0u32.to_be_bytes()); // out_port
-        buf.extend_from_slice(&0u32.to_be_bytes()); // out_group
-        buf.extend_from_slice(&0u16.to_be_bytes()); // flags
-        buf.extend_from_slice(&0u16.to_be_bytes()); // importance (padding)
-        buf.extend_from_slice(&self.match_fields);
-        buf.extend_from_slice(&self.instructions);
-        buf
-    }
-
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+/// Probe the switch periodically; a missed echo closes the connection
+async fn keepalive(shared: Arc<Shared>) {
+    let mut ticker = tokio::time::interval(ECHO_INTERVAL);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if shared.closed.load(Ordering::SeqCst) {
+            break;
+        }
+        if let Err(e) = shared.request(&Message::EchoRequest(Vec::new())).await {
+            log::warn!("OpenFlow switch stopped answering echo requests: {}", e);
+            shared.close();
+            break;
+        }
     }
 }
 
-// Generic message for unsupported types
-struct OpenFlowGenericMessage {
-    header: OpenFlowHeader,
-    payload: Vec<u8>,
-}
-
-impl OpenFlowMessage for OpenFlowGenericMessage {
-    fn message_type(&self) -> OpenFlowMessageType {
-        // This is a hack - we'd need proper enum conversion
-        unsafe { std::mem::transmute(self.header.message_type) }
-    }
-
-    fn xid(&self) -> u32 {
-        self.header.xid
-    }
-
-    fn to_bytes(&self) -> Vec<u8> {
-        self.payload.clone()
+/// In-process OpenFlow 1.3 switch for tests
+#[cfg(test)]
+pub(crate) mod fake_switch {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    #[derive(Debug, Default)]
+    pub(crate) struct SwitchState {
+        pub flows: Vec<FlowMod>,
+        pub groups: Vec<GroupMod>,
+        pub meters: Vec<MeterMod>,
+        pub echo_replies: usize,
+    }
+
+    /// Start a switch; returns the controller end of the connection
+    pub(crate) fn spawn() -> (DuplexStream, Arc<std::sync::Mutex<SwitchState>>) {
+        let (controller, mut switch) = tokio::io::duplex(1 << 16);
+        let state = Arc::new(std::sync::Mutex::new(SwitchState::default()));
+        let shared = state.clone();
+
+        tokio::spawn(async move {
+            while let Ok((header, message)) = read_message(&mut switch).await {
+                let replies = handle(&shared, &header, message);
+                for reply in replies {
+                    if switch.write_all(&reply.encode(header.xid)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        (controller, state)
+    }
+
+    fn handle(
+        state: &std::sync::Mutex<SwitchState>,
+        header: &OpenFlowHeader,
+        message: Message,
+    ) -> Vec<Message> {
+        let mut state = state.lock().unwrap();
+        match message {
+            // Probe the controller's echo handling right after the handshake
+            Message::Hello { .. } => vec![Message::hello()],
+            Message::FeaturesRequest => vec![
+                Message::FeaturesReply(FeaturesReply {
+                    datapath_id: 0x1234,
+                    n_buffers: 0,
+                    n_tables: 254,
+                    auxiliary_id: 0,
+                    capabilities: 0x4f,
+                }),
+                Message::EchoRequest(b"ping".to_vec()),
+            ],
+            Message::EchoRequest(data) => vec![Message::EchoReply(data)],
+            Message::EchoReply(_) => {
+                state.echo_replies += 1;
+                vec![]
+            }
+            Message::FlowMod(flow) if flow.table_id >= 254 && flow.table_id != OFPTT_ALL => {
+                vec![reject(
+                    OpenFlowError::FLOW_MOD_FAILED,
+                    2,
+                    header,
+                    &Message::FlowMod(flow),
+                )]
+            }
+            Message::FlowMod(flow) => {
+                match flow.command {
+                    FlowModCommand::Add | FlowModCommand::Modify | FlowModCommand::ModifyStrict => {
+                        state.flows.retain(|f| {
+                            !(f.table_id == flow.table_id
+                                && f.priority == flow.priority
+                                && f.match_fields.same_fields(&flow.match_fields))
+                        });
+                        state.flows.push(flow);
+                    }
+                    FlowModCommand::Delete | FlowModCommand::DeleteStrict => {
+                        state.flows.retain(|f| {
+                            !((flow.table_id == OFPTT_ALL || f.table_id == flow.table_id)
+                                && f.cookie & flow.cookie_mask == flow.cookie & flow.cookie_mask
+                                && (flow.match_fields.fields.is_empty()
+                                    || f.match_fields.same_fields(&flow.match_fields)))
+                        });
+                    }
+                }
+                vec![]
+            }
+            Message::GroupMod(group) => {
+                state.groups.push(group);
+                vec![]
+            }
+            Message::MeterMod(meter) => {
+                state.meters.push(meter);
+                vec![]
+            }
+            Message::MultipartRequest(MultipartRequest::Flow(request)) => {
+                let flows: Vec<FlowStats> = state
+                    .flows
+                    .iter()
+                    .filter(|f| request.table_id == OFPTT_ALL || f.table_id == request.table_id)
+                    .filter(|f| {
+                        f.cookie & request.cookie_mask == request.cookie & request.cookie_mask
+                    })
+                    .map(|f| FlowStats {
+                        table_id: f.table_id,
+                        duration_sec: 1,
+                        duration_nsec: 0,
+                        priority: f.priority,
+                        idle_timeout: f.idle_timeout,
+                        hard_timeout: f.hard_timeout,
+                        flags: f.flags,
+                        cookie: f.cookie,
+                        packet_count: 0,
+                        byte_count: 0,
+                        match_fields: f.match_fields.clone(),
+                        instructions: f.instructions.clone(),
+                    })
+                    .collect();
+                // One flow per part, so the controller has to reassemble
+                let count = flows.len();
+                let mut parts: Vec<Message> = flows
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, flow)| {
+                        Message::MultipartReply(MultipartReply {
+                            flags: if i + 1 < count { OFPMPF_REPLY_MORE } else { 0 },
+                            body: MultipartReplyBody::Flow(vec![flow]),
+                        })
+                    })
+                    .collect();
+                if parts.is_empty() {
+                    parts.push(Message::MultipartReply(MultipartReply {
+                        flags: 0,
+                        body: MultipartReplyBody::Flow(vec![]),
+                    }));
+                }
+                parts
+            }
+            Message::MultipartRequest(MultipartRequest::PortStats { port_no }) => {
+                let ports = [1u32, 2, ofp_port::LOCAL]
+                    .into_iter()
+                    .filter(|p| port_no == ofp_port::ANY || *p == port_no)
+                    .map(|p| PortStats {
+                        port_no: p,
+                        rx_packets: u64::from(p) * 10,
+                        ..Default::default()
+                    })
+                    .collect();
+                vec![Message::MultipartReply(MultipartReply {
+                    flags: 0,
+                    body: MultipartReplyBody::PortStats(ports),
+                })]
+            }
+            Message::BarrierRequest => vec![Message::BarrierReply],
+            other => vec![reject(OpenFlowError::BAD_REQUEST, 1, header, &other)],
+        }
     }
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+    fn reject(error_type: u16, code: u16, header: &OpenFlowHeader, message: &Message) -> Message {
+        let mut data = message.encode(header.xid);
+        data.truncate(64);
+        Message::Error(OpenFlowError {
+            error_type,
+            code,
+            data,
+        })
     }
 }
 
@@ -474,4 +652,89 @@ mod tests {
         assert_eq!(decoded.length, 8);
         assert_eq!(decoded.xid, 123);
     }
-}
\ No newline at end of file
+
+    fn flow(table: u8, cookie: u64, port: u32) -> FlowMod {
+        FlowMod::add(
+            table,
+            100,
+            Match::new().with(OxmField::uint(OxmKind::InPort, port.into())),
+        )
+        .cookie(cookie, 0)
+        .apply(vec![Action::output(ofp_port::NORMAL)])
+    }
+
+    #[tokio::test]
+    async fn test_client_against_fake_switch() {
+        let (stream, state) = fake_switch::spawn();
+        let client = OpenFlowClient::from_stream(stream).await.unwrap();
+        assert_eq!(client.features().datapath_id, 0x1234);
+
+        client
+            .flow_mods(&[
+                flow(0, 0xDEAD_0001, 1),
+                flow(0, 0xDEAD_0002, 2),
+                flow(1, 0xCAFE_0001, 3),
+            ])
+            .await
+            .unwrap();
+        let flows = client.query_flows().await.unwrap();
+        assert_eq!(flows.len(), 3);
+        assert_eq!(flows[2].cookie, 0xCAFE_0001);
+        assert_eq!(flows[0].instructions, flow(0, 0, 1).instructions);
+
+        // Delete by cookie prefix only touches the matching family
+        client
+            .delete_flows(OFPTT_ALL, 0xDEAD_0000, 0xFFFF_0000)
+            .await
+            .unwrap();
+        let remaining = client.query_flows().await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].table_id, 1);
+
+        // Switch errors surface as typed errors pointing at the rejected message
+        let err = client
+            .flow_mods(&[flow(0, 1, 4), flow(254, 2, 5)])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("message 2 of 2"));
+        let of_error = err.downcast_ref::<OpenFlowError>().unwrap();
+        assert_eq!(of_error.code_name(), Some("BAD_TABLE_ID"));
+        assert_eq!(
+            of_error.failed_message_type(),
+            Some(OpenFlowMessageType::FlowMod.as_u8())
+        );
+
+        let ports = client.port_stats(Some(2)).await.unwrap();
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0].rx_packets, 20);
+
+        client
+            .meter_mod(&MeterMod {
+                command: MeterModCommand::Add,
+                flags: OFPMF_KBPS,
+                meter_id: 1,
+                bands: vec![MeterBand::Drop {
+                    rate: 1000,
+                    burst_size: 0,
+                }],
+            })
+            .await
+            .unwrap();
+        client
+            .group_mod(&GroupMod {
+                command: GroupModCommand::Add,
+                group_type: GroupType::All,
+                group_id: 1,
+                buckets: vec![Bucket::new(vec![Action::output(1)])],
+            })
+            .await
+            .unwrap();
+        client.echo().await.unwrap();
+
+        let state = state.lock().unwrap();
+        assert_eq!(state.meters.len(), 1);
+        assert_eq!(state.groups.len(), 1);
+        // The switch's echo request was answered by the reader task
+        assert_eq!(state.echo_replies, 1);
+    }
+}
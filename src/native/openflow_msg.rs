@@ -0,0 +1,2048 @@
+//! OpenFlow 1.3 wire format - messages, OXM matches, instructions and actions
+//! Everything the native client sends or receives is encoded and decoded here,
+//! including the switch side, so the client can be tested without OVS.
+
+use anyhow::{bail, Result};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const OFP_VERSION: u8 = 0x04;
+pub const OFP_HEADER_LEN: usize = 8;
+
+/// Reserved port numbers
+pub mod ofp_port {
+    pub const MAX: u32 = 0xffff_ff00;
+    pub const IN_PORT: u32 = 0xffff_fff8;
+    pub const TABLE: u32 = 0xffff_fff9;
+    pub const NORMAL: u32 = 0xffff_fffa;
+    pub const FLOOD: u32 = 0xffff_fffb;
+    pub const ALL: u32 = 0xffff_fffc;
+    pub const CONTROLLER: u32 = 0xffff_fffd;
+    pub const LOCAL: u32 = 0xffff_fffe;
+    pub const ANY: u32 = 0xffff_ffff;
+}
+
+pub const OFPG_ANY: u32 = 0xffff_ffff;
+pub const OFPTT_ALL: u8 = 0xff;
+pub const OFP_NO_BUFFER: u32 = 0xffff_ffff;
+pub const OFPCML_NO_BUFFER: u16 = 0xffff;
+pub const OFPVID_PRESENT: u16 = 0x1000;
+pub const OFPMPF_REPLY_MORE: u16 = 0x0001;
+
+/// Flow mod flags
+pub const OFPFF_SEND_FLOW_REM: u16 = 0x0001;
+pub const OFPFF_CHECK_OVERLAP: u16 = 0x0002;
+
+/// Meter flags
+pub const OFPMF_KBPS: u16 = 0x0001;
+pub const OFPMF_PKTPS: u16 = 0x0002;
+pub const OFPMF_BURST: u16 = 0x0004;
+pub const OFPMF_STATS: u16 = 0x0008;
+
+/// Nicira vendor id, used for OVS extension actions
+pub const NX_VENDOR_ID: u32 = 0x0000_2320;
+const NXAST_RESUBMIT_TABLE: u16 = 14;
+
+const OFPXMC_OPENFLOW_BASIC: u16 = 0x8000;
+const OFPXMC_NXM_1: u16 = 0x0001;
+const OFPMT_OXM: u16 = 1;
+const OFPHET_VERSIONBITMAP: u16 = 1;
+const OFPMP_FLOW: u16 = 1;
+const OFPMP_PORT_STATS: u16 = 4;
+
+/// OpenFlow protocol versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenFlowVersion {
+    V1_0 = 0x01,
+    V1_3 = 0x04,
+}
+
+impl OpenFlowVersion {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+/// OpenFlow 1.3 message types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenFlowMessageType {
+    Hello = 0,
+    Error = 1,
+    EchoRequest = 2,
+    EchoReply = 3,
+    Experimenter = 4,
+    FeaturesRequest = 5,
+    FeaturesReply = 6,
+    PacketIn = 10,
+    FlowRemoved = 11,
+    PortStatus = 12,
+    PacketOut = 13,
+    FlowMod = 14,
+    GroupMod = 15,
+    MultipartRequest = 18,
+    MultipartReply = 19,
+    BarrierRequest = 20,
+    BarrierReply = 21,
+    MeterMod = 29,
+}
+
+impl OpenFlowMessageType {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+/// OpenFlow header (8 bytes)
+#[derive(Debug, Clone)]
+pub struct OpenFlowHeader {
+    pub version: u8,
+    pub message_type: u8,
+    pub length: u16,
+    pub xid: u32,
+}
+
+impl OpenFlowHeader {
+    pub fn new(message_type: OpenFlowMessageType, length: u16, xid: u32) -> Self {
+        Self {
+            version: OpenFlowVersion::V1_3.as_u8(),
+            message_type: message_type.as_u8(),
+            length,
+            xid,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(OFP_HEADER_LEN);
+        buf.push(self.version);
+        buf.push(self.message_type);
+        buf.extend_from_slice(&self.length.to_be_bytes());
+        buf.extend_from_slice(&self.xid.to_be_bytes());
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < OFP_HEADER_LEN {
+            return Err(anyhow::anyhow!("Header too short"));
+        }
+
+        Ok(Self {
+            version: bytes[0],
+            message_type: bytes[1],
+            length: u16::from_be_bytes([bytes[2], bytes[3]]),
+            xid: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        })
+    }
+}
+
+/// Bounds-checked big-endian reader
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.remaining() < n {
+            bail!(
+                "Truncated OpenFlow message: need {} bytes at offset {}, have {}",
+                n,
+                self.pos,
+                self.remaining()
+            );
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.buf[self.pos..];
+        self.pos = self.buf.len();
+        bytes
+    }
+
+    /// Sub-cursor over the next `n` bytes
+    fn sub(&mut self, n: usize) -> Result<Cursor<'a>> {
+        Ok(Cursor::new(self.take(n)?))
+    }
+}
+
+fn padded_len(len: usize) -> usize {
+    len.div_ceil(8) * 8
+}
+
+/// Zero-pad `buf` so the bytes written since `start` are a multiple of 8
+fn pad_to_8(buf: &mut Vec<u8>, start: usize) {
+    buf.resize(start + padded_len(buf.len() - start), 0);
+}
+
+/// Overwrite the u16 length field at `at`
+fn patch_len(buf: &mut [u8], at: usize, len: usize) {
+    buf[at..at + 2].copy_from_slice(&(len as u16).to_be_bytes());
+}
+
+/// OXM match field types (OpenFlow basic class plus Nicira NXM_1 extensions)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OxmKind {
+    InPort,
+    Metadata,
+    EthDst,
+    EthSrc,
+    EthType,
+    VlanVid,
+    VlanPcp,
+    IpDscp,
+    IpEcn,
+    IpProto,
+    Ipv4Src,
+    Ipv4Dst,
+    TcpSrc,
+    TcpDst,
+    UdpSrc,
+    UdpDst,
+    Icmpv4Type,
+    Icmpv4Code,
+    ArpOp,
+    ArpSpa,
+    ArpTpa,
+    Ipv6Src,
+    Ipv6Dst,
+    Icmpv6Type,
+    Icmpv6Code,
+    TunnelId,
+    /// NXM_NX_REG0..15
+    Reg(u8),
+    /// NXM_NX_IP_TTL
+    IpTtl,
+    /// NXM_NX_IP_FRAG
+    IpFrag,
+    /// NXM_NX_TCP_FLAGS
+    TcpFlags,
+    /// NXM_NX_CT_STATE
+    CtState,
+    Other {
+        class: u16,
+        field: u8,
+    },
+}
+
+impl OxmKind {
+    const BASIC: [(OxmKind, u8); 26] = [
+        (OxmKind::InPort, 0),
+        (OxmKind::Metadata, 2),
+        (OxmKind::EthDst, 3),
+        (OxmKind::EthSrc, 4),
+        (OxmKind::EthType, 5),
+        (OxmKind::VlanVid, 6),
+        (OxmKind::VlanPcp, 7),
+        (OxmKind::IpDscp, 8),
+        (OxmKind::IpEcn, 9),
+        (OxmKind::IpProto, 10),
+        (OxmKind::Ipv4Src, 11),
+        (OxmKind::Ipv4Dst, 12),
+        (OxmKind::TcpSrc, 13),
+        (OxmKind::TcpDst, 14),
+        (OxmKind::UdpSrc, 15),
+        (OxmKind::UdpDst, 16),
+        (OxmKind::Icmpv4Type, 19),
+        (OxmKind::Icmpv4Code, 20),
+        (OxmKind::ArpOp, 21),
+        (OxmKind::ArpSpa, 22),
+        (OxmKind::ArpTpa, 23),
+        (OxmKind::Ipv6Src, 26),
+        (OxmKind::Ipv6Dst, 27),
+        (OxmKind::Icmpv6Type, 29),
+        (OxmKind::Icmpv6Code, 30),
+        (OxmKind::TunnelId, 38),
+    ];
+
+    const NXM: [(OxmKind, u8); 4] = [
+        (OxmKind::IpFrag, 26),
+        (OxmKind::IpTtl, 29),
+        (OxmKind::TcpFlags, 34),
+        (OxmKind::CtState, 105),
+    ];
+
+    fn class_field(self) -> (u16, u8) {
+        match self {
+            OxmKind::Other { class, field } => (class, field),
+            OxmKind::Reg(n) => (OFPXMC_NXM_1, n),
+            kind => Self::BASIC
+                .iter()
+                .map(|(k, f)| (OFPXMC_OPENFLOW_BASIC, k, f))
+                .chain(Self::NXM.iter().map(|(k, f)| (OFPXMC_NXM_1, k, f)))
+                .find(|(_, k, _)| **k == kind)
+                .map(|(class, _, field)| (class, *field))
+                .expect("every named OXM kind has a code"),
+        }
+    }
+
+    fn from_class_field(class: u16, field: u8) -> Self {
+        let table: &[(OxmKind, u8)] = match class {
+            OFPXMC_OPENFLOW_BASIC => &Self::BASIC,
+            OFPXMC_NXM_1 if field < 16 => return OxmKind::Reg(field),
+            OFPXMC_NXM_1 => &Self::NXM,
+            _ => &[],
+        };
+        table
+            .iter()
+            .find(|(_, f)| *f == field)
+            .map(|(kind, _)| *kind)
+            .unwrap_or(OxmKind::Other { class, field })
+    }
+
+    /// Value width in bytes
+    pub fn width(self) -> Option<usize> {
+        Some(match self {
+            OxmKind::VlanPcp
+            | OxmKind::IpDscp
+            | OxmKind::IpEcn
+            | OxmKind::IpProto
+            | OxmKind::Icmpv4Type
+            | OxmKind::Icmpv4Code
+            | OxmKind::Icmpv6Type
+            | OxmKind::Icmpv6Code
+            | OxmKind::IpTtl
+            | OxmKind::IpFrag => 1,
+            OxmKind::EthType
+            | OxmKind::VlanVid
+            | OxmKind::TcpSrc
+            | OxmKind::TcpDst
+            | OxmKind::UdpSrc
+            | OxmKind::UdpDst
+            | OxmKind::ArpOp
+            | OxmKind::TcpFlags => 2,
+            OxmKind::InPort
+            | OxmKind::Ipv4Src
+            | OxmKind::Ipv4Dst
+            | OxmKind::ArpSpa
+            | OxmKind::ArpTpa
+            | OxmKind::Reg(_)
+            | OxmKind::CtState => 4,
+            OxmKind::EthDst | OxmKind::EthSrc => 6,
+            OxmKind::Metadata | OxmKind::TunnelId => 8,
+            OxmKind::Ipv6Src | OxmKind::Ipv6Dst => 16,
+            OxmKind::Other { .. } => return None,
+        })
+    }
+}
+
+/// One OXM TLV: a field value with an optional mask
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OxmField {
+    pub kind: OxmKind,
+    pub value: Vec<u8>,
+    /// None for an exact match; all-ones masks are normalized to None
+    pub mask: Option<Vec<u8>>,
+}
+
+impl OxmField {
+    pub fn new(kind: OxmKind, value: &[u8]) -> Self {
+        Self {
+            kind,
+            value: value.to_vec(),
+            mask: None,
+        }
+    }
+
+    pub fn masked(kind: OxmKind, value: &[u8], mask: &[u8]) -> Self {
+        // Bits outside the mask are don't-care; OpenFlow requires them zeroed
+        let value = value.iter().zip(mask).map(|(v, m)| v & m).collect();
+        let mask = (!mask.iter().all(|b| *b == 0xff)).then(|| mask.to_vec());
+        Self { kind, value, mask }
+    }
+
+    /// Integer field, truncated to the field's width
+    pub fn uint(kind: OxmKind, value: u64) -> Self {
+        Self::new(kind, &uint_bytes(kind, value))
+    }
+
+    pub fn uint_masked(kind: OxmKind, value: u64, mask: u64) -> Self {
+        Self::masked(kind, &uint_bytes(kind, value), &uint_bytes(kind, mask))
+    }
+
+    pub fn mac(kind: OxmKind, mac: [u8; 6]) -> Self {
+        Self::new(kind, &mac)
+    }
+
+    pub fn ipv4(kind: OxmKind, addr: Ipv4Addr, prefix: u8) -> Self {
+        let mask = u32::MAX
+            .checked_shl(32 - u32::from(prefix.min(32)))
+            .unwrap_or(0);
+        Self::masked(kind, &addr.octets(), &mask.to_be_bytes())
+    }
+
+    pub fn ipv6(kind: OxmKind, addr: Ipv6Addr, prefix: u8) -> Self {
+        let mask = u128::MAX
+            .checked_shl(128 - u32::from(prefix.min(128)))
+            .unwrap_or(0);
+        Self::masked(kind, &addr.octets(), &mask.to_be_bytes())
+    }
+
+    pub fn value_uint(&self) -> u64 {
+        be_uint(&self.value)
+    }
+
+    pub fn mask_uint(&self) -> Option<u64> {
+        self.mask.as_deref().map(be_uint)
+    }
+
+    /// Whether a packet's value for this field satisfies the (masked) match
+    pub fn matches(&self, value: &[u8]) -> bool {
+        if value.len() != self.value.len() {
+            return false;
+        }
+        match &self.mask {
+            Some(mask) => value
+                .iter()
+                .zip(&self.value)
+                .zip(mask)
+                .all(|((v, want), m)| v & m == want & m),
+            None => value == self.value.as_slice(),
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (class, field) = self.kind.class_field();
+        let has_mask = self.mask.is_some();
+        let len = self.value.len() + self.mask.as_ref().map_or(0, Vec::len);
+        buf.extend_from_slice(&class.to_be_bytes());
+        buf.push(field << 1 | u8::from(has_mask));
+        buf.push(len as u8);
+        buf.extend_from_slice(&self.value);
+        if let Some(mask) = &self.mask {
+            buf.extend_from_slice(mask);
+        }
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let class = cursor.u16()?;
+        let field_mask = cursor.u8()?;
+        let len = usize::from(cursor.u8()?);
+        let kind = OxmKind::from_class_field(class, field_mask >> 1);
+        let body = cursor.take(len)?;
+        if field_mask & 1 == 1 {
+            if len % 2 != 0 {
+                bail!("Masked OXM field {:?} has odd length {}", kind, len);
+            }
+            let (value, mask) = body.split_at(len / 2);
+            Ok(Self::masked(kind, value, mask))
+        } else {
+            Ok(Self::new(kind, body))
+        }
+    }
+}
+
+fn uint_bytes(kind: OxmKind, value: u64) -> Vec<u8> {
+    let width = kind.width().unwrap_or(8).min(8);
+    value.to_be_bytes()[8 - width..].to_vec()
+}
+
+fn be_uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .take(8)
+        .rev()
+        .fold(0, |acc, b| acc << 8 | u64::from(*b))
+}
+
+/// OXM match (ofp_match of type OFPMT_OXM)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Match {
+    pub fields: Vec<OxmField>,
+}
+
+impl Match {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, field: OxmField) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    pub fn get(&self, kind: OxmKind) -> Option<&OxmField> {
+        self.fields.iter().find(|f| f.kind == kind)
+    }
+
+    /// Same fields regardless of order
+    pub fn same_fields(&self, other: &Match) -> bool {
+        self.fields.len() == other.fields.len()
+            && self.fields.iter().all(|f| other.fields.contains(f))
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.extend_from_slice(&OFPMT_OXM.to_be_bytes());
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        for field in &self.fields {
+            field.encode(buf);
+        }
+        // The length excludes padding
+        let len = buf.len() - start;
+        patch_len(buf, start + 2, len);
+        pad_to_8(buf, start);
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let match_type = cursor.u16()?;
+        let len = usize::from(cursor.u16()?);
+        if match_type != OFPMT_OXM {
+            bail!("Unsupported match type {}", match_type);
+        }
+        if len < 4 {
+            bail!("Match length {} is too short", len);
+        }
+        let mut fields_cursor = cursor.sub(len - 4)?;
+        cursor.skip(padded_len(len) - len)?;
+
+        let mut fields = Vec::new();
+        while fields_cursor.remaining() > 0 {
+            fields.push(OxmField::decode(&mut fields_cursor)?);
+        }
+        Ok(Self { fields })
+    }
+}
+
+/// Action in an apply/write-actions instruction or group bucket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Output {
+        port: u32,
+        max_len: u16,
+    },
+    CopyTtlOut,
+    CopyTtlIn,
+    PushVlan(u16),
+    PopVlan,
+    SetQueue(u32),
+    Group(u32),
+    SetNwTtl(u8),
+    DecNwTtl,
+    SetField(OxmField),
+    /// Nicira resubmit(in_port, table); in_port 0xfff8 keeps the current port
+    Resubmit {
+        in_port: u16,
+        table: u8,
+    },
+    Experimenter {
+        experimenter: u32,
+        data: Vec<u8>,
+    },
+}
+
+impl Action {
+    pub fn output(port: u32) -> Self {
+        Action::Output { port, max_len: 0 }
+    }
+
+    pub fn controller(max_len: u16) -> Self {
+        Action::Output {
+            port: ofp_port::CONTROLLER,
+            max_len,
+        }
+    }
+
+    /// Resubmit to a table, keeping the packet's in_port
+    pub fn resubmit(table: u8) -> Self {
+        Action::Resubmit {
+            in_port: ofp_port::IN_PORT as u16,
+            table,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        let action_type: u16 = match self {
+            Action::Output { .. } => 0,
+            Action::CopyTtlOut => 11,
+            Action::CopyTtlIn => 12,
+            Action::PushVlan(_) => 17,
+            Action::PopVlan => 18,
+            Action::SetQueue(_) => 21,
+            Action::Group(_) => 22,
+            Action::SetNwTtl(_) => 23,
+            Action::DecNwTtl => 24,
+            Action::SetField(_) => 25,
+            Action::Resubmit { .. } | Action::Experimenter { .. } => 0xffff,
+        };
+        buf.extend_from_slice(&action_type.to_be_bytes());
+        buf.extend_from_slice(&0u16.to_be_bytes());
+
+        match self {
+            Action::Output { port, max_len } => {
+                buf.extend_from_slice(&port.to_be_bytes());
+                buf.extend_from_slice(&max_len.to_be_bytes());
+            }
+            Action::PushVlan(ethertype) => buf.extend_from_slice(&ethertype.to_be_bytes()),
+            Action::SetQueue(id) | Action::Group(id) => buf.extend_from_slice(&id.to_be_bytes()),
+            Action::SetNwTtl(ttl) => buf.push(*ttl),
+            Action::SetField(field) => field.encode(buf),
+            Action::Resubmit { in_port, table } => {
+                buf.extend_from_slice(&NX_VENDOR_ID.to_be_bytes());
+                buf.extend_from_slice(&NXAST_RESUBMIT_TABLE.to_be_bytes());
+                buf.extend_from_slice(&in_port.to_be_bytes());
+                buf.push(*table);
+            }
+            Action::Experimenter { experimenter, data } => {
+                buf.extend_from_slice(&experimenter.to_be_bytes());
+                buf.extend_from_slice(data);
+            }
+            Action::CopyTtlOut | Action::CopyTtlIn | Action::PopVlan | Action::DecNwTtl => {}
+        }
+
+        // Every action is at least 8 bytes and 8-byte aligned
+        if buf.len() - start == 4 {
+            buf.extend_from_slice(&[0; 4]);
+        }
+        pad_to_8(buf, start);
+        let len = buf.len() - start;
+        patch_len(buf, start + 2, len);
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let action_type = cursor.u16()?;
+        let len = usize::from(cursor.u16()?);
+        if len < 8 || len % 8 != 0 {
+            bail!("Action of type {} has invalid length {}", action_type, len);
+        }
+        let mut body = cursor.sub(len - 4)?;
+
+        Ok(match action_type {
+            0 => Action::Output {
+                port: body.u32()?,
+                max_len: body.u16()?,
+            },
+            11 => Action::CopyTtlOut,
+            12 => Action::CopyTtlIn,
+            17 => Action::PushVlan(body.u16()?),
+            18 => Action::PopVlan,
+            21 => Action::SetQueue(body.u32()?),
+            22 => Action::Group(body.u32()?),
+            23 => Action::SetNwTtl(body.u8()?),
+            24 => Action::DecNwTtl,
+            25 => Action::SetField(OxmField::decode(&mut body)?),
+            0xffff => {
+                let experimenter = body.u32()?;
+                let data = body.rest();
+                if experimenter == NX_VENDOR_ID
+                    && data.len() >= 5
+                    && u16::from_be_bytes([data[0], data[1]]) == NXAST_RESUBMIT_TABLE
+                {
+                    Action::Resubmit {
+                        in_port: u16::from_be_bytes([data[2], data[3]]),
+                        table: data[4],
+                    }
+                } else {
+                    Action::Experimenter {
+                        experimenter,
+                        data: data.to_vec(),
+                    }
+                }
+            }
+            other => bail!("Unsupported action type {}", other),
+        })
+    }
+}
+
+fn encode_actions(actions: &[Action], buf: &mut Vec<u8>) {
+    for action in actions {
+        action.encode(buf);
+    }
+}
+
+fn decode_actions(mut cursor: Cursor) -> Result<Vec<Action>> {
+    let mut actions = Vec::new();
+    while cursor.remaining() > 0 {
+        actions.push(Action::decode(&mut cursor)?);
+    }
+    Ok(actions)
+}
+
+/// Flow instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    GotoTable(u8),
+    WriteMetadata { metadata: u64, mask: u64 },
+    WriteActions(Vec<Action>),
+    ApplyActions(Vec<Action>),
+    ClearActions,
+    Meter(u32),
+}
+
+impl Instruction {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        let instruction_type: u16 = match self {
+            Instruction::GotoTable(_) => 1,
+            Instruction::WriteMetadata { .. } => 2,
+            Instruction::WriteActions(_) => 3,
+            Instruction::ApplyActions(_) => 4,
+            Instruction::ClearActions => 5,
+            Instruction::Meter(_) => 6,
+        };
+        buf.extend_from_slice(&instruction_type.to_be_bytes());
+        buf.extend_from_slice(&0u16.to_be_bytes());
+
+        match self {
+            Instruction::GotoTable(table) => buf.extend_from_slice(&[*table, 0, 0, 0]),
+            Instruction::WriteMetadata { metadata, mask } => {
+                buf.extend_from_slice(&[0; 4]);
+                buf.extend_from_slice(&metadata.to_be_bytes());
+                buf.extend_from_slice(&mask.to_be_bytes());
+            }
+            Instruction::WriteActions(actions) | Instruction::ApplyActions(actions) => {
+                buf.extend_from_slice(&[0; 4]);
+                encode_actions(actions, buf);
+            }
+            Instruction::ClearActions => buf.extend_from_slice(&[0; 4]),
+            Instruction::Meter(id) => buf.extend_from_slice(&id.to_be_bytes()),
+        }
+
+        let len = buf.len() - start;
+        patch_len(buf, start + 2, len);
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let instruction_type = cursor.u16()?;
+        let len = usize::from(cursor.u16()?);
+        if len < 8 {
+            bail!(
+                "Instruction of type {} has invalid length {}",
+                instruction_type,
+                len
+            );
+        }
+        let mut body = cursor.sub(len - 4)?;
+
+        Ok(match instruction_type {
+            1 => Instruction::GotoTable(body.u8()?),
+            2 => {
+                body.skip(4)?;
+                Instruction::WriteMetadata {
+                    metadata: body.u64()?,
+                    mask: body.u64()?,
+                }
+            }
+            3 | 4 => {
+                body.skip(4)?;
+                let actions = decode_actions(body)?;
+                if instruction_type == 3 {
+                    Instruction::WriteActions(actions)
+                } else {
+                    Instruction::ApplyActions(actions)
+                }
+            }
+            5 => Instruction::ClearActions,
+            6 => Instruction::Meter(body.u32()?),
+            other => bail!("Unsupported instruction type {}", other),
+        })
+    }
+}
+
+fn decode_instructions(mut cursor: Cursor) -> Result<Vec<Instruction>> {
+    let mut instructions = Vec::new();
+    while cursor.remaining() > 0 {
+        instructions.push(Instruction::decode(&mut cursor)?);
+    }
+    Ok(instructions)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowModCommand {
+    Add = 0,
+    Modify = 1,
+    ModifyStrict = 2,
+    Delete = 3,
+    DeleteStrict = 4,
+}
+
+impl FlowModCommand {
+    fn from_u8(value: u8) -> Result<Self> {
+        Ok(match value {
+            0 => FlowModCommand::Add,
+            1 => FlowModCommand::Modify,
+            2 => FlowModCommand::ModifyStrict,
+            3 => FlowModCommand::Delete,
+            4 => FlowModCommand::DeleteStrict,
+            other => bail!("Unknown flow mod command {}", other),
+        })
+    }
+}
+
+/// OFPT_FLOW_MOD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowMod {
+    pub cookie: u64,
+    pub cookie_mask: u64,
+    pub table_id: u8,
+    pub command: FlowModCommand,
+    pub idle_timeout: u16,
+    pub hard_timeout: u16,
+    pub priority: u16,
+    pub buffer_id: u32,
+    pub out_port: u32,
+    pub out_group: u32,
+    pub flags: u16,
+    pub match_fields: Match,
+    pub instructions: Vec<Instruction>,
+}
+
+impl FlowMod {
+    /// Add (or replace) a flow
+    pub fn add(table_id: u8, priority: u16, match_fields: Match) -> Self {
+        Self {
+            cookie: 0,
+            cookie_mask: 0,
+            table_id,
+            command: FlowModCommand::Add,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            priority,
+            buffer_id: OFP_NO_BUFFER,
+            out_port: ofp_port::ANY,
+            out_group: OFPG_ANY,
+            flags: 0,
+            match_fields,
+            instructions: Vec::new(),
+        }
+    }
+
+    /// Delete flows in a table (OFPTT_ALL for every table) whose match includes `match_fields`
+    pub fn delete(table_id: u8, match_fields: Match) -> Self {
+        Self {
+            command: FlowModCommand::Delete,
+            ..Self::add(table_id, 0, match_fields)
+        }
+    }
+
+    /// Delete exactly the flow with this table, priority and match
+    pub fn delete_strict(table_id: u8, priority: u16, match_fields: Match) -> Self {
+        Self {
+            command: FlowModCommand::DeleteStrict,
+            ..Self::add(table_id, priority, match_fields)
+        }
+    }
+
+    pub fn cookie(mut self, cookie: u64, cookie_mask: u64) -> Self {
+        self.cookie = cookie;
+        self.cookie_mask = cookie_mask;
+        self
+    }
+
+    pub fn timeouts(mut self, idle_timeout: u16, hard_timeout: u16) -> Self {
+        self.idle_timeout = idle_timeout;
+        self.hard_timeout = hard_timeout;
+        self
+    }
+
+    pub fn instruction(mut self, instruction: Instruction) -> Self {
+        self.instructions.push(instruction);
+        self
+    }
+
+    /// Shorthand for a single apply-actions instruction
+    pub fn apply(self, actions: Vec<Action>) -> Self {
+        self.instruction(Instruction::ApplyActions(actions))
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.cookie.to_be_bytes());
+        buf.extend_from_slice(&self.cookie_mask.to_be_bytes());
+        buf.push(self.table_id);
+        buf.push(self.command as u8);
+        buf.extend_from_slice(&self.idle_timeout.to_be_bytes());
+        buf.extend_from_slice(&self.hard_timeout.to_be_bytes());
+        buf.extend_from_slice(&self.priority.to_be_bytes());
+        buf.extend_from_slice(&self.buffer_id.to_be_bytes());
+        buf.extend_from_slice(&self.out_port.to_be_bytes());
+        buf.extend_from_slice(&self.out_group.to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        buf.extend_from_slice(&[0; 2]);
+        self.match_fields.encode(buf);
+        for instruction in &self.instructions {
+            instruction.encode(buf);
+        }
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let cookie = cursor.u64()?;
+        let cookie_mask = cursor.u64()?;
+        let table_id = cursor.u8()?;
+        let command = FlowModCommand::from_u8(cursor.u8()?)?;
+        let idle_timeout = cursor.u16()?;
+        let hard_timeout = cursor.u16()?;
+        let priority = cursor.u16()?;
+        let buffer_id = cursor.u32()?;
+        let out_port = cursor.u32()?;
+        let out_group = cursor.u32()?;
+        let flags = cursor.u16()?;
+        cursor.skip(2)?;
+        let match_fields = Match::decode(cursor)?;
+        let instructions = decode_instructions(Cursor::new(cursor.rest()))?;
+
+        Ok(Self {
+            cookie,
+            cookie_mask,
+            table_id,
+            command,
+            idle_timeout,
+            hard_timeout,
+            priority,
+            buffer_id,
+            out_port,
+            out_group,
+            flags,
+            match_fields,
+            instructions,
+        })
+    }
+}
+
+/// OFPMP_FLOW request body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowStatsRequest {
+    pub table_id: u8,
+    pub out_port: u32,
+    pub out_group: u32,
+    pub cookie: u64,
+    pub cookie_mask: u64,
+    pub match_fields: Match,
+}
+
+impl FlowStatsRequest {
+    /// Every flow in every table
+    pub fn all() -> Self {
+        Self {
+            table_id: OFPTT_ALL,
+            out_port: ofp_port::ANY,
+            out_group: OFPG_ANY,
+            cookie: 0,
+            cookie_mask: 0,
+            match_fields: Match::new(),
+        }
+    }
+
+    pub fn cookie(mut self, cookie: u64, cookie_mask: u64) -> Self {
+        self.cookie = cookie;
+        self.cookie_mask = cookie_mask;
+        self
+    }
+}
+
+/// One entry of an OFPMP_FLOW reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowStats {
+    pub table_id: u8,
+    pub duration_sec: u32,
+    pub duration_nsec: u32,
+    pub priority: u16,
+    pub idle_timeout: u16,
+    pub hard_timeout: u16,
+    pub flags: u16,
+    pub cookie: u64,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub match_fields: Match,
+    pub instructions: Vec<Instruction>,
+}
+
+impl FlowStats {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.push(self.table_id);
+        buf.push(0);
+        buf.extend_from_slice(&self.duration_sec.to_be_bytes());
+        buf.extend_from_slice(&self.duration_nsec.to_be_bytes());
+        buf.extend_from_slice(&self.priority.to_be_bytes());
+        buf.extend_from_slice(&self.idle_timeout.to_be_bytes());
+        buf.extend_from_slice(&self.hard_timeout.to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&self.cookie.to_be_bytes());
+        buf.extend_from_slice(&self.packet_count.to_be_bytes());
+        buf.extend_from_slice(&self.byte_count.to_be_bytes());
+        self.match_fields.encode(buf);
+        for instruction in &self.instructions {
+            instruction.encode(buf);
+        }
+        let len = buf.len() - start;
+        patch_len(buf, start, len);
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let len = usize::from(cursor.u16()?);
+        if len < 2 {
+            bail!("Flow stats entry has invalid length {}", len);
+        }
+        let mut entry = cursor.sub(len - 2)?;
+        let table_id = entry.u8()?;
+        entry.skip(1)?;
+        let duration_sec = entry.u32()?;
+        let duration_nsec = entry.u32()?;
+        let priority = entry.u16()?;
+        let idle_timeout = entry.u16()?;
+        let hard_timeout = entry.u16()?;
+        let flags = entry.u16()?;
+        entry.skip(4)?;
+        let cookie = entry.u64()?;
+        let packet_count = entry.u64()?;
+        let byte_count = entry.u64()?;
+        let match_fields = Match::decode(&mut entry)?;
+        let instructions = decode_instructions(entry)?;
+
+        Ok(Self {
+            table_id,
+            duration_sec,
+            duration_nsec,
+            priority,
+            idle_timeout,
+            hard_timeout,
+            flags,
+            cookie,
+            packet_count,
+            byte_count,
+            match_fields,
+            instructions,
+        })
+    }
+}
+
+/// One entry of an OFPMP_PORT_STATS reply
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortStats {
+    pub port_no: u32,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_frame_err: u64,
+    pub rx_over_err: u64,
+    pub rx_crc_err: u64,
+    pub collisions: u64,
+    pub duration_sec: u32,
+    pub duration_nsec: u32,
+}
+
+impl PortStats {
+    fn counters(&self) -> [u64; 12] {
+        [
+            self.rx_packets,
+            self.tx_packets,
+            self.rx_bytes,
+            self.tx_bytes,
+            self.rx_dropped,
+            self.tx_dropped,
+            self.rx_errors,
+            self.tx_errors,
+            self.rx_frame_err,
+            self.rx_over_err,
+            self.rx_crc_err,
+            self.collisions,
+        ]
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.port_no.to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        for counter in self.counters() {
+            buf.extend_from_slice(&counter.to_be_bytes());
+        }
+        buf.extend_from_slice(&self.duration_sec.to_be_bytes());
+        buf.extend_from_slice(&self.duration_nsec.to_be_bytes());
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let port_no = cursor.u32()?;
+        cursor.skip(4)?;
+        let mut counters = [0u64; 12];
+        for counter in &mut counters {
+            *counter = cursor.u64()?;
+        }
+        let [rx_packets, tx_packets, rx_bytes, tx_bytes, rx_dropped, tx_dropped, rx_errors, tx_errors, rx_frame_err, rx_over_err, rx_crc_err, collisions] =
+            counters;
+        Ok(Self {
+            port_no,
+            rx_packets,
+            tx_packets,
+            rx_bytes,
+            tx_bytes,
+            rx_dropped,
+            tx_dropped,
+            rx_errors,
+            tx_errors,
+            rx_frame_err,
+            rx_over_err,
+            rx_crc_err,
+            collisions,
+            duration_sec: cursor.u32()?,
+            duration_nsec: cursor.u32()?,
+        })
+    }
+}
+
+/// OFPT_MULTIPART_REQUEST
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultipartRequest {
+    Flow(FlowStatsRequest),
+    /// ofp_port::ANY for every port
+    PortStats {
+        port_no: u32,
+    },
+    Other {
+        mp_type: u16,
+        body: Vec<u8>,
+    },
+}
+
+impl MultipartRequest {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let mp_type = match self {
+            MultipartRequest::Flow(_) => OFPMP_FLOW,
+            MultipartRequest::PortStats { .. } => OFPMP_PORT_STATS,
+            MultipartRequest::Other { mp_type, .. } => *mp_type,
+        };
+        buf.extend_from_slice(&mp_type.to_be_bytes());
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+
+        match self {
+            MultipartRequest::Flow(request) => {
+                buf.push(request.table_id);
+                buf.extend_from_slice(&[0; 3]);
+                buf.extend_from_slice(&request.out_port.to_be_bytes());
+                buf.extend_from_slice(&request.out_group.to_be_bytes());
+                buf.extend_from_slice(&[0; 4]);
+                buf.extend_from_slice(&request.cookie.to_be_bytes());
+                buf.extend_from_slice(&request.cookie_mask.to_be_bytes());
+                request.match_fields.encode(buf);
+            }
+            MultipartRequest::PortStats { port_no } => {
+                buf.extend_from_slice(&port_no.to_be_bytes());
+                buf.extend_from_slice(&[0; 4]);
+            }
+            MultipartRequest::Other { body, .. } => buf.extend_from_slice(body),
+        }
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let mp_type = cursor.u16()?;
+        cursor.skip(2 + 4)?;
+
+        Ok(match mp_type {
+            OFPMP_FLOW => {
+                let table_id = cursor.u8()?;
+                cursor.skip(3)?;
+                let out_port = cursor.u32()?;
+                let out_group = cursor.u32()?;
+                cursor.skip(4)?;
+                MultipartRequest::Flow(FlowStatsRequest {
+                    table_id,
+                    out_port,
+                    out_group,
+                    cookie: cursor.u64()?,
+                    cookie_mask: cursor.u64()?,
+                    match_fields: Match::decode(cursor)?,
+                })
+            }
+            OFPMP_PORT_STATS => MultipartRequest::PortStats {
+                port_no: cursor.u32()?,
+            },
+            mp_type => MultipartRequest::Other {
+                mp_type,
+                body: cursor.rest().to_vec(),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MultipartReplyBody {
+    Flow(Vec<FlowStats>),
+    PortStats(Vec<PortStats>),
+    Other { mp_type: u16, body: Vec<u8> },
+}
+
+/// OFPT_MULTIPART_REPLY; `flags` carries OFPMPF_REPLY_MORE while more parts follow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartReply {
+    pub flags: u16,
+    pub body: MultipartReplyBody,
+}
+
+impl MultipartReply {
+    pub fn has_more(&self) -> bool {
+        self.flags & OFPMPF_REPLY_MORE != 0
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let mp_type = match &self.body {
+            MultipartReplyBody::Flow(_) => OFPMP_FLOW,
+            MultipartReplyBody::PortStats(_) => OFPMP_PORT_STATS,
+            MultipartReplyBody::Other { mp_type, .. } => *mp_type,
+        };
+        buf.extend_from_slice(&mp_type.to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+
+        match &self.body {
+            MultipartReplyBody::Flow(flows) => flows.iter().for_each(|f| f.encode(buf)),
+            MultipartReplyBody::PortStats(ports) => ports.iter().for_each(|p| p.encode(buf)),
+            MultipartReplyBody::Other { body, .. } => buf.extend_from_slice(body),
+        }
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let mp_type = cursor.u16()?;
+        let flags = cursor.u16()?;
+        cursor.skip(4)?;
+
+        let body = match mp_type {
+            OFPMP_FLOW => {
+                let mut flows = Vec::new();
+                while cursor.remaining() > 0 {
+                    flows.push(FlowStats::decode(cursor)?);
+                }
+                MultipartReplyBody::Flow(flows)
+            }
+            OFPMP_PORT_STATS => {
+                let mut ports = Vec::new();
+                while cursor.remaining() > 0 {
+                    ports.push(PortStats::decode(cursor)?);
+                }
+                MultipartReplyBody::PortStats(ports)
+            }
+            mp_type => MultipartReplyBody::Other {
+                mp_type,
+                body: cursor.rest().to_vec(),
+            },
+        };
+        Ok(Self { flags, body })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupModCommand {
+    Add = 0,
+    Modify = 1,
+    Delete = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupType {
+    All = 0,
+    Select = 1,
+    Indirect = 2,
+    FastFailover = 3,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+    pub weight: u16,
+    pub watch_port: u32,
+    pub watch_group: u32,
+    pub actions: Vec<Action>,
+}
+
+impl Bucket {
+    pub fn new(actions: Vec<Action>) -> Self {
+        Self {
+            weight: 0,
+            watch_port: ofp_port::ANY,
+            watch_group: OFPG_ANY,
+            actions,
+        }
+    }
+}
+
+/// OFPT_GROUP_MOD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupMod {
+    pub command: GroupModCommand,
+    pub group_type: GroupType,
+    pub group_id: u32,
+    pub buckets: Vec<Bucket>,
+}
+
+impl GroupMod {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(self.command as u16).to_be_bytes());
+        buf.push(self.group_type as u8);
+        buf.push(0);
+        buf.extend_from_slice(&self.group_id.to_be_bytes());
+        for bucket in &self.buckets {
+            let start = buf.len();
+            buf.extend_from_slice(&0u16.to_be_bytes());
+            buf.extend_from_slice(&bucket.weight.to_be_bytes());
+            buf.extend_from_slice(&bucket.watch_port.to_be_bytes());
+            buf.extend_from_slice(&bucket.watch_group.to_be_bytes());
+            buf.extend_from_slice(&[0; 4]);
+            encode_actions(&bucket.actions, buf);
+            let len = buf.len() - start;
+            patch_len(buf, start, len);
+        }
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let command = match cursor.u16()? {
+            0 => GroupModCommand::Add,
+            1 => GroupModCommand::Modify,
+            2 => GroupModCommand::Delete,
+            other => bail!("Unknown group mod command {}", other),
+        };
+        let group_type = match cursor.u8()? {
+            0 => GroupType::All,
+            1 => GroupType::Select,
+            2 => GroupType::Indirect,
+            3 => GroupType::FastFailover,
+            other => bail!("Unknown group type {}", other),
+        };
+        cursor.skip(1)?;
+        let group_id = cursor.u32()?;
+
+        let mut buckets = Vec::new();
+        while cursor.remaining() > 0 {
+            let len = usize::from(cursor.u16()?);
+            if len < 16 {
+                bail!("Group bucket has invalid length {}", len);
+            }
+            let mut bucket = cursor.sub(len - 2)?;
+            let weight = bucket.u16()?;
+            let watch_port = bucket.u32()?;
+            let watch_group = bucket.u32()?;
+            bucket.skip(4)?;
+            buckets.push(Bucket {
+                weight,
+                watch_port,
+                watch_group,
+                actions: decode_actions(bucket)?,
+            });
+        }
+
+        Ok(Self {
+            command,
+            group_type,
+            group_id,
+            buckets,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeterModCommand {
+    Add = 0,
+    Modify = 1,
+    Delete = 2,
+}
+
+/// Meter band; rates are in kb/s or packets/s depending on the meter flags
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeterBand {
+    Drop {
+        rate: u32,
+        burst_size: u32,
+    },
+    DscpRemark {
+        rate: u32,
+        burst_size: u32,
+        prec_level: u8,
+    },
+}
+
+/// OFPT_METER_MOD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeterMod {
+    pub command: MeterModCommand,
+    pub flags: u16,
+    pub meter_id: u32,
+    pub bands: Vec<MeterBand>,
+}
+
+impl MeterMod {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&(self.command as u16).to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        buf.extend_from_slice(&self.meter_id.to_be_bytes());
+        for band in &self.bands {
+            let (band_type, rate, burst_size, prec_level) = match band {
+                MeterBand::Drop { rate, burst_size } => (1u16, rate, burst_size, 0),
+                MeterBand::DscpRemark {
+                    rate,
+                    burst_size,
+                    prec_level,
+                } => (2, rate, burst_size, *prec_level),
+            };
+            buf.extend_from_slice(&band_type.to_be_bytes());
+            buf.extend_from_slice(&16u16.to_be_bytes());
+            buf.extend_from_slice(&rate.to_be_bytes());
+            buf.extend_from_slice(&burst_size.to_be_bytes());
+            buf.extend_from_slice(&[prec_level, 0, 0, 0]);
+        }
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self> {
+        let command = match cursor.u16()? {
+            0 => MeterModCommand::Add,
+            1 => MeterModCommand::Modify,
+            2 => MeterModCommand::Delete,
+            other => bail!("Unknown meter mod command {}", other),
+        };
+        let flags = cursor.u16()?;
+        let meter_id = cursor.u32()?;
+
+        let mut bands = Vec::new();
+        while cursor.remaining() > 0 {
+            let band_type = cursor.u16()?;
+            let len = usize::from(cursor.u16()?);
+            if len < 16 {
+                bail!("Meter band has invalid length {}", len);
+            }
+            let mut band = cursor.sub(len - 4)?;
+            let rate = band.u32()?;
+            let burst_size = band.u32()?;
+            bands.push(match band_type {
+                1 => MeterBand::Drop { rate, burst_size },
+                2 => MeterBand::DscpRemark {
+                    rate,
+                    burst_size,
+                    prec_level: band.u8()?,
+                },
+                other => bail!("Unsupported meter band type {}", other),
+            });
+        }
+
+        Ok(Self {
+            command,
+            flags,
+            meter_id,
+            bands,
+        })
+    }
+}
+
+/// OFPT_FEATURES_REPLY
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeaturesReply {
+    pub datapath_id: u64,
+    pub n_buffers: u32,
+    pub n_tables: u8,
+    pub auxiliary_id: u8,
+    pub capabilities: u32,
+}
+
+/// OFPT_PACKET_IN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketIn {
+    pub buffer_id: u32,
+    pub total_len: u16,
+    pub reason: u8,
+    pub table_id: u8,
+    pub cookie: u64,
+    pub match_fields: Match,
+    pub data: Vec<u8>,
+}
+
+/// OFPT_FLOW_REMOVED
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowRemoved {
+    pub cookie: u64,
+    pub priority: u16,
+    pub reason: u8,
+    pub table_id: u8,
+    pub duration_sec: u32,
+    pub duration_nsec: u32,
+    pub idle_timeout: u16,
+    pub hard_timeout: u16,
+    pub packet_count: u64,
+    pub byte_count: u64,
+    pub match_fields: Match,
+}
+
+/// OFPT_ERROR reported by the switch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenFlowError {
+    pub error_type: u16,
+    pub code: u16,
+    /// At least the first 64 bytes of the failed request
+    pub data: Vec<u8>,
+}
+
+impl OpenFlowError {
+    pub const HELLO_FAILED: u16 = 0;
+    pub const BAD_REQUEST: u16 = 1;
+    pub const BAD_ACTION: u16 = 2;
+    pub const BAD_INSTRUCTION: u16 = 3;
+    pub const BAD_MATCH: u16 = 4;
+    pub const FLOW_MOD_FAILED: u16 = 5;
+    pub const GROUP_MOD_FAILED: u16 = 6;
+    pub const METER_MOD_FAILED: u16 = 12;
+
+    pub fn type_name(&self) -> &'static str {
+        match self.error_type {
+            0 => "HELLO_FAILED",
+            1 => "BAD_REQUEST",
+            2 => "BAD_ACTION",
+            3 => "BAD_INSTRUCTION",
+            4 => "BAD_MATCH",
+            5 => "FLOW_MOD_FAILED",
+            6 => "GROUP_MOD_FAILED",
+            7 => "PORT_MOD_FAILED",
+            8 => "TABLE_MOD_FAILED",
+            9 => "QUEUE_OP_FAILED",
+            10 => "SWITCH_CONFIG_FAILED",
+            11 => "ROLE_REQUEST_FAILED",
+            12 => "METER_MOD_FAILED",
+            13 => "TABLE_FEATURES_FAILED",
+            0xffff => "EXPERIMENTER",
+            _ => "UNKNOWN",
+        }
+    }
+
+    pub fn code_name(&self) -> Option<&'static str> {
+        let names: &[&str] = match self.error_type {
+            0 => &["INCOMPATIBLE", "EPERM"],
+            1 => &[
+                "BAD_VERSION",
+                "BAD_TYPE",
+                "BAD_MULTIPART",
+                "BAD_EXPERIMENTER",
+                "BAD_EXP_TYPE",
+                "EPERM",
+                "BAD_LEN",
+                "BUFFER_EMPTY",
+                "BUFFER_UNKNOWN",
+                "BAD_TABLE_ID",
+                "IS_SLAVE",
+                "BAD_PORT",
+                "BAD_PACKET",
+                "MULTIPART_BUFFER_OVERFLOW",
+            ],
+            2 => &[
+                "BAD_TYPE",
+                "BAD_LEN",
+                "BAD_EXPERIMENTER",
+                "BAD_EXP_TYPE",
+                "BAD_OUT_PORT",
+                "BAD_ARGUMENT",
+                "EPERM",
+                "TOO_MANY",
+                "BAD_QUEUE",
+                "BAD_OUT_GROUP",
+                "MATCH_INCONSISTENT",
+                "UNSUPPORTED_ORDER",
+                "BAD_TAG",
+                "BAD_SET_TYPE",
+                "BAD_SET_LEN",
+                "BAD_SET_ARGUMENT",
+            ],
+            3 => &[
+                "UNKNOWN_INST",
+                "UNSUP_INST",
+                "BAD_TABLE_ID",
+                "UNSUP_METADATA",
+                "UNSUP_METADATA_MASK",
+                "BAD_EXPERIMENTER",
+                "BAD_EXP_TYPE",
+                "BAD_LEN",
+                "EPERM",
+            ],
+            4 => &[
+                "BAD_TYPE",
+                "BAD_LEN",
+                "BAD_TAG",
+                "BAD_DL_ADDR_MASK",
+                "BAD_NW_ADDR_MASK",
+                "BAD_WILDCARDS",
+                "BAD_FIELD",
+                "BAD_VALUE",
+                "BAD_MASK",
+                "BAD_PREREQ",
+                "DUP_FIELD",
+                "EPERM",
+            ],
+            5 => &[
+                "UNKNOWN",
+                "TABLE_FULL",
+                "BAD_TABLE_ID",
+                "OVERLAP",
+                "EPERM",
+                "BAD_TIMEOUT",
+                "BAD_COMMAND",
+                "BAD_FLAGS",
+            ],
+            6 => &[
+                "GROUP_EXISTS",
+                "INVALID_GROUP",
+                "WEIGHT_UNSUPPORTED",
+                "OUT_OF_GROUPS",
+                "OUT_OF_BUCKETS",
+                "CHAINING_UNSUPPORTED",
+                "WATCH_UNSUPPORTED",
+                "LOOP",
+                "UNKNOWN_GROUP",
+                "CHAINED_GROUP",
+                "BAD_TYPE",
+                "BAD_COMMAND",
+                "BAD_BUCKET",
+                "BAD_WATCH",
+                "EPERM",
+            ],
+            12 => &[
+                "UNKNOWN",
+                "METER_EXISTS",
+                "INVALID_METER",
+                "UNKNOWN_METER",
+                "BAD_COMMAND",
+                "BAD_FLAGS",
+                "BAD_RATE",
+                "BAD_BURST",
+                "BAD_BAND",
+                "BAD_BAND_VALUE",
+                "OUT_OF_METERS",
+                "OUT_OF_BANDS",
+            ],
+            _ => &[],
+        };
+        names.get(usize::from(self.code)).copied()
+    }
+
+    /// Type of the request that failed, from the echoed header
+    pub fn failed_message_type(&self) -> Option<u8> {
+        self.data.get(1).copied()
+    }
+}
+
+impl fmt::Display for OpenFlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OpenFlow error {}", self.type_name())?;
+        match self.code_name() {
+            Some(code) => write!(f, "/{}", code)?,
+            None => write!(f, "/code {}", self.code)?,
+        }
+        if let Some(message_type) = self.failed_message_type() {
+            write!(f, " (in reply to message type {})", message_type)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for OpenFlowError {}
+
+/// A decoded OpenFlow 1.3 message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Supported versions as an OFPHET_VERSIONBITMAP bitmap
+    Hello {
+        versions: u32,
+    },
+    Error(OpenFlowError),
+    EchoRequest(Vec<u8>),
+    EchoReply(Vec<u8>),
+    FeaturesRequest,
+    FeaturesReply(FeaturesReply),
+    PacketIn(PacketIn),
+    FlowRemoved(FlowRemoved),
+    FlowMod(FlowMod),
+    GroupMod(GroupMod),
+    MeterMod(MeterMod),
+    MultipartRequest(MultipartRequest),
+    MultipartReply(MultipartReply),
+    BarrierRequest,
+    BarrierReply,
+    /// Anything this codec doesn't model (port status, packet-out, ...)
+    Other {
+        message_type: u8,
+        body: Vec<u8>,
+    },
+}
+
+impl Message {
+    /// Hello advertising OpenFlow 1.3 only
+    pub fn hello() -> Self {
+        Message::Hello {
+            versions: 1 << OFP_VERSION,
+        }
+    }
+
+    pub fn message_type(&self) -> u8 {
+        use OpenFlowMessageType as T;
+        let t = match self {
+            Message::Hello { .. } => T::Hello,
+            Message::Error(_) => T::Error,
+            Message::EchoRequest(_) => T::EchoRequest,
+            Message::EchoReply(_) => T::EchoReply,
+            Message::FeaturesRequest => T::FeaturesRequest,
+            Message::FeaturesReply(_) => T::FeaturesReply,
+            Message::PacketIn(_) => T::PacketIn,
+            Message::FlowRemoved(_) => T::FlowRemoved,
+            Message::FlowMod(_) => T::FlowMod,
+            Message::GroupMod(_) => T::GroupMod,
+            Message::MeterMod(_) => T::MeterMod,
+            Message::MultipartRequest(_) => T::MultipartRequest,
+            Message::MultipartReply(_) => T::MultipartReply,
+            Message::BarrierRequest => T::BarrierRequest,
+            Message::BarrierReply => T::BarrierReply,
+            Message::Other { message_type, .. } => return *message_type,
+        };
+        t.as_u8()
+    }
+
+    /// Full wire encoding including the header
+    pub fn encode(&self, xid: u32) -> Vec<u8> {
+        let mut buf = vec![0; OFP_HEADER_LEN];
+        match self {
+            Message::Hello { versions } => {
+                if *versions != 0 {
+                    buf.extend_from_slice(&OFPHET_VERSIONBITMAP.to_be_bytes());
+                    buf.extend_from_slice(&8u16.to_be_bytes());
+                    buf.extend_from_slice(&versions.to_be_bytes());
+                }
+            }
+            Message::Error(error) => {
+                buf.extend_from_slice(&error.error_type.to_be_bytes());
+                buf.extend_from_slice(&error.code.to_be_bytes());
+                buf.extend_from_slice(&error.data);
+            }
+            Message::EchoRequest(data) | Message::EchoReply(data) => buf.extend_from_slice(data),
+            Message::FeaturesRequest | Message::BarrierRequest | Message::BarrierReply => {}
+            Message::FeaturesReply(features) => {
+                buf.extend_from_slice(&features.datapath_id.to_be_bytes());
+                buf.extend_from_slice(&features.n_buffers.to_be_bytes());
+                buf.push(features.n_tables);
+                buf.push(features.auxiliary_id);
+                buf.extend_from_slice(&[0; 2]);
+                buf.extend_from_slice(&features.capabilities.to_be_bytes());
+                buf.extend_from_slice(&[0; 4]);
+            }
+            Message::PacketIn(packet) => {
+                buf.extend_from_slice(&packet.buffer_id.to_be_bytes());
+                buf.extend_from_slice(&packet.total_len.to_be_bytes());
+                buf.push(packet.reason);
+                buf.push(packet.table_id);
+                buf.extend_from_slice(&packet.cookie.to_be_bytes());
+                packet.match_fields.encode(&mut buf);
+                buf.extend_from_slice(&[0; 2]);
+                buf.extend_from_slice(&packet.data);
+            }
+            Message::FlowRemoved(removed) => {
+                buf.extend_from_slice(&removed.cookie.to_be_bytes());
+                buf.extend_from_slice(&removed.priority.to_be_bytes());
+                buf.push(removed.reason);
+                buf.push(removed.table_id);
+                buf.extend_from_slice(&removed.duration_sec.to_be_bytes());
+                buf.extend_from_slice(&removed.duration_nsec.to_be_bytes());
+                buf.extend_from_slice(&removed.idle_timeout.to_be_bytes());
+                buf.extend_from_slice(&removed.hard_timeout.to_be_bytes());
+                buf.extend_from_slice(&removed.packet_count.to_be_bytes());
+                buf.extend_from_slice(&removed.byte_count.to_be_bytes());
+                removed.match_fields.encode(&mut buf);
+            }
+            Message::FlowMod(flow_mod) => flow_mod.encode(&mut buf),
+            Message::GroupMod(group_mod) => group_mod.encode(&mut buf),
+            Message::MeterMod(meter_mod) => meter_mod.encode(&mut buf),
+            Message::MultipartRequest(request) => request.encode(&mut buf),
+            Message::MultipartReply(reply) => reply.encode(&mut buf),
+            Message::Other { body, .. } => buf.extend_from_slice(body),
+        }
+
+        let header = OpenFlowHeader {
+            version: OFP_VERSION,
+            message_type: self.message_type(),
+            length: buf.len() as u16,
+            xid,
+        };
+        buf[..OFP_HEADER_LEN].copy_from_slice(&header.to_bytes());
+        buf
+    }
+
+    /// Decode a message body (everything after the header)
+    pub fn decode(header: &OpenFlowHeader, body: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(body);
+        let cursor = &mut cursor;
+        Ok(match header.message_type {
+            0 => {
+                let mut versions = 0;
+                while cursor.remaining() >= 4 {
+                    let element_type = cursor.u16()?;
+                    let len = usize::from(cursor.u16()?);
+                    if len < 4 {
+                        break;
+                    }
+                    let mut element = cursor.sub(len - 4)?;
+                    cursor.skip((padded_len(len) - len).min(cursor.remaining()))?;
+                    if element_type == OFPHET_VERSIONBITMAP && element.remaining() >= 4 {
+                        versions = element.u32()?;
+                    }
+                }
+                // Without a bitmap the header version is the highest supported
+                if versions == 0 && header.version < 32 {
+                    versions = 1 << header.version;
+                }
+                Message::Hello { versions }
+            }
+            1 => Message::Error(OpenFlowError {
+                error_type: cursor.u16()?,
+                code: cursor.u16()?,
+                data: cursor.rest().to_vec(),
+            }),
+            2 => Message::EchoRequest(body.to_vec()),
+            3 => Message::EchoReply(body.to_vec()),
+            5 => Message::FeaturesRequest,
+            6 => {
+                let datapath_id = cursor.u64()?;
+                let n_buffers = cursor.u32()?;
+                let n_tables = cursor.u8()?;
+                let auxiliary_id = cursor.u8()?;
+                cursor.skip(2)?;
+                Message::FeaturesReply(FeaturesReply {
+                    datapath_id,
+                    n_buffers,
+                    n_tables,
+                    auxiliary_id,
+                    capabilities: cursor.u32()?,
+                })
+            }
+            10 => {
+                let buffer_id = cursor.u32()?;
+                let total_len = cursor.u16()?;
+                let reason = cursor.u8()?;
+                let table_id = cursor.u8()?;
+                let cookie = cursor.u64()?;
+                let match_fields = Match::decode(cursor)?;
+                cursor.skip(2)?;
+                Message::PacketIn(PacketIn {
+                    buffer_id,
+                    total_len,
+                    reason,
+                    table_id,
+                    cookie,
+                    match_fields,
+                    data: cursor.rest().to_vec(),
+                })
+            }
+            11 => {
+                let cookie = cursor.u64()?;
+                let priority = cursor.u16()?;
+                let reason = cursor.u8()?;
+                let table_id = cursor.u8()?;
+                Message::FlowRemoved(FlowRemoved {
+                    cookie,
+                    priority,
+                    reason,
+                    table_id,
+                    duration_sec: cursor.u32()?,
+                    duration_nsec: cursor.u32()?,
+                    idle_timeout: cursor.u16()?,
+                    hard_timeout: cursor.u16()?,
+                    packet_count: cursor.u64()?,
+                    byte_count: cursor.u64()?,
+                    match_fields: Match::decode(cursor)?,
+                })
+            }
+            14 => Message::FlowMod(FlowMod::decode(cursor)?),
+            15 => Message::GroupMod(GroupMod::decode(cursor)?),
+            18 => Message::MultipartRequest(MultipartRequest::decode(cursor)?),
+            19 => Message::MultipartReply(MultipartReply::decode(cursor)?),
+            20 => Message::BarrierRequest,
+            21 => Message::BarrierReply,
+            29 => Message::MeterMod(MeterMod::decode(cursor)?),
+            message_type => Message::Other {
+                message_type,
+                body: body.to_vec(),
+            },
+        })
+    }
+
+    /// Decode a complete frame (header and body)
+    pub fn from_bytes(frame: &[u8]) -> Result<(OpenFlowHeader, Self)> {
+        let header = OpenFlowHeader::from_bytes(frame)?;
+        let len = usize::from(header.length);
+        if len < OFP_HEADER_LEN || len > frame.len() {
+            bail!(
+                "OpenFlow frame length {} doesn't fit {} bytes",
+                len,
+                frame.len()
+            );
+        }
+        let message = Self::decode(&header, &frame[OFP_HEADER_LEN..len])?;
+        Ok((header, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(message: Message) {
+        let bytes = message.encode(42);
+        assert_eq!(bytes.len() % 8, 0, "{:?} is not 8-byte aligned", message);
+        let (header, decoded) = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(header.xid, 42);
+        assert_eq!(usize::from(header.length), bytes.len());
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn test_match_encoding() {
+        let m = Match::new().with(OxmField::uint(OxmKind::InPort, 1));
+        let mut buf = Vec::new();
+        m.encode(&mut buf);
+        // type=OXM, length=12 (unpadded), OXM_OF_IN_PORT=1, then 4 bytes of padding
+        assert_eq!(buf, [0, 1, 0, 12, 0x80, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0, 0]);
+
+        let net = OxmField::ipv4(OxmKind::Ipv4Src, "10.1.2.3".parse().unwrap(), 8);
+        assert_eq!(net.value, [10, 0, 0, 0]);
+        assert_eq!(net.mask.as_deref(), Some(&[255, 0, 0, 0][..]));
+        assert!(net.matches(&[10, 9, 9, 9]));
+        assert!(!net.matches(&[11, 0, 0, 0]));
+        // A full mask is an exact match
+        assert_eq!(
+            OxmField::ipv4(OxmKind::Ipv4Dst, "192.0.2.1".parse().unwrap(), 32).mask,
+            None
+        );
+    }
+
+    #[test]
+    fn test_flow_mod_roundtrip() {
+        let flow = FlowMod::add(
+            3,
+            1000,
+            Match::new()
+                .with(OxmField::uint(OxmKind::InPort, 7))
+                .with(OxmField::uint(OxmKind::EthType, 0x0800))
+                .with(OxmField::uint(OxmKind::IpProto, 6))
+                .with(OxmField::ipv4(
+                    OxmKind::Ipv4Src,
+                    "224.0.0.0".parse().unwrap(),
+                    4,
+                ))
+                .with(OxmField::uint_masked(OxmKind::CtState, 0x22, 0x22))
+                .with(OxmField::uint_masked(OxmKind::TcpFlags, 0x02, 0x12)),
+        )
+        .cookie(0xDEAD_0001, u64::MAX)
+        .timeouts(30, 0)
+        .instruction(Instruction::Meter(5))
+        .apply(vec![
+            Action::SetField(OxmField::uint(OxmKind::Reg(2), 0x51820)),
+            Action::SetNwTtl(64),
+            Action::resubmit(10),
+            Action::controller(128),
+        ])
+        .instruction(Instruction::GotoTable(4));
+        roundtrip(Message::FlowMod(flow));
+        roundtrip(Message::FlowMod(
+            FlowMod::delete(OFPTT_ALL, Match::new()).cookie(0xCAFE, 0xFFFF),
+        ));
+    }
+
+    #[test]
+    fn test_stats_group_meter_error_roundtrip() {
+        roundtrip(Message::hello());
+        roundtrip(Message::MultipartRequest(MultipartRequest::Flow(
+            FlowStatsRequest::all().cookie(1, 1),
+        )));
+        roundtrip(Message::MultipartReply(MultipartReply {
+            flags: OFPMPF_REPLY_MORE,
+            body: MultipartReplyBody::Flow(vec![FlowStats {
+                table_id: 0,
+                duration_sec: 5,
+                duration_nsec: 0,
+                priority: 10,
+                idle_timeout: 0,
+                hard_timeout: 0,
+                flags: 0,
+                cookie: 9,
+                packet_count: 100,
+                byte_count: 6400,
+                match_fields: Match::new().with(OxmField::mac(OxmKind::EthSrc, [0xff; 6])),
+                instructions: vec![Instruction::ApplyActions(vec![Action::output(
+                    ofp_port::NORMAL,
+                )])],
+            }]),
+        }));
+        roundtrip(Message::MultipartReply(MultipartReply {
+            flags: 0,
+            body: MultipartReplyBody::PortStats(vec![PortStats {
+                port_no: 1,
+                rx_packets: 10,
+                tx_bytes: 20,
+                ..Default::default()
+            }]),
+        }));
+        roundtrip(Message::GroupMod(GroupMod {
+            command: GroupModCommand::Add,
+            group_type: GroupType::Select,
+            group_id: 1,
+            buckets: vec![
+                Bucket::new(vec![Action::output(1)]),
+                Bucket::new(vec![Action::PushVlan(0x8100), Action::output(2)]),
+            ],
+        }));
+        roundtrip(Message::MeterMod(MeterMod {
+            command: MeterModCommand::Add,
+            flags: OFPMF_KBPS | OFPMF_BURST,
+            meter_id: 5,
+            bands: vec![
+                MeterBand::Drop {
+                    rate: 1000,
+                    burst_size: 100,
+                },
+                MeterBand::DscpRemark {
+                    rate: 500,
+                    burst_size: 50,
+                    prec_level: 1,
+                },
+            ],
+        }));
+
+        let error = OpenFlowError {
+            error_type: OpenFlowError::FLOW_MOD_FAILED,
+            code: 2,
+            data: Message::BarrierRequest.encode(7),
+        };
+        assert_eq!(
+            error.to_string(),
+            "OpenFlow error FLOW_MOD_FAILED/BAD_TABLE_ID (in reply to message type 20)"
+        );
+        let bytes = Message::Error(error.clone()).encode(7);
+        assert_eq!(
+            Message::from_bytes(&bytes).unwrap().1,
+            Message::Error(error)
+        );
+    }
+}
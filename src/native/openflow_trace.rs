@@ -0,0 +1,288 @@
+//! Offline OpenFlow 1.3 pipeline evaluator
+//! Runs a packet through a set of flow mods the way a switch would - highest
+//! priority match per table, apply-actions in order, resubmit, goto_table and
+//! the action set - and records the path taken, so flow sets can be checked
+//! without a switch.
+
+use super::openflow_msg::{ofp_port, Action, FlowMod, Instruction, Match, OxmField, OxmKind};
+
+/// Resubmit/goto depth at which evaluation stops (OVS uses the same bound)
+const MAX_DEPTH: usize = 64;
+
+/// Packet header fields; fields that aren't set read as zero
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Packet {
+    fields: Vec<OxmField>,
+}
+
+impl Packet {
+    /// Packet with exactly the (unmasked) values of `fields`
+    pub fn new(fields: Match) -> Self {
+        let mut packet = Self::default();
+        for field in fields.fields {
+            packet.set(field);
+        }
+        packet
+    }
+
+    pub fn get(&self, kind: OxmKind) -> Option<&OxmField> {
+        self.fields.iter().find(|f| f.kind == kind)
+    }
+
+    fn value(&self, field: &OxmField) -> Vec<u8> {
+        match self.get(field.kind) {
+            Some(own) => own.value.clone(),
+            None => vec![0; field.value.len()],
+        }
+    }
+
+    pub fn set(&mut self, field: OxmField) {
+        let field = OxmField {
+            mask: None,
+            ..field
+        };
+        match self.fields.iter_mut().find(|f| f.kind == field.kind) {
+            Some(existing) => *existing = field,
+            None => self.fields.push(field),
+        }
+    }
+
+    pub fn fields(&self) -> &[OxmField] {
+        &self.fields
+    }
+
+    pub fn in_port(&self) -> u32 {
+        self.get(OxmKind::InPort)
+            .map_or(0, |f| f.value_uint() as u32)
+    }
+
+    fn matches(&self, m: &Match) -> bool {
+        m.fields.iter().all(|f| f.matches(&self.value(f)))
+    }
+}
+
+/// One table lookup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    pub table: u8,
+    /// Resubmit/goto nesting level
+    pub depth: usize,
+    /// Index of the matching flow, None on a table miss
+    pub flow: Option<usize>,
+    /// Other flows that matched at the same priority; which one wins is up to the switch
+    pub ties: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    /// Ports the packet was output to, in order (reserved ports kept as-is)
+    pub outputs: Vec<u32>,
+    /// Groups the packet was sent to
+    pub groups: Vec<u32>,
+    /// Packet headers after all set-field actions
+    pub packet: Packet,
+    /// Evaluation stopped at the resubmit depth limit
+    pub truncated: bool,
+}
+
+impl Trace {
+    pub fn dropped(&self) -> bool {
+        self.outputs.is_empty() && self.groups.is_empty()
+    }
+
+    /// The flows the packet matched, in order
+    pub fn matched(&self) -> impl Iterator<Item = usize> + '_ {
+        self.steps.iter().filter_map(|s| s.flow)
+    }
+}
+
+/// Evaluate `packet` against `flows`, starting at table 0
+pub fn trace(flows: &[FlowMod], packet: Packet) -> Trace {
+    let mut trace = Trace {
+        packet,
+        ..Default::default()
+    };
+    let mut action_set = Vec::new();
+    run_table(flows, 0, 0, &mut trace, &mut action_set);
+    for action in action_set {
+        execute(flows, &action, 0, &mut trace);
+    }
+    trace
+}
+
+/// Highest-priority matching flow in a table, plus same-priority ties
+fn lookup(flows: &[FlowMod], table: u8, packet: &Packet) -> Option<(usize, Vec<usize>)> {
+    let mut best: Option<(usize, Vec<usize>)> = None;
+    for (index, flow) in flows.iter().enumerate() {
+        if flow.table_id != table || !packet.matches(&flow.match_fields) {
+            continue;
+        }
+        match &mut best {
+            Some((winner, ties)) if flows[*winner].priority == flow.priority => ties.push(index),
+            Some((winner, _)) if flows[*winner].priority > flow.priority => {}
+            _ => best = Some((index, Vec::new())),
+        }
+    }
+    best
+}
+
+fn run_table(
+    flows: &[FlowMod],
+    table: u8,
+    depth: usize,
+    trace: &mut Trace,
+    action_set: &mut Vec<Action>,
+) {
+    if depth >= MAX_DEPTH {
+        trace.truncated = true;
+        return;
+    }
+
+    let Some((index, ties)) = lookup(flows, table, &trace.packet) else {
+        // OpenFlow 1.3 table miss without a miss flow: drop
+        trace.steps.push(TraceStep {
+            table,
+            depth,
+            flow: None,
+            ties: Vec::new(),
+        });
+        return;
+    };
+    trace.steps.push(TraceStep {
+        table,
+        depth,
+        flow: Some(index),
+        ties,
+    });
+
+    let mut goto = None;
+    for instruction in &flows[index].instructions {
+        match instruction {
+            Instruction::ApplyActions(actions) => {
+                for action in actions {
+                    execute(flows, action, depth, trace);
+                }
+            }
+            Instruction::WriteActions(actions) => action_set.extend(actions.iter().cloned()),
+            Instruction::ClearActions => action_set.clear(),
+            Instruction::WriteMetadata { metadata, mask } => {
+                let current = trace
+                    .packet
+                    .get(OxmKind::Metadata)
+                    .map_or(0, OxmField::value_uint);
+                let value = (current & !mask) | (metadata & mask);
+                trace.packet.set(OxmField::uint(OxmKind::Metadata, value));
+            }
+            Instruction::GotoTable(next) => goto = Some(*next),
+            Instruction::Meter(_) => {}
+        }
+    }
+
+    if let Some(next) = goto {
+        run_table(flows, next, depth + 1, trace, action_set);
+    }
+}
+
+fn execute(flows: &[FlowMod], action: &Action, depth: usize, trace: &mut Trace) {
+    match action {
+        Action::Output { port, .. } if *port == ofp_port::IN_PORT => {
+            let in_port = trace.packet.in_port();
+            trace.outputs.push(in_port);
+        }
+        Action::Output { port, .. } => trace.outputs.push(*port),
+        Action::Group(group) => trace.groups.push(*group),
+        Action::SetField(field) => trace.packet.set(field.clone()),
+        Action::SetNwTtl(ttl) => trace
+            .packet
+            .set(OxmField::uint(OxmKind::IpTtl, u64::from(*ttl))),
+        Action::DecNwTtl => {
+            let ttl = trace
+                .packet
+                .get(OxmKind::IpTtl)
+                .map_or(0, OxmField::value_uint);
+            trace
+                .packet
+                .set(OxmField::uint(OxmKind::IpTtl, ttl.saturating_sub(1)));
+        }
+        Action::Resubmit { in_port, table } => {
+            // A resubmit runs its own pipeline pass; its action set doesn't leak out
+            let mut action_set = Vec::new();
+            if u32::from(*in_port) == ofp_port::IN_PORT & 0xffff {
+                run_table(flows, *table, depth + 1, trace, &mut action_set);
+            } else {
+                let original = trace.packet.in_port();
+                trace
+                    .packet
+                    .set(OxmField::uint(OxmKind::InPort, u64::from(*in_port)));
+                run_table(flows, *table, depth + 1, trace, &mut action_set);
+                trace
+                    .packet
+                    .set(OxmField::uint(OxmKind::InPort, u64::from(original)));
+            }
+        }
+        Action::PushVlan(_)
+        | Action::PopVlan
+        | Action::CopyTtlOut
+        | Action::CopyTtlIn
+        | Action::SetQueue(_)
+        | Action::Experimenter { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_port(port: u32) -> Match {
+        Match::new().with(OxmField::uint(OxmKind::InPort, port.into()))
+    }
+
+    #[test]
+    fn test_priority_resubmit_and_set_field() {
+        let flows = vec![
+            FlowMod::add(0, 10, Match::new()).apply(vec![Action::output(ofp_port::NORMAL)]),
+            FlowMod::add(0, 100, in_port(1)).apply(vec![
+                Action::SetField(OxmField::uint(OxmKind::Reg(0), 7)),
+                Action::resubmit(1),
+                Action::output(3),
+            ]),
+            FlowMod::add(1, 5, Match::new().with(OxmField::uint(OxmKind::Reg(0), 7)))
+                .apply(vec![Action::output(2)]),
+        ];
+
+        let hit = trace(&flows, Packet::new(in_port(1)));
+        assert_eq!(hit.matched().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(hit.outputs, vec![2, 3]);
+        assert_eq!(hit.packet.get(OxmKind::Reg(0)).unwrap().value_uint(), 7);
+
+        let other = trace(&flows, Packet::new(in_port(9)));
+        assert_eq!(other.outputs, vec![ofp_port::NORMAL]);
+    }
+
+    #[test]
+    fn test_miss_ties_goto_and_loops() {
+        let flows = vec![
+            FlowMod::add(0, 50, Match::new()).instruction(Instruction::GotoTable(3)),
+            FlowMod::add(3, 1, Match::new())
+                .instruction(Instruction::WriteActions(vec![Action::output(4)])),
+            FlowMod::add(3, 1, in_port(1)).apply(vec![]),
+            FlowMod::add(5, 1, Match::new()).apply(vec![Action::resubmit(5)]),
+        ];
+
+        let result = trace(&flows, Packet::new(in_port(1)));
+        assert_eq!(result.steps[1].flow, Some(1));
+        assert_eq!(result.steps[1].ties, vec![2]);
+        // The action set runs at the end of the pipeline
+        assert_eq!(result.outputs, vec![4]);
+
+        let looping = vec![FlowMod::add(0, 1, Match::new()).apply(vec![Action::resubmit(0)])];
+        let result = trace(&looping, Packet::default());
+        assert!(result.truncated);
+        assert!(result.dropped());
+
+        let result = trace(&[], Packet::default());
+        assert_eq!(result.steps[0].flow, None);
+        assert!(result.dropped());
+    }
+}
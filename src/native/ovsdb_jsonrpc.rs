@@ -1,14 +1,21 @@
 //! Direct OVSDB JSON-RPC client - no wrappers, pure native protocol
 //! Talks directly to /var/run/openvswitch/db.sock
 
+use super::ovsdb_monitor::{MonitorRequest, OvsdbMonitor};
+use super::ovsdb_txn::{Atom, Condition, Datum, Mutation, OvsdbSchema, Row, Transaction, TransactionResult};
 use anyhow::{Context, Result};
 use serde_json::{json, Value};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
+use tokio::sync::OnceCell;
+
+const DATABASE: &str = "Open_vSwitch";
 
 /// Direct OVSDB JSON-RPC client
 pub struct OvsdbClient {
     socket_path: String,
+    /// Parsed schema, fetched on the first typed transaction
+    schema: OnceCell<OvsdbSchema>,
 }
 
 impl OvsdbClient {
@@ -16,6 +23,7 @@ impl OvsdbClient {
     pub fn new() -> Self {
         Self {
             socket_path: "/var/run/openvswitch/db.sock".to_string(),
+            schema: OnceCell::new(),
         }
     }
 
@@ -132,6 +140,26 @@ impl OvsdbClient {
         Ok(Value::Object(out))
     }
 
+    /// Parsed Open_vSwitch schema (cached per client)
+    pub async fn schema(&self) -> Result<&OvsdbSchema> {
+        self.schema
+            .get_or_try_init(|| async { OvsdbSchema::from_json(&self.get_schema().await?) })
+            .await
+    }
+
+    /// Run a typed transaction after checking it against the schema.
+    /// Failures carry every operation's result (see `TransactionError`).
+    pub async fn execute(&self, txn: &Transaction) -> Result<TransactionResult> {
+        txn.validate(self.schema().await?)?;
+        let result = self.rpc_call("transact", txn.to_params()).await?;
+        txn.parse_result(&result)
+    }
+
+    /// Monitor tables, streaming updates into an in-memory replica
+    pub async fn monitor(&self, requests: &[MonitorRequest]) -> Result<OvsdbMonitor> {
+        OvsdbMonitor::start(&self.socket_path, DATABASE, requests).await
+    }
+
     /// Transact - execute OVSDB operations
     pub async fn transact(&self, operations: Value) -> Result<Value> {
         let mut params = vec![json!("Open_vSwitch")];
@@ -154,54 +182,35 @@ impl OvsdbClient {
             return Ok(());
         }
 
-        // Generate UUIDs for bridge, port, and interface
-        let bridge_uuid = format!("bridge-{}", bridge_name);
-        let port_uuid = format!("port-{}", bridge_name);
-        let iface_uuid = format!("iface-{}", bridge_name);
-
-        let operations = json!([
-            {
-                "op": "insert",
-                "table": "Bridge",
-                "row": {
-                    "name": bridge_name,
-                    "datapath_type": "system",      // CRITICAL: Enables kernel interface and persistence
-                    "stp_enable": false,            // Disable Spanning Tree Protocol
-                    "ports": ["named-uuid", port_uuid],
-                    "other_config": {},             // Ensure other_config exists for future settings
-                    "external_ids": {}              // Ensure external_ids exists for future settings
-                },
-                "uuid-name": bridge_uuid
-            },
-            {
-                "op": "insert",
-                "table": "Port",
-                "row": {
-                    "name": bridge_name,
-                    "interfaces": ["named-uuid", iface_uuid]
-                },
-                "uuid-name": port_uuid
-            },
-            {
-                "op": "insert",
-                "table": "Interface",
-                "row": {
-                    "name": bridge_name,
-                    "type": "internal"
-                },
-                "uuid-name": iface_uuid
-            },
-            {
-                "op": "mutate",
-                "table": "Open_vSwitch",
-                "where": [],
-                "mutations": [
-                    ["bridges", "insert", ["set", [["named-uuid", bridge_uuid]]]]
-                ]
-            }
-        ]);
+        let mut txn = Transaction::new(DATABASE);
+        let iface = txn.insert(
+            "Interface",
+            Row::new().set("name", bridge_name).set("type", "internal"),
+        );
+        let port = txn.insert(
+            "Port",
+            Row::new()
+                .set("name", bridge_name)
+                .set("interfaces", Datum::set([&iface])),
+        );
+        let bridge = txn.insert(
+            "Bridge",
+            Row::new()
+                .set("name", bridge_name)
+                .set("datapath_type", "system") // CRITICAL: Enables kernel interface and persistence
+                .set("stp_enable", false) // Disable Spanning Tree Protocol
+                .set("ports", Datum::set([&port]))
+                .set("other_config", Datum::Map(Vec::new())) // Ensure other_config exists for future settings
+                .set("external_ids", Datum::Map(Vec::new())), // Ensure external_ids exists for future settings
+        );
+        txn.mutate(
+            DATABASE,
+            vec![],
+            vec![Mutation::insert("bridges", Datum::set([&bridge]))],
+        )
+        .comment(&format!("op-dbus: create bridge {}", bridge_name));
 
-        self.transact(operations).await?;
+        self.execute(&txn).await?;
 
         // Verify bridge was created and persisted
         if self.bridge_exists(bridge_name).await? {
@@ -217,38 +226,98 @@ impl OvsdbClient {
         // First, find the bridge UUID
         let bridge_uuid = self.find_bridge_uuid(bridge_name).await?;
 
-        let port_uuid = format!("port-{}", port_name);
-        let iface_uuid = format!("iface-{}", port_name);
+        let mut txn = Transaction::new(DATABASE);
+        let iface = txn.insert("Interface", Row::new().set("name", port_name));
+        let port = txn.insert(
+            "Port",
+            Row::new()
+                .set("name", port_name)
+                .set("interfaces", Datum::set([&iface])),
+        );
+        txn.mutate(
+            "Bridge",
+            vec![Condition::uuid(&bridge_uuid)],
+            vec![Mutation::insert("ports", Datum::set([&port]))],
+        )
+        .comment(&format!("op-dbus: add port {} to {}", port_name, bridge_name));
 
-        let operations = json!([
-            {
-                "op": "insert",
-                "table": "Port",
-                "row": {
-                    "name": port_name,
-                    "interfaces": ["set", [["named-uuid", iface_uuid]]]
-                },
-                "uuid-name": port_uuid
-            },
-            {
-                "op": "insert",
-                "table": "Interface",
-                "row": {
-                    "name": port_name
-                },
-                "uuid-name": iface_uuid
-            },
-            {
-                "op": "mutate",
-                "table": "Bridge",
-                "where": [["_uuid", "==", ["uuid", &bridge_uuid]]],
-                "mutations": [
-                    ["ports", "insert", ["set", [["named-uuid", port_uuid]]]]
-                ]
-            }
-        ]);
+        self.execute(&txn).await?;
+        Ok(())
+    }
+
+    /// Remove a port from a bridge; its interfaces go with it
+    pub async fn delete_port(&self, bridge_name: &str, port_name: &str) -> Result<()> {
+        let bridge_uuid = self.find_bridge_uuid(bridge_name).await?;
+
+        let mut txn = Transaction::new(DATABASE);
+        txn.select("Port", vec![Condition::eq("name", port_name)], Some(&["_uuid"]));
+        let result = self.execute(&txn).await?;
+        let port_uuid = result.results[0]
+            .rows
+            .iter()
+            .flatten()
+            .find_map(|row| match Atom::from_json(&row["_uuid"]) {
+                Some(Atom::Uuid(uuid)) => Some(uuid),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow::anyhow!("Port '{}' not found", port_name))?;
+
+        // Port is not a root table: dropping the reference deletes the row
+        let mut txn = Transaction::new(DATABASE);
+        txn.mutate(
+            "Bridge",
+            vec![Condition::uuid(&bridge_uuid)],
+            vec![Mutation::delete("ports", Atom::Uuid(port_uuid))],
+        )
+        .comment(&format!("op-dbus: remove port {} from {}", port_name, bridge_name));
 
-        self.transact(operations).await?;
+        self.execute(&txn).await?;
+        Ok(())
+    }
+
+    /// Set one external_ids key on a port
+    pub async fn set_port_external_id(&self, port_name: &str, key: &str, value: &str) -> Result<()> {
+        let mut txn = Transaction::new(DATABASE);
+        txn.mutate(
+            "Port",
+            vec![Condition::eq("name", port_name)],
+            vec![
+                Mutation::delete("external_ids", Datum::set([key])),
+                Mutation::insert("external_ids", Datum::map([(key, value)])),
+            ],
+        );
+
+        self.execute(&txn).await?;
+        Ok(())
+    }
+
+    /// Access VLAN of a port, None when it is untagged
+    pub async fn port_tag(&self, port_name: &str) -> Result<Option<u16>> {
+        let mut txn = Transaction::new(DATABASE);
+        txn.select("Port", vec![Condition::eq("name", port_name)], Some(&["tag"]));
+        let result = self.execute(&txn).await?;
+        let row = result.results[0]
+            .rows
+            .iter()
+            .flatten()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Port '{}' not found", port_name))?;
+
+        // An empty set when untagged, the bare integer otherwise
+        Ok(row["tag"].as_u64().and_then(|tag| u16::try_from(tag).ok()))
+    }
+
+    /// Set or clear the access VLAN of a port
+    pub async fn set_port_tag(&self, port_name: &str, tag: Option<u16>) -> Result<()> {
+        let mut txn = Transaction::new(DATABASE);
+        txn.update(
+            "Port",
+            vec![Condition::eq("name", port_name)],
+            Row::new().set("tag", Datum::set(tag.map(i64::from))),
+        )
+        .comment(&format!("op-dbus: set tag of port {}", port_name));
+
+        self.execute(&txn).await?;
         Ok(())
     }
 
@@ -256,23 +325,16 @@ impl OvsdbClient {
     pub async fn delete_bridge(&self, bridge_name: &str) -> Result<()> {
         let bridge_uuid = self.find_bridge_uuid(bridge_name).await?;
 
-        let operations = json!([
-            {
-                "op": "mutate",
-                "table": "Open_vSwitch",
-                "where": [],
-                "mutations": [
-                    ["bridges", "delete", ["uuid", &bridge_uuid]]
-                ]
-            },
-            {
-                "op": "delete",
-                "table": "Bridge",
-                "where": [["_uuid", "==", ["uuid", &bridge_uuid]]]
-            }
-        ]);
+        let mut txn = Transaction::new(DATABASE);
+        txn.mutate(
+            DATABASE,
+            vec![],
+            vec![Mutation::delete("bridges", Atom::Uuid(bridge_uuid.clone()))],
+        )
+        .delete("Bridge", vec![Condition::uuid(&bridge_uuid)])
+        .comment(&format!("op-dbus: delete bridge {}", bridge_name));
 
-        self.transact(operations).await?;
+        self.execute(&txn).await?;
         Ok(())
     }
 
@@ -406,27 +468,14 @@ impl OvsdbClient {
     /// Set interface type
     #[allow(dead_code)]
     pub async fn set_interface_type(&self, interface_name: &str, interface_type: &str) -> Result<()> {
-        let operations = json!([
-            {
-                "op": "update",
-                "table": "Interface",
-                "where": [["name", "==", interface_name]],
-                "row": {
-                    "type": interface_type
-                }
-            }
-        ]);
-
-        let result = self.transact(operations).await?;
-        // Check for errors in the response
-        if let Some(errors) = result.as_array() {
-            for error in errors {
-                if error.get("error").is_some() {
-                    return Err(anyhow::anyhow!("OVSDB transaction failed: {:?}", error));
-                }
-            }
-        }
-
+        let mut txn = Transaction::new(DATABASE);
+        txn.update(
+            "Interface",
+            vec![Condition::eq("name", interface_name)],
+            Row::new().set("type", interface_type),
+        );
+
+        self.execute(&txn).await?;
         Ok(())
     }
 }
@@ -0,0 +1,546 @@
+//! OVSDB monitor - streams table updates into an in-memory replica
+//!
+//! Uses `monitor_cond` (update2 notifications) and falls back to plain
+//! `monitor` on servers that don't support it. The replica holds the monitored
+//! columns of every row keyed by UUID; [`OvsdbMonitor::changes`] streams
+//! per-row change notifications as they are applied.
+
+use crate::native::ovsdb_txn::{ColumnKind, OvsdbSchema};
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::UnixStream;
+use tokio::sync::{broadcast, Mutex, RwLock};
+
+/// Row contents: column -> OVSDB wire value
+pub type OvsdbRow = Map<String, Value>;
+
+/// Monitored columns of one table; no columns means all
+#[derive(Debug, Clone)]
+pub struct MonitorRequest {
+    pub table: String,
+    pub columns: Vec<String>,
+    /// `monitor_cond` condition (RFC 7047 `where` syntax); ignored by plain `monitor`
+    pub condition: Option<Value>,
+}
+
+impl MonitorRequest {
+    pub fn table(table: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            columns: Vec::new(),
+            condition: None,
+        }
+    }
+
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.columns = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    pub fn condition(mut self, condition: Value) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    fn to_json(&self, conditional: bool) -> Value {
+        let mut request = json!({});
+        if !self.columns.is_empty() {
+            request["columns"] = json!(self.columns);
+        }
+        if conditional {
+            if let Some(condition) = &self.condition {
+                request["where"] = condition.clone();
+            }
+        }
+        json!([request])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Insert,
+    Modify,
+    Delete,
+}
+
+/// One row change applied to the replica
+#[derive(Debug, Clone)]
+pub struct RowChange {
+    pub table: String,
+    pub uuid: String,
+    pub kind: ChangeKind,
+}
+
+/// In-memory copy of the monitored tables
+#[derive(Debug, Clone, Default)]
+pub struct OvsdbReplica {
+    tables: HashMap<String, HashMap<String, OvsdbRow>>,
+    /// Column shapes from the schema, needed to apply update2 diffs
+    kinds: HashMap<String, HashMap<String, ColumnKind>>,
+}
+
+impl OvsdbReplica {
+    pub fn with_schema(schema: &OvsdbSchema) -> Self {
+        Self {
+            tables: HashMap::new(),
+            kinds: schema
+                .tables
+                .iter()
+                .map(|(table, def)| {
+                    let kinds = def
+                        .columns
+                        .iter()
+                        .map(|(column, def)| (column.clone(), def.kind()))
+                        .collect();
+                    (table.clone(), kinds)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn rows(&self, table: &str) -> impl Iterator<Item = (&String, &OvsdbRow)> {
+        self.tables
+            .get(table)
+            .into_iter()
+            .flat_map(|rows| rows.iter())
+    }
+
+    pub fn row(&self, table: &str, uuid: &str) -> Option<&OvsdbRow> {
+        self.tables.get(table)?.get(uuid)
+    }
+
+    /// First row whose `column` equals a string value (e.g. a bridge by name)
+    pub fn find(&self, table: &str, column: &str, value: &str) -> Option<(&String, &OvsdbRow)> {
+        self.rows(table)
+            .find(|(_, row)| row.get(column).and_then(|v| v.as_str()) == Some(value))
+    }
+
+    /// Apply a `<table-updates>` object (`monitor` / `update`)
+    pub fn apply_update(&mut self, updates: &Value) -> Vec<RowChange> {
+        let mut changes = Vec::new();
+        for (table, rows) in updates.as_object().into_iter().flatten() {
+            for (uuid, update) in rows.as_object().into_iter().flatten() {
+                let old = update.get("old").and_then(|o| o.as_object());
+                let new = update.get("new").and_then(|n| n.as_object());
+                let rows = self.tables.entry(table.clone()).or_default();
+                let kind = match (old, new) {
+                    (_, None) => {
+                        rows.remove(uuid);
+                        ChangeKind::Delete
+                    }
+                    (None, Some(new)) => {
+                        rows.insert(uuid.clone(), new.clone());
+                        ChangeKind::Insert
+                    }
+                    (Some(_), Some(new)) => {
+                        // `new` carries every monitored column of the row
+                        rows.insert(uuid.clone(), new.clone());
+                        ChangeKind::Modify
+                    }
+                };
+                changes.push(RowChange {
+                    table: table.clone(),
+                    uuid: uuid.clone(),
+                    kind,
+                });
+            }
+        }
+        changes
+    }
+
+    /// Apply a `<table-updates2>` object (`monitor_cond` / `update2`)
+    pub fn apply_update2(&mut self, updates: &Value) -> Vec<RowChange> {
+        let mut changes = Vec::new();
+        for (table, rows) in updates.as_object().into_iter().flatten() {
+            for (uuid, update) in rows.as_object().into_iter().flatten() {
+                let kinds = self.kinds.get(table);
+                let rows = self.tables.entry(table.clone()).or_default();
+                let kind = if let Some(row) = update
+                    .get("initial")
+                    .or_else(|| update.get("insert"))
+                    .and_then(|r| r.as_object())
+                {
+                    rows.insert(uuid.clone(), row.clone());
+                    ChangeKind::Insert
+                } else if let Some(diff) = update.get("modify").and_then(|m| m.as_object()) {
+                    let row = rows.entry(uuid.clone()).or_default();
+                    for (column, change) in diff {
+                        let column_kind = kinds.and_then(|k| k.get(column)).copied();
+                        let merged = match row.get(column) {
+                            Some(current) => apply_diff(current, change, column_kind),
+                            None => change.clone(),
+                        };
+                        row.insert(column.clone(), merged);
+                    }
+                    ChangeKind::Modify
+                } else if update.get("delete").is_some() {
+                    rows.remove(uuid);
+                    ChangeKind::Delete
+                } else {
+                    continue;
+                };
+                changes.push(RowChange {
+                    table: table.clone(),
+                    uuid: uuid.clone(),
+                    kind,
+                });
+            }
+        }
+        changes
+    }
+}
+
+/// Apply an update2 `modify` column diff to the current value.
+///
+/// Sets carry the symmetric difference; maps carry pairs to add (new key),
+/// remove (same key and value) or replace (same key, new value); scalars carry
+/// the new value. Without schema information the shape is guessed from the values.
+fn apply_diff(current: &Value, diff: &Value, column_kind: Option<ColumnKind>) -> Value {
+    let column_kind = column_kind.unwrap_or(match (kind(current), kind(diff)) {
+        (Some("map"), _) | (_, Some("map")) => ColumnKind::Map,
+        (Some("set"), _) | (_, Some("set")) => ColumnKind::Set,
+        _ => ColumnKind::Scalar,
+    });
+
+    match column_kind {
+        ColumnKind::Map => {
+            let mut pairs = elements(current);
+            for pair in elements(diff) {
+                let key = pair.get(0);
+                match pairs.iter().position(|p| p.get(0) == key) {
+                    Some(i) if pairs[i] == pair => {
+                        pairs.remove(i);
+                    }
+                    Some(i) => pairs[i] = pair,
+                    None => pairs.push(pair),
+                }
+            }
+            json!(["map", pairs])
+        }
+        ColumnKind::Set => {
+            let mut items = set_elements(current);
+            for item in set_elements(diff) {
+                match items.iter().position(|i| *i == item) {
+                    Some(i) => {
+                        items.remove(i);
+                    }
+                    None => items.push(item),
+                }
+            }
+            json!(["set", items])
+        }
+        ColumnKind::Scalar => diff.clone(),
+    }
+}
+
+/// "set"/"map" tag of a compound value
+fn kind(value: &Value) -> Option<&str> {
+    match value.as_array() {
+        Some(pair) if pair.len() == 2 && pair[1].is_array() => pair[0].as_str(),
+        _ => None,
+    }
+}
+
+fn elements(value: &Value) -> Vec<Value> {
+    value
+        .get(1)
+        .and_then(|e| e.as_array())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Set members; a lone atom is a one-element set
+fn set_elements(value: &Value) -> Vec<Value> {
+    match kind(value) {
+        Some("set") => elements(value),
+        _ => vec![value.clone()],
+    }
+}
+
+/// Live monitor session
+pub struct OvsdbMonitor {
+    replica: Arc<RwLock<OvsdbReplica>>,
+    changes: broadcast::Sender<RowChange>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl OvsdbMonitor {
+    /// Connect, install the monitor and load the initial contents
+    pub async fn start(
+        socket_path: &str,
+        database: &str,
+        requests: &[MonitorRequest],
+    ) -> Result<Self> {
+        let stream = UnixStream::connect(socket_path)
+            .await
+            .context("Failed to connect to OVSDB socket")?;
+        let (read_half, write_half) = stream.into_split();
+        let mut reader = BufReader::new(read_half);
+        let writer = Arc::new(Mutex::new(write_half));
+
+        let (changes, _) = broadcast::channel(256);
+
+        // Column shapes are needed to apply update2 diffs
+        let schema_id = json!("get_schema");
+        send(
+            &writer,
+            &json!({"method": "get_schema", "params": [database], "id": schema_id}),
+        )
+        .await?;
+        let placeholder = RwLock::new(OvsdbReplica::default());
+        let reply = read_reply(&mut reader, &writer, &schema_id, &placeholder, &changes).await?;
+        if let Some(error) = reply.get("error").filter(|e| !e.is_null()) {
+            bail!("OVSDB get_schema failed: {}", error);
+        }
+        let schema = OvsdbSchema::from_json(&reply["result"])?;
+        let replica = Arc::new(RwLock::new(OvsdbReplica::with_schema(&schema)));
+
+        // Prefer monitor_cond (update2 diffs); fall back for older servers
+        let mut conditional = true;
+        let initial = loop {
+            let method = if conditional {
+                "monitor_cond"
+            } else {
+                "monitor"
+            };
+            let monitor_requests: Map<String, Value> = requests
+                .iter()
+                .map(|r| (r.table.clone(), r.to_json(conditional)))
+                .collect();
+            let id = json!(method);
+            send(
+                &writer,
+                &json!({"method": method, "params": [database, "op-dbus", monitor_requests], "id": id}),
+            )
+            .await?;
+
+            let reply = read_reply(&mut reader, &writer, &id, &replica, &changes).await?;
+            match reply.get("error").filter(|e| !e.is_null()) {
+                None => break reply["result"].clone(),
+                Some(error) if conditional => {
+                    log::debug!(
+                        "monitor_cond unsupported ({}), falling back to monitor",
+                        error
+                    );
+                    conditional = false;
+                }
+                Some(error) => bail!("OVSDB monitor failed: {}", error),
+            }
+        };
+
+        {
+            let mut replica = replica.write().await;
+            if conditional {
+                replica.apply_update2(&initial);
+            } else {
+                replica.apply_update(&initial);
+            }
+        }
+
+        let task = {
+            let replica = Arc::clone(&replica);
+            let changes = changes.clone();
+            tokio::spawn(async move {
+                if let Err(e) = run(reader, writer, replica, changes).await {
+                    log::warn!("OVSDB monitor stopped: {}", e);
+                }
+            })
+        };
+
+        Ok(Self {
+            replica,
+            changes,
+            task,
+        })
+    }
+
+    /// Shared replica, kept current by the monitor task
+    pub fn replica(&self) -> Arc<RwLock<OvsdbReplica>> {
+        Arc::clone(&self.replica)
+    }
+
+    /// Stream of row changes applied after the initial load
+    pub fn changes(&self) -> broadcast::Receiver<RowChange> {
+        self.changes.subscribe()
+    }
+
+    /// Whether the monitor connection is still up
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+}
+
+impl Drop for OvsdbMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn send(writer: &Mutex<OwnedWriteHalf>, message: &Value) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    let mut writer = writer.lock().await;
+    writer.write_all(line.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read until the reply with `id`, handling notifications that arrive first
+async fn read_reply(
+    reader: &mut BufReader<tokio::net::unix::OwnedReadHalf>,
+    writer: &Mutex<OwnedWriteHalf>,
+    id: &Value,
+    replica: &RwLock<OvsdbReplica>,
+    changes: &broadcast::Sender<RowChange>,
+) -> Result<Value> {
+    loop {
+        let message = read_message(reader).await?;
+        if message.get("method").is_none() && message.get("id") == Some(id) {
+            return Ok(message);
+        }
+        handle_notification(&message, writer, replica, changes).await?;
+    }
+}
+
+async fn read_message(reader: &mut BufReader<tokio::net::unix::OwnedReadHalf>) -> Result<Value> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        bail!("OVSDB closed the monitor connection");
+    }
+    serde_json::from_str(&line).context("Invalid JSON from OVSDB")
+}
+
+async fn run(
+    mut reader: BufReader<tokio::net::unix::OwnedReadHalf>,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    replica: Arc<RwLock<OvsdbReplica>>,
+    changes: broadcast::Sender<RowChange>,
+) -> Result<()> {
+    loop {
+        let message = read_message(&mut reader).await?;
+        handle_notification(&message, &writer, &replica, &changes).await?;
+    }
+}
+
+async fn handle_notification(
+    message: &Value,
+    writer: &Mutex<OwnedWriteHalf>,
+    replica: &RwLock<OvsdbReplica>,
+    changes: &broadcast::Sender<RowChange>,
+) -> Result<()> {
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+    let applied = match message.get("method").and_then(|m| m.as_str()) {
+        // ovsdb-server probes idle connections and drops them without a reply
+        Some("echo") => {
+            send(
+                writer,
+                &json!({"result": params, "error": null, "id": message["id"]}),
+            )
+            .await?;
+            return Ok(());
+        }
+        Some("update") => replica.write().await.apply_update(
+            params
+                .get(1)
+                .ok_or_else(|| anyhow!("update without table-updates"))?,
+        ),
+        Some("update2") => replica.write().await.apply_update2(
+            params
+                .get(1)
+                .ok_or_else(|| anyhow!("update2 without table-updates2"))?,
+        ),
+        _ => return Ok(()),
+    };
+
+    for change in applied {
+        let _ = changes.send(change);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_old_new() {
+        let mut replica = OvsdbReplica::default();
+        let changes = replica.apply_update(&json!({
+            "Bridge": {"u1": {"new": {"name": "br0", "ports": ["set", []]}}}
+        }));
+        assert_eq!(changes[0].kind, ChangeKind::Insert);
+        assert_eq!(replica.find("Bridge", "name", "br0").unwrap().0, "u1");
+
+        replica.apply_update(&json!({
+            "Bridge": {"u1": {"old": {"ports": ["set", []]}, "new": {"name": "br0", "ports": ["uuid", "p1"]}}}
+        }));
+        assert_eq!(
+            replica.row("Bridge", "u1").unwrap()["ports"],
+            json!(["uuid", "p1"])
+        );
+
+        let changes = replica.apply_update(&json!({"Bridge": {"u1": {"old": {"name": "br0"}}}}));
+        assert_eq!(changes[0].kind, ChangeKind::Delete);
+        assert!(replica.row("Bridge", "u1").is_none());
+    }
+
+    #[test]
+    fn test_update2_modify_diffs() {
+        let schema = OvsdbSchema::from_json(&json!({
+            "name": "Open_vSwitch",
+            "tables": {"Bridge": {"columns": {
+                "name": {"type": "string"},
+                "ports": {"type": {"key": {"type": "uuid"}, "min": 0, "max": "unlimited"}},
+                "external_ids": {"type": {"key": "string", "value": "string", "min": 0, "max": "unlimited"}},
+                "stp_enable": {"type": "boolean"}
+            }}}
+        }))
+        .unwrap();
+        let mut replica = OvsdbReplica::with_schema(&schema);
+        replica.apply_update2(&json!({
+            "Bridge": {"u1": {"initial": {
+                "name": "br0",
+                "ports": ["set", [["uuid", "p1"], ["uuid", "p2"]]],
+                "external_ids": ["map", [["a", "1"], ["b", "2"]]],
+                "stp_enable": false
+            }}}
+        }));
+
+        let changes = replica.apply_update2(&json!({
+            "Bridge": {"u1": {"modify": {
+                // p2 removed, p3 added
+                "ports": ["set", [["uuid", "p2"], ["uuid", "p3"]]],
+                // a removed (same value), b replaced, c added
+                "external_ids": ["map", [["a", "1"], ["b", "3"], ["c", "4"]]],
+                "stp_enable": true
+            }}}
+        }));
+        assert_eq!(changes[0].kind, ChangeKind::Modify);
+
+        let row = replica.row("Bridge", "u1").unwrap();
+        assert_eq!(
+            row["ports"],
+            json!(["set", [["uuid", "p1"], ["uuid", "p3"]]])
+        );
+        assert_eq!(
+            row["external_ids"],
+            json!(["map", [["b", "3"], ["c", "4"]]])
+        );
+        assert_eq!(row["stp_enable"], json!(true));
+        assert_eq!(row["name"], "br0");
+
+        // A single-element set is sent as a bare atom
+        replica.apply_update2(&json!({"Bridge": {"u1": {"modify": {"ports": ["uuid", "p1"]}}}}));
+        assert_eq!(
+            replica.row("Bridge", "u1").unwrap()["ports"],
+            json!(["set", [["uuid", "p3"]]])
+        );
+
+        replica.apply_update2(&json!({"Bridge": {"u1": {"delete": null}}}));
+        assert!(replica.row("Bridge", "u1").is_none());
+    }
+}
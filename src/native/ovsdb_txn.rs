@@ -0,0 +1,1008 @@
+//! Typed OVSDB transactions (RFC 7047 section 5.2)
+//!
+//! [`Transaction`] builds the operation list for a `transact` call instead of
+//! hand-written JSON: insert/update/mutate/delete/select plus wait and comment,
+//! with named-uuid references between inserts. Transactions are checked against
+//! the database schema before they are sent, and a failed transaction reports
+//! every operation's result through [`TransactionError`].
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// A single OVSDB value (`<atom>`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    String(String),
+    Integer(i64),
+    Real(f64),
+    Boolean(bool),
+    /// Existing row, by UUID
+    Uuid(String),
+    /// Row inserted earlier in the same transaction
+    NamedUuid(String),
+}
+
+impl Atom {
+    pub fn to_json(&self) -> Value {
+        match self {
+            Atom::String(s) => json!(s),
+            Atom::Integer(i) => json!(i),
+            Atom::Real(r) => json!(r),
+            Atom::Boolean(b) => json!(b),
+            Atom::Uuid(u) => json!(["uuid", u]),
+            Atom::NamedUuid(n) => json!(["named-uuid", n]),
+        }
+    }
+
+    /// Parse an atom from its wire form
+    pub fn from_json(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(s) => Some(Atom::String(s.clone())),
+            Value::Bool(b) => Some(Atom::Boolean(*b)),
+            Value::Number(n) => n
+                .as_i64()
+                .map(Atom::Integer)
+                .or_else(|| n.as_f64().map(Atom::Real)),
+            Value::Array(pair) if pair.len() == 2 => match (pair[0].as_str(), pair[1].as_str()) {
+                (Some("uuid"), Some(u)) => Some(Atom::Uuid(u.to_string())),
+                (Some("named-uuid"), Some(n)) => Some(Atom::NamedUuid(n.to_string())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Atom {
+    fn from(value: &str) -> Self {
+        Atom::String(value.to_string())
+    }
+}
+
+impl From<String> for Atom {
+    fn from(value: String) -> Self {
+        Atom::String(value)
+    }
+}
+
+impl From<i64> for Atom {
+    fn from(value: i64) -> Self {
+        Atom::Integer(value)
+    }
+}
+
+impl From<bool> for Atom {
+    fn from(value: bool) -> Self {
+        Atom::Boolean(value)
+    }
+}
+
+impl From<&NamedUuid> for Atom {
+    fn from(value: &NamedUuid) -> Self {
+        Atom::NamedUuid(value.0.clone())
+    }
+}
+
+/// A column value (`<value>`): atom, set or map
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    Atom(Atom),
+    Set(Vec<Atom>),
+    Map(Vec<(Atom, Atom)>),
+}
+
+impl Datum {
+    pub fn set<I, A>(atoms: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<Atom>,
+    {
+        Datum::Set(atoms.into_iter().map(Into::into).collect())
+    }
+
+    pub fn map<I, K, V>(pairs: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<Atom>,
+        V: Into<Atom>,
+    {
+        Datum::Map(
+            pairs
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        )
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            Datum::Atom(atom) => atom.to_json(),
+            Datum::Set(atoms) => {
+                json!(["set", atoms.iter().map(Atom::to_json).collect::<Vec<_>>()])
+            }
+            Datum::Map(pairs) => json!([
+                "map",
+                pairs
+                    .iter()
+                    .map(|(k, v)| json!([k.to_json(), v.to_json()]))
+                    .collect::<Vec<_>>()
+            ]),
+        }
+    }
+
+    fn named_uuids(&self) -> Vec<&str> {
+        let atoms: Vec<&Atom> = match self {
+            Datum::Atom(atom) => vec![atom],
+            Datum::Set(atoms) => atoms.iter().collect(),
+            Datum::Map(pairs) => pairs.iter().flat_map(|(k, v)| [k, v]).collect(),
+        };
+        atoms
+            .into_iter()
+            .filter_map(|atom| match atom {
+                Atom::NamedUuid(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl<T: Into<Atom>> From<T> for Datum {
+    fn from(value: T) -> Self {
+        Datum::Atom(value.into())
+    }
+}
+
+/// Handle for a row inserted in the same transaction
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NamedUuid(String);
+
+impl NamedUuid {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Column/value pairs for insert and update
+#[derive(Debug, Clone, Default)]
+pub struct Row(Vec<(String, Datum)>);
+
+impl Row {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(mut self, column: &str, value: impl Into<Datum>) -> Self {
+        self.0.push((column.to_string(), value.into()));
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Object(
+            self.0
+                .iter()
+                .map(|(column, datum)| (column.clone(), datum.to_json()))
+                .collect(),
+        )
+    }
+}
+
+/// `<condition>`: column, function and value
+#[derive(Debug, Clone)]
+pub struct Condition {
+    column: String,
+    function: &'static str,
+    value: Datum,
+}
+
+impl Condition {
+    pub fn eq(column: &str, value: impl Into<Datum>) -> Self {
+        Self::new(column, "==", value)
+    }
+
+    pub fn ne(column: &str, value: impl Into<Datum>) -> Self {
+        Self::new(column, "!=", value)
+    }
+
+    pub fn includes(column: &str, value: impl Into<Datum>) -> Self {
+        Self::new(column, "includes", value)
+    }
+
+    pub fn excludes(column: &str, value: impl Into<Datum>) -> Self {
+        Self::new(column, "excludes", value)
+    }
+
+    /// Match a row by UUID
+    pub fn uuid(uuid: &str) -> Self {
+        Self::eq("_uuid", Atom::Uuid(uuid.to_string()))
+    }
+
+    fn new(column: &str, function: &'static str, value: impl Into<Datum>) -> Self {
+        Self {
+            column: column.to_string(),
+            function,
+            value: value.into(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!([self.column, self.function, self.value.to_json()])
+    }
+}
+
+/// `<mutation>`: column, mutator and value
+#[derive(Debug, Clone)]
+pub struct Mutation {
+    column: String,
+    mutator: &'static str,
+    value: Datum,
+}
+
+impl Mutation {
+    /// Add elements to a set or pairs to a map
+    pub fn insert(column: &str, value: impl Into<Datum>) -> Self {
+        Self::new(column, "insert", value)
+    }
+
+    /// Remove elements from a set, or keys/pairs from a map
+    pub fn delete(column: &str, value: impl Into<Datum>) -> Self {
+        Self::new(column, "delete", value)
+    }
+
+    /// Arithmetic mutators: "+=", "-=", "*=", "/=", "%="
+    pub fn arithmetic(column: &str, mutator: &'static str, value: impl Into<Datum>) -> Self {
+        Self::new(column, mutator, value)
+    }
+
+    fn new(column: &str, mutator: &'static str, value: impl Into<Datum>) -> Self {
+        Self {
+            column: column.to_string(),
+            mutator,
+            value: value.into(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!([self.column, self.mutator, self.value.to_json()])
+    }
+}
+
+/// `wait` operation condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitUntil {
+    Equal,
+    NotEqual,
+}
+
+#[derive(Debug, Clone)]
+enum Operation {
+    Insert {
+        table: String,
+        row: Row,
+        uuid_name: NamedUuid,
+    },
+    Select {
+        table: String,
+        conditions: Vec<Condition>,
+        columns: Option<Vec<String>>,
+    },
+    Update {
+        table: String,
+        conditions: Vec<Condition>,
+        row: Row,
+    },
+    Mutate {
+        table: String,
+        conditions: Vec<Condition>,
+        mutations: Vec<Mutation>,
+    },
+    Delete {
+        table: String,
+        conditions: Vec<Condition>,
+    },
+    Wait {
+        table: String,
+        conditions: Vec<Condition>,
+        columns: Vec<String>,
+        until: WaitUntil,
+        rows: Vec<Row>,
+        timeout_ms: Option<u64>,
+    },
+    Comment(String),
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Insert { .. } => "insert",
+            Operation::Select { .. } => "select",
+            Operation::Update { .. } => "update",
+            Operation::Mutate { .. } => "mutate",
+            Operation::Delete { .. } => "delete",
+            Operation::Wait { .. } => "wait",
+            Operation::Comment(_) => "comment",
+        }
+    }
+
+    fn table(&self) -> Option<&str> {
+        match self {
+            Operation::Insert { table, .. }
+            | Operation::Select { table, .. }
+            | Operation::Update { table, .. }
+            | Operation::Mutate { table, .. }
+            | Operation::Delete { table, .. }
+            | Operation::Wait { table, .. } => Some(table),
+            Operation::Comment(_) => None,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let conditions = |conditions: &[Condition]| {
+            Value::Array(conditions.iter().map(Condition::to_json).collect())
+        };
+        match self {
+            Operation::Insert {
+                table,
+                row,
+                uuid_name,
+            } => json!({
+                "op": "insert",
+                "table": table,
+                "row": row.to_json(),
+                "uuid-name": uuid_name.0,
+            }),
+            Operation::Select {
+                table,
+                conditions: where_,
+                columns,
+            } => {
+                let mut op = json!({"op": "select", "table": table, "where": conditions(where_)});
+                if let Some(columns) = columns {
+                    op["columns"] = json!(columns);
+                }
+                op
+            }
+            Operation::Update {
+                table,
+                conditions: where_,
+                row,
+            } => json!({
+                "op": "update",
+                "table": table,
+                "where": conditions(where_),
+                "row": row.to_json(),
+            }),
+            Operation::Mutate {
+                table,
+                conditions: where_,
+                mutations,
+            } => json!({
+                "op": "mutate",
+                "table": table,
+                "where": conditions(where_),
+                "mutations": mutations.iter().map(Mutation::to_json).collect::<Vec<_>>(),
+            }),
+            Operation::Delete {
+                table,
+                conditions: where_,
+            } => json!({"op": "delete", "table": table, "where": conditions(where_)}),
+            Operation::Wait {
+                table,
+                conditions: where_,
+                columns,
+                until,
+                rows,
+                timeout_ms,
+            } => {
+                let mut op = json!({
+                    "op": "wait",
+                    "table": table,
+                    "where": conditions(where_),
+                    "columns": columns,
+                    "until": match until {
+                        WaitUntil::Equal => "==",
+                        WaitUntil::NotEqual => "!=",
+                    },
+                    "rows": rows.iter().map(Row::to_json).collect::<Vec<_>>(),
+                });
+                if let Some(timeout) = timeout_ms {
+                    op["timeout"] = json!(timeout);
+                }
+                op
+            }
+            Operation::Comment(comment) => json!({"op": "comment", "comment": comment}),
+        }
+    }
+}
+
+/// Builder for a `transact` call
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    database: String,
+    operations: Vec<Operation>,
+}
+
+impl Transaction {
+    pub fn new(database: &str) -> Self {
+        Self {
+            database: database.to_string(),
+            operations: Vec::new(),
+        }
+    }
+
+    /// Insert a row; the returned handle references it from later operations
+    pub fn insert(&mut self, table: &str, row: Row) -> NamedUuid {
+        let uuid_name = NamedUuid(format!("row{}", self.operations.len()));
+        self.operations.push(Operation::Insert {
+            table: table.to_string(),
+            row,
+            uuid_name: uuid_name.clone(),
+        });
+        uuid_name
+    }
+
+    pub fn select(
+        &mut self,
+        table: &str,
+        conditions: Vec<Condition>,
+        columns: Option<&[&str]>,
+    ) -> &mut Self {
+        self.operations.push(Operation::Select {
+            table: table.to_string(),
+            conditions,
+            columns: columns.map(|c| c.iter().map(|s| s.to_string()).collect()),
+        });
+        self
+    }
+
+    pub fn update(&mut self, table: &str, conditions: Vec<Condition>, row: Row) -> &mut Self {
+        self.operations.push(Operation::Update {
+            table: table.to_string(),
+            conditions,
+            row,
+        });
+        self
+    }
+
+    pub fn mutate(
+        &mut self,
+        table: &str,
+        conditions: Vec<Condition>,
+        mutations: Vec<Mutation>,
+    ) -> &mut Self {
+        self.operations.push(Operation::Mutate {
+            table: table.to_string(),
+            conditions,
+            mutations,
+        });
+        self
+    }
+
+    pub fn delete(&mut self, table: &str, conditions: Vec<Condition>) -> &mut Self {
+        self.operations.push(Operation::Delete {
+            table: table.to_string(),
+            conditions,
+        });
+        self
+    }
+
+    /// Abort unless the selected rows' `columns` compare `until` against `rows`
+    pub fn wait(
+        &mut self,
+        table: &str,
+        conditions: Vec<Condition>,
+        columns: &[&str],
+        until: WaitUntil,
+        rows: Vec<Row>,
+        timeout_ms: Option<u64>,
+    ) -> &mut Self {
+        self.operations.push(Operation::Wait {
+            table: table.to_string(),
+            conditions,
+            columns: columns.iter().map(|s| s.to_string()).collect(),
+            until,
+            rows,
+            timeout_ms,
+        });
+        self
+    }
+
+    /// Record a comment in the database log
+    pub fn comment(&mut self, comment: &str) -> &mut Self {
+        self.operations
+            .push(Operation::Comment(comment.to_string()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    /// `transact` params: database name followed by the operations
+    pub fn to_params(&self) -> Value {
+        let mut params = vec![json!(self.database)];
+        params.extend(self.operations.iter().map(Operation::to_json));
+        Value::Array(params)
+    }
+
+    /// Check tables, columns, mutability and named-uuid references against the schema
+    pub fn validate(&self, schema: &OvsdbSchema) -> Result<()> {
+        if schema.name != self.database {
+            bail!(
+                "Transaction targets database '{}' but schema is for '{}'",
+                self.database,
+                schema.name
+            );
+        }
+
+        let declared: BTreeSet<&str> = self
+            .operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Insert { uuid_name, .. } => Some(uuid_name.name()),
+                _ => None,
+            })
+            .collect();
+
+        for (index, op) in self.operations.iter().enumerate() {
+            let Some(table_name) = op.table() else {
+                continue;
+            };
+            let context = |msg: String| anyhow!("operation {} ({}): {}", index, op.name(), msg);
+            let table = schema
+                .tables
+                .get(table_name)
+                .ok_or_else(|| context(format!("unknown table '{}'", table_name)))?;
+
+            let check_column = |column: &str, writing: bool| -> Result<()> {
+                if matches!(column, "_uuid" | "_version") {
+                    if writing {
+                        return Err(context(format!("column '{}' is read-only", column)));
+                    }
+                    return Ok(());
+                }
+                let schema_column = table.columns.get(column).ok_or_else(|| {
+                    context(format!("unknown column '{}.{}'", table_name, column))
+                })?;
+                if writing && !schema_column.mutable && !matches!(op, Operation::Insert { .. }) {
+                    return Err(context(format!(
+                        "column '{}.{}' is immutable",
+                        table_name, column
+                    )));
+                }
+                Ok(())
+            };
+            let check_refs = |datum: &Datum| -> Result<()> {
+                for name in datum.named_uuids() {
+                    if !declared.contains(name) {
+                        return Err(context(format!("undeclared named-uuid '{}'", name)));
+                    }
+                }
+                Ok(())
+            };
+
+            match op {
+                Operation::Insert { row, .. } | Operation::Update { row, .. } => {
+                    for (column, datum) in &row.0 {
+                        check_column(column, true)?;
+                        check_refs(datum)?;
+                    }
+                }
+                Operation::Mutate { mutations, .. } => {
+                    for mutation in mutations {
+                        check_column(&mutation.column, true)?;
+                        check_refs(&mutation.value)?;
+                    }
+                }
+                Operation::Select {
+                    columns: Some(columns),
+                    ..
+                } => {
+                    for column in columns {
+                        check_column(column, false)?;
+                    }
+                }
+                Operation::Wait { columns, rows, .. } => {
+                    for column in columns {
+                        check_column(column, false)?;
+                    }
+                    for (column, _) in rows.iter().flat_map(|r| r.0.iter()) {
+                        check_column(column, false)?;
+                    }
+                }
+                _ => {}
+            }
+
+            let conditions = match op {
+                Operation::Select { conditions, .. }
+                | Operation::Update { conditions, .. }
+                | Operation::Mutate { conditions, .. }
+                | Operation::Delete { conditions, .. }
+                | Operation::Wait { conditions, .. } => conditions.as_slice(),
+                _ => &[],
+            };
+            for condition in conditions {
+                check_column(&condition.column, false)?;
+                check_refs(&condition.value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Interpret a `transact` reply, failing with every operation's result on error
+    pub fn parse_result(&self, result: &Value) -> Result<TransactionResult> {
+        let replies = result
+            .as_array()
+            .ok_or_else(|| anyhow!("Invalid OVSDB transact reply: {}", result))?;
+
+        let results: Vec<OperationResult> =
+            replies.iter().map(OperationResult::from_json).collect();
+        let failed = results.iter().position(|r| r.error.is_some());
+
+        if let Some(index) = failed {
+            return Err(TransactionError {
+                // An extra trailing error means the commit itself failed
+                failed_operation: (index < self.operations.len()).then_some(index),
+                operations: self
+                    .operations
+                    .iter()
+                    .map(|op| match op.table() {
+                        Some(table) => format!("{} {}", op.name(), table),
+                        None => op.name().to_string(),
+                    })
+                    .collect(),
+                results,
+            }
+            .into());
+        }
+
+        let mut named_uuids = HashMap::new();
+        for (op, reply) in self.operations.iter().zip(&results) {
+            if let (Operation::Insert { uuid_name, .. }, Some(uuid)) = (op, &reply.uuid) {
+                named_uuids.insert(uuid_name.clone(), uuid.clone());
+            }
+        }
+
+        Ok(TransactionResult {
+            results,
+            named_uuids,
+        })
+    }
+}
+
+/// Result of one operation in a `transact` reply
+#[derive(Debug, Clone, Default)]
+pub struct OperationResult {
+    /// UUID of an inserted row
+    pub uuid: Option<String>,
+    /// Rows returned by select
+    pub rows: Option<Vec<Value>>,
+    /// Rows matched by update/mutate/delete
+    pub count: Option<u64>,
+    pub error: Option<String>,
+    pub details: Option<String>,
+}
+
+impl OperationResult {
+    fn from_json(value: &Value) -> Self {
+        Self {
+            uuid: value
+                .get("uuid")
+                .and_then(Atom::from_json)
+                .and_then(|atom| match atom {
+                    Atom::Uuid(uuid) => Some(uuid),
+                    _ => None,
+                }),
+            rows: value.get("rows").and_then(|r| r.as_array()).cloned(),
+            count: value.get("count").and_then(|c| c.as_u64()),
+            error: value
+                .get("error")
+                .and_then(|e| e.as_str())
+                .map(str::to_string),
+            details: value
+                .get("details")
+                .and_then(|d| d.as_str())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// Successful transaction
+#[derive(Debug, Clone)]
+pub struct TransactionResult {
+    pub results: Vec<OperationResult>,
+    named_uuids: HashMap<NamedUuid, String>,
+}
+
+impl TransactionResult {
+    /// Real UUID assigned to a row inserted by this transaction
+    pub fn uuid(&self, named: &NamedUuid) -> Option<&str> {
+        self.named_uuids.get(named).map(String::as_str)
+    }
+}
+
+/// Failed transaction with each operation's result
+#[derive(Debug, Clone)]
+pub struct TransactionError {
+    /// Index of the operation that failed; None when the commit itself failed
+    pub failed_operation: Option<usize>,
+    /// "op table" per operation, for reporting
+    pub operations: Vec<String>,
+    pub results: Vec<OperationResult>,
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = self
+            .failed_operation
+            .map_or_else(|| self.results.last(), |i| self.results.get(i));
+        let (error, details) = failed.map_or(("unknown error", None), |r| {
+            (
+                r.error.as_deref().unwrap_or("unknown error"),
+                r.details.as_deref(),
+            )
+        });
+
+        match self.failed_operation {
+            Some(index) => write!(
+                f,
+                "OVSDB transaction failed at operation {} ({}): {}",
+                index, self.operations[index], error
+            )?,
+            None => write!(f, "OVSDB transaction failed to commit: {}", error)?,
+        }
+        if let Some(details) = details {
+            write!(f, " ({})", details)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+/// Column definition from `get_schema`
+#[derive(Debug, Clone)]
+pub struct ColumnSchema {
+    pub mutable: bool,
+    /// Raw `<type>`
+    pub column_type: Value,
+}
+
+/// Shape of a column's values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Scalar,
+    Set,
+    Map,
+}
+
+impl ColumnSchema {
+    pub fn kind(&self) -> ColumnKind {
+        let Some(column_type) = self.column_type.as_object() else {
+            // Bare atomic type name
+            return ColumnKind::Scalar;
+        };
+        if column_type.contains_key("value") {
+            return ColumnKind::Map;
+        }
+        let min = column_type.get("min").and_then(|m| m.as_u64()).unwrap_or(1);
+        let max = column_type.get("max").map_or(Some(1), |m| m.as_u64());
+        if min == 1 && max == Some(1) {
+            ColumnKind::Scalar
+        } else {
+            ColumnKind::Set
+        }
+    }
+}
+
+/// Table definition from `get_schema`
+#[derive(Debug, Clone)]
+pub struct TableSchema {
+    pub columns: HashMap<String, ColumnSchema>,
+    pub is_root: bool,
+}
+
+/// Database schema (`<database-schema>`)
+#[derive(Debug, Clone)]
+pub struct OvsdbSchema {
+    pub name: String,
+    pub version: String,
+    pub tables: HashMap<String, TableSchema>,
+}
+
+impl OvsdbSchema {
+    pub fn from_json(schema: &Value) -> Result<Self> {
+        let tables = schema
+            .get("tables")
+            .and_then(|t| t.as_object())
+            .ok_or_else(|| anyhow!("Invalid OVSDB schema: missing tables"))?;
+
+        let parse_table = |table: &Map<String, Value>| TableSchema {
+            columns: table
+                .get("columns")
+                .and_then(|c| c.as_object())
+                .map(|columns| {
+                    columns
+                        .iter()
+                        .map(|(name, column)| {
+                            (
+                                name.clone(),
+                                ColumnSchema {
+                                    mutable: column
+                                        .get("mutable")
+                                        .and_then(|m| m.as_bool())
+                                        .unwrap_or(true),
+                                    column_type: column.get("type").cloned().unwrap_or(Value::Null),
+                                },
+                            )
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            is_root: table
+                .get("isRoot")
+                .and_then(|r| r.as_bool())
+                .unwrap_or(false),
+        };
+
+        Ok(Self {
+            name: schema
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            version: schema
+                .get("version")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            tables: tables
+                .iter()
+                .filter_map(|(name, table)| Some((name.clone(), parse_table(table.as_object()?))))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> OvsdbSchema {
+        OvsdbSchema::from_json(&json!({
+            "name": "Open_vSwitch",
+            "version": "8.3.0",
+            "tables": {
+                "Open_vSwitch": {"columns": {"bridges": {"type": {}}}, "isRoot": true},
+                "Bridge": {"columns": {
+                    "name": {"type": "string", "mutable": false},
+                    "ports": {"type": {}},
+                    "external_ids": {"type": {}}
+                }},
+                "Port": {"columns": {"name": {"type": "string", "mutable": false}, "interfaces": {"type": {}}}},
+                "Interface": {"columns": {"name": {"type": "string", "mutable": false}, "type": {"type": "string"}}}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_builder_renders_named_uuids() {
+        let mut txn = Transaction::new("Open_vSwitch");
+        let iface = txn.insert(
+            "Interface",
+            Row::new().set("name", "br0").set("type", "internal"),
+        );
+        let port = txn.insert(
+            "Port",
+            Row::new()
+                .set("name", "br0")
+                .set("interfaces", Datum::set([&iface])),
+        );
+        txn.mutate(
+            "Open_vSwitch",
+            vec![],
+            vec![Mutation::insert("bridges", Datum::set([&port]))],
+        )
+        .comment("op-dbus: add br0");
+
+        let params = txn.to_params();
+        assert_eq!(params[0], "Open_vSwitch");
+        assert_eq!(params[1]["uuid-name"], "row0");
+        assert_eq!(
+            params[2]["row"]["interfaces"],
+            json!(["set", [["named-uuid", "row0"]]])
+        );
+        assert_eq!(
+            params[3]["mutations"][0],
+            json!(["bridges", "insert", ["set", [["named-uuid", "row1"]]]])
+        );
+        assert_eq!(
+            params[4],
+            json!({"op": "comment", "comment": "op-dbus: add br0"})
+        );
+        assert!(txn.validate(&schema()).is_ok());
+    }
+
+    #[test]
+    fn test_validate_against_schema() {
+        let mut txn = Transaction::new("Open_vSwitch");
+        txn.update(
+            "Bridge",
+            vec![Condition::eq("name", "br0")],
+            Row::new().set("name", "br1"),
+        );
+        let err = txn.validate(&schema()).unwrap_err().to_string();
+        assert!(err.contains("immutable"), "{}", err);
+
+        let mut txn = Transaction::new("Open_vSwitch");
+        txn.delete("Brdige", vec![]);
+        assert!(txn
+            .validate(&schema())
+            .unwrap_err()
+            .to_string()
+            .contains("unknown table"));
+
+        let mut txn = Transaction::new("Open_vSwitch");
+        txn.select("Bridge", vec![Condition::eq("nmae", "br0")], None);
+        assert!(txn
+            .validate(&schema())
+            .unwrap_err()
+            .to_string()
+            .contains("unknown column"));
+
+        let mut txn = Transaction::new("Open_vSwitch");
+        txn.mutate(
+            "Bridge",
+            vec![Condition::uuid("5f1a")],
+            vec![Mutation::insert("ports", Atom::NamedUuid("missing".into()))],
+        );
+        assert!(txn
+            .validate(&schema())
+            .unwrap_err()
+            .to_string()
+            .contains("undeclared"));
+    }
+
+    #[test]
+    fn test_parse_result_reports_failed_operation() {
+        let mut txn = Transaction::new("Open_vSwitch");
+        let port = txn.insert("Port", Row::new().set("name", "p1"));
+        txn.mutate(
+            "Bridge",
+            vec![Condition::eq("name", "br0")],
+            vec![Mutation::insert("ports", Datum::set([&port]))],
+        );
+
+        let ok = txn
+            .parse_result(&json!([{"uuid": ["uuid", "1234"]}, {"count": 1}]))
+            .unwrap();
+        assert_eq!(ok.uuid(&port), Some("1234"));
+        assert_eq!(ok.results[1].count, Some(1));
+
+        let err = txn
+            .parse_result(&json!([
+                {"uuid": ["uuid", "1234"]},
+                {"error": "constraint violation", "details": "no bridge br0"}
+            ]))
+            .unwrap_err();
+        let txn_err = err.downcast_ref::<TransactionError>().unwrap();
+        assert_eq!(txn_err.failed_operation, Some(1));
+        assert_eq!(txn_err.results.len(), 2);
+        assert_eq!(
+            err.to_string(),
+            "OVSDB transaction failed at operation 1 (mutate Bridge): constraint violation (no bridge br0)"
+        );
+
+        let err = txn
+            .parse_result(&json!([{"uuid": ["uuid", "1"]}, {"count": 1}, {"error": "referential integrity violation"}]))
+            .unwrap_err();
+        assert!(err.to_string().contains("failed to commit"));
+    }
+}
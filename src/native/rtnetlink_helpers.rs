@@ -1,9 +1,17 @@
 //! Rtnetlink helpers - native netlink operations for IP addresses and routes
 
+use super::rtnetlink_routes::RTPROT_OPDBUS;
 use anyhow::{Context, Result};
 use futures::TryStreamExt;
+use netlink_packet_utils::nla::{DefaultNla, Nla};
 use rtnetlink::{new_connection, IpVersion};
-use std::net::Ipv4Addr;
+use rtnl_packet::address::Nla as AddressNla;
+use rtnl_packet::route::Nla as RouteNla;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Address origin attribute (Linux 6.1+); older kernels ignore it, so no
+/// address reads back as op-dbus owned there
+const IFA_PROTO: u16 = 11;
 
 /// Add IPv4 address to interface
 pub async fn add_ipv4_address(ifname: &str, ip: &str, prefix: u8) -> Result<()> {
@@ -67,6 +75,162 @@ pub async fn del_ipv4_address(ifname: &str, ip: &str, prefix: u8) -> Result<()>
     Ok(())
 }
 
+/// List addresses (IPv4 and IPv6) configured on an interface as (address, prefix)
+pub async fn list_addresses(ifname: &str) -> Result<Vec<(IpAddr, u8)>> {
+    let (connection, handle, _) = new_connection()?;
+    tokio::spawn(connection);
+
+    // Find interface by name
+    let mut links = handle.link().get().match_name(ifname.to_string()).execute();
+    let link = links
+        .try_next()
+        .await?
+        .context(format!("Interface '{}' not found", ifname))?;
+
+    let mut addresses = handle
+        .address()
+        .get()
+        .set_link_index_filter(link.header.index)
+        .execute();
+
+    let mut result = Vec::new();
+    while let Some(msg) = addresses.try_next().await? {
+        let addr = msg.nlas.iter().find_map(|nla| match nla {
+            AddressNla::Address(bytes) => match bytes.len() {
+                4 => <[u8; 4]>::try_from(bytes.as_slice()).ok().map(IpAddr::from),
+                16 => <[u8; 16]>::try_from(bytes.as_slice())
+                    .ok()
+                    .map(IpAddr::from),
+                _ => None,
+            },
+            _ => None,
+        });
+        if let Some(addr) = addr {
+            result.push((addr, msg.header.prefix_len));
+        }
+    }
+
+    Ok(result)
+}
+
+/// Add IPv6 address to interface, tagged as op-dbus owned
+pub async fn add_ipv6_address(ifname: &str, ip: &str, prefix: u8) -> Result<()> {
+    let (connection, handle, _) = new_connection()?;
+    tokio::spawn(connection);
+
+    let ifindex = link_index(&handle, ifname).await?;
+    let addr: Ipv6Addr = ip.parse().context("Invalid IPv6 address")?;
+
+    let mut request = handle.address().add(ifindex, addr.into(), prefix);
+    request
+        .message_mut()
+        .nlas
+        .push(AddressNla::Other(DefaultNla::new(
+            IFA_PROTO,
+            vec![RTPROT_OPDBUS],
+        )));
+    request
+        .execute()
+        .await
+        .context("Failed to add IPv6 address")?;
+
+    Ok(())
+}
+
+/// Delete IPv6 address from interface
+pub async fn del_ipv6_address(ifname: &str, ip: &str, prefix: u8) -> Result<()> {
+    let (connection, handle, _) = new_connection()?;
+    tokio::spawn(connection);
+
+    let ifindex = link_index(&handle, ifname).await?;
+    let addr: Ipv6Addr = ip.parse().context("Invalid IPv6 address")?;
+
+    let mut addresses = handle
+        .address()
+        .get()
+        .set_link_index_filter(ifindex)
+        .set_prefix_length_filter(prefix)
+        .set_address_filter(IpAddr::V6(addr))
+        .execute();
+
+    if let Some(addr_msg) = addresses.try_next().await? {
+        handle.address().del(addr_msg).execute().await?;
+    }
+
+    Ok(())
+}
+
+/// List statically configured global IPv6 addresses on an interface.
+/// SLAAC/DHCPv6 (non-permanent) and link-local addresses are excluded.
+pub async fn list_static_ipv6_addresses(ifname: &str) -> Result<Vec<(Ipv6Addr, u8)>> {
+    ipv6_addresses(ifname, false).await
+}
+
+/// List the global IPv6 addresses op-dbus added to an interface
+pub async fn list_owned_ipv6_addresses(ifname: &str) -> Result<Vec<(Ipv6Addr, u8)>> {
+    ipv6_addresses(ifname, true).await
+}
+
+fn address_protocol(msg: &rtnl_packet::AddressMessage) -> Option<u8> {
+    msg.nlas.iter().find_map(|nla| match nla {
+        AddressNla::Other(attr) if attr.kind() == IFA_PROTO && attr.value_len() == 1 => {
+            let mut value = [0u8; 1];
+            attr.emit_value(&mut value);
+            Some(value[0])
+        }
+        _ => None,
+    })
+}
+
+async fn ipv6_addresses(ifname: &str, owned_only: bool) -> Result<Vec<(Ipv6Addr, u8)>> {
+    let (connection, handle, _) = new_connection()?;
+    tokio::spawn(connection);
+
+    let ifindex = link_index(&handle, ifname).await?;
+    let mut addresses = handle
+        .address()
+        .get()
+        .set_link_index_filter(ifindex)
+        .execute();
+
+    let mut result = Vec::new();
+    while let Some(msg) = addresses.try_next().await? {
+        if msg.header.family != rtnl_packet::AF_INET6 as u8
+            || msg.header.scope != rtnl_packet::RT_SCOPE_UNIVERSE
+        {
+            continue;
+        }
+
+        // IFA_FLAGS supersedes the 8-bit header flags when present
+        let flags = msg
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                AddressNla::Flags(flags) => Some(*flags),
+                _ => None,
+            })
+            .unwrap_or(msg.header.flags as u32);
+        if flags & rtnl_packet::IFA_F_PERMANENT == 0 {
+            continue;
+        }
+        if owned_only && address_protocol(&msg) != Some(RTPROT_OPDBUS) {
+            continue;
+        }
+
+        let addr = msg.nlas.iter().find_map(|nla| match nla {
+            AddressNla::Address(bytes) => <[u8; 16]>::try_from(bytes.as_slice())
+                .ok()
+                .map(Ipv6Addr::from),
+            _ => None,
+        });
+        if let Some(addr) = addr {
+            result.push((addr, msg.header.prefix_len));
+        }
+    }
+
+    Ok(result)
+}
+
 /// Flush all addresses from interface
 #[allow(dead_code)]
 pub async fn flush_addresses(ifname: &str) -> Result<()> {
@@ -205,10 +369,173 @@ pub async fn del_default_route() -> Result<()> {
     Ok(())
 }
 
+/// Add (or replace) an op-dbus owned IPv6 route out of an interface
+pub async fn add_ipv6_route(
+    ifname: &str,
+    destination: Ipv6Addr,
+    prefix: u8,
+    gateway: Option<Ipv6Addr>,
+    metric: Option<u32>,
+) -> Result<()> {
+    let (connection, handle, _) = new_connection()?;
+    tokio::spawn(connection);
+
+    let ifindex = link_index(&handle, ifname).await?;
+
+    let mut request = handle
+        .route()
+        .add()
+        .v6()
+        .replace()
+        .destination_prefix(destination, prefix)
+        .output_interface(ifindex);
+    request.message_mut().header.protocol = RTPROT_OPDBUS;
+    if let Some(gw) = gateway {
+        request = request.gateway(gw);
+    }
+    if let Some(metric) = metric {
+        request.message_mut().nlas.push(RouteNla::Priority(metric));
+    }
+
+    request.execute().await.context(format!(
+        "Failed to add IPv6 route {}/{}",
+        destination, prefix
+    ))?;
+
+    Ok(())
+}
+
+/// Delete the op-dbus owned IPv6 route to destination/prefix out of an interface
+pub async fn del_ipv6_route(ifname: &str, destination: Ipv6Addr, prefix: u8) -> Result<()> {
+    for route in list_ipv6_routes(ifname).await? {
+        if route.owned && route.destination == destination && route.prefix == prefix {
+            let (connection, handle, _) = new_connection()?;
+            tokio::spawn(connection);
+            handle.route().del(route.message).execute().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Add IPv6 default route, replacing an existing one
+pub async fn add_ipv6_default_route(ifname: &str, gateway: &str) -> Result<()> {
+    let gw: Ipv6Addr = gateway.parse().context("Invalid IPv6 gateway address")?;
+    add_ipv6_route(ifname, Ipv6Addr::UNSPECIFIED, 0, Some(gw), None).await
+}
+
+/// An IPv6 unicast route in the main table
+#[derive(Debug, Clone)]
+pub struct Ipv6RouteEntry {
+    pub destination: Ipv6Addr,
+    pub prefix: u8,
+    pub gateway: Option<Ipv6Addr>,
+    pub metric: Option<u32>,
+    /// Installed by an administrator or op-dbus rather than RA or the kernel
+    pub is_static: bool,
+    /// Installed by op-dbus (RTPROT_OPDBUS), so op-dbus may remove it
+    pub owned: bool,
+    message: rtnl_packet::RouteMessage,
+}
+
+/// List IPv6 routes in the main table that leave through an interface
+pub async fn list_ipv6_routes(ifname: &str) -> Result<Vec<Ipv6RouteEntry>> {
+    let (connection, handle, _) = new_connection()?;
+    tokio::spawn(connection);
+
+    let ifindex = link_index(&handle, ifname).await?;
+    let mut routes = handle.route().get(IpVersion::V6).execute();
+
+    let ipv6 = |bytes: &Vec<u8>| {
+        <[u8; 16]>::try_from(bytes.as_slice())
+            .ok()
+            .map(Ipv6Addr::from)
+    };
+    let mut result = Vec::new();
+    while let Some(route) = routes.try_next().await? {
+        if route.header.table != rtnl_packet::RT_TABLE_MAIN
+            || route.header.kind != rtnl_packet::RTN_UNICAST
+        {
+            continue;
+        }
+        let mut oif = None;
+        let mut destination = None;
+        let mut gateway = None;
+        let mut metric = None;
+        for nla in &route.nlas {
+            match nla {
+                RouteNla::Oif(index) => oif = Some(*index),
+                RouteNla::Destination(bytes) => destination = ipv6(bytes),
+                RouteNla::Gateway(bytes) => gateway = ipv6(bytes),
+                RouteNla::Priority(value) => metric = Some(*value),
+                _ => {}
+            }
+        }
+        if oif != Some(ifindex) {
+            continue;
+        }
+
+        result.push(Ipv6RouteEntry {
+            destination: destination.unwrap_or(Ipv6Addr::UNSPECIFIED),
+            prefix: route.header.destination_prefix_length,
+            gateway,
+            metric,
+            is_static: matches!(
+                route.header.protocol,
+                rtnl_packet::RTPROT_STATIC | rtnl_packet::RTPROT_BOOT | RTPROT_OPDBUS
+            ),
+            owned: route.header.protocol == RTPROT_OPDBUS,
+            message: route,
+        });
+    }
+
+    Ok(result)
+}
+
+/// Per-interface IPv6 knobs (accept_ra, autoconf, use_tempaddr, disable_ipv6).
+/// RTM_SETLINK cannot write inet6 devconf, so these go through procfs.
+pub fn get_ipv6_conf(ifname: &str, key: &str) -> Result<i64> {
+    let path = format!("/proc/sys/net/ipv6/conf/{}/{}", ifname, key);
+    let value = std::fs::read_to_string(&path).context(format!("Failed to read {}", path))?;
+    value
+        .trim()
+        .parse()
+        .context(format!("Invalid value in {}", path))
+}
+
+/// Write a per-interface IPv6 knob, skipping the write when unchanged
+pub fn set_ipv6_conf(ifname: &str, key: &str, value: i64) -> Result<()> {
+    if get_ipv6_conf(ifname, key).ok() == Some(value) {
+        return Ok(());
+    }
+    let path = format!("/proc/sys/net/ipv6/conf/{}/{}", ifname, key);
+    std::fs::write(&path, value.to_string()).context(format!("Failed to write {}", path))
+}
+
+/// Resolve an interface name to its index
+async fn link_index(handle: &rtnetlink::Handle, ifname: &str) -> Result<u32> {
+    let mut links = handle.link().get().match_name(ifname.to_string()).execute();
+    let link = links
+        .try_next()
+        .await?
+        .context(format!("Interface '{}' not found", ifname))?;
+    Ok(link.header.index)
+}
+
 /// List IPv4 routes for a given interface (by name)
-pub async fn list_routes_for_interface(_ifname: &str) -> Result<Vec<serde_json::Value>> {
-    // Minimal, compile-safe stub; route filtering can be added later.
-    Ok(Vec::new())
+pub async fn list_routes_for_interface(ifname: &str) -> Result<Vec<serde_json::Value>> {
+    use super::rtnetlink_routes::{self, TableNames};
+
+    let tables = TableNames::load(&Default::default());
+    let mut result = Vec::new();
+    for route in rtnetlink_routes::list_routes().await? {
+        if route.dev.as_deref() != Some(ifname) || !route.destination.is_ipv4() {
+            continue;
+        }
+        let mut value = serde_json::to_value(route.to_spec(&tables))?;
+        value["managed"] = serde_json::Value::Bool(route.is_owned());
+        result.push(value);
+    }
+    Ok(result)
 }
 
 /// List all veth interfaces (simplified implementation)
@@ -219,7 +546,6 @@ pub async fn list_veth_interfaces() -> Result<Vec<String>> {
     Ok(Vec::new())
 }
 
-
 /// Rename network interface
 pub async fn link_set_name(old_name: &str, new_name: &str) -> Result<()> {
     let (connection, handle, _) = new_connection()?;
@@ -268,4 +594,15 @@ mod tests {
         // No strict expectation on content; presence/empty is both fine.
         println!("routes on lo: {:?}", routes);
     }
+
+    #[test]
+    fn test_address_protocol() {
+        let mut msg = rtnl_packet::AddressMessage::default();
+        assert_eq!(address_protocol(&msg), None);
+        msg.nlas.push(AddressNla::Other(DefaultNla::new(
+            IFA_PROTO,
+            vec![RTPROT_OPDBUS],
+        )));
+        assert_eq!(address_protocol(&msg), Some(RTPROT_OPDBUS));
+    }
 }
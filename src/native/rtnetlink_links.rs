@@ -0,0 +1,854 @@
+//! Rtnetlink link helpers - native creation, inspection and removal of kernel links
+//!
+//! Covers the virtual link kinds the net plugin declares: 802.1Q VLANs, bonds,
+//! veth pairs, VXLAN and GRE tunnels, and Linux bridges. Links created here are
+//! tagged with the [`OWNER_ALIAS`] ifalias so later diffs only remove links
+//! op-dbus created itself.
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::TryStreamExt;
+use rtnetlink::{new_connection, Handle};
+use rtnl_packet::link::nlas::{
+    Info, InfoBond, InfoBridge, InfoData, InfoKind, InfoVlan, InfoVxlan, Nla,
+};
+use rtnl_packet::{LinkMessage, IFF_UP};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// ifalias stamped on every link op-dbus creates
+pub const OWNER_ALIAS: &str = "op-dbus";
+
+/// Kernel bridge timers are expressed in centiseconds (USER_HZ)
+const BRIDGE_TIMER_HZ: u32 = 100;
+
+// IFLA_GRE_* attributes (linux/if_tunnel.h); rtnetlink carries GRE data as raw bytes
+const IFLA_GRE_IFLAGS: u16 = 2;
+const IFLA_GRE_OFLAGS: u16 = 3;
+const IFLA_GRE_IKEY: u16 = 4;
+const IFLA_GRE_OKEY: u16 = 5;
+const IFLA_GRE_LOCAL: u16 = 6;
+const IFLA_GRE_REMOTE: u16 = 7;
+const IFLA_GRE_TTL: u16 = 8;
+const GRE_KEY: u16 = 0x2000;
+
+/// Bonding mode (names as used by iproute2 / sysfs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BondMode {
+    #[serde(rename = "balance-rr")]
+    BalanceRr,
+    #[serde(rename = "active-backup")]
+    ActiveBackup,
+    #[serde(rename = "balance-xor")]
+    BalanceXor,
+    #[serde(rename = "broadcast")]
+    Broadcast,
+    #[serde(rename = "802.3ad")]
+    Lacp,
+    #[serde(rename = "balance-tlb")]
+    BalanceTlb,
+    #[serde(rename = "balance-alb")]
+    BalanceAlb,
+}
+
+impl BondMode {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(mode: u8) -> Option<Self> {
+        Some(match mode {
+            0 => BondMode::BalanceRr,
+            1 => BondMode::ActiveBackup,
+            2 => BondMode::BalanceXor,
+            3 => BondMode::Broadcast,
+            4 => BondMode::Lacp,
+            5 => BondMode::BalanceTlb,
+            6 => BondMode::BalanceAlb,
+            _ => return None,
+        })
+    }
+}
+
+/// 802.1Q VLAN subinterface
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VlanOptions {
+    /// Parent (lower) link name
+    pub parent: String,
+    /// VLAN id (1-4094)
+    pub id: u16,
+}
+
+/// Bond settings; unset fields are left at kernel defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BondOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<BondMode>,
+    /// MII link monitoring interval (ms)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub miimon: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updelay: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downdelay: Option<u32>,
+    /// Enslaved links
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub members: Option<Vec<String>>,
+}
+
+/// veth pair; the declared interface is one end, `peer` the other
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VethOptions {
+    pub peer: String,
+}
+
+/// VXLAN tunnel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VxlanOptions {
+    pub vni: u32,
+    /// Unicast remote VTEP or multicast group
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<IpAddr>,
+    /// Underlay device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// UDP destination port (kernel default 8472, IANA 4789)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+/// GRE (IPv4) tunnel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GreOptions {
+    pub remote: Ipv4Addr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<Ipv4Addr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<u32>,
+}
+
+/// Linux bridge settings; timers are in seconds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BridgeOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stp: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_delay: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hello_time: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vlan_filtering: Option<bool>,
+}
+
+/// Kind-specific link definition
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkSpec {
+    Vlan(VlanOptions),
+    Bond(BondOptions),
+    Veth(VethOptions),
+    Vxlan(VxlanOptions),
+    Gre(GreOptions),
+    Bridge(BridgeOptions),
+}
+
+impl LinkSpec {
+    /// Kernel link kind string (as reported by `ip -d link`)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LinkSpec::Vlan(_) => "vlan",
+            LinkSpec::Bond(_) => "bond",
+            LinkSpec::Veth(_) => "veth",
+            LinkSpec::Vxlan(_) => "vxlan",
+            LinkSpec::Gre(_) => "gre",
+            LinkSpec::Bridge(_) => "bridge",
+        }
+    }
+
+    /// Whether `current` satisfies every attribute this spec sets.
+    ///
+    /// Unset optional fields are ignored so partial declarations don't drift.
+    pub fn satisfied_by(&self, current: &LinkSpec) -> bool {
+        match (self, current) {
+            (LinkSpec::Vlan(d), LinkSpec::Vlan(c)) => d == c,
+            (LinkSpec::Veth(d), LinkSpec::Veth(c)) => d.peer == c.peer,
+            (LinkSpec::Bond(d), LinkSpec::Bond(c)) => {
+                opt_eq(&d.mode, &c.mode)
+                    && opt_eq(&d.miimon, &c.miimon)
+                    && opt_eq(&d.updelay, &c.updelay)
+                    && opt_eq(&d.downdelay, &c.downdelay)
+                    && members_attached(&d.members, &c.members)
+            }
+            (LinkSpec::Vxlan(d), LinkSpec::Vxlan(c)) => {
+                d.vni == c.vni
+                    && opt_eq(&d.remote, &c.remote)
+                    && opt_eq(&d.local, &c.local)
+                    && opt_eq(&d.parent, &c.parent)
+                    && opt_eq(&d.port, &c.port)
+            }
+            (LinkSpec::Gre(d), LinkSpec::Gre(c)) => {
+                d.remote == c.remote
+                    && opt_eq(&d.local, &c.local)
+                    && opt_eq(&d.ttl, &c.ttl)
+                    && opt_eq(&d.key, &c.key)
+            }
+            (LinkSpec::Bridge(d), LinkSpec::Bridge(c)) => {
+                opt_eq(&d.stp, &c.stp)
+                    && opt_eq(&d.priority, &c.priority)
+                    && opt_eq(&d.forward_delay, &c.forward_delay)
+                    && opt_eq(&d.hello_time, &c.hello_time)
+                    && opt_eq(&d.max_age, &c.max_age)
+                    && opt_eq(&d.vlan_filtering, &c.vlan_filtering)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether moving from `current` to this spec requires deleting and recreating the link
+    /// (identity attributes the kernel cannot change in place).
+    pub fn requires_recreate(&self, current: &LinkSpec) -> bool {
+        match (self, current) {
+            (LinkSpec::Vlan(d), LinkSpec::Vlan(c)) => d != c,
+            (LinkSpec::Veth(d), LinkSpec::Veth(c)) => d.peer != c.peer,
+            (LinkSpec::Vxlan(_), LinkSpec::Vxlan(_)) | (LinkSpec::Gre(_), LinkSpec::Gre(_)) => {
+                !self.satisfied_by(current)
+            }
+            (LinkSpec::Bond(d), LinkSpec::Bond(c)) => !opt_eq(&d.mode, &c.mode),
+            (LinkSpec::Bridge(_), LinkSpec::Bridge(_)) => false,
+            _ => true,
+        }
+    }
+}
+
+fn opt_eq<T: PartialEq>(desired: &Option<T>, current: &Option<T>) -> bool {
+    desired.is_none() || desired == current
+}
+
+/// Declared members are attached; members attached by others don't count as drift
+fn members_attached(desired: &Option<Vec<String>>, current: &Option<Vec<String>>) -> bool {
+    let current = current.as_deref().unwrap_or_default();
+    desired
+        .iter()
+        .flatten()
+        .all(|member| current.contains(member))
+}
+
+/// A kernel link as seen over rtnetlink
+#[derive(Debug, Clone)]
+pub struct KernelLink {
+    pub index: u32,
+    pub name: String,
+    /// Link kind (`vlan`, `bond`, ...); `None` for physical devices
+    pub kind: Option<String>,
+    pub spec: Option<LinkSpec>,
+    pub master: Option<String>,
+    pub alias: Option<String>,
+    pub mtu: Option<u32>,
+    pub mac: Option<String>,
+    pub up: bool,
+}
+
+impl KernelLink {
+    /// Whether op-dbus created this link
+    pub fn is_owned(&self) -> bool {
+        self.alias.as_deref() == Some(OWNER_ALIAS)
+    }
+}
+
+fn connect() -> Result<Handle> {
+    let (connection, handle, _) = new_connection()?;
+    tokio::spawn(connection);
+    Ok(handle)
+}
+
+async fn link_index(handle: &Handle, name: &str) -> Result<u32> {
+    let mut links = handle.link().get().match_name(name.to_string()).execute();
+    let link = links
+        .try_next()
+        .await?
+        .context(format!("Interface '{}' not found", name))?;
+    Ok(link.header.index)
+}
+
+/// Dump every link in the current network namespace
+pub async fn list_links() -> Result<Vec<KernelLink>> {
+    let handle = connect()?;
+    let mut messages = Vec::new();
+    let mut links = handle.link().get().execute();
+    while let Some(msg) = links.try_next().await? {
+        messages.push(msg);
+    }
+
+    let names: HashMap<u32, String> = messages
+        .iter()
+        .filter_map(|msg| {
+            msg.nlas.iter().find_map(|nla| match nla {
+                Nla::IfName(name) => Some((msg.header.index, name.clone())),
+                _ => None,
+            })
+        })
+        .collect();
+
+    let mut result: Vec<KernelLink> = messages.iter().map(|msg| parse_link(msg, &names)).collect();
+
+    // Bond membership is reported on the members (IFLA_MASTER), not on the bond
+    let mut members: HashMap<String, Vec<String>> = HashMap::new();
+    for link in &result {
+        if let Some(master) = &link.master {
+            members
+                .entry(master.clone())
+                .or_default()
+                .push(link.name.clone());
+        }
+    }
+    for link in &mut result {
+        if let Some(LinkSpec::Bond(bond)) = &mut link.spec {
+            bond.members = Some(members.remove(&link.name).unwrap_or_default());
+        }
+    }
+
+    Ok(result)
+}
+
+/// Look up a single link by name
+pub async fn get_link(name: &str) -> Result<Option<KernelLink>> {
+    Ok(list_links().await?.into_iter().find(|l| l.name == name))
+}
+
+pub(crate) fn parse_link(msg: &LinkMessage, names: &HashMap<u32, String>) -> KernelLink {
+    let mut link = KernelLink {
+        index: msg.header.index,
+        name: String::new(),
+        kind: None,
+        spec: None,
+        master: None,
+        alias: None,
+        mtu: None,
+        mac: None,
+        up: msg.header.flags & IFF_UP != 0,
+    };
+    let mut lower: Option<String> = None;
+    let mut kind: Option<InfoKind> = None;
+    let mut data: Option<&InfoData> = None;
+
+    for nla in &msg.nlas {
+        match nla {
+            Nla::IfName(name) => link.name = name.clone(),
+            Nla::IfAlias(alias) => link.alias = Some(alias.clone()),
+            Nla::Mtu(mtu) => link.mtu = Some(*mtu),
+            Nla::Master(idx) => link.master = names.get(idx).cloned(),
+            Nla::Link(idx) => lower = names.get(idx).cloned(),
+            Nla::Address(bytes) if bytes.len() == 6 => {
+                link.mac = Some(
+                    bytes
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<Vec<_>>()
+                        .join(":"),
+                )
+            }
+            Nla::Info(infos) => {
+                for info in infos {
+                    match info {
+                        Info::Kind(k) => kind = Some(k.clone()),
+                        Info::Data(d) => data = Some(d),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    link.kind = kind.as_ref().map(kind_name);
+    link.spec = match (kind, data) {
+        (Some(InfoKind::Vlan), Some(InfoData::Vlan(attrs))) => attrs.iter().find_map(|a| match a {
+            InfoVlan::Id(id) => Some(LinkSpec::Vlan(VlanOptions {
+                parent: lower.clone().unwrap_or_default(),
+                id: *id,
+            })),
+            _ => None,
+        }),
+        (Some(InfoKind::Veth), _) => lower.map(|peer| LinkSpec::Veth(VethOptions { peer })),
+        (Some(InfoKind::Bond), Some(InfoData::Bond(attrs))) => {
+            let mut bond = BondOptions::default();
+            for attr in attrs {
+                match attr {
+                    InfoBond::Mode(m) => bond.mode = BondMode::from_u8(*m),
+                    InfoBond::MiiMon(v) => bond.miimon = Some(*v),
+                    InfoBond::UpDelay(v) => bond.updelay = Some(*v),
+                    InfoBond::DownDelay(v) => bond.downdelay = Some(*v),
+                    _ => {}
+                }
+            }
+            Some(LinkSpec::Bond(bond))
+        }
+        (Some(InfoKind::Vxlan), Some(InfoData::Vxlan(attrs))) => {
+            let mut vxlan = VxlanOptions {
+                vni: 0,
+                remote: None,
+                local: None,
+                parent: None,
+                port: None,
+            };
+            for attr in attrs {
+                match attr {
+                    InfoVxlan::Id(vni) => vxlan.vni = *vni,
+                    InfoVxlan::Group(b) | InfoVxlan::Group6(b) => vxlan.remote = ip_from_bytes(b),
+                    InfoVxlan::Local(b) | InfoVxlan::Local6(b) => vxlan.local = ip_from_bytes(b),
+                    InfoVxlan::Link(idx) => vxlan.parent = names.get(idx).cloned(),
+                    InfoVxlan::Port(port) => vxlan.port = Some(*port),
+                    _ => {}
+                }
+            }
+            Some(LinkSpec::Vxlan(vxlan))
+        }
+        (Some(InfoKind::GreTun), Some(InfoData::GreTun(raw))) => parse_gre(raw).map(LinkSpec::Gre),
+        (Some(InfoKind::Bridge), data) => {
+            let mut bridge = BridgeOptions::default();
+            if let Some(InfoData::Bridge(attrs)) = data {
+                for attr in attrs {
+                    match attr {
+                        InfoBridge::StpState(s) => bridge.stp = Some(*s != 0),
+                        InfoBridge::Priority(p) => bridge.priority = Some(*p),
+                        InfoBridge::ForwardDelay(v) => {
+                            bridge.forward_delay = Some(v / BRIDGE_TIMER_HZ)
+                        }
+                        InfoBridge::HelloTime(v) => bridge.hello_time = Some(v / BRIDGE_TIMER_HZ),
+                        InfoBridge::MaxAge(v) => bridge.max_age = Some(v / BRIDGE_TIMER_HZ),
+                        InfoBridge::VlanFiltering(v) => bridge.vlan_filtering = Some(*v != 0),
+                        _ => {}
+                    }
+                }
+            }
+            Some(LinkSpec::Bridge(bridge))
+        }
+        _ => None,
+    };
+
+    link
+}
+
+fn kind_name(kind: &InfoKind) -> String {
+    match kind {
+        InfoKind::Vlan => "vlan".to_string(),
+        InfoKind::Veth => "veth".to_string(),
+        InfoKind::Bond => "bond".to_string(),
+        InfoKind::Vxlan => "vxlan".to_string(),
+        InfoKind::GreTun => "gre".to_string(),
+        InfoKind::GreTap => "gretap".to_string(),
+        InfoKind::Bridge => "bridge".to_string(),
+        InfoKind::Dummy => "dummy".to_string(),
+        InfoKind::Tun => "tun".to_string(),
+        InfoKind::Wireguard => "wireguard".to_string(),
+        InfoKind::MacVlan => "macvlan".to_string(),
+        InfoKind::Vrf => "vrf".to_string(),
+        InfoKind::Other(other) => other.clone(),
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => None,
+    }
+}
+
+/// Encode a single netlink attribute (header + value, padded to 4 bytes)
+fn raw_nla(kind: u16, value: &[u8]) -> Vec<u8> {
+    let len = 4 + value.len();
+    let mut buf = Vec::with_capacity((len + 3) & !3);
+    buf.extend_from_slice(&(len as u16).to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(value);
+    buf.resize((len + 3) & !3, 0);
+    buf
+}
+
+/// Encode IFLA_GRE_* attributes
+fn encode_gre(opts: &GreOptions) -> Vec<u8> {
+    let mut buf = raw_nla(IFLA_GRE_REMOTE, &opts.remote.octets());
+    if let Some(local) = opts.local {
+        buf.extend(raw_nla(IFLA_GRE_LOCAL, &local.octets()));
+    }
+    if let Some(ttl) = opts.ttl {
+        buf.extend(raw_nla(IFLA_GRE_TTL, &[ttl]));
+    }
+    if let Some(key) = opts.key {
+        for flags in [IFLA_GRE_IFLAGS, IFLA_GRE_OFLAGS] {
+            buf.extend(raw_nla(flags, &GRE_KEY.to_be_bytes()));
+        }
+        for attr in [IFLA_GRE_IKEY, IFLA_GRE_OKEY] {
+            buf.extend(raw_nla(attr, &key.to_be_bytes()));
+        }
+    }
+    buf
+}
+
+/// Decode IFLA_GRE_* attributes
+fn parse_gre(mut raw: &[u8]) -> Option<GreOptions> {
+    let mut remote = None;
+    let mut local = None;
+    let mut ttl = None;
+    let mut key = None;
+
+    while raw.len() >= 4 {
+        let len = u16::from_ne_bytes([raw[0], raw[1]]) as usize;
+        let kind = u16::from_ne_bytes([raw[2], raw[3]]) & 0x3fff;
+        if len < 4 || len > raw.len() {
+            break;
+        }
+        let value = &raw[4..len];
+        match (kind, value.len()) {
+            (IFLA_GRE_REMOTE, 4) => {
+                remote = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+            }
+            (IFLA_GRE_LOCAL, 4) => {
+                let addr = Ipv4Addr::new(value[0], value[1], value[2], value[3]);
+                local = (!addr.is_unspecified()).then_some(addr);
+            }
+            (IFLA_GRE_TTL, 1) => ttl = (value[0] != 0).then_some(value[0]),
+            (IFLA_GRE_IKEY, 4) => {
+                let k = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+                key = (k != 0).then_some(k);
+            }
+            _ => {}
+        }
+        raw = &raw[((len + 3) & !3).min(raw.len())..];
+    }
+
+    Some(GreOptions {
+        remote: remote?,
+        local,
+        ttl,
+        key,
+    })
+}
+
+fn bridge_attrs(opts: &BridgeOptions) -> Vec<InfoBridge> {
+    let mut attrs = Vec::new();
+    if let Some(stp) = opts.stp {
+        attrs.push(InfoBridge::StpState(stp as u32));
+    }
+    if let Some(priority) = opts.priority {
+        attrs.push(InfoBridge::Priority(priority));
+    }
+    if let Some(delay) = opts.forward_delay {
+        attrs.push(InfoBridge::ForwardDelay(delay * BRIDGE_TIMER_HZ));
+    }
+    if let Some(hello) = opts.hello_time {
+        attrs.push(InfoBridge::HelloTime(hello * BRIDGE_TIMER_HZ));
+    }
+    if let Some(age) = opts.max_age {
+        attrs.push(InfoBridge::MaxAge(age * BRIDGE_TIMER_HZ));
+    }
+    if let Some(filtering) = opts.vlan_filtering {
+        attrs.push(InfoBridge::VlanFiltering(filtering as u8));
+    }
+    attrs
+}
+
+fn bond_attrs(opts: &BondOptions, include_mode: bool) -> Vec<InfoBond> {
+    let mut attrs = Vec::new();
+    if include_mode {
+        if let Some(mode) = opts.mode {
+            attrs.push(InfoBond::Mode(mode.as_u8()));
+        }
+    }
+    if let Some(miimon) = opts.miimon {
+        attrs.push(InfoBond::MiiMon(miimon));
+    }
+    if let Some(updelay) = opts.updelay {
+        attrs.push(InfoBond::UpDelay(updelay));
+    }
+    if let Some(downdelay) = opts.downdelay {
+        attrs.push(InfoBond::DownDelay(downdelay));
+    }
+    attrs
+}
+
+/// Create a link and tag it as op-dbus owned
+pub async fn create_link(name: &str, spec: &LinkSpec) -> Result<()> {
+    let handle = connect()?;
+    let request = handle.link().add();
+
+    let result = match spec {
+        LinkSpec::Vlan(vlan) => {
+            let parent = link_index(&handle, &vlan.parent).await?;
+            request
+                .vlan(name.to_string(), parent, vlan.id)
+                .execute()
+                .await
+        }
+        LinkSpec::Veth(veth) => {
+            request
+                .veth(name.to_string(), veth.peer.clone())
+                .execute()
+                .await
+        }
+        LinkSpec::Vxlan(vxlan) => {
+            let mut req = request.vxlan(name.to_string(), vxlan.vni).up();
+            if let Some(parent) = &vxlan.parent {
+                req = req.link(link_index(&handle, parent).await?);
+            }
+            req = match vxlan.remote {
+                Some(IpAddr::V4(addr)) => req.remote(addr),
+                Some(IpAddr::V6(addr)) => req.remote6(addr),
+                None => req,
+            };
+            req = match vxlan.local {
+                Some(IpAddr::V4(addr)) => req.local(addr),
+                Some(IpAddr::V6(addr)) => req.local6(addr),
+                None => req,
+            };
+            if let Some(port) = vxlan.port {
+                req = req.port(port);
+            }
+            req.execute().await
+        }
+        LinkSpec::Bond(bond) => {
+            let mut req = request.bond(name.to_string()).up();
+            if let Some(mode) = bond.mode {
+                req = req.mode(mode.as_u8());
+            }
+            if let Some(miimon) = bond.miimon {
+                req = req.miimon(miimon);
+            }
+            if let Some(updelay) = bond.updelay {
+                req = req.updelay(updelay);
+            }
+            if let Some(downdelay) = bond.downdelay {
+                req = req.downdelay(downdelay);
+            }
+            req.execute().await
+        }
+        LinkSpec::Gre(gre) => {
+            let mut req = request;
+            let msg = req.message_mut();
+            msg.header.flags = IFF_UP;
+            msg.header.change_mask = IFF_UP;
+            msg.nlas.push(Nla::IfName(name.to_string()));
+            msg.nlas.push(Nla::Info(vec![
+                Info::Kind(InfoKind::GreTun),
+                Info::Data(InfoData::GreTun(encode_gre(gre))),
+            ]));
+            req.execute().await
+        }
+        LinkSpec::Bridge(bridge) => {
+            let mut req = request;
+            let msg = req.message_mut();
+            msg.header.flags = IFF_UP;
+            msg.header.change_mask = IFF_UP;
+            msg.nlas.push(Nla::IfName(name.to_string()));
+            let mut info = vec![Info::Kind(InfoKind::Bridge)];
+            let attrs = bridge_attrs(bridge);
+            if !attrs.is_empty() {
+                info.push(Info::Data(InfoData::Bridge(attrs)));
+            }
+            msg.nlas.push(Nla::Info(info));
+            req.execute().await
+        }
+    };
+    result.map_err(|e| anyhow!("Failed to create {} link {}: {}", spec.kind(), name, e))?;
+
+    set_alias(name, OWNER_ALIAS).await?;
+    Ok(())
+}
+
+/// Create a WireGuard link, up and tagged as op-dbus owned. The link has no
+/// rtnetlink options; keys and peers go through [`crate::native::wireguard`].
+pub async fn create_wireguard_link(name: &str) -> Result<()> {
+    let handle = connect()?;
+    let mut request = handle.link().add();
+    let msg = request.message_mut();
+    msg.header.flags = IFF_UP;
+    msg.header.change_mask = IFF_UP;
+    msg.nlas.push(Nla::IfName(name.to_string()));
+    msg.nlas.push(Nla::Info(vec![Info::Kind(InfoKind::Wireguard)]));
+    request
+        .execute()
+        .await
+        .map_err(|e| anyhow!("Failed to create wireguard link {}: {}", name, e))?;
+
+    set_alias(name, OWNER_ALIAS).await?;
+    Ok(())
+}
+
+/// Update attributes the kernel can change in place (bond timers, bridge STP/timers)
+pub async fn update_link(name: &str, spec: &LinkSpec) -> Result<()> {
+    let (kind, data) = match spec {
+        LinkSpec::Bond(bond) => (InfoKind::Bond, InfoData::Bond(bond_attrs(bond, false))),
+        LinkSpec::Bridge(bridge) => (InfoKind::Bridge, InfoData::Bridge(bridge_attrs(bridge))),
+        _ => return Ok(()),
+    };
+
+    let handle = connect()?;
+    let index = link_index(&handle, name).await?;
+
+    // RTM_NEWLINK without NLM_F_EXCL on an existing link is a changelink
+    let mut request = handle.link().add().replace();
+    let msg = request.message_mut();
+    msg.header.index = index;
+    msg.nlas
+        .push(Nla::Info(vec![Info::Kind(kind), Info::Data(data)]));
+    request
+        .execute()
+        .await
+        .map_err(|e| anyhow!("Failed to update {} link {}: {}", spec.kind(), name, e))
+}
+
+/// Delete a link by name
+pub async fn delete_link(name: &str) -> Result<()> {
+    let handle = connect()?;
+    let index = link_index(&handle, name).await?;
+    handle
+        .link()
+        .del(index)
+        .execute()
+        .await
+        .context(format!("Failed to delete link {}", name))
+}
+
+/// Enslave `name` to `master` (bond or bridge), or release it with `None`
+pub async fn set_master(name: &str, master: Option<&str>) -> Result<()> {
+    let handle = connect()?;
+    let index = link_index(&handle, name).await?;
+    let request = match master {
+        Some(master) => {
+            let master_index = link_index(&handle, master).await?;
+            handle.link().set(index).master(master_index)
+        }
+        None => handle.link().set(index).nomaster(),
+    };
+    request
+        .execute()
+        .await
+        .context(format!("Failed to set master of {}", name))
+}
+
+/// Set the link's ifalias
+pub async fn set_alias(name: &str, alias: &str) -> Result<()> {
+    let handle = connect()?;
+    let index = link_index(&handle, name).await?;
+    let mut request = handle.link().set(index);
+    request
+        .message_mut()
+        .nlas
+        .push(Nla::IfAlias(alias.to_string()));
+    request
+        .execute()
+        .await
+        .context(format!("Failed to set alias on {}", name))
+}
+
+/// Set the link MTU
+pub async fn set_mtu(name: &str, mtu: u32) -> Result<()> {
+    let handle = connect()?;
+    let index = link_index(&handle, name).await?;
+    handle
+        .link()
+        .set(index)
+        .mtu(mtu)
+        .execute()
+        .await
+        .context(format!("Failed to set MTU on {}", name))
+}
+
+/// Create `name` if missing, recreate it if an identity attribute changed, and
+/// update in-place attributes otherwise. Refuses to recreate links op-dbus does not own.
+pub async fn ensure_link(name: &str, spec: &LinkSpec) -> Result<&'static str> {
+    let Some(current) = get_link(name).await? else {
+        create_link(name, spec).await?;
+        return Ok("created");
+    };
+
+    match &current.spec {
+        Some(current_spec) if !spec.requires_recreate(current_spec) => {
+            if !spec.satisfied_by(current_spec) {
+                update_link(name, spec).await?;
+                return Ok("updated");
+            }
+            Ok("unchanged")
+        }
+        _ => {
+            if !current.is_owned() {
+                bail!(
+                    "{} exists as {} and is not managed by op-dbus; refusing to recreate it as {}",
+                    name,
+                    current.kind.as_deref().unwrap_or("a physical link"),
+                    spec.kind()
+                );
+            }
+            delete_link(name).await?;
+            create_link(name, spec).await?;
+            Ok("recreated")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gre_roundtrip() {
+        let opts = GreOptions {
+            remote: "203.0.113.7".parse().unwrap(),
+            local: Some("198.51.100.1".parse().unwrap()),
+            ttl: Some(64),
+            key: Some(42),
+        };
+        assert_eq!(parse_gre(&encode_gre(&opts)), Some(opts));
+    }
+
+    #[test]
+    fn test_partial_spec_matching() {
+        let desired = LinkSpec::Bond(BondOptions {
+            mode: Some(BondMode::Lacp),
+            miimon: Some(100),
+            ..Default::default()
+        });
+        let current = LinkSpec::Bond(BondOptions {
+            mode: Some(BondMode::Lacp),
+            miimon: Some(100),
+            updelay: Some(0),
+            downdelay: Some(0),
+            members: Some(vec!["eth1".into(), "eth0".into()]),
+        });
+        assert!(desired.satisfied_by(&current));
+        assert!(!desired.requires_recreate(&current));
+
+        // Declared members must be attached; extra ones are someone else's
+        let member = |members: &[&str]| {
+            LinkSpec::Bond(BondOptions {
+                members: Some(members.iter().map(|m| m.to_string()).collect()),
+                ..Default::default()
+            })
+        };
+        assert!(member(&["eth0"]).satisfied_by(&current));
+        assert!(!member(&["eth0", "eth2"]).satisfied_by(&current));
+
+        let other_mode = LinkSpec::Bond(BondOptions {
+            mode: Some(BondMode::ActiveBackup),
+            ..Default::default()
+        });
+        assert!(other_mode.requires_recreate(&current));
+        assert!(LinkSpec::Bridge(BridgeOptions::default()).requires_recreate(&current));
+    }
+
+    #[test]
+    fn test_bond_mode_serde() {
+        let mode: BondMode = serde_json::from_value(serde_json::json!("802.3ad")).unwrap();
+        assert_eq!(mode, BondMode::Lacp);
+        assert_eq!(BondMode::from_u8(mode.as_u8()), Some(BondMode::Lacp));
+    }
+}
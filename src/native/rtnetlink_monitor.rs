@@ -0,0 +1,527 @@
+//! Rtnetlink monitor - live link, address and route view fed by kernel notifications
+//!
+//! Subscribes to the link, IPv4/IPv6 address, route and rule multicast groups, keeps a
+//! cached [`LiveView`] current and publishes typed events ([`LinkAdded`],
+//! [`AddressRemoved`], ...) on the global event bus; [`events`] merges them into one
+//! stream. Consumers that only need to know whether anything changed compare
+//! [`NetlinkMonitor::generation`], which OVSDB changes to bridges and ports bump too.
+
+use crate::event_bus::{
+    self, AddressAdded, AddressRemoved, Event, LinkAdded, LinkChanged, LinkRemoved, RouteAdded,
+    RouteRemoved, RuleAdded, RuleRemoved,
+};
+use crate::native::ovsdb_monitor::MonitorRequest;
+use crate::native::{rtnetlink_links, rtnetlink_routes, OvsdbClient};
+use anyhow::{bail, Result};
+use futures::{Stream, StreamExt, TryStreamExt};
+use netlink_packet_core::NetlinkPayload;
+use netlink_sys::{AsyncSocket, SocketAddr};
+use once_cell::sync::OnceCell;
+use rtnetlink::{new_connection, Handle};
+use rtnl_packet::address::Nla as AddressNla;
+use rtnl_packet::{
+    AddressMessage, RtnlMessage, RTNLGRP_IPV4_IFADDR, RTNLGRP_IPV4_ROUTE, RTNLGRP_IPV4_RULE,
+    RTNLGRP_IPV6_IFADDR, RTNLGRP_IPV6_ROUTE, RTNLGRP_IPV6_RULE, RTNLGRP_LINK, RT_TABLE_LOCAL,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+
+/// Multicast groups the monitor joins
+const GROUPS: [u32; 7] = [
+    RTNLGRP_LINK,
+    RTNLGRP_IPV4_IFADDR,
+    RTNLGRP_IPV6_IFADDR,
+    RTNLGRP_IPV4_ROUTE,
+    RTNLGRP_IPV6_ROUTE,
+    RTNLGRP_IPV4_RULE,
+    RTNLGRP_IPV6_RULE,
+];
+
+/// Event types the monitor publishes
+pub const EVENT_TYPES: [&str; 9] = [
+    "LinkAdded",
+    "LinkChanged",
+    "LinkRemoved",
+    "AddressAdded",
+    "AddressRemoved",
+    "RouteAdded",
+    "RouteRemoved",
+    "RuleAdded",
+    "RuleRemoved",
+];
+
+/// OVSDB tables whose rows show up in the net plugin's state
+const OVSDB_TABLES: [&str; 6] = ["Bridge", "Port", "Interface", "Mirror", "QoS", "Queue"];
+
+/// Delay before re-subscribing after the notification socket fails
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// A link as tracked by the monitor
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LiveLink {
+    pub index: u32,
+    pub name: String,
+    pub kind: Option<String>,
+    pub up: bool,
+    pub mtu: Option<u32>,
+    pub master: Option<String>,
+}
+
+/// Cached kernel state, bumped to a new generation on every change
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LiveView {
+    pub generation: u64,
+    pub links: BTreeMap<u32, LiveLink>,
+    /// Addresses per link index, as "ip/prefix"
+    pub addresses: BTreeMap<u32, BTreeSet<String>>,
+}
+
+impl LiveView {
+    fn names(&self) -> HashMap<u32, String> {
+        self.links
+            .iter()
+            .map(|(index, link)| (*index, link.name.clone()))
+            .collect()
+    }
+
+    fn name(&self, index: u32) -> String {
+        self.links
+            .get(&index)
+            .map(|link| link.name.clone())
+            .unwrap_or_else(|| format!("if{}", index))
+    }
+
+    /// Addresses of a link by name
+    pub fn addresses_of(&self, ifname: &str) -> Vec<String> {
+        self.links
+            .values()
+            .find(|link| link.name == ifname)
+            .and_then(|link| self.addresses.get(&link.index))
+            .map(|addrs| addrs.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Fold one notification into the view, returning the events it produced
+    fn apply(&mut self, message: RtnlMessage) -> Vec<Box<dyn Event>> {
+        let mut events: Vec<Box<dyn Event>> = Vec::new();
+
+        match message {
+            RtnlMessage::NewLink(msg) => {
+                let mut link = LiveLink::from(rtnetlink_links::parse_link(&msg, &self.names()));
+                if link.name.is_empty() {
+                    link.name = self.name(link.index);
+                }
+                match self.links.insert(link.index, link.clone()) {
+                    None => events.push(Box::new(LinkAdded {
+                        ifname: link.name,
+                        index: link.index,
+                        kind: link.kind,
+                    })),
+                    Some(previous) if previous != link => events.push(Box::new(LinkChanged {
+                        ifname: link.name,
+                        index: link.index,
+                        up: link.up,
+                        mtu: link.mtu,
+                        master: link.master,
+                    })),
+                    Some(_) => {}
+                }
+            }
+            RtnlMessage::DelLink(msg) => {
+                let index = msg.header.index;
+                self.addresses.remove(&index);
+                if let Some(link) = self.links.remove(&index) {
+                    events.push(Box::new(LinkRemoved {
+                        ifname: link.name,
+                        index,
+                    }));
+                }
+            }
+            RtnlMessage::NewAddress(msg) => {
+                if let Some(address) = address_cidr(&msg) {
+                    let index = msg.header.index;
+                    if self
+                        .addresses
+                        .entry(index)
+                        .or_default()
+                        .insert(address.clone())
+                    {
+                        events.push(Box::new(AddressAdded {
+                            ifname: self.name(index),
+                            index,
+                            address,
+                        }));
+                    }
+                }
+            }
+            RtnlMessage::DelAddress(msg) => {
+                if let Some(address) = address_cidr(&msg) {
+                    let index = msg.header.index;
+                    let removed = self
+                        .addresses
+                        .get_mut(&index)
+                        .is_some_and(|addrs| addrs.remove(&address));
+                    if removed {
+                        events.push(Box::new(AddressRemoved {
+                            ifname: self.name(index),
+                            index,
+                            address,
+                        }));
+                    }
+                }
+            }
+            // Local/broadcast routes mirror address changes; skip the noise
+            RtnlMessage::NewRoute(msg) if msg.header.table != RT_TABLE_LOCAL => {
+                if let Some(route) = rtnetlink_routes::parse_route(&msg, &self.names()) {
+                    events.push(Box::new(RouteAdded {
+                        destination: format!("{}/{}", route.destination, route.prefix),
+                        table: route.table,
+                        dev: route.dev,
+                        gateway: route.gateway.map(|gw| gw.to_string()),
+                        protocol: route.protocol,
+                    }));
+                }
+            }
+            RtnlMessage::DelRoute(msg) if msg.header.table != RT_TABLE_LOCAL => {
+                if let Some(route) = rtnetlink_routes::parse_route(&msg, &self.names()) {
+                    events.push(Box::new(RouteRemoved {
+                        destination: format!("{}/{}", route.destination, route.prefix),
+                        table: route.table,
+                        dev: route.dev,
+                        gateway: route.gateway.map(|gw| gw.to_string()),
+                        protocol: route.protocol,
+                    }));
+                }
+            }
+            RtnlMessage::NewRule(msg) => {
+                let rule = rtnetlink_routes::parse_rule(&msg);
+                events.push(Box::new(RuleAdded {
+                    priority: rule.priority,
+                    table: rule.table,
+                    ipv6: rule.ipv6,
+                    protocol: rule.protocol,
+                }));
+            }
+            RtnlMessage::DelRule(msg) => {
+                let rule = rtnetlink_routes::parse_rule(&msg);
+                events.push(Box::new(RuleRemoved {
+                    priority: rule.priority,
+                    table: rule.table,
+                    ipv6: rule.ipv6,
+                    protocol: rule.protocol,
+                }));
+            }
+            _ => {}
+        }
+
+        if !events.is_empty() {
+            self.generation += 1;
+        }
+        events
+    }
+}
+
+impl From<rtnetlink_links::KernelLink> for LiveLink {
+    fn from(link: rtnetlink_links::KernelLink) -> Self {
+        Self {
+            index: link.index,
+            name: link.name,
+            kind: link.kind,
+            up: link.up,
+            mtu: link.mtu,
+            master: link.master,
+        }
+    }
+}
+
+/// Local address of an address message as "ip/prefix"
+fn address_cidr(msg: &AddressMessage) -> Option<String> {
+    // IFA_LOCAL is the interface's own address; IFA_ADDRESS is the peer on point-to-point links
+    let local = msg.nlas.iter().find_map(|nla| match nla {
+        AddressNla::Local(bytes) => ip_from_bytes(bytes),
+        _ => None,
+    });
+    let address = local.or_else(|| {
+        msg.nlas.iter().find_map(|nla| match nla {
+            AddressNla::Address(bytes) => ip_from_bytes(bytes),
+            _ => None,
+        })
+    })?;
+    Some(format!("{}/{}", address, msg.header.prefix_len))
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
+/// Background subscriber keeping a [`LiveView`] current
+pub struct NetlinkMonitor {
+    view: RwLock<LiveView>,
+    ready: AtomicBool,
+}
+
+static MONITOR: OnceCell<Arc<NetlinkMonitor>> = OnceCell::new();
+
+/// Start the monitor (idempotent); must be called from within the tokio runtime
+pub fn start() -> Arc<NetlinkMonitor> {
+    MONITOR
+        .get_or_init(|| {
+            let monitor = Arc::new(NetlinkMonitor {
+                view: RwLock::new(LiveView::default()),
+                ready: AtomicBool::new(false),
+            });
+            tokio::spawn(Arc::clone(&monitor).run());
+            tokio::spawn(Arc::clone(&monitor).watch_ovsdb());
+            monitor
+        })
+        .clone()
+}
+
+/// The running monitor, if [`start`] was called
+pub fn get() -> Option<Arc<NetlinkMonitor>> {
+    MONITOR.get().cloned()
+}
+
+/// All monitor events as one stream; events missed by a lagging consumer are skipped
+pub async fn events() -> impl Stream<Item = Arc<Box<dyn Event>>> {
+    let mut streams = Vec::new();
+    for event_type in EVENT_TYPES {
+        let rx = event_bus::global().stream(event_type).await;
+        let stream = futures::stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        streams.push(stream.boxed());
+    }
+    futures::stream::select_all(streams)
+}
+
+impl NetlinkMonitor {
+    /// Whether the initial dump completed and notifications are flowing
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// Current generation; changes whenever the kernel or OVSDB reports a change
+    pub async fn generation(&self) -> u64 {
+        self.view.read().await.generation
+    }
+
+    async fn bump(&self) {
+        self.view.write().await.generation += 1;
+    }
+
+    /// Snapshot of the live view
+    pub async fn view(&self) -> LiveView {
+        self.view.read().await.clone()
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            if let Err(e) = self.listen().await {
+                log::warn!("Netlink monitor stopped: {}", e);
+            }
+            self.ready.store(false, Ordering::Release);
+            tokio::time::sleep(RESTART_DELAY).await;
+        }
+    }
+
+    async fn listen(&self) -> Result<()> {
+        let (mut connection, handle, mut messages) = new_connection()?;
+        let groups = GROUPS
+            .iter()
+            .fold(0u32, |mask, group| mask | (1 << (group - 1)));
+        connection
+            .socket_mut()
+            .socket_mut()
+            .bind(&SocketAddr::new(0, groups))?;
+        tokio::spawn(connection);
+
+        // Subscribe first, then dump, so nothing falls between the two
+        self.resync(&handle).await?;
+        self.ready.store(true, Ordering::Release);
+        log::info!("Netlink monitor subscribed to link, address and route changes");
+
+        while let Some((message, _)) = messages.next().await {
+            match message.payload {
+                NetlinkPayload::InnerMessage(msg) => {
+                    let events = self.view.write().await.apply(msg);
+                    for event in events {
+                        if let Err(e) = event_bus::global().publish(event).await {
+                            log::warn!("Failed to publish netlink event: {}", e);
+                        }
+                    }
+                }
+                NetlinkPayload::Overrun(_) => {
+                    log::warn!("Netlink monitor overrun; resynchronizing");
+                    self.resync(&handle).await?;
+                }
+                _ => {}
+            }
+        }
+
+        bail!("notification stream closed")
+    }
+
+    /// Bump the generation on OVSDB bridge and port changes, which raise no netlink
+    /// notification. Every (re)connect bumps too, since changes may have been missed.
+    async fn watch_ovsdb(self: Arc<Self>) {
+        let requests: Vec<MonitorRequest> = OVSDB_TABLES
+            .iter()
+            .map(|table| MonitorRequest::table(table))
+            .collect();
+        loop {
+            match OvsdbClient::new().monitor(&requests).await {
+                Ok(monitor) => {
+                    let mut changes = monitor.changes();
+                    self.bump().await;
+                    loop {
+                        match tokio::time::timeout(RESTART_DELAY, changes.recv()).await {
+                            Ok(Ok(_)) | Ok(Err(broadcast::error::RecvError::Lagged(_))) => {
+                                self.bump().await
+                            }
+                            Ok(Err(broadcast::error::RecvError::Closed)) => break,
+                            Err(_) if !monitor.is_running() => break,
+                            Err(_) => {}
+                        }
+                    }
+                    log::debug!("OVSDB monitor closed; reconnecting");
+                    self.bump().await;
+                }
+                Err(e) => log::debug!("OVSDB monitor unavailable: {}", e),
+            }
+            tokio::time::sleep(RESTART_DELAY).await;
+        }
+    }
+
+    /// Replace the view with a fresh dump of links and addresses
+    async fn resync(&self, handle: &Handle) -> Result<()> {
+        let links: BTreeMap<u32, LiveLink> = rtnetlink_links::list_links()
+            .await?
+            .into_iter()
+            .map(|link| (link.index, LiveLink::from(link)))
+            .collect();
+
+        let mut addresses: BTreeMap<u32, BTreeSet<String>> = BTreeMap::new();
+        let mut dump = handle.address().get().execute();
+        while let Some(msg) = dump.try_next().await? {
+            if let Some(address) = address_cidr(&msg) {
+                addresses
+                    .entry(msg.header.index)
+                    .or_default()
+                    .insert(address);
+            }
+        }
+
+        let mut view = self.view.write().await;
+        *view = LiveView {
+            generation: view.generation + 1,
+            links,
+            addresses,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtnl_packet::link::nlas::Nla as LinkNla;
+    use rtnl_packet::{LinkMessage, RuleMessage};
+
+    fn link(index: u32, name: &str, mtu: u32) -> LinkMessage {
+        let mut msg = LinkMessage::default();
+        msg.header.index = index;
+        msg.nlas.push(LinkNla::IfName(name.to_string()));
+        msg.nlas.push(LinkNla::Mtu(mtu));
+        msg
+    }
+
+    fn address(index: u32, ip: [u8; 4], prefix: u8) -> AddressMessage {
+        let mut msg = AddressMessage::default();
+        msg.header.index = index;
+        msg.header.prefix_len = prefix;
+        msg.nlas.push(AddressNla::Local(ip.to_vec()));
+        msg
+    }
+
+    fn event_types(events: &[Box<dyn Event>]) -> Vec<&'static str> {
+        events.iter().map(|e| e.event_type()).collect()
+    }
+
+    #[test]
+    fn test_link_lifecycle_events() {
+        let mut view = LiveView::default();
+
+        let events = view.apply(RtnlMessage::NewLink(link(7, "vlan100", 1500)));
+        assert_eq!(event_types(&events), ["LinkAdded"]);
+        assert_eq!(view.generation, 1);
+
+        // Repeated notification without a visible change is not an event
+        assert!(view
+            .apply(RtnlMessage::NewLink(link(7, "vlan100", 1500)))
+            .is_empty());
+        assert_eq!(view.generation, 1);
+
+        let events = view.apply(RtnlMessage::NewLink(link(7, "vlan100", 9000)));
+        assert_eq!(event_types(&events), ["LinkChanged"]);
+        assert_eq!(events[0].to_json()["mtu"], 9000);
+
+        let events = view.apply(RtnlMessage::DelLink(link(7, "vlan100", 9000)));
+        assert_eq!(event_types(&events), ["LinkRemoved"]);
+        assert!(view.links.is_empty());
+        assert_eq!(view.generation, 3);
+    }
+
+    #[test]
+    fn test_address_events() {
+        let mut view = LiveView::default();
+        view.apply(RtnlMessage::NewLink(link(3, "eth0", 1500)));
+
+        let events = view.apply(RtnlMessage::NewAddress(address(3, [192, 0, 2, 10], 24)));
+        assert_eq!(event_types(&events), ["AddressAdded"]);
+        assert_eq!(events[0].to_json()["ifname"], "eth0");
+        assert_eq!(view.addresses_of("eth0"), ["192.0.2.10/24"]);
+
+        let events = view.apply(RtnlMessage::DelAddress(address(3, [192, 0, 2, 10], 24)));
+        assert_eq!(event_types(&events), ["AddressRemoved"]);
+        assert_eq!(events[0].to_json()["address"], "192.0.2.10/24");
+        assert!(view.addresses_of("eth0").is_empty());
+
+        // Removing an address the view never saw is silent
+        assert!(view
+            .apply(RtnlMessage::DelAddress(address(3, [192, 0, 2, 11], 24)))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rule_events_bump_generation() {
+        let mut view = LiveView::default();
+        let mut rule = RuleMessage::default();
+        rule.header.family = rtnl_packet::AF_INET as u8;
+        rule.header.table = 100;
+
+        let events = view.apply(RtnlMessage::NewRule(rule.clone()));
+        assert_eq!(event_types(&events), ["RuleAdded"]);
+        assert_eq!(events[0].to_json()["table"], 100);
+        assert_eq!(view.generation, 1);
+
+        let events = view.apply(RtnlMessage::DelRule(rule));
+        assert_eq!(event_types(&events), ["RuleRemoved"]);
+        assert_eq!(view.generation, 2);
+    }
+}
@@ -0,0 +1,1004 @@
+//! Rtnetlink route helpers - static routes, routing tables and policy rules
+//!
+//! Routes and rules installed here carry the [`RTPROT_OPDBUS`] protocol number,
+//! which is how the net plugin tells its own entries apart from routes learned
+//! via DHCP/RA or installed by the kernel. Prune operations only ever touch
+//! entries with that protocol. Table names live in an rt_tables.d drop-in so
+//! `ip route show table <name>` works for operators too.
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{StreamExt, TryStreamExt};
+use rtnetlink::{new_connection, Handle, IpVersion};
+use rtnl_packet::route::Nla as RouteNla;
+use rtnl_packet::rule::Nla as RuleNla;
+use rtnl_packet::{RouteMessage, RuleMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Routing protocol number stamped on routes and rules op-dbus installs
+pub const RTPROT_OPDBUS: u8 = 222;
+
+/// Protocol of the privacy plugin's chain routes and rules, kept apart from
+/// [`RTPROT_OPDBUS`] so the net plugin's prune never touches them
+pub const RTPROT_PRIVACY: u8 = 223;
+
+/// Table-name drop-in owned by op-dbus
+pub const RT_TABLES_DROPIN: &str = "/etc/iproute2/rt_tables.d/op-dbus.conf";
+
+/// System table-name database (read-only for us)
+const RT_TABLES_FILE: &str = "/etc/iproute2/rt_tables";
+
+const RT_TABLE_MAIN: u32 = rtnl_packet::RT_TABLE_MAIN as u32;
+
+/// Route scope (`ip route ... scope`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteScope {
+    Global,
+    Site,
+    Link,
+    Host,
+}
+
+impl RouteScope {
+    fn as_u8(self) -> u8 {
+        match self {
+            RouteScope::Global => rtnl_packet::RT_SCOPE_UNIVERSE,
+            RouteScope::Site => rtnl_packet::RT_SCOPE_SITE,
+            RouteScope::Link => rtnl_packet::RT_SCOPE_LINK,
+            RouteScope::Host => rtnl_packet::RT_SCOPE_HOST,
+        }
+    }
+
+    fn from_u8(scope: u8) -> Self {
+        match scope {
+            rtnl_packet::RT_SCOPE_SITE => RouteScope::Site,
+            rtnl_packet::RT_SCOPE_LINK => RouteScope::Link,
+            rtnl_packet::RT_SCOPE_HOST => RouteScope::Host,
+            _ => RouteScope::Global,
+        }
+    }
+}
+
+/// Route type (`ip route add <type> ...`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteType {
+    #[default]
+    Unicast,
+    Blackhole,
+    Unreachable,
+    Prohibit,
+}
+
+impl RouteType {
+    fn as_u8(self) -> u8 {
+        match self {
+            RouteType::Unicast => rtnl_packet::RTN_UNICAST,
+            RouteType::Blackhole => rtnl_packet::RTN_BLACKHOLE,
+            RouteType::Unreachable => rtnl_packet::RTN_UNREACHABLE,
+            RouteType::Prohibit => rtnl_packet::RTN_PROHIBIT,
+        }
+    }
+
+    fn from_u8(kind: u8) -> Option<Self> {
+        Some(match kind {
+            rtnl_packet::RTN_UNICAST => RouteType::Unicast,
+            rtnl_packet::RTN_BLACKHOLE => RouteType::Blackhole,
+            rtnl_packet::RTN_UNREACHABLE => RouteType::Unreachable,
+            rtnl_packet::RTN_PROHIBIT => RouteType::Prohibit,
+            _ => return None,
+        })
+    }
+}
+
+/// Declarative static route
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteSpec {
+    /// CIDR destination or "default" (family taken from the gateway, IPv4 otherwise)
+    pub destination: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<IpAddr>,
+    /// Output interface
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dev: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metric: Option<u32>,
+    /// Table name or number; defaults to "main"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<RouteScope>,
+    /// Preferred source address (`src`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpAddr>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub route_type: Option<RouteType>,
+}
+
+/// Declarative policy rule (`ip rule`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleSpec {
+    /// Rule preference; the kernel picks one when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u32>,
+    /// Source selector in CIDR notation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Destination selector in CIDR notation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iif: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oif: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fwmark: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fwmask: Option<u32>,
+    /// Lookup table name or number
+    pub table: String,
+    /// Address family when no selector implies one
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ipv6: bool,
+}
+
+/// Resolves table names to ids and back
+#[derive(Debug, Clone, Default)]
+pub struct TableNames {
+    by_name: HashMap<String, u32>,
+    by_id: HashMap<u32, String>,
+}
+
+impl TableNames {
+    /// Builtin tables, the system database and the op-dbus drop-in, overlaid with `declared`
+    pub fn load(declared: &BTreeMap<String, u32>) -> Self {
+        let mut names = Self::default();
+        for (name, id) in [("default", 253), ("main", 254), ("local", 255)] {
+            names.insert(name, id);
+        }
+        for path in [RT_TABLES_FILE, RT_TABLES_DROPIN] {
+            if let Ok(content) = std::fs::read_to_string(path) {
+                for (name, id) in parse_rt_tables(&content) {
+                    names.insert(&name, id);
+                }
+            }
+        }
+        for (name, id) in declared {
+            names.insert(name, *id);
+        }
+        names
+    }
+
+    fn insert(&mut self, name: &str, id: u32) {
+        self.by_name.insert(name.to_string(), id);
+        self.by_id.insert(id, name.to_string());
+    }
+
+    pub fn resolve(&self, table: &str) -> Result<u32> {
+        if let Ok(id) = table.parse() {
+            return Ok(id);
+        }
+        self.by_name
+            .get(table)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown routing table '{}'", table))
+    }
+
+    pub fn name(&self, id: u32) -> String {
+        self.by_id
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+}
+
+/// Parse `id name` lines of an rt_tables file
+fn parse_rt_tables(content: &str) -> Vec<(String, u32)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let id = parts.next()?.parse().ok()?;
+            let name = parts.next()?;
+            Some((name.to_string(), id))
+        })
+        .collect()
+}
+
+/// Tables currently declared in the op-dbus drop-in
+pub fn read_table_dropin() -> BTreeMap<String, u32> {
+    std::fs::read_to_string(RT_TABLES_DROPIN)
+        .map(|content| parse_rt_tables(&content).into_iter().collect())
+        .unwrap_or_default()
+}
+
+/// Rewrite the op-dbus table-name drop-in
+pub fn write_table_dropin(tables: &BTreeMap<String, u32>) -> Result<()> {
+    if tables.is_empty() {
+        if std::path::Path::new(RT_TABLES_DROPIN).exists() {
+            std::fs::remove_file(RT_TABLES_DROPIN)
+                .context(format!("Failed to remove {}", RT_TABLES_DROPIN))?;
+        }
+        return Ok(());
+    }
+
+    let mut content = String::from("# Managed by op-dbus. Do not edit manually.\n");
+    for (name, id) in tables {
+        if matches!(id, 0 | 253..=255) {
+            bail!("Table id {} for '{}' is reserved", id, name);
+        }
+        content.push_str(&format!("{}\t{}\n", id, name));
+    }
+
+    if let Some(dir) = std::path::Path::new(RT_TABLES_DROPIN).parent() {
+        std::fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(RT_TABLES_DROPIN, content)
+        .context(format!("Failed to write {}", RT_TABLES_DROPIN))
+}
+
+/// Parse "10.0.0.0/8", "2001:db8::/32", a bare address (host route) or "default"
+pub fn parse_prefix(value: &str, ipv6_hint: bool) -> Result<(IpAddr, u8)> {
+    if value == "default" {
+        return Ok(if ipv6_hint {
+            (IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0)
+        } else {
+            (IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)
+        });
+    }
+
+    let (addr, prefix) = match value.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (value, None),
+    };
+    let addr: IpAddr = addr
+        .parse()
+        .context(format!("Invalid address in '{}'", value))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(p) => p
+            .parse()
+            .ok()
+            .filter(|p| *p <= max)
+            .context(format!("Invalid prefix length in '{}'", value))?,
+        None => max,
+    };
+    Ok((addr, prefix))
+}
+
+fn format_prefix(addr: IpAddr, prefix: u8) -> String {
+    if prefix == 0 && addr.is_unspecified() {
+        "default".to_string()
+    } else {
+        format!("{}/{}", addr, prefix)
+    }
+}
+
+/// A route as the kernel sees it (numeric table, resolved scope)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub destination: IpAddr,
+    pub prefix: u8,
+    pub gateway: Option<IpAddr>,
+    pub dev: Option<String>,
+    pub metric: Option<u32>,
+    pub table: u32,
+    pub scope: RouteScope,
+    pub source: Option<IpAddr>,
+    pub route_type: RouteType,
+    pub protocol: u8,
+}
+
+impl Route {
+    /// Resolve a declaration against the table names
+    pub fn from_spec(spec: &RouteSpec, tables: &TableNames) -> Result<Self> {
+        let ipv6_hint = spec.gateway.is_some_and(|gw| gw.is_ipv6());
+        let (destination, prefix) = parse_prefix(&spec.destination, ipv6_hint)?;
+        if let Some(gw) = spec.gateway {
+            if gw.is_ipv4() != destination.is_ipv4() {
+                bail!(
+                    "Gateway {} does not match the family of {}",
+                    gw,
+                    spec.destination
+                );
+            }
+        }
+
+        let route_type = spec.route_type.unwrap_or_default();
+        if route_type == RouteType::Unicast && spec.gateway.is_none() && spec.dev.is_none() {
+            bail!(
+                "Route {} needs a gateway or dev (or a blackhole/unreachable/prohibit type)",
+                spec.destination
+            );
+        }
+
+        // Same default as iproute2: directly connected unicast routes are link-scoped.
+        // IPv6 has no route scopes, the kernel reports them all as universe.
+        let scope = spec.scope.unwrap_or(
+            if destination.is_ipv4() && route_type == RouteType::Unicast && spec.gateway.is_none() {
+                RouteScope::Link
+            } else {
+                RouteScope::Global
+            },
+        );
+
+        Ok(Self {
+            destination,
+            prefix,
+            gateway: spec.gateway,
+            dev: spec.dev.clone(),
+            metric: spec.metric,
+            table: tables.resolve(spec.table.as_deref().unwrap_or("main"))?,
+            scope,
+            source: spec.source,
+            route_type,
+            protocol: RTPROT_OPDBUS,
+        })
+    }
+
+    /// Back to the declarative form (table ids become names where known)
+    pub fn to_spec(&self, tables: &TableNames) -> RouteSpec {
+        RouteSpec {
+            destination: format_prefix(self.destination, self.prefix),
+            gateway: self.gateway,
+            dev: self.dev.clone(),
+            metric: self.metric,
+            table: (self.table != RT_TABLE_MAIN).then(|| tables.name(self.table)),
+            scope: Some(self.scope),
+            source: self.source,
+            route_type: (self.route_type != RouteType::Unicast).then_some(self.route_type),
+        }
+    }
+
+    /// Kernel identity of the route: table, destination and metric
+    pub fn key(&self) -> String {
+        let mut key = format!(
+            "route:{}:{}",
+            self.table,
+            format_prefix(self.destination, self.prefix)
+        );
+        if let Some(metric) = self.metric {
+            key.push_str(&format!(":{}", metric));
+        }
+        if self.destination.is_ipv6() && self.prefix == 0 {
+            key.push_str(":v6");
+        }
+        key
+    }
+
+    pub fn is_owned(&self) -> bool {
+        self.protocol == RTPROT_OPDBUS
+    }
+
+    /// Whether the installed route carries every attribute of this one.
+    /// Kernel-assigned metrics (IPv6 defaults to 1024) are ignored when unset here,
+    /// and so is the scope of IPv6 routes, which the kernel doesn't keep.
+    pub fn satisfied_by(&self, current: &Route) -> bool {
+        self.destination == current.destination
+            && self.prefix == current.prefix
+            && self.table == current.table
+            && self.gateway == current.gateway
+            && self.dev == current.dev
+            && (self.destination.is_ipv6() || self.scope == current.scope)
+            && self.source == current.source
+            && self.route_type == current.route_type
+            && self.metric.is_none_or(|m| current.metric == Some(m))
+    }
+}
+
+/// A policy rule as the kernel sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub ipv6: bool,
+    pub priority: Option<u32>,
+    pub from: Option<(IpAddr, u8)>,
+    pub to: Option<(IpAddr, u8)>,
+    pub iif: Option<String>,
+    pub oif: Option<String>,
+    pub fwmark: Option<u32>,
+    pub fwmask: Option<u32>,
+    pub table: u32,
+    pub protocol: u8,
+}
+
+impl Rule {
+    pub fn from_spec(spec: &RuleSpec, tables: &TableNames) -> Result<Self> {
+        let from = spec
+            .from
+            .as_deref()
+            .map(|v| parse_prefix(v, spec.ipv6))
+            .transpose()?;
+        let to = spec
+            .to
+            .as_deref()
+            .map(|v| parse_prefix(v, spec.ipv6))
+            .transpose()?;
+        let ipv6 = match (from, to) {
+            (Some((a, _)), Some((b, _))) if a.is_ipv6() != b.is_ipv6() => {
+                bail!("Rule selectors 'from' and 'to' mix address families")
+            }
+            (Some((a, _)), _) | (None, Some((a, _))) => a.is_ipv6(),
+            (None, None) => spec.ipv6,
+        };
+
+        Ok(Self {
+            ipv6,
+            priority: spec.priority,
+            from,
+            to,
+            iif: spec.iif.clone(),
+            oif: spec.oif.clone(),
+            fwmark: spec.fwmark,
+            fwmask: full_mask(spec.fwmark, spec.fwmask),
+            table: tables.resolve(&spec.table)?,
+            protocol: RTPROT_OPDBUS,
+        })
+    }
+
+    pub fn to_spec(&self, tables: &TableNames) -> RuleSpec {
+        RuleSpec {
+            priority: self.priority,
+            from: self.from.map(|(a, p)| format!("{}/{}", a, p)),
+            to: self.to.map(|(a, p)| format!("{}/{}", a, p)),
+            iif: self.iif.clone(),
+            oif: self.oif.clone(),
+            fwmark: self.fwmark,
+            fwmask: self.fwmask.filter(|mask| *mask != u32::MAX),
+            table: tables.name(self.table),
+            ipv6: self.ipv6,
+        }
+    }
+
+    /// Identity used in diffs; rules have no kernel-side key besides their content
+    pub fn key(&self) -> String {
+        let selector = |sel: &Option<(IpAddr, u8)>| {
+            sel.map(|(a, p)| format!("{}/{}", a, p))
+                .unwrap_or_else(|| "all".to_string())
+        };
+        format!(
+            "rule:{}:{}:from={}:to={}:iif={}:oif={}:fwmark={}/{}:table={}",
+            if self.ipv6 { "v6" } else { "v4" },
+            self.priority.map_or("auto".to_string(), |p| p.to_string()),
+            selector(&self.from),
+            selector(&self.to),
+            self.iif.as_deref().unwrap_or("any"),
+            self.oif.as_deref().unwrap_or("any"),
+            self.fwmark.unwrap_or(0),
+            self.fwmask.unwrap_or(0),
+            self.table
+        )
+    }
+
+    pub fn is_owned(&self) -> bool {
+        self.protocol == RTPROT_OPDBUS
+    }
+
+    /// Match ignoring a kernel-assigned priority when none was declared
+    pub fn satisfied_by(&self, current: &Rule) -> bool {
+        let mut current = current.clone();
+        if self.priority.is_none() {
+            current.priority = None;
+        }
+        current.protocol = self.protocol;
+        *self == current
+    }
+}
+
+/// A fwmark without a mask matches all bits, which is how the kernel dumps it
+fn full_mask(fwmark: Option<u32>, fwmask: Option<u32>) -> Option<u32> {
+    fwmask.or(fwmark.map(|_| u32::MAX))
+}
+
+fn connect() -> Result<Handle> {
+    let (connection, handle, _) = new_connection().context("Failed to open rtnetlink socket")?;
+    tokio::spawn(connection);
+    Ok(handle)
+}
+
+async fn link_names(handle: &Handle) -> Result<HashMap<u32, String>> {
+    let mut names = HashMap::new();
+    let mut links = handle.link().get().execute();
+    while let Some(msg) = links.try_next().await? {
+        for nla in &msg.nlas {
+            if let rtnl_packet::link::nlas::Nla::IfName(name) = nla {
+                names.insert(msg.header.index, name.clone());
+            }
+        }
+    }
+    Ok(names)
+}
+
+async fn link_index(handle: &Handle, name: &str) -> Result<u32> {
+    let mut links = handle.link().get().match_name(name.to_string()).execute();
+    let link = links
+        .try_next()
+        .await?
+        .context(format!("Interface '{}' not found", name))?;
+    Ok(link.header.index)
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_route(msg: &RouteMessage, names: &HashMap<u32, String>) -> Option<Route> {
+    let route_type = RouteType::from_u8(msg.header.kind)?;
+    let ipv6 = msg.header.address_family == rtnl_packet::AF_INET6 as u8;
+    let mut route = Route {
+        destination: if ipv6 {
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        } else {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        },
+        prefix: msg.header.destination_prefix_length,
+        gateway: None,
+        dev: None,
+        metric: None,
+        table: msg.header.table as u32,
+        scope: RouteScope::from_u8(msg.header.scope),
+        source: None,
+        route_type,
+        protocol: msg.header.protocol,
+    };
+
+    for nla in &msg.nlas {
+        match nla {
+            RouteNla::Destination(bytes) => {
+                if let Some(addr) = ip_from_bytes(bytes) {
+                    route.destination = addr;
+                }
+            }
+            RouteNla::Gateway(bytes) => route.gateway = ip_from_bytes(bytes),
+            RouteNla::PrefSource(bytes) => route.source = ip_from_bytes(bytes),
+            RouteNla::Oif(index) => route.dev = names.get(index).cloned(),
+            RouteNla::Priority(metric) => route.metric = Some(*metric),
+            RouteNla::Table(table) => route.table = *table,
+            _ => {}
+        }
+    }
+
+    Some(route)
+}
+
+pub(crate) fn parse_rule(msg: &RuleMessage) -> Rule {
+    let ipv6 = msg.header.family == rtnl_packet::AF_INET6 as u8;
+    let mut rule = Rule {
+        ipv6,
+        priority: None,
+        from: None,
+        to: None,
+        iif: None,
+        oif: None,
+        fwmark: None,
+        fwmask: None,
+        table: msg.header.table as u32,
+        protocol: 0,
+    };
+
+    for nla in &msg.nlas {
+        match nla {
+            RuleNla::Priority(p) => rule.priority = Some(*p),
+            RuleNla::Source(bytes) => {
+                rule.from = ip_from_bytes(bytes).map(|a| (a, msg.header.src_len))
+            }
+            RuleNla::Destination(bytes) => {
+                rule.to = ip_from_bytes(bytes).map(|a| (a, msg.header.dst_len))
+            }
+            RuleNla::Iifname(name) => rule.iif = Some(name.clone()),
+            RuleNla::OifName(name) => rule.oif = Some(name.clone()),
+            RuleNla::FwMark(mark) => rule.fwmark = Some(*mark),
+            RuleNla::FwMask(mask) => rule.fwmask = Some(*mask),
+            RuleNla::Table(table) => rule.table = *table,
+            RuleNla::Protocol(proto) => rule.protocol = *proto,
+            _ => {}
+        }
+    }
+    rule.fwmask = full_mask(rule.fwmark, rule.fwmask);
+
+    rule
+}
+
+/// List unicast/blackhole/unreachable/prohibit routes of both families in all tables
+/// except `local`
+pub async fn list_routes() -> Result<Vec<Route>> {
+    let handle = connect()?;
+    let names = link_names(&handle).await?;
+
+    let mut routes = Vec::new();
+    for version in [IpVersion::V4, IpVersion::V6] {
+        let mut stream = handle.route().get(version).execute();
+        while let Some(msg) = stream.try_next().await? {
+            if let Some(route) = parse_route(&msg, &names) {
+                if route.table != rtnl_packet::RT_TABLE_LOCAL as u32 {
+                    routes.push(route);
+                }
+            }
+        }
+    }
+    Ok(routes)
+}
+
+/// List policy rules of both families
+pub async fn list_rules() -> Result<Vec<Rule>> {
+    let handle = connect()?;
+    let mut rules = Vec::new();
+    for version in [IpVersion::V4, IpVersion::V6] {
+        let mut stream = handle.rule().get(version).execute();
+        while let Some(msg) = stream.try_next().await? {
+            rules.push(parse_rule(&msg));
+        }
+    }
+    Ok(rules)
+}
+
+/// Install (or replace) a route
+pub async fn add_route(route: &Route) -> Result<()> {
+    let handle = connect()?;
+    let oif = match &route.dev {
+        Some(dev) => Some(link_index(&handle, dev).await?),
+        None => None,
+    };
+
+    let mut request = handle
+        .route()
+        .add()
+        .replace()
+        .table_id(route.table)
+        .protocol(route.protocol)
+        .scope(route.scope.as_u8())
+        .kind(route.route_type.as_u8());
+    if let Some(oif) = oif {
+        request = request.output_interface(oif);
+    }
+    if let Some(metric) = route.metric {
+        request.message_mut().nlas.push(RouteNla::Priority(metric));
+    }
+
+    let result = match route.destination {
+        IpAddr::V4(dest) => {
+            let mut request = request.v4().destination_prefix(dest, route.prefix);
+            if let Some(IpAddr::V4(gw)) = route.gateway {
+                request = request.gateway(gw);
+            }
+            if let Some(IpAddr::V4(src)) = route.source {
+                request = request.pref_source(src);
+            }
+            request.execute().await
+        }
+        IpAddr::V6(dest) => {
+            let mut request = request.v6().destination_prefix(dest, route.prefix);
+            if let Some(IpAddr::V6(gw)) = route.gateway {
+                request = request.gateway(gw);
+            }
+            if let Some(IpAddr::V6(src)) = route.source {
+                request = request.pref_source(src);
+            }
+            request.execute().await
+        }
+    };
+
+    result.map_err(|e| {
+        anyhow!(
+            "Failed to add route {} (table {}): {}",
+            format_prefix(route.destination, route.prefix),
+            route.table,
+            e
+        )
+    })
+}
+
+/// Remove a route matching the given key from the kernel
+pub async fn delete_route(route: &Route) -> Result<()> {
+    let handle = connect()?;
+    let names = link_names(&handle).await?;
+    let version = if route.destination.is_ipv6() {
+        IpVersion::V6
+    } else {
+        IpVersion::V4
+    };
+
+    let mut stream = handle.route().get(version).execute();
+    while let Some(msg) = stream.try_next().await? {
+        if parse_route(&msg, &names).is_some_and(|r| r.key() == route.key()) {
+            handle
+                .route()
+                .del(msg)
+                .execute()
+                .await
+                .map_err(|e| anyhow!("Failed to delete route {}: {}", route.key(), e))?;
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Ask the kernel how a packet to `destination` would be routed
+/// (`ip route get <destination> [from <source>] [iif <iif>]`), policy rules
+/// included. With `iif` the packet is looked up as if it arrived there.
+pub async fn lookup_route(
+    destination: IpAddr,
+    source: Option<IpAddr>,
+    iif: Option<&str>,
+) -> Result<Route> {
+    use netlink_packet_core::{NetlinkMessage, NetlinkPayload, NLM_F_REQUEST};
+    use rtnl_packet::RtnlMessage;
+
+    let mut handle = connect()?;
+    let names = link_names(&handle).await?;
+
+    let full = |addr: &IpAddr| if addr.is_ipv6() { 128 } else { 32 };
+    let octets = |addr: &IpAddr| match addr {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    };
+    let mut message = RouteMessage::default();
+    message.header.address_family = if destination.is_ipv6() {
+        rtnl_packet::AF_INET6
+    } else {
+        rtnl_packet::AF_INET
+    } as u8;
+    message.header.destination_prefix_length = full(&destination);
+    message
+        .nlas
+        .push(RouteNla::Destination(octets(&destination)));
+    if let Some(source) = &source {
+        message.header.source_prefix_length = full(source);
+        message.nlas.push(RouteNla::Source(octets(source)));
+    }
+    if let Some(iif) = iif {
+        message
+            .nlas
+            .push(RouteNla::Iif(link_index(&handle, iif).await?));
+    }
+
+    let mut request = NetlinkMessage::from(RtnlMessage::GetRoute(message));
+    request.header.flags = NLM_F_REQUEST;
+    let mut response = handle.request(request)?;
+    while let Some(reply) = response.next().await {
+        match reply.payload {
+            NetlinkPayload::InnerMessage(RtnlMessage::NewRoute(route)) => {
+                return parse_route(&route, &names)
+                    .ok_or_else(|| anyhow!("Unexpected route type for {}", destination));
+            }
+            NetlinkPayload::Error(e) => {
+                bail!("No route to {}: {}", destination, e.to_io());
+            }
+            _ => {}
+        }
+    }
+    bail!("No route to {}", destination)
+}
+
+/// Install a policy rule
+pub async fn add_rule(rule: &Rule) -> Result<()> {
+    let handle = connect()?;
+    let mut request = handle.rule().add().table_id(rule.table);
+    if let Some(priority) = rule.priority {
+        request = request.priority(priority);
+    }
+    if let Some(iif) = &rule.iif {
+        request = request.input_interface(iif.clone());
+    }
+    if let Some(oif) = &rule.oif {
+        request = request.output_interface(oif.clone());
+    }
+    {
+        let message = request.message_mut();
+        message.header.action = rtnl_packet::FR_ACT_TO_TBL;
+        message.nlas.push(RuleNla::Protocol(rule.protocol));
+        if let Some(mark) = rule.fwmark {
+            message.nlas.push(RuleNla::FwMark(mark));
+        }
+        if let Some(mask) = rule.fwmask {
+            message.nlas.push(RuleNla::FwMask(mask));
+        }
+    }
+
+    let result = if rule.ipv6 {
+        let mut request = request.v6();
+        if let Some((IpAddr::V6(addr), len)) = rule.from {
+            request = request.source_prefix(addr, len);
+        }
+        if let Some((IpAddr::V6(addr), len)) = rule.to {
+            request = request.destination_prefix(addr, len);
+        }
+        request.execute().await
+    } else {
+        let mut request = request.v4();
+        if let Some((IpAddr::V4(addr), len)) = rule.from {
+            request = request.source_prefix(addr, len);
+        }
+        if let Some((IpAddr::V4(addr), len)) = rule.to {
+            request = request.destination_prefix(addr, len);
+        }
+        request.execute().await
+    };
+
+    result.map_err(|e| anyhow!("Failed to add {}: {}", rule.key(), e))
+}
+
+/// Remove a policy rule matching the given one
+pub async fn delete_rule(rule: &Rule) -> Result<()> {
+    let handle = connect()?;
+    let version = if rule.ipv6 {
+        IpVersion::V6
+    } else {
+        IpVersion::V4
+    };
+
+    let mut stream = handle.rule().get(version).execute();
+    while let Some(msg) = stream.try_next().await? {
+        if parse_rule(&msg).key() == rule.key() {
+            handle
+                .rule()
+                .del(msg)
+                .execute()
+                .await
+                .map_err(|e| anyhow!("Failed to delete {}: {}", rule.key(), e))?;
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tables() -> TableNames {
+        let mut declared = BTreeMap::new();
+        declared.insert("wg-egress".to_string(), 100);
+        TableNames::load(&declared)
+    }
+
+    #[test]
+    fn test_parse_rt_tables() {
+        let parsed = parse_rt_tables("# comment\n255\tlocal\n\n100 wg-egress\nbogus\n");
+        assert_eq!(
+            parsed,
+            vec![("local".to_string(), 255), ("wg-egress".to_string(), 100)]
+        );
+    }
+
+    #[test]
+    fn test_route_spec_roundtrip() {
+        let tables = tables();
+        let spec: RouteSpec = serde_json::from_value(serde_json::json!({
+            "destination": "default",
+            "gateway": "10.66.0.1",
+            "dev": "wg0",
+            "table": "wg-egress"
+        }))
+        .unwrap();
+
+        let route = Route::from_spec(&spec, &tables).unwrap();
+        assert_eq!(route.table, 100);
+        assert_eq!(route.scope, RouteScope::Global);
+        assert_eq!(route.key(), "route:100:default");
+
+        let back = route.to_spec(&tables);
+        assert_eq!(back.table.as_deref(), Some("wg-egress"));
+        assert_eq!(Route::from_spec(&back, &tables).unwrap(), route);
+    }
+
+    #[test]
+    fn test_route_defaults_and_validation() {
+        let tables = tables();
+        let connected = RouteSpec {
+            destination: "192.168.50.0/24".to_string(),
+            gateway: None,
+            dev: Some("br0".to_string()),
+            metric: None,
+            table: None,
+            scope: None,
+            source: None,
+            route_type: None,
+        };
+        let route = Route::from_spec(&connected, &tables).unwrap();
+        assert_eq!(route.scope, RouteScope::Link);
+        assert_eq!(route.table, RT_TABLE_MAIN);
+
+        // Installed copy with a kernel-assigned metric still satisfies the declaration
+        let mut installed = route.clone();
+        installed.metric = Some(1024);
+        assert!(route.satisfied_by(&installed));
+
+        let dangling = RouteSpec {
+            dev: None,
+            ..connected.clone()
+        };
+        assert!(Route::from_spec(&dangling, &tables).is_err());
+
+        let unknown_table = RouteSpec {
+            table: Some("nope".to_string()),
+            ..connected.clone()
+        };
+        assert!(Route::from_spec(&unknown_table, &tables).is_err());
+
+        // IPv6 routes come back from the kernel with universe scope
+        let connected_v6 = RouteSpec {
+            destination: "fd00:50::/64".to_string(),
+            ..connected
+        };
+        let route = Route::from_spec(&connected_v6, &tables).unwrap();
+        assert_eq!(route.scope, RouteScope::Global);
+        let installed = Route {
+            scope: RouteScope::Link,
+            ..route.clone()
+        };
+        assert!(route.satisfied_by(&installed));
+    }
+
+    #[test]
+    fn test_rule_family_and_match() {
+        let tables = tables();
+        let spec = RuleSpec {
+            priority: None,
+            from: Some("fd00:66::/64".to_string()),
+            to: None,
+            iif: None,
+            oif: None,
+            fwmark: Some(0x66),
+            fwmask: None,
+            table: "wg-egress".to_string(),
+            ipv6: false,
+        };
+        let rule = Rule::from_spec(&spec, &tables).unwrap();
+        assert!(rule.ipv6);
+        assert_eq!(rule.fwmask, Some(u32::MAX));
+        assert_eq!(rule.to_spec(&tables).fwmask, None);
+
+        let mut installed = rule.clone();
+        installed.priority = Some(32765);
+        installed.protocol = 0;
+        assert!(rule.satisfied_by(&installed));
+
+        let mixed = RuleSpec {
+            to: Some("10.0.0.0/8".to_string()),
+            ..spec
+        };
+        assert!(Rule::from_spec(&mixed, &tables).is_err());
+    }
+
+    #[test]
+    fn test_parse_kernel_rule() {
+        // `ip rule add fwmark 0x66 table 100 pref 100` as the kernel dumps it
+        let mut msg = RuleMessage::default();
+        msg.header.family = rtnl_packet::AF_INET as u8;
+        msg.header.table = 100;
+        msg.nlas = vec![
+            RuleNla::Table(100),
+            RuleNla::Priority(100),
+            RuleNla::FwMark(0x66),
+            RuleNla::FwMask(u32::MAX),
+            RuleNla::Protocol(RTPROT_OPDBUS),
+        ];
+        let installed = parse_rule(&msg);
+
+        let declared = Rule::from_spec(
+            &RuleSpec {
+                priority: Some(100),
+                from: None,
+                to: None,
+                iif: None,
+                oif: None,
+                fwmark: Some(0x66),
+                fwmask: None,
+                table: "wg-egress".to_string(),
+                ipv6: false,
+            },
+            &tables(),
+        )
+        .unwrap();
+        assert!(declared.satisfied_by(&installed));
+        assert_eq!(declared.key(), installed.key());
+    }
+}
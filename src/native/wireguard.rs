@@ -0,0 +1,725 @@
+//! Native WireGuard client - the "wireguard" generic netlink family, no wg binary
+//!
+//! Reads device configuration and per-peer statistics with WG_CMD_GET_DEVICE
+//! and writes it with WG_CMD_SET_DEVICE. The family id is resolved through
+//! the generic netlink controller on connect. Unlike nfnetlink, generic
+//! netlink integers are in host byte order.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use netlink_sys::{protocols::NETLINK_GENERIC, Socket, SocketAddr};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr as InetAddr};
+use std::str::FromStr;
+
+const NLMSG_HDRLEN: usize = 16;
+const GENLMSG_HDRLEN: usize = 4;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+
+const NLA_F_NESTED: u16 = 0x8000;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const WG_GENL_NAME: &str = "wireguard";
+const WG_GENL_VERSION: u8 = 1;
+const WG_CMD_GET_DEVICE: u8 = 0;
+const WG_CMD_SET_DEVICE: u8 = 1;
+
+/// Length of private, public and preshared keys
+pub const KEY_LEN: usize = 32;
+
+mod device_attr {
+    pub const IFNAME: u16 = 2;
+    pub const PRIVATE_KEY: u16 = 3;
+    pub const PUBLIC_KEY: u16 = 4;
+    pub const LISTEN_PORT: u16 = 6;
+    pub const FWMARK: u16 = 7;
+    pub const PEERS: u16 = 8;
+}
+
+mod peer_attr {
+    pub const PUBLIC_KEY: u16 = 1;
+    pub const PRESHARED_KEY: u16 = 2;
+    pub const FLAGS: u16 = 3;
+    pub const ENDPOINT: u16 = 4;
+    pub const PERSISTENT_KEEPALIVE_INTERVAL: u16 = 5;
+    pub const LAST_HANDSHAKE_TIME: u16 = 6;
+    pub const RX_BYTES: u16 = 7;
+    pub const TX_BYTES: u16 = 8;
+    pub const ALLOWEDIPS: u16 = 9;
+    /// WGPEER_F_REMOVE_ME
+    pub const F_REMOVE_ME: u32 = 0x1;
+    /// WGPEER_F_REPLACE_ALLOWEDIPS
+    pub const F_REPLACE_ALLOWEDIPS: u32 = 0x2;
+}
+
+mod allowedip_attr {
+    pub const FAMILY: u16 = 1;
+    pub const IPADDR: u16 = 2;
+    pub const CIDR_MASK: u16 = 3;
+}
+
+/// A Curve25519 key, base64 encoded the way wg(8) prints it
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Key(pub [u8; KEY_LEN]);
+
+impl Key {
+    /// The all-zero key, which clears a preshared key
+    pub const ZERO: Key = Key([0; KEY_LEN]);
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; KEY_LEN]
+    }
+
+    fn from_slice(bytes: &[u8]) -> Option<Self> {
+        Some(Self(bytes.try_into().ok()?))
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = BASE64
+            .decode(s.trim())
+            .map_err(|_| anyhow!("WireGuard key is not valid base64"))?;
+        Self::from_slice(&bytes).ok_or_else(|| {
+            anyhow!(
+                "WireGuard key must be {} bytes, not {}",
+                KEY_LEN,
+                bytes.len()
+            )
+        })
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&BASE64.encode(self.0))
+    }
+}
+
+/// Keys may be private; never print them in debug output
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// An allowed-ips entry, "10.0.0.0/24"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AllowedIp {
+    pub address: IpAddr,
+    pub cidr: u8,
+}
+
+impl AllowedIp {
+    /// Clear host bits, as the kernel does when it stores the entry
+    pub fn network(self) -> Self {
+        let address = match self.address {
+            IpAddr::V4(a) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.cidr)).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(a) & mask))
+            }
+            IpAddr::V6(a) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.cidr))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(a) & mask))
+            }
+        };
+        Self { address, ..self }
+    }
+}
+
+impl FromStr for AllowedIp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (address, cidr) = match s.split_once('/') {
+            Some((address, cidr)) => (address, Some(cidr)),
+            None => (s, None),
+        };
+        let address: IpAddr = address
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid allowed IP '{}'", s))?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let cidr = match cidr {
+            Some(cidr) => cidr
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|c| *c <= max)
+                .ok_or_else(|| anyhow!("Invalid prefix length in allowed IP '{}'", s))?,
+            None => max,
+        };
+        Ok(Self { address, cidr })
+    }
+}
+
+impl fmt::Display for AllowedIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.cidr)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Peer {
+    pub public_key: Option<Key>,
+    pub preshared_key: Option<Key>,
+    pub endpoint: Option<InetAddr>,
+    /// Seconds, 0 disables
+    pub persistent_keepalive: u16,
+    pub allowed_ips: Vec<AllowedIp>,
+    /// Unix time of the last handshake, None if there never was one
+    pub last_handshake: Option<i64>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Device {
+    pub name: String,
+    pub private_key: Option<Key>,
+    pub public_key: Option<Key>,
+    pub listen_port: u16,
+    pub fwmark: u32,
+    pub peers: Vec<Peer>,
+}
+
+/// Netlink attribute builder (host byte order)
+#[derive(Debug, Default)]
+struct Attrs(Vec<u8>);
+
+impl Attrs {
+    fn bytes(&mut self, kind: u16, value: &[u8]) -> &mut Self {
+        let len = 4 + value.len();
+        self.0.extend_from_slice(&(len as u16).to_ne_bytes());
+        self.0.extend_from_slice(&kind.to_ne_bytes());
+        self.0.extend_from_slice(value);
+        while !self.0.len().is_multiple_of(4) {
+            self.0.push(0);
+        }
+        self
+    }
+
+    fn str(&mut self, kind: u16, value: &str) -> &mut Self {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.bytes(kind, &bytes)
+    }
+
+    fn u8(&mut self, kind: u16, value: u8) -> &mut Self {
+        self.bytes(kind, &[value])
+    }
+
+    fn u16(&mut self, kind: u16, value: u16) -> &mut Self {
+        self.bytes(kind, &value.to_ne_bytes())
+    }
+
+    fn u32(&mut self, kind: u16, value: u32) -> &mut Self {
+        self.bytes(kind, &value.to_ne_bytes())
+    }
+
+    fn nested(&mut self, kind: u16, build: impl FnOnce(&mut Attrs)) -> &mut Self {
+        let mut inner = Attrs::default();
+        build(&mut inner);
+        self.bytes(kind | NLA_F_NESTED, &inner.0)
+    }
+}
+
+/// Parsed attributes of one nesting level
+struct AttrSet<'a>(Vec<(u16, &'a [u8])>);
+
+impl<'a> AttrSet<'a> {
+    fn parse(mut buf: &'a [u8]) -> Self {
+        let mut attrs = Vec::new();
+        while buf.len() >= 4 {
+            let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+            let kind = u16::from_ne_bytes([buf[2], buf[3]]);
+            if len < 4 || len > buf.len() {
+                break;
+            }
+            attrs.push((kind & NLA_TYPE_MASK, &buf[4..len]));
+            buf = &buf[((len + 3) & !3).min(buf.len())..];
+        }
+        Self(attrs)
+    }
+
+    /// Elements of a nested list, whatever their index
+    fn elements(&self) -> impl Iterator<Item = AttrSet<'a>> + '_ {
+        self.0.iter().map(|(_, v)| AttrSet::parse(v))
+    }
+
+    fn get(&self, kind: u16) -> Option<&'a [u8]> {
+        self.0.iter().find(|(k, _)| *k == kind).map(|(_, v)| *v)
+    }
+
+    fn str(&self, kind: u16) -> Option<String> {
+        let value = self.get(kind)?;
+        let end = value.iter().position(|b| *b == 0).unwrap_or(value.len());
+        Some(String::from_utf8_lossy(&value[..end]).into_owned())
+    }
+
+    fn u16(&self, kind: u16) -> Option<u16> {
+        Some(u16::from_ne_bytes(
+            self.get(kind)?.get(..2)?.try_into().ok()?,
+        ))
+    }
+
+    fn u32(&self, kind: u16) -> Option<u32> {
+        Some(u32::from_ne_bytes(
+            self.get(kind)?.get(..4)?.try_into().ok()?,
+        ))
+    }
+
+    fn u64(&self, kind: u16) -> Option<u64> {
+        Some(u64::from_ne_bytes(
+            self.get(kind)?.get(..8)?.try_into().ok()?,
+        ))
+    }
+
+    fn key(&self, kind: u16) -> Option<Key> {
+        Key::from_slice(self.get(kind)?)
+    }
+}
+
+/// struct sockaddr_in / sockaddr_in6
+fn encode_endpoint(endpoint: &InetAddr) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match endpoint {
+        InetAddr::V4(a) => {
+            buf.extend_from_slice(&AF_INET.to_ne_bytes());
+            buf.extend_from_slice(&a.port().to_be_bytes());
+            buf.extend_from_slice(&a.ip().octets());
+            buf.extend_from_slice(&[0; 8]);
+        }
+        InetAddr::V6(a) => {
+            buf.extend_from_slice(&AF_INET6.to_ne_bytes());
+            buf.extend_from_slice(&a.port().to_be_bytes());
+            buf.extend_from_slice(&a.flowinfo().to_be_bytes());
+            buf.extend_from_slice(&a.ip().octets());
+            buf.extend_from_slice(&a.scope_id().to_ne_bytes());
+        }
+    }
+    buf
+}
+
+fn decode_endpoint(buf: &[u8]) -> Option<InetAddr> {
+    let family = u16::from_ne_bytes(buf.get(..2)?.try_into().ok()?);
+    let port = u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?);
+    match family {
+        AF_INET => {
+            let ip: [u8; 4] = buf.get(4..8)?.try_into().ok()?;
+            Some(InetAddr::new(IpAddr::from(ip), port))
+        }
+        AF_INET6 => {
+            let ip: [u8; 16] = buf.get(8..24)?.try_into().ok()?;
+            let scope = u32::from_ne_bytes(buf.get(24..28)?.try_into().ok()?);
+            Some(InetAddr::V6(std::net::SocketAddrV6::new(
+                Ipv6Addr::from(ip),
+                port,
+                0,
+                scope,
+            )))
+        }
+        _ => None,
+    }
+}
+
+fn decode_allowed_ip(attrs: &AttrSet) -> Option<AllowedIp> {
+    let cidr = *attrs.get(allowedip_attr::CIDR_MASK)?.first()?;
+    let bytes = attrs.get(allowedip_attr::IPADDR)?;
+    let address = match attrs.u16(allowedip_attr::FAMILY)? {
+        AF_INET => IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?),
+        AF_INET6 => IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?),
+        _ => return None,
+    };
+    Some(AllowedIp { address, cidr })
+}
+
+fn decode_peer(attrs: &AttrSet) -> Peer {
+    // struct __kernel_timespec: i64 seconds, i64 nanoseconds
+    let last_handshake = attrs
+        .get(peer_attr::LAST_HANDSHAKE_TIME)
+        .and_then(|t| t.get(..8))
+        .map(|s| i64::from_ne_bytes(s.try_into().unwrap_or_default()))
+        .filter(|secs| *secs != 0);
+    Peer {
+        public_key: attrs.key(peer_attr::PUBLIC_KEY),
+        preshared_key: attrs.key(peer_attr::PRESHARED_KEY).filter(|k| !k.is_zero()),
+        endpoint: attrs.get(peer_attr::ENDPOINT).and_then(decode_endpoint),
+        persistent_keepalive: attrs
+            .u16(peer_attr::PERSISTENT_KEEPALIVE_INTERVAL)
+            .unwrap_or(0),
+        allowed_ips: attrs
+            .get(peer_attr::ALLOWEDIPS)
+            .map(|list| {
+                AttrSet::parse(list)
+                    .elements()
+                    .filter_map(|ip| decode_allowed_ip(&ip))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        last_handshake,
+        rx_bytes: attrs.u64(peer_attr::RX_BYTES).unwrap_or(0),
+        tx_bytes: attrs.u64(peer_attr::TX_BYTES).unwrap_or(0),
+    }
+}
+
+/// Fold one GET_DEVICE reply into the device. Large devices span several
+/// messages; a peer split across two repeats its public key in the second.
+fn merge_reply(device: &mut Device, attrs: &[u8]) {
+    let attrs = AttrSet::parse(attrs);
+    if let Some(name) = attrs.str(device_attr::IFNAME) {
+        device.name = name;
+    }
+    if let Some(key) = attrs.key(device_attr::PRIVATE_KEY) {
+        device.private_key = Some(key).filter(|k| !k.is_zero());
+    }
+    if let Some(key) = attrs.key(device_attr::PUBLIC_KEY) {
+        device.public_key = Some(key).filter(|k| !k.is_zero());
+    }
+    if let Some(port) = attrs.u16(device_attr::LISTEN_PORT) {
+        device.listen_port = port;
+    }
+    if let Some(mark) = attrs.u32(device_attr::FWMARK) {
+        device.fwmark = mark;
+    }
+    let Some(peers) = attrs.get(device_attr::PEERS) else {
+        return;
+    };
+    for peer in AttrSet::parse(peers).elements() {
+        let peer = decode_peer(&peer);
+        match device.peers.last_mut() {
+            Some(last) if last.public_key == peer.public_key => {
+                last.allowed_ips.extend(peer.allowed_ips)
+            }
+            _ => device.peers.push(peer),
+        }
+    }
+}
+
+/// Attributes of one WG_CMD_SET_DEVICE peer entry. The entry states the
+/// peer completely: allowed IPs are replaced, and a missing preshared key
+/// or keepalive clears the current one.
+fn encode_peer(out: &mut Attrs, peer: &Peer) -> Result<()> {
+    let key = peer
+        .public_key
+        .ok_or_else(|| anyhow!("WireGuard peer without a public key"))?;
+    out.bytes(peer_attr::PUBLIC_KEY, &key.0);
+    out.u32(peer_attr::FLAGS, peer_attr::F_REPLACE_ALLOWEDIPS);
+    out.bytes(
+        peer_attr::PRESHARED_KEY,
+        &peer.preshared_key.unwrap_or(Key::ZERO).0,
+    );
+    if let Some(endpoint) = &peer.endpoint {
+        out.bytes(peer_attr::ENDPOINT, &encode_endpoint(endpoint));
+    }
+    out.u16(
+        peer_attr::PERSISTENT_KEEPALIVE_INTERVAL,
+        peer.persistent_keepalive,
+    );
+    out.nested(peer_attr::ALLOWEDIPS, |list| {
+        for (index, ip) in peer.allowed_ips.iter().enumerate() {
+            list.nested(index as u16, |attrs| {
+                let (family, bytes) = match ip.address {
+                    IpAddr::V4(a) => (AF_INET, a.octets().to_vec()),
+                    IpAddr::V6(a) => (AF_INET6, a.octets().to_vec()),
+                };
+                attrs.u16(allowedip_attr::FAMILY, family);
+                attrs.bytes(allowedip_attr::IPADDR, &bytes);
+                attrs.u8(allowedip_attr::CIDR_MASK, ip.cidr);
+            });
+        }
+    });
+    Ok(())
+}
+
+/// SET_DEVICE requests bringing `device` to the given state. Peers listed
+/// are added or updated in place, so established sessions survive; peers
+/// in `remove` are dropped. The device fields go in the first message, each
+/// peer in its own so no message outgrows the kernel's limit.
+fn set_requests(device: &Device, remove: &[Key]) -> Result<Vec<Vec<u8>>> {
+    let mut head = Attrs::default();
+    head.str(device_attr::IFNAME, &device.name);
+    if let Some(key) = &device.private_key {
+        head.bytes(device_attr::PRIVATE_KEY, &key.0);
+    }
+    head.u16(device_attr::LISTEN_PORT, device.listen_port);
+    head.u32(device_attr::FWMARK, device.fwmark);
+    if !remove.is_empty() {
+        head.nested(device_attr::PEERS, |list| {
+            for (index, key) in remove.iter().enumerate() {
+                list.nested(index as u16, |peer| {
+                    peer.bytes(peer_attr::PUBLIC_KEY, &key.0);
+                    peer.u32(peer_attr::FLAGS, peer_attr::F_REMOVE_ME);
+                });
+            }
+        });
+    }
+
+    let mut requests = vec![head.0];
+    for peer in &device.peers {
+        let mut entry = Attrs::default();
+        encode_peer(&mut entry, peer)?;
+        let mut attrs = Attrs::default();
+        attrs.str(device_attr::IFNAME, &device.name);
+        attrs.nested(device_attr::PEERS, |list| {
+            list.bytes(NLA_F_NESTED, &entry.0);
+        });
+        requests.push(attrs.0);
+    }
+    Ok(requests)
+}
+
+/// A received netlink message
+struct Received<'a> {
+    message_type: u16,
+    seq: u32,
+    payload: &'a [u8],
+}
+
+fn split_messages(mut buf: &[u8]) -> Vec<Received<'_>> {
+    let mut messages = Vec::new();
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            break;
+        }
+        messages.push(Received {
+            message_type: u16::from_ne_bytes([buf[4], buf[5]]),
+            seq: u32::from_ne_bytes([buf[8], buf[9], buf[10], buf[11]]),
+            payload: &buf[NLMSG_HDRLEN..len],
+        });
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+    messages
+}
+
+/// Errno carried by an NLMSG_ERROR payload (0 is an ack)
+fn error_code(payload: &[u8]) -> i32 {
+    payload
+        .get(..4)
+        .map_or(0, |b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Synchronous generic netlink socket; callers on the runtime wrap it in spawn_blocking
+pub struct WgClient {
+    socket: Socket,
+    family: u16,
+    seq: u32,
+}
+
+impl WgClient {
+    /// Open a socket and resolve the wireguard family; fails if the module isn't loaded
+    pub fn connect() -> Result<Self> {
+        let mut socket =
+            Socket::new(NETLINK_GENERIC).context("Failed to open generic netlink socket")?;
+        socket.bind_auto()?;
+        socket.connect(&SocketAddr::new(0, 0))?;
+        let mut client = Self {
+            socket,
+            family: GENL_ID_CTRL,
+            seq: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(1, |d| d.as_secs() as u32),
+        };
+
+        let mut attrs = Attrs::default();
+        attrs.str(CTRL_ATTR_FAMILY_NAME, WG_GENL_NAME);
+        let replies = client
+            .request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, 0, &attrs.0)
+            .context("WireGuard generic netlink family not found (is the module loaded?)")?;
+        client.family = replies
+            .iter()
+            .find_map(|reply| AttrSet::parse(reply).u16(CTRL_ATTR_FAMILY_ID))
+            .ok_or_else(|| anyhow!("Controller reply has no family id"))?;
+        Ok(client)
+    }
+
+    /// Send one request and collect the attribute payloads of its replies
+    fn request(&mut self, family: u16, cmd: u8, flags: u16, attrs: &[u8]) -> Result<Vec<Vec<u8>>> {
+        self.seq = self.seq.wrapping_add(1);
+        let seq = self.seq;
+        let len = NLMSG_HDRLEN + GENLMSG_HDRLEN + attrs.len();
+        let mut request = Vec::with_capacity(len);
+        request.extend_from_slice(&(len as u32).to_ne_bytes());
+        request.extend_from_slice(&family.to_ne_bytes());
+        request.extend_from_slice(&(NLM_F_REQUEST | NLM_F_ACK | flags).to_ne_bytes());
+        request.extend_from_slice(&seq.to_ne_bytes());
+        request.extend_from_slice(&0u32.to_ne_bytes());
+        // genlmsghdr: cmd, version, reserved
+        let version = if family == GENL_ID_CTRL {
+            1
+        } else {
+            WG_GENL_VERSION
+        };
+        request.extend_from_slice(&[cmd, version, 0, 0]);
+        request.extend_from_slice(attrs);
+        self.socket.send(&request, 0)?;
+
+        let dump = flags & NLM_F_DUMP == NLM_F_DUMP;
+        let mut replies = Vec::new();
+        loop {
+            let (buf, _) = self.socket.recv_from_full()?;
+            for received in split_messages(&buf) {
+                if received.seq != seq {
+                    continue;
+                }
+                match received.message_type {
+                    NLMSG_DONE => return Ok(replies),
+                    NLMSG_ERROR => match error_code(received.payload) {
+                        0 if dump => {}
+                        0 => return Ok(replies),
+                        code => return Err(io::Error::from_raw_os_error(-code).into()),
+                    },
+                    _ => {
+                        if let Some(body) = received.payload.get(GENLMSG_HDRLEN..) {
+                            replies.push(body.to_vec());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Configuration and peer statistics of one interface
+    pub fn get_device(&mut self, name: &str) -> Result<Device> {
+        let mut attrs = Attrs::default();
+        attrs.str(device_attr::IFNAME, name);
+        let family = self.family;
+        let replies = self
+            .request(family, WG_CMD_GET_DEVICE, NLM_F_DUMP, &attrs.0)
+            .with_context(|| format!("Failed to read WireGuard device {}", name))?;
+        if replies.is_empty() {
+            bail!("{} is not a WireGuard device", name);
+        }
+        let mut device = Device::default();
+        for reply in &replies {
+            merge_reply(&mut device, reply);
+        }
+        Ok(device)
+    }
+
+    /// Bring an interface to `device`'s configuration, dropping `remove`d peers
+    pub fn set_device(&mut self, device: &Device, remove: &[Key]) -> Result<()> {
+        let family = self.family;
+        for request in set_requests(device, remove)? {
+            self.request(family, WG_CMD_SET_DEVICE, 0, &request)
+                .with_context(|| format!("Failed to configure WireGuard device {}", device.name))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> Key {
+        Key([byte; KEY_LEN])
+    }
+
+    #[test]
+    fn test_key_and_allowed_ip_parsing() {
+        let k = key(7);
+        assert_eq!(k.to_string().len(), 44);
+        assert_eq!(k.to_string().parse::<Key>().unwrap(), k);
+        assert!("c2hvcnQ=".parse::<Key>().is_err());
+        assert_eq!(format!("{:?}", k), "Key(..)");
+
+        let ip: AllowedIp = "10.1.2.3/24".parse().unwrap();
+        assert_eq!(ip.network().to_string(), "10.1.2.0/24");
+        assert_eq!("fd00::1".parse::<AllowedIp>().unwrap().cidr, 128);
+        assert_eq!(
+            "0.0.0.0/0"
+                .parse::<AllowedIp>()
+                .unwrap()
+                .network()
+                .to_string(),
+            "0.0.0.0/0"
+        );
+        assert!("10.0.0.0/33".parse::<AllowedIp>().is_err());
+    }
+
+    #[test]
+    fn test_endpoint_round_trip() {
+        for endpoint in ["203.0.113.5:51820", "[2001:db8::1]:443"] {
+            let endpoint: InetAddr = endpoint.parse().unwrap();
+            assert_eq!(decode_endpoint(&encode_endpoint(&endpoint)), Some(endpoint));
+        }
+    }
+
+    #[test]
+    fn test_set_requests_decode_as_device() {
+        let device = Device {
+            name: "wg0".to_string(),
+            private_key: Some(key(1)),
+            public_key: None,
+            listen_port: 51820,
+            fwmark: 0x51,
+            peers: vec![Peer {
+                public_key: Some(key(2)),
+                preshared_key: Some(key(3)),
+                endpoint: Some("198.51.100.1:51820".parse().unwrap()),
+                persistent_keepalive: 25,
+                allowed_ips: vec!["10.8.0.0/24".parse().unwrap(), "fd00::/64".parse().unwrap()],
+                ..Default::default()
+            }],
+        };
+        let requests = set_requests(&device, &[key(9)]).unwrap();
+        assert_eq!(requests.len(), 2);
+
+        // The removal rides on the device message with REMOVE_ME set
+        let head = AttrSet::parse(&requests[0]);
+        let removed: Vec<_> = AttrSet::parse(head.get(device_attr::PEERS).unwrap())
+            .elements()
+            .map(|p| (p.key(peer_attr::PUBLIC_KEY), p.u32(peer_attr::FLAGS)))
+            .collect();
+        assert_eq!(removed, vec![(Some(key(9)), Some(peer_attr::F_REMOVE_ME))]);
+
+        // Set requests use the same attributes GET_DEVICE replies carry
+        let mut decoded = Device::default();
+        merge_reply(&mut decoded, &requests[0]);
+        decoded.peers.clear();
+        merge_reply(&mut decoded, &requests[1]);
+        assert_eq!(decoded, device);
+    }
+
+    #[test]
+    fn test_split_peer_is_merged() {
+        let peer = |ips: &[&str]| Peer {
+            public_key: Some(key(4)),
+            allowed_ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            ..Default::default()
+        };
+        let reply = |peer: &Peer| {
+            let mut entry = Attrs::default();
+            encode_peer(&mut entry, peer).unwrap();
+            let mut attrs = Attrs::default();
+            attrs.nested(device_attr::PEERS, |list| {
+                list.bytes(NLA_F_NESTED, &entry.0);
+            });
+            attrs.0
+        };
+        let mut device = Device::default();
+        merge_reply(&mut device, &reply(&peer(&["10.0.0.1/32"])));
+        merge_reply(&mut device, &reply(&peer(&["10.0.0.2/32"])));
+        assert_eq!(device.peers, vec![peer(&["10.0.0.1/32", "10.0.0.2/32"])]);
+    }
+}
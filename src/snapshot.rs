@@ -69,6 +69,17 @@ impl SnapshotManager {
         &self,
         source: impl AsRef<Path>,
         name: Option<&str>,
+    ) -> Result<PathBuf> {
+        self.create_tagged_snapshot(source, name, None)
+    }
+
+    /// Create a new snapshot, tagging it before retention runs so a tagged
+    /// snapshot is never pruned on its way in
+    pub fn create_tagged_snapshot(
+        &self,
+        source: impl AsRef<Path>,
+        name: Option<&str>,
+        tag: Option<&str>,
     ) -> Result<PathBuf> {
         let source = source.as_ref();
 
@@ -107,6 +118,10 @@ impl SnapshotManager {
 
         log::info!("   Created: {}", snapshot_path.display());
 
+        if let Some(tag) = tag {
+            self.tag_snapshot(&snapshot_name, tag)?;
+        }
+
         // Apply retention policy
         self.apply_retention_policy()?;
 
@@ -115,75 +130,27 @@ impl SnapshotManager {
 
     /// Apply retention policy (delete old snapshots)
     pub fn apply_retention_policy(&self) -> Result<()> {
-        let snapshots = self.list_snapshots()?;
-
-        match &self.policy {
-            RetentionPolicy::Rolling { keep } => {
-                self.apply_rolling_retention(&snapshots, *keep)?;
-            }
-            RetentionPolicy::TimeBased { days } => {
-                self.apply_time_based_retention(&snapshots, *days)?;
-            }
-            RetentionPolicy::Tagged { keep_untagged } => {
-                self.apply_tagged_retention(&snapshots, *keep_untagged)?;
-            }
-        }
-
-        Ok(())
-    }
-
-    /// Apply rolling N retention (keep last N snapshots)
-    fn apply_rolling_retention(&self, snapshots: &[SnapshotInfo], keep: usize) -> Result<()> {
-        if snapshots.len() <= keep {
-            return Ok(()); // Nothing to delete
-        }
-
-        // Sort by creation time (oldest first)
-        let mut sorted = snapshots.to_vec();
-        sorted.sort_by_key(|s| s.created);
-
-        // Delete oldest snapshots beyond retention limit
-        let to_delete = sorted.len() - keep;
-        for snapshot in sorted.iter().take(to_delete) {
+        for snapshot in self.plan_retention()? {
             log::info!("🗑️  Deleting old snapshot: {}", snapshot.name);
             self.delete_snapshot(&snapshot.path)?;
         }
 
-        if to_delete > 0 {
-            log::info!("   Kept {} most recent snapshots", keep);
-        }
-
         Ok(())
     }
 
-    /// Apply time-based retention
-    fn apply_time_based_retention(&self, snapshots: &[SnapshotInfo], days: usize) -> Result<()> {
-        let cutoff = chrono::Utc::now().timestamp() - (days as i64 * 86400);
-
-        for snapshot in snapshots {
-            if snapshot.created < cutoff {
-                log::info!("🗑️  Deleting expired snapshot: {}", snapshot.name);
-                self.delete_snapshot(&snapshot.path)?;
-            }
-        }
-
-        Ok(())
+    /// Snapshots the retention policy would delete right now (dry run)
+    pub fn plan_retention(&self) -> Result<Vec<SnapshotInfo>> {
+        let snapshots = self.list_snapshots()?;
+        Ok(retention_victims(
+            &snapshots,
+            &self.policy,
+            chrono::Utc::now().timestamp(),
+        ))
     }
 
-    /// Apply tagged retention (keep all tagged, rolling for untagged)
-    fn apply_tagged_retention(&self, snapshots: &[SnapshotInfo], keep_untagged: usize) -> Result<()> {
-        let mut untagged: Vec<_> = snapshots.iter().filter(|s| !s.tagged).collect();
-        untagged.sort_by_key(|s| s.created);
-
-        if untagged.len() > keep_untagged {
-            let to_delete = untagged.len() - keep_untagged;
-            for snapshot in untagged.iter().take(to_delete) {
-                log::info!("🗑️  Deleting old untagged snapshot: {}", snapshot.name);
-                self.delete_snapshot(&snapshot.path)?;
-            }
-        }
-
-        Ok(())
+    /// Retention policy in effect
+    pub fn policy(&self) -> &RetentionPolicy {
+        &self.policy
     }
 
     /// Delete a snapshot
@@ -204,6 +171,14 @@ impl SnapshotManager {
             );
         }
 
+        // The tag lives next to the snapshot, so it goes with it
+        if let Some(name) = snapshot_path.file_name() {
+            let tag_file = self.tag_file(&name.to_string_lossy());
+            if tag_file.exists() {
+                std::fs::remove_file(&tag_file)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -239,17 +214,22 @@ impl SnapshotManager {
                 .duration_since(std::time::UNIX_EPOCH)?
                 .as_secs() as i64;
 
-            // Check for tag file
-            let tag_file = path.join(".snapshot-tag");
-            let (tagged, tag) = if tag_file.exists() {
-                let tag_content = std::fs::read_to_string(&tag_file).ok();
+            // Check for tag file (sidecar, or legacy file inside the snapshot)
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let tag_file = [self.tag_file(&name), path.join(".snapshot-tag")]
+                .into_iter()
+                .find(|file| file.exists());
+            let (tagged, tag) = if let Some(tag_file) = tag_file {
+                let tag_content = std::fs::read_to_string(&tag_file)
+                    .ok()
+                    .map(|t| t.trim().to_string());
                 (true, tag_content)
             } else {
                 (false, None)
             };
 
             snapshots.push(SnapshotInfo {
-                name: path.file_name().unwrap().to_string_lossy().to_string(),
+                name,
                 path,
                 created,
                 tagged,
@@ -268,13 +248,82 @@ impl SnapshotManager {
             bail!("Snapshot not found: {}", snapshot_name);
         }
 
-        let tag_file = snapshot_path.join(".snapshot-tag");
-        std::fs::write(&tag_file, tag)?;
+        // Snapshots are read-only, so tags live next to them
+        std::fs::write(self.tag_file(snapshot_name), tag)?;
 
         log::info!("🏷️  Tagged snapshot '{}' as '{}'", snapshot_name, tag);
         Ok(())
     }
 
+    /// Remove a snapshot's tag (makes it eligible for retention again)
+    pub fn untag_snapshot(&self, snapshot_name: &str) -> Result<()> {
+        let tag_file = self.tag_file(snapshot_name);
+        if tag_file.exists() {
+            std::fs::remove_file(&tag_file)?;
+        }
+        log::info!("🏷️  Removed tag from snapshot '{}'", snapshot_name);
+        Ok(())
+    }
+
+    fn tag_file(&self, snapshot_name: &str) -> PathBuf {
+        self.snapshots_dir.join(format!(".{}.tag", snapshot_name))
+    }
+
+    /// Replace `target` with a writable copy of a snapshot
+    ///
+    /// The current subvolume is renamed to `<target>.pre-restore-<timestamp>`
+    /// rather than deleted; its path is returned so callers can clean it up.
+    pub fn restore_snapshot(
+        &self,
+        snapshot_name: &str,
+        target: impl AsRef<Path>,
+    ) -> Result<Option<PathBuf>> {
+        let target = target.as_ref();
+        let snapshot_path = self.snapshots_dir.join(snapshot_name);
+        if !snapshot_path.exists() {
+            bail!("Snapshot not found: {}", snapshot_name);
+        }
+
+        let backup = if target.exists() {
+            let file_name = target
+                .file_name()
+                .context("Restore target has no file name")?
+                .to_string_lossy();
+            let backup = target.with_file_name(format!(
+                "{}.pre-restore-{}",
+                file_name,
+                chrono::Utc::now().format("%Y-%m-%d-%H%M%S")
+            ));
+            std::fs::rename(target, &backup).with_context(|| {
+                format!("Failed to move {} aside", target.display())
+            })?;
+            Some(backup)
+        } else {
+            None
+        };
+
+        log::info!("♻️  Restoring {} from {}", target.display(), snapshot_name);
+        let output = Command::new("btrfs")
+            .args(["subvolume", "snapshot"])
+            .arg(&snapshot_path)
+            .arg(target)
+            .output()
+            .context("Failed to execute btrfs snapshot command")?;
+
+        if !output.status.success() {
+            // Put the original back so a failed restore leaves things as they were
+            if let Some(backup) = &backup {
+                std::fs::rename(backup, target)?;
+            }
+            bail!(
+                "Failed to restore snapshot: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(backup)
+    }
+
     /// Get total size of all snapshots
     pub fn total_size(&self) -> Result<u64> {
         // TODO: Use `btrfs qgroup show` for accurate size
@@ -310,6 +359,32 @@ impl SnapshotManager {
     }
 }
 
+/// Pick the snapshots a policy would delete, oldest first
+pub fn retention_victims(
+    snapshots: &[SnapshotInfo],
+    policy: &RetentionPolicy,
+    now: i64,
+) -> Vec<SnapshotInfo> {
+    let mut sorted = snapshots.to_vec();
+    sorted.sort_by_key(|s| s.created);
+
+    match policy {
+        RetentionPolicy::Rolling { keep } => {
+            let to_delete = sorted.len().saturating_sub(*keep);
+            sorted.into_iter().take(to_delete).collect()
+        }
+        RetentionPolicy::TimeBased { days } => {
+            let cutoff = now - (*days as i64 * 86400);
+            sorted.into_iter().filter(|s| s.created < cutoff).collect()
+        }
+        RetentionPolicy::Tagged { keep_untagged } => {
+            let untagged: Vec<_> = sorted.into_iter().filter(|s| !s.tagged).collect();
+            let to_delete = untagged.len().saturating_sub(*keep_untagged);
+            untagged.into_iter().take(to_delete).collect()
+        }
+    }
+}
+
 /// Snapshot configuration for a specific component
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SnapshotConfig {
@@ -353,6 +428,160 @@ impl SnapshotConfig {
             auto_snapshot_on_change: true,
         }
     }
+
+    /// Default config for container checkpoints
+    ///
+    /// Used by the LXC plugin unless an "lxc" target is configured. Not one of
+    /// the built-in targets: containers are snapshotted one by one, under
+    /// `snapshots_dir/<id>` (or by `pct` on Proxmox), not as one subvolume.
+    pub fn lxc() -> Self {
+        Self {
+            enabled: true,
+            subvolume: PathBuf::from("/var/lib/lxc"),
+            snapshots_dir: PathBuf::from("/var/lib/op-dbus/@snapshots/lxc"),
+            policy: RetentionPolicy::Rolling { keep: 3 },
+            auto_snapshot_on_change: true,
+        }
+    }
+
+    /// All built-in snapshot targets, by name
+    pub fn defaults() -> Vec<(&'static str, Self)> {
+        vec![
+            ("dbus-index", Self::dbus_index()),
+            ("cache", Self::cache()),
+            ("config", Self::config()),
+        ]
+    }
+
+    /// Snapshot manager for this target
+    pub fn manager(&self) -> SnapshotManager {
+        SnapshotManager::with_policy(&self.snapshots_dir, self.policy.clone())
+    }
+}
+
+/// Default location of the per-target snapshot policy file
+pub const SNAPSHOT_TARGETS_FILE: &str = "/etc/op-dbus/snapshots.json";
+
+/// Named snapshot targets with their retention policies
+///
+/// Built-in targets can be overridden and new ones added through a JSON file
+/// mapping target names to `SnapshotConfig`, e.g.
+/// `{"cache": {"enabled": true, "subvolume": "/var/lib/op-dbus/@cache",
+///   "snapshots_dir": "/var/lib/op-dbus/@snapshots/cache",
+///   "policy": {"TimeBased": {"days": 7}}, "auto_snapshot_on_change": false}}`
+#[derive(Debug, Clone)]
+pub struct SnapshotTargets {
+    targets: std::collections::BTreeMap<String, SnapshotConfig>,
+}
+
+impl Default for SnapshotTargets {
+    fn default() -> Self {
+        Self {
+            targets: SnapshotConfig::defaults()
+                .into_iter()
+                .map(|(name, config)| (name.to_string(), config))
+                .collect(),
+        }
+    }
+}
+
+impl SnapshotTargets {
+    /// Load targets from `OPDBUS_SNAPSHOT_CONFIG` or the default file, over the built-ins
+    pub fn load() -> Result<Self> {
+        let path = std::env::var("OPDBUS_SNAPSHOT_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(SNAPSHOT_TARGETS_FILE));
+        Self::load_from(&path)
+    }
+
+    /// Load targets from a specific file; a missing file yields the built-ins
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let mut targets = Self::default();
+        if !path.exists() {
+            return Ok(targets);
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let overrides: std::collections::BTreeMap<String, SnapshotConfig> =
+            serde_json::from_str(&content)
+                .with_context(|| format!("Invalid snapshot config {}", path.display()))?;
+        targets.targets.extend(overrides);
+        Ok(targets)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&SnapshotConfig> {
+        self.targets
+            .get(name)
+            .ok_or_else(|| self.unknown_target(name))
+    }
+
+    fn unknown_target(&self, name: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Unknown snapshot target '{}' (known: {})",
+            name,
+            self.names().collect::<Vec<_>>().join(", ")
+        )
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.targets.keys().map(|k| k.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SnapshotConfig)> {
+        self.targets.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// The selected target, or every enabled target when none is given
+    pub fn select(&self, name: Option<&str>) -> Result<Vec<(&str, &SnapshotConfig)>> {
+        match name {
+            Some(name) => self
+                .targets
+                .get_key_value(name)
+                .map(|(key, config)| vec![(key.as_str(), config)])
+                .ok_or_else(|| self.unknown_target(name)),
+            None => Ok(self.iter().filter(|(_, config)| config.enabled).collect()),
+        }
+    }
+}
+
+/// Resolve a snapshot argument to a path
+///
+/// Accepts an existing path, `<target>/<name>` (e.g. `dbus-index/2025-01-01-120000`)
+/// or a bare snapshot name that is unique across all configured targets.
+pub fn resolve_snapshot(name: &str) -> Result<PathBuf> {
+    let direct = PathBuf::from(name);
+    if direct.exists() {
+        return Ok(direct);
+    }
+
+    let targets = SnapshotTargets::load()?;
+
+    if let Some((target, snapshot)) = name.split_once('/') {
+        if let Ok(config) = targets.get(target) {
+            let path = config.snapshots_dir.join(snapshot);
+            if path.exists() {
+                return Ok(path);
+            }
+        }
+    }
+
+    let matches: Vec<PathBuf> = targets
+        .iter()
+        .map(|(_, config)| config.snapshots_dir.join(name))
+        .filter(|path| path.exists())
+        .collect();
+
+    match matches.len() {
+        0 => bail!("Snapshot not found: {}", name),
+        1 => Ok(matches.into_iter().next().unwrap()),
+        _ => bail!(
+            "Snapshot name '{}' is ambiguous, use <target>/{}: {:?}",
+            name,
+            name,
+            matches
+        ),
+    }
 }
 
 #[cfg(test)]
@@ -395,4 +624,91 @@ mod tests {
         assert_eq!(sorted[0].name, "2025-01-01");
         assert_eq!(sorted[2].name, "2025-01-03");
     }
+
+    fn snapshot(name: &str, created: i64, tagged: bool) -> SnapshotInfo {
+        SnapshotInfo {
+            name: name.to_string(),
+            path: PathBuf::from("/snap").join(name),
+            created,
+            tagged,
+            tag: tagged.then(|| "golden".to_string()),
+            size_bytes: 0,
+        }
+    }
+
+    fn names(victims: &[SnapshotInfo]) -> Vec<&str> {
+        victims.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_retention_victims() {
+        let day = 86400;
+        let snapshots = vec![
+            snapshot("c", 3 * day, false),
+            snapshot("a", day, true),
+            snapshot("b", 2 * day, false),
+        ];
+        let now = 4 * day;
+
+        assert_eq!(
+            names(&retention_victims(&snapshots, &RetentionPolicy::Rolling { keep: 2 }, now)),
+            vec!["a"]
+        );
+        assert_eq!(
+            names(&retention_victims(&snapshots, &RetentionPolicy::TimeBased { days: 2 }, now)),
+            vec!["a"]
+        );
+        assert_eq!(
+            names(&retention_victims(
+                &snapshots,
+                &RetentionPolicy::Tagged { keep_untagged: 1 },
+                now
+            )),
+            vec!["b"]
+        );
+        assert!(
+            retention_victims(&snapshots, &RetentionPolicy::Rolling { keep: 5 }, now).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_snapshot_targets_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("snapshots.json");
+
+        // Missing file: built-in targets only
+        let targets = SnapshotTargets::load_from(&file).unwrap();
+        assert_eq!(
+            targets.names().collect::<Vec<_>>(),
+            vec!["cache", "config", "dbus-index"]
+        );
+
+        std::fs::write(
+            &file,
+            r#"{
+                "cache": {"enabled": false, "subvolume": "/data/@cache",
+                          "snapshots_dir": "/data/@snapshots/cache",
+                          "policy": {"TimeBased": {"days": 7}},
+                          "auto_snapshot_on_change": false},
+                "lxc": {"enabled": true, "subvolume": "/var/lib/lxc",
+                        "snapshots_dir": "/var/lib/op-dbus/@snapshots/lxc",
+                        "policy": {"Rolling": {"keep": 10}},
+                        "auto_snapshot_on_change": false}
+            }"#,
+        )
+        .unwrap();
+
+        let targets = SnapshotTargets::load_from(&file).unwrap();
+        assert!(matches!(
+            targets.get("cache").unwrap().policy,
+            RetentionPolicy::TimeBased { days: 7 }
+        ));
+        assert!(targets.get("lxc").is_ok());
+        assert!(targets.get("nope").is_err());
+
+        // Disabled targets are skipped unless named explicitly
+        let all: Vec<_> = targets.select(None).unwrap().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(all, vec!["config", "dbus-index", "lxc"]);
+        assert_eq!(targets.select(Some("cache")).unwrap().len(), 1);
+    }
 }
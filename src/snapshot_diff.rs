@@ -0,0 +1,668 @@
+//! Snapshot content diffing
+//!
+//! Compares two snapshots (or any two directories) and reports which files
+//! were added, removed or modified, with sizes. When both sides are BTRFS
+//! subvolumes, `btrfs subvolume find-new` is used to find changed files
+//! without reading their contents; otherwise both trees are walked and files
+//! are compared by size and content.
+//!
+//! JSON documents (D-Bus index and state snapshots) additionally get a
+//! structured diff keyed by JSON pointer.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// How a file differs between two snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A single file-level change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChange {
+    /// Path relative to the snapshot root
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+}
+
+/// Which strategy produced a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffMethod {
+    BtrfsFindNew,
+    TreeWalk,
+}
+
+/// A single change inside a JSON document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonChange {
+    /// JSON pointer (RFC 6901) to the changed value
+    pub pointer: String,
+    pub kind: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<Value>,
+}
+
+/// Full diff between two snapshots
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub method: DiffMethod,
+    pub changes: Vec<FileChange>,
+    /// Structured diffs for modified `.json` files, keyed by relative path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub json: BTreeMap<String, Vec<JsonChange>>,
+}
+
+impl SnapshotDiff {
+    pub fn added(&self) -> impl Iterator<Item = &FileChange> {
+        self.changes.iter().filter(|c| c.kind == ChangeKind::Added)
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = &FileChange> {
+        self.changes
+            .iter()
+            .filter(|c| c.kind == ChangeKind::Removed)
+    }
+
+    pub fn modified(&self) -> impl Iterator<Item = &FileChange> {
+        self.changes
+            .iter()
+            .filter(|c| c.kind == ChangeKind::Modified)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Net change in bytes (new minus old)
+    pub fn size_delta(&self) -> i64 {
+        self.changes
+            .iter()
+            .map(|c| c.new_size.unwrap_or(0) as i64 - c.old_size.unwrap_or(0) as i64)
+            .sum()
+    }
+}
+
+/// Diff two snapshot directories
+///
+/// Tries `btrfs subvolume find-new` first and falls back to a tree walk when
+/// either side is not a subvolume, the two are not snapshots of the same
+/// subvolume taken in order, or the btrfs tool is unavailable.
+pub fn diff_snapshots(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<SnapshotDiff> {
+    let from = from.as_ref();
+    let to = to.as_ref();
+
+    if !from.is_dir() {
+        bail!("Snapshot not found: {}", from.display());
+    }
+    if !to.is_dir() {
+        bail!("Snapshot not found: {}", to.display());
+    }
+
+    let (method, changes) = match diff_with_find_new(from, to) {
+        Ok(changes) => (DiffMethod::BtrfsFindNew, changes),
+        Err(e) => {
+            log::debug!("btrfs find-new not usable ({}), walking trees", e);
+            (DiffMethod::TreeWalk, diff_tree_walk(from, to)?)
+        }
+    };
+
+    let mut json = BTreeMap::new();
+    for change in changes.iter().filter(|c| c.kind == ChangeKind::Modified) {
+        if change.path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        match diff_json_files(&from.join(&change.path), &to.join(&change.path)) {
+            Ok(json_changes) => {
+                json.insert(change.path.display().to_string(), json_changes);
+            }
+            Err(e) => log::debug!("Skipping JSON diff for {}: {}", change.path.display(), e),
+        }
+    }
+
+    Ok(SnapshotDiff {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+        method,
+        changes,
+        json,
+    })
+}
+
+/// Diff two JSON files (index.json, state.json, ...)
+pub fn diff_json_files(from: &Path, to: &Path) -> Result<Vec<JsonChange>> {
+    let read = |path: &Path| -> Result<Value> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid JSON in {}", path.display()))
+    };
+    Ok(diff_json(&read(from)?, &read(to)?))
+}
+
+/// Structural diff of two JSON values
+///
+/// Objects are compared key by key and arrays index by index; any other
+/// difference is reported as a modification of the value at that pointer.
+pub fn diff_json(from: &Value, to: &Value) -> Vec<JsonChange> {
+    let mut changes = Vec::new();
+    diff_json_at("", from, to, &mut changes);
+    changes
+}
+
+fn diff_json_at(pointer: &str, from: &Value, to: &Value, changes: &mut Vec<JsonChange>) {
+    match (from, to) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, old) in a {
+                let child = format!("{}/{}", pointer, escape_pointer(key));
+                match b.get(key) {
+                    Some(new) => diff_json_at(&child, old, new, changes),
+                    None => changes.push(JsonChange {
+                        pointer: child,
+                        kind: ChangeKind::Removed,
+                        old: Some(old.clone()),
+                        new: None,
+                    }),
+                }
+            }
+            for (key, new) in b {
+                if !a.contains_key(key) {
+                    changes.push(JsonChange {
+                        pointer: format!("{}/{}", pointer, escape_pointer(key)),
+                        kind: ChangeKind::Added,
+                        old: None,
+                        new: Some(new.clone()),
+                    });
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for (i, old) in a.iter().enumerate() {
+                let child = format!("{}/{}", pointer, i);
+                match b.get(i) {
+                    Some(new) => diff_json_at(&child, old, new, changes),
+                    None => changes.push(JsonChange {
+                        pointer: child,
+                        kind: ChangeKind::Removed,
+                        old: Some(old.clone()),
+                        new: None,
+                    }),
+                }
+            }
+            for (i, new) in b.iter().enumerate().skip(a.len()) {
+                changes.push(JsonChange {
+                    pointer: format!("{}/{}", pointer, i),
+                    kind: ChangeKind::Added,
+                    old: None,
+                    new: Some(new.clone()),
+                });
+            }
+        }
+        (a, b) if a != b => changes.push(JsonChange {
+            pointer: pointer.to_string(),
+            kind: ChangeKind::Modified,
+            old: Some(a.clone()),
+            new: Some(b.clone()),
+        }),
+        _ => {}
+    }
+}
+
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Use `btrfs subvolume find-new` to list files written in `to` after the
+/// generation `from` was taken at. Removals are found by comparing names.
+///
+/// Generations only order writes within one subvolume's history, so this
+/// refuses pairs that are not snapshots of the same subvolume or are reversed.
+fn diff_with_find_new(from: &Path, to: &Path) -> Result<Vec<FileChange>> {
+    let from_info = subvolume_info(from)?;
+    let to_info = subvolume_info(to)?;
+    if !find_new_applies(&from_info, &to_info) {
+        bail!(
+            "{} is not a later snapshot of the same subvolume as {}",
+            to.display(),
+            from.display()
+        );
+    }
+    let from_gen = from_info.generation;
+
+    let output = Command::new("btrfs")
+        .args(["subvolume", "find-new"])
+        .arg(to)
+        .arg(from_gen.to_string())
+        .output()
+        .context("Failed to execute btrfs find-new")?;
+
+    if !output.status.success() {
+        bail!(
+            "btrfs find-new failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let changed = parse_find_new(&String::from_utf8_lossy(&output.stdout));
+    let old_files = list_files(from)?;
+    let new_files = list_files(to)?;
+
+    let mut changes = Vec::new();
+    for path in changed {
+        let Some(new) = new_files.get(&path) else {
+            continue; // written then deleted again
+        };
+        let old_size = old_files.get(&path).map(|old| old.size);
+        changes.push(FileChange {
+            kind: if old_size.is_some() {
+                ChangeKind::Modified
+            } else {
+                ChangeKind::Added
+            },
+            path,
+            old_size,
+            new_size: Some(new.size),
+        });
+    }
+
+    // find-new only reports data writes: pick up removals, empty new files
+    // and symlinks that were repointed
+    for (path, old) in &old_files {
+        match new_files.get(path) {
+            None => changes.push(FileChange {
+                path: path.clone(),
+                kind: ChangeKind::Removed,
+                old_size: Some(old.size),
+                new_size: None,
+            }),
+            Some(new)
+                if (old.target.is_some() || new.target.is_some())
+                    && old.target != new.target
+                    && !changes.iter().any(|c| &c.path == path) =>
+            {
+                changes.push(FileChange {
+                    path: path.clone(),
+                    kind: ChangeKind::Modified,
+                    old_size: Some(old.size),
+                    new_size: Some(new.size),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    for (path, new) in &new_files {
+        if !old_files.contains_key(path) && !changes.iter().any(|c| &c.path == path) {
+            changes.push(FileChange {
+                path: path.clone(),
+                kind: ChangeKind::Added,
+                old_size: None,
+                new_size: Some(new.size),
+            });
+        }
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// Where a snapshot came from and how far its history goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SubvolumeInfo {
+    parent_uuid: Option<String>,
+    generation: u64,
+}
+
+/// find-new on `to` with `from`'s generation only lists the right files when
+/// both share a source subvolume and `from` is the older one
+fn find_new_applies(from: &SubvolumeInfo, to: &SubvolumeInfo) -> bool {
+    from.parent_uuid.is_some()
+        && from.parent_uuid == to.parent_uuid
+        && from.generation < to.generation
+}
+
+fn subvolume_info(path: &Path) -> Result<SubvolumeInfo> {
+    let output = Command::new("btrfs")
+        .args(["subvolume", "show"])
+        .arg(path)
+        .output()
+        .context("Failed to execute btrfs subvolume show")?;
+
+    if !output.status.success() {
+        bail!(
+            "Not a BTRFS subvolume: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(SubvolumeInfo {
+        parent_uuid: parse_parent_uuid(&String::from_utf8_lossy(&output.stdout)),
+        generation: subvolume_generation(path)?,
+    })
+}
+
+/// `Parent UUID:` from `btrfs subvolume show`, `-` meaning not a snapshot
+fn parse_parent_uuid(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Parent UUID:"))
+        .map(str::trim)
+        .filter(|uuid| !uuid.is_empty() && *uuid != "-")
+        .map(str::to_string)
+}
+
+/// Current generation (transid) of a subvolume
+fn subvolume_generation(path: &Path) -> Result<u64> {
+    // Asking for changes after an impossibly high generation prints only the marker
+    let output = Command::new("btrfs")
+        .args(["subvolume", "find-new"])
+        .arg(path)
+        .arg(u64::MAX.to_string())
+        .output()
+        .context("Failed to execute btrfs find-new")?;
+
+    if !output.status.success() {
+        bail!(
+            "Not a BTRFS subvolume: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("transid marker was "))
+        .and_then(|gen| gen.trim().parse().ok())
+        .context("No transid marker in btrfs find-new output")
+}
+
+/// Extract unique file paths from `btrfs subvolume find-new` output
+///
+/// Lines look like:
+/// `inode 257 file offset 0 len 4096 disk start 0 offset 0 gen 12 flags NONE path/to/file`
+fn parse_find_new(output: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = output
+        .lines()
+        .filter(|line| line.starts_with("inode "))
+        .filter_map(|line| {
+            let (_, rest) = line.split_once(" flags ")?;
+            let (_flags, path) = rest.split_once(' ')?;
+            Some(PathBuf::from(path))
+        })
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Walk both trees and compare every regular file and symlink
+fn diff_tree_walk(from: &Path, to: &Path) -> Result<Vec<FileChange>> {
+    let old_files = list_files(from)?;
+    let new_files = list_files(to)?;
+    let mut changes = Vec::new();
+
+    for (path, old) in &old_files {
+        match new_files.get(path) {
+            None => changes.push(FileChange {
+                path: path.clone(),
+                kind: ChangeKind::Removed,
+                old_size: Some(old.size),
+                new_size: None,
+            }),
+            Some(new) => {
+                if nodes_differ(&from.join(path), &to.join(path), old, new)? {
+                    changes.push(FileChange {
+                        path: path.clone(),
+                        kind: ChangeKind::Modified,
+                        old_size: Some(old.size),
+                        new_size: Some(new.size),
+                    });
+                }
+            }
+        }
+    }
+
+    for (path, new) in &new_files {
+        if !old_files.contains_key(path) {
+            changes.push(FileChange {
+                path: path.clone(),
+                kind: ChangeKind::Added,
+                old_size: None,
+                new_size: Some(new.size),
+            });
+        }
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// A regular file or symlink found while walking a tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Node {
+    size: u64,
+    /// Link target for symlinks, which are compared by target instead of content
+    target: Option<PathBuf>,
+}
+
+/// Relative path -> node for every regular file and symlink under `root`
+fn list_files(root: &Path) -> Result<BTreeMap<PathBuf, Node>> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?
+        {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() || file_type.is_symlink() {
+                let relative = path.strip_prefix(root)?.to_path_buf();
+                let target = if file_type.is_symlink() {
+                    Some(std::fs::read_link(&path)?)
+                } else {
+                    None
+                };
+                let size = entry.metadata()?.len();
+                files.insert(relative, Node { size, target });
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn nodes_differ(a: &Path, b: &Path, a_node: &Node, b_node: &Node) -> Result<bool> {
+    if a_node.target.is_some() || b_node.target.is_some() {
+        return Ok(a_node.target != b_node.target);
+    }
+    files_differ(a, b, a_node.size, b_node.size)
+}
+
+fn files_differ(a: &Path, b: &Path, a_size: u64, b_size: u64) -> Result<bool> {
+    if a_size != b_size {
+        return Ok(true);
+    }
+
+    let mut a_file = std::fs::File::open(a)?;
+    let mut b_file = std::fs::File::open(b)?;
+    let mut a_buf = [0u8; 8192];
+    let mut b_buf = [0u8; 8192];
+    loop {
+        let n = a_file.read(&mut a_buf)?;
+        if n == 0 {
+            return Ok(false);
+        }
+        b_file.read_exact(&mut b_buf[..n])?;
+        if a_buf[..n] != b_buf[..n] {
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_find_new() {
+        let output = "\
+inode 257 file offset 0 len 12 disk start 0 offset 0 gen 10 flags INLINE hello.txt
+inode 258 file offset 0 len 4096 disk start 13631488 offset 0 gen 11 flags NONE dir/my file.bin
+inode 258 file offset 4096 len 4096 disk start 13635584 offset 0 gen 11 flags NONE dir/my file.bin
+transid marker was 11
+";
+        assert_eq!(
+            parse_find_new(output),
+            vec![PathBuf::from("dir/my file.bin"), PathBuf::from("hello.txt")]
+        );
+    }
+
+    #[test]
+    fn test_find_new_only_for_ordered_snapshots_of_one_subvolume() {
+        let show = "\
+snapshots/state-2
+\tName: \t\t\tstate-2
+\tUUID: \t\t\t5d1c4f7e-0f39-4c4b-9d6a-3f0e8c1d2b11
+\tParent UUID: \t\t0b8e2a6c-7d41-4e3f-a1b9-6c2d5e8f9a70
+\tReceived UUID: \t\t-
+\tGeneration: \t\t42
+";
+        let parent = parse_parent_uuid(show);
+        assert_eq!(
+            parent.as_deref(),
+            Some("0b8e2a6c-7d41-4e3f-a1b9-6c2d5e8f9a70")
+        );
+        assert_eq!(parse_parent_uuid("\tParent UUID: \t\t-\n"), None);
+
+        let older = SubvolumeInfo {
+            parent_uuid: parent.clone(),
+            generation: 40,
+        };
+        let newer = SubvolumeInfo {
+            parent_uuid: parent,
+            generation: 42,
+        };
+        let unrelated = SubvolumeInfo {
+            parent_uuid: Some("9f3e1d2c-0000-4000-8000-000000000000".to_string()),
+            generation: 50,
+        };
+        let original = SubvolumeInfo {
+            parent_uuid: None,
+            generation: 60,
+        };
+
+        assert!(find_new_applies(&older, &newer));
+        // Reversed arguments would miss every modification made in between
+        assert!(!find_new_applies(&newer, &older));
+        assert!(!find_new_applies(&newer, &newer));
+        assert!(!find_new_applies(&older, &unrelated));
+        assert!(!find_new_applies(&original, &original));
+    }
+
+    #[test]
+    fn test_reversed_tree_walk_diff() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        std::fs::write(from.path().join("edit.txt"), "aaaa").unwrap();
+        std::fs::write(to.path().join("edit.txt"), "bbbb").unwrap();
+        std::fs::write(to.path().join("new.txt"), "hello").unwrap();
+
+        let diff = diff_snapshots(to.path(), from.path()).unwrap();
+        let removed: Vec<_> = diff.removed().map(|c| c.path.clone()).collect();
+        let modified: Vec<_> = diff.modified().map(|c| c.path.clone()).collect();
+        assert_eq!(removed, vec![PathBuf::from("new.txt")]);
+        assert_eq!(modified, vec![PathBuf::from("edit.txt")]);
+    }
+
+    #[test]
+    fn test_tree_walk_diff() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+
+        std::fs::write(from.path().join("same.txt"), "unchanged").unwrap();
+        std::fs::write(to.path().join("same.txt"), "unchanged").unwrap();
+        std::fs::write(from.path().join("gone.txt"), "bye").unwrap();
+        std::fs::create_dir(to.path().join("sub")).unwrap();
+        std::fs::write(to.path().join("sub/new.txt"), "hello").unwrap();
+        std::fs::write(from.path().join("edit.txt"), "aaaa").unwrap();
+        std::fs::write(to.path().join("edit.txt"), "bbbb").unwrap();
+
+        let diff = diff_snapshots(from.path(), to.path()).unwrap();
+        assert_eq!(diff.method, DiffMethod::TreeWalk);
+
+        let added: Vec<_> = diff.added().map(|c| c.path.clone()).collect();
+        let removed: Vec<_> = diff.removed().map(|c| c.path.clone()).collect();
+        let modified: Vec<_> = diff.modified().map(|c| c.path.clone()).collect();
+        assert_eq!(added, vec![PathBuf::from("sub/new.txt")]);
+        assert_eq!(removed, vec![PathBuf::from("gone.txt")]);
+        assert_eq!(modified, vec![PathBuf::from("edit.txt")]);
+        assert_eq!(diff.size_delta(), 5 - 3);
+    }
+
+    #[test]
+    fn test_json_files_get_structured_diff() {
+        let from = tempfile::tempdir().unwrap();
+        let to = tempfile::tempdir().unwrap();
+        std::fs::write(
+            from.path().join("index.json"),
+            r#"{"services":{"org.a":{}},"statistics":{"total_services":1}}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            to.path().join("index.json"),
+            r#"{"services":{"org.a":{},"org.b":{}},"statistics":{"total_services":2}}"#,
+        )
+        .unwrap();
+
+        let diff = diff_snapshots(from.path(), to.path()).unwrap();
+        let json_changes = &diff.json["index.json"];
+        assert_eq!(json_changes.len(), 2);
+        assert!(json_changes
+            .iter()
+            .any(|c| c.pointer == "/services/org.b" && c.kind == ChangeKind::Added));
+        assert!(json_changes
+            .iter()
+            .any(|c| c.pointer == "/statistics/total_services" && c.kind == ChangeKind::Modified));
+    }
+
+    #[test]
+    fn test_diff_json() {
+        let from = json!({"plugins": {"net": {"mtu": 1500}, "lxc": [1, 2]}, "a/b": 1});
+        let to = json!({"plugins": {"net": {"mtu": 9000}, "lxc": [1]}, "version": 1});
+
+        let changes = diff_json(&from, &to);
+        let mut summary: Vec<_> = changes
+            .iter()
+            .map(|c| (c.pointer.as_str(), c.kind))
+            .collect();
+        summary.sort_by_key(|(pointer, _)| *pointer);
+
+        assert_eq!(
+            summary,
+            vec![
+                ("/a~1b", ChangeKind::Removed),
+                ("/plugins/lxc/1", ChangeKind::Removed),
+                ("/plugins/net/mtu", ChangeKind::Modified),
+                ("/version", ChangeKind::Added),
+            ]
+        );
+    }
+}
@@ -0,0 +1,480 @@
+//! Pre-apply host filesystem snapshots
+//!
+//! Most plugin checkpoints only hold a JSON view of the plugin's state, which
+//! is not enough to undo file edits (resolv.conf, container configs, ...).
+//! When enabled, the state manager snapshots `/etc` and the op-dbus config
+//! subvolume right before applying changes and references the snapshot from
+//! every `Checkpoint.backend_checkpoint`. If a plugin fails and cannot roll
+//! back by itself, the files it owns (`StatePlugin::owned_paths`) are restored
+//! from that snapshot.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+use crate::state::plugin::Checkpoint;
+
+/// Key used inside `Checkpoint.backend_checkpoint`
+pub const BACKEND_KEY: &str = "host_snapshot";
+
+/// Which paths to snapshot before apply and where to keep the snapshots
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostSnapshotConfig {
+    /// Directories captured before Phase 3 (BTRFS subvolumes are snapshotted,
+    /// anything else is copied with reflinks where possible)
+    pub paths: Vec<PathBuf>,
+    /// Where pre-apply snapshots are stored
+    pub snapshots_dir: PathBuf,
+    /// Number of pre-apply snapshots to keep
+    pub keep: usize,
+    /// Restore files automatically when an apply fails
+    pub restore_on_failure: bool,
+}
+
+impl Default for HostSnapshotConfig {
+    fn default() -> Self {
+        Self {
+            paths: vec![
+                PathBuf::from("/etc"),
+                PathBuf::from("/var/lib/op-dbus/@config"),
+            ],
+            snapshots_dir: PathBuf::from("/var/lib/op-dbus/@snapshots/pre-apply"),
+            keep: 5,
+            restore_on_failure: true,
+        }
+    }
+}
+
+/// How a path was captured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureMethod {
+    Btrfs,
+    Copy,
+}
+
+/// One captured path inside a host snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostSnapshotEntry {
+    pub source: PathBuf,
+    pub snapshot: PathBuf,
+    pub method: CaptureMethod,
+}
+
+/// A pre-apply host snapshot, stored in checkpoints as JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostSnapshot {
+    pub id: String,
+    pub created: i64,
+    pub root: PathBuf,
+    pub entries: Vec<HostSnapshotEntry>,
+}
+
+impl HostSnapshot {
+    /// Snapshot every configured path that exists
+    pub async fn take(config: &HostSnapshotConfig) -> Result<Self> {
+        let created = chrono::Utc::now();
+        let id = format!("pre-apply-{}", created.format("%Y-%m-%d-%H%M%S%.3f"));
+        let root = config.snapshots_dir.join(&id);
+        tokio::fs::create_dir_all(&root)
+            .await
+            .with_context(|| format!("Failed to create {}", root.display()))?;
+
+        let mut entries = Vec::new();
+        for source in &config.paths {
+            if !source.exists() {
+                log::debug!("Skipping missing host snapshot path {}", source.display());
+                continue;
+            }
+
+            let snapshot = root.join(snapshot_name(source));
+            let method = if is_subvolume(source).await {
+                run("btrfs", &["subvolume", "snapshot", "-r"], source, &snapshot).await?;
+                CaptureMethod::Btrfs
+            } else {
+                run("cp", &["-a", "--reflink=auto"], source, &snapshot).await?;
+                CaptureMethod::Copy
+            };
+
+            log::info!(
+                "Captured {} -> {} ({:?})",
+                source.display(),
+                snapshot.display(),
+                method
+            );
+            entries.push(HostSnapshotEntry {
+                source: source.clone(),
+                snapshot,
+                method,
+            });
+        }
+
+        let snapshot = Self {
+            id,
+            created: created.timestamp(),
+            root,
+            entries,
+        };
+
+        tokio::fs::write(
+            snapshot.root.join("manifest.json"),
+            serde_json::to_string_pretty(&snapshot)?,
+        )
+        .await?;
+
+        prune(config).await?;
+        Ok(snapshot)
+    }
+
+    /// Attach this snapshot to a checkpoint, keeping any plugin backend data
+    pub fn attach(&self, checkpoint: &mut Checkpoint) -> Result<()> {
+        let value = serde_json::to_value(self)?;
+        match checkpoint.backend_checkpoint.as_mut() {
+            Some(Value::Object(map)) => {
+                map.insert(BACKEND_KEY.to_string(), value);
+            }
+            Some(other) => {
+                let plugin_data = other.take();
+                checkpoint.backend_checkpoint = Some(serde_json::json!({
+                    "plugin": plugin_data,
+                    BACKEND_KEY: value,
+                }));
+            }
+            None => {
+                checkpoint.backend_checkpoint = Some(serde_json::json!({ BACKEND_KEY: value }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Extract the host snapshot referenced by a checkpoint, if any
+    pub fn from_checkpoint(checkpoint: &Checkpoint) -> Option<Self> {
+        let value = checkpoint.backend_checkpoint.as_ref()?.get(BACKEND_KEY)?;
+        serde_json::from_value(value.clone()).ok()
+    }
+
+    /// Put every captured path back the way it was when the snapshot was taken
+    ///
+    /// Returns the number of files written or removed.
+    pub async fn restore(&self) -> Result<usize> {
+        let everything: Vec<PathBuf> = self.entries.iter().map(|e| e.source.clone()).collect();
+        self.restore_paths(&everything).await
+    }
+
+    /// Restore only the files at or below `paths`, leaving the rest of the
+    /// captured trees (other plugins' changes, unrelated host edits) alone
+    ///
+    /// Returns the number of files written or removed.
+    pub async fn restore_paths(&self, paths: &[PathBuf]) -> Result<usize> {
+        let mut touched = 0;
+        for entry in &self.entries {
+            let scope: Vec<PathBuf> = paths
+                .iter()
+                .filter_map(|path| path.strip_prefix(&entry.source).ok())
+                .map(Path::to_path_buf)
+                .collect();
+            if scope.is_empty() {
+                continue;
+            }
+
+            let snapshot = entry.snapshot.clone();
+            let source = entry.source.clone();
+            touched +=
+                tokio::task::spawn_blocking(move || restore_tree(&snapshot, &source, &scope))
+                    .await??;
+            log::info!(
+                "Restored {} from {}",
+                entry.source.display(),
+                entry.snapshot.display()
+            );
+        }
+        Ok(touched)
+    }
+}
+
+/// Directory name for a captured path (`/etc` -> `etc`, `/var/lib/x/@config` -> `var-lib-x-@config`)
+fn snapshot_name(source: &Path) -> String {
+    let name = source.to_string_lossy().trim_matches('/').replace('/', "-");
+    if name.is_empty() {
+        "root".to_string()
+    } else {
+        name
+    }
+}
+
+async fn is_subvolume(path: &Path) -> bool {
+    Command::new("btrfs")
+        .args(["subvolume", "show"])
+        .arg(path)
+        .output()
+        .await
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+async fn run(program: &str, args: &[&str], source: &Path, dest: &Path) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .arg(source)
+        .arg(dest)
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {}", program))?;
+
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            program,
+            source.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+/// Make `live` match `snapshot` file by file, for paths under `scope`
+/// (relative to both roots; an empty path covers the whole tree)
+fn restore_tree(snapshot: &Path, live: &Path, scope: &[PathBuf]) -> Result<usize> {
+    use crate::snapshot_diff::{diff_snapshots, ChangeKind};
+
+    let diff = diff_snapshots(snapshot, live)?;
+    let mut touched = 0;
+    for change in &diff.changes {
+        if !scope.iter().any(|path| change.path.starts_with(path)) {
+            continue;
+        }
+        let live_path = live.join(&change.path);
+        match change.kind {
+            // Created after the snapshot was taken
+            ChangeKind::Added => {
+                std::fs::remove_file(&live_path)
+                    .with_context(|| format!("Failed to remove {}", live_path.display()))?;
+            }
+            ChangeKind::Removed | ChangeKind::Modified => {
+                let saved = snapshot.join(&change.path);
+                if let Some(parent) = live_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                let saved_link = std::fs::symlink_metadata(&saved)?.is_symlink();
+                let live_link = std::fs::symlink_metadata(&live_path)
+                    .map(|m| m.is_symlink())
+                    .unwrap_or(false);
+                // Replace links instead of writing through them
+                if (saved_link || live_link) && live_path.symlink_metadata().is_ok() {
+                    std::fs::remove_file(&live_path)
+                        .with_context(|| format!("Failed to remove {}", live_path.display()))?;
+                }
+
+                if saved_link {
+                    std::os::unix::fs::symlink(std::fs::read_link(&saved)?, &live_path)
+                        .with_context(|| format!("Failed to restore {}", live_path.display()))?;
+                } else {
+                    std::fs::copy(&saved, &live_path)
+                        .with_context(|| format!("Failed to restore {}", live_path.display()))?;
+                    restore_owner(&saved, &live_path)?;
+                }
+            }
+        }
+        touched += 1;
+    }
+    Ok(touched)
+}
+
+fn restore_owner(saved: &Path, live: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(saved)?;
+    if let Err(e) = std::os::unix::fs::chown(live, Some(metadata.uid()), Some(metadata.gid())) {
+        log::debug!("Could not restore owner of {}: {}", live.display(), e);
+    }
+    Ok(())
+}
+
+/// Delete the oldest pre-apply snapshots beyond `config.keep`
+async fn prune(config: &HostSnapshotConfig) -> Result<()> {
+    let mut ids = Vec::new();
+    let mut entries = tokio::fs::read_dir(&config.snapshots_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("pre-apply-") && entry.file_type().await?.is_dir() {
+            ids.push(entry.path());
+        }
+    }
+
+    // Names embed a sortable timestamp
+    ids.sort();
+    let excess = ids.len().saturating_sub(config.keep);
+    for root in ids.into_iter().take(excess) {
+        log::info!("Pruning pre-apply snapshot {}", root.display());
+        let manifest: Option<HostSnapshot> = tokio::fs::read_to_string(root.join("manifest.json"))
+            .await
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok());
+
+        for entry in manifest.map(|m| m.entries).unwrap_or_default() {
+            if entry.method == CaptureMethod::Btrfs {
+                let status = Command::new("btrfs")
+                    .args(["subvolume", "delete"])
+                    .arg(&entry.snapshot)
+                    .status()
+                    .await?;
+                if !status.success() {
+                    log::warn!("Failed to delete subvolume {}", entry.snapshot.display());
+                }
+            }
+        }
+        tokio::fs::remove_dir_all(&root).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(backend: Option<Value>) -> Checkpoint {
+        Checkpoint {
+            id: "test-1".to_string(),
+            plugin: "test".to_string(),
+            timestamp: 0,
+            state_snapshot: serde_json::json!({}),
+            backend_checkpoint: backend,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_name() {
+        assert_eq!(snapshot_name(Path::new("/etc")), "etc");
+        assert_eq!(
+            snapshot_name(Path::new("/var/lib/op-dbus/@config")),
+            "var-lib-op-dbus-@config"
+        );
+        assert_eq!(snapshot_name(Path::new("/")), "root");
+    }
+
+    #[test]
+    fn test_attach_preserves_plugin_data() {
+        let snapshot = HostSnapshot {
+            id: "pre-apply-x".to_string(),
+            created: 1,
+            root: PathBuf::from("/snap"),
+            entries: vec![],
+        };
+
+        let mut empty = checkpoint(None);
+        snapshot.attach(&mut empty).unwrap();
+        assert_eq!(
+            HostSnapshot::from_checkpoint(&empty).unwrap().id,
+            "pre-apply-x"
+        );
+
+        let mut object = checkpoint(Some(serde_json::json!({"flows": 3})));
+        snapshot.attach(&mut object).unwrap();
+        assert_eq!(object.backend_checkpoint.as_ref().unwrap()["flows"], 3);
+        assert!(HostSnapshot::from_checkpoint(&object).is_some());
+
+        let mut scalar = checkpoint(Some(serde_json::json!("opaque")));
+        snapshot.attach(&mut scalar).unwrap();
+        assert_eq!(
+            scalar.backend_checkpoint.as_ref().unwrap()["plugin"],
+            "opaque"
+        );
+        assert!(HostSnapshot::from_checkpoint(&scalar).is_some());
+
+        assert!(HostSnapshot::from_checkpoint(&checkpoint(None)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_take_and_restore_copy() {
+        let live = tempfile::tempdir().unwrap();
+        let store = tempfile::tempdir().unwrap();
+        std::fs::write(live.path().join("resolv.conf"), "nameserver 1.1.1.1\n").unwrap();
+        std::fs::write(live.path().join("hosts"), "127.0.0.1 localhost\n").unwrap();
+
+        let config = HostSnapshotConfig {
+            paths: vec![live.path().to_path_buf()],
+            snapshots_dir: store.path().to_path_buf(),
+            keep: 5,
+            restore_on_failure: true,
+        };
+        let snapshot = HostSnapshot::take(&config).await.unwrap();
+        assert_eq!(snapshot.entries.len(), 1);
+
+        // Simulate a half-applied change
+        std::fs::write(live.path().join("resolv.conf"), "nameserver 9.9.9.9\n").unwrap();
+        std::fs::remove_file(live.path().join("hosts")).unwrap();
+        std::fs::write(live.path().join("stray.conf"), "x").unwrap();
+
+        assert_eq!(snapshot.restore().await.unwrap(), 3);
+        assert_eq!(
+            std::fs::read_to_string(live.path().join("resolv.conf")).unwrap(),
+            "nameserver 1.1.1.1\n"
+        );
+        assert!(live.path().join("hosts").exists());
+        assert!(!live.path().join("stray.conf").exists());
+    }
+
+    #[tokio::test]
+    async fn test_restore_paths_is_scoped_and_keeps_symlinks() {
+        let live = tempfile::tempdir().unwrap();
+        let store = tempfile::tempdir().unwrap();
+        std::fs::write(
+            live.path().join("stub-resolv.conf"),
+            "nameserver 127.0.0.53\n",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("stub-resolv.conf", live.path().join("resolv.conf")).unwrap();
+        std::fs::write(live.path().join("hosts"), "127.0.0.1 localhost\n").unwrap();
+
+        let config = HostSnapshotConfig {
+            paths: vec![live.path().to_path_buf()],
+            snapshots_dir: store.path().to_path_buf(),
+            keep: 5,
+            restore_on_failure: true,
+        };
+        let snapshot = HostSnapshot::take(&config).await.unwrap();
+
+        // The failing plugin replaced the link; someone else edited hosts
+        std::fs::remove_file(live.path().join("resolv.conf")).unwrap();
+        std::fs::write(live.path().join("resolv.conf"), "nameserver 9.9.9.9\n").unwrap();
+        std::fs::write(live.path().join("hosts"), "10.0.0.1 db\n").unwrap();
+        std::fs::write(live.path().join("other.conf"), "x").unwrap();
+
+        let owned = vec![live.path().join("resolv.conf")];
+        assert_eq!(snapshot.restore_paths(&owned).await.unwrap(), 1);
+        assert_eq!(
+            std::fs::read_link(live.path().join("resolv.conf")).unwrap(),
+            PathBuf::from("stub-resolv.conf")
+        );
+        assert_eq!(
+            std::fs::read_to_string(live.path().join("hosts")).unwrap(),
+            "10.0.0.1 db\n"
+        );
+        assert!(live.path().join("other.conf").exists());
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_newest() {
+        let store = tempfile::tempdir().unwrap();
+        for name in [
+            "pre-apply-2025-01-01",
+            "pre-apply-2025-01-02",
+            "pre-apply-2025-01-03",
+        ] {
+            std::fs::create_dir(store.path().join(name)).unwrap();
+        }
+        let config = HostSnapshotConfig {
+            paths: vec![],
+            snapshots_dir: store.path().to_path_buf(),
+            keep: 2,
+            restore_on_failure: false,
+        };
+
+        prune(&config).await.unwrap();
+        assert!(!store.path().join("pre-apply-2025-01-01").exists());
+        assert!(store.path().join("pre-apply-2025-01-03").exists());
+    }
+}
@@ -1,12 +1,15 @@
 // State manager orchestrator - coordinates plugins and provides atomic operations
 // Note: Ledger functionality has been replaced with streaming blockchain
-use crate::state::plugin::{ApplyResult, Checkpoint, StateDiff, StatePlugin};
+use crate::state::host_snapshot::{HostSnapshot, HostSnapshotConfig};
+use crate::metrics;
+use crate::state::plugin::{ApplyResult, Checkpoint, StateAction, StateDiff, StatePlugin};
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
 
 #[cfg(feature = "streaming-blockchain")]
@@ -39,6 +42,7 @@ pub struct ApplyReport {
 pub struct StateManager {
     plugins: Arc<RwLock<HashMap<String, Arc<dyn StatePlugin>>>>,
     workflows: std::sync::Mutex<crate::state::plugin_workflow::PluginWorkflowManager>,
+    host_snapshots: Option<HostSnapshotConfig>,
     #[cfg(feature = "streaming-blockchain")]
     blockchain_sender: Option<FootprintSender>,
 }
@@ -55,11 +59,108 @@ impl StateManager {
         Self {
             plugins: Arc::new(RwLock::new(HashMap::new())),
             workflows: std::sync::Mutex::new(crate::state::plugin_workflow::PluginWorkflowManager::new()),
+            host_snapshots: None,
             #[cfg(feature = "streaming-blockchain")]
             blockchain_sender: None,
         }
     }
 
+    /// Snapshot host files before applying changes and reference the snapshot from checkpoints
+    pub fn with_host_snapshots(mut self, config: HostSnapshotConfig) -> Self {
+        self.host_snapshots = Some(config);
+        self
+    }
+
+    /// Take a pre-apply host snapshot (if enabled) and attach it to every checkpoint
+    async fn take_host_snapshot(
+        &self,
+        checkpoints: &mut [(String, Checkpoint)],
+    ) -> Option<HostSnapshot> {
+        let config = self.host_snapshots.as_ref()?;
+        match HostSnapshot::take(config).await {
+            Ok(snapshot) => {
+                log::info!("Created pre-apply host snapshot: {}", snapshot.id);
+                for (plugin_name, checkpoint) in checkpoints.iter_mut() {
+                    if let Err(e) = snapshot.attach(checkpoint) {
+                        log::warn!("Failed to attach host snapshot to {}: {}", plugin_name, e);
+                    }
+                }
+                Some(snapshot)
+            }
+            Err(e) => {
+                log::error!("Failed to create pre-apply host snapshot: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Recover from a failed plugin apply
+    ///
+    /// Plugins that support rollback are rolled back to their checkpoint; for
+    /// the rest (or if their rollback fails) the host files the plugin owns
+    /// are restored from the pre-apply snapshot, when host snapshots are
+    /// enabled with `restore_on_failure`. Returns a description of what was done.
+    async fn recover_failed_plugin(
+        &self,
+        plugin_name: &str,
+        checkpoints: &[(String, Checkpoint)],
+        host_snapshot: Option<&HostSnapshot>,
+    ) -> Option<String> {
+        let plugin = self.get_plugin(plugin_name).await?;
+        let checkpoint = checkpoints
+            .iter()
+            .find(|(name, _)| name == plugin_name)
+            .map(|(_, checkpoint)| checkpoint);
+
+        if let Some(checkpoint) = checkpoint {
+            if plugin.capabilities().supports_rollback {
+                match plugin.rollback(checkpoint).await {
+                    Ok(()) => {
+                        return Some(format!(
+                            "Rolled back {} to checkpoint {}",
+                            plugin_name, checkpoint.id
+                        ))
+                    }
+                    Err(e) => log::warn!(
+                        "Rollback of {} failed, falling back to host snapshot: {}",
+                        plugin_name,
+                        e
+                    ),
+                }
+            }
+        }
+
+        let config = self.host_snapshots.as_ref()?;
+        if !config.restore_on_failure {
+            return None;
+        }
+
+        // Other plugins' changes and unrelated host edits stay in place
+        let owned = plugin.owned_paths();
+        if owned.is_empty() {
+            log::warn!(
+                "{} declares no host files, not restoring from the pre-apply snapshot",
+                plugin_name
+            );
+            return None;
+        }
+
+        // Prefer the snapshot referenced by the plugin's own checkpoint
+        let snapshot = checkpoint
+            .and_then(HostSnapshot::from_checkpoint)
+            .or_else(|| host_snapshot.cloned())?;
+        match snapshot.restore_paths(&owned).await {
+            Ok(count) => Some(format!(
+                "Restored {} host files of {} from pre-apply snapshot {}",
+                count, plugin_name, snapshot.id
+            )),
+            Err(e) => {
+                log::error!("Failed to restore host snapshot {}: {}", snapshot.id, e);
+                None
+            }
+        }
+    }
+
 
     /// Enable blockchain footprints by providing a sender to a StreamingBlockchain receiver
     #[cfg(feature = "streaming-blockchain")]
@@ -171,7 +272,10 @@ impl StateManager {
         let mut state = HashMap::new();
 
         for (name, plugin) in plugins.iter() {
-            match plugin.query_current_state().await {
+            let started = Instant::now();
+            let result = plugin.query_current_state().await;
+            metrics::record_query(name, started);
+            match result {
                 Ok(plugin_state) => {
                     state.insert(name.clone(), plugin_state);
                 }
@@ -190,11 +294,36 @@ impl StateManager {
         let plugins = self.plugins.read().await;
 
         match plugins.get(plugin_name) {
-            Some(plugin) => plugin.query_current_state().await,
+            Some(plugin) => {
+                let started = Instant::now();
+                let result = plugin.query_current_state().await;
+                metrics::record_query(plugin_name, started);
+                result
+            }
             None => Err(anyhow!("Plugin not found: {}", plugin_name)),
         }
     }
 
+    /// Query a plugin and diff it against its desired state, recording query latency and drift
+    async fn diff_plugin(
+        plugin_name: &str,
+        plugin: &Arc<dyn StatePlugin>,
+        desired_state: &Value,
+    ) -> Result<StateDiff> {
+        let started = Instant::now();
+        let current_state = plugin.query_current_state().await;
+        metrics::record_query(plugin_name, started);
+
+        let diff = plugin.calculate_diff(&current_state?, desired_state).await?;
+        let pending = diff
+            .actions
+            .iter()
+            .filter(|action| !matches!(action, StateAction::NoOp { .. }))
+            .count();
+        metrics::record_drift(plugin_name, pending);
+        Ok(diff)
+    }
+
     /// Calculate diffs for all plugins
     async fn calculate_all_diffs(&self, desired: &DesiredState) -> Result<Vec<StateDiff>> {
         let plugins = self.plugins.read().await;
@@ -202,8 +331,7 @@ impl StateManager {
 
         for (plugin_name, desired_state) in &desired.plugins {
             if let Some(plugin) = plugins.get(plugin_name) {
-                let current_state = plugin.query_current_state().await?;
-                let diff = plugin.calculate_diff(&current_state, desired_state).await?;
+                let diff = Self::diff_plugin(plugin_name, plugin, desired_state).await?;
 
                 // Only include diffs that have actual actions
                 if !diff.actions.is_empty() {
@@ -224,16 +352,36 @@ impl StateManager {
 
         log::info!("Starting atomic state apply operation");
 
-        // Phase 1: Create checkpoints for all affected plugins
+        // Phase 1: Calculate diffs
+        log::info!("Phase 1: Calculating diffs");
+        let diffs = match self.calculate_all_diffs(&desired).await {
+            Ok(diffs) => diffs,
+            Err(e) => {
+                log::error!("Failed to calculate diffs: {}", e);
+                return Err(e);
+            }
+        };
+
+        if diffs.is_empty() {
+            log::info!("No changes needed - current state matches desired state");
+            return Ok(ApplyReport {
+                success: true,
+                results,
+                checkpoints,
+            });
+        }
+
+        // Phase 2: Checkpoint what each plugin is about to change
         // Note: Lock is acquired briefly for each plugin to minimize contention
-        log::info!("Phase 1: Creating checkpoints");
-        for (plugin_name, _desired_state) in desired.plugins.iter() {
+        log::info!("Phase 2: Creating checkpoints");
+        for diff in &diffs {
+            let plugin_name = &diff.plugin;
             // Acquire lock, check if plugin exists, and create checkpoint
             let checkpoint_opt = {
                 let plugins = self.plugins.read().await;
                 if let Some(plugin) = plugins.get(plugin_name) {
-                    // Call create_checkpoint while holding the lock (briefly)
-                    match plugin.create_checkpoint().await {
+                    // Call create_checkpoint_for while holding the lock (briefly)
+                    match plugin.create_checkpoint_for(diff).await {
                         Ok(checkpoint) => Some(checkpoint),
                         Err(e) => {
                             log::error!("Failed to create checkpoint for {}: {}", plugin_name, e);
@@ -251,29 +399,13 @@ impl StateManager {
             }
         }
 
-        // Phase 2: Calculate diffs
-        log::info!("Phase 2: Calculating diffs");
-        let diffs = match self.calculate_all_diffs(&desired).await {
-            Ok(diffs) => diffs,
-            Err(e) => {
-                log::error!("Failed to calculate diffs: {}", e);
-                return Err(e);
-            }
-        };
-
-        if diffs.is_empty() {
-            log::info!("No changes needed - current state matches desired state");
-            return Ok(ApplyReport {
-                success: true,
-                results,
-                checkpoints,
-            });
-        }
+        let host_snapshot = self.take_host_snapshot(&mut checkpoints).await;
 
         // Phase 3: Apply changes in dependency order
         log::info!("Phase 3: Applying changes ({} plugins)", diffs.len());
         for diff in diffs {
             // Acquire lock, check if plugin exists, and apply state
+            let started = Instant::now();
             let apply_result = {
                 let plugins = self.plugins.read().await;
                 if let Some(plugin) = plugins.get(&diff.plugin) {
@@ -283,9 +415,16 @@ impl StateManager {
                     None
                 }
             };
+            if let Some(result) = &apply_result {
+                metrics::record_apply(
+                    &diff.plugin,
+                    matches!(result, Ok(r) if r.success),
+                    started,
+                );
+            }
 
             match apply_result {
-                Some(Ok(result)) => {
+                Some(Ok(mut result)) => {
                     log::info!("Applied state for plugin: {}", diff.plugin);
                     log::info!(
                         "Result success: {}, changes: {:?}, errors: {:?}",
@@ -309,7 +448,14 @@ impl StateManager {
 
                     // Check if result indicates failure
                     if !result.success {
-                        log::error!("Plugin {} returned success=false, but not triggering rollback (treating as warning)", diff.plugin);
+                        log::error!("Plugin {} returned success=false, recovering it without rolling back other plugins", diff.plugin);
+                        if let Some(recovery) = self
+                            .recover_failed_plugin(&diff.plugin, &checkpoints, host_snapshot.as_ref())
+                            .await
+                        {
+                            log::warn!("{}", recovery);
+                            result.changes_applied.push(recovery);
+                        }
                     }
 
                     // State changes are automatically logged to streaming blockchain via plugin footprints
@@ -319,7 +465,7 @@ impl StateManager {
                 }
                 Some(Err(e)) => {
                     log::error!(
-                        "State apply FAILED for {}: {}, SKIPPING rollback (disabled for testing)",
+                        "State apply FAILED for {}: {}, recovering only this plugin (full rollback disabled for testing)",
                         diff.plugin,
                         e
                     );
@@ -328,10 +474,16 @@ impl StateManager {
                     // self.rollback_all(&checkpoints).await?;
                     // return Err(e);
 
-                    // Continue anyway
+                    // Continue anyway, rolling the plugin back (or restoring its host files)
+                    let recovery = self
+                        .recover_failed_plugin(&diff.plugin, &checkpoints, host_snapshot.as_ref())
+                        .await;
+                    if let Some(recovery) = &recovery {
+                        log::warn!("{}", recovery);
+                    }
                     results.push(ApplyResult {
                         success: false,
-                        changes_applied: vec![],
+                        changes_applied: recovery.into_iter().collect(),
                         errors: vec![format!("Failed: {}", e)],
                         checkpoint: None,
                     });
@@ -389,6 +541,25 @@ impl StateManager {
         self.calculate_all_diffs(&desired).await
     }
 
+    /// Diff one plugin against its desired state without applying; returns the
+    /// number of pending actions, also exported as the plugin's drift metric
+    pub async fn check_drift(&self, desired: &DesiredState, plugin_name: &str) -> Result<usize> {
+        let desired_state = desired
+            .plugins
+            .get(plugin_name)
+            .ok_or_else(|| anyhow!("Plugin '{}' not found in state file", plugin_name))?;
+        let plugins = self.plugins.read().await;
+        let plugin = plugins
+            .get(plugin_name)
+            .ok_or_else(|| anyhow!("Plugin '{}' not registered", plugin_name))?;
+        let diff = Self::diff_plugin(plugin_name, plugin, desired_state).await?;
+        Ok(diff
+            .actions
+            .iter()
+            .filter(|action| !matches!(action, StateAction::NoOp { .. }))
+            .count())
+    }
+
     /// Apply state for a single plugin only (safer)
     pub async fn apply_state_single_plugin(
         &self,
@@ -406,12 +577,32 @@ impl StateManager {
             .get(plugin_name)
             .ok_or_else(|| anyhow!("Plugin '{}' not found in state file", plugin_name))?;
 
-        // Phase 1: Create checkpoint for this plugin
-        log::info!("Phase 1: Creating checkpoint for {}", plugin_name);
+        // Phase 1: Calculate diff for this plugin
+        log::info!("Phase 1: Calculating diff for {}", plugin_name);
+        let diff = {
+            let plugins = self.plugins.read().await;
+            if let Some(plugin) = plugins.get(plugin_name) {
+                Self::diff_plugin(plugin_name, plugin, plugin_desired_state).await?
+            } else {
+                return Err(anyhow!("Plugin '{}' not registered", plugin_name));
+            }
+        };
+
+        if diff.actions.is_empty() {
+            log::info!("No changes needed for {}", plugin_name);
+            return Ok(ApplyReport {
+                success: true,
+                results,
+                checkpoints,
+            });
+        }
+
+        // Phase 2: Checkpoint what the diff is about to change
+        log::info!("Phase 2: Creating checkpoint for {}", plugin_name);
         let checkpoint_opt = {
             let plugins = self.plugins.read().await;
             if let Some(plugin) = plugins.get(plugin_name) {
-                match plugin.create_checkpoint().await {
+                match plugin.create_checkpoint_for(&diff).await {
                     Ok(checkpoint) => Some(checkpoint),
                     Err(e) => {
                         log::error!("Failed to create checkpoint for {}: {}", plugin_name, e);
@@ -428,31 +619,11 @@ impl StateManager {
             checkpoints.push((plugin_name.to_string(), checkpoint));
         }
 
-        // Phase 2: Calculate diff for this plugin
-        log::info!("Phase 2: Calculating diff for {}", plugin_name);
-        let diff = {
-            let plugins = self.plugins.read().await;
-            if let Some(plugin) = plugins.get(plugin_name) {
-                let current_state = plugin.query_current_state().await?;
-                plugin
-                    .calculate_diff(&current_state, plugin_desired_state)
-                    .await?
-            } else {
-                return Err(anyhow!("Plugin '{}' not registered", plugin_name));
-            }
-        };
-
-        if diff.actions.is_empty() {
-            log::info!("No changes needed for {}", plugin_name);
-            return Ok(ApplyReport {
-                success: true,
-                results,
-                checkpoints,
-            });
-        }
+        let host_snapshot = self.take_host_snapshot(&mut checkpoints).await;
 
         // Phase 3: Apply changes
         log::info!("Phase 3: Applying changes for {}", plugin_name);
+        let started = Instant::now();
         let apply_result = {
             let plugins = self.plugins.read().await;
             if let Some(plugin) = plugins.get(plugin_name) {
@@ -461,9 +632,14 @@ impl StateManager {
                 return Err(anyhow!("Plugin '{}' not registered", plugin_name));
             }
         };
+        metrics::record_apply(
+            plugin_name,
+            matches!(&apply_result, Ok(r) if r.success),
+            started,
+        );
 
         match apply_result {
-            Ok(result) => {
+            Ok(mut result) => {
                 log::info!(
                     "Applied state for {}: success={}, changes={:?}",
                     plugin_name,
@@ -471,6 +647,16 @@ impl StateManager {
                     result.changes_applied
                 );
 
+                if !result.success {
+                    if let Some(recovery) = self
+                        .recover_failed_plugin(plugin_name, &checkpoints, host_snapshot.as_ref())
+                        .await
+                    {
+                        log::warn!("{}", recovery);
+                        result.changes_applied.push(recovery);
+                    }
+                }
+
                 //                 // Record footprint
                 //                 let data = serde_json::json!({
                 //                     "plugin": plugin_name,
@@ -489,6 +675,13 @@ impl StateManager {
             Err(e) => {
                 log::error!("Failed to apply state for {}: {}", plugin_name, e);
 
+                if let Some(recovery) = self
+                    .recover_failed_plugin(plugin_name, &checkpoints, host_snapshot.as_ref())
+                    .await
+                {
+                    log::warn!("{}", recovery);
+                }
+
                 //                 // Record error footprint
                 //                 let data = serde_json::json!({
                 //                     "plugin": plugin_name,
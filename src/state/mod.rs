@@ -4,10 +4,12 @@ pub mod auto_plugin;
 pub mod crypto;
 pub mod dbus_plugin_base;
 pub mod dbus_server;
+pub mod host_snapshot;
 pub mod manager;
 pub mod plugin;
 pub mod plugin_workflow;
 pub mod plugins;
 pub mod plugtree;
+pub mod secret;
 
 pub use manager::StateManager;
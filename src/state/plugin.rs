@@ -43,6 +43,20 @@ pub trait StatePlugin: Send + Sync {
     /// Create a checkpoint for rollback capability
     async fn create_checkpoint(&self) -> Result<Checkpoint>;
 
+    /// Checkpoint taken right before `diff` is applied. Plugins whose full
+    /// checkpoints are costly or cover more than they change narrow it to
+    /// what the diff touches; by default it is a full checkpoint.
+    async fn create_checkpoint_for(&self, _diff: &StateDiff) -> Result<Checkpoint> {
+        self.create_checkpoint().await
+    }
+
+    /// Host files and directories this plugin writes. When the plugin fails
+    /// and cannot roll back by itself, only these are restored from the
+    /// pre-apply host snapshot.
+    fn owned_paths(&self) -> Vec<std::path::PathBuf> {
+        Vec::new()
+    }
+
     /// Rollback to a previous checkpoint
     #[allow(dead_code)]
     async fn rollback(&self, checkpoint: &Checkpoint) -> Result<()>;
@@ -0,0 +1,798 @@
+//! dhcp plugin - declarative DHCP and DNS forwarding through dnsmasq
+//!
+//! Design
+//! - Desired state is rendered into dnsmasq config fragments: one global
+//!   `op-dbus-dhcp.conf` (upstream servers, domain) and one
+//!   `op-dbus-dhcp-<bridge>.conf` per served bridge. Other files in the
+//!   directory are never touched.
+//! - Static leases keyed by container id are resolved to the MAC of the
+//!   container's interface on that bridge at diff time.
+//! - Fragments are checked with `dnsmasq --test` before dnsmasq is restarted
+//!   through the systemd plugin; a rejected config puts the old files back.
+//! - Query reports the rendered config plus the live leases from dnsmasq's
+//!   lease file.
+
+use crate::state::plugin::{
+    ApplyResult, Checkpoint, DiffMetadata, PluginCapabilities, StateAction, StateDiff, StatePlugin,
+};
+use crate::state::plugins::lxc::LxcPlugin;
+use crate::state::plugins::systemd::{SystemdStatePlugin, UnitConfig};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+
+const GLOBAL_FRAGMENT: &str = "op-dbus-dhcp.conf";
+const HEADER: &str = "# Managed by op-dbus (dhcp plugin); local edits are overwritten";
+const UNIT: &str = "dnsmasq.service";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct DhcpConfig {
+    /// Upstream DNS servers ("1.1.1.1", "10.0.0.53#5353"); empty keeps
+    /// dnsmasq reading /etc/resolv.conf
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub upstream_dns: Vec<String>,
+    /// Local domain handed to clients and used for their hostnames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub bridges: Vec<BridgeDhcp>,
+    /// Live leases (query only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub leases: Vec<Lease>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BridgeDhcp {
+    pub bridge: String,
+    #[serde(default)]
+    pub ranges: Vec<DhcpRange>,
+    /// Default route handed out (option 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>,
+    /// DNS servers handed out (option 6); dnsmasq offers itself when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns_servers: Vec<String>,
+    /// Further DHCP options by dnsmasq name ("ntp-server") or number ("42")
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub options: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub static_leases: Vec<StaticLease>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DhcpRange {
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+    /// "12h", "30m", "3600" or "infinite"
+    #[serde(default = "default_lease_time")]
+    pub lease_time: String,
+}
+
+fn default_lease_time() -> String {
+    "12h".to_string()
+}
+
+/// A fixed address for one client, identified by MAC or by container id
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StaticLease {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    pub ip: Ipv4Addr,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Lease {
+    pub mac: String,
+    pub ip: IpAddr,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Unix time the lease runs out; None for infinite leases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+    /// Bridge whose range or static leases cover the address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<String>,
+}
+
+/// Directory dnsmasq reads fragments from (`OPDBUS_DNSMASQ_DIR`)
+fn fragment_dir() -> PathBuf {
+    std::env::var("OPDBUS_DNSMASQ_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/etc/dnsmasq.d"))
+}
+
+/// dnsmasq lease database (`OPDBUS_DNSMASQ_LEASES`)
+fn lease_file() -> PathBuf {
+    std::env::var("OPDBUS_DNSMASQ_LEASES")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/var/lib/misc/dnsmasq.leases"))
+}
+
+fn is_fragment(name: &str) -> bool {
+    name == GLOBAL_FRAGMENT || (name.starts_with("op-dbus-dhcp-") && name.ends_with(".conf"))
+}
+
+fn bridge_fragment(bridge: &str) -> String {
+    format!("op-dbus-dhcp-{}.conf", bridge)
+}
+
+fn check_name(what: &str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        bail!("Invalid {} '{}'", what, name);
+    }
+    Ok(())
+}
+
+fn normalize_mac(mac: &str) -> Result<String> {
+    let octets: Vec<&str> = mac.split(':').collect();
+    let valid = octets.len() == 6
+        && octets
+            .iter()
+            .all(|o| o.len() == 2 && o.chars().all(|c| c.is_ascii_hexdigit()));
+    if !valid {
+        bail!("Invalid MAC address '{}'", mac);
+    }
+    Ok(mac.to_lowercase())
+}
+
+fn check_lease_time(time: &str) -> Result<()> {
+    let digits = time.trim_end_matches(['s', 'm', 'h', 'd', 'w']);
+    let valid = time == "infinite"
+        || (!digits.is_empty()
+            && digits.chars().all(|c| c.is_ascii_digit())
+            && time.len() - digits.len() <= 1);
+    if !valid {
+        bail!("Invalid lease time '{}'", time);
+    }
+    Ok(())
+}
+
+fn check_server(server: &str) -> Result<()> {
+    let (address, port) = match server.split_once('#') {
+        Some((address, port)) => (address, Some(port)),
+        None => (server, None),
+    };
+    address
+        .parse::<IpAddr>()
+        .with_context(|| format!("Invalid DNS server '{}'", server))?;
+    if let Some(port) = port {
+        port.parse::<u16>()
+            .with_context(|| format!("Invalid DNS server port in '{}'", server))?;
+    }
+    Ok(())
+}
+
+fn option_key(key: &str) -> String {
+    if key.chars().all(|c| c.is_ascii_digit()) {
+        key.to_string()
+    } else {
+        format!("option:{}", key)
+    }
+}
+
+impl DhcpConfig {
+    /// Config fragments by file name, container leases already resolved to MACs
+    fn render(
+        &self,
+        container_macs: &BTreeMap<(String, String), String>,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut fragments = BTreeMap::new();
+        if self.bridges.is_empty() && self.upstream_dns.is_empty() && self.domain.is_none() {
+            return Ok(fragments);
+        }
+
+        let mut global = vec![HEADER.to_string()];
+        if !self.bridges.is_empty() {
+            global.push("dhcp-authoritative".to_string());
+        }
+        if let Some(domain) = &self.domain {
+            check_name("domain", domain)?;
+            global.push(format!("domain={}", domain));
+        }
+        if !self.upstream_dns.is_empty() {
+            global.push("no-resolv".to_string());
+            for server in &self.upstream_dns {
+                check_server(server)?;
+                global.push(format!("server={}", server));
+            }
+        }
+        fragments.insert(GLOBAL_FRAGMENT.to_string(), global.join("\n") + "\n");
+
+        for bridge in &self.bridges {
+            let name = &bridge.bridge;
+            check_name("bridge name", name)?;
+            let file = bridge_fragment(name);
+            if fragments.contains_key(&file) {
+                bail!("Bridge {} is declared twice", name);
+            }
+            let context = || format!("dhcp on bridge {}", name);
+
+            let mut lines = vec![HEADER.to_string(), format!("interface={}", name)];
+            for range in &bridge.ranges {
+                check_lease_time(&range.lease_time).with_context(context)?;
+                if range.start > range.end {
+                    bail!(
+                        "Range {}-{} is reversed ({})",
+                        range.start,
+                        range.end,
+                        context()
+                    );
+                }
+                lines.push(format!(
+                    "dhcp-range=set:{},{},{},{}",
+                    name, range.start, range.end, range.lease_time
+                ));
+            }
+            if let Some(gateway) = &bridge.gateway {
+                let gateway: Ipv4Addr = gateway
+                    .parse()
+                    .with_context(|| format!("Invalid gateway '{}' ({})", gateway, context()))?;
+                lines.push(format!(
+                    "dhcp-option=tag:{},option:router,{}",
+                    name, gateway
+                ));
+            }
+            if !bridge.dns_servers.is_empty() {
+                for server in &bridge.dns_servers {
+                    server.parse::<Ipv4Addr>().with_context(|| {
+                        format!("Invalid DNS server '{}' ({})", server, context())
+                    })?;
+                }
+                lines.push(format!(
+                    "dhcp-option=tag:{},option:dns-server,{}",
+                    name,
+                    bridge.dns_servers.join(",")
+                ));
+            }
+            for (key, value) in &bridge.options {
+                if matches!(key.as_str(), "router" | "3" | "dns-server" | "6") {
+                    bail!(
+                        "Set option {} through gateway/dns_servers ({})",
+                        key,
+                        context()
+                    );
+                }
+                check_name("DHCP option", key).with_context(context)?;
+                if value.contains('\n') {
+                    bail!("Option {} spans lines ({})", key, context());
+                }
+                lines.push(format!(
+                    "dhcp-option=tag:{},{},{}",
+                    name,
+                    option_key(key),
+                    value
+                ));
+            }
+            for lease in &bridge.static_leases {
+                let mac = match (&lease.mac, &lease.container) {
+                    (Some(mac), None) => normalize_mac(mac).with_context(context)?,
+                    (None, Some(container)) => {
+                        lines.push(format!("# container {}", container));
+                        container_macs
+                            .get(&(container.clone(), name.clone()))
+                            .cloned()
+                            .ok_or_else(|| {
+                                anyhow!(
+                                    "Container {} has no interface on {} ({})",
+                                    container,
+                                    name,
+                                    context()
+                                )
+                            })?
+                    }
+                    _ => bail!(
+                        "Static lease for {} needs exactly one of mac and container ({})",
+                        lease.ip,
+                        context()
+                    ),
+                };
+                let mut host = format!("dhcp-host={},{}", mac, lease.ip);
+                if let Some(hostname) = &lease.hostname {
+                    check_name("hostname", hostname).with_context(context)?;
+                    host.push(',');
+                    host.push_str(hostname);
+                }
+                lines.push(host);
+            }
+            fragments.insert(file, lines.join("\n") + "\n");
+        }
+        Ok(fragments)
+    }
+
+    /// Read rendered fragments back into a config
+    fn parse(fragments: &BTreeMap<String, String>) -> DhcpConfig {
+        let mut config = DhcpConfig::default();
+        for text in fragments.values() {
+            let mut container = None;
+            for line in text.lines().map(str::trim) {
+                if let Some(id) = line.strip_prefix("# container ") {
+                    container = Some(id.trim().to_string());
+                    continue;
+                }
+                let Some((key, value)) = line.split_once('=') else {
+                    continue;
+                };
+                match key {
+                    "server" => config.upstream_dns.push(value.to_string()),
+                    "domain" => config.domain = Some(value.to_string()),
+                    "interface" => config.bridges.push(BridgeDhcp {
+                        bridge: value.to_string(),
+                        ranges: Vec::new(),
+                        gateway: None,
+                        dns_servers: Vec::new(),
+                        options: BTreeMap::new(),
+                        static_leases: Vec::new(),
+                    }),
+                    _ => {
+                        if let Some(bridge) = config.bridges.last_mut() {
+                            parse_bridge_line(bridge, key, value, container.take());
+                        }
+                    }
+                }
+            }
+        }
+        config
+    }
+}
+
+fn parse_bridge_line(bridge: &mut BridgeDhcp, key: &str, value: &str, container: Option<String>) {
+    let fields: Vec<&str> = value.split(',').collect();
+    match (key, fields.as_slice()) {
+        ("dhcp-range", [_tag, start, end, lease_time]) => {
+            if let (Ok(start), Ok(end)) = (start.parse(), end.parse()) {
+                bridge.ranges.push(DhcpRange {
+                    start,
+                    end,
+                    lease_time: lease_time.to_string(),
+                });
+            }
+        }
+        ("dhcp-option", [_tag, option, values @ ..]) => match option.strip_prefix("option:") {
+            Some("router") => bridge.gateway = values.first().map(|v| v.to_string()),
+            Some("dns-server") => {
+                bridge.dns_servers = values.iter().map(|v| v.to_string()).collect()
+            }
+            Some(name) => {
+                bridge.options.insert(name.to_string(), values.join(","));
+            }
+            None => {
+                bridge.options.insert(option.to_string(), values.join(","));
+            }
+        },
+        ("dhcp-host", [mac, ip, rest @ ..]) => {
+            if let Ok(ip) = ip.parse() {
+                bridge.static_leases.push(StaticLease {
+                    mac: container.is_none().then(|| mac.to_string()),
+                    container,
+                    ip,
+                    hostname: rest.first().map(|h| h.to_string()),
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+/// dnsmasq lease lines: "<expiry> <mac> <ip> <hostname|*> <client-id|*>"
+fn parse_leases(text: &str, config: &DhcpConfig) -> Vec<Lease> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [expiry, mac, ip, hostname, ..] = fields.as_slice() else {
+                return None;
+            };
+            let ip: IpAddr = ip.parse().ok()?;
+            let expiry: i64 = expiry.parse().ok()?;
+            Some(Lease {
+                mac: mac.to_string(),
+                ip,
+                hostname: (*hostname != "*").then(|| hostname.to_string()),
+                expires: (expiry != 0).then_some(expiry),
+                bridge: lease_bridge(config, ip),
+            })
+        })
+        .collect()
+}
+
+fn lease_bridge(config: &DhcpConfig, ip: IpAddr) -> Option<String> {
+    let IpAddr::V4(ip) = ip else {
+        return None;
+    };
+    config
+        .bridges
+        .iter()
+        .find(|b| {
+            b.ranges.iter().any(|r| r.start <= ip && ip <= r.end)
+                || b.static_leases.iter().any(|l| l.ip == ip)
+        })
+        .map(|b| b.bridge.clone())
+}
+
+pub struct DhcpPlugin;
+
+impl DhcpPlugin {
+    pub fn new() -> Self {
+        Self
+    }
+
+    async fn read_fragments() -> Result<BTreeMap<String, String>> {
+        let dir = fragment_dir();
+        let mut fragments = BTreeMap::new();
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(fragments),
+            Err(e) => return Err(e).with_context(|| format!("Failed to list {}", dir.display())),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if is_fragment(&name) {
+                fragments.insert(name, tokio::fs::read_to_string(entry.path()).await?);
+            }
+        }
+        Ok(fragments)
+    }
+
+    /// Make the fragment directory hold exactly `fragments` (of our files)
+    async fn write_fragments(fragments: &BTreeMap<String, String>) -> Result<()> {
+        let dir = fragment_dir();
+        tokio::fs::create_dir_all(&dir).await?;
+        for name in Self::read_fragments().await?.keys() {
+            if !fragments.contains_key(name) {
+                tokio::fs::remove_file(dir.join(name)).await?;
+            }
+        }
+        for (name, content) in fragments {
+            let tmp = dir.join(format!(".{}.tmp", name));
+            tokio::fs::write(&tmp, content).await?;
+            tokio::fs::rename(&tmp, dir.join(name)).await?;
+        }
+        Ok(())
+    }
+
+    async fn check_config() -> Result<()> {
+        let output = tokio::process::Command::new("dnsmasq")
+            .arg("--test")
+            .output()
+            .await
+            .context("Failed to run dnsmasq --test")?;
+        if !output.status.success() {
+            bail!(
+                "dnsmasq rejected the config: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    async fn restart_dnsmasq(enable: bool) -> Result<()> {
+        let systemd = SystemdStatePlugin::new();
+        systemd.restart_unit(UNIT).await?;
+        if enable {
+            let result = systemd
+                .apply_unit(
+                    UNIT,
+                    &UnitConfig {
+                        active_state: None,
+                        enabled: Some(true),
+                        masked: None,
+                        properties: None,
+                    },
+                )
+                .await?;
+            if !result.success {
+                bail!(result.errors.join("; "));
+            }
+        }
+        Ok(())
+    }
+
+    /// Desired fragments, with container leases looked up on this host
+    async fn resolve(&self, desired: &DhcpConfig) -> Result<BTreeMap<String, String>> {
+        let lxc = LxcPlugin::new();
+        let mut macs = BTreeMap::new();
+        for bridge in &desired.bridges {
+            for container in bridge
+                .static_leases
+                .iter()
+                .filter_map(|l| l.container.as_ref())
+            {
+                if let Some(mac) = lxc.container_hwaddr(container, &bridge.bridge).await? {
+                    macs.insert((container.clone(), bridge.bridge.clone()), mac);
+                }
+            }
+        }
+        desired.render(&macs)
+    }
+
+    async fn query(&self) -> Result<DhcpConfig> {
+        let mut config = DhcpConfig::parse(&Self::read_fragments().await?);
+        if let Ok(text) = tokio::fs::read_to_string(lease_file()).await {
+            config.leases = parse_leases(&text, &config);
+        }
+        Ok(config)
+    }
+}
+
+impl Default for DhcpPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl StatePlugin for DhcpPlugin {
+    fn name(&self) -> &str {
+        "dhcp"
+    }
+
+    fn version(&self) -> &str {
+        "1.0.0"
+    }
+
+    fn is_available(&self) -> bool {
+        std::env::var_os("PATH").is_some_and(|paths| {
+            std::env::split_paths(&paths).any(|dir| dir.join("dnsmasq").is_file())
+        })
+    }
+
+    fn unavailable_reason(&self) -> String {
+        "dnsmasq is not installed (declare it in the packagekit plugin)".to_string()
+    }
+
+    async fn query_current_state(&self) -> Result<Value> {
+        Ok(serde_json::to_value(self.query().await?)?)
+    }
+
+    async fn calculate_diff(&self, current: &Value, desired: &Value) -> Result<StateDiff> {
+        let desired_config: DhcpConfig = serde_json::from_value(desired.clone())?;
+        let wanted = self.resolve(&desired_config).await?;
+        let existing = Self::read_fragments().await?;
+
+        let mut actions = Vec::new();
+        for (name, content) in &wanted {
+            match existing.get(name) {
+                None => actions.push(StateAction::Create {
+                    resource: name.clone(),
+                    config: json!({ "content": content }),
+                }),
+                Some(old) if old != content => actions.push(StateAction::Modify {
+                    resource: name.clone(),
+                    changes: json!({ "content": content }),
+                }),
+                Some(_) => {}
+            }
+        }
+        for name in existing.keys().filter(|n| !wanted.contains_key(*n)) {
+            actions.push(StateAction::Delete {
+                resource: name.clone(),
+            });
+        }
+
+        Ok(StateDiff {
+            plugin: self.name().to_string(),
+            actions,
+            metadata: DiffMetadata {
+                timestamp: chrono::Utc::now().timestamp(),
+                current_hash: format!("{:x}", md5::compute(serde_json::to_string(current)?)),
+                desired_hash: format!("{:x}", md5::compute(serde_json::to_string(desired)?)),
+            },
+        })
+    }
+
+    async fn apply_state(&self, diff: &StateDiff) -> Result<ApplyResult> {
+        let previous = Self::read_fragments().await?;
+        let mut fragments = previous.clone();
+        let mut changes_applied = Vec::new();
+
+        for action in &diff.actions {
+            match action {
+                StateAction::Create { resource, config }
+                | StateAction::Modify {
+                    resource,
+                    changes: config,
+                } => {
+                    if !is_fragment(resource) {
+                        bail!("{} is not a dhcp plugin fragment", resource);
+                    }
+                    let content = config["content"]
+                        .as_str()
+                        .ok_or_else(|| anyhow!("{}: missing content", resource))?;
+                    fragments.insert(resource.clone(), content.to_string());
+                    changes_applied.push(format!("Wrote {}", resource));
+                }
+                StateAction::Delete { resource } => {
+                    fragments.remove(resource);
+                    changes_applied.push(format!("Removed {}", resource));
+                }
+                StateAction::NoOp { .. } => {}
+            }
+        }
+
+        if changes_applied.is_empty() {
+            return Ok(ApplyResult {
+                success: true,
+                changes_applied,
+                errors: Vec::new(),
+                checkpoint: None,
+            });
+        }
+
+        Self::write_fragments(&fragments).await?;
+        if let Err(e) = Self::check_config().await {
+            Self::write_fragments(&previous).await?;
+            return Ok(ApplyResult {
+                success: false,
+                changes_applied: Vec::new(),
+                errors: vec![format!("{:#}", e)],
+                checkpoint: None,
+            });
+        }
+
+        let mut errors = Vec::new();
+        match Self::restart_dnsmasq(!fragments.is_empty()).await {
+            Ok(()) => changes_applied.push(format!("Restarted {}", UNIT)),
+            Err(e) => errors.push(format!("Failed to restart {}: {:#}", UNIT, e)),
+        }
+
+        Ok(ApplyResult {
+            success: errors.is_empty(),
+            changes_applied,
+            errors,
+            checkpoint: None,
+        })
+    }
+
+    async fn verify_state(&self, desired: &Value) -> Result<bool> {
+        let desired_config: DhcpConfig = serde_json::from_value(desired.clone())?;
+        Ok(self.resolve(&desired_config).await? == Self::read_fragments().await?)
+    }
+
+    async fn create_checkpoint(&self) -> Result<Checkpoint> {
+        Ok(Checkpoint {
+            id: format!("dhcp-{}", chrono::Utc::now().timestamp()),
+            plugin: self.name().to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            state_snapshot: self.query_current_state().await?,
+            backend_checkpoint: Some(json!({ "fragments": Self::read_fragments().await? })),
+        })
+    }
+
+    async fn rollback(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let fragments: BTreeMap<String, String> = checkpoint
+            .backend_checkpoint
+            .as_ref()
+            .and_then(|b| b.get("fragments"))
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()?
+            .ok_or_else(|| anyhow!("Checkpoint {} has no dnsmasq fragments", checkpoint.id))?;
+
+        Self::write_fragments(&fragments).await?;
+        Self::restart_dnsmasq(false).await?;
+        log::info!(
+            "Restored dnsmasq fragments from checkpoint {}",
+            checkpoint.id
+        );
+        Ok(())
+    }
+
+    fn capabilities(&self) -> PluginCapabilities {
+        PluginCapabilities {
+            supports_rollback: true,
+            supports_checkpoints: true,
+            supports_verification: true,
+            atomic_operations: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DhcpConfig {
+        serde_json::from_value(json!({
+            "upstream_dns": ["1.1.1.1", "10.0.0.53#5353"],
+            "domain": "lan",
+            "bridges": [{
+                "bridge": "ovsbr0",
+                "ranges": [{"start": "192.168.1.50", "end": "192.168.1.150"}],
+                "gateway": "192.168.1.1",
+                "dns_servers": ["192.168.1.1"],
+                "options": {"ntp-server": "192.168.1.1", "42": "192.168.1.2"},
+                "static_leases": [
+                    {"mac": "AA:BB:CC:00:11:22", "ip": "192.168.1.10", "hostname": "web"},
+                    {"container": "101", "ip": "192.168.1.11"}
+                ]
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn macs() -> BTreeMap<(String, String), String> {
+        BTreeMap::from([(
+            ("101".to_string(), "ovsbr0".to_string()),
+            "bc:24:11:0a:0b:0c".to_string(),
+        )])
+    }
+
+    #[test]
+    fn test_render_and_parse_round_trip() {
+        let fragments = config().render(&macs()).unwrap();
+        assert_eq!(
+            fragments.keys().collect::<Vec<_>>(),
+            vec!["op-dbus-dhcp-ovsbr0.conf", "op-dbus-dhcp.conf"]
+        );
+        let bridge = &fragments["op-dbus-dhcp-ovsbr0.conf"];
+        assert!(bridge.contains("dhcp-range=set:ovsbr0,192.168.1.50,192.168.1.150,12h\n"));
+        assert!(bridge.contains("dhcp-option=tag:ovsbr0,42,192.168.1.2\n"));
+        assert!(bridge.contains("dhcp-host=aa:bb:cc:00:11:22,192.168.1.10,web\n"));
+        assert!(bridge.contains("# container 101\ndhcp-host=bc:24:11:0a:0b:0c,192.168.1.11\n"));
+        assert!(fragments[GLOBAL_FRAGMENT].contains("no-resolv\nserver=1.1.1.1\n"));
+
+        let mut expected = config();
+        expected.bridges[0].static_leases[0].mac = Some("aa:bb:cc:00:11:22".to_string());
+        assert_eq!(DhcpConfig::parse(&fragments), expected);
+
+        assert!(DhcpConfig::default().render(&macs()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_render_rejects_bad_config() {
+        let reject = |edit: fn(&mut DhcpConfig)| {
+            let mut config = config();
+            edit(&mut config);
+            config.render(&macs()).is_err()
+        };
+        assert!(reject(|c| c.bridges[0].bridge = "br0;rm".to_string()));
+        assert!(reject(
+            |c| c.bridges[0].ranges[0].lease_time = "12x".to_string()
+        ));
+        assert!(reject(
+            |c| c.bridges[0].ranges[0].end = Ipv4Addr::new(192, 168, 1, 1)
+        ));
+        assert!(reject(
+            |c| c.bridges[0].static_leases[1].container = Some("102".to_string())
+        ));
+        assert!(reject(
+            |c| c.bridges[0].static_leases[0].container = Some("101".to_string())
+        ));
+        assert!(reject(|c| {
+            c.bridges[0]
+                .options
+                .insert("router".to_string(), "10.0.0.1".to_string());
+        }));
+        assert!(reject(|c| c.upstream_dns.push("dns.google".to_string())));
+        assert!(reject(|c| c.bridges.push(c.bridges[0].clone())));
+    }
+
+    #[test]
+    fn test_parse_leases() {
+        let config = DhcpConfig::parse(&config().render(&macs()).unwrap());
+        let leases = parse_leases(
+            "1760000000 aa:bb:cc:00:11:22 192.168.1.10 web 01:aa:bb:cc:00:11:22\n\
+             0 bc:24:11:0a:0b:0c 192.168.1.99 * *\n\
+             1760000000 de:ad:be:ef:00:01 10.9.9.9 * *\n\
+             garbage\n",
+            &config,
+        );
+        assert_eq!(leases.len(), 3);
+        assert_eq!(leases[0].hostname.as_deref(), Some("web"));
+        assert_eq!(leases[0].bridge.as_deref(), Some("ovsbr0"));
+        assert_eq!(leases[1].expires, None);
+        assert_eq!(leases[1].hostname, None);
+        assert_eq!(leases[2].bridge, None);
+    }
+}
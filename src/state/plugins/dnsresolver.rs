@@ -1,11 +1,19 @@
 // dnsresolver_plugin.rs
-use anyhow::{Context, Result};
+//
+// Two backends: /etc/resolv.conf written directly, or systemd-resolved over
+// D-Bus when resolved owns resolv.conf (it would overwrite direct writes).
+mod resolved;
+
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
 use std::process::Command;
 
+use resolved::{LinkDns, ResolvedClient};
+
 use crate::state::plugin::{
     ApplyResult, Checkpoint, DiffMetadata, PluginCapabilities, StateAction, StateDiff, StatePlugin,
 };
@@ -14,6 +22,29 @@ use crate::state::plugin::{
 pub struct DnsState {
     pub version: u32,
     pub items: Vec<DnsItem>,
+    /// Global fallback servers (resolved only); an empty list disables fallback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Vec<String>>,
+    /// Picked from who owns /etc/resolv.conf unless set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<Backend>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    ResolvConf,
+    Resolved,
+}
+
+impl Backend {
+    fn detect() -> Self {
+        if resolved::owns_resolv_conf() {
+            Backend::Resolved
+        } else {
+            Backend::ResolvConf
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
@@ -32,6 +63,15 @@ pub struct DnsItem {
     pub search: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub options: Option<Vec<String>>,
+    /// Interface the settings apply to (resolved only); global when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    /// yes, no or allow-downgrade (resolved only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dnssec: Option<String>,
+    /// yes, no or opportunistic (resolved only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_over_tls: Option<String>,
 }
 
 pub struct DnsResolverPlugin;
@@ -86,6 +126,9 @@ impl DnsResolverPlugin {
             servers,
             search,
             options,
+            link: None,
+            dnssec: None,
+            dns_over_tls: None,
         }
     }
 
@@ -99,6 +142,10 @@ impl DnsResolverPlugin {
     }
 
     fn write_resolv_conf(item: &DnsItem) -> Result<()> {
+        Self::write_resolv_conf_text(&Self::render_resolv_conf(item))
+    }
+
+    fn render_resolv_conf(item: &DnsItem) -> String {
         let mut buf = String::new();
         if let Some(sr) = &item.search {
             if !sr.is_empty() {
@@ -119,7 +166,10 @@ impl DnsResolverPlugin {
             buf.push_str(ns);
             buf.push('\n');
         }
+        buf
+    }
 
+    fn write_resolv_conf_text(buf: &str) -> Result<()> {
         let tmp_path = "/etc/resolv.conf.sysdecl.tmp";
         fs::write(tmp_path, buf.as_bytes()).context("write temp resolv.conf")?;
         let mv_cmd = format!("mv -f {} /etc/resolv.conf", tmp_path);
@@ -157,6 +207,187 @@ impl DnsResolverPlugin {
         }
         vec![Self::parse_resolv_conf(&txt)]
     }
+
+    fn backend(want: &DnsState) -> Backend {
+        want.backend.unwrap_or_else(Backend::detect)
+    }
+
+    fn resolved_item(id: &str, link: Option<&str>, settings: &LinkDns) -> DnsItem {
+        let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_string());
+        DnsItem {
+            id: id.to_string(),
+            mode: Mode::ObserveOnly,
+            servers: settings.servers.clone(),
+            search: (!settings.domains.is_empty()).then(|| settings.domains.clone()),
+            options: None,
+            link: link.map(str::to_string),
+            dnssec: non_empty(&settings.dnssec),
+            dns_over_tls: non_empty(&settings.dns_over_tls),
+        }
+    }
+
+    async fn query_resolved() -> Result<DnsState> {
+        let client = ResolvedClient::connect().await?;
+        let mut items = vec![Self::resolved_item("global", None, &client.global().await?)];
+        for (name, settings) in client.links().await? {
+            if !settings.servers.is_empty() || !settings.domains.is_empty() {
+                items.push(Self::resolved_item(&name, Some(&name), &settings));
+            }
+        }
+        Ok(DnsState {
+            version: 1,
+            items,
+            fallback: Some(client.fallback().await?),
+            backend: Some(Backend::Resolved),
+        })
+    }
+
+    /// Unset search/modes in the desired item leave the current ones alone
+    fn resolved_matches(cur: &LinkDns, want: &DnsItem) -> bool {
+        Self::normalize(&cur.servers) == Self::normalize(&want.servers)
+            && want
+                .search
+                .as_ref()
+                .is_none_or(|s| Self::normalize(s) == Self::normalize(&cur.domains))
+            && want.dnssec.as_ref().is_none_or(|m| *m == cur.dnssec)
+            && want
+                .dns_over_tls
+                .as_ref()
+                .is_none_or(|m| *m == cur.dns_over_tls)
+    }
+
+    fn resolved_resource(item: &DnsItem) -> String {
+        match &item.link {
+            Some(link) => format!("resolved/link/{}", link),
+            None => "resolved/global".to_string(),
+        }
+    }
+
+    async fn diff_resolved(want: &DnsState) -> Result<Vec<StateAction>> {
+        let client = ResolvedClient::connect().await?;
+        let mut actions = Vec::new();
+        for item in &want.items {
+            if let Some(mode) = &item.dnssec {
+                resolved::check_dnssec(mode)?;
+            }
+            if let Some(mode) = &item.dns_over_tls {
+                resolved::check_dns_over_tls(mode)?;
+            }
+            if item.options.as_ref().is_some_and(|o| !o.is_empty()) {
+                log::warn!(
+                    "{}: resolv.conf options are ignored by systemd-resolved",
+                    item.id
+                );
+            }
+            let current = match &item.link {
+                Some(link) => client.link(resolved::ifindex(link).await?).await?,
+                None => client.global().await?,
+            };
+            let resource = Self::resolved_resource(item);
+            if Self::resolved_matches(&current, item) {
+                actions.push(StateAction::NoOp { resource });
+            } else {
+                actions.push(StateAction::Modify {
+                    resource,
+                    changes: serde_json::to_value(item)?,
+                });
+            }
+        }
+        if let Some(fallback) = &want.fallback {
+            if client.fallback().await? != *fallback {
+                actions.push(StateAction::Modify {
+                    resource: "resolved/fallback".to_string(),
+                    changes: serde_json::json!({ "fallback": fallback }),
+                });
+            }
+        }
+        Ok(actions)
+    }
+
+    async fn apply_resolved(resource: &str, payload: &Value) -> Result<String> {
+        if resource == "resolved/fallback" {
+            let fallback: Vec<String> = serde_json::from_value(payload["fallback"].clone())?;
+            let dropin = resolved::update_dropin(
+                resolved::read_dropin().as_deref(),
+                &[("FallbackDNS", fallback.join(" "))],
+            );
+            resolved::write_dropin(Some(&dropin)).await?;
+            return Ok(format!("{}: fallback servers updated", resource));
+        }
+
+        let item: DnsItem = serde_json::from_value(payload.clone())?;
+        if let Mode::ObserveOnly = item.mode {
+            return Ok(format!("{}: no action required", resource));
+        }
+        match &item.link {
+            None => {
+                let mut keys = vec![("DNS", item.servers.join(" "))];
+                if let Some(search) = &item.search {
+                    keys.push(("Domains", search.join(" ")));
+                }
+                if let Some(mode) = &item.dnssec {
+                    keys.push(("DNSSEC", mode.clone()));
+                }
+                if let Some(mode) = &item.dns_over_tls {
+                    keys.push(("DNSOverTLS", mode.clone()));
+                }
+                let dropin = resolved::update_dropin(resolved::read_dropin().as_deref(), &keys);
+                resolved::write_dropin(Some(&dropin)).await?;
+                Ok(format!("{}: resolved.conf drop-in updated", resource))
+            }
+            Some(link) => {
+                let client = ResolvedClient::connect().await?;
+                client
+                    .set_link(
+                        resolved::ifindex(link).await?,
+                        &item.servers,
+                        item.search.as_deref(),
+                        item.dnssec.as_deref(),
+                        item.dns_over_tls.as_deref(),
+                    )
+                    .await?;
+                Ok(format!("{}: link settings updated", resource))
+            }
+        }
+    }
+
+    /// Resolved drop-in and per-link settings, if resolved is reachable
+    async fn resolved_checkpoint() -> Option<Value> {
+        let client = ResolvedClient::connect().await.ok()?;
+        let links = client.links().await.ok()?;
+        Some(serde_json::json!({
+            "dropin": resolved::read_dropin(),
+            "links": links,
+        }))
+    }
+
+    async fn rollback_resolved(saved: &Value) -> Result<()> {
+        let dropin: Option<String> = serde_json::from_value(saved["dropin"].clone())?;
+        let links: BTreeMap<String, LinkDns> = serde_json::from_value(saved["links"].clone())?;
+        resolved::write_dropin(dropin.as_deref()).await?;
+
+        let client = ResolvedClient::connect().await?;
+        for (name, current) in client.links().await? {
+            let Some(old) = links.get(&name) else {
+                continue;
+            };
+            if *old == current {
+                continue;
+            }
+            // Modes are reported as effective values; only pin ones that moved
+            client
+                .set_link(
+                    resolved::ifindex(&name).await?,
+                    &old.servers,
+                    Some(&old.domains),
+                    (old.dnssec != current.dnssec).then_some(old.dnssec.as_str()),
+                    (old.dns_over_tls != current.dns_over_tls).then_some(old.dns_over_tls.as_str()),
+                )
+                .await?;
+            log::info!("Restored resolved settings of {}", name);
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -169,8 +400,11 @@ impl StatePlugin for DnsResolverPlugin {
     }
 
     async fn query_current_state(&self) -> Result<Value> {
+        if Backend::detect() == Backend::Resolved {
+            return Ok(serde_json::to_value(Self::query_resolved().await?)?);
+        }
         let items = Self::query_system();
-        Ok(serde_json::json!({ "version": 1, "items": items }))
+        Ok(serde_json::json!({ "version": 1, "items": items, "backend": Backend::ResolvConf }))
     }
 
     async fn calculate_diff(&self, _current: &Value, desired: &Value) -> Result<StateDiff> {
@@ -179,24 +413,44 @@ impl StatePlugin for DnsResolverPlugin {
             Err(_) => DnsState {
                 version: 1,
                 items: Vec::new(),
+                fallback: None,
+                backend: None,
             },
         };
+        let backend = Self::backend(&want);
         let cur_all = Self::query_system();
-        let cur = cur_all.first();
         let mut actions = Vec::new();
-        for item in &want.items {
-            match cur {
-                Some(c) if Self::equal_desired(c, item) => actions.push(StateAction::NoOp {
-                    resource: item.id.clone(),
-                }),
-                Some(_) => actions.push(StateAction::Modify {
-                    resource: item.id.clone(),
-                    changes: serde_json::to_value(item).unwrap_or(serde_json::json!({})),
-                }),
-                None => actions.push(StateAction::Create {
-                    resource: item.id.clone(),
-                    config: serde_json::to_value(item).unwrap_or(serde_json::json!({})),
-                }),
+        if backend == Backend::Resolved {
+            actions = Self::diff_resolved(&want).await?;
+        } else {
+            if let Some(item) = want
+                .items
+                .iter()
+                .find(|i| i.link.is_some() || i.dnssec.is_some() || i.dns_over_tls.is_some())
+            {
+                bail!(
+                    "{}: link, dnssec and dns_over_tls need the systemd-resolved backend",
+                    item.id
+                );
+            }
+            if want.fallback.is_some() {
+                bail!("fallback needs the systemd-resolved backend");
+            }
+            let cur = cur_all.first();
+            for item in &want.items {
+                match cur {
+                    Some(c) if Self::equal_desired(c, item) => actions.push(StateAction::NoOp {
+                        resource: item.id.clone(),
+                    }),
+                    Some(_) => actions.push(StateAction::Modify {
+                        resource: item.id.clone(),
+                        changes: serde_json::to_value(item).unwrap_or(serde_json::json!({})),
+                    }),
+                    None => actions.push(StateAction::Create {
+                        resource: item.id.clone(),
+                        config: serde_json::to_value(item).unwrap_or(serde_json::json!({})),
+                    }),
+                }
             }
         }
         let meta = DiffMetadata {
@@ -225,6 +479,16 @@ impl StatePlugin for DnsResolverPlugin {
         let mut errors = Vec::new();
         for action in &diff.actions {
             match action {
+                StateAction::Create { resource, config }
+                | StateAction::Modify {
+                    resource,
+                    changes: config,
+                } if resource.starts_with("resolved/") => {
+                    match Self::apply_resolved(resource, config).await {
+                        Ok(change) => changes_applied.push(change),
+                        Err(e) => errors.push(format!("{}: {:#}", resource, e)),
+                    }
+                }
                 StateAction::Create { resource, config }
                 | StateAction::Modify {
                     resource,
@@ -270,6 +534,12 @@ impl StatePlugin for DnsResolverPlugin {
             Ok(v) => v,
             Err(_) => return Ok(true),
         };
+        if Self::backend(&want) == Backend::Resolved {
+            let actions = Self::diff_resolved(&want).await?;
+            return Ok(actions
+                .iter()
+                .all(|a| matches!(a, StateAction::NoOp { .. })));
+        }
         let cur_all = Self::query_system();
         let cur = match cur_all.first() {
             Some(v) => v,
@@ -284,24 +554,110 @@ impl StatePlugin for DnsResolverPlugin {
     }
 
     async fn create_checkpoint(&self) -> Result<Checkpoint> {
+        // A symlinked resolv.conf belongs to resolved; restoring its text
+        // would replace the link with a stale copy
+        let resolv_conf = fs::symlink_metadata("/etc/resolv.conf")
+            .is_ok_and(|m| m.file_type().is_file())
+            .then(Self::read_resolv_conf);
         Ok(Checkpoint {
             id: format!("{}-{}", self.name(), chrono::Utc::now().timestamp()),
             plugin: self.name().to_string(),
             timestamp: chrono::Utc::now().timestamp(),
-            state_snapshot: serde_json::json!({}),
-            backend_checkpoint: None,
+            state_snapshot: self.query_current_state().await?,
+            backend_checkpoint: Some(serde_json::json!({
+                "resolv_conf": resolv_conf,
+                "resolved": Self::resolved_checkpoint().await,
+            })),
         })
     }
-    async fn rollback(&self, _checkpoint: &Checkpoint) -> Result<()> {
+
+    async fn rollback(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let saved = checkpoint
+            .backend_checkpoint
+            .as_ref()
+            .ok_or_else(|| anyhow!("Checkpoint {} has no DNS settings", checkpoint.id))?;
+        if let Some(text) = saved["resolv_conf"].as_str() {
+            if Self::read_resolv_conf() != text {
+                Self::write_resolv_conf_text(text)?;
+                log::info!(
+                    "Restored /etc/resolv.conf from checkpoint {}",
+                    checkpoint.id
+                );
+            }
+        }
+        if !saved["resolved"].is_null() {
+            Self::rollback_resolved(&saved["resolved"]).await?;
+        }
         Ok(())
     }
 
+    fn owned_paths(&self) -> Vec<std::path::PathBuf> {
+        vec![
+            std::path::PathBuf::from("/etc/resolv.conf"),
+            std::path::PathBuf::from(resolved::DROPIN),
+        ]
+    }
+
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities {
-            supports_rollback: false,
+            supports_rollback: true,
             supports_checkpoints: true,
             supports_verification: true,
             atomic_operations: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(value: Value) -> DnsItem {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_resolved_matches_ignores_unset_fields() {
+        let current = LinkDns {
+            servers: vec!["10.0.0.53".to_string(), "10.0.0.1".to_string()],
+            domains: vec!["~corp".to_string()],
+            dnssec: "allow-downgrade".to_string(),
+            dns_over_tls: "no".to_string(),
+        };
+        let want = item(serde_json::json!({
+            "id": "wg", "mode": "enforce", "link": "wg0",
+            "servers": ["10.0.0.1", "10.0.0.53"]
+        }));
+        assert!(DnsResolverPlugin::resolved_matches(&current, &want));
+        assert_eq!(
+            DnsResolverPlugin::resolved_resource(&want),
+            "resolved/link/wg0"
+        );
+
+        let strict = DnsItem {
+            dns_over_tls: Some("opportunistic".to_string()),
+            ..want.clone()
+        };
+        assert!(!DnsResolverPlugin::resolved_matches(&current, &strict));
+        let search = DnsItem {
+            search: Some(vec!["corp".to_string()]),
+            ..want
+        };
+        assert!(!DnsResolverPlugin::resolved_matches(&current, &search));
+    }
+
+    #[test]
+    fn test_resolved_item_reports_link_settings() {
+        let settings = LinkDns {
+            servers: vec!["1.1.1.1".to_string()],
+            domains: Vec::new(),
+            dnssec: "yes".to_string(),
+            dns_over_tls: String::new(),
+        };
+        let reported = DnsResolverPlugin::resolved_item("eth0", Some("eth0"), &settings);
+        assert_eq!(reported.link.as_deref(), Some("eth0"));
+        assert_eq!(reported.search, None);
+        assert_eq!(reported.dnssec.as_deref(), Some("yes"));
+        assert_eq!(reported.dns_over_tls, None);
+    }
+}
@@ -0,0 +1,304 @@
+//! systemd-resolved backend over org.freedesktop.resolve1
+//!
+//! Per-link servers, domains and modes go through the Manager's SetLink*
+//! calls; resolved keeps them in /run across its own restarts. Global
+//! settings and the fallback list have no D-Bus setter, so they live in a
+//! resolved.conf drop-in and take effect on restart.
+
+use crate::state::plugins::systemd::SystemdStatePlugin;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use zbus::{Connection, Proxy};
+
+pub(super) const DROPIN: &str = "/etc/systemd/resolved.conf.d/op-dbus.conf";
+const UNIT: &str = "systemd-resolved.service";
+const AF_INET: i32 = 2;
+const AF_INET6: i32 = 10;
+
+/// DNS settings of a link, or the global ones
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct LinkDns {
+    pub servers: Vec<String>,
+    /// Search domains; "~domain" routes queries without searching
+    pub domains: Vec<String>,
+    pub dnssec: String,
+    pub dns_over_tls: String,
+}
+
+/// Whether /etc/resolv.conf is resolved's stub or its generated copy
+pub fn owns_resolv_conf() -> bool {
+    std::fs::read_link("/etc/resolv.conf")
+        .is_ok_and(|target| target.to_string_lossy().contains("/run/systemd/resolve/"))
+}
+
+pub fn check_dnssec(mode: &str) -> Result<()> {
+    if !matches!(mode, "yes" | "no" | "allow-downgrade") {
+        bail!(
+            "DNSSEC mode must be yes, no or allow-downgrade, not '{}'",
+            mode
+        );
+    }
+    Ok(())
+}
+
+pub fn check_dns_over_tls(mode: &str) -> Result<()> {
+    if !matches!(mode, "yes" | "no" | "opportunistic") {
+        bail!(
+            "DNS-over-TLS mode must be yes, no or opportunistic, not '{}'",
+            mode
+        );
+    }
+    Ok(())
+}
+
+fn format_address(family: i32, bytes: &[u8]) -> Option<String> {
+    let address = match (family, bytes.len()) {
+        (AF_INET, 4) => IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?),
+        (AF_INET6, 16) => IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?),
+        _ => return None,
+    };
+    Some(address.to_string())
+}
+
+fn parse_address(server: &str) -> Result<(i32, Vec<u8>)> {
+    let address: IpAddr = server
+        .parse()
+        .with_context(|| format!("Invalid DNS server '{}'", server))?;
+    Ok(match address {
+        IpAddr::V4(a) => (AF_INET, a.octets().to_vec()),
+        IpAddr::V6(a) => (AF_INET6, a.octets().to_vec()),
+    })
+}
+
+fn format_domain((domain, route_only): (String, bool)) -> String {
+    if route_only {
+        format!("~{}", domain)
+    } else {
+        domain
+    }
+}
+
+fn parse_domain(domain: &str) -> (String, bool) {
+    match domain.strip_prefix('~') {
+        Some(domain) => (domain.to_string(), true),
+        None => (domain.to_string(), false),
+    }
+}
+
+pub struct ResolvedClient {
+    conn: Connection,
+}
+
+impl ResolvedClient {
+    pub async fn connect() -> Result<Self> {
+        let conn = Connection::system()
+            .await
+            .context("Failed to connect to system D-Bus")?;
+        Ok(Self { conn })
+    }
+
+    async fn manager(&self) -> Result<Proxy<'static>> {
+        Proxy::new(
+            &self.conn,
+            "org.freedesktop.resolve1",
+            "/org/freedesktop/resolve1",
+            "org.freedesktop.resolve1.Manager",
+        )
+        .await
+        .context("Failed to create resolved D-Bus proxy")
+    }
+
+    /// Global servers, domains and modes (from resolved.conf and its drop-ins)
+    pub async fn global(&self) -> Result<LinkDns> {
+        let manager = self.manager().await?;
+        let servers: Vec<(i32, i32, Vec<u8>)> = manager.get_property("DNS").await?;
+        let domains: Vec<(i32, String, bool)> = manager.get_property("Domains").await?;
+        Ok(LinkDns {
+            servers: servers
+                .iter()
+                .filter(|(ifindex, _, _)| *ifindex == 0)
+                .filter_map(|(_, family, bytes)| format_address(*family, bytes))
+                .collect(),
+            domains: domains
+                .into_iter()
+                .filter(|(ifindex, _, _)| *ifindex == 0)
+                .map(|(_, domain, route_only)| format_domain((domain, route_only)))
+                .collect(),
+            dnssec: manager.get_property("DNSSEC").await?,
+            dns_over_tls: manager.get_property("DNSOverTLS").await?,
+        })
+    }
+
+    pub async fn fallback(&self) -> Result<Vec<String>> {
+        let servers: Vec<(i32, i32, Vec<u8>)> =
+            self.manager().await?.get_property("FallbackDNS").await?;
+        Ok(servers
+            .iter()
+            .filter_map(|(_, family, bytes)| format_address(*family, bytes))
+            .collect())
+    }
+
+    async fn link_proxy(&self, ifindex: i32) -> Result<Proxy<'static>> {
+        let path: zbus::zvariant::OwnedObjectPath = self
+            .manager()
+            .await?
+            .call("GetLink", &(ifindex,))
+            .await
+            .with_context(|| format!("resolved does not know link {}", ifindex))?;
+        Ok(Proxy::new(
+            &self.conn,
+            "org.freedesktop.resolve1",
+            path,
+            "org.freedesktop.resolve1.Link",
+        )
+        .await?)
+    }
+
+    /// Settings of one link; modes are the effective ones, global if unset
+    pub async fn link(&self, ifindex: i32) -> Result<LinkDns> {
+        let link = self.link_proxy(ifindex).await?;
+        let servers: Vec<(i32, Vec<u8>)> = link.get_property("DNS").await?;
+        let domains: Vec<(String, bool)> = link.get_property("Domains").await?;
+        Ok(LinkDns {
+            servers: servers
+                .iter()
+                .filter_map(|(family, bytes)| format_address(*family, bytes))
+                .collect(),
+            domains: domains.into_iter().map(format_domain).collect(),
+            dnssec: link.get_property("DNSSEC").await?,
+            dns_over_tls: link.get_property("DNSOverTLS").await?,
+        })
+    }
+
+    /// Settings of every link except loopback, by interface name
+    pub async fn links(&self) -> Result<BTreeMap<String, LinkDns>> {
+        let mut links = BTreeMap::new();
+        for link in crate::native::rtnetlink_links::list_links().await? {
+            if link.name == "lo" {
+                continue;
+            }
+            match self.link(link.index as i32).await {
+                Ok(settings) => {
+                    links.insert(link.name, settings);
+                }
+                Err(e) => log::debug!("Skipping link {}: {:#}", link.name, e),
+            }
+        }
+        Ok(links)
+    }
+
+    /// Replace a link's servers and, where given, domains and modes
+    pub async fn set_link(
+        &self,
+        ifindex: i32,
+        servers: &[String],
+        domains: Option<&[String]>,
+        dnssec: Option<&str>,
+        dns_over_tls: Option<&str>,
+    ) -> Result<()> {
+        let manager = self.manager().await?;
+        let addresses = servers
+            .iter()
+            .map(|s| parse_address(s))
+            .collect::<Result<Vec<_>>>()?;
+        let _: () = manager.call("SetLinkDNS", &(ifindex, addresses)).await?;
+        if let Some(domains) = domains {
+            let domains: Vec<(String, bool)> = domains.iter().map(|d| parse_domain(d)).collect();
+            let _: () = manager.call("SetLinkDomains", &(ifindex, domains)).await?;
+        }
+        if let Some(mode) = dnssec {
+            check_dnssec(mode)?;
+            let _: () = manager.call("SetLinkDNSSEC", &(ifindex, mode)).await?;
+        }
+        if let Some(mode) = dns_over_tls {
+            check_dns_over_tls(mode)?;
+            let _: () = manager.call("SetLinkDNSOverTLS", &(ifindex, mode)).await?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn ifindex(name: &str) -> Result<i32> {
+    crate::native::rtnetlink_links::get_link(name)
+        .await?
+        .map(|link| link.index as i32)
+        .with_context(|| format!("Interface '{}' not found", name))
+}
+
+pub fn read_dropin() -> Option<String> {
+    std::fs::read_to_string(DROPIN).ok()
+}
+
+/// Set keys of the [Resolve] section in the op-dbus drop-in, keeping the rest
+pub fn update_dropin(current: Option<&str>, keys: &[(&str, String)]) -> String {
+    let mut entries: BTreeMap<String, String> = current
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    for (key, value) in keys {
+        entries.insert(key.to_string(), value.clone());
+    }
+
+    let mut text = String::from("# Managed by op-dbus (dnsresolver plugin)\n[Resolve]\n");
+    for (key, value) in entries {
+        text.push_str(&format!("{}={}\n", key, value));
+    }
+    text
+}
+
+/// Write (or with None remove) the drop-in and restart resolved if it changed
+pub async fn write_dropin(content: Option<&str>) -> Result<bool> {
+    if read_dropin().as_deref() == content {
+        return Ok(false);
+    }
+    match content {
+        Some(content) => {
+            tokio::fs::create_dir_all("/etc/systemd/resolved.conf.d").await?;
+            let tmp = format!("{}.tmp", DROPIN);
+            tokio::fs::write(&tmp, content).await?;
+            tokio::fs::rename(&tmp, DROPIN).await?;
+        }
+        None => tokio::fs::remove_file(DROPIN).await?,
+    }
+    SystemdStatePlugin::new().restart_unit(UNIT).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_dropin_keeps_other_keys() {
+        let first = update_dropin(None, &[("DNS", "1.1.1.1 9.9.9.9".to_string())]);
+        assert_eq!(
+            first,
+            "# Managed by op-dbus (dnsresolver plugin)\n[Resolve]\nDNS=1.1.1.1 9.9.9.9\n"
+        );
+        let second = update_dropin(
+            Some(&first),
+            &[
+                ("FallbackDNS", String::new()),
+                ("DNS", "10.0.0.1".to_string()),
+            ],
+        );
+        assert!(second.ends_with("[Resolve]\nDNS=10.0.0.1\nFallbackDNS=\n"));
+    }
+
+    #[test]
+    fn test_address_and_domain_encoding() {
+        assert_eq!(
+            parse_address("10.0.0.1").unwrap(),
+            (AF_INET, vec![10, 0, 0, 1])
+        );
+        let (family, bytes) = parse_address("fd00::53").unwrap();
+        assert_eq!(format_address(family, &bytes).as_deref(), Some("fd00::53"));
+        assert!(parse_address("dns.google").is_err());
+        assert_eq!(parse_domain("~corp"), ("corp".to_string(), true));
+        assert_eq!(format_domain(("lan".to_string(), false)), "lan");
+    }
+}
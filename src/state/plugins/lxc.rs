@@ -1,725 +1,905 @@
-//! LXC plugin - read-only introspection of LXC containers on the host.
+//! LXC plugin - containers on the host, whichever manager runs them.
 //!
 //! Design
-//! - Discovers LXC containers by correlating OVS ports (vi{VMID}) with OVS bridges
-//!   and known cgroup paths on Proxmox (pve-container@{VMID}.service) for running state.
-//! - No CLI; native OVSDB JSON-RPC and filesystem reads only.
-//! - Read-only for now (apply is a no-op). StateManager will still produce footprints on apply.
-
+//! - One backend per container manager: Proxmox `pct`, plain LXC (`lxc-*` tools and
+//!   config files) or systemd-nspawn through org.freedesktop.machine1. The backend is
+//!   picked per host (OPDBUS_CONTAINER_BACKEND overrides detection), so one state
+//!   file works on all of them.
+//! - Every backend reports the same ContainerInfo: the container id, its host-side
+//!   OVS port vi{ID}, the bridge and the running state.
+//! - Reads are native (OVSDB JSON-RPC, D-Bus, config files); lifecycle goes through
+//!   each manager's own tools.
+//! - Existing containers are diffed on their declared limits, mounts, features and
+//!   NICs. Limits and the vi{ID} port's bridge/VLAN change live; the rest is written
+//!   to the config and the container restarted if it is running.
+//! - Checkpoints snapshot the root filesystem and config of each container an apply
+//!   modifies or deletes (`pct snapshot` on Proxmox, btrfs elsewhere), pruned by the
+//!   "lxc" snapshot target's policy; rollback restores them and destroys the
+//!   containers the apply created.
+//! - New containers get their `provision` section (hostname, packages, users,
+//!   files, commands) rendered into the root filesystem before the first start;
+//!   `query` reports how the run went.
+mod checkpoint;
+mod nspawn;
+mod provision;
+mod proxmox;
+mod tools;
+
+use crate::snapshot::SnapshotConfig;
 use crate::state::plugin::{
     ApplyResult, Checkpoint, DiffMetadata, PluginCapabilities, StateAction, StateDiff, StatePlugin,
 };
 use crate::state::plugtree::PlugTree;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+pub use nspawn::NspawnBackend;
+pub use provision::{Provision, ProvisionResult};
+pub use proxmox::ProxmoxBackend;
+pub use tools::LxcToolsBackend;
+
+/// Environment override for the container backend (proxmox, lxc or nspawn)
+pub const BACKEND_ENV: &str = "OPDBUS_CONTAINER_BACKEND";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LxcState {
     pub containers: Vec<ContainerInfo>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct ContainerInfo {
     pub id: String,
     pub veth: String,
     pub bridge: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub running: Option<bool>,
+    #[serde(flatten)]
+    pub config: ContainerConfig,
+    /// Set up at first boot; ignored for containers that already exist
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provision: Option<Provision>,
+    /// How provisioning went, read from running containers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provisioned: Option<ProvisionResult>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub properties: Option<HashMap<String, Value>>, // extensible (includes network_type, template, etc.)
 }
 
-pub struct LxcPlugin;
+/// Container settings kept in sync after creation. Omitted sections are left
+/// as the container has them; a declared mount or NIC list is the full list.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ContainerConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mounts: Option<Vec<MountPoint>>,
+    /// Proxmox features (nesting, keyctl, fuse, ...); undeclared ones are kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<BTreeMap<String, bool>>,
+    /// The first NIC is the one on the vi{ID} port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nics: Option<Vec<Nic>>,
+}
 
-impl LxcPlugin {
-    pub fn new() -> Self {
-        Self
-    }
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Resources {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cores: Option<u64>,
+    /// MiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u64>,
+    /// MiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap: Option<u64>,
+}
 
-    /// Apply state for a single container
-    pub async fn apply_container_state(&self, container: &ContainerInfo) -> Result<ApplyResult> {
-        let mut changes_applied = Vec::new();
-        let mut errors = Vec::new();
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MountPoint {
+    /// Host directory, or a storage volume on Proxmox
+    pub source: String,
+    /// Path inside the container
+    pub target: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
 
-        // Check if container exists
-        let current_containers = self.discover_from_ovs().await?;
-        let exists = current_containers.iter().any(|c| c.id == container.id);
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Nic {
+    /// Interface name inside the container
+    pub name: String,
+    pub bridge: String,
+    /// Access VLAN, untagged when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vlan: Option<u16>,
+    /// Proxmox firewall bridge; left alone when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firewall: Option<bool>,
+}
 
-        if !exists {
-            // Create container
-            match Self::create_container(container).await {
-                Ok(_) => {
-                    changes_applied.push(format!("Created container {}", container.id));
+impl Nic {
+    fn in_sync(&self, current: &Nic) -> bool {
+        self.name == current.name
+            && self.bridge == current.bridge
+            && self.vlan == current.vlan
+            && self
+                .firewall
+                .is_none_or(|firewall| current.firewall.unwrap_or(false) == firewall)
+    }
+}
 
-                    // Start it
-                    if let Err(e) = Self::start_container(&container.id).await {
-                        errors.push(format!("Failed to start container {}: {}", container.id, e));
-                    } else {
-                        changes_applied.push(format!("Started container {}", container.id));
-                    }
-                }
-                Err(e) => {
-                    errors.push(format!(
-                        "Failed to create container {}: {}",
-                        container.id, e
-                    ));
-                }
-            }
-        } else {
-            changes_applied.push(format!("Container {} already exists", container.id));
+impl ContainerConfig {
+    /// The declared settings that differ from `current`; NICs and mounts are
+    /// carried whole since backends rewrite them as a list
+    pub fn changes(&self, current: &ContainerConfig) -> ContainerConfig {
+        let resources = self.resources.as_ref().and_then(|want| {
+            let have = current.resources.clone().unwrap_or_default();
+            let changed = Resources {
+                cores: want.cores.filter(|cores| have.cores != Some(*cores)),
+                memory: want.memory.filter(|memory| have.memory != Some(*memory)),
+                swap: want.swap.filter(|swap| have.swap != Some(*swap)),
+            };
+            (changed != Resources::default()).then_some(changed)
+        });
+        let mounts = self
+            .mounts
+            .as_ref()
+            .filter(|want| {
+                let mut want = want.to_vec();
+                let mut have = current.mounts.clone().unwrap_or_default();
+                want.sort();
+                have.sort();
+                want != have
+            })
+            .cloned();
+        let features = self.features.as_ref().and_then(|want| {
+            let have = current.features.clone().unwrap_or_default();
+            let changed: BTreeMap<String, bool> = want
+                .iter()
+                .filter(|(name, on)| have.get(*name).copied().unwrap_or(false) != **on)
+                .map(|(name, on)| (name.clone(), *on))
+                .collect();
+            (!changed.is_empty()).then_some(changed)
+        });
+        let nics = self
+            .nics
+            .as_ref()
+            .filter(|want| {
+                let have = current.nics.as_deref().unwrap_or_default();
+                want.len() != have.len() || want.iter().zip(have).any(|(w, h)| !w.in_sync(h))
+            })
+            .cloned();
+        ContainerConfig {
+            resources,
+            mounts,
+            features,
+            nics,
         }
-
-        Ok(ApplyResult {
-            success: errors.is_empty(),
-            changes_applied,
-            errors,
-            checkpoint: None,
-        })
     }
 
-    fn is_running(ct_id: &str) -> Option<bool> {
-        // Proxmox systemd service path: pve-container@{vmid}.service (cgroup v2)
-        let path = format!(
-            "/sys/fs/cgroup/system.slice/pve-container@{}.service",
-            ct_id
-        );
-        Some(fs::metadata(path).is_ok())
+    pub fn is_empty(&self) -> bool {
+        *self == ContainerConfig::default()
     }
 
-    async fn discover_from_ovs(&self) -> Result<Vec<ContainerInfo>> {
-        let client = crate::native::OvsdbClient::new();
-        // If OVSDB is not reachable, return empty list
-        if client.list_dbs().await.is_err() {
-            return Ok(Vec::new());
+    /// Changed settings that only take effect on the next start. Resource
+    /// limits apply live, and so do the bridge and VLAN of the first NIC,
+    /// which are just settings of the vi{ID} port
+    fn restart_reasons(&self, current: &ContainerConfig) -> Vec<&'static str> {
+        let mut reasons = Vec::new();
+        if self.mounts.is_some() {
+            reasons.push("mounts");
         }
-
-        let mut results = Vec::new();
-        let bridges = client.list_bridges().await.unwrap_or_default();
-        for br in bridges {
-            let ports = client.list_bridge_ports(&br).await.unwrap_or_default();
-            for p in ports {
-                if let Some(ct_id) = p.strip_prefix("vi") {
-                    // ensure ID is numeric-like
-                    if ct_id.chars().all(|c| c.is_ascii_digit()) {
-                        let running = Self::is_running(ct_id);
-                        results.push(ContainerInfo {
-                            id: ct_id.to_string(),
-                            veth: p.clone(),
-                            bridge: br.clone(),
-                            running,
-                            properties: None,
-                        });
+        if self.features.is_some() {
+            reasons.push("features");
+        }
+        if let Some(want) = &self.nics {
+            let have = current.nics.as_deref().unwrap_or_default();
+            let live = want.len() == have.len()
+                && want.iter().zip(have).enumerate().all(|(i, (w, h))| {
+                    let port_only = Nic {
+                        bridge: h.bridge.clone(),
+                        vlan: h.vlan,
+                        ..w.clone()
+                    };
+                    if i == 0 {
+                        port_only.in_sync(h)
+                    } else {
+                        w.in_sync(h)
                     }
-                }
+                });
+            if !live {
+                reasons.push("nics");
             }
         }
-        Ok(results)
+        reasons
     }
 }
 
-impl Default for LxcPlugin {
-    fn default() -> Self {
-        Self::new()
-    }
+/// A container snapshot taken for a checkpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ContainerSnapshot {
+    id: String,
+    snapshot: String,
+    /// Whether the container was running when snapshotted
+    running: bool,
 }
 
-#[async_trait]
-impl PlugTree for LxcPlugin {
-    fn pluglet_type(&self) -> &str {
-        "container"
-    }
-
-    fn pluglet_id_field(&self) -> &str {
-        "id"
-    }
-
-    fn extract_pluglet_id(&self, resource: &Value) -> Result<String> {
-        resource
-            .get("id")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string())
-            .ok_or_else(|| anyhow::anyhow!("Container missing 'id' field"))
-    }
-
-    async fn apply_pluglet(&self, _pluglet_id: &str, desired: &Value) -> Result<ApplyResult> {
-        let container: ContainerInfo = serde_json::from_value(desired.clone())?;
-        self.apply_container_state(&container).await
-    }
-
-    async fn query_pluglet(&self, pluglet_id: &str) -> Result<Option<Value>> {
-        let containers = self.discover_from_ovs().await?;
+/// A Modify action on an existing container
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ContainerChanges {
+    /// Bridge and VLAN the vi{ID} port should be on
+    pub bridge: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vlan: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub running: Option<bool>,
+    #[serde(flatten)]
+    pub config: ContainerConfig,
+}
 
-        for container in containers {
-            if container.id == pluglet_id {
-                return Ok(Some(serde_json::to_value(container)?));
+impl ContainerChanges {
+    /// What it takes to turn `current` into `desired`, None when in sync
+    pub fn between(current: &ContainerInfo, desired: &ContainerInfo) -> Option<Self> {
+        let (bridge, vlan) = desired.port_settings(current);
+        let mut config = desired.config.changes(&current.config);
+
+        // Without declared NICs the top-level bridge is the first NIC's
+        if desired.config.nics.is_none() {
+            if let Some(first) = current.config.nics.as_ref().and_then(|nics| nics.first()) {
+                if first.bridge != bridge {
+                    let mut nics = current.config.nics.clone().unwrap_or_default();
+                    nics[0].bridge = bridge.clone();
+                    config.nics = Some(nics);
+                }
             }
         }
 
-        Ok(None)
-    }
+        let running = desired.running.filter(|on| current.running != Some(*on));
+        // A stopped container has no port; it is put right when started
+        let current_vlan = current
+            .config
+            .nics
+            .as_ref()
+            .and_then(|nics| nics.first())
+            .and_then(|nic| nic.vlan);
+        let port_moved =
+            current.running == Some(true) && (current.bridge != bridge || current_vlan != vlan);
 
-    async fn list_pluglet_ids(&self) -> Result<Vec<String>> {
-        let containers = self.discover_from_ovs().await?;
-        Ok(containers.into_iter().map(|c| c.id).collect())
+        if config.is_empty() && running.is_none() && !port_moved {
+            return None;
+        }
+        Some(Self {
+            bridge,
+            vlan,
+            running,
+            config,
+        })
     }
 }
 
-impl LxcPlugin {
-    /// Find container's veth interface name
-    async fn find_container_veth(ct_id: &str) -> Result<String> {
-        // Try to get the actual interface name from the container's eth0 peer
-        let output = tokio::process::Command::new("ip")
-            .args([
-                "netns",
-                "exec",
-                &format!("ct{}", ct_id),
-                "ip",
-                "link",
-                "show",
-                "eth0",
-            ])
-            .output()
-            .await?;
+impl ContainerInfo {
+    /// Hostname to create the container with: the provisioned one, else the
+    /// `hostname` property, else ct{ID}
+    fn hostname(&self) -> String {
+        self.provision
+            .as_ref()
+            .and_then(|provision| provision.hostname.clone())
+            .or_else(|| {
+                prop(self, "hostname")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            })
+            .unwrap_or_else(|| format!("ct{}", self.id))
+    }
 
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            // Look for the peer link index
-            for line in stdout.lines() {
-                if line.contains("link-netnsid") || line.contains("@if") {
-                    // Parse the peer interface name from the host side
-                    // Format: "veth<random>@if<index>"
-                    let veth_interfaces = crate::native::rtnetlink_helpers::list_veth_interfaces().await?;
-
-                    // Find veth that matches this container's namespace
-                    for veth_name in veth_interfaces {
-                        if !veth_name.is_empty() {
-                            return Ok(veth_name);
-                        }
-                    }
-                }
-            }
+    /// Bridge and VLAN of the vi{ID} port: the first declared NIC's, else the
+    /// top-level bridge (the current one if unset) with the VLAN the container
+    /// already has
+    fn port_settings(&self, current: &ContainerInfo) -> (String, Option<u16>) {
+        if let Some(first) = self.config.nics.as_ref().and_then(|nics| nics.first()) {
+            return (first.bridge.clone(), first.vlan);
         }
-
-        // Fallback: try to find veth by checking all veth pairs
-        let veth_interfaces = crate::native::rtnetlink_helpers::list_veth_interfaces().await?;
-        // Look for any veth interface (first one found)
-        for veth_name in veth_interfaces {
-            if !veth_name.is_empty() && veth_name.starts_with("veth") {
-                log::info!("Found veth interface: {}", veth_name);
-                return Ok(veth_name);
-            }
+        let vlan = current
+            .config
+            .nics
+            .as_ref()
+            .and_then(|nics| nics.first())
+            .and_then(|nic| nic.vlan);
+        let bridge = LxcPlugin::get_bridge_for_network_type(self);
+        if bridge.is_empty() {
+            return (current.bridge.clone(), vlan);
         }
-
-        Err(anyhow::anyhow!(
-            "Could not find veth interface for container {}",
-            ct_id
-        ))
+        (bridge, vlan)
     }
+}
 
-    /// Determine bridge based on network type
-    fn get_bridge_for_network_type(container: &ContainerInfo) -> String {
-        let network_type = container
-            .properties
-            .as_ref()
-            .and_then(|p| p.get("network_type"))
-            .and_then(|v| v.as_str())
-            .unwrap_or("bridge");
+/// A container manager on this host
+#[async_trait]
+pub trait ContainerBackend: Send + Sync {
+    fn name(&self) -> &'static str;
 
-        match network_type {
-            "netmaker" => "mesh".to_string(),     // Netmaker mesh bridge
-            "bridge" => container.bridge.clone(), // Traditional bridge (ovsbr0)
-            _ => container.bridge.clone(),
-        }
+    /// Whether the manager's tooling is installed
+    fn is_available(&self) -> bool;
+
+    /// Host files holding container configs, restored from the pre-apply
+    /// host snapshot when the plugin cannot roll back
+    fn config_paths(&self) -> Vec<PathBuf> {
+        Vec::new()
     }
 
-    /// Create LXC container via pct (Proxmox)
-    async fn create_container(container: &ContainerInfo) -> Result<()> {
-        log::info!("Creating LXC container {}", container.id);
+    /// Containers of this manager, with their port, bridge and running state
+    async fn discover(&self) -> Result<Vec<ContainerInfo>>;
 
-        // Select bridge based on network type
-        let bridge = Self::get_bridge_for_network_type(container);
-        log::info!("Container {} will use bridge {}", container.id, bridge);
+    /// Addresses configured for a container
+    async fn addresses(&self, id: &str) -> Result<Vec<IpAddr>>;
 
-        // Extract properties with sensible defaults
-        let props = container.properties.as_ref();
+    /// MAC address of a container's interface on `bridge`, if its config fixes one
+    async fn hwaddr(&self, id: &str, bridge: &str) -> Result<Option<String>>;
 
-        // Check if using BTRFS golden image (fast path) or tar.zst template (slow path)
-        let golden_image = props
-            .and_then(|p| p.get("golden_image"))
-            .and_then(|v| v.as_str());
+    /// Resource limits, mounts, features and NICs from the container's config
+    async fn config(&self, id: &str) -> Result<ContainerConfig>;
 
-        if let Some(golden_image_name) = golden_image {
-            // BTRFS snapshot path - instant container creation
-            return Self::create_container_from_btrfs_snapshot(container, golden_image_name, &bridge).await;
-        }
+    /// Write the settings in `changes` to the container's config, applying
+    /// resource limits live when it is running
+    async fn reconfigure(&self, id: &str, changes: &ContainerConfig, running: bool) -> Result<()>;
 
-        // Traditional tar.zst template path (fallback)
-        let template = props
-            .and_then(|p| p.get("template"))
-            .and_then(|v| v.as_str())
-            .unwrap_or("local-btrfs:vztmpl/debian-13-standard_13.1-2_amd64.tar.zst");
+    /// Create (but don't start) a container attached to `bridge`
+    async fn create(&self, container: &ContainerInfo, bridge: &str) -> Result<()>;
 
-        // Hostname
-        let hostname = props
-            .and_then(|p| p.get("hostname"))
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| format!("ct{}", container.id));
-
-        // Memory (MB)
-        let memory = props
-            .and_then(|p| p.get("memory"))
-            .and_then(|v| v.as_u64())
-            .unwrap_or(512);
-
-        // Swap (MB)
-        let swap = props
-            .and_then(|p| p.get("swap"))
-            .and_then(|v| v.as_u64())
-            .unwrap_or(512);
-
-        // Storage location and size
-        let storage = props
-            .and_then(|p| p.get("storage"))
-            .and_then(|v| v.as_str())
-            .unwrap_or("local-btrfs");
-
-        let rootfs_size = props
-            .and_then(|p| p.get("rootfs_size"))
-            .and_then(|v| v.as_u64())
-            .unwrap_or(8);
-
-        let rootfs = format!("{}:{}", storage, rootfs_size);
-
-        // CPU cores
-        let cores = props
-            .and_then(|p| p.get("cores"))
-            .and_then(|v| v.as_u64())
-            .unwrap_or(2);
-
-        // Unprivileged mode
-        let unprivileged = props
-            .and_then(|p| p.get("unprivileged"))
-            .and_then(|v| v.as_bool())
-            .unwrap_or(true);
-
-        // Features (comma-separated)
-        let features = props
-            .and_then(|p| p.get("features"))
-            .and_then(|v| v.as_str())
-            .unwrap_or("nesting=1");
+    async fn start(&self, id: &str) -> Result<()>;
 
-        // Network configuration
-        let firewall = props
-            .and_then(|p| p.get("firewall"))
-            .and_then(|v| v.as_bool())
-            .unwrap_or(true);
+    async fn stop(&self, id: &str) -> Result<()>;
 
-        let net0 = format!(
-            "name=eth0,bridge={},firewall={}",
-            bridge,
-            if firewall { "1" } else { "0" }
-        );
+    async fn destroy(&self, id: &str) -> Result<()>;
 
-        // Optional: IP address configuration
-        if let Some(ip) = props.and_then(|p| p.get("ip")).and_then(|v| v.as_str()) {
-            // ip can be "dhcp" or "192.168.1.100/24"
-            // pct expects: --net0 "name=eth0,bridge=vmbr0,ip=192.168.1.100/24,gw=192.168.1.1"
-            // For now, we'll handle this in a future enhancement
-            log::info!("IP configuration: {} (note: not yet implemented)", ip);
-        }
+    /// Host end of a running container's veth, before it is renamed to vi{ID}
+    async fn host_veth(&self, id: &str) -> Result<String>;
 
-        log::info!(
-            "Creating container {}: template={}, memory={}MB, cores={}, rootfs={}",
-            container.id,
-            template,
-            memory,
-            cores,
-            rootfs
-        );
+    /// Host path of a stopped container's root filesystem, mounting it if the
+    /// storage needs that
+    async fn mount_rootfs(&self, id: &str) -> Result<PathBuf>;
 
-        // Build pct create command
-        let mut cmd = tokio::process::Command::new("pct");
-        cmd.args([
-            "create",
-            &container.id,
-            template,
-            "--hostname",
-            hostname.as_str(),
-            "--memory",
-            &memory.to_string(),
-            "--swap",
-            &swap.to_string(),
-            "--cores",
-            &cores.to_string(),
-            "--rootfs",
-            &rootfs,
-            "--net0",
-            &net0,
-            "--unprivileged",
-            if unprivileged { "1" } else { "0" },
-            "--features",
-            features,
-        ]);
+    /// Undo mount_rootfs
+    async fn unmount_rootfs(&self, id: &str) -> Result<()>;
 
-        // Optional: Start on boot
-        if let Some(onboot) = props.and_then(|p| p.get("onboot")).and_then(|v| v.as_bool()) {
-            cmd.args(["--onboot", if onboot { "1" } else { "0" }]);
-        }
+    /// PID of a running container's init, as seen from the host
+    async fn init_pid(&self, id: &str) -> Result<Option<u32>>;
 
-        // Optional: Protection (prevent accidental deletion)
-        if let Some(protection) = props.and_then(|p| p.get("protection")).and_then(|v| v.as_bool()) {
-            cmd.args(["--protection", if protection { "1" } else { "0" }]);
-        }
+    /// Snapshot a container's root filesystem and config as `name`, pruning
+    /// older op-dbus snapshots by the snapshot target's policy
+    async fn snapshot(&self, id: &str, name: &str, config: &SnapshotConfig) -> Result<()>;
 
-        // Optional: Nameserver
-        if let Some(nameserver) = props.and_then(|p| p.get("nameserver")).and_then(|v| v.as_str()) {
-            cmd.args(["--nameserver", nameserver]);
-        }
+    /// Put a stopped container back the way it was at snapshot `name`
+    async fn restore(&self, id: &str, name: &str, config: &SnapshotConfig) -> Result<()>;
+}
 
-        // Optional: Searchdomain
-        if let Some(searchdomain) = props.and_then(|p| p.get("searchdomain")).and_then(|v| v.as_str()) {
-            cmd.args(["--searchdomain", searchdomain]);
-        }
+/// Resolve a backend by name
+pub fn backend_for(name: &str) -> Result<Box<dyn ContainerBackend>> {
+    Ok(match name {
+        "proxmox" | "pct" => Box::new(ProxmoxBackend),
+        "lxc" => Box::new(LxcToolsBackend),
+        "nspawn" | "machined" => Box::new(NspawnBackend),
+        other => anyhow::bail!(
+            "Unknown container backend '{}' (expected proxmox, lxc or nspawn)",
+            other
+        ),
+    })
+}
 
-        // Execute pct create
-        let output = cmd.output().await?;
+/// Pick the first manager installed on this host, Proxmox first since it
+/// ships the lxc tools too
+fn detect_backend() -> Box<dyn ContainerBackend> {
+    let candidates: [Box<dyn ContainerBackend>; 3] = [
+        Box::new(ProxmoxBackend),
+        Box::new(LxcToolsBackend),
+        Box::new(NspawnBackend),
+    ];
+    candidates
+        .into_iter()
+        .find(|backend| backend.is_available())
+        .unwrap_or_else(|| Box::new(ProxmoxBackend))
+}
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("pct create failed: {}", stderr));
-        }
+/// Whether an executable is on $PATH
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
 
-        log::info!(
-            "Container {} created successfully on bridge {}",
-            container.id,
-            bridge
-        );
+/// Run a manager tool, failing with its stderr
+async fn run(program: &str, args: &[&str]) -> Result<()> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {}", program))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{} failed: {}", program, stderr.trim());
+    }
+    Ok(())
+}
 
-        // Inject netmaker token for first-boot join (if netmaker network type)
-        let network_type = props
-            .and_then(|p| p.get("network_type"))
-            .and_then(|v| v.as_str())
-            .unwrap_or("bridge");
+/// A container property from the state file
+fn prop<'a>(container: &'a ContainerInfo, key: &str) -> Option<&'a Value> {
+    container.properties.as_ref()?.get(key)
+}
 
-        if network_type == "netmaker" {
-            // Read token from host
-            if let Ok(token) = tokio::fs::read_to_string("/etc/op-dbus/netmaker.env").await {
-                // Parse NETMAKER_TOKEN=xxx from env file
-                for line in token.lines() {
-                    if let Some(token_value) = line.strip_prefix("NETMAKER_TOKEN=") {
-                        let token_clean = token_value.trim_matches('"').trim();
-
-                        // Write token to container's rootfs /root/.bashrc
-                        let bashrc_path =
-                            format!("/var/lib/lxc/{}/rootfs/root/.bashrc", container.id);
-
-                        // Append export statement to bashrc
-                        let export_line = format!("\nexport NETMAKER_TOKEN={}\n", token_clean);
-
-                        // Read existing bashrc if it exists
-                        let existing_content = tokio::fs::read_to_string(&bashrc_path)
-                            .await
-                            .unwrap_or_default();
-
-                        // Append export if not already present
-                        if !existing_content.contains("NETMAKER_TOKEN") {
-                            match tokio::fs::write(
-                                &bashrc_path,
-                                format!("{}{}", existing_content, export_line),
-                            )
-                            .await
-                            {
-                                Ok(_) => {
-                                    log::info!(
-                                        "Injected netmaker token into {} .bashrc",
-                                        container.id
-                                    );
-                                }
-                                Err(e) => {
-                                    log::warn!(
-                                        "Failed to inject netmaker token into {}: {}",
-                                        container.id,
-                                        e
-                                    );
-                                }
-                            }
-                        }
-                        break;
-                    }
-                }
-            }
+/// OVS bridge of every port, empty when OVSDB is unreachable
+async fn ovs_port_bridges() -> HashMap<String, String> {
+    let client = crate::native::OvsdbClient::new();
+    let mut bridges = HashMap::new();
+    for bridge in client.list_bridges().await.unwrap_or_default() {
+        for port in client.list_bridge_ports(&bridge).await.unwrap_or_default() {
+            bridges.insert(port, bridge.clone());
         }
-
-        Ok(())
     }
+    bridges
+}
 
-    /// Create LXC container from BTRFS golden image snapshot (instant provisioning)
-    async fn create_container_from_btrfs_snapshot(
-        container: &ContainerInfo,
-        golden_image_name: &str,
-        bridge: &str,
-    ) -> Result<()> {
-        log::info!(
-            "Creating container {} from BTRFS golden image: {}",
-            container.id,
-            golden_image_name
+/// Container ids become part of the vi{ID} interface name (at most 15 bytes)
+fn check_port_name(id: &str) -> Result<()> {
+    if id.is_empty()
+        || id.len() > 13
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!(
+            "Container id '{}' must be 1-13 letters, digits, '-' or '_' so vi{} is a valid interface name",
+            id,
+            id
         );
+    }
+    Ok(())
+}
 
-        let props = container.properties.as_ref();
+pub struct LxcPlugin {
+    backend: Box<dyn ContainerBackend>,
+}
 
-        // Storage backend (configurable per container)
-        let storage = props
-            .and_then(|p| p.get("storage"))
-            .and_then(|v| v.as_str())
-            .unwrap_or("local-btrfs");
-
-        // Proxmox storage paths (adjust based on storage.cfg configuration)
-        let storage_path = format!("/var/lib/pve/{}", storage);
-        let golden_image_path = format!("{}/templates/subvol/{}", storage_path, golden_image_name);
-        let container_rootfs = format!("{}/images/{}/rootfs", storage_path, container.id);
-        let container_dir = format!("{}/images/{}", storage_path, container.id);
-
-        // Verify golden image exists
-        if tokio::fs::metadata(&golden_image_path).await.is_err() {
-            return Err(anyhow::anyhow!(
-                "Golden image not found: {}. Create it with: sudo ./create-btrfs-golden-image.sh {}",
-                golden_image_path,
-                golden_image_name
-            ));
+impl LxcPlugin {
+    pub fn new() -> Self {
+        let configured = std::env::var(BACKEND_ENV).ok().and_then(|name| {
+            backend_for(&name)
+                .map_err(|e| log::warn!("{}; detecting the container backend instead", e))
+                .ok()
+        });
+        Self {
+            backend: configured.unwrap_or_else(detect_backend),
         }
+    }
 
-        // Check if it's a BTRFS subvolume
-        let check_output = tokio::process::Command::new("btrfs")
-            .args(["subvolume", "show", &golden_image_path])
-            .output()
-            .await?;
+    /// Plugin on an explicit backend
+    pub fn with_backend(backend: Box<dyn ContainerBackend>) -> Self {
+        Self { backend }
+    }
+
+    /// Name of the container manager in use
+    pub fn backend_name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    /// Apply state for a single container
+    pub async fn apply_container_state(&self, container: &ContainerInfo) -> Result<ApplyResult> {
+        let mut changes_applied = Vec::new();
+        let mut errors = Vec::new();
+
+        // Check if container exists
+        let current_containers = self.inspect().await?;
+        let current = current_containers.iter().find(|c| c.id == container.id);
+
+        if let Some(current) = current {
+            match ContainerChanges::between(current, container) {
+                Some(changes) => match self.modify_container(&container.id, &changes).await {
+                    Ok(mut applied) => changes_applied.append(&mut applied),
+                    Err(e) => errors.push(format!(
+                        "Failed to update container {}: {:#}",
+                        container.id, e
+                    )),
+                },
+                None => changes_applied.push(format!("Container {} is up to date", container.id)),
+            }
+        } else {
+            // Create container
+            let (bridge, _) = container.port_settings(&ContainerInfo::default());
+            match self.create_container(container, &bridge).await {
+                Ok(_) => {
+                    changes_applied.push(format!("Created container {}", container.id));
 
-        if !check_output.status.success() {
-            return Err(anyhow::anyhow!(
-                "Golden image is not a BTRFS subvolume: {}",
-                golden_image_path
-            ));
+                    // Start it
+                    if let Err(e) = self.backend.start(&container.id).await {
+                        errors.push(format!("Failed to start container {}: {}", container.id, e));
+                    } else {
+                        changes_applied.push(format!("Started container {}", container.id));
+                    }
+                }
+                Err(e) => {
+                    errors.push(format!(
+                        "Failed to create container {}: {}",
+                        container.id, e
+                    ));
+                }
+            }
         }
 
-        log::info!("✓ Golden image verified: {}", golden_image_path);
-
-        // Create container directory
-        tokio::fs::create_dir_all(&container_dir).await?;
-
-        // Create BTRFS snapshot (instant copy-on-write)
-        log::info!("Creating BTRFS snapshot...");
-        let snapshot_output = tokio::process::Command::new("btrfs")
-            .args([
-                "subvolume",
-                "snapshot",
-                &golden_image_path,
-                &container_rootfs,
-            ])
-            .output()
-            .await?;
+        Ok(ApplyResult {
+            success: errors.is_empty(),
+            changes_applied,
+            errors,
+            checkpoint: None,
+        })
+    }
 
-        if !snapshot_output.status.success() {
-            let stderr = String::from_utf8_lossy(&snapshot_output.stderr);
-            return Err(anyhow::anyhow!("BTRFS snapshot failed: {}", stderr));
+    /// Static addresses of the containers on this host, read from their
+    /// configs (DHCP/SLAAC interfaces have none to report)
+    pub async fn container_addresses(&self) -> Result<Vec<(ContainerInfo, Vec<IpAddr>)>> {
+        let mut results = Vec::new();
+        for container in self.backend.discover().await? {
+            let addresses = match self.backend.addresses(&container.id).await {
+                Ok(addresses) => addresses,
+                Err(e) => {
+                    log::debug!("No config for container {}: {}", container.id, e);
+                    Vec::new()
+                }
+            };
+            results.push((container, addresses));
         }
+        Ok(results)
+    }
 
-        log::info!("✓ BTRFS snapshot created in <1ms: {}", container_rootfs);
+    /// Addresses configured for one container
+    pub async fn container_config_addresses(&self, id: &str) -> Result<Vec<IpAddr>> {
+        self.backend.addresses(id).await
+    }
 
-        // Extract properties
-        let hostname = props
-            .and_then(|p| p.get("hostname"))
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| format!("ct{}", container.id));
-
-        let memory = props
-            .and_then(|p| p.get("memory"))
-            .and_then(|v| v.as_u64())
-            .unwrap_or(512);
-
-        let swap = props
-            .and_then(|p| p.get("swap"))
-            .and_then(|v| v.as_u64())
-            .unwrap_or(512);
-
-        let cores = props
-            .and_then(|p| p.get("cores"))
-            .and_then(|v| v.as_u64())
-            .unwrap_or(2);
-
-        let unprivileged = props
-            .and_then(|p| p.get("unprivileged"))
-            .and_then(|v| v.as_bool())
-            .unwrap_or(true);
-
-        let features = props
-            .and_then(|p| p.get("features"))
-            .and_then(|v| v.as_str())
-            .unwrap_or("nesting=1");
-
-        let firewall = props
-            .and_then(|p| p.get("firewall"))
-            .and_then(|v| v.as_bool())
-            .unwrap_or(true);
-
-        // Create Proxmox container configuration
-        let config_path = format!("/etc/pve/lxc/{}.conf", container.id);
-        let config_content = format!(
-            r#"arch: amd64
-cores: {}
-hostname: {}
-memory: {}
-swap: {}
-net0: name=eth0,bridge={},firewall={}
-ostype: debian
-rootfs: local-btrfs:images/{}/rootfs
-unprivileged: {}
-features: {}
-"#,
-            cores,
-            hostname,
-            memory,
-            swap,
-            bridge,
-            if firewall { "1" } else { "0" },
+    /// MAC address of a container's interface on `bridge`, from its config
+    pub async fn container_hwaddr(&self, id: &str, bridge: &str) -> Result<Option<String>> {
+        self.backend.hwaddr(id, bridge).await
+    }
+
+    /// Create a container on the backend with its declared settings
+    async fn create_container(&self, container: &ContainerInfo, bridge: &str) -> Result<()> {
+        log::info!(
+            "Creating container {} with {} on bridge {}",
             container.id,
-            if unprivileged { "1" } else { "0" },
-            features
+            self.backend.name(),
+            bridge
         );
+        let provision = provision::for_container(container).await?;
+        self.backend.create(container, bridge).await?;
+        if !container.config.is_empty() {
+            self.backend
+                .reconfigure(&container.id, &container.config, false)
+                .await?;
+        }
+        if let Some(provision) = provision {
+            self.provision_container(&container.id, &provision).await?;
+        }
+        Ok(())
+    }
 
-        // Add optional properties
-        let mut config = config_content;
+    /// Render provisioning into a new container's root filesystem
+    async fn provision_container(&self, id: &str, provision: &Provision) -> Result<()> {
+        let rootfs = self.backend.mount_rootfs(id).await?;
+        let rendered = provision::render(&rootfs, provision).await;
+        let unmounted = self.backend.unmount_rootfs(id).await;
+        if rendered.with_context(|| format!("Failed to provision container {}", id))? {
+            log::info!("Provisioning for container {} runs at first boot", id);
+        } else {
+            log::info!("Container {} was already provisioned", id);
+        }
+        unmounted
+    }
 
-        if let Some(onboot) = props.and_then(|p| p.get("onboot")).and_then(|v| v.as_bool()) {
-            config.push_str(&format!("onboot: {}\n", if onboot { "1" } else { "0" }));
+    /// Fill in the provisioning outcome of running containers
+    async fn read_provisioned(&self, containers: &mut [ContainerInfo]) {
+        for container in containers.iter_mut().filter(|c| c.running == Some(true)) {
+            match self.backend.init_pid(&container.id).await {
+                Ok(Some(pid)) => {
+                    let root = PathBuf::from(format!("/proc/{}/root", pid));
+                    container.provisioned = provision::read_result(&root).await;
+                }
+                Ok(None) => {}
+                Err(e) => log::debug!("No init PID for container {}: {}", container.id, e),
+            }
         }
+    }
 
-        if let Some(protection) = props.and_then(|p| p.get("protection")).and_then(|v| v.as_bool()) {
-            config.push_str(&format!("protection: {}\n", if protection { "1" } else { "0" }));
+    /// Containers with their configured settings filled in
+    async fn inspect(&self) -> Result<Vec<ContainerInfo>> {
+        let mut containers = self.backend.discover().await?;
+        for container in &mut containers {
+            match self.backend.config(&container.id).await {
+                Ok(config) => container.config = config,
+                Err(e) => log::debug!("No config for container {}: {}", container.id, e),
+            }
         }
+        Ok(containers)
+    }
 
-        if let Some(nameserver) = props.and_then(|p| p.get("nameserver")).and_then(|v| v.as_str()) {
-            config.push_str(&format!("nameserver: {}\n", nameserver));
+    /// Bring an existing container in line with `changes`, restarting it when
+    /// a changed setting can't be applied live
+    async fn modify_container(&self, id: &str, changes: &ContainerChanges) -> Result<Vec<String>> {
+        let current = self
+            .inspect()
+            .await?
+            .into_iter()
+            .find(|c| c.id == id)
+            .with_context(|| format!("Container {} not found", id))?;
+        let running = current.running == Some(true);
+        let mut applied = Vec::new();
+
+        if !changes.config.is_empty() {
+            self.backend
+                .reconfigure(id, &changes.config, running)
+                .await?;
+            applied.push(format!("Updated config of container {}", id));
         }
 
-        if let Some(searchdomain) = props.and_then(|p| p.get("searchdomain")).and_then(|v| v.as_str()) {
-            config.push_str(&format!("searchdomain: {}\n", searchdomain));
+        let reasons = changes.config.restart_reasons(&current.config);
+        match changes.running {
+            Some(false) if running => {
+                self.backend.stop(id).await?;
+                applied.push(format!("Stopped container {}", id));
+            }
+            Some(true) if !running => {
+                let mut attached = self
+                    .start_container(id, &changes.bridge, changes.vlan)
+                    .await?;
+                applied.push(format!("Started container {}", id));
+                applied.append(&mut attached);
+            }
+            _ if running && !reasons.is_empty() => {
+                self.backend.stop(id).await?;
+                let mut attached = self
+                    .start_container(id, &changes.bridge, changes.vlan)
+                    .await?;
+                applied.push(format!(
+                    "Restarted container {} to apply {}",
+                    id,
+                    reasons.join(", ")
+                ));
+                applied.append(&mut attached);
+            }
+            _ if running => {
+                let port = format!("vi{}", id);
+                applied.append(&mut Self::sync_port(&port, &changes.bridge, changes.vlan).await?);
+            }
+            _ => {}
         }
+        Ok(applied)
+    }
 
-        // Write Proxmox config
-        tokio::fs::write(&config_path, config).await?;
+    /// Start a container and put its port on the bridge once the veth is up,
+    /// returning the port changes
+    async fn start_container(
+        &self,
+        id: &str,
+        bridge: &str,
+        vlan: Option<u16>,
+    ) -> Result<Vec<String>> {
+        self.backend.start(id).await?;
 
-        log::info!("✓ Proxmox configuration written: {}", config_path);
+        // Wait for veth to appear
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        self.attach_port(id, bridge, vlan).await
+    }
 
-        // Inject firstboot script if specified
-        if let Some(firstboot_script) = props.and_then(|p| p.get("firstboot_script")).and_then(|v| v.as_str()) {
-            Self::inject_firstboot_script(container, storage, firstboot_script).await?;
+    /// Rename the container's host veth to vi{ID} and put it on the OVS bridge,
+    /// skipping whatever the backend already did itself
+    async fn attach_port(&self, id: &str, bridge: &str, vlan: Option<u16>) -> Result<Vec<String>> {
+        let mut changes = Vec::new();
+        let port = format!("vi{}", id);
+
+        if crate::native::rtnetlink_links::get_link(&port)
+            .await?
+            .is_none()
+        {
+            let veth = self.backend.host_veth(id).await?;
+            log::info!("Found veth {} for container {}", veth, id);
+            crate::native::rtnetlink_helpers::link_set_name(&veth, &port).await?;
+            changes.push(format!("Renamed {} to {}", veth, port));
         }
 
-        // Inject Netmaker token for netmaker network type
-        let network_type = props
-            .and_then(|p| p.get("network_type"))
-            .and_then(|v| v.as_str())
-            .unwrap_or("bridge");
+        changes.append(&mut Self::sync_port(&port, bridge, vlan).await?);
+        Ok(changes)
+    }
 
-        if network_type == "netmaker" {
-            Self::inject_netmaker_token(container, storage).await?;
+    /// Restore a container from a checkpoint snapshot, stopping it for the
+    /// restore and starting it again if it was running when snapshotted
+    async fn restore_container(
+        &self,
+        snapshot: &ContainerSnapshot,
+        running: bool,
+        saved: &ContainerInfo,
+        config: &SnapshotConfig,
+    ) -> Result<()> {
+        if running {
+            self.backend.stop(&snapshot.id).await?;
         }
-
+        self.backend
+            .restore(&snapshot.id, &snapshot.snapshot, config)
+            .await?;
         log::info!(
-            "✓ Container {} created from golden image '{}' (BTRFS snapshot)",
-            container.id,
-            golden_image_name
+            "Restored container {} from snapshot {}",
+            snapshot.id,
+            snapshot.snapshot
         );
 
+        if snapshot.running {
+            let (bridge, vlan) = saved.port_settings(saved);
+            self.start_container(&snapshot.id, &bridge, vlan).await?;
+        }
         Ok(())
     }
 
-    /// Inject firstboot script into container rootfs
-    async fn inject_firstboot_script(container: &ContainerInfo, storage: &str, script_content: &str) -> Result<()> {
-        let rootfs = format!("/var/lib/pve/{}/images/{}/rootfs", storage, container.id);
-        let script_path = format!("{}/usr/local/bin/lxc-firstboot.sh", rootfs);
-        let service_path = format!("{}/etc/systemd/system/lxc-firstboot.service", rootfs);
-
-        // Create script directory if needed
-        tokio::fs::create_dir_all(format!("{}/usr/local/bin", rootfs)).await?;
-
-        // Write firstboot script
-        tokio::fs::write(&script_path, script_content).await?;
-
-        // Make executable
-        tokio::process::Command::new("chmod")
-            .args(["+x", &script_path])
-            .output()
-            .await?;
-
-        // Create systemd service
-        let service_content =
-            r#"[Unit]
-Description=LXC First Boot Initialization
-After=network-online.target
-Wants=network-online.target
-ConditionPathExists=!/var/lib/lxc-firstboot-complete
+    /// Checkpoint of the containers in `ids` (all of them when None),
+    /// snapshotted unless the "lxc" snapshot target is disabled, plus the
+    /// containers an apply is about to create
+    async fn checkpoint_containers(
+        &self,
+        ids: Option<&[String]>,
+        created: Vec<String>,
+    ) -> Result<Checkpoint> {
+        let containers: Vec<ContainerInfo> = self
+            .inspect()
+            .await?
+            .into_iter()
+            .filter(|c| ids.is_none_or(|ids| ids.contains(&c.id)))
+            .collect();
+        let config = checkpoint::snapshot_config();
+
+        let mut snapshots = Vec::new();
+        if config.enabled && !containers.is_empty() {
+            let name = checkpoint::snapshot_name();
+            for container in &containers {
+                match self.backend.snapshot(&container.id, &name, &config).await {
+                    Ok(()) => snapshots.push(ContainerSnapshot {
+                        id: container.id.clone(),
+                        snapshot: name.clone(),
+                        running: container.running == Some(true),
+                    }),
+                    Err(e) => log::warn!(
+                        "No checkpoint snapshot of container {}: {:#}",
+                        container.id,
+                        e
+                    ),
+                }
+            }
+        }
 
-[Service]
-Type=oneshot
-ExecStart=/usr/local/bin/lxc-firstboot.sh
-RemainAfterExit=yes
+        Ok(Checkpoint {
+            id: format!("lxc-{}", chrono::Utc::now().timestamp()),
+            plugin: "lxc".into(),
+            timestamp: chrono::Utc::now().timestamp(),
+            state_snapshot: serde_json::to_value(LxcState { containers })?,
+            backend_checkpoint: Some(json!({ "snapshots": snapshots, "created": created })),
+        })
+    }
 
-[Install]
-WantedBy=multi-user.target
-"#.to_string();
+    /// Stop and destroy a container, dropping its OVS port
+    async fn remove_container(&self, container: &ContainerInfo) -> Result<()> {
+        if container.running == Some(true) {
+            self.backend.stop(&container.id).await?;
+        }
+        if let Err(e) = Self::cleanup_ovs_port_for_container(&container.id).await {
+            log::debug!(
+                "No OVS port to remove for container {}: {}",
+                container.id,
+                e
+            );
+        }
+        self.backend.destroy(&container.id).await?;
+        log::info!(
+            "Removed container {} created since the checkpoint",
+            container.id
+        );
+        Ok(())
+    }
 
-        tokio::fs::create_dir_all(format!("{}/etc/systemd/system", rootfs)).await?;
-        tokio::fs::write(&service_path, service_content).await?;
+    /// Put a port on `bridge`, moving it off any other, with the given VLAN
+    async fn sync_port(port: &str, bridge: &str, vlan: Option<u16>) -> Result<Vec<String>> {
+        let client = crate::native::OvsdbClient::new();
+        let mut changes = Vec::new();
+
+        match ovs_port_bridges().await.get(port) {
+            Some(current) if current == bridge => {}
+            Some(current) => {
+                client.delete_port(current, port).await?;
+                client.add_port(bridge, port).await?;
+                changes.push(format!(
+                    "Moved {} from bridge {} to {}",
+                    port, current, bridge
+                ));
+            }
+            None => {
+                client.add_port(bridge, port).await?;
+                changes.push(format!("Added {} to bridge {}", port, bridge));
+            }
+        }
 
-        // Enable service (create symlink)
-        let symlink_dir = format!("{}/etc/systemd/system/multi-user.target.wants", rootfs);
-        tokio::fs::create_dir_all(&symlink_dir).await?;
+        if client.port_tag(port).await? != vlan {
+            client.set_port_tag(port, vlan).await?;
+            changes.push(match vlan {
+                Some(tag) => format!("Set VLAN {} on {}", tag, port),
+                None => format!("Removed VLAN tag from {}", port),
+            });
+        }
+        Ok(changes)
+    }
+}
 
-        let symlink_path = format!("{}/lxc-firstboot.service", symlink_dir);
-        tokio::fs::symlink("../lxc-firstboot.service", &symlink_path).await.ok(); // Ignore if exists
+impl Default for LxcPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        log::info!("✓ Firstboot script injected into container {}", container.id);
+#[async_trait]
+impl PlugTree for LxcPlugin {
+    fn pluglet_type(&self) -> &str {
+        "container"
+    }
 
-        Ok(())
+    fn pluglet_id_field(&self) -> &str {
+        "id"
     }
 
-    /// Inject Netmaker enrollment token into container
-    async fn inject_netmaker_token(container: &ContainerInfo, storage: &str) -> Result<()> {
-        // Read token from host
-        if let Ok(token_content) = tokio::fs::read_to_string("/etc/op-dbus/netmaker.env").await {
-            for line in token_content.lines() {
-                if let Some(token_value) = line.strip_prefix("NETMAKER_TOKEN=") {
-                    let token_clean = token_value.trim_matches('"').trim();
+    fn extract_pluglet_id(&self, resource: &Value) -> Result<String> {
+        resource
+            .get("id")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .ok_or_else(|| anyhow::anyhow!("Container missing 'id' field"))
+    }
 
-                    let rootfs = format!("/var/lib/pve/{}/images/{}/rootfs", storage, container.id);
-                    let token_path = format!("{}/etc/netmaker/enrollment-token", rootfs);
+    async fn apply_pluglet(&self, _pluglet_id: &str, desired: &Value) -> Result<ApplyResult> {
+        let container: ContainerInfo = serde_json::from_value(desired.clone())?;
+        self.apply_container_state(&container).await
+    }
 
-                    // Create netmaker directory
-                    tokio::fs::create_dir_all(format!("{}/etc/netmaker", rootfs)).await?;
+    async fn query_pluglet(&self, pluglet_id: &str) -> Result<Option<Value>> {
+        let mut containers: Vec<ContainerInfo> = self
+            .inspect()
+            .await?
+            .into_iter()
+            .filter(|c| c.id == pluglet_id)
+            .collect();
+        self.read_provisioned(&mut containers).await;
+
+        match containers.pop() {
+            Some(container) => Ok(Some(serde_json::to_value(container)?)),
+            None => Ok(None),
+        }
+    }
 
-                    // Write token
-                    tokio::fs::write(&token_path, token_clean).await?;
+    async fn list_pluglet_ids(&self) -> Result<Vec<String>> {
+        let containers = self.backend.discover().await?;
+        Ok(containers.into_iter().map(|c| c.id).collect())
+    }
+}
 
-                    // Set permissions
-                    tokio::process::Command::new("chmod")
-                        .args(["600", &token_path])
-                        .output()
-                        .await?;
+impl LxcPlugin {
+    /// Determine bridge based on network type
+    fn get_bridge_for_network_type(container: &ContainerInfo) -> String {
+        let network_type = container
+            .properties
+            .as_ref()
+            .and_then(|p| p.get("network_type"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("bridge");
 
-                    log::info!("✓ Netmaker token injected into container {}", container.id);
-                    break;
-                }
-            }
+        match network_type {
+            "netmaker" => "mesh".to_string(),     // Netmaker mesh bridge
+            "bridge" => container.bridge.clone(), // Traditional bridge (ovsbr0)
+            _ => container.bridge.clone(),
         }
-
-        Ok(())
     }
 
     /// Cleanup OVS port for deleted container
@@ -825,36 +1005,6 @@ WantedBy=multi-user.target
 
         Err(anyhow::anyhow!("No OVS port found for container {}", ct_id))
     }
-
-    /// Start LXC container
-    async fn start_container(ct_id: &str) -> Result<()> {
-        let output = tokio::process::Command::new("pct")
-            .args(["start", ct_id])
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("pct start failed: {}", stderr));
-        }
-
-        Ok(())
-    }
-
-    /// Stop LXC container
-    async fn stop_container(ct_id: &str) -> Result<()> {
-        let output = tokio::process::Command::new("pct")
-            .args(["stop", ct_id])
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("pct stop failed: {}", stderr));
-        }
-
-        Ok(())
-    }
 }
 
 #[async_trait]
@@ -867,33 +1017,50 @@ impl StatePlugin for LxcPlugin {
     }
 
     fn is_available(&self) -> bool {
-        // Check if pct command is available (Proxmox specific)
-        std::process::Command::new("pct")
-            .arg("--version")
-            .output()
-            .map(|output| output.status.success())
-            .unwrap_or(false)
+        self.backend.is_available()
     }
 
     fn unavailable_reason(&self) -> String {
-        "Proxmox pct command not found - this plugin requires Proxmox VE".to_string()
+        format!(
+            "No container manager found for the {} backend - install Proxmox VE, LXC or \
+             systemd-container, or pick one with {}",
+            self.backend.name(),
+            BACKEND_ENV
+        )
     }
 
     async fn query_current_state(&self) -> Result<Value> {
-        let containers = self.discover_from_ovs().await?;
+        let mut containers = self.inspect().await?;
+        self.read_provisioned(&mut containers).await;
         Ok(serde_json::to_value(LxcState { containers })?)
     }
 
     async fn calculate_diff(&self, current: &Value, desired: &Value) -> Result<StateDiff> {
-        // For now, emit a single modify if different; once lifecycle is defined, compute granular actions.
-        let actions = if current != desired {
-            vec![StateAction::Modify {
-                resource: "lxc".into(),
-                changes: desired.clone(),
-            }]
-        } else {
-            vec![]
-        };
+        let current_state: LxcState = serde_json::from_value(current.clone())?;
+        let desired_state: LxcState = serde_json::from_value(desired.clone())?;
+
+        // Containers missing from the state file are left alone
+        let mut actions = Vec::new();
+        for container in &desired_state.containers {
+            match current_state
+                .containers
+                .iter()
+                .find(|c| c.id == container.id)
+            {
+                Some(existing) => {
+                    if let Some(changes) = ContainerChanges::between(existing, container) {
+                        actions.push(StateAction::Modify {
+                            resource: container.id.clone(),
+                            changes: serde_json::to_value(changes)?,
+                        });
+                    }
+                }
+                None => actions.push(StateAction::Create {
+                    resource: container.id.clone(),
+                    config: serde_json::to_value(container)?,
+                }),
+            }
+        }
         Ok(StateDiff {
             plugin: self.name().to_string(),
             actions,
@@ -916,158 +1083,55 @@ impl StatePlugin for LxcPlugin {
                     config,
                 } => {
                     let container: ContainerInfo = serde_json::from_value(config.clone())?;
+                    let (bridge, vlan) = container.port_settings(&ContainerInfo::default());
 
                     // 1. Create LXC container
-                    match Self::create_container(&container).await {
-                        Ok(_) => {
-                            changes_applied.push(format!("Created container {}", container.id));
-
-                            // 2. Start container to create veth interface
-                            if let Err(e) = Self::start_container(&container.id).await {
-                                errors.push(format!(
-                                    "Failed to start container {}: {}",
-                                    container.id, e
-                                ));
-                                continue;
-                            }
+                    if let Err(e) = self.create_container(&container, &bridge).await {
+                        errors.push(format!(
+                            "Failed to create container {}: {}",
+                            container.id, e
+                        ));
+                        continue;
+                    }
+                    changes_applied.push(format!("Created container {}", container.id));
 
-                            // Wait for veth to appear
-                            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-
-                            // 3. Find and rename veth
-                            let veth_name = format!("vi{}", container.id);
-                            match Self::find_container_veth(&container.id).await {
-                                Ok(old_veth) => {
-                                    log::info!(
-                                        "Found veth {} for container {}",
-                                        old_veth,
-                                        container.id
-                                    );
-
-                                    match crate::native::rtnetlink_helpers::link_set_name(
-                                        &old_veth, &veth_name,
-                                    )
-                                    .await
-                                    {
-                                        Ok(_) => {
-                                            changes_applied.push(format!(
-                                                "Renamed {} to {}",
-                                                old_veth, veth_name
-                                            ));
-
-                                            // 4. Network enrollment based on type
-                                            let network_type = container
-                                                .properties
-                                                .as_ref()
-                                                .and_then(|p| p.get("network_type"))
-                                                .and_then(|v| v.as_str())
-                                                .unwrap_or("bridge");
-
-                                            match network_type {
-                                                "netmaker" => {
-                                                    // Add to mesh (netmaker mesh bridge)
-                                                    let client = crate::native::OvsdbClient::new();
-                                                    match client.add_port("mesh", &veth_name).await
-                                                    {
-                                                        Ok(_) => {
-                                                            log::info!(
-                                                                "Container {} on mesh bridge",
-                                                                container.id
-                                                            );
-                                                            changes_applied.push(format!(
-                                                                "Added {} to mesh (netmaker bridge)",
-                                                                veth_name
-                                                            ));
-                                                        }
-                                                        Err(e) => {
-                                                            errors.push(format!(
-                                                                "Failed to add {} to mesh: {}",
-                                                                veth_name, e
-                                                            ));
-                                                        }
-                                                    }
-                                                }
-                                                "bridge" => {
-                                                    // Add to traditional bridge (ovsbr0)
-                                                    let client = crate::native::OvsdbClient::new();
-                                                    match client
-                                                        .add_port(&container.bridge, &veth_name)
-                                                        .await
-                                                    {
-                                                        Ok(_) => {
-                                                            changes_applied.push(format!(
-                                                                "Added {} to bridge {}",
-                                                                veth_name, container.bridge
-                                                            ));
-                                                        }
-                                                        Err(e) => {
-                                                            errors.push(format!(
-                                                                "Failed to add port to bridge: {}",
-                                                                e
-                                                            ));
-                                                        }
-                                                    }
-                                                }
-                                                _ => {
-                                                    // Default to traditional bridge
-                                                    let client = crate::native::OvsdbClient::new();
-                                                    match client
-                                                        .add_port(&container.bridge, &veth_name)
-                                                        .await
-                                                    {
-                                                        Ok(_) => {
-                                                            changes_applied.push(format!(
-                                                                "Added {} to bridge {}",
-                                                                veth_name, container.bridge
-                                                            ));
-                                                        }
-                                                        Err(e) => {
-                                                            errors.push(format!(
-                                                                "Failed to add port to bridge: {}",
-                                                                e
-                                                            ));
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        Err(e) => {
-                                            errors.push(format!("Failed to rename veth: {}", e));
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    // If we can't find the veth, stop the container to prevent orphan
-                                    log::warn!(
-                                        "Failed to find veth for container {}, stopping container",
-                                        container.id
-                                    );
-                                    let _ = Self::stop_container(&container.id).await;
-                                    errors.push(format!(
-                                        "Failed to find veth for container {}: {}",
-                                        container.id, e
-                                    ));
-                                }
-                            }
-                        }
+                    // 2. Start container to create veth interface
+                    if let Err(e) = self.backend.start(&container.id).await {
+                        errors.push(format!(
+                            "Failed to start container {}: {}",
+                            container.id, e
+                        ));
+                        continue;
+                    }
+
+                    // Wait for veth to appear
+                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+
+                    // 3. Put the host end on the bridge as vi{ID}
+                    match self.attach_port(&container.id, &bridge, vlan).await {
+                        Ok(mut attached) => changes_applied.append(&mut attached),
                         Err(e) => {
+                            // Stop the container rather than leave it unreachable
+                            log::warn!(
+                                "Failed to attach container {}, stopping container",
+                                container.id
+                            );
+                            let _ = self.backend.stop(&container.id).await;
                             errors.push(format!(
-                                "Failed to create container {}: {}",
-                                container.id, e
+                                "Failed to attach container {} to {}: {}",
+                                container.id, bridge, e
                             ));
                         }
                     }
                 }
-                StateAction::Modify {
-                    resource,
-                    changes: _,
-                } => {
-                    // Handle container state changes (start/stop)
-                    log::info!(
-                        "Modify operation for container {} (not yet implemented)",
-                        resource
-                    );
-                    changes_applied.push(format!("Skipped modify for {}", resource));
+                StateAction::Modify { resource, changes } => {
+                    let changes: ContainerChanges = serde_json::from_value(changes.clone())?;
+                    match self.modify_container(resource, &changes).await {
+                        Ok(mut applied) => changes_applied.append(&mut applied),
+                        Err(e) => {
+                            errors.push(format!("Failed to update container {}: {:#}", resource, e))
+                        }
+                    }
                 }
                 StateAction::Delete { resource } => {
                     // Delete container and cleanup OVS ports
@@ -1097,17 +1161,12 @@ impl StatePlugin for LxcPlugin {
                     }
 
                     // Then delete the container
-                    let output = tokio::process::Command::new("pct")
-                        .args(["destroy", resource])
-                        .output()
-                        .await;
-
-                    match output {
-                        Ok(out) if out.status.success() => {
+                    match self.backend.destroy(resource).await {
+                        Ok(()) => {
                             changes_applied.push(format!("Deleted container {}", resource));
                         }
-                        _ => {
-                            errors.push(format!("Failed to delete container {}", resource));
+                        Err(e) => {
+                            errors.push(format!("Failed to delete container {}: {}", resource, e));
                         }
                     }
                 }
@@ -1127,26 +1186,356 @@ impl StatePlugin for LxcPlugin {
         Ok(true)
     }
 
+    /// Snapshots every container (root filesystem and config) unless the
+    /// "lxc" snapshot target is disabled
     async fn create_checkpoint(&self) -> Result<Checkpoint> {
-        Ok(Checkpoint {
-            id: format!("lxc-{}", chrono::Utc::now().timestamp()),
-            plugin: self.name().into(),
-            timestamp: chrono::Utc::now().timestamp(),
-            state_snapshot: json!({}),
-            backend_checkpoint: None,
-        })
+        self.checkpoint_containers(None, Vec::new()).await
     }
 
-    async fn rollback(&self, _checkpoint: &Checkpoint) -> Result<()> {
+    /// Snapshots only the containers the diff modifies or deletes, and notes
+    /// the ones it creates
+    async fn create_checkpoint_for(&self, diff: &StateDiff) -> Result<Checkpoint> {
+        let mut changed = Vec::new();
+        let mut created = Vec::new();
+        for action in &diff.actions {
+            match action {
+                StateAction::Create { resource, .. } => created.push(resource.clone()),
+                StateAction::Modify { resource, .. } | StateAction::Delete { resource } => {
+                    changed.push(resource.clone())
+                }
+                StateAction::NoOp { .. } => {}
+            }
+        }
+        self.checkpoint_containers(Some(&changed), created).await
+    }
+
+    /// Restores the snapshotted containers and destroys the ones the
+    /// checkpointed diff created; other containers are left alone, and
+    /// destroyed ones can't be brought back.
+    async fn rollback(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let saved_checkpoint = |key: &str| {
+            checkpoint
+                .backend_checkpoint
+                .as_ref()
+                .and_then(|saved| saved.get(key))
+                .cloned()
+        };
+        let snapshots: Vec<ContainerSnapshot> = saved_checkpoint("snapshots")
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default();
+        let created: Vec<String> = saved_checkpoint("created")
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default();
+        if snapshots.is_empty() && created.is_empty() {
+            anyhow::bail!("Checkpoint {} has no container snapshots", checkpoint.id);
+        }
+        let saved: LxcState = serde_json::from_value(checkpoint.state_snapshot.clone())?;
+        let config = checkpoint::snapshot_config();
+        let current = self.backend.discover().await?;
+
+        let mut errors = Vec::new();
+        for snapshot in &snapshots {
+            let Some(container) = current.iter().find(|c| c.id == snapshot.id) else {
+                log::warn!(
+                    "Container {} no longer exists, not restoring it",
+                    snapshot.id
+                );
+                continue;
+            };
+            let saved = saved
+                .containers
+                .iter()
+                .find(|c| c.id == snapshot.id)
+                .unwrap_or(container);
+            if let Err(e) = self
+                .restore_container(snapshot, container.running == Some(true), saved, &config)
+                .await
+            {
+                errors.push(format!("container {}: {:#}", snapshot.id, e));
+            }
+        }
+
+        for id in &created {
+            let Some(container) = current.iter().find(|c| &c.id == id) else {
+                continue;
+            };
+            if let Err(e) = self.remove_container(container).await {
+                errors.push(format!("container {}: {:#}", id, e));
+            }
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("Failed to restore {}", errors.join("; "));
+        }
         Ok(())
     }
 
+    fn owned_paths(&self) -> Vec<PathBuf> {
+        self.backend.config_paths()
+    }
+
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities {
-            supports_rollback: false,
-            supports_checkpoints: false,
+            supports_rollback: true,
+            supports_checkpoints: true,
             supports_verification: false,
             atomic_operations: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Stopped containers that record the snapshot/restore/destroy calls
+    struct RecordingBackend {
+        ids: Vec<&'static str>,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl RecordingBackend {
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    #[async_trait]
+    impl ContainerBackend for RecordingBackend {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+        fn is_available(&self) -> bool {
+            true
+        }
+        async fn discover(&self) -> Result<Vec<ContainerInfo>> {
+            Ok(self
+                .ids
+                .iter()
+                .map(|id| ContainerInfo {
+                    id: id.to_string(),
+                    running: Some(false),
+                    ..Default::default()
+                })
+                .collect())
+        }
+        async fn addresses(&self, _id: &str) -> Result<Vec<IpAddr>> {
+            Ok(Vec::new())
+        }
+        async fn hwaddr(&self, _id: &str, _bridge: &str) -> Result<Option<String>> {
+            Ok(None)
+        }
+        async fn config(&self, _id: &str) -> Result<ContainerConfig> {
+            Ok(ContainerConfig::default())
+        }
+        async fn reconfigure(&self, _id: &str, _: &ContainerConfig, _: bool) -> Result<()> {
+            Ok(())
+        }
+        async fn create(&self, _container: &ContainerInfo, _bridge: &str) -> Result<()> {
+            Ok(())
+        }
+        async fn start(&self, _id: &str) -> Result<()> {
+            Ok(())
+        }
+        async fn stop(&self, _id: &str) -> Result<()> {
+            Ok(())
+        }
+        async fn destroy(&self, id: &str) -> Result<()> {
+            self.record(format!("destroy {}", id));
+            Ok(())
+        }
+        async fn host_veth(&self, id: &str) -> Result<String> {
+            Ok(format!("vi{}", id))
+        }
+        async fn mount_rootfs(&self, _id: &str) -> Result<PathBuf> {
+            anyhow::bail!("not mountable")
+        }
+        async fn unmount_rootfs(&self, _id: &str) -> Result<()> {
+            Ok(())
+        }
+        async fn init_pid(&self, _id: &str) -> Result<Option<u32>> {
+            Ok(None)
+        }
+        async fn snapshot(&self, id: &str, _name: &str, _: &SnapshotConfig) -> Result<()> {
+            self.record(format!("snapshot {}", id));
+            Ok(())
+        }
+        async fn restore(&self, id: &str, _name: &str, _: &SnapshotConfig) -> Result<()> {
+            self.record(format!("restore {}", id));
+            Ok(())
+        }
+    }
+
+    fn nic(name: &str, bridge: &str, vlan: Option<u16>) -> Nic {
+        Nic {
+            name: name.to_string(),
+            bridge: bridge.to_string(),
+            vlan,
+            firewall: None,
+        }
+    }
+
+    #[test]
+    fn test_changes_compare_declared_settings() {
+        let current = ContainerInfo {
+            id: "100".to_string(),
+            veth: "vi100".to_string(),
+            bridge: "vmbr0".to_string(),
+            running: Some(true),
+            config: ContainerConfig {
+                resources: Some(Resources {
+                    cores: Some(2),
+                    memory: Some(512),
+                    swap: Some(512),
+                }),
+                mounts: Some(Vec::new()),
+                features: Some([("nesting".to_string(), true)].into()),
+                nics: Some(vec![
+                    Nic {
+                        firewall: Some(true),
+                        ..nic("eth0", "vmbr0", None)
+                    },
+                    nic("eth1", "mesh", None),
+                ]),
+            },
+            provision: None,
+            provisioned: None,
+            properties: None,
+        };
+
+        // Undeclared sections and features already set are in sync
+        let mut desired = ContainerInfo {
+            config: ContainerConfig {
+                resources: Some(Resources {
+                    memory: Some(512),
+                    ..Default::default()
+                }),
+                features: Some([("nesting".to_string(), true)].into()),
+                ..Default::default()
+            },
+            // Provisioning only applies to new containers
+            provision: Some(Provision {
+                hostname: Some("web1".to_string()),
+                ..Default::default()
+            }),
+            ..current.clone()
+        };
+        assert_eq!(ContainerChanges::between(&current, &desired), None);
+
+        // Limits and the first NIC's bridge and VLAN apply live
+        desired.config.resources = Some(Resources {
+            memory: Some(1024),
+            ..Default::default()
+        });
+        desired.config.nics = Some(vec![
+            nic("eth0", "ovsbr0", Some(10)),
+            nic("eth1", "mesh", None),
+        ]);
+        let changes = ContainerChanges::between(&current, &desired).unwrap();
+        assert_eq!(
+            (changes.bridge.as_str(), changes.vlan),
+            ("ovsbr0", Some(10))
+        );
+        assert_eq!(
+            changes.config.resources.as_ref().unwrap().memory,
+            Some(1024)
+        );
+        assert_eq!(changes.config.resources.as_ref().unwrap().cores, None);
+        assert!(changes.config.restart_reasons(&current.config).is_empty());
+
+        // Mounts, features and other NIC changes need a restart
+        desired.config.mounts = Some(vec![MountPoint {
+            source: "/srv/data".to_string(),
+            target: "/data".to_string(),
+            read_only: true,
+        }]);
+        desired.config.features = Some([("keyctl".to_string(), true)].into());
+        desired.config.nics = Some(vec![nic("eth0", "ovsbr0", Some(10))]);
+        let changes = ContainerChanges::between(&current, &desired).unwrap();
+        assert_eq!(
+            changes.config.features,
+            Some([("keyctl".to_string(), true)].into())
+        );
+        assert_eq!(
+            changes.config.restart_reasons(&current.config),
+            vec!["mounts", "features", "nics"]
+        );
+    }
+
+    #[test]
+    fn test_top_level_bridge_moves_the_first_nic() {
+        let current = ContainerInfo {
+            id: "100".to_string(),
+            bridge: "vmbr0".to_string(),
+            running: Some(false),
+            config: ContainerConfig {
+                nics: Some(vec![nic("eth0", "vmbr0", Some(20))]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let desired = ContainerInfo {
+            id: "100".to_string(),
+            bridge: "ovsbr0".to_string(),
+            running: Some(true),
+            ..Default::default()
+        };
+
+        let changes = ContainerChanges::between(&current, &desired).unwrap();
+        assert_eq!(changes.running, Some(true));
+        assert_eq!(
+            (changes.bridge.as_str(), changes.vlan),
+            ("ovsbr0", Some(20))
+        );
+        assert_eq!(
+            changes.config.nics,
+            Some(vec![nic("eth0", "ovsbr0", Some(20))])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_covers_only_the_diff() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let plugin = LxcPlugin::with_backend(Box::new(RecordingBackend {
+            ids: vec!["100", "101", "102", "103"],
+            calls: calls.clone(),
+        }));
+        let diff = StateDiff {
+            plugin: "lxc".to_string(),
+            actions: vec![
+                StateAction::Modify {
+                    resource: "101".to_string(),
+                    changes: json!({}),
+                },
+                StateAction::Delete {
+                    resource: "102".to_string(),
+                },
+                // Created by the apply, as discover sees it at rollback time
+                StateAction::Create {
+                    resource: "103".to_string(),
+                    config: json!({}),
+                },
+            ],
+            metadata: DiffMetadata {
+                timestamp: 0,
+                current_hash: String::new(),
+                desired_hash: String::new(),
+            },
+        };
+
+        let checkpoint = plugin.create_checkpoint_for(&diff).await.unwrap();
+        assert_eq!(*calls.lock().unwrap(), vec!["snapshot 101", "snapshot 102"]);
+        let saved: LxcState = serde_json::from_value(checkpoint.state_snapshot.clone()).unwrap();
+        assert_eq!(saved.containers.len(), 2);
+
+        calls.lock().unwrap().clear();
+        plugin.rollback(&checkpoint).await.unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["restore 101", "restore 102", "destroy 103"]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Container snapshots for plugin checkpoints
+//!
+//! Snapshots op-dbus takes are named `opdbus-<timestamp>` so retention never
+//! touches the ones taken by hand. Proxmox snapshots through `pct`; the other
+//! backends keep a read-only btrfs snapshot of the root filesystem, plus a copy
+//! of the config file, under the "lxc" snapshot target's directory.
+
+use crate::snapshot::{
+    retention_victims, SnapshotConfig, SnapshotInfo, SnapshotManager, SnapshotTargets,
+};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Prefix of the snapshots op-dbus manages
+pub const SNAPSHOT_PREFIX: &str = "opdbus-";
+
+/// Name for a new checkpoint snapshot (valid for `pct` too: a letter first, at
+/// most 40 characters). Microseconds keep back-to-back checkpoints apart.
+pub fn snapshot_name() -> String {
+    format!(
+        "{}{}",
+        SNAPSHOT_PREFIX,
+        chrono::Utc::now().format("%Y%m%d-%H%M%S-%6f")
+    )
+}
+
+/// The "lxc" snapshot target, or the default one when it isn't configured
+pub fn snapshot_config() -> SnapshotConfig {
+    SnapshotTargets::load()
+        .and_then(|targets| targets.get("lxc").cloned())
+        .unwrap_or_else(|_| SnapshotConfig::lxc())
+}
+
+/// Snapshots (name, creation time) the retention policy deletes; only op-dbus
+/// snapshots are considered
+pub fn to_prune(snapshots: &[(String, i64)], config: &SnapshotConfig) -> Vec<String> {
+    let managed: Vec<SnapshotInfo> = snapshots
+        .iter()
+        .filter(|(name, _)| name.starts_with(SNAPSHOT_PREFIX))
+        .map(|(name, created)| SnapshotInfo {
+            name: name.clone(),
+            path: PathBuf::from(name),
+            created: *created,
+            tagged: false,
+            tag: None,
+            size_bytes: 0,
+        })
+        .collect();
+    retention_victims(&managed, &config.policy, chrono::Utc::now().timestamp())
+        .into_iter()
+        .map(|snapshot| snapshot.name)
+        .collect()
+}
+
+fn manager(config: &SnapshotConfig, id: &str) -> SnapshotManager {
+    SnapshotManager::with_policy(config.snapshots_dir.join(id), config.policy.clone())
+}
+
+/// Copy of the config file kept next to a snapshot
+fn saved_config(config: &SnapshotConfig, id: &str, name: &str) -> PathBuf {
+    config
+        .snapshots_dir
+        .join(id)
+        .join(format!(".{}.config", name))
+}
+
+/// Snapshot a root filesystem (which must be a btrfs subvolume) and its config
+/// file; retention applies to the container's snapshots right away
+pub async fn btrfs_snapshot(
+    config: &SnapshotConfig,
+    id: &str,
+    rootfs: &Path,
+    config_file: &Path,
+    name: &str,
+) -> Result<()> {
+    let manager = manager(config, id);
+    let rootfs = rootfs.to_path_buf();
+    let snapshot_name = name.to_string();
+    tokio::task::spawn_blocking(move || manager.create_snapshot(&rootfs, Some(&snapshot_name)))
+        .await??;
+
+    let saved = saved_config(config, id, name);
+    tokio::fs::copy(config_file, &saved)
+        .await
+        .with_context(|| format!("Failed to save {}", config_file.display()))?;
+
+    // Drop the config copies of snapshots retention deleted
+    let dir = config.snapshots_dir.join(id);
+    let mut entries = tokio::fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(snapshot) = file_name
+            .strip_prefix('.')
+            .and_then(|f| f.strip_suffix(".config"))
+        {
+            if !dir.join(snapshot).exists() {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Put a root filesystem and config file back as they were in a snapshot. The
+/// container must be stopped.
+pub async fn btrfs_restore(
+    config: &SnapshotConfig,
+    id: &str,
+    rootfs: &Path,
+    config_file: &Path,
+    name: &str,
+) -> Result<()> {
+    let manager = manager(config, id);
+    let target = rootfs.to_path_buf();
+    let snapshot_name = name.to_string();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        // The replaced root filesystem is of no further use
+        if let Some(replaced) = manager.restore_snapshot(&snapshot_name, &target)? {
+            manager.delete_snapshot(&replaced)?;
+        }
+        Ok(())
+    })
+    .await??;
+
+    let saved = saved_config(config, id, name);
+    tokio::fs::copy(&saved, config_file)
+        .await
+        .with_context(|| format!("Failed to restore {}", config_file.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::RetentionPolicy;
+
+    #[test]
+    fn test_retention_skips_manual_snapshots() {
+        let config = SnapshotConfig {
+            policy: RetentionPolicy::Rolling { keep: 2 },
+            ..SnapshotConfig::lxc()
+        };
+        let snapshots = vec![
+            ("before-upgrade".to_string(), 100),
+            ("opdbus-20250101-000000".to_string(), 200),
+            ("opdbus-20250102-000000".to_string(), 300),
+            ("opdbus-20250103-000000".to_string(), 400),
+        ];
+        assert_eq!(
+            to_prune(&snapshots, &config),
+            vec!["opdbus-20250101-000000"]
+        );
+        assert!(snapshot_name().starts_with(SNAPSHOT_PREFIX));
+        assert!(snapshot_name().len() <= 40);
+    }
+}
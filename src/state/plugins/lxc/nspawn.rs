@@ -0,0 +1,463 @@
+//! systemd-nspawn backend over org.freedesktop.machine1
+//!
+//! Containers are machine images started as systemd-nspawn@{ID}.service. A
+//! .nspawn file gives each one a private veth (VirtualEthernet=yes) whose host
+//! end ve-{ID} is renamed to vi{ID} and added to the OVS bridge like the other
+//! backends' ports; nspawn's own Bridge= only handles Linux bridges, so the
+//! port itself is the NIC's config. Memory and CPU limits are properties of the
+//! unit, mounts are Bind= lines of the .nspawn file.
+
+use super::checkpoint::{btrfs_restore, btrfs_snapshot};
+use super::{
+    check_port_name, on_path, ovs_port_bridges, prop, ContainerBackend, ContainerConfig,
+    ContainerInfo, MountPoint, Nic, Resources,
+};
+use crate::snapshot::SnapshotConfig;
+use crate::state::plugins::systemd::SystemdStatePlugin;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use zbus::zvariant::OwnedObjectPath;
+use zbus::{Connection, Proxy};
+
+const NSPAWN_DIR: &str = "/etc/systemd/nspawn";
+const MACHINES_DIR: &str = "/var/lib/machines";
+
+const SERVICE_INTERFACE: &str = "org.freedesktop.systemd1.Service";
+
+/// Name of the container end of the VirtualEthernet= veth
+const NIC_NAME: &str = "host0";
+
+pub struct NspawnBackend;
+
+fn unit_name(id: &str) -> String {
+    format!("systemd-nspawn@{}.service", id)
+}
+
+async fn machine1() -> Result<Proxy<'static>> {
+    let conn = Connection::system()
+        .await
+        .context("Failed to connect to system D-Bus")?;
+    Proxy::new(
+        &conn,
+        "org.freedesktop.machine1",
+        "/org/freedesktop/machine1",
+        "org.freedesktop.machine1.Manager",
+    )
+    .await
+    .context("Failed to create machine1 D-Bus proxy")
+}
+
+/// The .nspawn settings op-dbus writes for a container
+fn render_nspawn(container: &ContainerInfo) -> String {
+    format!(
+        "# Managed by op-dbus\n[Exec]\nHostname={}\n\n[Network]\nVirtualEthernet=yes\n",
+        container.hostname()
+    )
+}
+
+fn nspawn_path(id: &str) -> PathBuf {
+    Path::new(NSPAWN_DIR).join(format!("{}.nspawn", id))
+}
+
+/// Bind mounts of a .nspawn file
+fn parse_binds(config: &str) -> Vec<MountPoint> {
+    config
+        .lines()
+        .filter_map(|line| {
+            let (value, read_only) = match line.trim().split_once('=') {
+                Some(("Bind", value)) => (value, false),
+                Some(("BindReadOnly", value)) => (value, true),
+                _ => return None,
+            };
+            // "SOURCE[:DESTINATION[:OPTIONS]]"
+            let mut fields = value.split(':');
+            let source = fields.next()?.to_string();
+            let target = fields
+                .next()
+                .map(str::to_string)
+                .unwrap_or_else(|| source.clone());
+            Some(MountPoint {
+                source,
+                target,
+                read_only,
+            })
+        })
+        .collect()
+}
+
+/// The .nspawn file with its bind mounts replaced by `mounts`
+fn render_binds(existing: &str, mounts: &[MountPoint]) -> String {
+    let mut lines: Vec<String> = existing
+        .lines()
+        .filter(|line| parse_binds(line).is_empty())
+        .map(str::to_string)
+        .collect();
+    let binds = mounts.iter().map(|mount| {
+        let key = if mount.read_only {
+            "BindReadOnly"
+        } else {
+            "Bind"
+        };
+        format!("{}={}:{}", key, mount.source, mount.target)
+    });
+    match lines.iter().position(|line| line.trim() == "[Files]") {
+        Some(section) => {
+            lines.splice(section + 1..section + 1, binds);
+        }
+        None if !mounts.is_empty() => {
+            lines.push(String::new());
+            lines.push("[Files]".to_string());
+            lines.extend(binds);
+        }
+        None => {}
+    }
+    lines.into_iter().map(|line| line + "\n").collect()
+}
+
+/// A unit limit in `unit`s, None when unlimited
+async fn unit_limit(
+    systemd: &SystemdStatePlugin,
+    id: &str,
+    property: &str,
+    unit: u64,
+) -> Result<Option<u64>> {
+    let value = systemd
+        .unit_property_u64(&unit_name(id), SERVICE_INTERFACE, property)
+        .await?;
+    Ok((value != u64::MAX).then(|| value / unit))
+}
+
+#[async_trait]
+impl ContainerBackend for NspawnBackend {
+    fn name(&self) -> &'static str {
+        "nspawn"
+    }
+
+    fn is_available(&self) -> bool {
+        on_path("systemd-nspawn")
+    }
+
+    fn config_paths(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(NSPAWN_DIR)]
+    }
+
+    async fn discover(&self) -> Result<Vec<ContainerInfo>> {
+        let proxy = match machine1().await {
+            Ok(proxy) => proxy,
+            Err(_) => return Ok(Vec::new()),
+        };
+        let images: Vec<(String, String, bool, u64, u64, u64, OwnedObjectPath)> = proxy
+            .call("ListImages", &())
+            .await
+            .context("Failed to list machine images")?;
+        let machines: Vec<(String, String, String, OwnedObjectPath)> = proxy
+            .call("ListMachines", &())
+            .await
+            .context("Failed to list machines")?;
+        let ports = ovs_port_bridges().await;
+
+        let mut results: Vec<ContainerInfo> = images
+            .into_iter()
+            // ".host" is the host's own root
+            .filter(|(name, ..)| !name.starts_with('.'))
+            .map(|(id, ..)| {
+                let veth = format!("vi{}", id);
+                let running = machines
+                    .iter()
+                    .any(|(name, class, ..)| *name == id && class == "container");
+                ContainerInfo {
+                    bridge: ports.get(&veth).cloned().unwrap_or_default(),
+                    id,
+                    veth,
+                    running: Some(running),
+                    config: ContainerConfig::default(),
+                    provision: None,
+                    provisioned: None,
+                    properties: None,
+                }
+            })
+            .collect();
+        results.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(results)
+    }
+
+    /// nspawn configs carry no addressing, so this is what a running
+    /// container reports; a stopped one has none
+    async fn addresses(&self, id: &str) -> Result<Vec<IpAddr>> {
+        let proxy = machine1().await?;
+        let addresses: Vec<(i32, Vec<u8>)> = match proxy.call("GetMachineAddresses", &(id,)).await {
+            Ok(addresses) => addresses,
+            Err(_) => return Ok(Vec::new()),
+        };
+        Ok(addresses
+            .into_iter()
+            .filter_map(|(_, bytes)| match bytes.len() {
+                4 => <[u8; 4]>::try_from(bytes.as_slice()).ok().map(IpAddr::from),
+                16 => <[u8; 16]>::try_from(bytes.as_slice())
+                    .ok()
+                    .map(IpAddr::from),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// nspawn derives container MACs itself
+    async fn hwaddr(&self, _id: &str, _bridge: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn config(&self, id: &str) -> Result<ContainerConfig> {
+        let systemd = SystemdStatePlugin::new();
+        let resources = Resources {
+            cores: unit_limit(&systemd, id, "CPUQuotaPerSecUSec", 1_000_000).await?,
+            memory: unit_limit(&systemd, id, "MemoryMax", 1024 * 1024).await?,
+            swap: unit_limit(&systemd, id, "MemorySwapMax", 1024 * 1024).await?,
+        };
+        let mounts = match tokio::fs::read_to_string(nspawn_path(id)).await {
+            Ok(config) => parse_binds(&config),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        // OVSDB keeps the port while the container is stopped
+        let port = format!("vi{}", id);
+        let mut nics = Vec::new();
+        if let Some(bridge) = ovs_port_bridges().await.remove(&port) {
+            nics.push(Nic {
+                name: NIC_NAME.to_string(),
+                bridge,
+                vlan: crate::native::OvsdbClient::new().port_tag(&port).await?,
+                firewall: None,
+            });
+        }
+
+        Ok(ContainerConfig {
+            resources: Some(resources),
+            mounts: Some(mounts),
+            features: None,
+            nics: Some(nics),
+        })
+    }
+
+    /// Limits are set on the unit, live and persistent; mounts wait for the
+    /// next start. The NIC has nothing to write - its bridge and VLAN are the
+    /// port's, which the plugin sets.
+    async fn reconfigure(&self, id: &str, changes: &ContainerConfig, _running: bool) -> Result<()> {
+        if changes.features.is_some() {
+            anyhow::bail!("Container {}: features are a Proxmox setting", id);
+        }
+        if let Some(nics) = &changes.nics {
+            if nics.len() > 1
+                || nics
+                    .iter()
+                    .any(|nic| nic.name != NIC_NAME || nic.firewall == Some(true))
+            {
+                anyhow::bail!(
+                    "Container {}: nspawn containers have a single NIC, {}, without firewall",
+                    id,
+                    NIC_NAME
+                );
+            }
+        }
+
+        if let Some(resources) = &changes.resources {
+            let mut properties = Vec::new();
+            if let Some(memory) = resources.memory {
+                properties.push(("MemoryMax", (memory * 1024 * 1024).into()));
+            }
+            if let Some(swap) = resources.swap {
+                properties.push(("MemorySwapMax", (swap * 1024 * 1024).into()));
+            }
+            if let Some(cores) = resources.cores {
+                properties.push(("CPUQuotaPerSecUSec", (cores * 1_000_000).into()));
+            }
+            SystemdStatePlugin::new()
+                .set_unit_properties(&unit_name(id), properties)
+                .await?;
+        }
+
+        if let Some(mounts) = &changes.mounts {
+            let path = nspawn_path(id);
+            let existing = match tokio::fs::read_to_string(&path).await {
+                Ok(config) => config,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e.into()),
+            };
+            tokio::fs::write(&path, render_binds(&existing, mounts))
+                .await
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    async fn create(&self, container: &ContainerInfo, bridge: &str) -> Result<()> {
+        check_port_name(&container.id)?;
+        let id = container.id.as_str();
+        let golden = prop(container, "golden_image")
+            .and_then(Value::as_str)
+            .with_context(|| {
+                format!(
+                    "Container {}: the nspawn backend clones a golden_image machine image",
+                    id
+                )
+            })?;
+
+        let proxy = machine1().await?;
+        let _: () = proxy
+            .call("CloneImage", &(golden, id, false))
+            .await
+            .with_context(|| format!("Failed to clone image {} to {}", golden, id))?;
+
+        tokio::fs::create_dir_all(NSPAWN_DIR).await?;
+        let nspawn = nspawn_path(id);
+        tokio::fs::write(&nspawn, render_nspawn(container))
+            .await
+            .with_context(|| format!("Failed to write {}", nspawn.display()))?;
+
+        let memory = prop(container, "memory")
+            .and_then(Value::as_u64)
+            .unwrap_or(512);
+        let cores = prop(container, "cores")
+            .and_then(Value::as_u64)
+            .unwrap_or(2);
+        let systemd = SystemdStatePlugin::new();
+        systemd
+            .set_unit_properties(
+                &unit_name(id),
+                vec![
+                    ("MemoryMax", (memory * 1024 * 1024).into()),
+                    ("CPUQuotaPerSecUSec", (cores * 1_000_000).into()),
+                ],
+            )
+            .await?;
+        if prop(container, "onboot").and_then(Value::as_bool) == Some(true) {
+            systemd.enable_unit(&unit_name(id)).await?;
+        }
+
+        log::info!(
+            "Container {} cloned from {} for bridge {}",
+            id,
+            golden,
+            bridge
+        );
+        Ok(())
+    }
+
+    async fn start(&self, id: &str) -> Result<()> {
+        SystemdStatePlugin::new().start_unit(&unit_name(id)).await
+    }
+
+    async fn stop(&self, id: &str) -> Result<()> {
+        SystemdStatePlugin::new().stop_unit(&unit_name(id)).await
+    }
+
+    async fn destroy(&self, id: &str) -> Result<()> {
+        let proxy = machine1().await?;
+        let _: () = proxy
+            .call("RemoveImage", &(id,))
+            .await
+            .with_context(|| format!("Failed to remove image {}", id))?;
+        let nspawn = nspawn_path(id);
+        match tokio::fs::remove_file(&nspawn).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn host_veth(&self, id: &str) -> Result<String> {
+        // check_port_name keeps ids short enough that nspawn doesn't hash the name
+        Ok(format!("ve-{}", id))
+    }
+
+    /// Directory images only; raw disk images would need a loop mount
+    async fn mount_rootfs(&self, id: &str) -> Result<PathBuf> {
+        let rootfs = Path::new(MACHINES_DIR).join(id);
+        if !rootfs.is_dir() {
+            anyhow::bail!(
+                "Container {} is not a directory image under {}, can't provision it",
+                id,
+                MACHINES_DIR
+            );
+        }
+        Ok(rootfs)
+    }
+
+    async fn unmount_rootfs(&self, _id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn init_pid(&self, id: &str) -> Result<Option<u32>> {
+        let proxy = machine1().await?;
+        let path: OwnedObjectPath = match proxy.call("GetMachine", &(id,)).await {
+            Ok(path) => path,
+            // Not running
+            Err(_) => return Ok(None),
+        };
+        let machine = Proxy::new(
+            proxy.connection(),
+            "org.freedesktop.machine1",
+            path,
+            "org.freedesktop.machine1.Machine",
+        )
+        .await?;
+        let leader: u32 = machine
+            .get_property("Leader")
+            .await
+            .with_context(|| format!("Failed to read the leader of machine {}", id))?;
+        Ok(Some(leader))
+    }
+
+    async fn snapshot(&self, id: &str, name: &str, config: &SnapshotConfig) -> Result<()> {
+        let rootfs = Path::new(MACHINES_DIR).join(id);
+        btrfs_snapshot(config, id, &rootfs, &nspawn_path(id), name).await
+    }
+
+    async fn restore(&self, id: &str, name: &str, config: &SnapshotConfig) -> Result<()> {
+        let rootfs = Path::new(MACHINES_DIR).join(id);
+        btrfs_restore(config, id, &rootfs, &nspawn_path(id), name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_binds() {
+        let existing = "# Managed by op-dbus\n[Exec]\nHostname=web\n\n\
+            [Files]\nBind=/srv/old:/old\n\n[Network]\nVirtualEthernet=yes\n";
+        assert_eq!(
+            parse_binds(existing),
+            vec![MountPoint {
+                source: "/srv/old".to_string(),
+                target: "/old".to_string(),
+                read_only: false,
+            }]
+        );
+
+        let mounts = vec![
+            MountPoint {
+                source: "/srv/data".to_string(),
+                target: "/data".to_string(),
+                read_only: true,
+            },
+            MountPoint {
+                source: "/srv/logs".to_string(),
+                target: "/var/log/app".to_string(),
+                read_only: false,
+            },
+        ];
+        let rendered = render_binds(existing, &mounts);
+        assert!(rendered
+            .contains("[Files]\nBindReadOnly=/srv/data:/data\nBind=/srv/logs:/var/log/app\n"));
+        assert!(rendered.ends_with("[Network]\nVirtualEthernet=yes\n"));
+        assert_eq!(parse_binds(&rendered), mounts);
+
+        // A file without a [Files] section gets one
+        let rendered = render_binds(&render_nspawn(&ContainerInfo::default()), &mounts[..1]);
+        assert!(rendered.ends_with("\n[Files]\nBindReadOnly=/srv/data:/data\n"));
+    }
+}
@@ -0,0 +1,707 @@
+//! Cloud-init-style provisioning of new containers
+//!
+//! A container's `provision` section is rendered into its root filesystem
+//! before the first start: a shell script plus a oneshot systemd unit (or an
+//! OpenRC service) that runs it at first boot. Paths are resolved the way the
+//! container sees them, so symlinks in the image never redirect a write to the
+//! host. The script sets the hostname, installs packages, creates
+//! users with their SSH keys, writes files and runs the commands, then records
+//! the outcome in /var/lib/op-dbus/provision.json and removes itself (it may
+//! hold secrets). The marker carries a hash of the spec, so the same spec is
+//! never rendered or run twice, even in a clone of a provisioned image.
+//! Host-held secrets such as the Netmaker token are written straight into the
+//! root filesystem instead, so they are neither in the script nor in the hash.
+
+use super::{prop, ContainerInfo};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::OsString;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// Outcome of the provisioning run, inside the container
+const MARKER_PATH: &str = "var/lib/op-dbus/provision.json";
+const SCRIPT_PATH: &str = "usr/local/lib/op-dbus/provision.sh";
+const UNIT_NAME: &str = "op-dbus-provision.service";
+const OPENRC_SERVICE: &str = "op-dbus-provision";
+
+/// Symlinks followed while resolving one path, as in the kernel
+const MAX_SYMLINKS: usize = 40;
+
+/// Where the `firstboot_script` property is installed
+const FIRSTBOOT_PATH: &str = "/usr/local/bin/lxc-firstboot.sh";
+/// Host file holding the Netmaker enrollment token (NETMAKER_TOKEN=...)
+const NETMAKER_ENV: &str = "/etc/op-dbus/netmaker.env";
+
+/// What a new container is set up with at first boot
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Provision {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Installed with the distribution's package manager
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<ProvisionUser>,
+    /// Written after the users are created, so they can own them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<ProvisionFile>,
+    /// Shell commands run last, in order; the first failure stops the run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<String>,
+    /// Root-only files (path -> content) taken from the host, written at
+    /// render time; rotating one does not change the hash
+    #[serde(skip)]
+    pub secrets: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ProvisionUser {
+    pub name: String,
+    /// Supplementary groups, which must exist
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ssh_authorized_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ProvisionFile {
+    /// Absolute path inside the container
+    pub path: String,
+    pub content: String,
+    /// Octal permissions, "0644" by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// "user[:group]", root by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// The provisioning marker, as reported by `query`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ProvisionResult {
+    /// Hash of the spec that was run
+    pub hash: String,
+    /// "ok" or "failed"
+    pub status: String,
+    /// Step that failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    /// Unix time the run finished
+    pub finished: i64,
+}
+
+impl Provision {
+    pub fn is_empty(&self) -> bool {
+        *self == Provision::default()
+    }
+
+    /// Identifies the spec in the marker
+    pub fn hash(&self) -> String {
+        let spec = serde_json::to_string(self).unwrap_or_default();
+        format!("{:x}", md5::compute(spec))
+    }
+
+    /// Reject specs the script can't carry out as written
+    pub fn validate(&self) -> Result<()> {
+        if let Some(hostname) = &self.hostname {
+            let valid = !hostname.is_empty()
+                && hostname.len() <= 253
+                && hostname.split('.').all(|label| {
+                    !label.is_empty()
+                        && label.len() <= 63
+                        && !label.starts_with('-')
+                        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                });
+            if !valid {
+                anyhow::bail!("Invalid hostname '{}'", hostname);
+            }
+        }
+        for user in &self.users {
+            let mut chars = user.name.chars();
+            let valid = user.name.len() <= 32
+                && chars
+                    .next()
+                    .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+                && chars
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+            if !valid {
+                anyhow::bail!("Invalid user name '{}'", user.name);
+            }
+        }
+        for file in &self.files {
+            // The path ends up in the marker's JSON when writing it fails
+            if !file.path.starts_with('/')
+                || file.path.ends_with('/')
+                || file
+                    .path
+                    .chars()
+                    .any(|c| c == '"' || c == '\\' || c.is_control())
+            {
+                anyhow::bail!("File path '{}' must be a plain absolute path", file.path);
+            }
+            if let Some(mode) = &file.mode {
+                if u32::from_str_radix(mode, 8).map_or(true, |mode| mode > 0o7777) {
+                    anyhow::bail!("Invalid mode '{}' for {}", mode, file.path);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Quote a value for the script
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn quote_all(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|value| quote(value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The first-boot script for a spec
+fn render_script(provision: &Provision) -> String {
+    let mut script = format!(
+        r#"#!/bin/sh
+# Rendered by op-dbus: provisions the container once, then removes itself
+HASH={hash}
+MARKER=/{marker}
+STEP=start
+if grep -qs "\"hash\":\"$HASH\"" "$MARKER"; then
+    exit 0
+fi
+umask 022
+
+finish() {{
+    mkdir -p "$(dirname "$MARKER")"
+    if [ "$1" = ok ]; then
+        printf '{{"hash":"%s","status":"ok","finished":%s}}\n' "$HASH" "$(date +%s)" > "$MARKER"
+    else
+        printf '{{"hash":"%s","status":"failed","step":"%s","finished":%s}}\n' \
+            "$HASH" "$STEP" "$(date +%s)" > "$MARKER"
+    fi
+    rm -f "$0"
+}}
+
+fail() {{
+    echo "op-dbus provisioning failed at: $STEP" >&2
+    finish failed
+    exit 1
+}}
+"#,
+        hash = quote(&provision.hash()),
+        marker = MARKER_PATH,
+    );
+
+    if let Some(hostname) = &provision.hostname {
+        let hostname = quote(hostname);
+        script.push_str(&format!(
+            "\nSTEP=hostname\necho {0} > /etc/hostname || fail\nhostname {0} 2>/dev/null\n",
+            hostname
+        ));
+    }
+
+    if !provision.packages.is_empty() {
+        let packages = quote_all(&provision.packages);
+        script.push_str(&format!(
+            r#"
+STEP=packages
+if command -v apt-get >/dev/null; then
+    export DEBIAN_FRONTEND=noninteractive
+    apt-get update && apt-get install -y {0} || fail
+elif command -v dnf >/dev/null; then
+    dnf install -y {0} || fail
+elif command -v yum >/dev/null; then
+    yum install -y {0} || fail
+elif command -v apk >/dev/null; then
+    apk add --no-cache {0} || fail
+elif command -v pacman >/dev/null; then
+    pacman -Sy --noconfirm {0} || fail
+elif command -v zypper >/dev/null; then
+    zypper --non-interactive install {0} || fail
+else
+    fail
+fi
+"#,
+            packages
+        ));
+    }
+
+    for user in &provision.users {
+        let name = quote(&user.name);
+        script.push_str(&format!(
+            "\nSTEP={}\n",
+            quote(&format!("user {}", user.name))
+        ));
+        let shell = user
+            .shell
+            .as_deref()
+            .map(|shell| format!(" -s {}", quote(shell)))
+            .unwrap_or_default();
+        script.push_str(&format!(
+            "id -u {0} >/dev/null 2>&1 || useradd -m{1} {0} || fail\n",
+            name, shell
+        ));
+        if !user.groups.is_empty() {
+            script.push_str(&format!(
+                "usermod -aG {} {} || fail\n",
+                quote(&user.groups.join(",")),
+                name
+            ));
+        }
+        if !user.ssh_authorized_keys.is_empty() {
+            script.push_str(&format!(
+                "home=$(getent passwd {} | cut -d: -f6)\n\
+                 [ -n \"$home\" ] && mkdir -p \"$home/.ssh\" || fail\n",
+                name
+            ));
+            for key in &user.ssh_authorized_keys {
+                script.push_str(&format!(
+                    "grep -qxsF -e {0} \"$home/.ssh/authorized_keys\" || \
+                     printf '%s\\n' {0} >> \"$home/.ssh/authorized_keys\" || fail\n",
+                    quote(key.trim())
+                ));
+            }
+            script.push_str(&format!(
+                "chown -R {}: \"$home/.ssh\" && chmod 700 \"$home/.ssh\" && \
+                 chmod 600 \"$home/.ssh/authorized_keys\" || fail\n",
+                name
+            ));
+        }
+    }
+
+    for file in &provision.files {
+        let path = quote(&file.path);
+        script.push_str(&format!(
+            "\nSTEP={}\n",
+            quote(&format!("file {}", file.path))
+        ));
+        script.push_str(&format!(
+            "mkdir -p \"$(dirname {0})\" || fail\n\
+             (umask 077 && printf '%s' {1} > {0}) || fail\n\
+             chmod {2} {0} && chown {3} {0} || fail\n",
+            path,
+            quote(&file.content),
+            quote(file.mode.as_deref().unwrap_or("0644")),
+            quote(file.owner.as_deref().unwrap_or("root:root")),
+        ));
+    }
+
+    for (i, command) in provision.commands.iter().enumerate() {
+        script.push_str(&format!(
+            "\nSTEP='command {}'\nsh -c {} || fail\n",
+            i + 1,
+            quote(command)
+        ));
+    }
+
+    script.push_str("\nfinish ok\n");
+    script
+}
+
+fn render_unit() -> String {
+    format!(
+        r#"[Unit]
+Description=op-dbus container provisioning
+After=network-online.target
+Wants=network-online.target
+ConditionPathExists=/{script}
+
+[Service]
+Type=oneshot
+ExecStart=/bin/sh /{script}
+RemainAfterExit=yes
+
+[Install]
+WantedBy=multi-user.target
+"#,
+        script = SCRIPT_PATH
+    )
+}
+
+fn render_openrc_service() -> String {
+    format!(
+        r#"#!/sbin/openrc-run
+description="op-dbus container provisioning"
+
+depend() {{
+    need net
+}}
+
+start() {{
+    [ -f /{script} ] || return 0
+    ebegin "Provisioning container"
+    /bin/sh /{script}
+    eend $?
+}}
+"#,
+        script = SCRIPT_PATH
+    )
+}
+
+/// Init systems a first-boot hook can be installed for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InitSystem {
+    Systemd,
+    OpenRc,
+}
+
+/// The init system of a root filesystem, from what /sbin/init is and what is installed
+fn detect_init(rootfs: &Path) -> Option<InitSystem> {
+    let exists = |path: &str| resolve_in_root(rootfs, Path::new(path)).is_ok_and(|p| p.exists());
+    let init = resolve_in_root(rootfs, Path::new("sbin/init")).ok();
+    if init
+        .as_deref()
+        .and_then(Path::file_name)
+        .is_some_and(|name| name == "systemd")
+    {
+        return Some(InitSystem::Systemd);
+    }
+    if exists("sbin/openrc") || exists("sbin/openrc-run") {
+        return Some(InitSystem::OpenRc);
+    }
+    if exists("lib/systemd/systemd") || exists("usr/lib/systemd/systemd") {
+        return Some(InitSystem::Systemd);
+    }
+    None
+}
+
+/// Resolve `path` under `root` the way the container sees it: absolute symlink
+/// targets restart at `root` and `..` never climbs above it. Components that
+/// don't exist yet are kept as named.
+fn resolve_in_root(root: &Path, path: &Path) -> Result<PathBuf> {
+    let mut resolved = PathBuf::new();
+    let mut pending = components(path);
+    let mut links = 0;
+
+    while let Some(name) = pending.pop_front() {
+        if name == ".." {
+            resolved.pop();
+            continue;
+        }
+        let candidate = resolved.join(&name);
+        let full = root.join(&candidate);
+        let is_link = std::fs::symlink_metadata(&full).is_ok_and(|m| m.file_type().is_symlink());
+        if !is_link {
+            resolved = candidate;
+            continue;
+        }
+
+        links += 1;
+        if links > MAX_SYMLINKS {
+            bail!(
+                "Too many symlinks resolving {} under {}",
+                path.display(),
+                root.display()
+            );
+        }
+        let target = std::fs::read_link(&full)
+            .with_context(|| format!("Failed to read link {}", full.display()))?;
+        if target.has_root() {
+            resolved = PathBuf::new();
+        }
+        let mut next = components(&target);
+        next.extend(pending);
+        pending = next;
+    }
+
+    Ok(root.join(resolved))
+}
+
+/// Names and `..` steps of a path; root and `.` components carry no step
+fn components(path: &Path) -> VecDeque<OsString> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::ParentDir => Some(OsString::from("..")),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Host path of a file under `root`, with its parent directory resolved and created
+async fn prepare_in_root(root: &Path, path: &str) -> Result<PathBuf> {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} has no file name", path.display()))?;
+    let dir = resolve_in_root(root, path.parent().unwrap_or(Path::new("")))?;
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(dir.join(name))
+}
+
+/// Write a file under `root`, replacing whatever is there (a symlink included)
+/// instead of writing through it
+async fn write_in_root(root: &Path, path: &str, contents: &str, mode: u32) -> Result<()> {
+    let file = prepare_in_root(root, path).await?;
+    if tokio::fs::symlink_metadata(&file).await.is_ok() {
+        tokio::fs::remove_file(&file).await?;
+    }
+    let mut out = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&file)
+        .await
+        .with_context(|| format!("Failed to write {}", file.display()))?;
+    out.write_all(contents.as_bytes()).await?;
+    out.set_permissions(std::fs::Permissions::from_mode(mode))
+        .await?;
+    Ok(())
+}
+
+/// Create a symlink under `root` unless something already has its name
+async fn link_in_root(root: &Path, path: &str, target: &str) -> Result<()> {
+    let link = prepare_in_root(root, path).await?;
+    if tokio::fs::symlink_metadata(&link).await.is_err() {
+        tokio::fs::symlink(target, &link).await?;
+    }
+    Ok(())
+}
+
+/// A container's provisioning: the declared section plus what the legacy
+/// `firstboot_script` and netmaker `network_type` properties ask for. None
+/// when there is nothing to do.
+pub async fn for_container(container: &ContainerInfo) -> Result<Option<Provision>> {
+    let mut provision = container.provision.clone().unwrap_or_default();
+
+    if let Some(script) = prop(container, "firstboot_script").and_then(Value::as_str) {
+        provision.files.push(ProvisionFile {
+            path: FIRSTBOOT_PATH.to_string(),
+            content: script.to_string(),
+            mode: Some("0755".to_string()),
+            owner: None,
+        });
+        provision.commands.push(FIRSTBOOT_PATH.to_string());
+    }
+
+    if prop(container, "network_type").and_then(Value::as_str) == Some("netmaker") {
+        let token = tokio::fs::read_to_string(NETMAKER_ENV)
+            .await
+            .ok()
+            .and_then(|env| {
+                env.lines().find_map(|line| {
+                    line.strip_prefix("NETMAKER_TOKEN=")
+                        .map(|token| token.trim_matches('"').trim().to_string())
+                })
+            });
+        match token {
+            Some(token) => {
+                provision
+                    .secrets
+                    .insert("/etc/netmaker/enrollment-token".to_string(), token);
+            }
+            None => log::warn!(
+                "Container {}: no NETMAKER_TOKEN in {}, not enrolling it",
+                container.id,
+                NETMAKER_ENV
+            ),
+        }
+    }
+
+    provision
+        .validate()
+        .with_context(|| format!("Container {}: invalid provision section", container.id))?;
+    Ok((!provision.is_empty()).then_some(provision))
+}
+
+/// The marker under a container root (a mounted rootfs or /proc/PID/root)
+pub async fn read_result(root: &Path) -> Option<ProvisionResult> {
+    let path = resolve_in_root(root, Path::new(MARKER_PATH)).ok()?;
+    let marker = tokio::fs::read_to_string(path).await.ok()?;
+    serde_json::from_str(&marker).ok()
+}
+
+/// Install the first-boot script and its systemd unit or OpenRC service into a
+/// stopped container's root filesystem. Returns false when this spec already
+/// ran there; fails for images with another init system.
+pub async fn render(rootfs: &Path, provision: &Provision) -> Result<bool> {
+    if read_result(rootfs)
+        .await
+        .is_some_and(|result| result.hash == provision.hash())
+    {
+        return Ok(false);
+    }
+
+    let init = detect_init(rootfs).ok_or_else(|| {
+        anyhow!("Provisioning needs systemd or OpenRC; found neither in the container image")
+    })?;
+
+    for (path, content) in &provision.secrets {
+        write_in_root(rootfs, path, content, 0o600).await?;
+    }
+    write_in_root(rootfs, SCRIPT_PATH, &render_script(provision), 0o700).await?;
+
+    match init {
+        InitSystem::Systemd => {
+            let unit = format!("etc/systemd/system/{}", UNIT_NAME);
+            write_in_root(rootfs, &unit, &render_unit(), 0o644).await?;
+            let wants = format!("etc/systemd/system/multi-user.target.wants/{}", UNIT_NAME);
+            link_in_root(rootfs, &wants, &format!("../{}", UNIT_NAME)).await?;
+        }
+        InitSystem::OpenRc => {
+            let service = format!("etc/init.d/{}", OPENRC_SERVICE);
+            write_in_root(rootfs, &service, &render_openrc_service(), 0o755).await?;
+            let runlevel = format!("etc/runlevels/default/{}", OPENRC_SERVICE);
+            link_in_root(rootfs, &runlevel, &format!("/{}", service)).await?;
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_script() {
+        let provision = Provision {
+            hostname: Some("web1".to_string()),
+            packages: vec!["nginx".to_string()],
+            users: vec![ProvisionUser {
+                name: "deploy".to_string(),
+                groups: vec!["sudo".to_string(), "www-data".to_string()],
+                shell: Some("/bin/bash".to_string()),
+                ssh_authorized_keys: vec!["ssh-ed25519 AAAA deploy@laptop".to_string()],
+            }],
+            files: vec![ProvisionFile {
+                path: "/etc/motd".to_string(),
+                content: "it's web1\n".to_string(),
+                mode: None,
+                owner: Some("deploy".to_string()),
+            }],
+            commands: vec!["systemctl enable --now nginx".to_string()],
+            ..Default::default()
+        };
+        provision.validate().unwrap();
+
+        let script = render_script(&provision);
+        assert!(script.contains(&format!("HASH='{}'", provision.hash())));
+        assert!(script.contains("echo 'web1' > /etc/hostname || fail"));
+        assert!(script.contains("apt-get install -y 'nginx' || fail"));
+        assert!(script.contains("useradd -m -s '/bin/bash' 'deploy'"));
+        assert!(script.contains("usermod -aG 'sudo,www-data' 'deploy'"));
+        assert!(script.contains("printf '%s\\n' 'ssh-ed25519 AAAA deploy@laptop'"));
+        // Quotes in content survive the shell quoting
+        assert!(script.contains(r"printf '%s' 'it'\''s web1"));
+        assert!(script.contains("chmod '0644' '/etc/motd' && chown 'deploy' '/etc/motd'"));
+        assert!(script.contains("STEP='command 1'\nsh -c 'systemctl enable --now nginx' || fail"));
+        assert!(script.ends_with("finish ok\n"));
+
+        // Users exist before their files are written
+        assert!(script.find("STEP='user deploy'") < script.find("STEP='file /etc/motd'"));
+    }
+
+    #[tokio::test]
+    async fn test_secrets_stay_out_of_script_and_hash() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        std::fs::create_dir_all(root.join("lib/systemd")).unwrap();
+        std::fs::write(root.join("lib/systemd/systemd"), "").unwrap();
+
+        let token = |token: &str| Provision {
+            hostname: Some("mesh1".to_string()),
+            secrets: [(
+                "/etc/netmaker/enrollment-token".to_string(),
+                token.to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let provision = token("tok-old");
+        assert_eq!(provision.hash(), token("tok-new").hash());
+        assert!(!render_script(&provision).contains("tok-old"));
+
+        assert!(render(root, &provision).await.unwrap());
+        let secret = root.join("etc/netmaker/enrollment-token");
+        assert_eq!(std::fs::read_to_string(&secret).unwrap(), "tok-old");
+        assert_eq!(
+            std::fs::metadata(&secret).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let user = |name: &str| Provision {
+            users: vec![ProvisionUser {
+                name: name.to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(user("deploy").validate().is_ok());
+        assert!(user("Deploy").validate().is_err());
+        assert!(user("x; rm -rf /").validate().is_err());
+
+        let file = |path: &str, mode: &str| Provision {
+            files: vec![ProvisionFile {
+                path: path.to_string(),
+                mode: Some(mode.to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(file("/etc/app.conf", "0640").validate().is_ok());
+        assert!(file("etc/app.conf", "0640").validate().is_err());
+        assert!(file("/etc/app.conf", "rw-r--r--").validate().is_err());
+
+        let hostname = |name: &str| Provision {
+            hostname: Some(name.to_string()),
+            ..Default::default()
+        };
+        assert!(hostname("web1.example.com").validate().is_ok());
+        assert!(hostname("web_1").validate().is_err());
+    }
+
+    #[test]
+    fn test_resolve_in_root_stays_inside() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        std::fs::create_dir_all(root.join("usr/lib/systemd")).unwrap();
+        std::fs::create_dir_all(root.join("sbin")).unwrap();
+        std::os::unix::fs::symlink("usr/lib", root.join("lib")).unwrap();
+        std::os::unix::fs::symlink("/lib/systemd/systemd", root.join("sbin/init")).unwrap();
+        // Absolute and climbing links land in the root, not on the host
+        std::os::unix::fs::symlink("/etc", root.join("hostetc")).unwrap();
+        std::os::unix::fs::symlink("../../../..", root.join("usr/up")).unwrap();
+
+        let resolve = |path: &str| resolve_in_root(root, Path::new(path)).unwrap();
+        assert_eq!(resolve("sbin/init"), root.join("usr/lib/systemd/systemd"));
+        assert_eq!(resolve("hostetc/passwd"), root.join("etc/passwd"));
+        assert_eq!(resolve("usr/up/etc/shadow"), root.join("etc/shadow"));
+        assert_eq!(resolve("/var/lib/../lib/x"), root.join("var/lib/x"));
+
+        std::os::unix::fs::symlink("loop", root.join("loop")).unwrap();
+        assert!(resolve_in_root(root, Path::new("loop/x")).is_err());
+    }
+
+    #[test]
+    fn test_detect_init() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        assert_eq!(detect_init(root), None);
+
+        std::fs::create_dir_all(root.join("sbin")).unwrap();
+        std::fs::write(root.join("sbin/openrc"), "").unwrap();
+        std::os::unix::fs::symlink("/bin/busybox", root.join("sbin/init")).unwrap();
+        assert_eq!(detect_init(root), Some(InitSystem::OpenRc));
+
+        std::fs::create_dir_all(root.join("lib/systemd")).unwrap();
+        std::fs::write(root.join("lib/systemd/systemd"), "").unwrap();
+        std::fs::remove_file(root.join("sbin/init")).unwrap();
+        std::os::unix::fs::symlink("/lib/systemd/systemd", root.join("sbin/init")).unwrap();
+        assert_eq!(detect_init(root), Some(InitSystem::Systemd));
+    }
+}
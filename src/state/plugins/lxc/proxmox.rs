@@ -0,0 +1,938 @@
+//! Proxmox VE backend - `pct` for lifecycle, /etc/pve/lxc/{VMID}.conf for config
+//!
+//! Containers are found by their OVS ports (vi{VMID}); the running state comes
+//! from the pve-container@{VMID}.service cgroup.
+
+use super::checkpoint::to_prune;
+use super::tools::lxc_init_pid;
+use super::{
+    on_path, ContainerBackend, ContainerConfig, ContainerInfo, MountPoint, Nic, Resources,
+};
+use crate::snapshot::SnapshotConfig;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+pub struct ProxmoxBackend;
+
+impl ProxmoxBackend {
+    fn is_running(ct_id: &str) -> Option<bool> {
+        // Proxmox systemd service path: pve-container@{vmid}.service (cgroup v2)
+        let path = format!(
+            "/sys/fs/cgroup/system.slice/pve-container@{}.service",
+            ct_id
+        );
+        Some(fs::metadata(path).is_ok())
+    }
+
+    /// Find container's veth interface name
+    async fn find_container_veth(ct_id: &str) -> Result<String> {
+        // Try to get the actual interface name from the container's eth0 peer
+        let output = tokio::process::Command::new("ip")
+            .args([
+                "netns",
+                "exec",
+                &format!("ct{}", ct_id),
+                "ip",
+                "link",
+                "show",
+                "eth0",
+            ])
+            .output()
+            .await?;
+
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            // Look for the peer link index
+            for line in stdout.lines() {
+                if line.contains("link-netnsid") || line.contains("@if") {
+                    // Parse the peer interface name from the host side
+                    // Format: "veth<random>@if<index>"
+                    let veth_interfaces = crate::native::rtnetlink_helpers::list_veth_interfaces().await?;
+
+                    // Find veth that matches this container's namespace
+                    for veth_name in veth_interfaces {
+                        if !veth_name.is_empty() {
+                            return Ok(veth_name);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Fallback: try to find veth by checking all veth pairs
+        let veth_interfaces = crate::native::rtnetlink_helpers::list_veth_interfaces().await?;
+        // Look for any veth interface (first one found)
+        for veth_name in veth_interfaces {
+            if !veth_name.is_empty() && veth_name.starts_with("veth") {
+                log::info!("Found veth interface: {}", veth_name);
+                return Ok(veth_name);
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Could not find veth interface for container {}",
+            ct_id
+        ))
+    }
+
+    /// Create LXC container via pct (Proxmox)
+    async fn create_container(container: &ContainerInfo, bridge: &str) -> Result<()> {
+        // Extract properties with sensible defaults
+        let props = container.properties.as_ref();
+
+        // Check if using BTRFS golden image (fast path) or tar.zst template (slow path)
+        let golden_image = props
+            .and_then(|p| p.get("golden_image"))
+            .and_then(|v| v.as_str());
+
+        if let Some(golden_image_name) = golden_image {
+            // BTRFS snapshot path - instant container creation
+            return Self::create_container_from_btrfs_snapshot(container, golden_image_name, bridge).await;
+        }
+
+        // Traditional tar.zst template path (fallback)
+        let template = props
+            .and_then(|p| p.get("template"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("local-btrfs:vztmpl/debian-13-standard_13.1-2_amd64.tar.zst");
+
+        // Hostname
+        let hostname = container.hostname();
+
+        // Memory (MB)
+        let memory = props
+            .and_then(|p| p.get("memory"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(512);
+
+        // Swap (MB)
+        let swap = props
+            .and_then(|p| p.get("swap"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(512);
+
+        // Storage location and size
+        let storage = props
+            .and_then(|p| p.get("storage"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("local-btrfs");
+
+        let rootfs_size = props
+            .and_then(|p| p.get("rootfs_size"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(8);
+
+        let rootfs = format!("{}:{}", storage, rootfs_size);
+
+        // CPU cores
+        let cores = props
+            .and_then(|p| p.get("cores"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(2);
+
+        // Unprivileged mode
+        let unprivileged = props
+            .and_then(|p| p.get("unprivileged"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+
+        // Features (comma-separated)
+        let features = props
+            .and_then(|p| p.get("features"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("nesting=1");
+
+        // Network configuration
+        let firewall = props
+            .and_then(|p| p.get("firewall"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+
+        let net0 = format!(
+            "name=eth0,bridge={},firewall={}",
+            bridge,
+            if firewall { "1" } else { "0" }
+        );
+
+        // Optional: IP address configuration
+        if let Some(ip) = props.and_then(|p| p.get("ip")).and_then(|v| v.as_str()) {
+            // ip can be "dhcp" or "192.168.1.100/24"
+            // pct expects: --net0 "name=eth0,bridge=vmbr0,ip=192.168.1.100/24,gw=192.168.1.1"
+            // For now, we'll handle this in a future enhancement
+            log::info!("IP configuration: {} (note: not yet implemented)", ip);
+        }
+
+        log::info!(
+            "Creating container {}: template={}, memory={}MB, cores={}, rootfs={}",
+            container.id,
+            template,
+            memory,
+            cores,
+            rootfs
+        );
+
+        // Build pct create command
+        let mut cmd = tokio::process::Command::new("pct");
+        cmd.args([
+            "create",
+            &container.id,
+            template,
+            "--hostname",
+            hostname.as_str(),
+            "--memory",
+            &memory.to_string(),
+            "--swap",
+            &swap.to_string(),
+            "--cores",
+            &cores.to_string(),
+            "--rootfs",
+            &rootfs,
+            "--net0",
+            &net0,
+            "--unprivileged",
+            if unprivileged { "1" } else { "0" },
+            "--features",
+            features,
+        ]);
+
+        // Optional: Start on boot
+        if let Some(onboot) = props.and_then(|p| p.get("onboot")).and_then(|v| v.as_bool()) {
+            cmd.args(["--onboot", if onboot { "1" } else { "0" }]);
+        }
+
+        // Optional: Protection (prevent accidental deletion)
+        if let Some(protection) = props.and_then(|p| p.get("protection")).and_then(|v| v.as_bool()) {
+            cmd.args(["--protection", if protection { "1" } else { "0" }]);
+        }
+
+        // Optional: Nameserver
+        if let Some(nameserver) = props.and_then(|p| p.get("nameserver")).and_then(|v| v.as_str()) {
+            cmd.args(["--nameserver", nameserver]);
+        }
+
+        // Optional: Searchdomain
+        if let Some(searchdomain) = props.and_then(|p| p.get("searchdomain")).and_then(|v| v.as_str()) {
+            cmd.args(["--searchdomain", searchdomain]);
+        }
+
+        // Execute pct create
+        let output = cmd.output().await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!("pct create failed: {}", stderr));
+        }
+
+        log::info!(
+            "Container {} created successfully on bridge {}",
+            container.id,
+            bridge
+        );
+
+        Ok(())
+    }
+
+    /// Create LXC container from BTRFS golden image snapshot (instant provisioning)
+    async fn create_container_from_btrfs_snapshot(
+        container: &ContainerInfo,
+        golden_image_name: &str,
+        bridge: &str,
+    ) -> Result<()> {
+        log::info!(
+            "Creating container {} from BTRFS golden image: {}",
+            container.id,
+            golden_image_name
+        );
+
+        let props = container.properties.as_ref();
+
+        // Storage backend (configurable per container)
+        let storage = props
+            .and_then(|p| p.get("storage"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("local-btrfs");
+
+        // Proxmox storage paths (adjust based on storage.cfg configuration)
+        let storage_path = format!("/var/lib/pve/{}", storage);
+        let golden_image_path = format!("{}/templates/subvol/{}", storage_path, golden_image_name);
+        let container_rootfs = format!("{}/images/{}/rootfs", storage_path, container.id);
+        let container_dir = format!("{}/images/{}", storage_path, container.id);
+
+        // Verify golden image exists
+        if tokio::fs::metadata(&golden_image_path).await.is_err() {
+            return Err(anyhow::anyhow!(
+                "Golden image not found: {}. Create it with: sudo ./create-btrfs-golden-image.sh {}",
+                golden_image_path,
+                golden_image_name
+            ));
+        }
+
+        // Check if it's a BTRFS subvolume
+        let check_output = tokio::process::Command::new("btrfs")
+            .args(["subvolume", "show", &golden_image_path])
+            .output()
+            .await?;
+
+        if !check_output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Golden image is not a BTRFS subvolume: {}",
+                golden_image_path
+            ));
+        }
+
+        log::info!("✓ Golden image verified: {}", golden_image_path);
+
+        // Create container directory
+        tokio::fs::create_dir_all(&container_dir).await?;
+
+        // Create BTRFS snapshot (instant copy-on-write)
+        log::info!("Creating BTRFS snapshot...");
+        let snapshot_output = tokio::process::Command::new("btrfs")
+            .args([
+                "subvolume",
+                "snapshot",
+                &golden_image_path,
+                &container_rootfs,
+            ])
+            .output()
+            .await?;
+
+        if !snapshot_output.status.success() {
+            let stderr = String::from_utf8_lossy(&snapshot_output.stderr);
+            return Err(anyhow::anyhow!("BTRFS snapshot failed: {}", stderr));
+        }
+
+        log::info!("✓ BTRFS snapshot created in <1ms: {}", container_rootfs);
+
+        // Extract properties
+        let hostname = container.hostname();
+
+        let memory = props
+            .and_then(|p| p.get("memory"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(512);
+
+        let swap = props
+            .and_then(|p| p.get("swap"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(512);
+
+        let cores = props
+            .and_then(|p| p.get("cores"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(2);
+
+        let unprivileged = props
+            .and_then(|p| p.get("unprivileged"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+
+        let features = props
+            .and_then(|p| p.get("features"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("nesting=1");
+
+        let firewall = props
+            .and_then(|p| p.get("firewall"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+
+        // Create Proxmox container configuration
+        let config_path = format!("/etc/pve/lxc/{}.conf", container.id);
+        let config_content = format!(
+            r#"arch: amd64
+cores: {}
+hostname: {}
+memory: {}
+swap: {}
+net0: name=eth0,bridge={},firewall={}
+ostype: debian
+rootfs: local-btrfs:images/{}/rootfs
+unprivileged: {}
+features: {}
+"#,
+            cores,
+            hostname,
+            memory,
+            swap,
+            bridge,
+            if firewall { "1" } else { "0" },
+            container.id,
+            if unprivileged { "1" } else { "0" },
+            features
+        );
+
+        // Add optional properties
+        let mut config = config_content;
+
+        if let Some(onboot) = props.and_then(|p| p.get("onboot")).and_then(|v| v.as_bool()) {
+            config.push_str(&format!("onboot: {}\n", if onboot { "1" } else { "0" }));
+        }
+
+        if let Some(protection) = props.and_then(|p| p.get("protection")).and_then(|v| v.as_bool()) {
+            config.push_str(&format!("protection: {}\n", if protection { "1" } else { "0" }));
+        }
+
+        if let Some(nameserver) = props.and_then(|p| p.get("nameserver")).and_then(|v| v.as_str()) {
+            config.push_str(&format!("nameserver: {}\n", nameserver));
+        }
+
+        if let Some(searchdomain) = props.and_then(|p| p.get("searchdomain")).and_then(|v| v.as_str()) {
+            config.push_str(&format!("searchdomain: {}\n", searchdomain));
+        }
+
+        // Write Proxmox config
+        tokio::fs::write(&config_path, config).await?;
+
+        log::info!("✓ Proxmox configuration written: {}", config_path);
+
+        log::info!(
+            "✓ Container {} created from golden image '{}' (BTRFS snapshot)",
+            container.id,
+            golden_image_name
+        );
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ContainerBackend for ProxmoxBackend {
+    fn name(&self) -> &'static str {
+        "proxmox"
+    }
+
+    fn is_available(&self) -> bool {
+        on_path("pct")
+    }
+
+    fn config_paths(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from("/etc/pve/lxc")]
+    }
+
+    async fn discover(&self) -> Result<Vec<ContainerInfo>> {
+        let client = crate::native::OvsdbClient::new();
+        // If OVSDB is not reachable, return empty list
+        if client.list_dbs().await.is_err() {
+            return Ok(Vec::new());
+        }
+
+        let mut results = Vec::new();
+        let bridges = client.list_bridges().await.unwrap_or_default();
+        for br in bridges {
+            let ports = client.list_bridge_ports(&br).await.unwrap_or_default();
+            for p in ports {
+                if let Some(ct_id) = p.strip_prefix("vi") {
+                    // ensure ID is numeric-like
+                    if ct_id.chars().all(|c| c.is_ascii_digit()) {
+                        let running = Self::is_running(ct_id);
+                        results.push(ContainerInfo {
+                            id: ct_id.to_string(),
+                            veth: p.clone(),
+                            bridge: br.clone(),
+                            running,
+                            config: ContainerConfig::default(),
+                            provision: None,
+                            provisioned: None,
+                            properties: None,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    async fn addresses(&self, id: &str) -> Result<Vec<IpAddr>> {
+        Ok(config_addresses(&read_config(id).await?))
+    }
+
+    async fn hwaddr(&self, id: &str, bridge: &str) -> Result<Option<String>> {
+        Ok(config_hwaddr(&read_config(id).await?, bridge))
+    }
+
+    async fn config(&self, id: &str) -> Result<ContainerConfig> {
+        Ok(parse_config(&read_config(id).await?))
+    }
+
+    /// Limits go through `pct set`, which applies them to a running container;
+    /// mounts, features and NICs are written to the config and are pending
+    /// until the next start
+    async fn reconfigure(&self, id: &str, changes: &ContainerConfig, _running: bool) -> Result<()> {
+        if let Some(resources) = &changes.resources {
+            let mut args = vec!["set".to_string(), id.to_string()];
+            for (option, value) in [
+                ("--cores", resources.cores),
+                ("--memory", resources.memory),
+                ("--swap", resources.swap),
+            ] {
+                if let Some(value) = value {
+                    args.push(option.to_string());
+                    args.push(value.to_string());
+                }
+            }
+            pct(&args.iter().map(String::as_str).collect::<Vec<_>>()).await?;
+        }
+
+        if changes.mounts.is_some() || changes.features.is_some() || changes.nics.is_some() {
+            let path = format!("/etc/pve/lxc/{}.conf", id);
+            let existing = read_config(id).await?;
+            tokio::fs::write(&path, render_changes(&existing, changes))
+                .await
+                .with_context(|| format!("Failed to write {}", path))?;
+        }
+        Ok(())
+    }
+
+    async fn create(&self, container: &ContainerInfo, bridge: &str) -> Result<()> {
+        Self::create_container(container, bridge).await
+    }
+
+    async fn start(&self, id: &str) -> Result<()> {
+        pct(&["start", id]).await
+    }
+
+    async fn stop(&self, id: &str) -> Result<()> {
+        pct(&["stop", id]).await
+    }
+
+    async fn destroy(&self, id: &str) -> Result<()> {
+        pct(&["destroy", id]).await
+    }
+
+    async fn host_veth(&self, id: &str) -> Result<String> {
+        Self::find_container_veth(id).await
+    }
+
+    /// `pct mount` puts the root filesystem (and mount points) under
+    /// /var/lib/lxc/{VMID}/rootfs, whatever the storage
+    async fn mount_rootfs(&self, id: &str) -> Result<PathBuf> {
+        pct(&["mount", id]).await?;
+        Ok(PathBuf::from(format!("/var/lib/lxc/{}/rootfs", id)))
+    }
+
+    async fn unmount_rootfs(&self, id: &str) -> Result<()> {
+        pct(&["unmount", id]).await
+    }
+
+    async fn init_pid(&self, id: &str) -> Result<Option<u32>> {
+        lxc_init_pid(id).await
+    }
+
+    /// `pct snapshot` covers the root filesystem and the config
+    async fn snapshot(&self, id: &str, name: &str, config: &SnapshotConfig) -> Result<()> {
+        pct(&["snapshot", id, name, "--description", "op-dbus checkpoint"]).await?;
+        let snapshots = config_snapshots(&read_config(id).await?);
+        for victim in to_prune(&snapshots, config) {
+            log::info!("Deleting snapshot {} of container {}", victim, id);
+            pct(&["delsnapshot", id, &victim]).await?;
+        }
+        Ok(())
+    }
+
+    async fn restore(&self, id: &str, name: &str, _config: &SnapshotConfig) -> Result<()> {
+        pct(&["rollback", id, name]).await
+    }
+}
+
+async fn pct(args: &[&str]) -> Result<()> {
+    let output = tokio::process::Command::new("pct")
+        .args(args)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("pct {} failed: {}", args[0], stderr));
+    }
+
+    Ok(())
+}
+
+async fn read_config(id: &str) -> Result<String> {
+    let path = format!("/etc/pve/lxc/{}.conf", id);
+    tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("Failed to read {}", path))
+}
+
+/// Key/value lines of the live config
+fn config_entries(config: &str) -> impl Iterator<Item = (&str, &str)> {
+    config
+        .lines()
+        // Snapshot sections follow the live config
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+}
+
+/// Index of a numbered key such as net0 or mp3
+fn key_index(key: &str, prefix: &str) -> Option<u32> {
+    key.strip_prefix(prefix)
+        .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))?
+        .parse()
+        .ok()
+}
+
+/// Values of the netN lines in a container config
+fn config_nets(config: &str) -> impl Iterator<Item = &str> {
+    config_entries(config)
+        .filter(|(key, _)| key_index(key, "net").is_some())
+        .map(|(_, value)| value)
+}
+
+/// Options of a property string like "name=eth0,bridge=vmbr0"; a leading bare
+/// value (a mount point's volume) gets the key ""
+fn options(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter(|option| !option.is_empty())
+        .map(|option| match option.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => (String::new(), option.to_string()),
+        })
+        .collect()
+}
+
+fn option<'a>(options: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Set an option in place, append it, or remove it for None
+fn set_option(options: &mut Vec<(String, String)>, key: &str, value: Option<String>) {
+    match value {
+        Some(value) => match options.iter_mut().find(|(k, _)| k == key) {
+            Some(option) => option.1 = value,
+            None => options.push((key.to_string(), value)),
+        },
+        None => options.retain(|(k, _)| k != key),
+    }
+}
+
+fn join_options(options: &[(String, String)]) -> String {
+    options
+        .iter()
+        .map(|(key, value)| match key.as_str() {
+            "" => value.clone(),
+            _ => format!("{}={}", key, value),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Snapshot sections of a container config with their creation times
+fn config_snapshots(config: &str) -> Vec<(String, i64)> {
+    let mut snapshots = Vec::new();
+    let mut section = None;
+    for line in config.lines() {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = Some(name);
+            continue;
+        }
+        let Some(name) = section else {
+            continue;
+        };
+        if let Some(("snaptime", time)) = line.split_once(':').map(|(k, v)| (k.trim(), v.trim())) {
+            if let Ok(time) = time.parse() {
+                snapshots.push((name.to_string(), time));
+            }
+        }
+    }
+    snapshots
+}
+
+/// Limits, mount points, features and NICs of the live config
+fn parse_config(config: &str) -> ContainerConfig {
+    let mut resources = Resources::default();
+    let mut mounts = Vec::new();
+    let mut features = BTreeMap::new();
+    let mut nics = BTreeMap::new();
+
+    for (key, value) in config_entries(config) {
+        let opts = options(value);
+        match key {
+            "cores" => resources.cores = value.parse().ok(),
+            "memory" => resources.memory = value.parse().ok(),
+            "swap" => resources.swap = value.parse().ok(),
+            "features" => {
+                features = opts
+                    .into_iter()
+                    .map(|(name, on)| (name, on == "1"))
+                    .collect()
+            }
+            _ if key_index(key, "mp").is_some() => {
+                let source = option(&opts, "").or_else(|| option(&opts, "volume"));
+                if let (Some(source), Some(target)) = (source, option(&opts, "mp")) {
+                    mounts.push(MountPoint {
+                        source: source.to_string(),
+                        target: target.to_string(),
+                        read_only: option(&opts, "ro") == Some("1"),
+                    });
+                }
+            }
+            _ => {
+                if let Some(index) = key_index(key, "net") {
+                    nics.insert(
+                        index,
+                        Nic {
+                            name: option(&opts, "name").unwrap_or_default().to_string(),
+                            bridge: option(&opts, "bridge").unwrap_or_default().to_string(),
+                            vlan: option(&opts, "tag").and_then(|tag| tag.parse().ok()),
+                            firewall: Some(option(&opts, "firewall") == Some("1")),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    ContainerConfig {
+        resources: Some(resources),
+        mounts: Some(mounts),
+        features: Some(features),
+        nics: Some(nics.into_values().collect()),
+    }
+}
+
+/// The config with the mounts, features and NICs of `changes` written into
+/// its live section. Options op-dbus doesn't manage (volume sizes, MACs,
+/// addresses) are carried over by mount target and NIC name.
+fn render_changes(existing: &str, changes: &ContainerConfig) -> String {
+    let split = existing
+        .lines()
+        .position(|line| line.starts_with('['))
+        .unwrap_or(usize::MAX);
+    let (live, snapshots): (Vec<_>, Vec<_>) =
+        existing.lines().enumerate().partition(|(i, _)| *i < split);
+
+    let mut old_mounts = Vec::new();
+    let mut old_features = Vec::new();
+    let mut old_nics = Vec::new();
+    let mut lines = Vec::new();
+    for (_, line) in live {
+        let (key, value) = line
+            .split_once(':')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .unwrap_or_default();
+        if changes.mounts.is_some() && key_index(key, "mp").is_some() {
+            old_mounts.push(options(value));
+        } else if changes.features.is_some() && key == "features" {
+            old_features = options(value);
+        } else if changes.nics.is_some() && key_index(key, "net").is_some() {
+            old_nics.push(options(value));
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+
+    if let Some(mounts) = &changes.mounts {
+        for (i, mount) in mounts.iter().enumerate() {
+            let mut opts = old_mounts
+                .iter()
+                .find(|o| option(o, "mp") == Some(mount.target.as_str()))
+                .cloned()
+                .unwrap_or_default();
+            opts.retain(|(key, _)| !key.is_empty() && key != "volume");
+            opts.insert(0, (String::new(), mount.source.clone()));
+            set_option(&mut opts, "mp", Some(mount.target.clone()));
+            set_option(&mut opts, "ro", mount.read_only.then(|| "1".to_string()));
+            lines.push(format!("mp{}: {}", i, join_options(&opts)));
+        }
+    }
+
+    if let Some(features) = &changes.features {
+        let mut opts = old_features;
+        for (name, on) in features {
+            set_option(
+                &mut opts,
+                name,
+                Some(if *on { "1" } else { "0" }.to_string()),
+            );
+        }
+        lines.push(format!("features: {}", join_options(&opts)));
+    }
+
+    if let Some(nics) = &changes.nics {
+        for (i, nic) in nics.iter().enumerate() {
+            let mut opts = old_nics
+                .iter()
+                .find(|o| option(o, "name") == Some(nic.name.as_str()))
+                .cloned()
+                .unwrap_or_else(|| vec![("name".to_string(), nic.name.clone())]);
+            set_option(&mut opts, "bridge", Some(nic.bridge.clone()));
+            set_option(&mut opts, "tag", nic.vlan.map(|vlan| vlan.to_string()));
+            if let Some(firewall) = nic.firewall {
+                set_option(
+                    &mut opts,
+                    "firewall",
+                    Some(if firewall { "1" } else { "0" }.to_string()),
+                );
+            }
+            set_option(&mut opts, "type", Some("veth".to_string()));
+            lines.push(format!("net{}: {}", i, join_options(&opts)));
+        }
+    }
+
+    let mut config: String = lines.into_iter().map(|line| line + "\n").collect();
+    for (_, line) in snapshots {
+        config.push_str(line);
+        config.push('\n');
+    }
+    config
+}
+
+fn config_hwaddr(config: &str, bridge: &str) -> Option<String> {
+    config_nets(config).find_map(|net| {
+        let options: Vec<&str> = net.split(',').collect();
+        if !options.contains(&format!("bridge={}", bridge).as_str()) {
+            return None;
+        }
+        options
+            .iter()
+            .find_map(|o| o.strip_prefix("hwaddr="))
+            .map(str::to_lowercase)
+    })
+}
+
+/// ip=/ip6= values of the netN lines in a container config
+fn config_addresses(config: &str) -> Vec<IpAddr> {
+    config_nets(config)
+        .flat_map(|net| net.split(','))
+        .filter_map(|option| {
+            let value = option
+                .strip_prefix("ip=")
+                .or_else(|| option.strip_prefix("ip6="))?;
+            value.split('/').next()?.parse().ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_addresses() {
+        let config = "arch: amd64\n\
+            net0: name=eth0,bridge=vmbr0,ip=10.0.0.5/24,gw=10.0.0.1,ip6=fd00::5/64\n\
+            net1: name=eth1,bridge=ovsbr0,hwaddr=BC:24:11:0A:0B:0C,ip=dhcp,ip6=auto\n\
+            nameserver: 10.0.0.1\n\
+            [before-upgrade]\n\
+            net0: name=eth0,bridge=vmbr0,ip=10.0.0.99/24\n";
+        assert_eq!(
+            config_addresses(config),
+            vec![
+                "10.0.0.5".parse::<IpAddr>().unwrap(),
+                "fd00::5".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            config_hwaddr(config, "ovsbr0").as_deref(),
+            Some("bc:24:11:0a:0b:0c")
+        );
+        assert_eq!(config_hwaddr(config, "vmbr0"), None);
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let existing = "arch: amd64\n\
+            cores: 2\n\
+            features: nesting=1\n\
+            memory: 512\n\
+            mp0: local-btrfs:subvol-100-disk-1,mp=/data,size=8G\n\
+            net0: name=eth0,bridge=vmbr0,firewall=1,hwaddr=BC:24:11:0A:0B:0C,ip=dhcp,type=veth\n\
+            [before-upgrade]\n\
+            memory: 256\n";
+        let current = parse_config(existing);
+        let resources = current.resources.as_ref().unwrap();
+        assert_eq!(
+            (resources.cores, resources.memory, resources.swap),
+            (Some(2), Some(512), None)
+        );
+        assert_eq!(
+            current.features,
+            Some([("nesting".to_string(), true)].into())
+        );
+        assert_eq!(
+            current.mounts.as_ref().unwrap()[0].source,
+            "local-btrfs:subvol-100-disk-1"
+        );
+        assert_eq!(current.nics.as_ref().unwrap()[0].firewall, Some(true));
+
+        let changes = ContainerConfig {
+            resources: None,
+            mounts: Some(vec![
+                MountPoint {
+                    source: "local-btrfs:subvol-100-disk-1".to_string(),
+                    target: "/data".to_string(),
+                    read_only: true,
+                },
+                MountPoint {
+                    source: "/srv/logs".to_string(),
+                    target: "/var/log/app".to_string(),
+                    read_only: false,
+                },
+            ]),
+            features: Some([("keyctl".to_string(), true)].into()),
+            nics: Some(vec![
+                Nic {
+                    name: "eth0".to_string(),
+                    bridge: "ovsbr0".to_string(),
+                    vlan: Some(10),
+                    firewall: None,
+                },
+                Nic {
+                    name: "eth1".to_string(),
+                    bridge: "mesh".to_string(),
+                    vlan: None,
+                    firewall: Some(false),
+                },
+            ]),
+        };
+        let rendered = render_changes(existing, &changes);
+        assert!(rendered.contains("mp0: local-btrfs:subvol-100-disk-1,mp=/data,size=8G,ro=1\n"));
+        assert!(rendered.contains("mp1: /srv/logs,mp=/var/log/app\n"));
+        assert!(rendered.contains("features: nesting=1,keyctl=1\n"));
+        // MAC and addressing survive, the snapshot is untouched
+        assert!(rendered.contains(
+            "net0: name=eth0,bridge=ovsbr0,firewall=1,hwaddr=BC:24:11:0A:0B:0C,ip=dhcp,type=veth,tag=10\n"
+        ));
+        assert!(rendered.contains("net1: name=eth1,bridge=mesh,firewall=0,type=veth\n"));
+        assert!(rendered.ends_with("[before-upgrade]\nmemory: 256\n"));
+
+        let parsed = parse_config(&rendered);
+        assert_eq!(parsed.mounts, changes.mounts);
+        assert_eq!(parsed.nics.unwrap()[0].vlan, Some(10));
+    }
+
+    #[test]
+    fn test_config_snapshots() {
+        let config = "memory: 512\n\
+            parent: opdbus-20250102-000000\n\
+            \n\
+            [before-upgrade]\n\
+            memory: 256\n\
+            snaptime: 1735689600\n\
+            \n\
+            [opdbus-20250102-000000]\n\
+            parent: before-upgrade\n\
+            snaptime: 1735776000\n\
+            \n\
+            [pve:pending]\n\
+            memory: 1024\n";
+        assert_eq!(
+            config_snapshots(config),
+            vec![
+                ("before-upgrade".to_string(), 1735689600),
+                ("opdbus-20250102-000000".to_string(), 1735776000)
+            ]
+        );
+    }
+}
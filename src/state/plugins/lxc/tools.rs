@@ -0,0 +1,639 @@
+//! Plain LXC backend - `lxc-*` tools and config files under /var/lib/lxc
+//!
+//! Containers are named by their id. The host end of the first veth is pinned
+//! to vi{ID} with lxc.net.0.veth.pair and LXC attaches it to the bridge itself
+//! (OVS bridges included), which is what the Proxmox backend ends up with too.
+
+use super::checkpoint::{btrfs_restore, btrfs_snapshot};
+use super::{
+    check_port_name, on_path, ovs_port_bridges, prop, run, ContainerBackend, ContainerConfig,
+    ContainerInfo, MountPoint, Nic, Resources,
+};
+use crate::snapshot::SnapshotConfig;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+const LXC_PATH: &str = "/var/lib/lxc";
+
+/// Marks the block op-dbus appends to a container config
+const MANAGED_MARKER: &str = "# op-dbus managed";
+
+/// Keys replaced by the managed block
+const MANAGED_KEYS: [&str; 5] = [
+    "lxc.uts.name",
+    "lxc.start.auto",
+    "lxc.cgroup2.memory.max",
+    "lxc.cgroup2.memory.swap.max",
+    "lxc.cgroup2.cpu.max",
+];
+
+pub struct LxcToolsBackend;
+
+fn container_dir(id: &str) -> PathBuf {
+    Path::new(LXC_PATH).join(id)
+}
+
+async fn read_config(id: &str) -> Result<String> {
+    let path = container_dir(id).join("config");
+    tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// `lxc.net.N.*` settings by interface index, in file order
+fn config_nets(config: &str) -> BTreeMap<u32, Vec<(&str, &str)>> {
+    let mut nets: BTreeMap<u32, Vec<(&str, &str)>> = BTreeMap::new();
+    for line in config.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let Some(rest) = key.trim().strip_prefix("lxc.net.") else {
+            continue;
+        };
+        let Some((index, setting)) = rest.split_once('.') else {
+            continue;
+        };
+        if let Ok(index) = index.parse() {
+            nets.entry(index).or_default().push((setting, value.trim()));
+        }
+    }
+    nets
+}
+
+fn config_addresses(config: &str) -> Vec<IpAddr> {
+    config_nets(config)
+        .values()
+        .flatten()
+        .filter(|(key, _)| matches!(*key, "ipv4.address" | "ipv6.address"))
+        .filter_map(|(_, value)| value.split('/').next()?.parse().ok())
+        .collect()
+}
+
+fn config_hwaddr(config: &str, bridge: &str) -> Option<String> {
+    config_nets(config).values().find_map(|net| {
+        if !net.contains(&("link", bridge)) {
+            return None;
+        }
+        net.iter()
+            .find(|(key, _)| *key == "hwaddr")
+            .map(|(_, value)| value.to_lowercase())
+    })
+}
+
+/// Bridge of the first interface
+fn config_bridge(config: &str) -> Option<String> {
+    config_nets(config)
+        .values()
+        .next()?
+        .iter()
+        .find(|(key, _)| *key == "link")
+        .map(|(_, value)| value.to_string())
+}
+
+/// Host directory of the root filesystem in lxc.rootfs.path; the upper layer
+/// of an overlay clone
+fn config_rootfs(config: &str, id: &str) -> Result<PathBuf> {
+    let path = config.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == "lxc.rootfs.path").then(|| value.trim())
+    });
+    let Some(path) = path else {
+        return Ok(container_dir(id).join("rootfs"));
+    };
+    let dir = match path.split_once(':') {
+        None => path,
+        Some(("dir" | "btrfs", dir)) => dir,
+        Some(("overlay" | "overlayfs", layers)) => layers.rsplit(':').next().unwrap_or_default(),
+        Some((storage, _)) => anyhow::bail!(
+            "Container {} is on {} storage, which has to be mounted to provision it",
+            id,
+            storage
+        ),
+    };
+    Ok(PathBuf::from(dir))
+}
+
+/// PID of a running container's init (Proxmox ships lxc-info too)
+pub(super) async fn lxc_init_pid(id: &str) -> Result<Option<u32>> {
+    let output = tokio::process::Command::new("lxc-info")
+        .args(["-n", id, "-p", "-H"])
+        .output()
+        .await
+        .context("Failed to run lxc-info")?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().parse().ok())
+}
+
+/// A cgroup memory limit ("512M", "1G" or bytes) in MiB; None for "max"
+fn parse_mib(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Some(mib) = value.strip_suffix('M') {
+        mib.parse().ok()
+    } else if let Some(gib) = value.strip_suffix('G') {
+        gib.parse::<u64>().ok().map(|gib| gib * 1024)
+    } else {
+        value.parse::<u64>().ok().map(|bytes| bytes / (1024 * 1024))
+    }
+}
+
+/// Limits, bind mounts and NICs; LXC has no Proxmox-style features
+fn parse_config(config: &str) -> ContainerConfig {
+    let mut resources = Resources::default();
+    let mut mounts = Vec::new();
+    for (key, value) in config
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+    {
+        match key {
+            "lxc.cgroup2.memory.max" => resources.memory = parse_mib(value),
+            "lxc.cgroup2.memory.swap.max" => resources.swap = parse_mib(value),
+            "lxc.cgroup2.cpu.max" => {
+                // "<quota> <period>"
+                let mut fields = value.split_whitespace();
+                if let (Some(Ok(quota)), Some(Ok(period))) = (
+                    fields.next().map(str::parse::<u64>),
+                    fields.next().map(str::parse::<u64>),
+                ) {
+                    resources.cores = (period > 0).then(|| quota / period);
+                }
+            }
+            "lxc.mount.entry" => {
+                // "<source> <target relative to the rootfs> <type> <options> 0 0"
+                let fields: Vec<&str> = value.split_whitespace().collect();
+                if let [source, target, _, options, ..] = fields[..] {
+                    mounts.push(MountPoint {
+                        source: source.to_string(),
+                        target: format!("/{}", target.trim_start_matches('/')),
+                        read_only: options.split(',').any(|option| option == "ro"),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let nics = config_nets(config)
+        .into_iter()
+        .map(|(index, net)| {
+            let get = |key: &str| {
+                net.iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, value)| value.to_string())
+            };
+            Nic {
+                name: get("name").unwrap_or_else(|| format!("eth{}", index)),
+                bridge: get("link").unwrap_or_default(),
+                vlan: get("veth.vlan.id").and_then(|vlan| vlan.parse().ok()),
+                firewall: None,
+            }
+        })
+        .collect();
+
+    ContainerConfig {
+        resources: Some(resources),
+        mounts: Some(mounts),
+        features: None,
+        nics: Some(nics),
+    }
+}
+
+/// The config with the limits, mounts and NICs of `changes` in place of the
+/// current ones. NIC settings op-dbus doesn't manage (hwaddr, addresses) are
+/// carried over by interface name.
+fn render_changes(existing: &str, id: &str, changes: &ContainerConfig) -> String {
+    let resources = changes.resources.clone().unwrap_or_default();
+    let mut replaced: Vec<&str> = Vec::new();
+    if resources.memory.is_some() {
+        replaced.push("lxc.cgroup2.memory.max");
+    }
+    if resources.swap.is_some() {
+        replaced.push("lxc.cgroup2.memory.swap.max");
+    }
+    if resources.cores.is_some() {
+        replaced.push("lxc.cgroup2.cpu.max");
+    }
+    if changes.mounts.is_some() {
+        replaced.push("lxc.mount.entry");
+    }
+
+    let old_nets = config_nets(existing);
+    let mut config: String = existing
+        .lines()
+        .filter(|line| {
+            let key = line.split('=').next().unwrap_or_default().trim();
+            let net = changes.nics.is_some() && key.starts_with("lxc.net.");
+            !net && !replaced.contains(&key)
+        })
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let mut set = |key: &str, value: &str| config.push_str(&format!("{} = {}\n", key, value));
+
+    if let Some(memory) = resources.memory {
+        set("lxc.cgroup2.memory.max", &format!("{}M", memory));
+    }
+    if let Some(swap) = resources.swap {
+        set("lxc.cgroup2.memory.swap.max", &format!("{}M", swap));
+    }
+    if let Some(cores) = resources.cores {
+        set(
+            "lxc.cgroup2.cpu.max",
+            &format!("{} 100000", cores * 100_000),
+        );
+    }
+    for mount in changes.mounts.iter().flatten() {
+        set(
+            "lxc.mount.entry",
+            &format!(
+                "{} {} none bind,create=dir{} 0 0",
+                mount.source,
+                mount.target.trim_start_matches('/'),
+                if mount.read_only { ",ro" } else { "" }
+            ),
+        );
+    }
+    for (i, nic) in changes.nics.iter().flatten().enumerate() {
+        let kept = old_nets
+            .values()
+            .find(|net| net.contains(&("name", nic.name.as_str())))
+            .cloned()
+            .unwrap_or_else(|| vec![("type", "veth"), ("flags", "up")]);
+        for (key, value) in kept {
+            if !matches!(key, "name" | "link" | "veth.vlan.id" | "veth.pair") {
+                set(&format!("lxc.net.{}.{}", i, key), value);
+            }
+        }
+        set(&format!("lxc.net.{}.name", i), &nic.name);
+        set(&format!("lxc.net.{}.link", i), &nic.bridge);
+        if let Some(vlan) = nic.vlan {
+            set(&format!("lxc.net.{}.veth.vlan.id", i), &vlan.to_string());
+        }
+        // The first NIC's host end is the vi{ID} port
+        if i == 0 {
+            set("lxc.net.0.veth.pair", &format!("vi{}", id));
+        }
+    }
+    config
+}
+
+/// The template's config with networking, limits and autostart replaced by
+/// the declared ones
+fn render_config(existing: &str, container: &ContainerInfo, bridge: &str) -> String {
+    let mut config: String = existing
+        .lines()
+        .filter(|line| {
+            let key = line.split('=').next().unwrap_or_default().trim();
+            line.trim() != MANAGED_MARKER
+                && !key.starts_with("lxc.net.")
+                && !MANAGED_KEYS.contains(&key)
+        })
+        .map(|line| format!("{}\n", line))
+        .collect();
+
+    let hostname = container.hostname();
+    let memory = prop(container, "memory")
+        .and_then(Value::as_u64)
+        .unwrap_or(512);
+    let swap = prop(container, "swap")
+        .and_then(Value::as_u64)
+        .unwrap_or(512);
+    let cores = prop(container, "cores")
+        .and_then(Value::as_u64)
+        .unwrap_or(2);
+
+    config.push_str(MANAGED_MARKER);
+    config.push('\n');
+    let mut set = |key: &str, value: &str| config.push_str(&format!("{} = {}\n", key, value));
+    set("lxc.uts.name", &hostname);
+    set("lxc.net.0.type", "veth");
+    set("lxc.net.0.name", "eth0");
+    set("lxc.net.0.link", bridge);
+    set("lxc.net.0.flags", "up");
+    set("lxc.net.0.veth.pair", &format!("vi{}", container.id));
+    if let Some(hwaddr) = prop(container, "hwaddr").and_then(Value::as_str) {
+        set("lxc.net.0.hwaddr", hwaddr);
+    }
+    // "dhcp" is left to the container's own network setup
+    if let Some(ip) = prop(container, "ip")
+        .and_then(Value::as_str)
+        .filter(|ip| ip.contains('/'))
+    {
+        set("lxc.net.0.ipv4.address", ip);
+        if let Some(gw) = prop(container, "gateway").and_then(Value::as_str) {
+            set("lxc.net.0.ipv4.gateway", gw);
+        }
+    }
+    set("lxc.cgroup2.memory.max", &format!("{}M", memory));
+    set("lxc.cgroup2.memory.swap.max", &format!("{}M", swap));
+    set(
+        "lxc.cgroup2.cpu.max",
+        &format!("{} 100000", cores * 100_000),
+    );
+    if prop(container, "onboot").and_then(Value::as_bool) == Some(true) {
+        set("lxc.start.auto", "1");
+    }
+    config
+}
+
+#[async_trait]
+impl ContainerBackend for LxcToolsBackend {
+    fn name(&self) -> &'static str {
+        "lxc"
+    }
+
+    fn is_available(&self) -> bool {
+        on_path("lxc-start")
+    }
+
+    async fn discover(&self) -> Result<Vec<ContainerInfo>> {
+        let mut entries = match tokio::fs::read_dir(LXC_PATH).await {
+            Ok(entries) => entries,
+            Err(_) => return Ok(Vec::new()),
+        };
+        let ports = ovs_port_bridges().await;
+
+        let mut results = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            let Ok(config) = read_config(&id).await else {
+                continue;
+            };
+            let veth = format!("vi{}", id);
+            // cgroup v2 layout of LXC 4+, then the legacy one
+            let running = Path::new(&format!("/sys/fs/cgroup/lxc.payload.{}", id)).exists()
+                || Path::new(&format!("/sys/fs/cgroup/lxc/{}", id)).exists();
+            results.push(ContainerInfo {
+                bridge: ports
+                    .get(&veth)
+                    .cloned()
+                    .or_else(|| config_bridge(&config))
+                    .unwrap_or_default(),
+                id,
+                veth,
+                running: Some(running),
+                config: ContainerConfig::default(),
+                provision: None,
+                provisioned: None,
+                properties: None,
+            });
+        }
+        results.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(results)
+    }
+
+    async fn addresses(&self, id: &str) -> Result<Vec<IpAddr>> {
+        Ok(config_addresses(&read_config(id).await?))
+    }
+
+    async fn hwaddr(&self, id: &str, bridge: &str) -> Result<Option<String>> {
+        Ok(config_hwaddr(&read_config(id).await?, bridge))
+    }
+
+    async fn config(&self, id: &str) -> Result<ContainerConfig> {
+        Ok(parse_config(&read_config(id).await?))
+    }
+
+    /// Limits are also set on the running container's cgroup; mounts and NICs
+    /// wait for the next start
+    async fn reconfigure(&self, id: &str, changes: &ContainerConfig, running: bool) -> Result<()> {
+        if changes.features.is_some() {
+            anyhow::bail!("Container {}: features are a Proxmox setting", id);
+        }
+        if changes
+            .nics
+            .iter()
+            .flatten()
+            .any(|nic| nic.firewall == Some(true))
+        {
+            anyhow::bail!("Container {}: the NIC firewall is a Proxmox setting", id);
+        }
+
+        let path = container_dir(id).join("config");
+        let existing = read_config(id).await?;
+        tokio::fs::write(&path, render_changes(&existing, id, changes))
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        if let (true, Some(resources)) = (running, &changes.resources) {
+            if let Some(memory) = resources.memory {
+                run(
+                    "lxc-cgroup",
+                    &["-n", id, "memory.max", &format!("{}M", memory)],
+                )
+                .await?;
+            }
+            if let Some(swap) = resources.swap {
+                run(
+                    "lxc-cgroup",
+                    &["-n", id, "memory.swap.max", &format!("{}M", swap)],
+                )
+                .await?;
+            }
+            if let Some(cores) = resources.cores {
+                let quota = format!("{} 100000", cores * 100_000);
+                run("lxc-cgroup", &["-n", id, "cpu.max", &quota]).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn create(&self, container: &ContainerInfo, bridge: &str) -> Result<()> {
+        check_port_name(&container.id)?;
+        let id = container.id.as_str();
+
+        if let Some(golden) = prop(container, "golden_image").and_then(Value::as_str) {
+            // Copy-on-write clone (btrfs/zfs/overlay, depending on the source)
+            run("lxc-copy", &["-n", golden, "-N", id, "-s"]).await?;
+        } else {
+            let dist = prop(container, "dist")
+                .and_then(Value::as_str)
+                .unwrap_or("debian");
+            let release = prop(container, "release")
+                .and_then(Value::as_str)
+                .unwrap_or("trixie");
+            let arch = prop(container, "arch")
+                .and_then(Value::as_str)
+                .unwrap_or("amd64");
+            run(
+                "lxc-create",
+                &[
+                    "-n", id, "-t", "download", "--", "-d", dist, "-r", release, "-a", arch,
+                ],
+            )
+            .await?;
+        }
+
+        let path = container_dir(id).join("config");
+        let existing = read_config(id).await?;
+        tokio::fs::write(&path, render_config(&existing, container, bridge))
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        log::info!("Container {} created on bridge {}", id, bridge);
+        Ok(())
+    }
+
+    async fn start(&self, id: &str) -> Result<()> {
+        run("lxc-start", &["-n", id]).await
+    }
+
+    async fn stop(&self, id: &str) -> Result<()> {
+        run("lxc-stop", &["-n", id]).await
+    }
+
+    async fn destroy(&self, id: &str) -> Result<()> {
+        run("lxc-destroy", &["-n", id]).await
+    }
+
+    async fn host_veth(&self, id: &str) -> Result<String> {
+        // Pinned by lxc.net.0.veth.pair
+        Ok(format!("vi{}", id))
+    }
+
+    async fn mount_rootfs(&self, id: &str) -> Result<PathBuf> {
+        config_rootfs(&read_config(id).await?, id)
+    }
+
+    async fn unmount_rootfs(&self, _id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn init_pid(&self, id: &str) -> Result<Option<u32>> {
+        lxc_init_pid(id).await
+    }
+
+    async fn snapshot(&self, id: &str, name: &str, config: &SnapshotConfig) -> Result<()> {
+        let dir = container_dir(id);
+        btrfs_snapshot(config, id, &dir.join("rootfs"), &dir.join("config"), name).await
+    }
+
+    async fn restore(&self, id: &str, name: &str, config: &SnapshotConfig) -> Result<()> {
+        let dir = container_dir(id);
+        btrfs_restore(config, id, &dir.join("rootfs"), &dir.join("config"), name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_config_networking() {
+        let config = "lxc.include = /usr/share/lxc/config/common.conf\n\
+            lxc.net.0.type = veth\n\
+            lxc.net.0.link = lxcbr0\n\
+            lxc.net.0.hwaddr = 00:16:3E:AA:BB:CC\n\
+            lxc.net.0.ipv4.address = 10.0.3.5/24\n\
+            lxc.net.1.link = ovsbr0\n\
+            lxc.net.1.ipv6.address = fd00::5/64\n\
+            lxc.uts.name = old\n";
+        assert_eq!(
+            config_addresses(config),
+            vec![
+                "10.0.3.5".parse::<IpAddr>().unwrap(),
+                "fd00::5".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            config_hwaddr(config, "lxcbr0").as_deref(),
+            Some("00:16:3e:aa:bb:cc")
+        );
+        assert_eq!(config_hwaddr(config, "ovsbr0"), None);
+        assert_eq!(config_bridge(config).as_deref(), Some("lxcbr0"));
+        assert_eq!(
+            config_rootfs(config, "web").unwrap(),
+            Path::new("/var/lib/lxc/web/rootfs")
+        );
+        assert_eq!(
+            config_rootfs(
+                "lxc.rootfs.path = overlay:/var/lib/lxc/base/rootfs:/var/lib/lxc/web/delta0\n",
+                "web"
+            )
+            .unwrap(),
+            Path::new("/var/lib/lxc/web/delta0")
+        );
+        assert!(config_rootfs("lxc.rootfs.path = lvm:/dev/vg/web\n", "web").is_err());
+
+        let container = ContainerInfo {
+            id: "web".to_string(),
+            veth: String::new(),
+            bridge: "ovsbr0".to_string(),
+            running: None,
+            config: ContainerConfig::default(),
+            provision: None,
+            provisioned: None,
+            properties: Some(
+                [
+                    ("ip".to_string(), json!("10.0.0.9/24")),
+                    ("onboot".to_string(), json!(true)),
+                ]
+                .into(),
+            ),
+        };
+        let rendered = render_config(config, &container, "ovsbr0");
+        assert!(rendered.starts_with("lxc.include"));
+        assert!(!rendered.contains("lxcbr0") && !rendered.contains("= old"));
+        assert_eq!(config_bridge(&rendered).as_deref(), Some("ovsbr0"));
+        assert!(rendered.contains("lxc.net.0.veth.pair = viweb\n"));
+        assert!(rendered.contains("lxc.start.auto = 1\n"));
+        // Rendering again replaces the managed block instead of stacking it
+        assert_eq!(render_config(&rendered, &container, "ovsbr0"), rendered);
+    }
+
+    #[test]
+    fn test_render_changes() {
+        let existing = "lxc.include = /usr/share/lxc/config/common.conf\n\
+            # op-dbus managed\n\
+            lxc.net.0.type = veth\n\
+            lxc.net.0.name = eth0\n\
+            lxc.net.0.link = ovsbr0\n\
+            lxc.net.0.hwaddr = 00:16:3e:aa:bb:cc\n\
+            lxc.net.0.veth.pair = viweb\n\
+            lxc.cgroup2.memory.max = 512M\n\
+            lxc.cgroup2.memory.swap.max = 512M\n\
+            lxc.cgroup2.cpu.max = 200000 100000\n";
+        let current = parse_config(existing);
+        let resources = current.resources.as_ref().unwrap();
+        assert_eq!(
+            (resources.cores, resources.memory, resources.swap),
+            (Some(2), Some(512), Some(512))
+        );
+        assert_eq!(current.mounts, Some(Vec::new()));
+
+        let changes = ContainerConfig {
+            resources: Some(Resources {
+                memory: Some(2048),
+                ..Default::default()
+            }),
+            mounts: Some(vec![MountPoint {
+                source: "/srv/data".to_string(),
+                target: "/data".to_string(),
+                read_only: true,
+            }]),
+            features: None,
+            nics: Some(vec![Nic {
+                name: "eth0".to_string(),
+                bridge: "mesh".to_string(),
+                vlan: Some(30),
+                firewall: None,
+            }]),
+        };
+        let rendered = render_changes(existing, "web", &changes);
+        assert!(rendered.contains("lxc.net.0.hwaddr = 00:16:3e:aa:bb:cc\n"));
+        assert!(rendered.contains("lxc.net.0.veth.pair = viweb\n"));
+        assert!(rendered.contains("lxc.mount.entry = /srv/data data none bind,create=dir,ro 0 0\n"));
+
+        let parsed = parse_config(&rendered);
+        let resources = parsed.resources.unwrap();
+        assert_eq!((resources.cores, resources.memory), (Some(2), Some(2048)));
+        assert_eq!(parsed.mounts, changes.mounts);
+        assert_eq!(parsed.nics, changes.nics);
+    }
+}
@@ -3,24 +3,34 @@ pub mod keyring;
 pub mod login1;
 pub mod lxc;
 pub mod net;
+pub mod nft;
 #[cfg(feature = "openflow")]
 pub mod netmaker;
 #[cfg(feature = "openflow")]
 pub mod openflow;
+#[cfg(feature = "openflow")]
+pub mod privacy;
 pub mod packagekit;
 pub mod sessdecl;
+pub mod sysctl;
 pub mod systemd;
+pub mod wireguard;
 
+pub mod dhcp;
 pub mod dnsresolver;
 pub mod pcidecl;
+pub use dhcp::DhcpPlugin;
 pub use dnsresolver::DnsResolverPlugin;
 pub use login1::Login1Plugin;
 pub use lxc::LxcPlugin;
 pub use net::NetStatePlugin;
+pub use nft::NftPlugin;
 pub use packagekit::PackageKitPlugin;
 pub use pcidecl::PciDeclPlugin;
 pub use sessdecl::SessDeclPlugin;
+pub use sysctl::SysctlPlugin;
 pub use systemd::SystemdStatePlugin;
+pub use wireguard::WireGuardPlugin;
 
 #[cfg(feature = "openflow")]
 pub use netmaker::NetmakerPlugin;
@@ -1,6 +1,14 @@
 // Net state plugin - authoritative OVS state management via D-Bus
 // Handles: interfaces, bridges, IPs, basic connectivity via plugin schema
+// Kernel links (VLAN, bond, veth, VXLAN, GRE, Linux bridge) are managed via rtnetlink
 use crate::blockchain::PluginFootprint;
+use crate::native::rtnetlink_helpers;
+use crate::native::rtnetlink_links::{
+    self, BondOptions, BridgeOptions, GreOptions, KernelLink, LinkSpec, VethOptions, VlanOptions,
+    VxlanOptions,
+};
+use crate::native::rtnetlink_monitor;
+use crate::native::rtnetlink_routes::{self, Route, RouteSpec, Rule, RuleSpec, TableNames};
 
 // Use D-Bus introspection instead of CLI commands
 use crate::state::plugin::{
@@ -11,13 +19,121 @@ use async_trait::async_trait;
 use log;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-// use std::net::Ipv4Addr; // not needed currently
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::Ipv6Addr;
+
+pub mod l3;
+pub mod ovs;
 
 /// Network configuration schema
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
     pub interfaces: Vec<InterfaceConfig>,
+
+    /// Static routes, named tables and policy rules; unmanaged when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing: Option<RoutingConfig>,
+}
+
+/// Routing section of the net state. When declared it is authoritative for
+/// routes and rules op-dbus installed; routes from DHCP, RA, the kernel or
+/// other daemons are never pruned.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoutingConfig {
+    /// Named routing tables (name -> id), written to the rt_tables.d drop-in
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tables: BTreeMap<String, u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<RouteSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RuleSpec>,
+}
+
+/// Resource name of the table-name drop-in in diffs
+const ROUTING_TABLES_RESOURCE: &str = "routing:tables";
+
+impl RoutingConfig {
+    /// Actions that turn `current` (owned entries only) into `self`.
+    /// Tables go first so later route/rule actions can resolve their names.
+    fn diff(&self, current: &RoutingConfig) -> Result<Vec<StateAction>> {
+        let mut actions = Vec::new();
+        if self.tables != current.tables {
+            actions.push(StateAction::Modify {
+                resource: ROUTING_TABLES_RESOURCE.to_string(),
+                changes: serde_json::to_value(&self.tables)?,
+            });
+        }
+
+        let desired_tables = TableNames::load(&self.tables);
+        let current_tables = TableNames::load(&current.tables);
+        // Installed entries that can't be represented (e.g. multipath) are left alone
+        let current_routes: Vec<Route> = current
+            .routes
+            .iter()
+            .filter_map(|spec| Route::from_spec(spec, &current_tables).ok())
+            .collect();
+        let current_rules: Vec<Rule> = current
+            .rules
+            .iter()
+            .filter_map(|spec| Rule::from_spec(spec, &current_tables).ok())
+            .collect();
+
+        // Pin table ids in the action payload so apply doesn't depend on name lookups
+        let mut desired_keys = Vec::new();
+        for spec in &self.routes {
+            let route = Route::from_spec(spec, &desired_tables)?;
+            let config = serde_json::to_value(RouteSpec {
+                table: Some(route.table.to_string()),
+                ..spec.clone()
+            })?;
+            match current_routes.iter().find(|r| r.key() == route.key()) {
+                None => actions.push(StateAction::Create {
+                    resource: route.key(),
+                    config,
+                }),
+                Some(installed) if !route.satisfied_by(installed) => {
+                    actions.push(StateAction::Modify {
+                        resource: route.key(),
+                        changes: config,
+                    })
+                }
+                Some(_) => {}
+            }
+            desired_keys.push(route.key());
+        }
+
+        let mut desired_rules = Vec::new();
+        for spec in &self.rules {
+            let rule = Rule::from_spec(spec, &desired_tables)?;
+            if !current_rules.iter().any(|r| rule.satisfied_by(r)) {
+                actions.push(StateAction::Create {
+                    resource: rule.key(),
+                    config: serde_json::to_value(RuleSpec {
+                        table: rule.table.to_string(),
+                        ..spec.clone()
+                    })?,
+                });
+            }
+            desired_rules.push(rule);
+        }
+
+        for rule in &current_rules {
+            if !desired_rules.iter().any(|want| want.satisfied_by(rule)) {
+                actions.push(StateAction::Delete {
+                    resource: rule.key(),
+                });
+            }
+        }
+        for route in &current_routes {
+            if !desired_keys.contains(&route.key()) {
+                actions.push(StateAction::Delete {
+                    resource: route.key(),
+                });
+            }
+        }
+
+        Ok(actions)
+    }
 }
 
 /// Interface configuration with immutable identity and tunable config
@@ -43,13 +159,15 @@ pub struct InterfaceConfig {
 }
 
 /// Tunable configuration - can be changed, each change tracked in blockchain
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TunableConfig {
     /// Ports attached to this interface
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ports: Option<Vec<String>>,
 
-    /// L3 driver for IP configuration (e.g., "rtnetlink", "networkmanager")
+    /// L3 renderer persisting IP configuration: "ifupdown", "networkd",
+    /// "networkmanager" or "netlink" (runtime only); detected when unset or
+    /// "rtnetlink" (the old default)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub l3_driver: Option<String>,
 
@@ -65,6 +183,34 @@ pub struct TunableConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub controller: Option<String>,
 
+    /// 802.1Q settings (type "vlan")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vlan: Option<VlanOptions>,
+
+    /// Bonding settings (type "bond")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bond: Option<BondOptions>,
+
+    /// Peer name (type "veth")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub veth: Option<VethOptions>,
+
+    /// VXLAN tunnel settings (type "vxlan")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vxlan: Option<VxlanOptions>,
+
+    /// GRE tunnel settings (type "gre")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gre: Option<GreOptions>,
+
+    /// Linux bridge STP/timer settings (type "bridge"); members go in `ports`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<BridgeOptions>,
+
+    /// OVS port VLANs, bonds, QoS, mirrors and external_ids (type "ovs-bridge")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ovs: Option<ovs::OvsBridgeOptions>,
+
     /// Dynamic properties - introspection captures ALL hardware properties here
     /// Examples: mtu, mac_addresses (array), speed, duplex, txqueuelen, etc.
     ///
@@ -85,7 +231,133 @@ pub enum InterfaceType {
     Ethernet,
     OvsBridge,
     OvsPort,
+    /// Linux (kernel) bridge
     Bridge,
+    Vlan,
+    Bond,
+    Veth,
+    Vxlan,
+    Gre,
+}
+
+impl InterfaceType {
+    /// Map a kernel link kind to the interface type managed via rtnetlink
+    fn from_kernel_kind(kind: &str) -> Option<Self> {
+        Some(match kind {
+            "bridge" => InterfaceType::Bridge,
+            "vlan" => InterfaceType::Vlan,
+            "bond" => InterfaceType::Bond,
+            "veth" => InterfaceType::Veth,
+            "vxlan" => InterfaceType::Vxlan,
+            "gre" => InterfaceType::Gre,
+            _ => return None,
+        })
+    }
+
+    /// Whether this type is a kernel link managed via rtnetlink
+    pub fn is_kernel_link(&self) -> bool {
+        !matches!(
+            self,
+            InterfaceType::Ethernet | InterfaceType::OvsBridge | InterfaceType::OvsPort
+        )
+    }
+}
+
+impl InterfaceConfig {
+    /// Kind-specific rtnetlink definition for kernel link types
+    pub fn link_spec(&self) -> Result<Option<LinkSpec>> {
+        let t = &self.tunable;
+        let missing = |section: &str| {
+            anyhow::anyhow!(
+                "Interface {} of type {:?} requires a '{}' section",
+                self.name,
+                self.if_type,
+                section
+            )
+        };
+
+        Ok(Some(match self.if_type {
+            InterfaceType::Bridge => LinkSpec::Bridge(t.bridge.clone().unwrap_or_default()),
+            InterfaceType::Bond => LinkSpec::Bond(t.bond.clone().unwrap_or_default()),
+            InterfaceType::Vlan => LinkSpec::Vlan(t.vlan.clone().ok_or_else(|| missing("vlan"))?),
+            InterfaceType::Veth => LinkSpec::Veth(t.veth.clone().ok_or_else(|| missing("veth"))?),
+            InterfaceType::Vxlan => {
+                LinkSpec::Vxlan(t.vxlan.clone().ok_or_else(|| missing("vxlan"))?)
+            }
+            InterfaceType::Gre => LinkSpec::Gre(t.gre.clone().ok_or_else(|| missing("gre"))?),
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Whether a kernel-link declaration is satisfied by the queried interface.
+    /// Only attributes present in the declaration are compared.
+    fn kernel_link_in_sync(&self, current: &InterfaceConfig) -> bool {
+        if self.if_type != current.if_type {
+            return false;
+        }
+        match (self.link_spec(), current.link_spec()) {
+            (Ok(Some(desired)), Ok(Some(actual))) if desired.satisfied_by(&actual) => {}
+            _ => return false,
+        }
+
+        // Declared ports must be attached; ports others attached (VM taps,
+        // container veths) are left alone
+        if let Some(ports) = &self.tunable.ports {
+            let actual = current.tunable.ports.as_deref().unwrap_or_default();
+            if !ports.iter().all(|port| actual.contains(port)) {
+                return false;
+            }
+        }
+
+        if let Some(mtu) = self.desired_mtu() {
+            if current.desired_mtu() != Some(mtu) {
+                return false;
+            }
+        }
+
+        if let Some(ipv6) = &self.tunable.ipv6 {
+            if !ipv6.in_sync(current.tunable.ipv6.as_ref()) {
+                return false;
+            }
+        }
+
+        match &self.tunable.ipv4 {
+            Some(ipv4) if ipv4.enabled => {
+                let actual: Vec<(&str, u8)> = current
+                    .tunable
+                    .ipv4
+                    .iter()
+                    .flat_map(|c| c.address.iter().flatten())
+                    .map(|a| (a.ip.as_str(), a.prefix))
+                    .collect();
+                ipv4.address
+                    .iter()
+                    .flatten()
+                    .all(|a| actual.contains(&(a.ip.as_str(), a.prefix)))
+            }
+            _ => true,
+        }
+    }
+
+    /// MTU requested through `properties.mtu`
+    fn desired_mtu(&self) -> Option<u32> {
+        self.tunable
+            .properties
+            .as_ref()?
+            .get("mtu")?
+            .as_u64()
+            .map(|mtu| mtu as u32)
+    }
+
+    /// Whether op-dbus created this (queried) kernel link
+    fn is_owned_link(&self) -> bool {
+        self.tunable
+            .properties
+            .as_ref()
+            .and_then(|p| p.get("managed"))
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -101,11 +373,158 @@ pub struct Ipv4Config {
     pub dns: Option<Vec<String>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Ipv6Config {
     pub enabled: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dhcp: Option<bool>,
+    /// Static addresses; when present, other static global addresses are removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<Vec<AddressConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>,
+    /// Accept router advertisements (net.ipv6.conf.<iface>.accept_ra)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accept_ra: Option<bool>,
+    /// SLAAC address autoconfiguration (net.ipv6.conf.<iface>.autoconf)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autoconf: Option<bool>,
+    /// RFC 4941 temporary addresses (net.ipv6.conf.<iface>.use_tempaddr)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privacy: Option<Ipv6Privacy>,
+    /// Static routes; when present, other static routes on the interface are removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routes: Option<Vec<Ipv6RouteConfig>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Ipv6Privacy {
+    Disabled,
+    PreferPublic,
+    PreferTemporary,
+}
+
+impl Ipv6Privacy {
+    fn use_tempaddr(self) -> i64 {
+        match self {
+            Ipv6Privacy::Disabled => 0,
+            Ipv6Privacy::PreferPublic => 1,
+            Ipv6Privacy::PreferTemporary => 2,
+        }
+    }
+
+    fn from_use_tempaddr(value: i64) -> Self {
+        match value {
+            v if v <= 0 => Ipv6Privacy::Disabled,
+            1 => Ipv6Privacy::PreferPublic,
+            _ => Ipv6Privacy::PreferTemporary,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ipv6RouteConfig {
+    /// Destination in CIDR notation, e.g. "2001:db8:100::/48"
+    pub destination: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metric: Option<u32>,
+}
+
+impl Ipv6Config {
+    /// Declared static addresses, normalized for comparison
+    fn address_set(&self) -> Result<BTreeSet<(Ipv6Addr, u8)>> {
+        self.address
+            .iter()
+            .flatten()
+            .map(|a| {
+                let ip: Ipv6Addr =
+                    a.ip.parse()
+                        .context(format!("Invalid IPv6 address {}", a.ip))?;
+                Ok((ip, a.prefix))
+            })
+            .collect()
+    }
+
+    fn gateway_addr(&self) -> Result<Option<Ipv6Addr>> {
+        self.gateway
+            .as_deref()
+            .map(|gw| gw.parse().context(format!("Invalid IPv6 gateway {}", gw)))
+            .transpose()
+    }
+
+    /// Whether the queried configuration satisfies this declaration.
+    /// Toggles left unset are not compared.
+    fn in_sync(&self, current: Option<&Ipv6Config>) -> bool {
+        let Some(current) = current else {
+            return !self.enabled;
+        };
+        if self.enabled != current.enabled {
+            return false;
+        }
+        if !self.enabled {
+            return true;
+        }
+
+        let toggles_match = self.accept_ra.is_none_or(|v| current.accept_ra == Some(v))
+            && self.autoconf.is_none_or(|v| current.autoconf == Some(v))
+            && self.privacy.is_none_or(|v| current.privacy == Some(v));
+        if !toggles_match {
+            return false;
+        }
+
+        if self.address.is_some() && self.address_set().ok() != current.address_set().ok() {
+            return false;
+        }
+        if self.gateway.is_some() && self.gateway_addr().ok() != current.gateway_addr().ok() {
+            return false;
+        }
+
+        match &self.routes {
+            Some(routes) => {
+                let actual = current.routes.as_deref().unwrap_or_default();
+                routes.len() == actual.len()
+                    && routes.iter().all(|want| {
+                        let Ok(dest) = parse_ipv6_cidr(&want.destination) else {
+                            return false;
+                        };
+                        actual.iter().any(|have| {
+                            parse_ipv6_cidr(&have.destination).ok() == Some(dest)
+                                && want.gateway.as_deref().map(str::parse::<Ipv6Addr>)
+                                    == have.gateway.as_deref().map(str::parse::<Ipv6Addr>)
+                                && want.metric.is_none_or(|m| have.metric == Some(m))
+                        })
+                    })
+            }
+            None => true,
+        }
+    }
+}
+
+/// Parse "2001:db8::/48" into (network, prefix)
+fn parse_ipv6_cidr(cidr: &str) -> Result<(Ipv6Addr, u8)> {
+    let (addr, prefix) = cidr
+        .split_once('/')
+        .context(format!("Expected CIDR notation, got {}", cidr))?;
+    let addr: Ipv6Addr = addr
+        .parse()
+        .context(format!("Invalid IPv6 address {}", addr))?;
+    let prefix: u8 = prefix
+        .parse()
+        .ok()
+        .filter(|p| *p <= 128)
+        .context(format!("Invalid IPv6 prefix length in {}", cidr))?;
+    Ok((addr, prefix))
+}
+
+fn ipv6_route_gateway(route: &Ipv6RouteConfig) -> Result<Option<Ipv6Addr>> {
+    route
+        .gateway
+        .as_deref()
+        .map(|gw| gw.parse().context(format!("Invalid IPv6 gateway {}", gw)))
+        .transpose()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -118,6 +537,16 @@ pub struct AddressConfig {
 pub struct NetStatePlugin {
     #[allow(dead_code)]
     blockchain_sender: Option<tokio::sync::mpsc::UnboundedSender<PluginFootprint>>,
+    /// Last query, reused while the netlink monitor reports no change
+    live_cache: tokio::sync::Mutex<Option<LiveCache>>,
+}
+
+/// Cached `query_current_state` result, keyed by the monitor generation it was taken at.
+/// Link, address, route, rule and OVSDB changes all bump the generation; applies clear it.
+/// IPv6 sysctl toggles changed behind op-dbus' back show up with the next such change.
+struct LiveCache {
+    generation: u64,
+    state: Value,
 }
 
 #[allow(dead_code)]
@@ -125,6 +554,7 @@ impl NetStatePlugin {
     pub fn new() -> Self {
         Self {
             blockchain_sender: None,
+            live_cache: tokio::sync::Mutex::new(None),
         }
     }
 
@@ -134,6 +564,7 @@ impl NetStatePlugin {
     ) -> Self {
         Self {
             blockchain_sender: Some(blockchain_sender),
+            live_cache: tokio::sync::Mutex::new(None),
         }
     }
 
@@ -164,8 +595,29 @@ impl NetStatePlugin {
         let ovs_bridges = self.query_ovs_bridges().await?;
         network_interfaces.extend(ovs_bridges);
 
+        // Kernel links (VLAN, bond, veth, VXLAN, GRE, Linux bridge) via rtnetlink
+        match self.query_kernel_links().await {
+            Ok(links) => network_interfaces.extend(links),
+            Err(e) => log::warn!("Failed to query kernel links via rtnetlink: {}", e),
+        }
+
+        // IPv6 addressing, SLAAC toggles and routes via rtnetlink/procfs
+        for iface in &mut network_interfaces {
+            iface.tunable.ipv6 = Self::query_ipv6_config(&iface.name).await;
+        }
+
+        // Routes and rules op-dbus owns, plus its named tables
+        let routing = match self.query_routing().await {
+            Ok(routing) => Some(routing),
+            Err(e) => {
+                log::warn!("Failed to query routes via rtnetlink: {}", e);
+                None
+            }
+        };
+
         Ok(NetworkConfig {
             interfaces: network_interfaces,
+            routing,
         })
     }
 
@@ -212,6 +664,124 @@ impl NetStatePlugin {
         }
     }
 
+    /// Query the routes, policy rules and table names op-dbus manages
+    pub async fn query_routing(&self) -> Result<RoutingConfig> {
+        let tables = rtnetlink_routes::read_table_dropin();
+        let names = TableNames::load(&tables);
+
+        let routes = rtnetlink_routes::list_routes()
+            .await?
+            .into_iter()
+            .filter(Route::is_owned)
+            .map(|route| route.to_spec(&names))
+            .collect();
+        let rules = rtnetlink_routes::list_rules()
+            .await?
+            .into_iter()
+            .filter(Rule::is_owned)
+            .map(|rule| rule.to_spec(&names))
+            .collect();
+
+        Ok(RoutingConfig {
+            tables,
+            routes,
+            rules,
+        })
+    }
+
+    /// Apply a routing action produced by [`RoutingConfig::diff`]
+    async fn apply_routing_action(&self, action: &StateAction) -> Result<String> {
+        // Payloads carry numeric table ids, so no declared names are needed here
+        let tables = TableNames::default();
+        match action {
+            StateAction::Create { resource, config }
+            | StateAction::Modify {
+                resource,
+                changes: config,
+            } => {
+                if resource == ROUTING_TABLES_RESOURCE {
+                    let declared: BTreeMap<String, u32> = serde_json::from_value(config.clone())?;
+                    rtnetlink_routes::write_table_dropin(&declared)?;
+                    return Ok(format!("Updated routing tables: {:?}", declared));
+                }
+                if resource.starts_with("rule:") {
+                    let spec: RuleSpec = serde_json::from_value(config.clone())?;
+                    rtnetlink_routes::add_rule(&Rule::from_spec(&spec, &tables)?).await?;
+                    return Ok(format!("Added policy {}", resource));
+                }
+                let spec: RouteSpec = serde_json::from_value(config.clone())?;
+                rtnetlink_routes::add_route(&Route::from_spec(&spec, &tables)?).await?;
+                Ok(format!("Installed {}", resource))
+            }
+            StateAction::Delete { resource } => {
+                if resource.starts_with("rule:") {
+                    let rules = rtnetlink_routes::list_rules().await?;
+                    if let Some(rule) = rules.iter().find(|r| r.is_owned() && &r.key() == resource)
+                    {
+                        rtnetlink_routes::delete_rule(rule).await?;
+                    }
+                    return Ok(format!("Deleted policy {}", resource));
+                }
+                let routes = rtnetlink_routes::list_routes().await?;
+                if let Some(route) = routes.iter().find(|r| r.is_owned() && &r.key() == resource) {
+                    rtnetlink_routes::delete_route(route).await?;
+                }
+                Ok(format!("Deleted {}", resource))
+            }
+            StateAction::NoOp { resource } => Ok(format!("No change for {}", resource)),
+        }
+    }
+
+    /// Query IPv6 state of an interface; None when the interface has no IPv6 stack
+    async fn query_ipv6_config(ifname: &str) -> Option<Ipv6Config> {
+        let disabled = rtnetlink_helpers::get_ipv6_conf(ifname, "disable_ipv6").ok()?;
+        let mut config = Ipv6Config {
+            enabled: disabled == 0,
+            accept_ra: rtnetlink_helpers::get_ipv6_conf(ifname, "accept_ra")
+                .ok()
+                .map(|v| v > 0),
+            autoconf: rtnetlink_helpers::get_ipv6_conf(ifname, "autoconf")
+                .ok()
+                .map(|v| v > 0),
+            privacy: rtnetlink_helpers::get_ipv6_conf(ifname, "use_tempaddr")
+                .ok()
+                .map(Ipv6Privacy::from_use_tempaddr),
+            ..Default::default()
+        };
+
+        if let Ok(addresses) = rtnetlink_helpers::list_static_ipv6_addresses(ifname).await {
+            config.address = Some(
+                addresses
+                    .into_iter()
+                    .map(|(ip, prefix)| AddressConfig {
+                        ip: ip.to_string(),
+                        prefix,
+                    })
+                    .collect(),
+            );
+        }
+
+        if let Ok(routes) = rtnetlink_helpers::list_ipv6_routes(ifname).await {
+            let mut static_routes = Vec::new();
+            for route in routes {
+                if route.prefix == 0 {
+                    if config.gateway.is_none() {
+                        config.gateway = route.gateway.map(|gw| gw.to_string());
+                    }
+                } else if route.is_static {
+                    static_routes.push(Ipv6RouteConfig {
+                        destination: format!("{}/{}", route.destination, route.prefix),
+                        gateway: route.gateway.map(|gw| gw.to_string()),
+                        metric: route.metric,
+                    });
+                }
+            }
+            config.routes = Some(static_routes);
+        }
+
+        Some(config)
+    }
+
     /// Parse CIDR notation like "192.168.1.100/24" into (ip, prefix)
     fn parse_cidr(cidr: &str) -> Option<(String, u32)> {
         let parts: Vec<&str> = cidr.split('/').collect();
@@ -310,6 +880,15 @@ impl NetStatePlugin {
                 );
             }
 
+            // Port VLANs, bonds, QoS and mirrors
+            let ovs_options = match ovs::query(&client, &bridge_name).await {
+                Ok(options) => Some(options),
+                Err(e) => {
+                    log::debug!("Failed to query OVS settings of {}: {}", bridge_name, e);
+                    None
+                }
+            };
+
             bridges.push(InterfaceConfig {
                 name: bridge_name,
                 if_type: InterfaceType::OvsBridge,
@@ -317,11 +896,13 @@ impl NetStatePlugin {
                 tunable: TunableConfig {
                     ports,
                     l3_driver: None, // Bridges typically don't need L3 config
-                    ipv4: None, // OVS bridges don't have IP config directly
+                    ipv4: None,      // OVS bridges don't have IP config directly
                     ipv6: None,
                     controller: None,
+                    ovs: ovs_options,
                     properties: Some(bridge_info),
                     property_schema: Some(vec!["ovsdb".to_string()]),
+                    ..Default::default()
                 },
             });
         }
@@ -329,6 +910,360 @@ impl NetStatePlugin {
         Ok(bridges)
     }
 
+    /// Query kernel links of the types this plugin manages via rtnetlink
+    pub async fn query_kernel_links(&self) -> Result<Vec<InterfaceConfig>> {
+        let links = rtnetlink_links::list_links().await?;
+        let mut interfaces = Vec::new();
+
+        for link in &links {
+            let Some(if_type) = link
+                .kind
+                .as_deref()
+                .and_then(InterfaceType::from_kernel_kind)
+            else {
+                continue;
+            };
+
+            let addresses = crate::native::rtnetlink_helpers::list_addresses(&link.name)
+                .await
+                .unwrap_or_default();
+            interfaces.push(Self::kernel_link_config(link, if_type, &links, &addresses));
+        }
+
+        Ok(interfaces)
+    }
+
+    /// Build the interface view of a kernel link
+    fn kernel_link_config(
+        link: &KernelLink,
+        if_type: InterfaceType,
+        all_links: &[KernelLink],
+        addresses: &[(std::net::IpAddr, u8)],
+    ) -> InterfaceConfig {
+        let mut tunable = TunableConfig::default();
+        match link.spec.clone() {
+            Some(LinkSpec::Vlan(v)) => tunable.vlan = Some(v),
+            Some(LinkSpec::Bond(b)) => tunable.bond = Some(b),
+            Some(LinkSpec::Veth(v)) => tunable.veth = Some(v),
+            Some(LinkSpec::Vxlan(v)) => tunable.vxlan = Some(v),
+            Some(LinkSpec::Gre(g)) => tunable.gre = Some(g),
+            Some(LinkSpec::Bridge(b)) => tunable.bridge = Some(b),
+            None => {}
+        }
+
+        if if_type == InterfaceType::Bridge {
+            let ports: Vec<String> = all_links
+                .iter()
+                .filter(|l| l.master.as_deref() == Some(link.name.as_str()))
+                .map(|l| l.name.clone())
+                .collect();
+            tunable.ports = Some(ports);
+        }
+
+        let ipv4: Vec<AddressConfig> = addresses
+            .iter()
+            .filter(|(ip, _)| ip.is_ipv4())
+            .map(|(ip, prefix)| AddressConfig {
+                ip: ip.to_string(),
+                prefix: *prefix,
+            })
+            .collect();
+        if !ipv4.is_empty() {
+            tunable.ipv4 = Some(Ipv4Config {
+                enabled: true,
+                dhcp: None,
+                address: Some(ipv4),
+                gateway: None,
+                dns: None,
+            });
+        }
+
+        let mut properties = HashMap::new();
+        properties.insert("ifindex".to_string(), Value::from(link.index));
+        properties.insert("up".to_string(), Value::from(link.up));
+        properties.insert("managed".to_string(), Value::from(link.is_owned()));
+        if let Some(mtu) = link.mtu {
+            properties.insert("mtu".to_string(), Value::from(mtu));
+        }
+        if let Some(mac) = &link.mac {
+            properties.insert("mac_address".to_string(), Value::from(mac.clone()));
+        }
+        if let Some(master) = &link.master {
+            properties.insert("master".to_string(), Value::from(master.clone()));
+        }
+        tunable.properties = Some(properties);
+        tunable.property_schema = Some(vec!["rtnetlink".to_string()]);
+
+        InterfaceConfig {
+            name: link.name.clone(),
+            if_type,
+            driver: Some("kernel".to_string()),
+            tunable,
+        }
+    }
+
+    /// Create or reconcile a kernel link (VLAN, bond, veth, VXLAN, GRE, Linux bridge) via rtnetlink
+    pub async fn apply_kernel_link(&self, config: &InterfaceConfig) -> Result<String> {
+        let spec = config
+            .link_spec()?
+            .context(format!("{} is not a kernel link type", config.name))?;
+        log::info!(
+            "Starting apply_kernel_link for {} ({})",
+            config.name,
+            spec.kind()
+        );
+
+        let outcome = rtnetlink_links::ensure_link(&config.name, &spec).await?;
+
+        // Enslave bond members / bridge ports. Undeclared members are released
+        // only when op-dbus created them; others (VM taps, container veths) stay.
+        let members = match &spec {
+            LinkSpec::Bond(bond) => bond.members.as_ref(),
+            LinkSpec::Bridge(_) => config.tunable.ports.as_ref(),
+            _ => None,
+        };
+        if let Some(members) = members {
+            let links = rtnetlink_links::list_links().await?;
+            for link in &links {
+                if link.master.as_deref() == Some(config.name.as_str())
+                    && link.is_owned()
+                    && !members.contains(&link.name)
+                {
+                    rtnetlink_links::set_master(&link.name, None).await?;
+                    log::info!("Released {} from {}", link.name, config.name);
+                }
+            }
+            for member in members {
+                let current = links.iter().find(|l| &l.name == member);
+                if current.and_then(|l| l.master.as_deref()) == Some(config.name.as_str()) {
+                    continue;
+                }
+                if matches!(spec, LinkSpec::Bond(_)) {
+                    // The kernel only enslaves links that are down
+                    crate::native::rtnetlink_helpers::link_down(member).await?;
+                }
+                rtnetlink_links::set_master(member, Some(&config.name)).await?;
+                let _ = crate::native::rtnetlink_helpers::link_up(member).await;
+                log::info!("Attached {} to {}", member, config.name);
+            }
+        }
+
+        if let Some(mtu) = config.desired_mtu() {
+            rtnetlink_links::set_mtu(&config.name, mtu).await?;
+        }
+
+        if let Err(e) = crate::native::rtnetlink_helpers::link_up(&config.name).await {
+            log::warn!("Failed to bring {} up via netlink: {}", config.name, e);
+        }
+
+        self.apply_ipv4_config(&config.name, &config.tunable.ipv4)
+            .await;
+        self.apply_ipv6_config(&config.name, &config.tunable.ipv6)
+            .await;
+        self.render_l3(config).await?;
+
+        log::info!("Finished apply_kernel_link for {}", config.name);
+        Ok(outcome.to_string())
+    }
+
+    /// Persist an interface's configuration through its L3 renderer
+    async fn render_l3(&self, config: &InterfaceConfig) -> Result<()> {
+        let renderer = l3::renderer_for(config.tunable.l3_driver.as_deref())?;
+        renderer.render(config).await.context(format!(
+            "{} renderer failed for {}",
+            renderer.name(),
+            config.name
+        ))
+    }
+
+    /// Drop whatever any L3 renderer persisted for a removed interface (best-effort).
+    /// The declaration is gone, so its `l3_driver` is unknown: every renderer
+    /// that has something for the interface removes it.
+    async fn remove_l3(&self, ifname: &str) {
+        for renderer in l3::persistent_renderers() {
+            if !matches!(renderer.query(ifname).await, Ok(Some(_))) {
+                continue;
+            }
+            if let Err(e) = renderer.remove(ifname).await {
+                log::warn!(
+                    "Failed to remove {} L3 config for {}: {}",
+                    renderer.name(),
+                    ifname,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Configure IPv4 addresses and default gateway via rtnetlink (best-effort)
+    async fn apply_ipv4_config(&self, ifname: &str, ipv4: &Option<Ipv4Config>) {
+        let Some(ipv4) = ipv4 else {
+            return;
+        };
+        if !ipv4.enabled {
+            return;
+        }
+
+        if let Some(ref addresses) = ipv4.address {
+            for addr in addresses {
+                match crate::native::rtnetlink_helpers::add_ipv4_address(
+                    ifname,
+                    &addr.ip,
+                    addr.prefix,
+                )
+                .await
+                {
+                    Ok(_) => {
+                        log::info!(
+                            "Added IP {}/{} to {} via rtnetlink",
+                            addr.ip,
+                            addr.prefix,
+                            ifname
+                        );
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to add IP {} (may already exist): {}", addr.ip, e);
+                    }
+                }
+            }
+        }
+
+        // Configure gateway if specified via rtnetlink (native netlink)
+        if let Some(ref gateway) = ipv4.gateway {
+            // Delete existing default route (ignore errors)
+            let _ = crate::native::rtnetlink_helpers::del_default_route().await;
+
+            // Add new default route
+            match crate::native::rtnetlink_helpers::add_default_route(ifname, gateway).await {
+                Ok(_) => {
+                    log::info!(
+                        "Added default route via {} on {} via rtnetlink",
+                        gateway,
+                        ifname
+                    );
+                }
+                Err(e) => {
+                    log::warn!("Failed to add default route: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Configure IPv6 addressing, SLAAC/privacy toggles, gateway and routes
+    /// (best-effort, like IPv4). Addresses and routes op-dbus added but no
+    /// longer declared are removed; anything else is left alone.
+    async fn apply_ipv6_config(&self, ifname: &str, ipv6: &Option<Ipv6Config>) {
+        let Some(ipv6) = ipv6 else {
+            return;
+        };
+
+        if let Err(e) =
+            rtnetlink_helpers::set_ipv6_conf(ifname, "disable_ipv6", i64::from(!ipv6.enabled))
+        {
+            log::warn!("Failed to set disable_ipv6 on {}: {}", ifname, e);
+        }
+        if !ipv6.enabled {
+            log::info!("Disabled IPv6 on {}", ifname);
+            return;
+        }
+
+        let toggles = [
+            ("accept_ra", ipv6.accept_ra.map(i64::from)),
+            ("autoconf", ipv6.autoconf.map(i64::from)),
+            ("use_tempaddr", ipv6.privacy.map(|p| p.use_tempaddr())),
+        ];
+        for (key, value) in toggles {
+            if let Some(value) = value {
+                if let Err(e) = rtnetlink_helpers::set_ipv6_conf(ifname, key, value) {
+                    log::warn!("Failed to set {} on {}: {}", key, ifname, e);
+                }
+            }
+        }
+        if ipv6.dhcp == Some(true) {
+            log::warn!(
+                "DHCPv6 requested on {} - no native DHCPv6 client, relying on RA/SLAAC",
+                ifname
+            );
+        }
+
+        if ipv6.address.is_some() {
+            if let Err(e) = self.sync_ipv6_addresses(ifname, ipv6).await {
+                log::warn!("Failed to configure IPv6 addresses on {}: {}", ifname, e);
+            }
+        }
+
+        if let Some(ref gateway) = ipv6.gateway {
+            match rtnetlink_helpers::add_ipv6_default_route(ifname, gateway).await {
+                Ok(()) => log::info!("Set IPv6 default route via {} on {}", gateway, ifname),
+                Err(e) => log::warn!("Failed to set IPv6 default route on {}: {}", ifname, e),
+            }
+        }
+
+        if let Some(ref routes) = ipv6.routes {
+            if let Err(e) = self.sync_ipv6_routes(ifname, routes).await {
+                log::warn!("Failed to configure IPv6 routes on {}: {}", ifname, e);
+            }
+        }
+    }
+
+    /// Add declared IPv6 addresses and remove the op-dbus owned ones that are
+    /// no longer declared
+    async fn sync_ipv6_addresses(&self, ifname: &str, ipv6: &Ipv6Config) -> Result<()> {
+        let desired = ipv6.address_set()?;
+        let present: BTreeSet<(Ipv6Addr, u8)> =
+            rtnetlink_helpers::list_static_ipv6_addresses(ifname)
+                .await?
+                .into_iter()
+                .collect();
+        let owned = rtnetlink_helpers::list_owned_ipv6_addresses(ifname).await?;
+
+        for (ip, prefix) in owned.iter().filter(|addr| !desired.contains(addr)) {
+            match rtnetlink_helpers::del_ipv6_address(ifname, &ip.to_string(), *prefix).await {
+                Ok(()) => log::info!(
+                    "Removed IPv6 {}/{} from {} via rtnetlink",
+                    ip,
+                    prefix,
+                    ifname
+                ),
+                Err(e) => log::warn!("Failed to remove IPv6 {}/{}: {}", ip, prefix, e),
+            }
+        }
+        for (ip, prefix) in desired.difference(&present) {
+            match rtnetlink_helpers::add_ipv6_address(ifname, &ip.to_string(), *prefix).await {
+                Ok(()) => log::info!("Added IPv6 {}/{} to {} via rtnetlink", ip, prefix, ifname),
+                Err(e) => log::warn!("Failed to add IPv6 {}/{}: {}", ip, prefix, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Add declared IPv6 routes and remove the op-dbus owned ones that are no
+    /// longer declared
+    async fn sync_ipv6_routes(&self, ifname: &str, routes: &[Ipv6RouteConfig]) -> Result<()> {
+        let mut declared = Vec::new();
+        for route in routes {
+            let (dest, prefix) = parse_ipv6_cidr(&route.destination)?;
+            let gateway = ipv6_route_gateway(route)?;
+            declared.push((dest, prefix));
+            if let Err(e) =
+                rtnetlink_helpers::add_ipv6_route(ifname, dest, prefix, gateway, route.metric).await
+            {
+                log::warn!("Failed to add IPv6 route {}: {}", route.destination, e);
+            }
+        }
+
+        for existing in rtnetlink_helpers::list_ipv6_routes(ifname).await? {
+            let key = (existing.destination, existing.prefix);
+            if existing.owned && existing.prefix != 0 && !declared.contains(&key) {
+                match rtnetlink_helpers::del_ipv6_route(ifname, key.0, key.1).await {
+                    Ok(()) => log::info!("Removed IPv6 route {}/{} from {}", key.0, key.1, ifname),
+                    Err(e) => log::warn!("Failed to remove IPv6 route {}/{}: {}", key.0, key.1, e),
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Apply OVS bridge configuration via JSON-RPC and rtnetlink
     pub async fn apply_ovs_config(&self, config: &InterfaceConfig) -> Result<()> {
         let client = crate::native::OvsdbClient::new();
@@ -355,6 +1290,13 @@ impl NetStatePlugin {
                 .list_bridge_ports(&config.name)
                 .await
                 .context("Failed to list ports via JSON-RPC")?;
+            // Bond ports are built from their members by the OVS settings below
+            let bonds: Vec<&String> = config
+                .tunable
+                .ovs
+                .iter()
+                .flat_map(|o| o.bond_ports())
+                .collect();
 
             for port in ports {
                 // Skip netmaker/wireguard interfaces - netclient manages them
@@ -366,19 +1308,30 @@ impl NetStatePlugin {
                     continue;
                 }
 
-                if !current_ports.contains(port) {
+                if !current_ports.contains(port) && !bonds.contains(&port) {
                     client.add_port(&config.name, port).await.context(format!(
                         "Failed to add port {} to bridge {} via JSON-RPC",
                         port, config.name
                     ))?;
+                    // Marked so rollback can remove it again
+                    client
+                        .set_port_external_id(port, ovs::OWNER_KEY, "true")
+                        .await?;
                     log::info!("Added port {} to bridge {} via JSON-RPC", port, config.name);
                 }
             }
         }
 
-        // Update /etc/network/interfaces with bridge and IP configuration
-        self.update_interfaces_file(&config.name, None, &config.tunable.ipv4)
-            .await?;
+        // VLAN tags, trunks, bonds, QoS, mirrors and external_ids
+        if let Some(ref options) = config.tunable.ovs {
+            let changed = ovs::apply(&client, &config.name, options).await?;
+            if changed > 0 {
+                log::info!("Updated {} OVS rows for bridge {}", changed, config.name);
+            }
+        }
+
+        // Persist bridge and IP configuration through the selected L3 renderer
+        self.render_l3(config).await?;
 
         // Bring bridge up via rtnetlink (native netlink)
         if let Err(e) = crate::native::rtnetlink_helpers::link_up(&config.name).await {
@@ -386,59 +1339,12 @@ impl NetStatePlugin {
         }
 
         // Configure IPv4 if specified via rtnetlink (native netlink)
-        if let Some(ref ipv4) = config.tunable.ipv4 {
-            if ipv4.enabled {
-                if let Some(ref addresses) = ipv4.address {
-                    for addr in addresses {
-                        match crate::native::rtnetlink_helpers::add_ipv4_address(
-                            &config.name,
-                            &addr.ip,
-                            addr.prefix,
-                        )
-                        .await
-                        {
-                            Ok(_) => {
-                                log::info!(
-                                    "Added IP {}/{} to {} via rtnetlink",
-                                    addr.ip,
-                                    addr.prefix,
-                                    config.name
-                                );
-                            }
-                            Err(e) => {
-                                log::warn!(
-                                    "Failed to add IP {} (may already exist): {}",
-                                    addr.ip,
-                                    e
-                                );
-                            }
-                        }
-                    }
-                }
+        self.apply_ipv4_config(&config.name, &config.tunable.ipv4)
+            .await;
 
-                // Configure gateway if specified via rtnetlink (native netlink)
-                if let Some(ref gateway) = ipv4.gateway {
-                    // Delete existing default route (ignore errors)
-                    let _ = crate::native::rtnetlink_helpers::del_default_route().await;
-
-                    // Add new default route
-                    match crate::native::rtnetlink_helpers::add_default_route(&config.name, gateway)
-                        .await
-                    {
-                        Ok(_) => {
-                            log::info!(
-                                "Added default route via {} on {} via rtnetlink",
-                                gateway,
-                                config.name
-                            );
-                        }
-                        Err(e) => {
-                            log::warn!("Failed to add default route: {}", e);
-                        }
-                    }
-                }
-            }
-        }
+        // Configure IPv6 if specified via rtnetlink (native netlink)
+        self.apply_ipv6_config(&config.name, &config.tunable.ipv6)
+            .await;
 
         log::info!("Finished apply_ovs_config for {}", config.name);
         Ok(())
@@ -452,69 +1358,19 @@ impl NetStatePlugin {
         // Internal ports are created as part of their parent bridge
         // This function handles IP configuration only
 
-        // Determine L3 driver (default to rtnetlink)
-        let l3_driver = config.tunable.l3_driver.as_deref().unwrap_or("rtnetlink");
-
-        if l3_driver == "rtnetlink" {
-            // Bring interface up via native rtnetlink
-            if let Err(e) = crate::native::rtnetlink_helpers::link_up(&config.name).await {
-                log::warn!("Failed to bring port up: {}", e);
-            }
-
-            // Configure IPv4 if specified via rtnetlink
-            if let Some(ref ipv4) = config.tunable.ipv4 {
-                if ipv4.enabled {
-                    if let Some(ref addresses) = ipv4.address {
-                        for addr in addresses {
-                            match crate::native::rtnetlink_helpers::add_ipv4_address(
-                                &config.name,
-                                &addr.ip,
-                                addr.prefix,
-                            )
-                            .await
-                            {
-                                Ok(_) => {
-                                    log::info!(
-                                        "Added IP {}/{} to {} via rtnetlink",
-                                        addr.ip,
-                                        addr.prefix,
-                                        config.name
-                                    );
-                                }
-                                Err(e) => {
-                                    log::warn!(
-                                        "Failed to add IP {} (may already exist): {}",
-                                        addr.ip,
-                                        e
-                                    );
-                                }
-                            }
-                        }
-                    }
+        // Bring interface up via native rtnetlink
+        if let Err(e) = crate::native::rtnetlink_helpers::link_up(&config.name).await {
+            log::warn!("Failed to bring port up: {}", e);
+        }
 
-                    // Configure gateway if specified
-                    if let Some(ref gateway) = ipv4.gateway {
-                        let _ = crate::native::rtnetlink_helpers::del_default_route().await;
-                        match crate::native::rtnetlink_helpers::add_default_route(&config.name, gateway)
-                            .await
-                        {
-                            Ok(_) => {
-                                log::info!("Added default route via {} via rtnetlink", gateway);
-                            }
-                            Err(e) => {
-                                log::warn!("Failed to add default route: {}", e);
-                            }
-                        }
-                    }
-                }
-            }
+        // Configure IPv4 and IPv6 if specified via rtnetlink
+        self.apply_ipv4_config(&config.name, &config.tunable.ipv4)
+            .await;
+        self.apply_ipv6_config(&config.name, &config.tunable.ipv6)
+            .await;
 
-            // Update /etc/network/interfaces for persistence
-            self.update_interfaces_file(&config.name, None, &config.tunable.ipv4)
-                .await?;
-        } else {
-            log::warn!("Unsupported L3 driver '{}' for {}", l3_driver, config.name);
-        }
+        // Persist through the selected L3 renderer
+        self.render_l3(config).await?;
 
         log::info!("Finished apply_ovs_port_config for {}", config.name);
         Ok(())
@@ -531,135 +1387,6 @@ impl NetStatePlugin {
 
         Ok(())
     }
-
-    /// Update /etc/network/interfaces with bridge configuration
-    async fn update_interfaces_file(
-        &self,
-        bridge: &str,
-        uplink: Option<&str>,
-        ipv4: &Option<Ipv4Config>,
-    ) -> Result<()> {
-        let interfaces_path = std::path::Path::new("/etc/network/interfaces");
-        let tag = "op-dbus-managed";
-        let begin_marker = format!("# BEGIN {}\n", tag);
-        let end_marker = format!("# END {}\n", tag);
-
-        // Build the managed block
-        let mut block = String::new();
-        block.push_str(&begin_marker);
-        block.push_str(&format!("# Managed by {}. Do not edit manually.\n\n", tag));
-
-        // OVS Bridge with IP configuration
-        // Use allow-ovs instead of auto to prevent ifupdown hang
-        block.push_str(&format!("allow-ovs {}\n", bridge));
-        block.push_str(&format!("iface {} inet ", bridge));
-
-        if let Some(ref ipv4_cfg) = ipv4 {
-            if ipv4_cfg.enabled {
-                if ipv4_cfg.dhcp == Some(true) {
-                    block.push_str("dhcp\n");
-                } else if let Some(ref addresses) = ipv4_cfg.address {
-                    if let Some(addr) = addresses.first() {
-                        block.push_str("static\n");
-                        block.push_str(&format!("    address {}\n", addr.ip));
-                        block.push_str(&format!(
-                            "    netmask {}\n",
-                            Self::prefix_to_netmask(addr.prefix)
-                        ));
-
-                        if let Some(ref gateway) = ipv4_cfg.gateway {
-                            block.push_str(&format!("    gateway {}\n", gateway));
-                        }
-                    } else {
-                        block.push_str("manual\n");
-                    }
-                } else {
-                    block.push_str("manual\n");
-                }
-            } else {
-                block.push_str("manual\n");
-            }
-        } else {
-            block.push_str("manual\n");
-        }
-
-        block.push_str("    ovs_type OVSBridge\n");
-
-        // Add uplink to ovs_ports if specified
-        if let Some(uplink_iface) = uplink {
-            block.push_str(&format!("    ovs_ports {}\n", uplink_iface));
-        }
-        block.push('\n');
-
-        // Physical uplink (if specified)
-        if let Some(uplink_iface) = uplink {
-            block.push_str(&format!("allow-{} {}\n", bridge, uplink_iface));
-            block.push_str(&format!("iface {} inet manual\n", uplink_iface));
-            block.push_str(&format!("    ovs_bridge {}\n", bridge));
-            block.push_str("    ovs_type OVSPort\n");
-            block.push('\n');
-        }
-
-        block.push_str(&end_marker);
-
-        // Read current file content
-        let content = tokio::fs::read_to_string(interfaces_path)
-            .await
-            .unwrap_or_else(|_| String::from("# network interfaces file\n"));
-
-        // Replace or append the managed block
-        let new_content = Self::replace_block(&content, &begin_marker, &end_marker, &block);
-
-        // Write back if changed
-        if new_content != content {
-            tokio::fs::write(interfaces_path, new_content)
-                .await
-                .context("Failed to write /etc/network/interfaces")?;
-            log::info!("Updated /etc/network/interfaces");
-        }
-
-        Ok(())
-    }
-
-    /// Convert CIDR prefix to netmask string
-    fn prefix_to_netmask(prefix: u8) -> String {
-        let mask: u32 = !0u32 << (32 - prefix);
-        format!(
-            "{}.{}.{}.{}",
-            (mask >> 24) & 0xFF,
-            (mask >> 16) & 0xFF,
-            (mask >> 8) & 0xFF,
-            mask & 0xFF
-        )
-    }
-
-    /// Replace a marked block in text content
-    fn replace_block(
-        content: &str,
-        begin_marker: &str,
-        end_marker: &str,
-        new_block: &str,
-    ) -> String {
-        if let Some(start) = content.find(begin_marker) {
-            if let Some(end) = content[start..].find(end_marker) {
-                let end_idx = start + end + end_marker.len();
-                let mut result = String::with_capacity(content.len() + new_block.len());
-                result.push_str(&content[..start]);
-                result.push_str(new_block);
-                result.push_str(&content[end_idx..]);
-                return result;
-            }
-        }
-
-        // Block not found, append it
-        let mut result = String::with_capacity(content.len() + new_block.len() + 1);
-        result.push_str(content);
-        if !content.ends_with('\n') {
-            result.push('\n');
-        }
-        result.push_str(new_block);
-        result
-    }
 }
 
 impl Default for NetStatePlugin {
@@ -687,9 +1414,31 @@ impl StatePlugin for NetStatePlugin {
     }
 
     async fn query_current_state(&self) -> Result<Value> {
+        // Served from cache while the netlink monitor has seen no change
+        let monitor = rtnetlink_monitor::get().filter(|m| m.is_ready());
+        let generation = match &monitor {
+            Some(monitor) => Some(monitor.generation().await),
+            None => None,
+        };
+        if let Some(generation) = generation {
+            let cache = self.live_cache.lock().await;
+            if let Some(cached) = cache.as_ref().filter(|c| c.generation == generation) {
+                return Ok(cached.state.clone());
+            }
+        }
+
         // Query current OVS state via D-Bus exclusively
         let network_config = self.query_current_state_dbus().await?;
-        Ok(serde_json::to_value(network_config)?)
+        let state = serde_json::to_value(network_config)?;
+
+        // Keyed by the generation read before querying, so a change mid-query forces a refresh
+        if let Some(generation) = generation {
+            *self.live_cache.lock().await = Some(LiveCache {
+                generation,
+                state: state.clone(),
+            });
+        }
+        Ok(state)
     }
 
     async fn calculate_diff(&self, current: &Value, desired: &Value) -> Result<StateDiff> {
@@ -714,8 +1463,52 @@ impl StatePlugin for NetStatePlugin {
         // Find interfaces to create or modify
         for (name, desired_iface) in &desired_map {
             if let Some(current_iface) = current_map.get(name) {
+                // Kernel links compare only declared attributes
+                let in_sync = if desired_iface.if_type.is_kernel_link() {
+                    desired_iface.kernel_link_in_sync(current_iface)
+                } else {
+                    // IPv6 is compared on declared attributes only
+                    let ipv6_in_sync = desired_iface
+                        .tunable
+                        .ipv6
+                        .as_ref()
+                        .is_none_or(|ipv6| ipv6.in_sync(current_iface.tunable.ipv6.as_ref()));
+                    // OVS settings likewise, per declared port and section
+                    let ovs_in_sync = desired_iface
+                        .tunable
+                        .ovs
+                        .as_ref()
+                        .is_none_or(|ovs| ovs.in_sync(current_iface.tunable.ovs.as_ref()));
+                    let mut current_rest = (*current_iface).clone();
+                    let mut desired_rest = (*desired_iface).clone();
+                    current_rest.tunable.ipv6 = None;
+                    desired_rest.tunable.ipv6 = None;
+                    current_rest.tunable.ovs = None;
+                    desired_rest.tunable.ovs = None;
+                    // The driver is a rendering choice, checked against the backend below
+                    current_rest.tunable.l3_driver = None;
+                    desired_rest.tunable.l3_driver = None;
+                    ipv6_in_sync
+                        && ovs_in_sync
+                        && serde_json::to_value(current_rest)?
+                            == serde_json::to_value(desired_rest)?
+                };
+                // Persisted configuration must match too, or a reboot reverts it
+                let in_sync = in_sync && {
+                    let renderer = l3::renderer_for(desired_iface.tunable.l3_driver.as_deref())?;
+                    let persisted = renderer.query(name).await.unwrap_or_else(|e| {
+                        log::warn!(
+                            "Failed to query {} config for {}: {}",
+                            renderer.name(),
+                            name,
+                            e
+                        );
+                        None
+                    });
+                    renderer.in_sync(desired_iface, persisted.as_ref())
+                };
                 // Check if modification needed
-                if serde_json::to_value(current_iface)? != serde_json::to_value(desired_iface)? {
+                if !in_sync {
                     actions.push(StateAction::Modify {
                         resource: (*name).clone(),
                         changes: serde_json::to_value(desired_iface)?,
@@ -730,14 +1523,24 @@ impl StatePlugin for NetStatePlugin {
         }
 
         // Find interfaces to delete
-        for name in current_map.keys() {
+        for (name, current_iface) in &current_map {
             if !desired_map.contains_key(name) {
+                // Never delete kernel links op-dbus didn't create (container veths, docker bridges, ...)
+                if current_iface.if_type.is_kernel_link() && !current_iface.is_owned_link() {
+                    continue;
+                }
                 actions.push(StateAction::Delete {
                     resource: (*name).clone(),
                 });
             }
         }
 
+        // Routing is only managed when declared
+        if let Some(routing) = &desired_config.routing {
+            let current_routing = current_config.routing.clone().unwrap_or_default();
+            actions.extend(routing.diff(&current_routing)?);
+        }
+
         Ok(StateDiff {
             plugin: self.name().to_string(),
             actions,
@@ -750,10 +1553,29 @@ impl StatePlugin for NetStatePlugin {
     }
 
     async fn apply_state(&self, diff: &StateDiff) -> Result<ApplyResult> {
+        // OVSDB and file changes aren't visible to the netlink monitor
+        *self.live_cache.lock().await = None;
+
         let mut changes_applied = Vec::new();
         let mut errors = Vec::new();
 
         for action in &diff.actions {
+            // Routes, rules and tables use ':' in their resource names, which
+            // interface names can't contain
+            let resource = match action {
+                StateAction::Create { resource, .. }
+                | StateAction::Modify { resource, .. }
+                | StateAction::Delete { resource }
+                | StateAction::NoOp { resource } => resource,
+            };
+            if resource.contains(':') {
+                match self.apply_routing_action(action).await {
+                    Ok(change) => changes_applied.push(change),
+                    Err(e) => errors.push(format!("Failed to apply {}: {}", resource, e)),
+                }
+                continue;
+            }
+
             match action {
                 StateAction::Create { resource, config }
                 | StateAction::Modify {
@@ -762,6 +1584,20 @@ impl StatePlugin for NetStatePlugin {
                 } => {
                     let iface_config: InterfaceConfig = serde_json::from_value(config.clone())?;
 
+                    if iface_config.if_type.is_kernel_link() {
+                        match self.apply_kernel_link(&iface_config).await {
+                            Ok(outcome) => changes_applied.push(format!(
+                                "Applied {:?} link {} ({})",
+                                iface_config.if_type, resource, outcome
+                            )),
+                            Err(e) => errors.push(format!(
+                                "Failed to apply {:?} link {}: {}",
+                                iface_config.if_type, resource, e
+                            )),
+                        }
+                        continue;
+                    }
+
                     match self.apply_ovs_config(&iface_config).await {
                         Ok(_) => {
                             changes_applied.push(format!("Applied OVS config for: {}", resource));
@@ -775,6 +1611,24 @@ impl StatePlugin for NetStatePlugin {
                     }
                 }
                 StateAction::Delete { resource } => {
+                    self.remove_l3(resource).await;
+
+                    // Kernel links op-dbus created are removed via rtnetlink
+                    let kernel_link = rtnetlink_links::get_link(resource).await.ok().flatten();
+                    if let Some(link) = kernel_link.filter(KernelLink::is_owned) {
+                        match rtnetlink_links::delete_link(&link.name).await {
+                            Ok(_) => changes_applied.push(format!(
+                                "Deleted {} link: {}",
+                                link.kind.as_deref().unwrap_or("kernel"),
+                                resource
+                            )),
+                            Err(e) => {
+                                errors.push(format!("Failed to delete link {}: {}", resource, e))
+                            }
+                        }
+                        continue;
+                    }
+
                     // Delete OVS bridge via D-Bus
                     if resource.starts_with("ovsbr") || resource.starts_with("br") {
                         match self.delete_ovs_bridge(resource).await {
@@ -796,6 +1650,8 @@ impl StatePlugin for NetStatePlugin {
             }
         }
 
+        *self.live_cache.lock().await = None;
+
         Ok(ApplyResult {
             success: errors.is_empty(),
             changes_applied,
@@ -834,25 +1690,103 @@ impl StatePlugin for NetStatePlugin {
         })
     }
 
+    async fn create_checkpoint_for(&self, diff: &StateDiff) -> Result<Checkpoint> {
+        let mut checkpoint = self.create_checkpoint().await?;
+        // Interfaces the diff touches; rollback restores those and owned links only
+        let resources: Vec<&str> = diff
+            .actions
+            .iter()
+            .filter_map(|action| match action {
+                StateAction::Create { resource, .. }
+                | StateAction::Modify { resource, .. }
+                | StateAction::Delete { resource } => Some(resource.as_str()),
+                StateAction::NoOp { .. } => None,
+            })
+            .collect();
+        checkpoint.backend_checkpoint = Some(serde_json::json!({ "resources": resources }));
+        Ok(checkpoint)
+    }
+
     async fn rollback(&self, checkpoint: &Checkpoint) -> Result<()> {
+        *self.live_cache.lock().await = None;
         let old_config: NetworkConfig = serde_json::from_value(checkpoint.state_snapshot.clone())?;
+        let touched: Vec<String> = checkpoint
+            .backend_checkpoint
+            .as_ref()
+            .and_then(|saved| serde_json::from_value(saved["resources"].clone()).ok())
+            .unwrap_or_default();
+
+        // Remove kernel links op-dbus created after the checkpoint
+        let links = rtnetlink_links::list_links().await.unwrap_or_default();
+        for link in &links {
+            let existed = old_config.interfaces.iter().any(|i| i.name == link.name);
+            if link.is_owned() && !existed {
+                rtnetlink_links::delete_link(&link.name).await?;
+            }
+        }
 
         // Restore old OVS configuration via D-Bus
         for iface in &old_config.interfaces {
             match iface.if_type {
                 InterfaceType::OvsBridge => {
+                    // Drop ports op-dbus added after the checkpoint
+                    let client = crate::native::OvsdbClient::new();
+                    let kept = iface.tunable.ports.as_deref().unwrap_or_default();
+                    for port in ovs::owned_ports(&client, &iface.name)
+                        .await
+                        .unwrap_or_default()
+                    {
+                        if !kept.contains(&port) {
+                            client.delete_port(&iface.name, &port).await?;
+                        }
+                    }
                     self.apply_ovs_config(iface).await?;
                 }
                 InterfaceType::OvsPort => {
                     self.apply_ovs_port_config(iface).await?;
                 }
-                _ => {}
+                InterfaceType::Ethernet => {}
+                _ => {
+                    // The checkpoint holds every kernel link on the host; only
+                    // restore links op-dbus owns or the undone diff touched, and
+                    // never re-create one that op-dbus did not create
+                    let owned = iface.is_owned_link();
+                    let exists = links.iter().any(|link| link.name == iface.name);
+                    if owned || (exists && touched.contains(&iface.name)) {
+                        // Members that went away since (e.g. container veths) stay gone
+                        let present = |name: &String| links.iter().any(|link| &link.name == name);
+                        let mut iface = iface.clone();
+                        if let Some(ports) = &mut iface.tunable.ports {
+                            ports.retain(present);
+                        }
+                        if let Some(members) =
+                            iface.tunable.bond.as_mut().and_then(|b| b.members.as_mut())
+                        {
+                            members.retain(present);
+                        }
+                        self.apply_kernel_link(&iface).await?;
+                    }
+                }
+            }
+        }
+
+        // Restore owned routes, rules and table names
+        if let Some(old_routing) = &old_config.routing {
+            let current_routing = self.query_routing().await?;
+            for action in old_routing.diff(&current_routing)? {
+                self.apply_routing_action(&action).await?;
             }
         }
 
         Ok(())
     }
 
+    fn owned_paths(&self) -> Vec<std::path::PathBuf> {
+        let mut paths = l3::persisted_paths();
+        paths.push(rtnetlink_routes::RT_TABLES_DROPIN.into());
+        paths
+    }
+
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities {
             supports_rollback: true,
@@ -868,3 +1802,127 @@ impl StatePlugin for NetStatePlugin {
 //         Self::new()
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv6(value: Value) -> Ipv6Config {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_bridge_ports_in_sync_checks_declared_only() {
+        let iface = |ports: &[&str]| -> InterfaceConfig {
+            serde_json::from_value(serde_json::json!({
+                "name": "vmbr0",
+                "type": "bridge",
+                "ports": ports
+            }))
+            .unwrap()
+        };
+        // VM taps and container veths joined by others are not drift
+        let current = iface(&["eno1", "tap100i0", "veth101i0"]);
+        assert!(iface(&["eno1"]).kernel_link_in_sync(&current));
+        assert!(!iface(&["eno1", "eno2"]).kernel_link_in_sync(&current));
+    }
+
+    #[test]
+    fn test_parse_ipv6_cidr() {
+        assert_eq!(
+            parse_ipv6_cidr("2001:db8::/48").unwrap(),
+            ("2001:db8::".parse().unwrap(), 48)
+        );
+        assert!(parse_ipv6_cidr("2001:db8::").is_err());
+        assert!(parse_ipv6_cidr("2001:db8::/129").is_err());
+    }
+
+    #[test]
+    fn test_ipv6_in_sync_ignores_undeclared_toggles() {
+        let current = ipv6(serde_json::json!({
+            "enabled": true,
+            "address": [{"ip": "2001:db8:0:1::10", "prefix": 64}],
+            "accept_ra": false,
+            "autoconf": true,
+            "privacy": "prefer-temporary",
+            "routes": []
+        }));
+
+        // Address spelled differently but equal once parsed
+        let desired = ipv6(serde_json::json!({
+            "enabled": true,
+            "address": [{"ip": "2001:0db8:0:1:0:0:0:10", "prefix": 64}],
+            "accept_ra": false
+        }));
+        assert!(desired.in_sync(Some(&current)));
+
+        let desired = ipv6(serde_json::json!({"enabled": true, "privacy": "disabled"}));
+        assert!(!desired.in_sync(Some(&current)));
+
+        let desired = ipv6(serde_json::json!({
+            "enabled": true,
+            "routes": [{"destination": "2001:db8:100::/48", "gateway": "fe80::1"}]
+        }));
+        assert!(!desired.in_sync(Some(&current)));
+    }
+
+    #[test]
+    fn test_inet6_stanza() {
+        let config = ipv6(serde_json::json!({
+            "enabled": true,
+            "address": [{"ip": "2001:db8::2", "prefix": 64}],
+            "gateway": "2001:db8::1",
+            "accept_ra": false,
+            "privacy": "prefer-public"
+        }));
+        let stanza = l3::render_inet6_stanza("vmbr0", &config);
+        assert!(stanza.contains("iface vmbr0 inet6 static\n    address 2001:db8::2/64\n"));
+        assert!(stanza.contains("    gateway 2001:db8::1\n"));
+        assert!(stanza.contains("    accept_ra 0\n"));
+        assert!(stanza.contains("    privext 1\n"));
+    }
+
+    #[test]
+    fn test_routing_diff_prunes_only_owned_entries() {
+        let desired: RoutingConfig = serde_json::from_value(serde_json::json!({
+            "tables": {"wg-egress": 100},
+            "routes": [
+                {"destination": "default", "gateway": "10.66.0.1", "dev": "wg0", "table": "wg-egress"},
+                {"destination": "10.20.0.0/16", "dev": "br0"}
+            ],
+            "rules": [{"priority": 1000, "fwmark": 102, "table": "wg-egress"}]
+        }))
+        .unwrap();
+
+        // Current state only ever lists owned entries
+        let current: RoutingConfig = serde_json::from_value(serde_json::json!({
+            "tables": {"wg-egress": 100},
+            "routes": [
+                {"destination": "default", "gateway": "10.66.0.1", "dev": "wg0", "table": "wg-egress", "scope": "global"},
+                {"destination": "172.16.0.0/12", "gateway": "10.0.0.1", "dev": "eth0", "scope": "global"}
+            ],
+            "rules": [{"priority": 1000, "fwmark": 102, "table": "wg-egress"}]
+        }))
+        .unwrap();
+
+        let actions = desired.diff(&current).unwrap();
+        let summary: Vec<String> = actions
+            .iter()
+            .map(|a| match a {
+                StateAction::Create { resource, .. } => format!("create {}", resource),
+                StateAction::Modify { resource, .. } => format!("modify {}", resource),
+                StateAction::Delete { resource } => format!("delete {}", resource),
+                StateAction::NoOp { resource } => format!("noop {}", resource),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                "create route:254:10.20.0.0/16".to_string(),
+                "delete route:254:172.16.0.0/12".to_string(),
+            ]
+        );
+
+        assert!(desired.diff(&desired).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1527 @@
+// L3 renderers - persist interface addressing for the host's network stack
+// Selected per interface by `l3_driver`: ifupdown, networkd, networkmanager or netlink.
+// Runtime state is always applied via rtnetlink by the net plugin; a renderer only
+// makes it survive reboots (and, for networkd/NetworkManager, hands it to the daemon).
+use super::{AddressConfig, InterfaceConfig, InterfaceType, Ipv4Config, Ipv6Config, Ipv6Privacy};
+use crate::native::rtnetlink_links::{BondMode, LinkSpec};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+use zbus::{Connection, Proxy};
+
+/// Environment override for interfaces that don't set `l3_driver`
+pub const L3_DRIVER_ENV: &str = "OPDBUS_L3_DRIVER";
+
+const INTERFACES_PATH: &str = "/etc/network/interfaces";
+const NETWORKD_DIR: &str = "/etc/systemd/network";
+const NETWORKD_PREFIX: &str = "50-op-dbus-";
+const MANAGED_TAG: &str = "op-dbus-managed";
+const NM_CONNECTION_PREFIX: &str = "op-dbus-";
+const NM_CONNECTIONS_DIR: &str = "/etc/NetworkManager/system-connections";
+
+/// Files the L3 renderers write
+pub fn persisted_paths() -> Vec<PathBuf> {
+    vec![
+        PathBuf::from(INTERFACES_PATH),
+        PathBuf::from(NETWORKD_DIR),
+        PathBuf::from(NM_CONNECTIONS_DIR),
+    ]
+}
+
+/// L3 configuration as persisted by a backend
+#[derive(Debug, Clone, Default)]
+pub struct L3Config {
+    pub ipv4: Option<Ipv4Config>,
+    pub ipv6: Option<Ipv6Config>,
+}
+
+#[async_trait]
+pub trait L3Renderer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Persisted configuration for an interface; None if nothing was rendered
+    async fn query(&self, ifname: &str) -> Result<Option<L3Config>>;
+
+    /// Whether the persisted configuration matches the declaration
+    fn in_sync(&self, desired: &InterfaceConfig, persisted: Option<&L3Config>) -> bool {
+        l3_in_sync(desired, persisted, true)
+    }
+
+    /// Persist (and, where the backend owns the device, activate) the configuration
+    async fn render(&self, config: &InterfaceConfig) -> Result<()>;
+
+    /// Remove whatever was rendered for an interface
+    async fn remove(&self, ifname: &str) -> Result<()>;
+}
+
+/// Resolve the renderer for an interface: explicit `l3_driver`, then
+/// OPDBUS_L3_DRIVER, then whatever the host runs.
+pub fn renderer_for(driver: Option<&str>) -> Result<Box<dyn L3Renderer>> {
+    let driver = match driver {
+        Some(driver) => driver.to_string(),
+        None => std::env::var(L3_DRIVER_ENV).unwrap_or_else(|_| detect_driver().to_string()),
+    };
+
+    Ok(match driver.as_str() {
+        "ifupdown" => Box::new(IfupdownRenderer::new(INTERFACES_PATH)),
+        "networkd" => Box::new(NetworkdRenderer::new(NETWORKD_DIR)),
+        "networkmanager" | "nm" => Box::new(NetworkManagerRenderer),
+        "netlink" => Box::new(NetlinkRenderer),
+        // The old default: runtime via rtnetlink, persisted by whatever the host runs
+        "rtnetlink" => return renderer_for(Some(detect_driver())),
+        other => bail!(
+            "Unknown l3_driver '{}' (expected ifupdown, networkd, networkmanager or netlink)",
+            other
+        ),
+    })
+}
+
+/// Every renderer that persists something, for cleaning up after interfaces
+/// whose declaration (and `l3_driver`) is gone
+pub fn persistent_renderers() -> Vec<Box<dyn L3Renderer>> {
+    vec![
+        Box::new(IfupdownRenderer::new(INTERFACES_PATH)),
+        Box::new(NetworkdRenderer::new(NETWORKD_DIR)),
+        Box::new(NetworkManagerRenderer),
+    ]
+}
+
+/// Pick a backend from what is installed and running
+fn detect_driver() -> &'static str {
+    if Path::new(INTERFACES_PATH).exists() {
+        "ifupdown"
+    } else if Path::new("/run/systemd/netif/state").exists() {
+        "networkd"
+    } else if Path::new("/run/NetworkManager").exists() {
+        "networkmanager"
+    } else {
+        "netlink"
+    }
+}
+
+fn address_set(addresses: &Option<Vec<AddressConfig>>) -> BTreeSet<(String, u8)> {
+    addresses
+        .iter()
+        .flatten()
+        .map(|a| {
+            // Normalize spelling (e.g. IPv6 zero compression)
+            let ip =
+                a.ip.parse::<std::net::IpAddr>()
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_else(|_| a.ip.clone());
+            (ip, a.prefix)
+        })
+        .collect()
+}
+
+/// Whether IPv4 has anything to persist. An enabled config without addresses
+/// or DHCP renders the same as a disabled one (ifupdown `manual`, no networkd
+/// `Address=`), so it is compared as disabled.
+fn ipv4_configured(ipv4: &Ipv4Config) -> bool {
+    ipv4.enabled
+        && (ipv4.dhcp == Some(true) || !ipv4.address.as_deref().unwrap_or_default().is_empty())
+}
+
+/// Compare declared addressing with what a backend persisted.
+/// `ra_toggles` is false for backends that can't express accept_ra/autoconf.
+pub fn l3_in_sync(
+    desired: &InterfaceConfig,
+    persisted: Option<&L3Config>,
+    ra_toggles: bool,
+) -> bool {
+    let empty = L3Config::default();
+    let persisted = persisted.unwrap_or(&empty);
+
+    if let Some(want) = &desired.tunable.ipv4 {
+        let have = persisted.ipv4.as_ref();
+        let enabled = have.is_some_and(|h| h.enabled);
+        if ipv4_configured(want) != enabled {
+            return false;
+        }
+        if enabled {
+            let have = have.expect("enabled implies present");
+            if want.dhcp.unwrap_or(false) != have.dhcp.unwrap_or(false)
+                || address_set(&want.address) != address_set(&have.address)
+                || want.gateway != have.gateway
+            {
+                return false;
+            }
+        }
+    }
+
+    if let Some(want) = &desired.tunable.ipv6 {
+        let Some(have) = persisted.ipv6.as_ref() else {
+            return !want.enabled;
+        };
+        if want.enabled != have.enabled {
+            return false;
+        }
+        if !want.enabled {
+            return true;
+        }
+        if want.address.is_some() && address_set(&want.address) != address_set(&have.address) {
+            return false;
+        }
+        if want.gateway.is_some() && want.gateway != have.gateway {
+            return false;
+        }
+        if want.privacy.is_some() && want.privacy != have.privacy {
+            return false;
+        }
+        if ra_toggles
+            && ((want.accept_ra.is_some() && want.accept_ra != have.accept_ra)
+                || (want.autoconf.is_some() && want.autoconf != have.autoconf))
+        {
+            return false;
+        }
+    }
+
+    true
+}
+
+// ---------------------------------------------------------------------------
+// netlink-only
+// ---------------------------------------------------------------------------
+
+/// Runtime configuration only; nothing survives a reboot
+pub struct NetlinkRenderer;
+
+#[async_trait]
+impl L3Renderer for NetlinkRenderer {
+    fn name(&self) -> &'static str {
+        "netlink"
+    }
+
+    async fn query(&self, _ifname: &str) -> Result<Option<L3Config>> {
+        Ok(None)
+    }
+
+    fn in_sync(&self, _desired: &InterfaceConfig, _persisted: Option<&L3Config>) -> bool {
+        true
+    }
+
+    async fn render(&self, _config: &InterfaceConfig) -> Result<()> {
+        Ok(())
+    }
+
+    async fn remove(&self, _ifname: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// ifupdown (/etc/network/interfaces)
+// ---------------------------------------------------------------------------
+
+/// Debian/Proxmox ifupdown: one marked block per interface
+pub struct IfupdownRenderer {
+    path: PathBuf,
+}
+
+impl IfupdownRenderer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn markers(ifname: &str) -> (String, String) {
+        (
+            format!("# BEGIN {} {}\n", MANAGED_TAG, ifname),
+            format!("# END {} {}\n", MANAGED_TAG, ifname),
+        )
+    }
+
+    /// Render the managed block for an interface; OVS internal ports need
+    /// the bridge they are attached to
+    pub fn render_block(config: &InterfaceConfig, ovs_bridge: Option<&str>) -> String {
+        let name = &config.name;
+        let (begin_marker, end_marker) = Self::markers(name);
+        let mut block = begin_marker;
+        block.push_str(&format!(
+            "# Managed by {}. Do not edit manually.\n\n",
+            MANAGED_TAG
+        ));
+
+        // Use allow-ovs instead of auto for OVS bridges to prevent ifupdown hang
+        match (&config.if_type, ovs_bridge) {
+            (InterfaceType::OvsBridge, _) => block.push_str(&format!("allow-ovs {}\n", name)),
+            (InterfaceType::OvsPort, Some(bridge)) => {
+                block.push_str(&format!("allow-{} {}\n", bridge, name))
+            }
+            _ => block.push_str(&format!("auto {}\n", name)),
+        }
+        block.push_str(&format!("iface {} inet ", name));
+
+        match &config.tunable.ipv4 {
+            Some(ipv4) if ipv4.enabled && ipv4.dhcp == Some(true) => block.push_str("dhcp\n"),
+            Some(ipv4)
+                if ipv4.enabled && !ipv4.address.as_deref().unwrap_or_default().is_empty() =>
+            {
+                let addresses = ipv4.address.as_deref().unwrap_or_default();
+                let first = &addresses[0];
+                block.push_str("static\n");
+                block.push_str(&format!("    address {}\n", first.ip));
+                block.push_str(&format!(
+                    "    netmask {}\n",
+                    prefix_to_netmask(first.prefix)
+                ));
+                if let Some(ref gateway) = ipv4.gateway {
+                    block.push_str(&format!("    gateway {}\n", gateway));
+                }
+                for addr in &addresses[1..] {
+                    block.push_str(&format!(
+                        "    up ip addr add {}/{} dev {}\n",
+                        addr.ip, addr.prefix, name
+                    ));
+                }
+            }
+            _ => block.push_str("manual\n"),
+        }
+
+        match config.link_spec().ok().flatten() {
+            Some(LinkSpec::Vlan(vlan)) => {
+                block.push_str(&format!("    vlan-raw-device {}\n", vlan.parent));
+            }
+            Some(LinkSpec::Bond(bond)) => {
+                let members = bond.members.as_deref().unwrap_or_default();
+                block.push_str(&format!(
+                    "    bond-slaves {}\n",
+                    if members.is_empty() {
+                        "none".to_string()
+                    } else {
+                        members.join(" ")
+                    }
+                ));
+                if let Some(mode) = bond.mode {
+                    block.push_str(&format!("    bond-mode {}\n", bond_mode_name(mode)));
+                }
+                if let Some(miimon) = bond.miimon {
+                    block.push_str(&format!("    bond-miimon {}\n", miimon));
+                }
+            }
+            Some(LinkSpec::Bridge(bridge)) => {
+                let ports = config.tunable.ports.as_deref().unwrap_or_default();
+                block.push_str(&format!(
+                    "    bridge-ports {}\n",
+                    if ports.is_empty() {
+                        "none".to_string()
+                    } else {
+                        ports.join(" ")
+                    }
+                ));
+                if let Some(stp) = bridge.stp {
+                    block.push_str(&format!(
+                        "    bridge-stp {}\n",
+                        if stp { "on" } else { "off" }
+                    ));
+                }
+                if let Some(fd) = bridge.forward_delay {
+                    block.push_str(&format!("    bridge-fd {}\n", fd));
+                }
+                if bridge.vlan_filtering == Some(true) {
+                    block.push_str("    bridge-vlan-aware yes\n");
+                }
+            }
+            _ => {}
+        }
+        match (&config.if_type, ovs_bridge) {
+            (InterfaceType::OvsBridge, _) => block.push_str("    ovs_type OVSBridge\n"),
+            (InterfaceType::OvsPort, Some(bridge)) => {
+                block.push_str("    ovs_type OVSIntPort\n");
+                block.push_str(&format!("    ovs_bridge {}\n", bridge));
+            }
+            _ => {}
+        }
+
+        if let Some(ref ipv6) = config.tunable.ipv6 {
+            block.push_str(&render_inet6_stanza(name, ipv6));
+        }
+
+        block.push('\n');
+        block.push_str(&end_marker);
+        block
+    }
+
+    /// Parse a managed block back into L3 configuration
+    pub fn parse_block(block: &str, ifname: &str) -> L3Config {
+        let mut config = L3Config::default();
+        let mut family = "";
+        let inet = format!("iface {} inet ", ifname);
+        let inet6 = format!("iface {} inet6 ", ifname);
+
+        for line in block.lines().map(str::trim) {
+            if let Some(method) = line.strip_prefix(&inet) {
+                family = "inet";
+                config.ipv4 = Some(Ipv4Config {
+                    enabled: method != "manual",
+                    dhcp: (method == "dhcp").then_some(true),
+                    address: None,
+                    gateway: None,
+                    dns: None,
+                });
+                continue;
+            }
+            if let Some(method) = line.strip_prefix(&inet6) {
+                family = "inet6";
+                config.ipv6 = Some(Ipv6Config {
+                    enabled: true,
+                    dhcp: (method == "dhcp").then_some(true),
+                    autoconf: (method == "auto").then_some(true),
+                    ..Default::default()
+                });
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let (Some(key), Some(value)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            match family {
+                "inet" => {
+                    let Some(ipv4) = config.ipv4.as_mut() else {
+                        continue;
+                    };
+                    match key {
+                        "address" => {
+                            let (ip, prefix) = split_cidr(value, 32);
+                            ipv4.address
+                                .get_or_insert_with(Vec::new)
+                                .push(AddressConfig { ip, prefix });
+                        }
+                        "netmask" => {
+                            if let Some(addr) = ipv4.address.as_mut().and_then(|a| a.first_mut()) {
+                                addr.prefix = netmask_to_prefix(value);
+                            }
+                        }
+                        "gateway" => ipv4.gateway = Some(value.to_string()),
+                        "up" if value == "ip" => {
+                            // up ip addr add <cidr> dev <iface>
+                            if let Some(cidr) = parts.nth(2) {
+                                let (ip, prefix) = split_cidr(cidr, 32);
+                                ipv4.address
+                                    .get_or_insert_with(Vec::new)
+                                    .push(AddressConfig { ip, prefix });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                "inet6" => {
+                    let Some(ipv6) = config.ipv6.as_mut() else {
+                        continue;
+                    };
+                    match key {
+                        "address" => {
+                            let (ip, prefix) = split_cidr(value, 128);
+                            ipv6.address
+                                .get_or_insert_with(Vec::new)
+                                .push(AddressConfig { ip, prefix });
+                        }
+                        "gateway" => ipv6.gateway = Some(value.to_string()),
+                        "accept_ra" => ipv6.accept_ra = Some(value != "0"),
+                        "autoconf" => ipv6.autoconf = Some(value != "0"),
+                        "privext" => {
+                            ipv6.privacy = value.parse().ok().map(Ipv6Privacy::from_use_tempaddr)
+                        }
+                        "up" if value == "ip" => {
+                            // up ip -6 addr add <cidr> dev <iface>
+                            if let Some(cidr) = parts.nth(3) {
+                                let (ip, prefix) = split_cidr(cidr, 128);
+                                ipv6.address
+                                    .get_or_insert_with(Vec::new)
+                                    .push(AddressConfig { ip, prefix });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        config
+    }
+
+    async fn read(&self) -> String {
+        tokio::fs::read_to_string(&self.path)
+            .await
+            .unwrap_or_else(|_| String::from("# network interfaces file\n"))
+    }
+
+    async fn write(&self, content: &str) -> Result<()> {
+        tokio::fs::write(&self.path, content)
+            .await
+            .context(format!("Failed to write {}", self.path.display()))?;
+        log::info!("Updated {}", self.path.display());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl L3Renderer for IfupdownRenderer {
+    fn name(&self) -> &'static str {
+        "ifupdown"
+    }
+
+    async fn query(&self, ifname: &str) -> Result<Option<L3Config>> {
+        let content = self.read().await;
+        let (begin, end) = Self::markers(ifname);
+        Ok(find_block(&content, &begin, &end).map(|block| Self::parse_block(block, ifname)))
+    }
+
+    async fn render(&self, config: &InterfaceConfig) -> Result<()> {
+        let content = self.read().await;
+        let (begin, end) = Self::markers(&config.name);
+        let bridge = match config.if_type {
+            InterfaceType::OvsPort => Some(
+                ovs_bridge_of(&config.name)
+                    .await?
+                    .with_context(|| format!("{} is not attached to an OVS bridge", config.name))?,
+            ),
+            _ => None,
+        };
+        let block = Self::render_block(config, bridge.as_deref());
+
+        // Drop the single-block layout older releases wrote for this interface
+        let mut new_content = content.clone();
+        let legacy = (
+            format!("# BEGIN {}\n", MANAGED_TAG),
+            format!("# END {}\n", MANAGED_TAG),
+        );
+        if find_block(&content, &legacy.0, &legacy.1)
+            .is_some_and(|b| b.contains(&format!("iface {} inet", config.name)))
+        {
+            new_content = replace_block(&new_content, &legacy.0, &legacy.1, "");
+        }
+        new_content = replace_block(&new_content, &begin, &end, &block);
+
+        if new_content != content {
+            self.write(&new_content).await?;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, ifname: &str) -> Result<()> {
+        let content = self.read().await;
+        let (begin, end) = Self::markers(ifname);
+        if find_block(&content, &begin, &end).is_some() {
+            self.write(&replace_block(&content, &begin, &end, ""))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Bridge an OVS port belongs to
+async fn ovs_bridge_of(port: &str) -> Result<Option<String>> {
+    let client = crate::native::OvsdbClient::new();
+    for bridge in client.list_bridges().await? {
+        if client
+            .list_bridge_ports(&bridge)
+            .await?
+            .iter()
+            .any(|p| p == port)
+        {
+            return Ok(Some(bridge));
+        }
+    }
+    Ok(None)
+}
+
+/// Render the ifupdown `inet6` stanza for an interface
+pub fn render_inet6_stanza(iface: &str, ipv6: &Ipv6Config) -> String {
+    if !ipv6.enabled {
+        return String::new();
+    }
+
+    let addresses = ipv6.address.as_deref().unwrap_or_default();
+    let method = if let Some(addr) = addresses.first() {
+        format!("static\n    address {}/{}\n", addr.ip, addr.prefix)
+    } else if ipv6.dhcp == Some(true) {
+        "dhcp\n".to_string()
+    } else if ipv6.autoconf != Some(false) {
+        "auto\n".to_string()
+    } else {
+        "manual\n".to_string()
+    };
+
+    let mut stanza = format!("\niface {} inet6 {}", iface, method);
+    for addr in addresses.iter().skip(1) {
+        stanza.push_str(&format!(
+            "    up ip -6 addr add {}/{} dev {}\n",
+            addr.ip, addr.prefix, iface
+        ));
+    }
+    if let Some(ref gateway) = ipv6.gateway {
+        stanza.push_str(&format!("    gateway {}\n", gateway));
+    }
+    if let Some(accept_ra) = ipv6.accept_ra {
+        stanza.push_str(&format!("    accept_ra {}\n", u8::from(accept_ra)));
+    }
+    if let Some(autoconf) = ipv6.autoconf {
+        stanza.push_str(&format!("    autoconf {}\n", u8::from(autoconf)));
+    }
+    if let Some(privacy) = ipv6.privacy {
+        stanza.push_str(&format!("    privext {}\n", privacy.use_tempaddr()));
+    }
+    stanza
+}
+
+/// Convert CIDR prefix to netmask string
+fn prefix_to_netmask(prefix: u8) -> String {
+    let mask: u32 = (!0u32)
+        .checked_shl(32 - u32::from(prefix.min(32)))
+        .unwrap_or(0);
+    std::net::Ipv4Addr::from(mask).to_string()
+}
+
+fn netmask_to_prefix(netmask: &str) -> u8 {
+    netmask
+        .parse::<std::net::Ipv4Addr>()
+        .map(|mask| u32::from(mask).count_ones() as u8)
+        .unwrap_or(32)
+}
+
+fn split_cidr(value: &str, default_prefix: u8) -> (String, u8) {
+    match value.split_once('/') {
+        Some((ip, prefix)) => (ip.to_string(), prefix.parse().unwrap_or(default_prefix)),
+        None => (value.to_string(), default_prefix),
+    }
+}
+
+fn find_block<'a>(content: &'a str, begin_marker: &str, end_marker: &str) -> Option<&'a str> {
+    let start = content.find(begin_marker)?;
+    let end = content[start..].find(end_marker)?;
+    Some(&content[start..start + end + end_marker.len()])
+}
+
+/// Replace a marked block in text content (appending when absent)
+fn replace_block(content: &str, begin_marker: &str, end_marker: &str, new_block: &str) -> String {
+    if let Some(start) = content.find(begin_marker) {
+        if let Some(end) = content[start..].find(end_marker) {
+            let end_idx = start + end + end_marker.len();
+            let mut result = String::with_capacity(content.len() + new_block.len());
+            result.push_str(&content[..start]);
+            result.push_str(new_block);
+            result.push_str(&content[end_idx..]);
+            return result;
+        }
+    }
+
+    if new_block.is_empty() {
+        return content.to_string();
+    }
+
+    let mut result = String::with_capacity(content.len() + new_block.len() + 1);
+    result.push_str(content);
+    if !content.ends_with('\n') {
+        result.push('\n');
+    }
+    result.push_str(new_block);
+    result
+}
+
+fn bond_mode_name(mode: BondMode) -> String {
+    serde_json::to_value(mode)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+// ---------------------------------------------------------------------------
+// systemd-networkd
+// ---------------------------------------------------------------------------
+
+/// systemd-networkd: `.network`/`.netdev` units, reloaded over D-Bus
+pub struct NetworkdRenderer {
+    dir: PathBuf,
+}
+
+impl NetworkdRenderer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn unit_path(&self, ifname: &str, ext: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}{}.{}", NETWORKD_PREFIX, ifname, ext))
+    }
+
+    /// Member units enslave ports to a bond/bridge op-dbus manages
+    fn member_path(&self, master: &str, member: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{}{}-member-{}.network",
+            NETWORKD_PREFIX, master, member
+        ))
+    }
+
+    /// Render the `.network` unit for an interface
+    pub fn render_network(config: &InterfaceConfig) -> String {
+        let mut unit = format!(
+            "# Managed by {}. Do not edit manually.\n[Match]\nName={}\n\n[Network]\n",
+            MANAGED_TAG, config.name
+        );
+
+        let ipv4 = config.tunable.ipv4.as_ref().filter(|c| c.enabled);
+        let ipv6 = config.tunable.ipv6.as_ref();
+        let dhcp4 = ipv4.is_some_and(|c| c.dhcp == Some(true));
+        let dhcp6 = ipv6.is_some_and(|c| c.enabled && c.dhcp == Some(true));
+        unit.push_str(&format!(
+            "DHCP={}\n",
+            match (dhcp4, dhcp6) {
+                (true, true) => "yes",
+                (true, false) => "ipv4",
+                (false, true) => "ipv6",
+                (false, false) => "no",
+            }
+        ));
+
+        if let Some(ipv4) = ipv4 {
+            for addr in ipv4.address.iter().flatten() {
+                unit.push_str(&format!("Address={}/{}\n", addr.ip, addr.prefix));
+            }
+            if let Some(ref gateway) = ipv4.gateway {
+                unit.push_str(&format!("Gateway={}\n", gateway));
+            }
+        }
+
+        let mut ra_section = String::new();
+        match ipv6 {
+            Some(ipv6) if !ipv6.enabled => {
+                unit.push_str("LinkLocalAddressing=no\nIPv6AcceptRA=no\n")
+            }
+            Some(ipv6) => {
+                for addr in ipv6.address.iter().flatten() {
+                    unit.push_str(&format!("Address={}/{}\n", addr.ip, addr.prefix));
+                }
+                if let Some(ref gateway) = ipv6.gateway {
+                    unit.push_str(&format!("Gateway={}\n", gateway));
+                }
+                if let Some(accept_ra) = ipv6.accept_ra {
+                    unit.push_str(&format!("IPv6AcceptRA={}\n", yes_no(accept_ra)));
+                }
+                if let Some(privacy) = ipv6.privacy {
+                    unit.push_str(&format!(
+                        "IPv6PrivacyExtensions={}\n",
+                        match privacy {
+                            Ipv6Privacy::Disabled => "no",
+                            Ipv6Privacy::PreferPublic => "prefer-public",
+                            Ipv6Privacy::PreferTemporary => "yes",
+                        }
+                    ));
+                }
+                if let Some(autoconf) = ipv6.autoconf {
+                    ra_section = format!(
+                        "\n[IPv6AcceptRA]\nUseAutonomousPrefix={}\n",
+                        yes_no(autoconf)
+                    );
+                }
+            }
+            None => {}
+        }
+
+        if let Some(LinkSpec::Vlan(_)) = config.link_spec().ok().flatten() {
+            // networkd attaches VLANs from the parent's unit; parents op-dbus doesn't
+            // render need `VLAN=<name>` added by hand.
+            unit.push_str("# Parent .network must list VLAN=");
+            unit.push_str(&config.name);
+            unit.push('\n');
+        }
+
+        unit.push_str(&ra_section);
+        unit
+    }
+
+    /// Render the `.netdev` unit for kernel link types (None for physical/OVS devices)
+    pub fn render_netdev(config: &InterfaceConfig) -> Option<String> {
+        let spec = config.link_spec().ok().flatten()?;
+        let mut unit = format!(
+            "# Managed by {}. Do not edit manually.\n[NetDev]\nName={}\nKind={}\n",
+            MANAGED_TAG,
+            config.name,
+            spec.kind()
+        );
+        if let Some(mtu) = config.desired_mtu() {
+            unit.push_str(&format!("MTUBytes={}\n", mtu));
+        }
+
+        match spec {
+            LinkSpec::Vlan(vlan) => unit.push_str(&format!("\n[VLAN]\nId={}\n", vlan.id)),
+            LinkSpec::Bond(bond) => {
+                unit.push_str("\n[Bond]\n");
+                if let Some(mode) = bond.mode {
+                    unit.push_str(&format!("Mode={}\n", bond_mode_name(mode)));
+                }
+                if let Some(miimon) = bond.miimon {
+                    unit.push_str(&format!("MIIMonitorSec={}ms\n", miimon));
+                }
+                if let Some(updelay) = bond.updelay {
+                    unit.push_str(&format!("UpDelaySec={}ms\n", updelay));
+                }
+                if let Some(downdelay) = bond.downdelay {
+                    unit.push_str(&format!("DownDelaySec={}ms\n", downdelay));
+                }
+            }
+            LinkSpec::Veth(veth) => unit.push_str(&format!("\n[Peer]\nName={}\n", veth.peer)),
+            LinkSpec::Vxlan(vxlan) => {
+                unit.push_str(&format!("\n[VXLAN]\nVNI={}\n", vxlan.vni));
+                if let Some(remote) = vxlan.remote {
+                    unit.push_str(&format!("Remote={}\n", remote));
+                }
+                if let Some(local) = vxlan.local {
+                    unit.push_str(&format!("Local={}\n", local));
+                }
+                if let Some(port) = vxlan.port {
+                    unit.push_str(&format!("DestinationPort={}\n", port));
+                }
+            }
+            LinkSpec::Gre(gre) => {
+                unit.push_str(&format!("\n[Tunnel]\nRemote={}\n", gre.remote));
+                if let Some(local) = gre.local {
+                    unit.push_str(&format!("Local={}\n", local));
+                }
+                if let Some(ttl) = gre.ttl {
+                    unit.push_str(&format!("TTL={}\n", ttl));
+                }
+                if let Some(key) = gre.key {
+                    unit.push_str(&format!("Key={}\n", key));
+                }
+            }
+            LinkSpec::Bridge(bridge) => {
+                unit.push_str("\n[Bridge]\n");
+                if let Some(stp) = bridge.stp {
+                    unit.push_str(&format!("STP={}\n", yes_no(stp)));
+                }
+                if let Some(priority) = bridge.priority {
+                    unit.push_str(&format!("Priority={}\n", priority));
+                }
+                if let Some(fd) = bridge.forward_delay {
+                    unit.push_str(&format!("ForwardDelaySec={}\n", fd));
+                }
+                if let Some(hello) = bridge.hello_time {
+                    unit.push_str(&format!("HelloTimeSec={}\n", hello));
+                }
+                if let Some(max_age) = bridge.max_age {
+                    unit.push_str(&format!("MaxAgeSec={}\n", max_age));
+                }
+                if let Some(filtering) = bridge.vlan_filtering {
+                    unit.push_str(&format!("VLANFiltering={}\n", yes_no(filtering)));
+                }
+            }
+        }
+        Some(unit)
+    }
+
+    /// Parse a `.network` unit back into L3 configuration
+    pub fn parse_network(unit: &str) -> L3Config {
+        let mut ipv4 = Ipv4Config {
+            enabled: false,
+            dhcp: None,
+            address: None,
+            gateway: None,
+            dns: None,
+        };
+        let mut ipv6 = Ipv6Config {
+            enabled: true,
+            ..Default::default()
+        };
+        let mut section = "";
+
+        for line in unit.lines().map(str::trim) {
+            if line.starts_with('[') {
+                section = line;
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match (section, key) {
+                ("[Network]", "DHCP") => {
+                    if matches!(value, "yes" | "ipv4") {
+                        ipv4.enabled = true;
+                        ipv4.dhcp = Some(true);
+                    }
+                    if matches!(value, "yes" | "ipv6") {
+                        ipv6.dhcp = Some(true);
+                    }
+                }
+                ("[Network]", "Address") => {
+                    let is_v6 = value.contains(':');
+                    let (ip, prefix) = split_cidr(value, if is_v6 { 128 } else { 32 });
+                    let addr = AddressConfig { ip, prefix };
+                    if is_v6 {
+                        ipv6.address.get_or_insert_with(Vec::new).push(addr);
+                    } else {
+                        ipv4.enabled = true;
+                        ipv4.address.get_or_insert_with(Vec::new).push(addr);
+                    }
+                }
+                ("[Network]", "Gateway") => {
+                    if value.contains(':') {
+                        ipv6.gateway = Some(value.to_string());
+                    } else {
+                        ipv4.gateway = Some(value.to_string());
+                    }
+                }
+                ("[Network]", "LinkLocalAddressing") if value == "no" => ipv6.enabled = false,
+                ("[Network]", "IPv6AcceptRA") => ipv6.accept_ra = Some(value == "yes"),
+                ("[Network]", "IPv6PrivacyExtensions") => {
+                    ipv6.privacy = Some(match value {
+                        "yes" => Ipv6Privacy::PreferTemporary,
+                        "prefer-public" => Ipv6Privacy::PreferPublic,
+                        _ => Ipv6Privacy::Disabled,
+                    })
+                }
+                ("[IPv6AcceptRA]", "UseAutonomousPrefix") => ipv6.autoconf = Some(value == "yes"),
+                _ => {}
+            }
+        }
+
+        if !ipv6.enabled {
+            ipv6 = Ipv6Config::default();
+        }
+        L3Config {
+            ipv4: Some(ipv4),
+            ipv6: Some(ipv6),
+        }
+    }
+
+    /// Ask networkd to re-read its units and reconfigure the link
+    async fn reload(&self, ifname: &str) -> Result<()> {
+        let conn = Connection::system()
+            .await
+            .context("Failed to connect to system D-Bus")?;
+        let proxy = Proxy::new(
+            &conn,
+            "org.freedesktop.network1",
+            "/org/freedesktop/network1",
+            "org.freedesktop.network1.Manager",
+        )
+        .await
+        .context("Failed to create networkd D-Bus proxy")?;
+
+        let _: () = proxy
+            .call("Reload", &())
+            .await
+            .context("Failed to reload systemd-networkd")?;
+
+        if let Ok(Some(link)) = crate::native::rtnetlink_links::get_link(ifname).await {
+            let _: () = proxy
+                .call("ReconfigureLink", &(link.index as i32,))
+                .await
+                .context(format!("Failed to reconfigure {} via networkd", ifname))?;
+        }
+        Ok(())
+    }
+
+    async fn write_if_changed(path: &Path, content: &str) -> Result<bool> {
+        if tokio::fs::read_to_string(path).await.ok().as_deref() == Some(content) {
+            return Ok(false);
+        }
+        tokio::fs::write(path, content)
+            .await
+            .context(format!("Failed to write {}", path.display()))?;
+        log::info!("Wrote {}", path.display());
+        Ok(true)
+    }
+
+    /// Remove units of a master's former members
+    async fn prune_members(&self, master: &str, keep: &[String]) -> Result<bool> {
+        let prefix = format!("{}{}-member-", NETWORKD_PREFIX, master);
+        let mut removed = false;
+        let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await else {
+            return Ok(false);
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(member) = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".network"))
+            else {
+                continue;
+            };
+            if !keep.iter().any(|k| k == member) {
+                tokio::fs::remove_file(entry.path()).await?;
+                removed = true;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[async_trait]
+impl L3Renderer for NetworkdRenderer {
+    fn name(&self) -> &'static str {
+        "networkd"
+    }
+
+    async fn query(&self, ifname: &str) -> Result<Option<L3Config>> {
+        match tokio::fs::read_to_string(self.unit_path(ifname, "network")).await {
+            Ok(unit) => Ok(Some(Self::parse_network(&unit))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(format!("Failed to read networkd unit for {}", ifname)),
+        }
+    }
+
+    async fn render(&self, config: &InterfaceConfig) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .context(format!("Failed to create {}", self.dir.display()))?;
+
+        let mut changed = Self::write_if_changed(
+            &self.unit_path(&config.name, "network"),
+            &Self::render_network(config),
+        )
+        .await?;
+        if let Some(netdev) = Self::render_netdev(config) {
+            changed |=
+                Self::write_if_changed(&self.unit_path(&config.name, "netdev"), &netdev).await?;
+        }
+
+        let members = match config.link_spec().ok().flatten() {
+            Some(LinkSpec::Bond(bond)) => Some(("Bond", bond.members.unwrap_or_default())),
+            Some(LinkSpec::Bridge(_)) => {
+                Some(("Bridge", config.tunable.ports.clone().unwrap_or_default()))
+            }
+            _ => None,
+        };
+        if let Some((key, members)) = members {
+            for member in &members {
+                let unit = format!(
+                    "# Managed by {}. Do not edit manually.\n[Match]\nName={}\n\n[Network]\n{}={}\n",
+                    MANAGED_TAG, member, key, config.name
+                );
+                changed |=
+                    Self::write_if_changed(&self.member_path(&config.name, member), &unit).await?;
+            }
+            changed |= self.prune_members(&config.name, &members).await?;
+        }
+
+        if changed {
+            self.reload(&config.name).await?;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, ifname: &str) -> Result<()> {
+        let mut removed = self.prune_members(ifname, &[]).await?;
+        for ext in ["network", "netdev"] {
+            let path = self.unit_path(ifname, ext);
+            if path.exists() {
+                tokio::fs::remove_file(&path)
+                    .await
+                    .context(format!("Failed to remove {}", path.display()))?;
+                removed = true;
+            }
+        }
+        if removed {
+            self.reload(ifname).await?;
+        }
+        Ok(())
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+// ---------------------------------------------------------------------------
+// NetworkManager
+// ---------------------------------------------------------------------------
+
+/// NetworkManager: one `op-dbus-<iface>` connection profile per interface
+pub struct NetworkManagerRenderer;
+
+type NmSettings = HashMap<String, HashMap<String, OwnedValue>>;
+
+impl NetworkManagerRenderer {
+    fn connection_id(ifname: &str) -> String {
+        format!("{}{}", NM_CONNECTION_PREFIX, ifname)
+    }
+
+    async fn proxy(conn: &Connection, path: &str, interface: &str) -> Result<Proxy<'static>> {
+        Proxy::new(
+            conn,
+            "org.freedesktop.NetworkManager",
+            path.to_string(),
+            interface.to_string(),
+        )
+        .await
+        .context("Failed to create NetworkManager D-Bus proxy")
+    }
+
+    /// Find our connection profile for an interface
+    async fn find_connection(
+        conn: &Connection,
+        ifname: &str,
+    ) -> Result<Option<(OwnedObjectPath, NmSettings)>> {
+        let settings = Self::proxy(
+            conn,
+            "/org/freedesktop/NetworkManager/Settings",
+            "org.freedesktop.NetworkManager.Settings",
+        )
+        .await?;
+        let paths: Vec<OwnedObjectPath> = settings
+            .call("ListConnections", &())
+            .await
+            .context("Failed to list NetworkManager connections")?;
+
+        let id = Self::connection_id(ifname);
+        for path in paths {
+            let connection = Self::proxy(
+                conn,
+                path.as_str(),
+                "org.freedesktop.NetworkManager.Settings.Connection",
+            )
+            .await?;
+            let values: NmSettings = match connection.call("GetSettings", &()).await {
+                Ok(values) => values,
+                Err(_) => continue,
+            };
+            let matches = values
+                .get("connection")
+                .and_then(|c| c.get("id"))
+                .and_then(|v| <&str>::try_from(v).ok())
+                == Some(id.as_str());
+            if matches {
+                return Ok(Some((path, values)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn address_data(
+        addresses: &Option<Vec<AddressConfig>>,
+    ) -> Vec<HashMap<&'static str, Value<'static>>> {
+        addresses
+            .iter()
+            .flatten()
+            .map(|a| {
+                HashMap::from([
+                    ("address", Value::from(a.ip.clone())),
+                    ("prefix", Value::from(u32::from(a.prefix))),
+                ])
+            })
+            .collect()
+    }
+
+    /// Build the connection settings (a{sa{sv}}) for an interface
+    fn settings(
+        config: &InterfaceConfig,
+    ) -> HashMap<&'static str, HashMap<&'static str, Value<'static>>> {
+        let spec = config.link_spec().ok().flatten();
+        let conn_type = match (&config.if_type, &spec) {
+            (_, Some(LinkSpec::Gre(_))) => "ip-tunnel",
+            (_, Some(spec)) => spec.kind(),
+            (InterfaceType::Ethernet, None) => "802-3-ethernet",
+            // OVS devices are created by ovs-vswitchd; NM only manages their addressing
+            _ => "generic",
+        };
+
+        let mut settings = HashMap::new();
+        settings.insert(
+            "connection",
+            HashMap::from([
+                ("id", Value::from(Self::connection_id(&config.name))),
+                ("type", Value::from(conn_type)),
+                ("interface-name", Value::from(config.name.clone())),
+                ("autoconnect", Value::from(true)),
+            ]),
+        );
+
+        match spec {
+            Some(LinkSpec::Vlan(vlan)) => {
+                settings.insert(
+                    "vlan",
+                    HashMap::from([
+                        ("parent", Value::from(vlan.parent)),
+                        ("id", Value::from(u32::from(vlan.id))),
+                    ]),
+                );
+            }
+            Some(LinkSpec::Bond(bond)) => {
+                let mut options = HashMap::new();
+                if let Some(mode) = bond.mode {
+                    options.insert("mode".to_string(), bond_mode_name(mode));
+                }
+                if let Some(miimon) = bond.miimon {
+                    options.insert("miimon".to_string(), miimon.to_string());
+                }
+                settings.insert("bond", HashMap::from([("options", Value::from(options))]));
+            }
+            Some(LinkSpec::Bridge(bridge)) => {
+                let mut values = HashMap::new();
+                if let Some(stp) = bridge.stp {
+                    values.insert("stp", Value::from(stp));
+                }
+                settings.insert("bridge", values);
+            }
+            Some(LinkSpec::Veth(veth)) => {
+                settings.insert("veth", HashMap::from([("peer", Value::from(veth.peer))]));
+            }
+            Some(LinkSpec::Vxlan(vxlan)) => {
+                let mut values = HashMap::from([("id", Value::from(vxlan.vni))]);
+                if let Some(remote) = vxlan.remote {
+                    values.insert("remote", Value::from(remote.to_string()));
+                }
+                if let Some(local) = vxlan.local {
+                    values.insert("local", Value::from(local.to_string()));
+                }
+                if let Some(port) = vxlan.port {
+                    values.insert("destination-port", Value::from(u32::from(port)));
+                }
+                settings.insert("vxlan", values);
+            }
+            Some(LinkSpec::Gre(gre)) => {
+                // NM_IP_TUNNEL_MODE_GRE
+                let mut values = HashMap::from([
+                    ("mode", Value::from(2u32)),
+                    ("remote", Value::from(gre.remote.to_string())),
+                ]);
+                if let Some(local) = gre.local {
+                    values.insert("local", Value::from(local.to_string()));
+                }
+                if let Some(ttl) = gre.ttl {
+                    values.insert("ttl", Value::from(u32::from(ttl)));
+                }
+                settings.insert("ip-tunnel", values);
+            }
+            None => {}
+        }
+
+        let mut ipv4 = HashMap::new();
+        match &config.tunable.ipv4 {
+            Some(c) if c.enabled && c.dhcp == Some(true) => {
+                ipv4.insert("method", Value::from("auto"));
+            }
+            Some(c) if ipv4_configured(c) => {
+                ipv4.insert("method", Value::from("manual"));
+                ipv4.insert("address-data", Value::from(Self::address_data(&c.address)));
+                if let Some(ref gateway) = c.gateway {
+                    ipv4.insert("gateway", Value::from(gateway.clone()));
+                }
+            }
+            _ => {
+                ipv4.insert("method", Value::from("disabled"));
+            }
+        }
+        settings.insert("ipv4", ipv4);
+
+        let mut ipv6 = HashMap::new();
+        match &config.tunable.ipv6 {
+            Some(c) if !c.enabled => {
+                ipv6.insert("method", Value::from("disabled"));
+            }
+            Some(c) => {
+                let has_static = !c.address.as_deref().unwrap_or_default().is_empty();
+                let method = if c.dhcp == Some(true) {
+                    "dhcp"
+                } else if has_static && c.accept_ra != Some(true) {
+                    "manual"
+                } else {
+                    "auto"
+                };
+                ipv6.insert("method", Value::from(method));
+                if has_static {
+                    ipv6.insert("address-data", Value::from(Self::address_data(&c.address)));
+                }
+                if let Some(ref gateway) = c.gateway {
+                    ipv6.insert("gateway", Value::from(gateway.clone()));
+                }
+                if let Some(privacy) = c.privacy {
+                    ipv6.insert("ip6-privacy", Value::from(privacy.use_tempaddr() as i32));
+                }
+            }
+            None => {
+                ipv6.insert("method", Value::from("ignore"));
+            }
+        }
+        settings.insert("ipv6", ipv6);
+
+        settings
+    }
+
+    /// Parse NetworkManager connection settings back into L3 configuration
+    fn parse_settings(values: &NmSettings) -> L3Config {
+        let string = |section: &HashMap<String, OwnedValue>, key: &str| {
+            section
+                .get(key)
+                .and_then(|v| <&str>::try_from(v).ok())
+                .map(str::to_string)
+        };
+        let addresses = |section: &HashMap<String, OwnedValue>| {
+            let entries = section
+                .get("address-data")
+                .and_then(|v| v.try_clone().ok())
+                .and_then(|v| Vec::<HashMap<String, OwnedValue>>::try_from(v).ok())?;
+            Some(
+                entries
+                    .iter()
+                    .filter_map(|entry| {
+                        Some(AddressConfig {
+                            ip: entry
+                                .get("address")
+                                .and_then(|v| <&str>::try_from(v).ok())?
+                                .to_string(),
+                            prefix: entry.get("prefix").and_then(|v| u32::try_from(v).ok())? as u8,
+                        })
+                    })
+                    .collect(),
+            )
+        };
+
+        let mut config = L3Config::default();
+        if let Some(section) = values.get("ipv4") {
+            let method = string(section, "method").unwrap_or_default();
+            config.ipv4 = Some(Ipv4Config {
+                enabled: method != "disabled",
+                dhcp: (method == "auto").then_some(true),
+                address: addresses(section),
+                gateway: string(section, "gateway"),
+                dns: None,
+            });
+        }
+        if let Some(section) = values.get("ipv6") {
+            let method = string(section, "method").unwrap_or_default();
+            config.ipv6 = Some(Ipv6Config {
+                enabled: !matches!(method.as_str(), "disabled" | "ignore"),
+                dhcp: (method == "dhcp").then_some(true),
+                address: addresses(section),
+                gateway: string(section, "gateway"),
+                privacy: section
+                    .get("ip6-privacy")
+                    .and_then(|v| i32::try_from(v).ok())
+                    .filter(|v| *v >= 0)
+                    .map(|v| Ipv6Privacy::from_use_tempaddr(i64::from(v))),
+                ..Default::default()
+            });
+        }
+        config
+    }
+}
+
+#[async_trait]
+impl L3Renderer for NetworkManagerRenderer {
+    fn name(&self) -> &'static str {
+        "networkmanager"
+    }
+
+    async fn query(&self, ifname: &str) -> Result<Option<L3Config>> {
+        let conn = Connection::system()
+            .await
+            .context("Failed to connect to system D-Bus")?;
+        Ok(Self::find_connection(&conn, ifname)
+            .await?
+            .map(|(_, values)| Self::parse_settings(&values)))
+    }
+
+    fn in_sync(&self, desired: &InterfaceConfig, persisted: Option<&L3Config>) -> bool {
+        // accept_ra/autoconf are folded into the ipv6 method
+        l3_in_sync(desired, persisted, false)
+    }
+
+    async fn render(&self, config: &InterfaceConfig) -> Result<()> {
+        let conn = Connection::system()
+            .await
+            .context("Failed to connect to system D-Bus")?;
+        let settings = Self::settings(config);
+
+        let path = match Self::find_connection(&conn, &config.name).await? {
+            Some((path, _)) => {
+                let connection = Self::proxy(
+                    &conn,
+                    path.as_str(),
+                    "org.freedesktop.NetworkManager.Settings.Connection",
+                )
+                .await?;
+                let _: () = connection
+                    .call("Update", &(settings,))
+                    .await
+                    .context(format!(
+                        "Failed to update NetworkManager profile for {}",
+                        config.name
+                    ))?;
+                path
+            }
+            None => {
+                let proxy = Self::proxy(
+                    &conn,
+                    "/org/freedesktop/NetworkManager/Settings",
+                    "org.freedesktop.NetworkManager.Settings",
+                )
+                .await?;
+                proxy
+                    .call("AddConnection", &(settings,))
+                    .await
+                    .context(format!(
+                        "Failed to add NetworkManager profile for {}",
+                        config.name
+                    ))?
+            }
+        };
+
+        let nm = Self::proxy(
+            &conn,
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+        )
+        .await?;
+        let root = OwnedObjectPath::try_from("/").expect("valid object path");
+        let _active: OwnedObjectPath = nm
+            .call("ActivateConnection", &(path, root.clone(), root))
+            .await
+            .context(format!(
+                "Failed to activate NetworkManager profile for {}",
+                config.name
+            ))?;
+        log::info!("Activated NetworkManager profile for {}", config.name);
+        Ok(())
+    }
+
+    async fn remove(&self, ifname: &str) -> Result<()> {
+        let conn = Connection::system()
+            .await
+            .context("Failed to connect to system D-Bus")?;
+        if let Some((path, _)) = Self::find_connection(&conn, ifname).await? {
+            let connection = Self::proxy(
+                &conn,
+                path.as_str(),
+                "org.freedesktop.NetworkManager.Settings.Connection",
+            )
+            .await?;
+            let _: () = connection.call("Delete", &()).await.context(format!(
+                "Failed to delete NetworkManager profile for {}",
+                ifname
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iface(value: serde_json::Value) -> InterfaceConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_ifupdown_block_roundtrip() {
+        let config = iface(serde_json::json!({
+            "name": "bond0",
+            "type": "bond",
+            "bond": {"mode": "802.3ad", "miimon": 100, "members": ["eth1", "eth2"]},
+            "ipv4": {
+                "enabled": true,
+                "address": [
+                    {"ip": "192.0.2.10", "prefix": 24},
+                    {"ip": "192.0.2.11", "prefix": 32}
+                ],
+                "gateway": "192.0.2.1"
+            },
+            "ipv6": {
+                "enabled": true,
+                "address": [{"ip": "2001:db8::10", "prefix": 64}],
+                "accept_ra": false,
+                "privacy": "prefer-public"
+            }
+        }));
+
+        let block = IfupdownRenderer::render_block(&config, None);
+        assert!(block.starts_with("# BEGIN op-dbus-managed bond0\n"));
+        assert!(block.contains("auto bond0\niface bond0 inet static\n"));
+        assert!(block.contains("    netmask 255.255.255.0\n"));
+        assert!(block.contains("    bond-slaves eth1 eth2\n    bond-mode 802.3ad\n"));
+
+        let parsed = IfupdownRenderer::parse_block(&block, "bond0");
+        assert_eq!(
+            address_set(&parsed.ipv4.as_ref().unwrap().address),
+            address_set(&config.tunable.ipv4.as_ref().unwrap().address)
+        );
+        assert!(l3_in_sync(&config, Some(&parsed), true));
+
+        let mut changed = config.clone();
+        changed.tunable.ipv4.as_mut().unwrap().gateway = Some("192.0.2.254".into());
+        assert!(!l3_in_sync(&changed, Some(&parsed), true));
+        assert!(!l3_in_sync(&config, None, true));
+    }
+
+    #[test]
+    fn test_replace_block_removes_only_own_interface() {
+        let content = "auto lo\niface lo inet loopback\n";
+        let (begin_a, end_a) = IfupdownRenderer::markers("a");
+        let (begin_b, end_b) = IfupdownRenderer::markers("b");
+        let block_a = format!("{}x\n{}", begin_a, end_a);
+        let block_b = format!("{}y\n{}", begin_b, end_b);
+
+        let both = replace_block(
+            &replace_block(content, &begin_a, &end_a, &block_a),
+            &begin_b,
+            &end_b,
+            &block_b,
+        );
+        assert!(both.contains(&block_a) && both.contains(&block_b));
+
+        let without_a = replace_block(&both, &begin_a, &end_a, "");
+        assert_eq!(without_a, format!("{}{}", content, block_b));
+        assert_eq!(replace_block(content, &begin_a, &end_a, ""), content);
+    }
+
+    #[test]
+    fn test_networkd_units() {
+        let config = iface(serde_json::json!({
+            "name": "vlan100",
+            "type": "vlan",
+            "vlan": {"parent": "eth0", "id": 100},
+            "ipv4": {"enabled": true, "dhcp": true},
+            "ipv6": {"enabled": true, "autoconf": false, "privacy": "prefer-temporary"}
+        }));
+
+        let network = NetworkdRenderer::render_network(&config);
+        assert!(network.contains("[Match]\nName=vlan100\n"));
+        assert!(network.contains("DHCP=ipv4\n"));
+        assert!(network.contains("IPv6PrivacyExtensions=yes\n"));
+        assert!(network.contains("[IPv6AcceptRA]\nUseAutonomousPrefix=no\n"));
+
+        let netdev = NetworkdRenderer::render_netdev(&config).unwrap();
+        assert!(netdev.contains("Kind=vlan\n"));
+        assert!(netdev.contains("[VLAN]\nId=100\n"));
+
+        let parsed = NetworkdRenderer::parse_network(&network);
+        assert!(l3_in_sync(&config, Some(&parsed), true));
+    }
+
+    #[test]
+    fn test_ovs_port_and_addressless_ipv4() {
+        let config = iface(serde_json::json!({
+            "name": "vi100",
+            "type": "ovs-port",
+            "ipv4": {"enabled": true}
+        }));
+
+        let block = IfupdownRenderer::render_block(&config, Some("ovsbr0"));
+        assert!(block.contains("allow-ovsbr0 vi100\niface vi100 inet manual\n"));
+        assert!(block.contains("    ovs_type OVSIntPort\n    ovs_bridge ovsbr0\n"));
+
+        // Enabled without addresses or DHCP reads back as manual/disabled
+        let parsed = IfupdownRenderer::parse_block(&block, "vi100");
+        assert!(l3_in_sync(&config, Some(&parsed), true));
+        let network = NetworkdRenderer::render_network(&config);
+        let parsed = NetworkdRenderer::parse_network(&network);
+        assert!(l3_in_sync(&config, Some(&parsed), true));
+    }
+
+    #[test]
+    fn test_prefix_to_netmask() {
+        assert_eq!(prefix_to_netmask(24), "255.255.255.0");
+        assert_eq!(prefix_to_netmask(32), "255.255.255.255");
+        assert_eq!(prefix_to_netmask(0), "0.0.0.0");
+        assert_eq!(netmask_to_prefix("255.255.240.0"), 20);
+    }
+}
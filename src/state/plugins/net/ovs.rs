@@ -0,0 +1,1025 @@
+// OVS port model - VLAN tagging, bonds, mirrors, QoS, external_ids and other_config
+// Declared under `ovs` on an "ovs-bridge" interface and applied through OvsdbClient
+// in a single transaction per bridge. A declared port is described completely: unset
+// fields clear their column, so restoring a checkpoint puts back exactly what it saw.
+// The external_ids and other_config maps are the exception - left alone unless declared,
+// since OVN, Proxmox and others keep their own keys there.
+use crate::native::ovsdb_txn::{Atom, Condition, Datum, Mutation, Row, Transaction};
+use crate::native::OvsdbClient;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+const DATABASE: &str = "Open_vSwitch";
+
+/// Port external_ids key marking ports op-dbus created; hidden from queried state
+pub const OWNER_KEY: &str = "op-dbus-managed";
+
+const MAX_VLAN: u16 = 4095;
+
+/// OVS settings of a bridge (type "ovs-bridge")
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OvsBridgeOptions {
+    /// Per-port settings by port name; ports not listed are left alone
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ports: BTreeMap<String, OvsPortOptions>,
+
+    /// Port mirrors; when present, other mirrors on the bridge are removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirrors: Option<Vec<MirrorConfig>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_ids: Option<BTreeMap<String, String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub other_config: Option<BTreeMap<String, String>>,
+}
+
+/// Settings of one OVS port. Unset fields clear the corresponding column,
+/// except `bond`, which leaves the port's interfaces alone, and the
+/// external_ids/other_config maps, which are unmanaged when unset.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OvsPortOptions {
+    /// Access VLAN (0-4095)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<u16>,
+
+    /// VLANs carried by a trunk port; all VLANs when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trunks: Option<Vec<u16>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vlan_mode: Option<OvsVlanMode>,
+
+    /// Makes the port a bond of several interfaces, created if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bond: Option<OvsBondOptions>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qos: Option<QosConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_ids: Option<BTreeMap<String, String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub other_config: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OvsVlanMode {
+    Access,
+    Trunk,
+    NativeTagged,
+    NativeUntagged,
+    Dot1qTunnel,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OvsBondOptions {
+    /// Member interfaces (at least two)
+    pub interfaces: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<OvsBondMode>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lacp: Option<OvsLacp>,
+
+    /// Milliseconds a member must be up before it is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updelay: Option<u32>,
+
+    /// Milliseconds a member must be down before it is disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downdelay: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OvsBondMode {
+    ActiveBackup,
+    BalanceSlb,
+    BalanceTcp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OvsLacp {
+    Active,
+    Passive,
+    Off,
+}
+
+/// QoS row attached to a port
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QosConfig {
+    /// Implementation, e.g. "linux-htb" or "linux-hfsc"
+    #[serde(rename = "type")]
+    pub qos_type: String,
+
+    /// Ceiling for the whole port in bit/s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_rate: Option<u64>,
+
+    /// Queues by queue id, as used by OpenFlow set_queue actions
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub queues: BTreeMap<u32, QueueConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueueConfig {
+    /// Guaranteed rate in bit/s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_rate: Option<u64>,
+
+    /// Ceiling in bit/s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_rate: Option<u64>,
+
+    /// Burst size in bits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u32>,
+}
+
+/// Port mirror; ports are referenced by name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MirrorConfig {
+    pub name: String,
+
+    /// Mirror every packet on the bridge
+    #[serde(default)]
+    pub select_all: bool,
+
+    /// Mirror packets received on these ports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub select_src_port: Vec<String>,
+
+    /// Mirror packets sent to these ports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub select_dst_port: Vec<String>,
+
+    /// Only mirror packets in these VLANs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub select_vlan: Vec<u16>,
+
+    /// Port receiving mirrored packets; exclusive with `output_vlan`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_port: Option<String>,
+
+    /// VLAN receiving mirrored packets; exclusive with `output_port`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_vlan: Option<u16>,
+}
+
+impl OvsBridgeOptions {
+    /// Whether the queried options satisfy the declared ones. Only ports and
+    /// sections present in the declaration are compared.
+    pub fn in_sync(&self, current: Option<&OvsBridgeOptions>) -> bool {
+        let empty = OvsBridgeOptions::default();
+        let current = current.unwrap_or(&empty);
+
+        map_in_sync(&self.external_ids, &current.external_ids)
+            && map_in_sync(&self.other_config, &current.other_config)
+            && self.mirrors.as_ref().is_none_or(|mirrors| {
+                normalized_mirrors(mirrors)
+                    == normalized_mirrors(current.mirrors.as_deref().unwrap_or_default())
+            })
+            && self.ports.iter().all(|(name, port)| {
+                current
+                    .ports
+                    .get(name)
+                    .is_some_and(|actual| port.in_sync(actual))
+            })
+    }
+
+    /// Ports declared as bonds; created here rather than as single-interface ports
+    pub fn bond_ports(&self) -> impl Iterator<Item = &String> {
+        self.ports
+            .iter()
+            .filter(|(_, port)| port.bond.is_some())
+            .map(|(name, _)| name)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (name, port) in &self.ports {
+            port.validate()
+                .with_context(|| format!("Invalid settings for OVS port {}", name))?;
+        }
+        for mirror in self.mirrors.iter().flatten() {
+            if mirror.output_port.is_some() == mirror.output_vlan.is_some() {
+                bail!(
+                    "Mirror {} needs exactly one of output_port and output_vlan",
+                    mirror.name
+                );
+            }
+            check_vlans(mirror.select_vlan.iter().chain(&mirror.output_vlan))?;
+        }
+        Ok(())
+    }
+}
+
+impl OvsPortOptions {
+    fn in_sync(&self, current: &OvsPortOptions) -> bool {
+        let desired = self.normalized();
+        let mut current = current.normalized();
+        // Interfaces are only managed on ports declared as bonds
+        if desired.bond.is_none() {
+            current.bond = None;
+        }
+        if self.external_ids.is_none() {
+            current.external_ids = None;
+        }
+        if self.other_config.is_none() {
+            current.other_config = None;
+        }
+        desired == current
+    }
+
+    /// Canonical form: empty collections unset, lists sorted, zero delays unset
+    fn normalized(&self) -> Self {
+        let mut port = self.clone();
+        if let Some(trunks) = &mut port.trunks {
+            trunks.sort_unstable();
+            trunks.dedup();
+        }
+        port.trunks = port.trunks.filter(|t| !t.is_empty());
+        port.external_ids = port.external_ids.filter(|m| !m.is_empty());
+        port.other_config = port.other_config.filter(|m| !m.is_empty());
+        if let Some(bond) = &mut port.bond {
+            bond.interfaces.sort();
+            bond.updelay = bond.updelay.filter(|d| *d != 0);
+            bond.downdelay = bond.downdelay.filter(|d| *d != 0);
+        }
+        port
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_vlans(self.tag.iter().chain(self.trunks.iter().flatten()))?;
+        if let Some(bond) = &self.bond {
+            if bond.interfaces.len() < 2 {
+                bail!("A bond needs at least two interfaces");
+            }
+        }
+        Ok(())
+    }
+
+    /// Port columns this declaration owns; qos and interfaces are set separately.
+    /// `owned` keeps the owner marker in a declared external_ids map, `created`
+    /// writes it on a new port even when the map is undeclared.
+    fn to_row(&self, owned: bool, created: bool) -> Row {
+        let mut row = Row::new()
+            .set("tag", Datum::set(self.tag.map(i64::from)))
+            .set(
+                "trunks",
+                Datum::set(self.trunks.iter().flatten().copied().map(i64::from)),
+            )
+            .set("vlan_mode", Datum::set(self.vlan_mode.map(enum_str)));
+
+        if self.external_ids.is_some() || created {
+            let mut external_ids = self.external_ids.clone().unwrap_or_default();
+            if owned || created {
+                external_ids.insert(OWNER_KEY.to_string(), "true".to_string());
+            }
+            row = row.set("external_ids", Datum::map(external_ids));
+        }
+        if let Some(other_config) = &self.other_config {
+            row = row.set("other_config", Datum::map(other_config.clone()));
+        }
+        if let Some(bond) = &self.bond {
+            row = row
+                .set("bond_mode", Datum::set(bond.mode.map(enum_str)))
+                .set("lacp", Datum::set(bond.lacp.map(enum_str)))
+                .set("bond_updelay", i64::from(bond.updelay.unwrap_or(0)))
+                .set("bond_downdelay", i64::from(bond.downdelay.unwrap_or(0)));
+        }
+        row
+    }
+}
+
+fn check_vlans<'a>(vlans: impl IntoIterator<Item = &'a u16>) -> Result<()> {
+    match vlans.into_iter().find(|vlan| **vlan > MAX_VLAN) {
+        Some(vlan) => bail!("VLAN {} is out of range (0-{})", vlan, MAX_VLAN),
+        None => Ok(()),
+    }
+}
+
+/// A declared map is authoritative; an undeclared one is unmanaged
+fn map_in_sync(
+    desired: &Option<BTreeMap<String, String>>,
+    current: &Option<BTreeMap<String, String>>,
+) -> bool {
+    desired
+        .as_ref()
+        .is_none_or(|desired| desired == current.as_ref().unwrap_or(&BTreeMap::new()))
+}
+
+fn normalized_mirrors(mirrors: &[MirrorConfig]) -> Vec<MirrorConfig> {
+    let mut mirrors: Vec<MirrorConfig> = mirrors
+        .iter()
+        .cloned()
+        .map(|mut mirror| {
+            mirror.select_src_port.sort();
+            mirror.select_dst_port.sort();
+            mirror.select_vlan.sort_unstable();
+            mirror
+        })
+        .collect();
+    mirrors.sort_by(|a, b| a.name.cmp(&b.name));
+    mirrors
+}
+
+/// Current OVS settings of a bridge, all of its ports and its mirrors
+pub async fn query(client: &OvsdbClient, bridge: &str) -> Result<OvsBridgeOptions> {
+    Ok(Snapshot::fetch(client, bridge).await?.options())
+}
+
+/// Ports on a bridge that op-dbus created
+pub async fn owned_ports(client: &OvsdbClient, bridge: &str) -> Result<Vec<String>> {
+    let snapshot = Snapshot::fetch(client, bridge).await?;
+    Ok(snapshot
+        .ports
+        .iter()
+        .filter(|(_, (_, row))| string_map(&row["external_ids"]).contains_key(OWNER_KEY))
+        .map(|(name, _)| name.clone())
+        .collect())
+}
+
+/// Bring a bridge's OVS settings in line with the declaration in one
+/// transaction. Returns the number of rows changed.
+pub async fn apply(
+    client: &OvsdbClient,
+    bridge: &str,
+    desired: &OvsBridgeOptions,
+) -> Result<usize> {
+    desired.validate()?;
+    let snapshot = Snapshot::fetch(client, bridge).await?;
+    let current = snapshot.options();
+
+    let mut txn = Transaction::new(DATABASE);
+    let mut changed = 0;
+    let mut bridge_row = Row::new();
+    let mut bridge_changed = false;
+
+    for (column, declared, actual) in [
+        ("external_ids", &desired.external_ids, &current.external_ids),
+        ("other_config", &desired.other_config, &current.other_config),
+    ] {
+        if let Some(map) = declared.as_ref().filter(|_| !map_in_sync(declared, actual)) {
+            bridge_row = bridge_row.set(column, Datum::map(map.clone()));
+            bridge_changed = true;
+        }
+    }
+
+    // Mirrors reference ports by row, including bonds created below
+    let mut port_refs: HashMap<String, Atom> = snapshot
+        .ports
+        .iter()
+        .map(|(name, (uuid, _))| (name.clone(), Atom::Uuid(uuid.clone())))
+        .collect();
+    let mut new_ports = Vec::new();
+    let mut absorbed_ports = Vec::new();
+
+    for (name, port) in &desired.ports {
+        let actual = current.ports.get(name);
+        if actual.is_some_and(|actual| port.in_sync(actual)) {
+            continue;
+        }
+        let existing = snapshot.ports.get(name);
+        let owned = existing
+            .is_none_or(|(_, row)| string_map(&row["external_ids"]).contains_key(OWNER_KEY));
+        let mut row = port.to_row(owned, existing.is_none());
+
+        // Rebuilding QoS resets the qdisc, so only touch it when it differs
+        if actual.map(|a| &a.qos) != Some(&port.qos) {
+            let qos = match &port.qos {
+                Some(qos) => Datum::set([&insert_qos(&mut txn, qos)]),
+                None => Datum::Set(Vec::new()),
+            };
+            row = row.set("qos", qos);
+            if let Some((uuid, existing)) = existing {
+                release_qos(&mut txn, &snapshot, uuid, existing);
+            }
+        }
+
+        if let Some(bond) = &port.bond {
+            let interfaces = bond_interfaces(&mut txn, &snapshot, name, bond, &mut absorbed_ports);
+            row = row.set("interfaces", interfaces);
+        }
+
+        match (existing, &port.bond) {
+            (Some((uuid, _)), _) => {
+                txn.update("Port", vec![Condition::uuid(uuid)], row);
+            }
+            (None, Some(_)) => {
+                let named = txn.insert("Port", row.set("name", name.as_str()));
+                port_refs.insert(name.clone(), Atom::from(&named));
+                new_ports.push(Atom::from(&named));
+            }
+            (None, None) => bail!("Port {} not found on bridge {}", name, bridge),
+        }
+        changed += 1;
+    }
+
+    let bridge_cond = || vec![Condition::uuid(&snapshot.bridge_uuid)];
+    if !new_ports.is_empty() {
+        txn.mutate(
+            "Bridge",
+            bridge_cond(),
+            vec![Mutation::insert("ports", Datum::Set(new_ports))],
+        );
+    }
+    if !absorbed_ports.is_empty() {
+        txn.mutate(
+            "Bridge",
+            bridge_cond(),
+            vec![Mutation::delete("ports", Datum::Set(absorbed_ports))],
+        );
+    }
+
+    if let Some(mirrors) = &desired.mirrors {
+        let in_sync = normalized_mirrors(mirrors)
+            == normalized_mirrors(current.mirrors.as_deref().unwrap_or_default());
+        if !in_sync {
+            let mut rows = Vec::new();
+            for mirror in mirrors {
+                let row = mirror_row(mirror, &port_refs)?;
+                rows.push(Atom::from(&txn.insert("Mirror", row)));
+            }
+            // Replaced mirrors are garbage collected once unreferenced
+            bridge_row = bridge_row.set("mirrors", Datum::Set(rows));
+            bridge_changed = true;
+        }
+    }
+
+    if bridge_changed {
+        txn.update("Bridge", bridge_cond(), bridge_row);
+        changed += 1;
+    }
+    if txn.is_empty() {
+        return Ok(0);
+    }
+
+    txn.comment(&format!("op-dbus: configure OVS ports of {}", bridge));
+    client
+        .execute(&txn)
+        .await
+        .with_context(|| format!("Failed to configure OVS settings of {}", bridge))?;
+    Ok(changed)
+}
+
+/// Insert QoS and Queue rows for a port
+fn insert_qos(txn: &mut Transaction, qos: &QosConfig) -> crate::native::ovsdb_txn::NamedUuid {
+    let queues: Vec<(Atom, Atom)> = qos
+        .queues
+        .iter()
+        .map(|(id, queue)| {
+            let other_config = [
+                ("min-rate", queue.min_rate),
+                ("max-rate", queue.max_rate),
+                ("burst", queue.burst),
+                ("priority", queue.priority.map(u64::from)),
+            ]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?.to_string())));
+            let row = Row::new().set("other_config", Datum::map(other_config));
+            (
+                Atom::Integer(i64::from(*id)),
+                Atom::from(&txn.insert("Queue", row)),
+            )
+        })
+        .collect();
+
+    let other_config = qos.max_rate.map(|rate| ("max-rate", rate.to_string()));
+    txn.insert(
+        "QoS",
+        Row::new()
+            .set("type", qos.qos_type.as_str())
+            .set("other_config", Datum::map(other_config))
+            .set("queues", Datum::Map(queues)),
+    )
+}
+
+/// QoS and Queue are root tables, so a replaced QoS row (and its queues) is
+/// deleted explicitly unless something else still references it
+fn release_qos(txn: &mut Transaction, snapshot: &Snapshot, port_uuid: &str, port: &Value) {
+    for qos_uuid in uuids(&port["qos"]) {
+        let shared = snapshot
+            .all_ports
+            .iter()
+            .any(|(uuid, row)| uuid != port_uuid && uuids(&row["qos"]).contains(&qos_uuid));
+        if shared {
+            continue;
+        }
+        txn.delete("QoS", vec![Condition::uuid(&qos_uuid)]);
+
+        let Some(qos) = snapshot.qos.get(&qos_uuid) else {
+            continue;
+        };
+        for queue_uuid in map_pairs(&qos["queues"]).filter_map(|(_, v)| uuid_of(v)) {
+            let shared = snapshot.qos.iter().any(|(uuid, row)| {
+                *uuid != qos_uuid
+                    && map_pairs(&row["queues"])
+                        .any(|(_, v)| uuid_of(v).as_ref() == Some(&queue_uuid))
+            });
+            if !shared {
+                txn.delete("Queue", vec![Condition::uuid(&queue_uuid)]);
+            }
+        }
+    }
+}
+
+/// Interfaces of a bond port. Members that are single-interface ports on the
+/// bridge are moved into the bond and their old ports dropped.
+fn bond_interfaces(
+    txn: &mut Transaction,
+    snapshot: &Snapshot,
+    bond_name: &str,
+    bond: &OvsBondOptions,
+    absorbed_ports: &mut Vec<Atom>,
+) -> Datum {
+    let mut members = Vec::new();
+    for member in &bond.interfaces {
+        let found = snapshot
+            .ports
+            .iter()
+            .find_map(|(port_name, (port_uuid, row))| {
+                let interface = uuids(&row["interfaces"])
+                    .into_iter()
+                    .find(|uuid| snapshot.interfaces.get(uuid) == Some(member))?;
+                Some((port_name, port_uuid, interface))
+            });
+        match found {
+            Some((port_name, port_uuid, interface)) => {
+                if port_name != bond_name {
+                    absorbed_ports.push(Atom::Uuid(port_uuid.clone()));
+                }
+                members.push(Atom::Uuid(interface));
+            }
+            None => {
+                let named = txn.insert("Interface", Row::new().set("name", member.as_str()));
+                members.push(Atom::from(&named));
+            }
+        }
+    }
+    Datum::Set(members)
+}
+
+fn mirror_row(mirror: &MirrorConfig, port_refs: &HashMap<String, Atom>) -> Result<Row> {
+    let port = |name: &String| {
+        port_refs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Mirror {} references unknown port {}", mirror.name, name))
+    };
+    let ports = |names: &[String]| -> Result<Datum> {
+        Ok(Datum::Set(names.iter().map(port).collect::<Result<_>>()?))
+    };
+
+    Ok(Row::new()
+        .set("name", mirror.name.as_str())
+        .set("select_all", mirror.select_all)
+        .set("select_src_port", ports(&mirror.select_src_port)?)
+        .set("select_dst_port", ports(&mirror.select_dst_port)?)
+        .set(
+            "select_vlan",
+            Datum::set(mirror.select_vlan.iter().copied().map(i64::from)),
+        )
+        .set(
+            "output_port",
+            Datum::Set(mirror.output_port.iter().map(port).collect::<Result<_>>()?),
+        )
+        .set("output_vlan", Datum::set(mirror.output_vlan.map(i64::from))))
+}
+
+/// Rows describing one bridge, fetched in a single transaction
+struct Snapshot {
+    bridge_uuid: String,
+    bridge: Value,
+    /// Ports on this bridge: name -> (uuid, row)
+    ports: BTreeMap<String, (String, Value)>,
+    /// Every Port row, for reference counting shared QoS rows
+    all_ports: HashMap<String, Value>,
+    /// Interface names by uuid
+    interfaces: HashMap<String, String>,
+    qos: HashMap<String, Value>,
+    queues: HashMap<String, Value>,
+    mirrors: HashMap<String, Value>,
+}
+
+impl Snapshot {
+    async fn fetch(client: &OvsdbClient, bridge: &str) -> Result<Self> {
+        let mut txn = Transaction::new(DATABASE);
+        txn.select("Bridge", vec![Condition::eq("name", bridge)], None)
+            .select("Port", vec![], None)
+            .select("Interface", vec![], Some(&["_uuid", "name"]))
+            .select("QoS", vec![], None)
+            .select("Queue", vec![], None)
+            .select("Mirror", vec![], None);
+        let result = client.execute(&txn).await?;
+
+        let mut tables = result
+            .results
+            .into_iter()
+            .map(|r| r.rows.unwrap_or_default());
+        let mut next = || tables.next().unwrap_or_default();
+        let bridge_row = next()
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Bridge '{}' not found", bridge))?;
+        let by_uuid = |rows: Vec<Value>| -> HashMap<String, Value> {
+            rows.into_iter()
+                .filter_map(|row| Some((uuid_of(&row["_uuid"])?, row)))
+                .collect()
+        };
+        let all_ports = by_uuid(next());
+        let interfaces = by_uuid(next())
+            .into_iter()
+            .filter_map(|(uuid, row)| Some((uuid, row["name"].as_str()?.to_string())))
+            .collect();
+
+        let ports = uuids(&bridge_row["ports"])
+            .into_iter()
+            .filter_map(|uuid| {
+                let row = all_ports.get(&uuid)?;
+                Some((row["name"].as_str()?.to_string(), (uuid, row.clone())))
+            })
+            .collect();
+
+        Ok(Self {
+            bridge_uuid: uuid_of(&bridge_row["_uuid"])
+                .ok_or_else(|| anyhow!("Bridge '{}' has no _uuid", bridge))?,
+            bridge: bridge_row,
+            ports,
+            all_ports,
+            interfaces,
+            qos: by_uuid(next()),
+            queues: by_uuid(next()),
+            mirrors: by_uuid(next()),
+        })
+    }
+
+    fn options(&self) -> OvsBridgeOptions {
+        let port_names: HashMap<&str, &str> = self
+            .ports
+            .iter()
+            .map(|(name, (uuid, _))| (uuid.as_str(), name.as_str()))
+            .collect();
+        let names = |value: &Value| -> Vec<String> {
+            uuids(value)
+                .iter()
+                .filter_map(|uuid| port_names.get(uuid.as_str()).map(|n| n.to_string()))
+                .collect()
+        };
+
+        let mirrors = uuids(&self.bridge["mirrors"])
+            .iter()
+            .filter_map(|uuid| self.mirrors.get(uuid))
+            .map(|row| MirrorConfig {
+                name: row["name"].as_str().unwrap_or_default().to_string(),
+                select_all: row["select_all"].as_bool().unwrap_or(false),
+                select_src_port: names(&row["select_src_port"]),
+                select_dst_port: names(&row["select_dst_port"]),
+                select_vlan: vlans(&row["select_vlan"]),
+                output_port: names(&row["output_port"]).into_iter().next(),
+                output_vlan: vlans(&row["output_vlan"]).into_iter().next(),
+            })
+            .collect();
+
+        OvsBridgeOptions {
+            ports: self
+                .ports
+                .iter()
+                .map(|(name, (_, row))| (name.clone(), self.port_options(row)))
+                .collect(),
+            mirrors: Some(mirrors),
+            external_ids: Some(string_map(&self.bridge["external_ids"])),
+            other_config: Some(string_map(&self.bridge["other_config"])),
+        }
+    }
+
+    fn port_options(&self, row: &Value) -> OvsPortOptions {
+        let interfaces: Vec<String> = uuids(&row["interfaces"])
+            .iter()
+            .filter_map(|uuid| self.interfaces.get(uuid).cloned())
+            .collect();
+        let delay = |column: &str| row[column].as_u64().filter(|d| *d != 0).map(|d| d as u32);
+        let mut external_ids = string_map(&row["external_ids"]);
+        external_ids.remove(OWNER_KEY);
+
+        // Maps are reported even when empty, so a checkpoint restores them exactly
+
+        OvsPortOptions {
+            tag: vlans(&row["tag"]).into_iter().next(),
+            trunks: Some(vlans(&row["trunks"])).filter(|t| !t.is_empty()),
+            vlan_mode: optional_str(&row["vlan_mode"]).and_then(parse_enum),
+            bond: (interfaces.len() > 1).then(|| OvsBondOptions {
+                interfaces,
+                mode: optional_str(&row["bond_mode"]).and_then(parse_enum),
+                lacp: optional_str(&row["lacp"]).and_then(parse_enum),
+                updelay: delay("bond_updelay"),
+                downdelay: delay("bond_downdelay"),
+            }),
+            qos: uuids(&row["qos"])
+                .first()
+                .and_then(|uuid| self.qos_config(uuid)),
+            external_ids: Some(external_ids),
+            other_config: Some(string_map(&row["other_config"])),
+        }
+    }
+
+    fn qos_config(&self, uuid: &str) -> Option<QosConfig> {
+        let row = self.qos.get(uuid)?;
+        let rate = |config: &BTreeMap<String, String>, key: &str| {
+            config.get(key).and_then(|v| v.parse().ok())
+        };
+        let queues = map_pairs(&row["queues"])
+            .filter_map(|(id, queue)| {
+                let queue = self.queues.get(&uuid_of(queue)?)?;
+                let config = string_map(&queue["other_config"]);
+                Some((
+                    u32::try_from(id.as_u64()?).ok()?,
+                    QueueConfig {
+                        min_rate: rate(&config, "min-rate"),
+                        max_rate: rate(&config, "max-rate"),
+                        burst: rate(&config, "burst"),
+                        priority: config.get("priority").and_then(|v| v.parse().ok()),
+                    },
+                ))
+            })
+            .collect();
+
+        Some(QosConfig {
+            qos_type: row["type"].as_str().unwrap_or_default().to_string(),
+            max_rate: rate(&string_map(&row["other_config"]), "max-rate"),
+            queues,
+        })
+    }
+}
+
+/// Members of a set column; a lone atom is a one-element set
+fn set_members(value: &Value) -> Vec<&Value> {
+    match value.as_array() {
+        Some(pair) if pair.len() == 2 && pair[0] == "set" => pair[1]
+            .as_array()
+            .map(|m| m.iter().collect())
+            .unwrap_or_default(),
+        _ if value.is_null() => Vec::new(),
+        _ => vec![value],
+    }
+}
+
+fn map_pairs(value: &Value) -> impl Iterator<Item = (&Value, &Value)> {
+    let pairs = match value.as_array() {
+        Some(pair) if pair.len() == 2 && pair[0] == "map" => pair[1].as_array(),
+        _ => None,
+    };
+    pairs.into_iter().flatten().filter_map(|pair| {
+        let pair = pair.as_array()?;
+        Some((pair.first()?, pair.get(1)?))
+    })
+}
+
+fn string_map(value: &Value) -> BTreeMap<String, String> {
+    map_pairs(value)
+        .filter_map(|(k, v)| Some((k.as_str()?.to_string(), v.as_str()?.to_string())))
+        .collect()
+}
+
+fn uuid_of(value: &Value) -> Option<String> {
+    match Atom::from_json(value)? {
+        Atom::Uuid(uuid) => Some(uuid),
+        _ => None,
+    }
+}
+
+fn uuids(value: &Value) -> Vec<String> {
+    set_members(value).into_iter().filter_map(uuid_of).collect()
+}
+
+fn vlans(value: &Value) -> Vec<u16> {
+    set_members(value)
+        .into_iter()
+        .filter_map(|v| u16::try_from(v.as_u64()?).ok())
+        .collect()
+}
+
+fn optional_str(value: &Value) -> Option<&str> {
+    set_members(value).first().and_then(|v| v.as_str())
+}
+
+/// Enum from its OVSDB string (the serde name)
+fn parse_enum<T: DeserializeOwned>(value: &str) -> Option<T> {
+    serde_json::from_value(Value::String(value.to_string())).ok()
+}
+
+fn enum_str<T: Serialize>(value: T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn snapshot() -> Snapshot {
+        let port = |uuid: &str, row: Value| (uuid.to_string(), row);
+        let all_ports: HashMap<String, Value> = [
+            port(
+                "p-vm",
+                json!({"name": "vi100", "interfaces": ["uuid", "i-vm"], "tag": 100,
+                       "trunks": ["set", []], "vlan_mode": ["set", []],
+                       "qos": ["uuid", "q1"], "bond_updelay": 0, "bond_downdelay": 0,
+                       "external_ids": ["map", [["op-dbus-managed", "true"], ["vm", "100"]]],
+                       "other_config": ["map", []]}),
+            ),
+            port(
+                "p-bond",
+                json!({"name": "bond0", "interfaces": ["set", [["uuid", "i-eth2"], ["uuid", "i-eth1"]]],
+                       "tag": ["set", []], "trunks": ["set", [20, 10]], "vlan_mode": "trunk",
+                       "bond_mode": "balance-tcp", "lacp": "active", "qos": ["set", []],
+                       "bond_updelay": 200, "bond_downdelay": 0,
+                       "external_ids": ["map", []], "other_config": ["map", [["lacp-time", "fast"]]]}),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let ports = all_ports
+            .iter()
+            .map(|(uuid, row)| {
+                (
+                    row["name"].as_str().unwrap().to_string(),
+                    (uuid.clone(), row.clone()),
+                )
+            })
+            .collect();
+
+        Snapshot {
+            bridge_uuid: "b1".to_string(),
+            bridge: json!({"name": "ovsbr0", "mirrors": ["uuid", "m1"],
+                           "external_ids": ["map", [["owner", "lab"]]], "other_config": ["map", []]}),
+            ports,
+            all_ports,
+            interfaces: [("i-vm", "vi100"), ("i-eth1", "eth1"), ("i-eth2", "eth2")]
+                .into_iter()
+                .map(|(u, n)| (u.to_string(), n.to_string()))
+                .collect(),
+            qos: [(
+                "q1".to_string(),
+                json!({"type": "linux-htb", "other_config": ["map", [["max-rate", "1000000000"]]],
+                       "queues": ["map", [[0, ["uuid", "qu0"]]]]}),
+            )]
+            .into_iter()
+            .collect(),
+            queues: [(
+                "qu0".to_string(),
+                json!({"other_config": ["map", [["min-rate", "100000000"], ["priority", "1"]]]}),
+            )]
+            .into_iter()
+            .collect(),
+            mirrors: [(
+                "m1".to_string(),
+                json!({"name": "span", "select_all": false, "select_src_port": ["uuid", "p-vm"],
+                       "select_dst_port": ["set", []], "select_vlan": ["set", []],
+                       "output_port": ["uuid", "p-bond"], "output_vlan": ["set", []]}),
+            )]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_options() {
+        let options = snapshot().options();
+
+        let vm = &options.ports["vi100"];
+        assert_eq!(vm.tag, Some(100));
+        assert_eq!(vm.trunks, None);
+        assert_eq!(
+            vm.external_ids,
+            Some(BTreeMap::from([("vm".into(), "100".into())]))
+        );
+        let qos = vm.qos.as_ref().unwrap();
+        assert_eq!(qos.max_rate, Some(1_000_000_000));
+        assert_eq!(qos.queues[&0].min_rate, Some(100_000_000));
+        assert_eq!(qos.queues[&0].priority, Some(1));
+
+        let bond = &options.ports["bond0"];
+        assert_eq!(bond.vlan_mode, Some(OvsVlanMode::Trunk));
+        let bond_options = bond.bond.as_ref().unwrap();
+        assert_eq!(bond_options.mode, Some(OvsBondMode::BalanceTcp));
+        assert_eq!(bond_options.lacp, Some(OvsLacp::Active));
+        assert_eq!(bond_options.updelay, Some(200));
+        assert_eq!(bond_options.downdelay, None);
+
+        let mirrors = options.mirrors.unwrap();
+        assert_eq!(mirrors[0].select_src_port, vec!["vi100".to_string()]);
+        assert_eq!(mirrors[0].output_port.as_deref(), Some("bond0"));
+    }
+
+    #[test]
+    fn test_in_sync_compares_declared_ports_only() {
+        let current = snapshot().options();
+        let desired: OvsBridgeOptions = serde_json::from_value(json!({
+            "ports": {
+                "bond0": {
+                    "trunks": [10, 20],
+                    "vlan_mode": "trunk",
+                    "bond": {"interfaces": ["eth1", "eth2"], "mode": "balance-tcp",
+                             "lacp": "active", "updelay": 200, "downdelay": 0},
+                    "other_config": {"lacp-time": "fast"}
+                }
+            },
+            "external_ids": {"owner": "lab"}
+        }))
+        .unwrap();
+        assert!(desired.in_sync(Some(&current)));
+
+        // An unset field on a declared port clears the column
+        let mut cleared = desired.clone();
+        cleared.ports.get_mut("bond0").unwrap().trunks = None;
+        assert!(!cleared.in_sync(Some(&current)));
+
+        // Undeclared port maps are unmanaged
+        let mut foreign = desired.clone();
+        foreign.ports.get_mut("bond0").unwrap().other_config = None;
+        assert!(foreign.in_sync(Some(&current)));
+
+        // Declared maps and mirrors are authoritative
+        let mut extra = desired.clone();
+        extra.external_ids = Some(BTreeMap::new());
+        assert!(!extra.in_sync(Some(&current)));
+        let mut port_extra = desired.clone();
+        port_extra.ports.get_mut("bond0").unwrap().other_config = Some(BTreeMap::new());
+        assert!(!port_extra.in_sync(Some(&current)));
+        let mut mirrors = desired;
+        mirrors.mirrors = Some(Vec::new());
+        assert!(!mirrors.in_sync(Some(&current)));
+    }
+
+    #[test]
+    fn test_port_row_and_validation() {
+        let port: OvsPortOptions = serde_json::from_value(json!({
+            "tag": 4096
+        }))
+        .unwrap();
+        assert!(port.validate().is_err());
+
+        let bond: OvsPortOptions = serde_json::from_value(json!({
+            "bond": {"interfaces": ["eth1"]}
+        }))
+        .unwrap();
+        assert!(bond.validate().is_err());
+
+        let port: OvsPortOptions = serde_json::from_value(json!({
+            "vlan_mode": "native-untagged",
+            "tag": 10,
+            "external_ids": {"role": "uplink"}
+        }))
+        .unwrap();
+        let mut txn = Transaction::new(DATABASE);
+        txn.update(
+            "Port",
+            vec![Condition::eq("name", "eth0")],
+            port.to_row(true, false),
+        );
+        let params = txn.to_params();
+        let row = &params[1]["row"];
+        assert_eq!(row["tag"], json!(["set", [10]]));
+        assert_eq!(row["vlan_mode"], json!(["set", ["native-untagged"]]));
+        assert_eq!(row["trunks"], json!(["set", []]));
+        assert_eq!(
+            row["external_ids"],
+            json!(["map", [["op-dbus-managed", "true"], ["role", "uplink"]]])
+        );
+        assert!(row.get("bond_mode").is_none());
+
+        // Undeclared maps are left alone, except for the marker on a new port
+        let port = OvsPortOptions {
+            tag: Some(10),
+            ..Default::default()
+        };
+        let mut txn = Transaction::new(DATABASE);
+        txn.update(
+            "Port",
+            vec![Condition::eq("name", "eth0")],
+            port.to_row(true, false),
+        );
+        txn.insert("Port", port.to_row(true, true));
+        let params = txn.to_params();
+        assert!(params[1]["row"].get("external_ids").is_none());
+        assert!(params[1]["row"].get("other_config").is_none());
+        assert_eq!(
+            params[2]["row"]["external_ids"],
+            json!(["map", [["op-dbus-managed", "true"]]])
+        );
+    }
+}
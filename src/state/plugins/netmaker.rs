@@ -240,7 +240,8 @@ impl StatePlugin for NetmakerPlugin {
         }
 
         // Check network membership changes
-        let current_networks = current.get("networks").and_then(|n| n.as_array()).unwrap_or(&vec![]);
+        let no_networks = Vec::new();
+        let current_networks = current.get("networks").and_then(|n| n.as_array()).unwrap_or(&no_networks);
         let desired_networks = desired.get("config").and_then(|c| c.get("default_network")).and_then(|n| n.as_str());
 
         if let Some(desired_network) = desired_networks {
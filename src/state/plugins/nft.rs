@@ -0,0 +1,1548 @@
+//! nft plugin - declarative nftables firewall over netlink
+//!
+//! Design
+//! - A declared table is owned wholesale: apply replaces its chains, sets and
+//!   rules, and every change of one apply is committed as a single nf_tables
+//!   transaction, so the kernel never runs a half-applied ruleset.
+//! - Owned tables carry an "op-dbus" comment. Tables without it belong to
+//!   other tools (firewalld, fail2ban, docker) and are only touched when a
+//!   table of the same name is declared.
+//! - Checkpoints capture the whole ruleset as replayable netlink messages;
+//!   rollback puts owned tables back exactly as they were, rules this plugin
+//!   can't express included.
+//! - Named sets can be filled from the static addresses of LXC containers.
+
+use crate::native::nftables::{
+    cmp_op, meta_key, payload_base, Batch, Chain, Expr, Family, Hook, Message, NftClient, Rule,
+    Ruleset, Set, Table, Verdict, CT_KEY_STATE, REG_1,
+};
+use crate::state::plugin::{
+    ApplyResult, Checkpoint, DiffMetadata, PluginCapabilities, StateAction, StateDiff, StatePlugin,
+};
+use crate::state::plugins::lxc::LxcPlugin;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Table comment marking op-dbus ownership
+const OWNER_COMMENT: &str = "op-dbus";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct NftConfig {
+    #[serde(default)]
+    pub tables: Vec<TableConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TableConfig {
+    #[serde(default = "default_family")]
+    pub family: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sets: Vec<SetConfig>,
+    #[serde(default)]
+    pub chains: Vec<ChainConfig>,
+    /// Reported by query for tables op-dbus owns; ignored in desired state
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub owned: bool,
+}
+
+fn default_family() -> String {
+    "inet".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChainConfig {
+    pub name: String,
+    /// filter, nat or route; base chains default to filter
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub chain_type: Option<String>,
+    /// prerouting, input, forward, output, postrouting (ingress/egress for netdev);
+    /// chains without a hook are only reachable by jump/goto
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hook: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// Netdev device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// accept or drop; base chains default to accept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    /// Kernel rules this plugin can't express, summarized (query only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub foreign_rules: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SetConfig {
+    pub name: String,
+    /// ipv4_addr, ipv6_addr, ether_addr, inet_service, inet_proto or ifname
+    #[serde(rename = "type")]
+    pub set_type: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub elements: Vec<String>,
+    /// Add the addresses of matching LXC containers to the elements
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_containers: Option<ContainerSource>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ContainerSource {
+    /// Container ids to include; every container when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ids: Vec<String>,
+    /// Only containers attached to this bridge
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<String>,
+}
+
+/// One rule; every match given must hold. Values take a leading "!" to negate
+/// and "@name" to look up a named set of the table.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RuleConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iifname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oifname: Option<String>,
+    /// Layer 4 protocol: tcp, udp, icmp, icmpv6, ... or a number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    /// Address or prefix, "10.0.0.0/8", "!fd00::1" or "@set"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saddr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daddr: Option<String>,
+    /// Needs a port protocol (tcp, udp, sctp, udplite)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sport: Option<PortMatch>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dport: Option<PortMatch>,
+    /// Matches if the connection is in any of these states:
+    /// invalid, established, related, new, untracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ct_state: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub counter: bool,
+    /// accept, drop, return, continue, "jump <chain>" or "goto <chain>";
+    /// none lets the packet continue to the next rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verdict: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum PortMatch {
+    Port(u16),
+    /// "!22", "@set" or "!@set"
+    Spec(String),
+}
+
+impl PortMatch {
+    fn spec(&self) -> String {
+        match self {
+            PortMatch::Port(port) => port.to_string(),
+            PortMatch::Spec(spec) => spec.clone(),
+        }
+    }
+}
+
+/// Key type and length of a set type, as nft registers them
+fn set_key(set_type: &str) -> Result<(u32, u32)> {
+    Ok(match set_type {
+        "ipv4_addr" => (7, 4),
+        "ipv6_addr" => (8, 16),
+        "ether_addr" => (9, 6),
+        "inet_proto" => (12, 1),
+        "inet_service" => (13, 2),
+        "ifname" => (41, 16),
+        other => bail!("Unsupported set type '{}'", other),
+    })
+}
+
+fn set_type_name(key_type: u32) -> Option<&'static str> {
+    Some(match key_type {
+        7 => "ipv4_addr",
+        8 => "ipv6_addr",
+        9 => "ether_addr",
+        12 => "inet_proto",
+        13 => "inet_service",
+        41 => "ifname",
+        _ => return None,
+    })
+}
+
+const PROTOCOLS: [(&str, u8); 9] = [
+    ("icmp", 1),
+    ("tcp", 6),
+    ("udp", 17),
+    ("gre", 47),
+    ("esp", 50),
+    ("ah", 51),
+    ("icmpv6", 58),
+    ("sctp", 132),
+    ("udplite", 136),
+];
+
+/// Protocols with ports at the start of the transport header
+const PORT_PROTOCOLS: [u8; 4] = [6, 17, 132, 136];
+
+fn protocol_number(name: &str) -> Result<u8> {
+    PROTOCOLS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, number)| *number)
+        .map_or_else(
+            || {
+                name.parse()
+                    .map_err(|_| anyhow!("Unknown protocol '{}'", name))
+            },
+            Ok,
+        )
+}
+
+fn protocol_name(number: u8) -> String {
+    PROTOCOLS
+        .iter()
+        .find(|(_, n)| *n == number)
+        .map_or_else(|| number.to_string(), |(name, _)| name.to_string())
+}
+
+const CT_STATES: [(&str, u32); 5] = [
+    ("invalid", 1),
+    ("established", 2),
+    ("related", 4),
+    ("new", 8),
+    ("untracked", 64),
+];
+
+fn hook_number(family: Family, hook: &str) -> Result<u32> {
+    let hooks: &[&str] = match family {
+        Family::Netdev => &["ingress", "egress"],
+        _ => &["prerouting", "input", "forward", "output", "postrouting"],
+    };
+    hooks
+        .iter()
+        .position(|h| *h == hook)
+        .map(|n| n as u32)
+        .ok_or_else(|| anyhow!("Unknown {} hook '{}'", family.as_str(), hook))
+}
+
+fn hook_name(family: Family, number: u32) -> String {
+    let hooks: &[&str] = match family {
+        Family::Netdev => &["ingress", "egress"],
+        _ => &["prerouting", "input", "forward", "output", "postrouting"],
+    };
+    hooks
+        .get(number as usize)
+        .map_or_else(|| number.to_string(), |h| h.to_string())
+}
+
+/// Interface names compare as NUL-terminated strings; "eth*" matches the prefix
+fn ifname_bytes(name: &str) -> Vec<u8> {
+    match name.strip_suffix('*') {
+        Some(prefix) => prefix.as_bytes().to_vec(),
+        None => {
+            let mut bytes = name.as_bytes().to_vec();
+            bytes.push(0);
+            bytes
+        }
+    }
+}
+
+fn ifname_from_bytes(bytes: &[u8]) -> String {
+    match bytes.iter().position(|b| *b == 0) {
+        Some(end) => String::from_utf8_lossy(&bytes[..end]).into_owned(),
+        None => format!("{}*", String::from_utf8_lossy(bytes)),
+    }
+}
+
+/// Set element key bytes for a set type
+fn element_key(set_type: &str, element: &str) -> Result<Vec<u8>> {
+    let key = match set_type {
+        "ipv4_addr" => element.parse::<Ipv4Addr>()?.octets().to_vec(),
+        "ipv6_addr" => element.parse::<Ipv6Addr>()?.octets().to_vec(),
+        "ether_addr" => {
+            let octets = element
+                .split(':')
+                .map(|octet| u8::from_str_radix(octet, 16))
+                .collect::<Result<Vec<_>, _>>()?;
+            if octets.len() != 6 {
+                bail!("'{}' is not a MAC address", element);
+            }
+            octets
+        }
+        "inet_proto" => vec![protocol_number(element)?],
+        "inet_service" => element.parse::<u16>()?.to_be_bytes().to_vec(),
+        "ifname" => {
+            let mut name = element.as_bytes().to_vec();
+            name.resize(16, 0);
+            name
+        }
+        other => bail!("Unsupported set type '{}'", other),
+    };
+    Ok(key)
+}
+
+fn element_string(set_type: &str, key: &[u8]) -> String {
+    match (set_type, key.len()) {
+        ("ipv4_addr", 4) => Ipv4Addr::from(<[u8; 4]>::try_from(key).unwrap()).to_string(),
+        ("ipv6_addr", 16) => Ipv6Addr::from(<[u8; 16]>::try_from(key).unwrap()).to_string(),
+        ("inet_service", 2) => u16::from_be_bytes([key[0], key[1]]).to_string(),
+        ("inet_proto", 1) => protocol_name(key[0]),
+        ("ifname", _) => ifname_from_bytes(key).trim_end_matches('*').to_string(),
+        _ => key
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":"),
+    }
+}
+
+/// A parsed "!"/"@set" prefixed match value
+struct Operand<'a> {
+    negate: bool,
+    set: Option<&'a str>,
+    value: &'a str,
+}
+
+fn operand(spec: &str) -> Operand<'_> {
+    let (negate, rest) = match spec.strip_prefix('!') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, spec),
+    };
+    Operand {
+        negate,
+        set: rest.strip_prefix('@'),
+        value: rest,
+    }
+}
+
+fn cmp(negate: bool, data: Vec<u8>) -> Expr {
+    Expr::Cmp {
+        sreg: REG_1,
+        op: if negate { cmp_op::NEQ } else { cmp_op::EQ },
+        data,
+    }
+}
+
+fn lookup(set: &str, negate: bool) -> Expr {
+    Expr::Lookup {
+        set: set.to_string(),
+        sreg: REG_1,
+        invert: negate,
+    }
+}
+
+fn negation(negate: bool) -> &'static str {
+    if negate {
+        "!"
+    } else {
+        ""
+    }
+}
+
+/// Rule compilation context: the table's family and set types
+struct Scope<'a> {
+    family: Family,
+    sets: &'a HashMap<String, String>,
+}
+
+impl Scope<'_> {
+    fn set_type(&self, name: &str) -> Result<&str> {
+        self.sets
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("Set @{} is not declared in the table", name))
+    }
+
+    fn compile(&self, rule: &RuleConfig) -> Result<Vec<Expr>> {
+        let mut exprs = Vec::new();
+
+        for (key, value) in [
+            (meta_key::IIFNAME, &rule.iifname),
+            (meta_key::OIFNAME, &rule.oifname),
+        ] {
+            let Some(value) = value else { continue };
+            let op = operand(value);
+            exprs.push(Expr::Meta { key, dreg: REG_1 });
+            match op.set {
+                Some(set) => exprs.push(lookup(set, op.negate)),
+                None => exprs.push(cmp(op.negate, ifname_bytes(op.value))),
+            }
+        }
+
+        let mut nfproto = None;
+        for (offsets, value) in [([12, 8], &rule.saddr), ([16, 24], &rule.daddr)] {
+            let Some(value) = value else { continue };
+            let op = operand(value);
+            let v6 = match op.set {
+                Some(set) => match self.set_type(set)? {
+                    "ipv4_addr" => false,
+                    "ipv6_addr" => true,
+                    other => bail!("Set @{} holds {}, not addresses", set, other),
+                },
+                None => op.value.contains(':'),
+            };
+            match (self.family, v6) {
+                (Family::Ip, false) | (Family::Ip6, true) => {}
+                (Family::Inet, _) => {
+                    let proto = if v6 { 10 } else { 2 };
+                    match nfproto {
+                        None => {
+                            exprs.push(Expr::Meta {
+                                key: meta_key::NFPROTO,
+                                dreg: REG_1,
+                            });
+                            exprs.push(cmp(false, vec![proto]));
+                            nfproto = Some(proto);
+                        }
+                        Some(existing) if existing != proto => {
+                            bail!("Rule mixes IPv4 and IPv6 addresses")
+                        }
+                        Some(_) => {}
+                    }
+                }
+                (family, _) => bail!(
+                    "{} address matches are not supported in {} tables",
+                    if v6 { "IPv6" } else { "IPv4" },
+                    family.as_str()
+                ),
+            }
+
+            let len = if v6 { 16 } else { 4 };
+            exprs.push(Expr::Payload {
+                base: payload_base::NETWORK,
+                offset: offsets[v6 as usize],
+                len,
+                dreg: REG_1,
+            });
+            if let Some(set) = op.set {
+                exprs.push(lookup(set, op.negate));
+                continue;
+            }
+
+            let (address, prefix) = match op.value.split_once('/') {
+                Some((address, prefix)) => (address, Some(prefix.parse::<u32>()?)),
+                None => (op.value, None),
+            };
+            let address: IpAddr = address
+                .parse()
+                .with_context(|| format!("Invalid address '{}'", op.value))?;
+            let bytes = match address {
+                IpAddr::V4(a) => a.octets().to_vec(),
+                IpAddr::V6(a) => a.octets().to_vec(),
+            };
+            let prefix = prefix.unwrap_or(len * 8);
+            if prefix > len * 8 {
+                bail!("Invalid prefix length in '{}'", op.value);
+            }
+            if prefix < len * 8 {
+                let mask = prefix_mask(prefix, len as usize);
+                exprs.push(Expr::Bitwise {
+                    sreg: REG_1,
+                    dreg: REG_1,
+                    xor: vec![0; mask.len()],
+                    mask: mask.clone(),
+                });
+                let network = bytes.iter().zip(&mask).map(|(b, m)| b & m).collect();
+                exprs.push(cmp(op.negate, network));
+            } else {
+                exprs.push(cmp(op.negate, bytes));
+            }
+        }
+
+        let protocol = rule.protocol.as_deref().map(protocol_number).transpose()?;
+        if let Some(protocol) = protocol {
+            exprs.push(Expr::Meta {
+                key: meta_key::L4PROTO,
+                dreg: REG_1,
+            });
+            exprs.push(cmp(false, vec![protocol]));
+        }
+
+        for (offset, port) in [(0, &rule.sport), (2, &rule.dport)] {
+            let Some(port) = port else { continue };
+            if !protocol.is_some_and(|p| PORT_PROTOCOLS.contains(&p)) {
+                bail!("Port matches need protocol tcp, udp, sctp or udplite");
+            }
+            let spec = port.spec();
+            let op = operand(&spec);
+            exprs.push(Expr::Payload {
+                base: payload_base::TRANSPORT,
+                offset,
+                len: 2,
+                dreg: REG_1,
+            });
+            match op.set {
+                Some(set) => {
+                    if self.set_type(set)? != "inet_service" {
+                        bail!("Set @{} does not hold ports", set);
+                    }
+                    exprs.push(lookup(set, op.negate));
+                }
+                None => {
+                    let number: u16 = op
+                        .value
+                        .parse()
+                        .with_context(|| format!("Invalid port '{}'", spec))?;
+                    exprs.push(cmp(op.negate, number.to_be_bytes().to_vec()));
+                }
+            }
+        }
+
+        if let Some(states) = &rule.ct_state {
+            let mut bits = 0u32;
+            for state in states {
+                bits |= CT_STATES
+                    .iter()
+                    .find(|(name, _)| name == state)
+                    .map(|(_, bit)| *bit)
+                    .ok_or_else(|| anyhow!("Unknown ct state '{}'", state))?;
+            }
+            // Conntrack state lives in host byte order in the register
+            exprs.push(Expr::Ct {
+                key: CT_KEY_STATE,
+                dreg: REG_1,
+            });
+            exprs.push(Expr::Bitwise {
+                sreg: REG_1,
+                dreg: REG_1,
+                mask: bits.to_ne_bytes().to_vec(),
+                xor: vec![0; 4],
+            });
+            exprs.push(cmp(true, vec![0; 4]));
+        }
+
+        if rule.counter {
+            exprs.push(Expr::Counter {
+                packets: 0,
+                bytes: 0,
+            });
+        }
+
+        if let Some(verdict) = &rule.verdict {
+            exprs.push(Expr::Verdict(parse_verdict(verdict)?));
+        }
+        Ok(exprs)
+    }
+}
+
+fn prefix_mask(prefix: u32, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            let bits = prefix.saturating_sub(i as u32 * 8).min(8);
+            (0xff00u16 >> bits) as u8
+        })
+        .collect()
+}
+
+fn parse_verdict(verdict: &str) -> Result<Verdict> {
+    let mut words = verdict.split_whitespace();
+    Ok(match (words.next(), words.next(), words.next()) {
+        (Some("accept"), None, _) => Verdict::Accept,
+        (Some("drop"), None, _) => Verdict::Drop,
+        (Some("return"), None, _) => Verdict::Return,
+        (Some("continue"), None, _) => Verdict::Continue,
+        (Some("jump"), Some(chain), None) => Verdict::Jump(chain.to_string()),
+        (Some("goto"), Some(chain), None) => Verdict::Goto(chain.to_string()),
+        _ => bail!("Unknown verdict '{}'", verdict),
+    })
+}
+
+fn verdict_string(verdict: &Verdict) -> String {
+    match verdict {
+        Verdict::Accept => "accept".to_string(),
+        Verdict::Drop => "drop".to_string(),
+        Verdict::Return => "return".to_string(),
+        Verdict::Continue => "continue".to_string(),
+        Verdict::Jump(chain) => format!("jump {}", chain),
+        Verdict::Goto(chain) => format!("goto {}", chain),
+    }
+}
+
+/// Read compiled expressions back into a rule; None if they use anything
+/// `Scope::compile` doesn't produce
+fn decode_rule(exprs: &[Expr], comment: Option<String>) -> Option<RuleConfig> {
+    let mut rule = RuleConfig {
+        comment,
+        ..Default::default()
+    };
+    let mut rest = exprs;
+    while !rest.is_empty() {
+        let consumed = match rest {
+            [Expr::Meta { key, dreg: REG_1 }, next, ..]
+                if *key == meta_key::IIFNAME || *key == meta_key::OIFNAME =>
+            {
+                let value = match next {
+                    Expr::Cmp {
+                        sreg: REG_1,
+                        op,
+                        data,
+                    } if *op <= cmp_op::NEQ => {
+                        format!(
+                            "{}{}",
+                            negation(*op == cmp_op::NEQ),
+                            ifname_from_bytes(data)
+                        )
+                    }
+                    Expr::Lookup {
+                        set,
+                        sreg: REG_1,
+                        invert,
+                    } => format!("{}@{}", negation(*invert), set),
+                    _ => return None,
+                };
+                let slot = if *key == meta_key::IIFNAME {
+                    &mut rule.iifname
+                } else {
+                    &mut rule.oifname
+                };
+                *slot = Some(value);
+                2
+            }
+            [Expr::Meta {
+                key: meta_key::NFPROTO,
+                dreg: REG_1,
+            }, Expr::Cmp {
+                sreg: REG_1,
+                op: cmp_op::EQ,
+                ..
+            }, ..] => 2,
+            [Expr::Meta {
+                key: meta_key::L4PROTO,
+                dreg: REG_1,
+            }, Expr::Cmp {
+                sreg: REG_1,
+                op: cmp_op::EQ,
+                data,
+            }, ..]
+                if data.len() == 1 =>
+            {
+                rule.protocol = Some(protocol_name(data[0]));
+                2
+            }
+            [Expr::Payload {
+                base: payload_base::NETWORK,
+                offset,
+                len,
+                dreg: REG_1,
+            }, tail @ ..] => {
+                let slot = match (*offset, *len) {
+                    (12, 4) | (8, 16) => &mut rule.saddr,
+                    (16, 4) | (24, 16) => &mut rule.daddr,
+                    _ => return None,
+                };
+                let (value, used) = decode_address(*len as usize, tail)?;
+                *slot = Some(value);
+                1 + used
+            }
+            [Expr::Payload {
+                base: payload_base::TRANSPORT,
+                offset,
+                len: 2,
+                dreg: REG_1,
+            }, next, ..]
+                if *offset <= 2 && offset % 2 == 0 =>
+            {
+                let port = match next {
+                    Expr::Cmp {
+                        sreg: REG_1,
+                        op: cmp_op::EQ,
+                        data,
+                    } if data.len() == 2 => PortMatch::Port(u16::from_be_bytes([data[0], data[1]])),
+                    Expr::Cmp {
+                        sreg: REG_1,
+                        op: cmp_op::NEQ,
+                        data,
+                    } if data.len() == 2 => {
+                        PortMatch::Spec(format!("!{}", u16::from_be_bytes([data[0], data[1]])))
+                    }
+                    Expr::Lookup {
+                        set,
+                        sreg: REG_1,
+                        invert,
+                    } => PortMatch::Spec(format!("{}@{}", negation(*invert), set)),
+                    _ => return None,
+                };
+                if *offset == 0 {
+                    rule.sport = Some(port);
+                } else {
+                    rule.dport = Some(port);
+                }
+                2
+            }
+            [Expr::Ct {
+                key: CT_KEY_STATE,
+                dreg: REG_1,
+            }, Expr::Bitwise {
+                sreg: REG_1,
+                dreg: REG_1,
+                mask,
+                xor,
+            }, Expr::Cmp {
+                sreg: REG_1,
+                op: cmp_op::NEQ,
+                data,
+            }, ..]
+                if mask.len() == 4
+                    && xor.iter().all(|b| *b == 0)
+                    && data.iter().all(|b| *b == 0) =>
+            {
+                let bits = u32::from_ne_bytes([mask[0], mask[1], mask[2], mask[3]]);
+                let states: Vec<String> = CT_STATES
+                    .iter()
+                    .filter(|(_, bit)| bits & bit != 0)
+                    .map(|(name, _)| name.to_string())
+                    .collect();
+                if states.is_empty() {
+                    return None;
+                }
+                rule.ct_state = Some(states);
+                3
+            }
+            [Expr::Counter { .. }, ..] => {
+                rule.counter = true;
+                1
+            }
+            [Expr::Verdict(verdict)] => {
+                rule.verdict = Some(verdict_string(verdict));
+                1
+            }
+            _ => return None,
+        };
+        rest = &rest[consumed..];
+    }
+    Some(rule)
+}
+
+/// Address match after a network payload load: "[!]addr[/prefix]" or "[!]@set"
+fn decode_address(len: usize, tail: &[Expr]) -> Option<(String, usize)> {
+    let format = |bytes: &[u8]| -> Option<IpAddr> {
+        match bytes.len() {
+            4 => Some(IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+            16 => Some(IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+            _ => None,
+        }
+    };
+    match tail {
+        [Expr::Lookup {
+            set,
+            sreg: REG_1,
+            invert,
+        }, ..] => Some((format!("{}@{}", negation(*invert), set), 1)),
+        [Expr::Cmp {
+            sreg: REG_1,
+            op,
+            data,
+        }, ..]
+            if *op <= cmp_op::NEQ && data.len() == len =>
+        {
+            Some((
+                format!("{}{}", negation(*op == cmp_op::NEQ), format(data)?),
+                1,
+            ))
+        }
+        [Expr::Bitwise {
+            sreg: REG_1,
+            dreg: REG_1,
+            mask,
+            xor,
+        }, Expr::Cmp {
+            sreg: REG_1,
+            op,
+            data,
+        }, ..]
+            if *op <= cmp_op::NEQ
+                && mask.len() == len
+                && data.len() == len
+                && xor.iter().all(|b| *b == 0) =>
+        {
+            let prefix = mask.iter().map(|b| b.leading_ones()).sum::<u32>();
+            if prefix_mask(prefix, len) != *mask {
+                return None;
+            }
+            Some((
+                format!(
+                    "{}{}/{}",
+                    negation(*op == cmp_op::NEQ),
+                    format(data)?,
+                    prefix
+                ),
+                2,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Short description of a rule this plugin can't express
+fn summarize(rule: &Rule) -> String {
+    let names: Vec<&str> = rule.expressions.iter().map(Expr::name).collect();
+    match &rule.comment {
+        Some(comment) => format!("{} # {}", names.join(" "), comment),
+        None => names.join(" "),
+    }
+}
+
+impl TableConfig {
+    fn family(&self) -> Result<Family> {
+        Family::parse(&self.family)
+    }
+
+    fn resource(&self) -> String {
+        format!("{}/{}", self.family, self.name)
+    }
+
+    fn set_types(&self) -> HashMap<String, String> {
+        self.sets
+            .iter()
+            .map(|set| (set.name.clone(), set.set_type.clone()))
+            .collect()
+    }
+
+    /// Validated form with defaults filled in and every value spelled the way
+    /// the kernel reports it back, so it compares equal to the queried table
+    fn canonical(&self) -> Result<TableConfig> {
+        let family = self.family()?;
+        let set_types = self.set_types();
+        let scope = Scope {
+            family,
+            sets: &set_types,
+        };
+        let context = |what: &str| format!("{} in table {}", what, self.resource());
+
+        let mut sets = Vec::new();
+        for set in &self.sets {
+            set_key(&set.set_type).with_context(|| context(&format!("set {}", set.name)))?;
+            let keys: BTreeSet<Vec<u8>> = set
+                .elements
+                .iter()
+                .map(|e| element_key(&set.set_type, e))
+                .collect::<Result<_>>()
+                .with_context(|| context(&format!("set {}", set.name)))?;
+            sets.push(SetConfig {
+                name: set.name.clone(),
+                set_type: set.set_type.clone(),
+                elements: keys
+                    .iter()
+                    .map(|key| element_string(&set.set_type, key))
+                    .collect(),
+                from_containers: None,
+            });
+        }
+        sets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let chain_names: HashSet<&str> = self.chains.iter().map(|c| c.name.as_str()).collect();
+        let mut chains = Vec::new();
+        for chain in &self.chains {
+            let what = format!("chain {}", chain.name);
+            let mut canonical = ChainConfig {
+                name: chain.name.clone(),
+                chain_type: None,
+                hook: chain.hook.clone(),
+                priority: None,
+                device: None,
+                policy: None,
+                rules: Vec::new(),
+                foreign_rules: Vec::new(),
+            };
+            match &chain.hook {
+                Some(hook) => {
+                    hook_number(family, hook).with_context(|| context(&what))?;
+                    canonical.chain_type = Some(
+                        chain
+                            .chain_type
+                            .clone()
+                            .unwrap_or_else(|| "filter".to_string()),
+                    );
+                    canonical.priority = Some(chain.priority.unwrap_or(0));
+                    canonical.device = chain.device.clone();
+                    let policy = chain.policy.as_deref().unwrap_or("accept");
+                    if policy != "accept" && policy != "drop" {
+                        bail!(
+                            "Chain policy must be accept or drop, not '{}' ({})",
+                            policy,
+                            context(&what)
+                        );
+                    }
+                    canonical.policy = Some(policy.to_string());
+                }
+                None if chain.chain_type.is_some()
+                    || chain.priority.is_some()
+                    || chain.policy.is_some() =>
+                {
+                    bail!("type, priority and policy need a hook ({})", context(&what));
+                }
+                None => {}
+            }
+
+            for (index, rule) in chain.rules.iter().enumerate() {
+                let what = format!("rule {} of chain {}", index, chain.name);
+                let exprs = scope.compile(rule).with_context(|| context(&what))?;
+                if let Some(Verdict::Jump(target) | Verdict::Goto(target)) =
+                    exprs.iter().find_map(|e| match e {
+                        Expr::Verdict(v) => Some(v.clone()),
+                        _ => None,
+                    })
+                {
+                    if !chain_names.contains(target.as_str()) {
+                        bail!("Unknown chain '{}' ({})", target, context(&what));
+                    }
+                }
+                let decoded = decode_rule(&exprs, rule.comment.clone())
+                    .ok_or_else(|| anyhow!("Rule does not round trip ({})", context(&what)))?;
+                canonical.rules.push(decoded);
+            }
+            chains.push(canonical);
+        }
+        chains.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(TableConfig {
+            family: family.as_str().to_string(),
+            name: self.name.clone(),
+            sets,
+            chains,
+            owned: false,
+        })
+    }
+
+    /// Queue the messages creating this table and its contents
+    fn build(&self, batch: &mut Batch) -> Result<()> {
+        let family = self.family()?;
+        let set_types = self.set_types();
+        let scope = Scope {
+            family,
+            sets: &set_types,
+        };
+
+        batch.add_table(&Table {
+            family,
+            name: self.name.clone(),
+            flags: 0,
+            comment: Some(OWNER_COMMENT.to_string()),
+        });
+        // Chains first so jumps resolve, sets before the rules that look them up
+        for chain in &self.chains {
+            let hook = match &chain.hook {
+                Some(hook) => Some(Hook {
+                    num: hook_number(family, hook)?,
+                    priority: chain.priority.unwrap_or(0),
+                    device: chain.device.clone(),
+                }),
+                None => None,
+            };
+            batch.add_chain(&Chain {
+                family,
+                table: self.name.clone(),
+                name: chain.name.clone(),
+                policy: hook.as_ref().map(|_| match chain.policy.as_deref() {
+                    Some("drop") => Verdict::NF_DROP,
+                    _ => Verdict::NF_ACCEPT,
+                }),
+                chain_type: hook.as_ref().map(|_| {
+                    chain
+                        .chain_type
+                        .clone()
+                        .unwrap_or_else(|| "filter".to_string())
+                }),
+                hook,
+            });
+        }
+        for set in &self.sets {
+            let (key_type, key_len) = set_key(&set.set_type)?;
+            batch.add_set(&Set {
+                family,
+                table: self.name.clone(),
+                name: set.name.clone(),
+                flags: 0,
+                key_type,
+                key_len,
+            });
+            let keys = set
+                .elements
+                .iter()
+                .map(|e| element_key(&set.set_type, e))
+                .collect::<Result<Vec<_>>>()?;
+            batch.add_elements(family, &self.name, &set.name, &keys);
+        }
+        for chain in &self.chains {
+            for rule in &chain.rules {
+                batch.add_rule(&Rule {
+                    family,
+                    table: self.name.clone(),
+                    chain: chain.name.clone(),
+                    expressions: scope.compile(rule)?,
+                    comment: rule.comment.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Queried view of the kernel ruleset
+fn tables_of(ruleset: &Ruleset) -> Vec<TableConfig> {
+    ruleset
+        .tables
+        .iter()
+        .map(|table| {
+            let in_table =
+                |family: Family, name: &str| family == table.family && name == table.name;
+            let sets = ruleset
+                .sets
+                .iter()
+                .filter(|set| in_table(set.family, &set.table))
+                // Anonymous sets belong to the rule that created them
+                .filter(|set| !set.name.starts_with("__"))
+                .map(|set| {
+                    let set_type = set_type_name(set.key_type)
+                        .map_or_else(|| format!("type {}", set.key_type), str::to_string);
+                    let mut keys = ruleset
+                        .elements
+                        .get(&(set.family, set.table.clone(), set.name.clone()))
+                        .cloned()
+                        .unwrap_or_default();
+                    keys.sort();
+                    keys.dedup();
+                    SetConfig {
+                        elements: keys.iter().map(|k| element_string(&set_type, k)).collect(),
+                        name: set.name.clone(),
+                        set_type,
+                        from_containers: None,
+                    }
+                })
+                .collect();
+
+            let mut chains: Vec<ChainConfig> = ruleset
+                .chains
+                .iter()
+                .filter(|chain| in_table(chain.family, &chain.table))
+                .map(|chain| {
+                    let mut config = ChainConfig {
+                        name: chain.name.clone(),
+                        chain_type: chain.hook.as_ref().and(chain.chain_type.clone()),
+                        hook: chain.hook.as_ref().map(|h| hook_name(table.family, h.num)),
+                        priority: chain.hook.as_ref().map(|h| h.priority),
+                        device: chain.hook.as_ref().and_then(|h| h.device.clone()),
+                        policy: chain.hook.as_ref().map(|_| {
+                            match chain.policy {
+                                Some(Verdict::NF_DROP) => "drop",
+                                _ => "accept",
+                            }
+                            .to_string()
+                        }),
+                        rules: Vec::new(),
+                        foreign_rules: Vec::new(),
+                    };
+                    for rule in ruleset
+                        .rules
+                        .iter()
+                        .filter(|r| in_table(r.family, &r.table) && r.chain == chain.name)
+                    {
+                        match decode_rule(&rule.expressions, rule.comment.clone()) {
+                            Some(decoded) => config.rules.push(decoded),
+                            None => config.foreign_rules.push(summarize(rule)),
+                        }
+                    }
+                    config
+                })
+                .collect();
+            chains.sort_by(|a, b| a.name.cmp(&b.name));
+
+            TableConfig {
+                family: table.family.as_str().to_string(),
+                name: table.name.clone(),
+                sets,
+                chains,
+                owned: table.comment.as_deref() == Some(OWNER_COMMENT),
+            }
+        })
+        .collect()
+}
+
+/// Actions turning `current` into `desired` (both canonical)
+fn plan(current: &[TableConfig], desired: &[TableConfig]) -> Result<Vec<StateAction>> {
+    let mut actions = Vec::new();
+    for table in desired {
+        let existing = current
+            .iter()
+            .find(|t| t.family == table.family && t.name == table.name);
+        match existing {
+            None => actions.push(StateAction::Create {
+                resource: table.resource(),
+                config: serde_json::to_value(table)?,
+            }),
+            Some(existing) if !same_table(existing, table) => actions.push(StateAction::Modify {
+                resource: table.resource(),
+                changes: serde_json::to_value(table)?,
+            }),
+            Some(_) => {}
+        }
+    }
+    for table in current.iter().filter(|t| t.owned) {
+        if !desired
+            .iter()
+            .any(|t| t.family == table.family && t.name == table.name)
+        {
+            actions.push(StateAction::Delete {
+                resource: table.resource(),
+            });
+        }
+    }
+    Ok(actions)
+}
+
+/// Tables compare equal regardless of who owns them now; adopting a foreign
+/// table with identical contents still has to mark it as owned
+fn same_table(current: &TableConfig, desired: &TableConfig) -> bool {
+    current.owned
+        && TableConfig {
+            owned: false,
+            ..current.clone()
+        } == *desired
+}
+
+pub struct NftPlugin;
+
+impl NftPlugin {
+    pub fn new() -> Self {
+        Self
+    }
+
+    async fn with_client<T, F>(f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut NftClient) -> Result<T> + Send + 'static,
+    {
+        tokio::task::spawn_blocking(move || f(&mut NftClient::connect()?)).await?
+    }
+
+    async fn query(&self) -> Result<NftConfig> {
+        let ruleset = Self::with_client(|client| client.ruleset()).await?;
+        Ok(NftConfig {
+            tables: tables_of(&ruleset),
+        })
+    }
+
+    /// Desired tables with container addresses filled in, canonicalized
+    async fn resolve(&self, desired: &NftConfig) -> Result<Vec<TableConfig>> {
+        let needs_containers = desired
+            .tables
+            .iter()
+            .flat_map(|t| &t.sets)
+            .any(|s| s.from_containers.is_some());
+        let containers = if needs_containers {
+            LxcPlugin::new().container_addresses().await?
+        } else {
+            Vec::new()
+        };
+
+        let mut tables = Vec::new();
+        for table in &desired.tables {
+            let mut table = table.clone();
+            for set in &mut table.sets {
+                let Some(source) = &set.from_containers else {
+                    continue;
+                };
+                let want_v6 = match set.set_type.as_str() {
+                    "ipv4_addr" => false,
+                    "ipv6_addr" => true,
+                    other => bail!(
+                        "Set {} fills from containers but holds {}, not addresses",
+                        set.name,
+                        other
+                    ),
+                };
+                for (container, addresses) in &containers {
+                    let selected = (source.ids.is_empty() || source.ids.contains(&container.id))
+                        && source
+                            .bridge
+                            .as_ref()
+                            .is_none_or(|b| *b == container.bridge);
+                    if !selected {
+                        continue;
+                    }
+                    set.elements.extend(
+                        addresses
+                            .iter()
+                            .filter(|a| a.is_ipv6() == want_v6)
+                            .map(IpAddr::to_string),
+                    );
+                }
+            }
+            tables.push(table.canonical()?);
+        }
+        Ok(tables)
+    }
+}
+
+impl Default for NftPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_resource(resource: &str) -> Result<(Family, String)> {
+    let (family, name) = resource
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Invalid nft resource '{}'", resource))?;
+    Ok((Family::parse(family)?, name.to_string()))
+}
+
+#[async_trait]
+impl StatePlugin for NftPlugin {
+    fn name(&self) -> &str {
+        "nft"
+    }
+
+    fn version(&self) -> &str {
+        "1.0.0"
+    }
+
+    fn is_available(&self) -> bool {
+        NftClient::connect().is_ok()
+    }
+
+    fn unavailable_reason(&self) -> String {
+        "Cannot open an nfnetlink socket - is nf_tables available?".to_string()
+    }
+
+    async fn query_current_state(&self) -> Result<Value> {
+        Ok(serde_json::to_value(self.query().await?)?)
+    }
+
+    async fn calculate_diff(&self, current: &Value, desired: &Value) -> Result<StateDiff> {
+        let current_config: NftConfig = serde_json::from_value(current.clone())?;
+        let desired_config: NftConfig = serde_json::from_value(desired.clone())?;
+        let desired_tables = self.resolve(&desired_config).await?;
+        let actions = plan(&current_config.tables, &desired_tables)?;
+
+        Ok(StateDiff {
+            plugin: self.name().to_string(),
+            actions,
+            metadata: DiffMetadata {
+                timestamp: chrono::Utc::now().timestamp(),
+                current_hash: format!("{:x}", md5::compute(serde_json::to_string(current)?)),
+                desired_hash: format!("{:x}", md5::compute(serde_json::to_string(desired)?)),
+            },
+        })
+    }
+
+    async fn apply_state(&self, diff: &StateDiff) -> Result<ApplyResult> {
+        let mut batch = Batch::new();
+        let mut changes = Vec::new();
+        for action in &diff.actions {
+            match action {
+                StateAction::Create { resource, config }
+                | StateAction::Modify {
+                    resource,
+                    changes: config,
+                } => {
+                    let table: TableConfig = serde_json::from_value(config.clone())?;
+                    let family = table.family()?;
+                    // Add-then-delete empties the table whether or not it exists
+                    batch.add_table(&Table {
+                        family,
+                        name: table.name.clone(),
+                        flags: 0,
+                        comment: None,
+                    });
+                    batch.delete_table(family, &table.name);
+                    table.build(&mut batch)?;
+                    changes.push(format!("Replaced table {}", resource));
+                }
+                StateAction::Delete { resource } => {
+                    let (family, name) = parse_resource(resource)?;
+                    batch.delete_table(family, &name);
+                    changes.push(format!("Deleted table {}", resource));
+                }
+                StateAction::NoOp { .. } => {}
+            }
+        }
+
+        if batch.is_empty() {
+            return Ok(ApplyResult {
+                success: true,
+                changes_applied: changes,
+                errors: Vec::new(),
+                checkpoint: None,
+            });
+        }
+
+        let messages = batch.len();
+        match Self::with_client(move |client| client.commit(&batch)).await {
+            Ok(()) => {
+                log::info!("Committed nftables transaction ({} messages)", messages);
+                Ok(ApplyResult {
+                    success: true,
+                    changes_applied: changes,
+                    errors: Vec::new(),
+                    checkpoint: None,
+                })
+            }
+            Err(e) => Ok(ApplyResult {
+                success: false,
+                changes_applied: Vec::new(),
+                errors: vec![format!("{:#}", e)],
+                checkpoint: None,
+            }),
+        }
+    }
+
+    async fn verify_state(&self, desired: &Value) -> Result<bool> {
+        let desired_config: NftConfig = serde_json::from_value(desired.clone())?;
+        let desired_tables = self.resolve(&desired_config).await?;
+        let current = self.query().await?;
+        Ok(plan(&current.tables, &desired_tables)?.is_empty())
+    }
+
+    async fn create_checkpoint(&self) -> Result<Checkpoint> {
+        let messages = Self::with_client(|client| client.capture()).await?;
+        let encoded: Vec<Value> = messages
+            .iter()
+            .map(|m| {
+                json!({
+                    "kind": m.kind,
+                    "family": m.family,
+                    "flags": m.flags,
+                    "attrs": BASE64.encode(&m.attrs),
+                })
+            })
+            .collect();
+
+        Ok(Checkpoint {
+            id: format!("nft-{}", chrono::Utc::now().timestamp()),
+            plugin: self.name().to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            state_snapshot: self.query_current_state().await?,
+            backend_checkpoint: Some(json!({ "messages": encoded })),
+        })
+    }
+
+    async fn rollback(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let messages = checkpoint
+            .backend_checkpoint
+            .as_ref()
+            .and_then(|b| b.get("messages"))
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("Checkpoint {} has no nftables ruleset", checkpoint.id))?
+            .iter()
+            .map(|m| {
+                Ok(Message {
+                    kind: m["kind"].as_u64().context("message kind")? as u16,
+                    family: m["family"].as_u64().context("message family")? as u8,
+                    flags: m["flags"].as_u64().unwrap_or(0) as u16,
+                    attrs: BASE64.decode(m["attrs"].as_str().context("message attrs")?)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let current = self.query().await?;
+        let present: HashSet<(u8, String)> = current
+            .tables
+            .iter()
+            .filter_map(|t| Some((t.family().ok()?.code(), t.name.clone())))
+            .collect();
+        let owned: HashSet<(u8, String)> = current
+            .tables
+            .iter()
+            .filter(|t| t.owned)
+            .filter_map(|t| Some((t.family().ok()?.code(), t.name.clone())))
+            .collect();
+
+        // Tables op-dbus owns now go back to their checkpointed contents (or
+        // away), and owned tables removed since the checkpoint come back;
+        // everyone else's tables are left alone
+        let restore: HashSet<(u8, String)> = messages
+            .iter()
+            .filter_map(|m| Table::decode(m).map(|t| (m, t)))
+            .filter_map(|(m, table)| {
+                let key = (m.family, table.name);
+                let was_owned = table.comment.as_deref() == Some(OWNER_COMMENT);
+                (owned.contains(&key) || (was_owned && !present.contains(&key))).then_some(key)
+            })
+            .collect();
+
+        let mut batch = Batch::new();
+        for (family, name) in &owned {
+            if let Some(family) = Family::from_code(*family) {
+                batch.delete_table(family, name);
+            }
+        }
+        for message in messages {
+            if message.table().is_some_and(|key| restore.contains(&key)) {
+                batch.push(message);
+            }
+        }
+
+        let restored = restore.len();
+        Self::with_client(move |client| client.commit(&batch)).await?;
+        log::info!(
+            "Rolled nftables back to checkpoint {} ({} tables restored)",
+            checkpoint.id,
+            restored
+        );
+        Ok(())
+    }
+
+    fn capabilities(&self) -> PluginCapabilities {
+        PluginCapabilities {
+            supports_rollback: true,
+            supports_checkpoints: true,
+            supports_verification: true,
+            atomic_operations: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> TableConfig {
+        serde_json::from_value(json!({
+            "name": "opdbus",
+            "sets": [
+                {"name": "containers", "type": "ipv4_addr", "elements": ["10.0.0.7", "10.0.0.5", "10.0.0.5"]},
+                {"name": "web", "type": "inet_service", "elements": ["443", "80"]}
+            ],
+            "chains": [
+                {"name": "input", "hook": "input", "policy": "drop", "rules": [
+                    {"ct_state": ["related", "established"], "verdict": "accept"},
+                    {"iifname": "lo", "verdict": "accept"},
+                    {"protocol": "tcp", "dport": 22, "saddr": "10.0.0.0/8", "counter": true,
+                     "verdict": "accept", "comment": "ssh"},
+                    {"protocol": "tcp", "dport": "@web", "saddr": "!@containers", "verdict": "jump web"},
+                    {"iifname": "veth*", "daddr": "fd00::1", "verdict": "drop"}
+                ]},
+                {"name": "web", "rules": [{"protocol": "udp", "sport": "!53"}]}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rules_round_trip_through_expressions() {
+        let canonical = table().canonical().unwrap();
+        assert_eq!(canonical.family, "inet");
+        assert_eq!(canonical.sets[0].elements, vec!["10.0.0.5", "10.0.0.7"]);
+        assert_eq!(canonical.sets[1].elements, vec!["80", "443"]);
+
+        let input = &canonical.chains[0];
+        assert_eq!(input.chain_type.as_deref(), Some("filter"));
+        assert_eq!(input.priority, Some(0));
+        let rules = &input.rules;
+        // ct states come back in bit order
+        assert_eq!(
+            rules[0].ct_state,
+            Some(vec!["established".to_string(), "related".to_string()])
+        );
+        assert_eq!(rules[2].dport, Some(PortMatch::Port(22)));
+        assert_eq!(rules[2].saddr.as_deref(), Some("10.0.0.0/8"));
+        assert!(rules[2].counter);
+        assert_eq!(rules[3].saddr.as_deref(), Some("!@containers"));
+        assert_eq!(rules[3].dport, Some(PortMatch::Spec("@web".to_string())));
+        assert_eq!(rules[4].iifname.as_deref(), Some("veth*"));
+        assert_eq!(rules[4].daddr.as_deref(), Some("fd00::1"));
+        assert_eq!(
+            canonical.chains[1].rules[0].sport,
+            Some(PortMatch::Spec("!53".to_string()))
+        );
+        assert_eq!(canonical.chains[1].rules[0].verdict, None);
+
+        // Canonical form is a fixed point
+        assert_eq!(canonical.canonical().unwrap(), canonical);
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        let reject = |rule: Value| {
+            let mut table = table();
+            table.chains[1].rules = vec![serde_json::from_value(rule).unwrap()];
+            table.canonical().is_err()
+        };
+        assert!(reject(json!({"dport": 22})));
+        assert!(reject(json!({"protocol": "icmp", "dport": 22})));
+        assert!(reject(json!({"saddr": "@missing"})));
+        assert!(reject(json!({"saddr": "@web"})));
+        assert!(reject(json!({"saddr": "10.0.0.1", "daddr": "fd00::1"})));
+        assert!(reject(json!({"verdict": "jump nowhere"})));
+        assert!(reject(json!({"ct_state": ["bogus"]})));
+
+        let mut ip6 = table();
+        ip6.family = "ip6".to_string();
+        ip6.chains[1].rules = vec![serde_json::from_value(json!({"saddr": "10.0.0.1"})).unwrap()];
+        assert!(ip6.canonical().is_err());
+    }
+
+    #[test]
+    fn test_plan_owns_only_marked_tables() {
+        let desired = table().canonical().unwrap();
+        let mut current = desired.clone();
+
+        // Same contents but not yet owned: adopt it
+        let actions = plan(
+            std::slice::from_ref(&current),
+            std::slice::from_ref(&desired),
+        )
+        .unwrap();
+        assert!(
+            matches!(actions.as_slice(), [StateAction::Modify { resource, .. }] if resource == "inet/opdbus")
+        );
+
+        current.owned = true;
+        assert!(plan(
+            std::slice::from_ref(&current),
+            std::slice::from_ref(&desired)
+        )
+        .unwrap()
+        .is_empty());
+
+        let foreign = TableConfig {
+            family: "ip".to_string(),
+            name: "fail2ban".to_string(),
+            sets: Vec::new(),
+            chains: Vec::new(),
+            owned: false,
+        };
+        let stale = TableConfig {
+            name: "old".to_string(),
+            owned: true,
+            ..foreign.clone()
+        };
+        let actions = plan(&[current, foreign, stale], &[]).unwrap();
+        assert_eq!(actions.len(), 2);
+        assert!(actions
+            .iter()
+            .all(|a| matches!(a, StateAction::Delete { resource } if resource != "ip/fail2ban")));
+    }
+
+    #[test]
+    fn test_build_emits_one_ordered_batch() {
+        let canonical = table().canonical().unwrap();
+        let mut batch = Batch::new();
+        canonical.build(&mut batch).unwrap();
+        let kinds: Vec<u16> = batch.messages().iter().map(|m| m.kind).collect();
+        use crate::native::nftables::msg;
+        // table, 2 chains, 2 sets with elements, 6 rules
+        assert_eq!(kinds[0], msg::NEWTABLE);
+        assert_eq!(&kinds[1..3], &[msg::NEWCHAIN, msg::NEWCHAIN]);
+        assert_eq!(
+            &kinds[3..7],
+            &[msg::NEWSET, msg::NEWSETELEM, msg::NEWSET, msg::NEWSETELEM]
+        );
+        assert_eq!(kinds[7..].len(), 6);
+        assert!(kinds[7..].iter().all(|k| *k == msg::NEWRULE));
+        let owner = Table::decode(&batch.messages()[0]).unwrap();
+        assert_eq!(owner.comment.as_deref(), Some(OWNER_COMMENT));
+    }
+}
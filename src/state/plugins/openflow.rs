@@ -4,7 +4,7 @@
 use crate::state::plugin::{
     ApplyResult, Checkpoint, DiffMetadata, PluginCapabilities, StateAction, StateDiff, StatePlugin,
 };
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use log;
 use serde::{Deserialize, Serialize};
@@ -12,6 +12,13 @@ use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+pub mod convert;
+pub mod cookie;
+pub mod trace;
+
+use convert::PortMap;
+use cookie::FlowFamily;
+
 /// OpenFlow controller configuration - Policy-based, not interface-based
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenFlowConfig {
@@ -90,9 +97,13 @@ pub struct BridgeFlowConfig {
     /// Bridge name (e.g., "ovsbr0")
     pub name: String,
 
-    /// OpenFlow flows for this bridge
+    /// OpenFlow flows for this bridge (owned by op-dbus, see `cookie`)
     pub flows: Vec<FlowEntry>,
 
+    /// Flows installed by other controllers or admins; reported by query, never modified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub foreign_flows: Option<Vec<FlowEntry>>,
+
     /// Container socket ports (internal OVS ports for containerless networking)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub socket_ports: Option<Vec<SocketPort>>,
@@ -195,13 +206,75 @@ impl OpenFlowPlugin {
         Self { ovsdb_client }
     }
 
-    /// Create OpenFlow client for a bridge
+    /// Open a native OpenFlow connection to a bridge through its management socket
     async fn create_openflow_client(&self, bridge: &str) -> Result<crate::native::openflow::OpenFlowClient> {
-        // Connect to OpenFlow switch (OVS typically listens on localhost:6633)
-        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 6633));
-        let client = crate::native::openflow::OpenFlowClient::connect(addr).await
-            .context(format!("Failed to connect to OpenFlow switch for bridge {}", bridge))?;
-        Ok(client)
+        crate::native::openflow::OpenFlowClient::connect_bridge(bridge).await
+    }
+
+    /// Map port names on a bridge to OpenFlow port numbers
+    async fn port_map(&self, bridge: &str) -> Result<PortMap> {
+        // Ofport numbers are per bridge, so only the bridge's own interfaces count
+        let operations = serde_json::json!([
+            {
+                "op": "select",
+                "table": "Bridge",
+                "where": [["name", "==", bridge]],
+                "columns": ["ports"]
+            },
+            {
+                "op": "select",
+                "table": "Port",
+                "where": [],
+                "columns": ["_uuid", "interfaces"]
+            },
+            {
+                "op": "select",
+                "table": "Interface",
+                "where": [],
+                "columns": ["_uuid", "name", "ofport"]
+            }
+        ]);
+
+        let result = self.ovsdb_client.transact(operations).await?;
+        let mut ports = Self::bridge_ofports(&result)
+            .ok_or_else(|| anyhow!("Bridge {} not found in OVSDB", bridge))?;
+
+        // The bridge's own internal port is OFPP_LOCAL, not its OVSDB ofport
+        ports.retain(|(name, _)| name != bridge);
+        ports.push((bridge.to_string(), crate::native::openflow::ofp_port::LOCAL));
+        Ok(PortMap::new(ports))
+    }
+
+    /// Interface names and ofports reachable through the Bridge -> Port -> Interface
+    /// rows of a [Bridge, Port, Interface] select; None when the bridge is missing
+    fn bridge_ofports(result: &Value) -> Option<Vec<(String, u32)>> {
+        let rows = |index: usize| {
+            result[index]["rows"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+        };
+        let row_uuid = |row: &Value| row["_uuid"][1].as_str().map(str::to_string);
+
+        let bridge = rows(0).into_iter().next()?;
+        let port_uuids = uuid_refs(&bridge["ports"]);
+        let interface_uuids: Vec<String> = rows(1)
+            .iter()
+            .filter(|port| row_uuid(port).is_some_and(|uuid| port_uuids.contains(&uuid)))
+            .flat_map(|port| uuid_refs(&port["interfaces"]))
+            .collect();
+
+        Some(
+            rows(2)
+                .iter()
+                .filter(|iface| row_uuid(iface).is_some_and(|uuid| interface_uuids.contains(&uuid)))
+                .filter_map(|iface| {
+                    let name = iface["name"].as_str()?;
+                    let ofport = iface["ofport"].as_i64().filter(|ofport| *ofport > 0)?;
+                    Some((name.to_string(), ofport as u32))
+                })
+                .collect(),
+        )
     }
 
     /// Discover containers from LXC plugin via OVSDB introspection
@@ -248,6 +321,11 @@ impl OpenFlowPlugin {
         }
     }
 
+    /// Whether a port name matches a pattern container discovery picks up
+    pub fn is_container_port(port_name: &str) -> bool {
+        Self::extract_container_id(port_name).is_some()
+    }
+
     /// Get OpenFlow port number for a port name
     async fn get_port_ofport(&self, port_name: &str) -> Result<u16> {
         let operations = serde_json::json!([{
@@ -383,15 +461,18 @@ impl OpenFlowPlugin {
             .map(|action| Self::substitute_action_variables(action, container))
             .collect();
 
-        Ok(FlowEntry {
+        let mut flow = FlowEntry {
             table: template.table,
             priority: template.priority,
             match_fields,
             actions,
-            cookie: Some(container.id.parse::<u64>().unwrap_or(0)),
+            cookie: None,
             idle_timeout: 0,
             hard_timeout: 0,
-        })
+        };
+        // The id hashes the match (in_port included), so each port's flow has its own cookie
+        cookie::claim(&mut flow, FlowFamily::Policy, cookie::tag(&policy.name));
+        Ok(flow)
     }
 
     /// Substitute variables in string ({container_id}, {port_name}, {bridge})
@@ -431,8 +512,10 @@ impl OpenFlowPlugin {
     async fn install_flow(&self, bridge: &str, flow: &FlowEntry) -> Result<()> {
         log::info!("Installing flow on {}: {:?}", bridge, flow);
 
-        let mut client = self.create_openflow_client(bridge).await?;
-        client.add_flow(flow).await?;
+        let ports = self.port_map(bridge).await?;
+        let flow_mod = convert::to_flow_mod(flow, &ports)?;
+        let client = self.create_openflow_client(bridge).await?;
+        client.add_flow(&flow_mod).await?;
 
         log::info!("Successfully installed flow on {}", bridge);
         Ok(())
@@ -440,148 +523,87 @@ impl OpenFlowPlugin {
 
     /// Query current flows via native OpenFlow protocol
     async fn query_flows(&self, bridge: &str) -> Result<Vec<FlowEntry>> {
-        // TODO: Implement native flow querying when OpenFlow client supports it
-        // For now, return empty list
-        log::warn!("Native OpenFlow flow querying not yet implemented, returning empty list");
-        Ok(Vec::new())
-    }
-
-    /// Parse ovs-ofctl dump-flows output
-    fn parse_flows(&self, output: &str) -> Result<Vec<FlowEntry>> {
-        let mut flows = Vec::new();
+        let ports = self.port_map(bridge).await?;
+        let client = self.create_openflow_client(bridge).await?;
+        let stats = client.query_flows().await?;
 
-        for line in output.lines() {
-            // Skip header and empty lines
-            if line.starts_with("NXST_FLOW") || line.trim().is_empty() {
-                continue;
-            }
-
-            // Parse flow line
-            // Format: cookie=0x0, duration=123s, table=0, n_packets=0, priority=100, in_port=1, actions=output:2
-            if let Some(flow) = self.parse_flow_line(line) {
-                flows.push(flow);
-            }
-        }
-
-        Ok(flows)
+        Ok(stats
+            .iter()
+            .map(|flow| convert::from_flow_stats(flow, &ports))
+            .collect())
     }
 
-    /// Parse a single flow line
-    fn parse_flow_line(&self, line: &str) -> Option<FlowEntry> {
-        let mut table = 0u8;
-        let mut priority = 0u16;
-        let mut cookie = None;
-        let mut match_fields = HashMap::new();
-        let mut actions = Vec::new();
-
-        // Split by comma and parse fields
-        for part in line.split(',') {
-            let part = part.trim();
+    /// Owned flows of one family on a bridge, as the switch reports them
+    pub(crate) async fn family_flows(&self, bridge: &str, family: FlowFamily) -> Result<Vec<FlowEntry>> {
+        Ok(self
+            .query_flows(bridge)
+            .await?
+            .into_iter()
+            .filter(|f| f.cookie.and_then(cookie::family) == Some(family))
+            .collect())
+    }
 
-            if let Some((key, value)) = part.split_once('=') {
-                match key.trim() {
-                    "table" => table = value.parse().ok()?,
-                    "priority" => priority = value.parse().ok()?,
-                    "cookie" => {
-                        cookie = Some(u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()?)
-                    }
-                    "actions" => actions = self.parse_actions(value),
-                    _ => {
-                        // Match field
-                        if !key.contains("duration")
-                            && !key.contains("n_packets")
-                            && !key.contains("n_bytes")
-                        {
-                            match_fields.insert(key.to_string(), value.to_string());
-                        }
-                    }
-                }
-            }
+    /// Replace every flow of `family` on a bridge with `flows` (claimed for that
+    /// family), sent as one batch confirmed by a single barrier
+    pub(crate) async fn replace_family_flows(
+        &self,
+        bridge: &str,
+        family: FlowFamily,
+        flows: &[FlowEntry],
+    ) -> Result<()> {
+        let ports = self.port_map(bridge).await?;
+        let (value, mask) = family.cookie_match();
+        let mut flow_mods = vec![crate::native::openflow::FlowMod::delete(
+            crate::native::openflow::OFPTT_ALL,
+            crate::native::openflow::Match::new(),
+        )
+        .cookie(value, mask)];
+        for flow in flows {
+            let mut flow = flow.clone();
+            cookie::claim(&mut flow, family, 0);
+            flow_mods.push(convert::to_flow_mod(&flow, &ports)?);
         }
-
-        Some(FlowEntry {
-            table,
-            priority,
-            match_fields,
-            actions,
-            cookie,
-            idle_timeout: 0,
-            hard_timeout: 0,
-        })
+        let client = self.create_openflow_client(bridge).await?;
+        client.flow_mods(&flow_mods).await
     }
 
-    /// Parse actions string
-    fn parse_actions(&self, actions_str: &str) -> Vec<FlowAction> {
-        let mut actions = Vec::new();
-
-        for action in actions_str.split(',') {
-            let action = action.trim();
-
-            if action == "NORMAL" {
-                actions.push(FlowAction::Normal);
-            } else if action == "drop" {
-                actions.push(FlowAction::Drop);
-            } else if let Some(port) = action.strip_prefix("output:") {
-                actions.push(FlowAction::Output {
-                    port: port.to_string(),
-                });
-            } else if let Some(rest) = action.strip_prefix("resubmit(,") {
-                if let Some(table_str) = rest.strip_suffix(')') {
-                    if let Ok(table) = table_str.parse() {
-                        actions.push(FlowAction::Resubmit { table });
-                    }
-                }
+    /// Rewrite desired flows the way the switch reports them so they compare equal
+    pub(crate) async fn canonicalize_flows(&self, bridge: &str, flows: &mut [FlowEntry]) {
+        let ports = match self.port_map(bridge).await {
+            Ok(ports) => ports,
+            Err(e) => {
+                log::warn!("Cannot map ports of {}, comparing flows as written: {}", bridge, e);
+                return;
+            }
+        };
+        for flow in flows {
+            match convert::canonicalize(flow, &ports) {
+                Ok(canonical) => *flow = canonical,
+                Err(e) => log::warn!("Flow on {} cannot be encoded: {:#}", bridge, e),
             }
         }
-
-        actions
     }
 
-    /// Convert flow to ovs-ofctl string format
-    fn flow_to_string(&self, flow: &FlowEntry) -> String {
-        let mut parts = Vec::new();
-
-        // Table
-        parts.push(format!("table={}", flow.table));
-
-        // Priority
-        parts.push(format!("priority={}", flow.priority));
-
-        // Match fields
-        for (key, value) in &flow.match_fields {
-            parts.push(format!("{}={}", key, value));
+    /// Delete an owned flow by its `{bridge}/flow/{table}/{cookie}` resource
+    async fn delete_flow(&self, resource: &str) -> Result<()> {
+        let parts: Vec<&str> = resource.split('/').collect();
+        let (bridge, table, flow_cookie) = match parts.as_slice() {
+            [bridge, "flow", table, flow_cookie] => (
+                *bridge,
+                table.parse::<u8>()?,
+                u64::from_str_radix(flow_cookie.trim_start_matches("0x"), 16)?,
+            ),
+            _ => return Err(anyhow!("Malformed flow resource {}", resource)),
+        };
+        if !cookie::is_owned(flow_cookie) {
+            return Err(anyhow!(
+                "Refusing to delete flow with cookie {:#x} not owned by op-dbus",
+                flow_cookie
+            ));
         }
 
-        // Actions
-        let actions_str = flow
-            .actions
-            .iter()
-            .map(|a| self.action_to_string(a))
-            .collect::<Vec<_>>()
-            .join(",");
-
-        format!("{},actions={}", parts.join(","), actions_str)
-    }
-
-    /// Convert action to string
-    fn action_to_string(&self, action: &FlowAction) -> String {
-        match action {
-            FlowAction::Output { port } => format!("output:{}", port),
-            FlowAction::LoadRegister { register, value } => {
-                format!("load:{}->NXM_NX_REG{}[]", value, register)
-            }
-            FlowAction::Resubmit { table } => format!("resubmit(,{})", table),
-            FlowAction::SetField { field, value } => format!("set_field:{}={}", value, field),
-            FlowAction::Drop => "drop".to_string(),
-            FlowAction::Normal => "NORMAL".to_string(),
-            FlowAction::Controller { max_len } => {
-                if let Some(len) = max_len {
-                    format!("CONTROLLER:{}", len)
-                } else {
-                    "CONTROLLER".to_string()
-                }
-            }
-        }
+        let client = self.create_openflow_client(bridge).await?;
+        client.delete_flows(table, flow_cookie, u64::MAX).await
     }
 
     /// Create OVS internal port for socket networking
@@ -678,6 +700,62 @@ impl OpenFlowPlugin {
         Err(anyhow::anyhow!("Bridge '{}' not found", bridge_name))
     }
 
+    /// Inject security and obfuscation flows based on configuration
+    pub fn inject_generated_flows(config: &mut OpenFlowConfig) {
+        if config.enable_security_flows {
+            log::info!(
+                "Security hardening enabled (obfuscation level {}), injecting flows",
+                config.obfuscation_level
+            );
+
+            for bridge_config in &mut config.bridges {
+                let mut all_flows = Vec::new();
+                let mut flow_count = 0;
+
+                // Level 1: Basic security (always enabled if enable_security_flows=true)
+                if config.obfuscation_level >= 1 {
+                    let mut security_flows = Self::generate_security_flows(&bridge_config.name);
+                    security_flows
+                        .iter_mut()
+                        .for_each(|f| cookie::claim(f, FlowFamily::Security, 0));
+                    flow_count += security_flows.len();
+                    all_flows.extend(security_flows);
+                }
+
+                // Level 2: Pattern hiding (TTL normalization, packet padding, timing)
+                if config.obfuscation_level >= 2 {
+                    let mut pattern_flows = Self::generate_pattern_hiding_flows(&bridge_config.name);
+                    pattern_flows
+                        .iter_mut()
+                        .for_each(|f| cookie::claim(f, FlowFamily::PatternHiding, 0));
+                    flow_count += pattern_flows.len();
+                    all_flows.extend(pattern_flows);
+                }
+
+                // Level 3: Advanced obfuscation (protocol mimicry, decoy traffic, morphing)
+                if config.obfuscation_level >= 3 {
+                    let mut advanced_flows = Self::generate_advanced_obfuscation_flows(&bridge_config.name);
+                    advanced_flows
+                        .iter_mut()
+                        .for_each(|f| cookie::claim(f, FlowFamily::Obfuscation, 0));
+                    flow_count += advanced_flows.len();
+                    all_flows.extend(advanced_flows);
+                }
+
+                // Prepend generated flows to user-defined flows (generated have higher priority)
+                all_flows.extend(bridge_config.flows.clone());
+                bridge_config.flows = all_flows;
+
+                log::info!(
+                    "Bridge {}: injected {} flows (Level {} obfuscation)",
+                    bridge_config.name,
+                    flow_count,
+                    config.obfuscation_level
+                );
+            }
+        }
+    }
+
     /// Compute SHA-256 hash of state
     fn compute_state_hash(&self, state: &Value) -> String {
         use sha2::{Digest, Sha256};
@@ -1135,6 +1213,16 @@ impl OpenFlowPlugin {
     }
 }
 
+/// Resource path of a flow; owned cookies are unique, so they identify it
+fn flow_resource(bridge: &str, flow: &FlowEntry) -> String {
+    format!(
+        "{}/flow/{}/{:#018x}",
+        bridge,
+        flow.table,
+        flow.cookie.unwrap_or(0)
+    )
+}
+
 #[async_trait]
 impl StatePlugin for OpenFlowPlugin {
     fn name(&self) -> &str {
@@ -1164,7 +1252,13 @@ impl StatePlugin for OpenFlowPlugin {
         let mut bridge_configs = Vec::new();
 
         for bridge in bridges {
-            let flows = self.query_flows(&bridge).await.unwrap_or_default();
+            // Only op-dbus-owned flows are state; the rest is reported separately
+            let (flows, foreign_flows): (Vec<FlowEntry>, Vec<FlowEntry>) = self
+                .query_flows(&bridge)
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .partition(|f| f.cookie.is_some_and(cookie::is_owned));
 
             // Convert discovered containers on this bridge to SocketPorts
             let socket_ports: Vec<SocketPort> = discovered_containers
@@ -1180,6 +1274,11 @@ impl StatePlugin for OpenFlowPlugin {
             bridge_configs.push(BridgeFlowConfig {
                 name: bridge,
                 flows,
+                foreign_flows: if foreign_flows.is_empty() {
+                    None
+                } else {
+                    Some(foreign_flows)
+                },
                 socket_ports: if socket_ports.is_empty() {
                     None
                 } else {
@@ -1206,50 +1305,7 @@ impl StatePlugin for OpenFlowPlugin {
         let current_config: OpenFlowConfig = serde_json::from_value(current.clone())?;
         let mut desired_config: OpenFlowConfig = serde_json::from_value(desired.clone())?;
 
-        // Inject security and obfuscation flows based on configuration
-        if desired_config.enable_security_flows {
-            log::info!(
-                "Security hardening enabled (obfuscation level {}), injecting flows",
-                desired_config.obfuscation_level
-            );
-
-            for bridge_config in &mut desired_config.bridges {
-                let mut all_flows = Vec::new();
-                let mut flow_count = 0;
-
-                // Level 1: Basic security (always enabled if enable_security_flows=true)
-                if desired_config.obfuscation_level >= 1 {
-                    let security_flows = Self::generate_security_flows(&bridge_config.name);
-                    flow_count += security_flows.len();
-                    all_flows.extend(security_flows);
-                }
-
-                // Level 2: Pattern hiding (TTL normalization, packet padding, timing)
-                if desired_config.obfuscation_level >= 2 {
-                    let pattern_flows = Self::generate_pattern_hiding_flows(&bridge_config.name);
-                    flow_count += pattern_flows.len();
-                    all_flows.extend(pattern_flows);
-                }
-
-                // Level 3: Advanced obfuscation (protocol mimicry, decoy traffic, morphing)
-                if desired_config.obfuscation_level >= 3 {
-                    let advanced_flows = Self::generate_advanced_obfuscation_flows(&bridge_config.name);
-                    flow_count += advanced_flows.len();
-                    all_flows.extend(advanced_flows);
-                }
-
-                // Prepend generated flows to user-defined flows (generated have higher priority)
-                all_flows.extend(bridge_config.flows.clone());
-                bridge_config.flows = all_flows;
-
-                log::info!(
-                    "Bridge {}: injected {} flows (Level {} obfuscation)",
-                    bridge_config.name,
-                    flow_count,
-                    desired_config.obfuscation_level
-                );
-            }
-        }
+        Self::inject_generated_flows(&mut desired_config);
 
         // If auto-discovery is enabled and policies are defined, generate flows
         if desired_config.auto_discover_containers {
@@ -1286,6 +1342,15 @@ impl StatePlugin for OpenFlowPlugin {
             }
         }
 
+        // Everything left unclaimed was written out in the config
+        for bridge_config in &mut desired_config.bridges {
+            for flow in &mut bridge_config.flows {
+                cookie::claim(flow, FlowFamily::Static, 0);
+            }
+            self.canonicalize_flows(&bridge_config.name, &mut bridge_config.flows)
+                .await;
+        }
+
         let mut actions = Vec::new();
 
         // Compare bridges
@@ -1296,31 +1361,29 @@ impl StatePlugin for OpenFlowPlugin {
                 .find(|b| b.name == desired_bridge.name);
 
             if let Some(current_bridge) = current_bridge {
-                // Compare flows
-                for desired_flow in &desired_bridge.flows {
-                    let flow_exists = current_bridge.flows.iter().any(|f| f == desired_flow);
-
-                    if !flow_exists {
-                        actions.push(StateAction::Create {
-                            resource: format!(
-                                "{}/flow/{}",
-                                desired_bridge.name, desired_flow.table
-                            ),
-                            config: serde_json::to_value(desired_flow)?,
+                // Only op-dbus-owned flows take part in the diff; the privacy
+                // plugin manages its own family
+                let owned: Vec<&FlowEntry> = current_bridge
+                    .flows
+                    .iter()
+                    .filter(|f| f.cookie.is_some_and(cookie::is_owned))
+                    .filter(|f| f.cookie.and_then(cookie::family) != Some(FlowFamily::Privacy))
+                    .collect();
+
+                // Owned flows that are no longer desired, or whose cookie now carries a different flow
+                for current_flow in &owned {
+                    if !desired_bridge.flows.contains(current_flow) {
+                        actions.push(StateAction::Delete {
+                            resource: flow_resource(&desired_bridge.name, current_flow),
                         });
                     }
                 }
 
-                // Check for flows to delete
-                for current_flow in &current_bridge.flows {
-                    let flow_desired = desired_bridge.flows.iter().any(|f| f == current_flow);
-
-                    if !flow_desired {
-                        actions.push(StateAction::Delete {
-                            resource: format!(
-                                "{}/flow/{}",
-                                desired_bridge.name, current_flow.table
-                            ),
+                for desired_flow in &desired_bridge.flows {
+                    if !owned.contains(&desired_flow) {
+                        actions.push(StateAction::Create {
+                            resource: flow_resource(&desired_bridge.name, desired_flow),
+                            config: serde_json::to_value(desired_flow)?,
                         });
                     }
                 }
@@ -1397,16 +1460,10 @@ impl StatePlugin for OpenFlowPlugin {
                 }
                 StateAction::Delete { resource } => {
                     if resource.contains("/flow/") {
-                        // Delete flow - parse flow info from resource string
-                        let parts: Vec<&str> = resource.split('/').collect();
-                        let _bridge = parts[0];
-                        // For deletion, we need to query current flows and match by table
-                        let _table_str = parts.get(2).unwrap_or(&"0");
-
-                        errors.push(format!(
-                            "Flow deletion by resource path needs implementation: {}",
-                            resource
-                        ));
+                        match self.delete_flow(resource).await {
+                            Ok(_) => changes.push(format!("Deleted flow {}", resource)),
+                            Err(e) => errors.push(format!("Failed to delete flow {}: {}", resource, e)),
+                        }
                     } else if resource.contains("/port/") {
                         // Delete socket port
                         let parts: Vec<&str> = resource.split('/').collect();
@@ -1460,6 +1517,8 @@ impl StatePlugin for OpenFlowPlugin {
             checkpoint.timestamp
         );
 
+        // Snapshots only list owned flows as state, so flows other controllers
+        // added or removed since the checkpoint are left as they are
         let current = self.query_current_state().await?;
         let diff = self
             .calculate_diff(&current, &checkpoint.state_snapshot)
@@ -1479,3 +1538,89 @@ impl StatePlugin for OpenFlowPlugin {
         }
     }
 }
+
+/// UUIDs in an OVSDB reference column: a bare ["uuid", ..] or a ["set", [..]]
+fn uuid_refs(value: &Value) -> Vec<String> {
+    let refs = match value[0].as_str() {
+        Some("set") => value[1].as_array().cloned().unwrap_or_default(),
+        Some("uuid") => vec![value.clone()],
+        _ => Vec::new(),
+    };
+    refs.iter()
+        .filter_map(|r| r[1].as_str().map(str::to_string))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_flows_have_distinct_cookies() {
+        let policy = FlowPolicy {
+            name: "tag-containers".to_string(),
+            selector: "container:*".to_string(),
+            template: FlowTemplate {
+                table: 0,
+                priority: 100,
+                actions: vec![FlowAction::Normal],
+                additional_matches: None,
+            },
+        };
+        let container = |id: &str, port_name: &str| DiscoveredContainer {
+            id: id.to_string(),
+            port_name: port_name.to_string(),
+            bridge: "ovsbr0".to_string(),
+            ofport: None,
+        };
+
+        let cookies: Vec<u64> = [
+            container("100", "vi100"),
+            container("100", "internal_100"),
+            container("web", "viweb"),
+            container("db", "vidb"),
+        ]
+        .iter()
+        .map(|c| {
+            OpenFlowPlugin::generate_flow_from_policy(&policy, c)
+                .unwrap()
+                .cookie
+                .unwrap()
+        })
+        .collect();
+        for (i, cookie) in cookies.iter().enumerate() {
+            assert_eq!(cookie::family(*cookie), Some(FlowFamily::Policy));
+            assert!(!cookies[i + 1..].contains(cookie));
+        }
+
+        // Regenerating gives the same cookie, so the diff sees the flow as installed
+        let again = OpenFlowPlugin::generate_flow_from_policy(&policy, &container("100", "vi100"));
+        assert_eq!(again.unwrap().cookie, Some(cookies[0]));
+    }
+
+    #[test]
+    fn test_bridge_ofports_skip_other_bridges() {
+        // ovsbr0 has vi100 and a bond; ovsbr1's eth9 reuses ofport 1
+        let result = serde_json::json!([
+            {"rows": [{"ports": ["set", [["uuid", "p1"], ["uuid", "p2"]]]}]},
+            {"rows": [
+                {"_uuid": ["uuid", "p1"], "interfaces": ["uuid", "i1"]},
+                {"_uuid": ["uuid", "p2"], "interfaces": ["set", [["uuid", "i2"], ["uuid", "i3"]]]},
+                {"_uuid": ["uuid", "p9"], "interfaces": ["uuid", "i9"]}
+            ]},
+            {"rows": [
+                {"_uuid": ["uuid", "i1"], "name": "vi100", "ofport": 1},
+                {"_uuid": ["uuid", "i2"], "name": "eth1", "ofport": 2},
+                {"_uuid": ["uuid", "i3"], "name": "eth2", "ofport": -1},
+                {"_uuid": ["uuid", "i9"], "name": "eth9", "ofport": 1}
+            ]}
+        ]);
+        assert_eq!(
+            OpenFlowPlugin::bridge_ofports(&result).unwrap(),
+            vec![("vi100".to_string(), 1), ("eth1".to_string(), 2)]
+        );
+
+        let missing = serde_json::json!([{"rows": []}, {"rows": []}, {"rows": []}]);
+        assert!(OpenFlowPlugin::bridge_ofports(&missing).is_none());
+    }
+}
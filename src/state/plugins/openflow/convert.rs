@@ -0,0 +1,813 @@
+//! Conversion between declarative `FlowEntry`s and native OpenFlow 1.3 messages
+//!
+//! Match keys and values use ovs-ofctl syntax (`tcp`, `nw_src=10.0.0.0/8`,
+//! `ct_state=+est+trk`, `tcp_flags=+syn-ack`) so existing flow definitions keep
+//! working. Rendering a native flow back produces one canonical spelling, and
+//! `canonicalize` runs desired flows through the same round trip so they
+//! compare equal to what the switch reports.
+
+use super::{FlowAction, FlowEntry};
+use crate::native::openflow::{
+    ofp_port, Action, FlowMod, FlowStats, Instruction, Match, OxmField, OxmKind, OFPCML_NO_BUFFER,
+    OFPVID_PRESENT,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const ETH_IP: u64 = 0x0800;
+const ETH_ARP: u64 = 0x0806;
+const ETH_IPV6: u64 = 0x86dd;
+const PROTO_ICMP: u64 = 1;
+const PROTO_TCP: u64 = 6;
+const PROTO_UDP: u64 = 17;
+const PROTO_ICMPV6: u64 = 58;
+
+/// ovs-ofctl protocol shorthands: (name, eth_type, ip_proto)
+const SHORTHANDS: [(&str, u64, Option<u64>); 9] = [
+    ("ip", ETH_IP, None),
+    ("tcp", ETH_IP, Some(PROTO_TCP)),
+    ("udp", ETH_IP, Some(PROTO_UDP)),
+    ("icmp", ETH_IP, Some(PROTO_ICMP)),
+    ("arp", ETH_ARP, None),
+    ("ipv6", ETH_IPV6, None),
+    ("tcp6", ETH_IPV6, Some(PROTO_TCP)),
+    ("udp6", ETH_IPV6, Some(PROTO_UDP)),
+    ("icmp6", ETH_IPV6, Some(PROTO_ICMPV6)),
+];
+
+const CT_STATE_FLAGS: [&str; 8] = ["new", "est", "rel", "rpl", "inv", "trk", "snat", "dnat"];
+const TCP_FLAGS: [&str; 9] = ["fin", "syn", "rst", "psh", "ack", "urg", "ece", "cwr", "ns"];
+const TCP_FLAGS_MASK: u64 = 0x0fff;
+
+/// ip_frag keywords as (name, value, mask)
+const IP_FRAG: [(&str, u64, u64); 5] = [
+    ("no", 0, 1),
+    ("yes", 1, 1),
+    ("first", 1, 3),
+    ("later", 3, 3),
+    ("not_later", 0, 2),
+];
+
+const RESERVED_PORTS: [(&str, u32); 7] = [
+    ("IN_PORT", ofp_port::IN_PORT),
+    ("TABLE", ofp_port::TABLE),
+    ("NORMAL", ofp_port::NORMAL),
+    ("FLOOD", ofp_port::FLOOD),
+    ("ALL", ofp_port::ALL),
+    ("CONTROLLER", ofp_port::CONTROLLER),
+    ("LOCAL", ofp_port::LOCAL),
+];
+
+/// Port name <-> OpenFlow port number mapping for one bridge
+#[derive(Debug, Clone, Default)]
+pub struct PortMap {
+    by_name: HashMap<String, u32>,
+    by_number: HashMap<u32, String>,
+}
+
+impl PortMap {
+    pub fn new(ports: impl IntoIterator<Item = (String, u32)>) -> Self {
+        let mut map = Self::default();
+        for (name, number) in ports {
+            map.by_number.insert(number, name.clone());
+            map.by_name.insert(name, number);
+        }
+        map
+    }
+
+    /// Resolve a port name, reserved port or number
+    pub fn number(&self, port: &str) -> Result<u32> {
+        if let Some((_, number)) = RESERVED_PORTS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(port))
+        {
+            return Ok(*number);
+        }
+        if let Ok(number) = port.parse::<u32>() {
+            return Ok(number);
+        }
+        self.by_name
+            .get(port)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown OpenFlow port '{}'", port))
+    }
+
+    /// Port name for a number, falling back to the number itself
+    pub fn name(&self, number: u32) -> String {
+        RESERVED_PORTS
+            .iter()
+            .find(|(_, n)| *n == number)
+            .map(|(name, _)| name.to_string())
+            .or_else(|| self.by_number.get(&number).cloned())
+            .unwrap_or_else(|| number.to_string())
+    }
+}
+
+/// Protocol context that decides how ambiguous keys (tp_dst, nw_src) are encoded
+#[derive(Debug, Clone, Copy, Default)]
+struct Protocols {
+    eth_type: Option<u64>,
+    ip_proto: Option<u64>,
+}
+
+/// Build an OFPFC_ADD flow mod for a declarative flow
+pub fn to_flow_mod(flow: &FlowEntry, ports: &PortMap) -> Result<FlowMod> {
+    let (ctx, match_fields) = build_match(&flow.match_fields, ports)?;
+
+    let mut actions = Vec::new();
+    for action in &flow.actions {
+        if let Some(action) = to_action(action, ctx, ports)? {
+            actions.push(action);
+        }
+    }
+
+    let mut flow_mod = FlowMod::add(flow.table, flow.priority, match_fields)
+        .cookie(flow.cookie.unwrap_or(0), 0)
+        .timeouts(flow.idle_timeout, flow.hard_timeout);
+    if !actions.is_empty() {
+        flow_mod = flow_mod.apply(actions);
+    }
+    Ok(flow_mod)
+}
+
+/// Declarative view of a flow reported by the switch
+pub fn from_flow_stats(stats: &FlowStats, ports: &PortMap) -> FlowEntry {
+    FlowEntry {
+        table: stats.table_id,
+        priority: stats.priority,
+        match_fields: render_match(&stats.match_fields, ports),
+        actions: render_actions(&stats.instructions, ports),
+        cookie: Some(stats.cookie),
+        idle_timeout: stats.idle_timeout,
+        hard_timeout: stats.hard_timeout,
+    }
+}
+
+/// The spelling the switch will report for a desired flow
+pub fn canonicalize(flow: &FlowEntry, ports: &PortMap) -> Result<FlowEntry> {
+    let flow_mod = to_flow_mod(flow, ports)?;
+    Ok(FlowEntry {
+        table: flow_mod.table_id,
+        priority: flow_mod.priority,
+        match_fields: render_match(&flow_mod.match_fields, ports),
+        actions: render_actions(&flow_mod.instructions, ports),
+        cookie: Some(flow_mod.cookie),
+        idle_timeout: flow_mod.idle_timeout,
+        hard_timeout: flow_mod.hard_timeout,
+    })
+}
+
+/// Native match for ovs-ofctl style match fields
+pub fn to_match(fields: &HashMap<String, String>, ports: &PortMap) -> Result<Match> {
+    build_match(fields, ports).map(|(_, m)| m)
+}
+
+fn build_match(fields: &HashMap<String, String>, ports: &PortMap) -> Result<(Protocols, Match)> {
+    let mut ctx = Protocols::default();
+
+    // Protocol prerequisites first, so later keys know what they refer to
+    for (key, value) in fields {
+        let key = key.as_str();
+        if let Some((_, eth_type, ip_proto)) = SHORTHANDS.iter().find(|(name, _, _)| *name == key) {
+            set_once(&mut ctx.eth_type, *eth_type, key)?;
+            if let Some(proto) = ip_proto {
+                set_once(&mut ctx.ip_proto, *proto, key)?;
+            }
+        } else if matches!(key, "dl_type" | "eth_type") {
+            set_once(&mut ctx.eth_type, parse_uint(value)?, key)?;
+        } else if matches!(key, "nw_proto" | "ip_proto") {
+            set_once(&mut ctx.ip_proto, parse_uint(value)?, key)?;
+        }
+    }
+
+    // Fields that only make sense for one protocol imply it
+    for key in fields.keys() {
+        let implied = match key.as_str() {
+            "icmp_type" | "icmp_code" => Some((ETH_IP, Some(PROTO_ICMP))),
+            "icmpv6_type" | "icmpv6_code" => Some((ETH_IPV6, Some(PROTO_ICMPV6))),
+            "tcp_flags" | "tcp_src" | "tcp_dst" => Some((ETH_IP, Some(PROTO_TCP))),
+            "udp_src" | "udp_dst" => Some((ETH_IP, Some(PROTO_UDP))),
+            "nw_src" | "nw_dst" | "nw_ttl" | "nw_tos" | "ip_dscp" | "ip_frag" => {
+                Some((ETH_IP, None))
+            }
+            _ => None,
+        };
+        if let Some((eth_type, ip_proto)) = implied {
+            ctx.eth_type.get_or_insert(eth_type);
+            if let Some(proto) = ip_proto {
+                ctx.ip_proto.get_or_insert(proto);
+            }
+        }
+    }
+
+    let mut m = Match::new();
+    if let Some(eth_type) = ctx.eth_type {
+        m = m.with(OxmField::uint(OxmKind::EthType, eth_type));
+    }
+    if let Some(ip_proto) = ctx.ip_proto {
+        if ctx.eth_type.is_none() {
+            bail!("nw_proto requires an IP protocol (ip, ipv6 or dl_type)");
+        }
+        m = m.with(OxmField::uint(OxmKind::IpProto, ip_proto));
+    }
+
+    let mut keys: Vec<&String> = fields.keys().collect();
+    keys.sort();
+    for key in keys {
+        let value = &fields[key];
+        if let Some(field) = parse_field(key, value, ctx, ports)
+            .with_context(|| format!("Invalid match {}={}", key, value))?
+        {
+            m = m.with(field);
+        }
+    }
+    Ok((ctx, m))
+}
+
+fn set_once(slot: &mut Option<u64>, value: u64, key: &str) -> Result<()> {
+    match slot {
+        Some(existing) if *existing != value => {
+            bail!(
+                "Match field '{}' conflicts with another protocol field",
+                key
+            )
+        }
+        _ => {
+            *slot = Some(value);
+            Ok(())
+        }
+    }
+}
+
+/// One match (or set_field) key; Ok(None) for keys handled as prerequisites
+fn parse_field(
+    key: &str,
+    value: &str,
+    ctx: Protocols,
+    ports: &PortMap,
+) -> Result<Option<OxmField>> {
+    let is_arp = ctx.eth_type == Some(ETH_ARP);
+    let field = match key {
+        _ if SHORTHANDS.iter().any(|(name, _, _)| *name == key) => return Ok(None),
+        "dl_type" | "eth_type" | "nw_proto" | "ip_proto" => return Ok(None),
+        "in_port" => OxmField::uint(OxmKind::InPort, ports.number(value)?.into()),
+        "dl_src" | "eth_src" => parse_mac(OxmKind::EthSrc, value)?,
+        "dl_dst" | "eth_dst" => parse_mac(OxmKind::EthDst, value)?,
+        "dl_vlan" | "vlan_vid" => OxmField::uint(
+            OxmKind::VlanVid,
+            (parse_uint(value)? & 0x0fff) | u64::from(OFPVID_PRESENT),
+        ),
+        "dl_vlan_pcp" | "vlan_pcp" => parse_uint_field(OxmKind::VlanPcp, value)?,
+        "nw_src" | "ip_src" if is_arp => parse_ipv4(OxmKind::ArpSpa, value)?,
+        "nw_dst" | "ip_dst" if is_arp => parse_ipv4(OxmKind::ArpTpa, value)?,
+        "nw_src" | "ip_src" => parse_ipv4(OxmKind::Ipv4Src, value)?,
+        "nw_dst" | "ip_dst" => parse_ipv4(OxmKind::Ipv4Dst, value)?,
+        "arp_spa" => parse_ipv4(OxmKind::ArpSpa, value)?,
+        "arp_tpa" => parse_ipv4(OxmKind::ArpTpa, value)?,
+        "arp_op" => parse_uint_field(OxmKind::ArpOp, value)?,
+        "ipv6_src" => parse_ipv6(OxmKind::Ipv6Src, value)?,
+        "ipv6_dst" => parse_ipv6(OxmKind::Ipv6Dst, value)?,
+        "ip_dscp" => parse_uint_field(OxmKind::IpDscp, value)?,
+        "nw_tos" => OxmField::uint(OxmKind::IpDscp, parse_uint(value)? >> 2),
+        "ip_ecn" | "nw_ecn" => parse_uint_field(OxmKind::IpEcn, value)?,
+        "nw_ttl" => parse_uint_field(OxmKind::IpTtl, value)?,
+        "tp_src" | "tp_dst" => {
+            let kind = match (ctx.ip_proto, key) {
+                (Some(PROTO_TCP), "tp_src") => OxmKind::TcpSrc,
+                (Some(PROTO_TCP), _) => OxmKind::TcpDst,
+                (Some(PROTO_UDP), "tp_src") => OxmKind::UdpSrc,
+                (Some(PROTO_UDP), _) => OxmKind::UdpDst,
+                _ => bail!("{} requires tcp or udp", key),
+            };
+            parse_uint_field(kind, value)?
+        }
+        "tcp_src" => parse_uint_field(OxmKind::TcpSrc, value)?,
+        "tcp_dst" => parse_uint_field(OxmKind::TcpDst, value)?,
+        "udp_src" => parse_uint_field(OxmKind::UdpSrc, value)?,
+        "udp_dst" => parse_uint_field(OxmKind::UdpDst, value)?,
+        "icmp_type" => parse_uint_field(OxmKind::Icmpv4Type, value)?,
+        "icmp_code" => parse_uint_field(OxmKind::Icmpv4Code, value)?,
+        "icmpv6_type" => parse_uint_field(OxmKind::Icmpv6Type, value)?,
+        "icmpv6_code" => parse_uint_field(OxmKind::Icmpv6Code, value)?,
+        "tun_id" | "tunnel_id" => parse_uint_field(OxmKind::TunnelId, value)?,
+        "metadata" => parse_uint_field(OxmKind::Metadata, value)?,
+        "ct_state" => {
+            let (bits, mask) = parse_flags(value, &CT_STATE_FLAGS)?;
+            OxmField::uint_masked(OxmKind::CtState, bits, mask)
+        }
+        "tcp_flags" => {
+            let (bits, mask) = if value.starts_with(['+', '-']) {
+                parse_flags(value, &TCP_FLAGS)?
+            } else {
+                split_mask(value, TCP_FLAGS_MASK)?
+            };
+            OxmField::uint_masked(OxmKind::TcpFlags, bits, mask)
+        }
+        "ip_frag" => {
+            let (_, bits, mask) = IP_FRAG
+                .iter()
+                .find(|(name, _, _)| *name == value)
+                .ok_or_else(|| anyhow!("unknown ip_frag value"))?;
+            OxmField::uint_masked(OxmKind::IpFrag, *bits, *mask)
+        }
+        _ => match key.strip_prefix("reg").and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) if n < 16 => parse_uint_field(OxmKind::Reg(n), value)?,
+            _ => bail!("unsupported match field"),
+        },
+    };
+    Ok(Some(field))
+}
+
+fn to_action(action: &FlowAction, ctx: Protocols, ports: &PortMap) -> Result<Option<Action>> {
+    Ok(Some(match action {
+        FlowAction::Output { port } => Action::output(ports.number(port)?),
+        FlowAction::Normal => Action::output(ofp_port::NORMAL),
+        FlowAction::Controller { max_len } => {
+            Action::controller(max_len.unwrap_or(OFPCML_NO_BUFFER))
+        }
+        FlowAction::Drop => return Ok(None),
+        FlowAction::Resubmit { table } => Action::resubmit(*table),
+        FlowAction::LoadRegister { register, value } => {
+            if *register >= 16 || *value > u64::from(u32::MAX) {
+                bail!("Cannot load {} into reg{}", value, register);
+            }
+            Action::SetField(OxmField::uint(OxmKind::Reg(*register), *value))
+        }
+        FlowAction::SetField { field, value } if field == "nw_ttl" => Action::SetNwTtl(
+            parse_uint(value)?
+                .try_into()
+                .context("nw_ttl out of range")?,
+        ),
+        FlowAction::SetField { field, value } => {
+            let oxm = parse_field(field, value, ctx, ports)?
+                .ok_or_else(|| anyhow!("Cannot set field {}", field))?;
+            if oxm.mask.is_some() {
+                bail!("set_field {} cannot take a mask", field);
+            }
+            Action::SetField(oxm)
+        }
+    }))
+}
+
+fn render_match(m: &Match, ports: &PortMap) -> HashMap<String, String> {
+    let ctx = Protocols {
+        eth_type: m.get(OxmKind::EthType).map(OxmField::value_uint),
+        ip_proto: m.get(OxmKind::IpProto).map(OxmField::value_uint),
+    };
+    let mut fields = HashMap::new();
+
+    match SHORTHANDS
+        .iter()
+        .find(|(_, eth, proto)| Some(*eth) == ctx.eth_type && *proto == ctx.ip_proto)
+    {
+        Some((name, _, _)) => {
+            fields.insert(name.to_string(), String::new());
+        }
+        None => {
+            match SHORTHANDS
+                .iter()
+                .find(|(_, eth, proto)| Some(*eth) == ctx.eth_type && proto.is_none())
+            {
+                Some((name, _, _)) => fields.insert(name.to_string(), String::new()),
+                None => ctx
+                    .eth_type
+                    .and_then(|eth| fields.insert("dl_type".into(), format!("0x{:04x}", eth))),
+            };
+            if let Some(proto) = ctx.ip_proto {
+                fields.insert("nw_proto".into(), proto.to_string());
+            }
+        }
+    }
+
+    for field in &m.fields {
+        if let Some((key, value)) = render_field(field, ports) {
+            fields.insert(key, value);
+        }
+    }
+    fields
+}
+
+fn render_field(field: &OxmField, ports: &PortMap) -> Option<(String, String)> {
+    let key = match field.kind {
+        OxmKind::EthType | OxmKind::IpProto => return None,
+        OxmKind::InPort => return Some(("in_port".into(), ports.name(field.value_uint() as u32))),
+        OxmKind::EthSrc | OxmKind::EthDst => {
+            let key = if field.kind == OxmKind::EthSrc {
+                "dl_src"
+            } else {
+                "dl_dst"
+            };
+            let mut value = format_mac(&field.value);
+            if let Some(mask) = &field.mask {
+                value = format!("{}/{}", value, format_mac(mask));
+            }
+            return Some((key.into(), value));
+        }
+        OxmKind::VlanVid => {
+            return Some(("dl_vlan".into(), (field.value_uint() & 0x0fff).to_string()))
+        }
+        OxmKind::Ipv4Src | OxmKind::Ipv4Dst | OxmKind::ArpSpa | OxmKind::ArpTpa => {
+            let key = match field.kind {
+                OxmKind::Ipv4Src => "nw_src",
+                OxmKind::Ipv4Dst => "nw_dst",
+                OxmKind::ArpSpa => "arp_spa",
+                _ => "arp_tpa",
+            };
+            let addr = Ipv4Addr::from(field.value_uint() as u32);
+            let value = match field.mask_uint() {
+                None => addr.to_string(),
+                Some(mask) => match prefix_len(u128::from(mask), 32) {
+                    Some(len) => format!("{}/{}", addr, len),
+                    None => format!("{}/{}", addr, Ipv4Addr::from(mask as u32)),
+                },
+            };
+            return Some((key.into(), value));
+        }
+        OxmKind::Ipv6Src | OxmKind::Ipv6Dst => {
+            let key = if field.kind == OxmKind::Ipv6Src {
+                "ipv6_src"
+            } else {
+                "ipv6_dst"
+            };
+            let addr = Ipv6Addr::from(u128_from(&field.value));
+            let value = match &field.mask {
+                None => addr.to_string(),
+                Some(mask) => match prefix_len(u128_from(mask), 128) {
+                    Some(len) => format!("{}/{}", addr, len),
+                    None => format!("{}/{}", addr, Ipv6Addr::from(u128_from(mask))),
+                },
+            };
+            return Some((key.into(), value));
+        }
+        OxmKind::CtState => {
+            let mask = field.mask_uint().unwrap_or(u64::from(u32::MAX));
+            return Some((
+                "ct_state".into(),
+                format_flags(field.value_uint(), mask, &CT_STATE_FLAGS),
+            ));
+        }
+        OxmKind::TcpFlags => {
+            let mask = field.mask_uint().unwrap_or(0xffff);
+            let value = if mask & TCP_FLAGS_MASK == TCP_FLAGS_MASK {
+                format!("0x{:03x}", field.value_uint())
+            } else {
+                format_flags(field.value_uint(), mask, &TCP_FLAGS)
+            };
+            return Some(("tcp_flags".into(), value));
+        }
+        OxmKind::IpFrag => {
+            let (value, mask) = (field.value_uint(), field.mask_uint().unwrap_or(0xff));
+            let name = IP_FRAG
+                .iter()
+                .find(|(_, v, m)| *v == value && *m == mask)
+                .map(|(name, _, _)| name.to_string())
+                .unwrap_or_else(|| format!("0x{:x}/0x{:x}", value, mask));
+            return Some(("ip_frag".into(), name));
+        }
+        OxmKind::TcpSrc | OxmKind::UdpSrc => "tp_src".to_string(),
+        OxmKind::TcpDst | OxmKind::UdpDst => "tp_dst".to_string(),
+        OxmKind::VlanPcp => "dl_vlan_pcp".to_string(),
+        OxmKind::IpDscp => "ip_dscp".to_string(),
+        OxmKind::IpEcn => "ip_ecn".to_string(),
+        OxmKind::IpTtl => "nw_ttl".to_string(),
+        OxmKind::Icmpv4Type => "icmp_type".to_string(),
+        OxmKind::Icmpv4Code => "icmp_code".to_string(),
+        OxmKind::Icmpv6Type => "icmpv6_type".to_string(),
+        OxmKind::Icmpv6Code => "icmpv6_code".to_string(),
+        OxmKind::ArpOp => "arp_op".to_string(),
+        OxmKind::TunnelId => "tun_id".to_string(),
+        OxmKind::Metadata => "metadata".to_string(),
+        OxmKind::Reg(n) => format!("reg{}", n),
+        OxmKind::Other { class, field: code } => {
+            let hex: String = field.value.iter().map(|b| format!("{:02x}", b)).collect();
+            return Some((format!("oxm_{:04x}_{}", class, code), hex));
+        }
+    };
+    let value = match field.mask_uint() {
+        None => field.value_uint().to_string(),
+        Some(mask) => format!("0x{:x}/0x{:x}", field.value_uint(), mask),
+    };
+    Some((key, value))
+}
+
+fn render_actions(instructions: &[Instruction], ports: &PortMap) -> Vec<FlowAction> {
+    let mut actions = Vec::new();
+    for instruction in instructions {
+        let list = match instruction {
+            Instruction::ApplyActions(list) | Instruction::WriteActions(list) => list,
+            other => {
+                log::debug!("Flow instruction {:?} has no declarative form", other);
+                continue;
+            }
+        };
+        for action in list {
+            actions.push(match action {
+                Action::Output { port, .. } if *port == ofp_port::NORMAL => FlowAction::Normal,
+                Action::Output { port, max_len } if *port == ofp_port::CONTROLLER => {
+                    FlowAction::Controller {
+                        max_len: (*max_len != OFPCML_NO_BUFFER).then_some(*max_len),
+                    }
+                }
+                Action::Output { port, .. } => FlowAction::Output {
+                    port: ports.name(*port),
+                },
+                Action::Resubmit { table, .. } => FlowAction::Resubmit { table: *table },
+                Action::SetNwTtl(ttl) => FlowAction::SetField {
+                    field: "nw_ttl".into(),
+                    value: ttl.to_string(),
+                },
+                Action::SetField(field) => match field.kind {
+                    OxmKind::Reg(register) => FlowAction::LoadRegister {
+                        register,
+                        value: field.value_uint(),
+                    },
+                    _ => match render_field(field, ports) {
+                        Some((field, value)) => FlowAction::SetField { field, value },
+                        None => continue,
+                    },
+                },
+                other => {
+                    log::debug!("OpenFlow action {:?} has no declarative form", other);
+                    continue;
+                }
+            });
+        }
+    }
+    if actions.is_empty() {
+        actions.push(FlowAction::Drop);
+    }
+    actions
+}
+
+fn parse_uint(value: &str) -> Result<u64> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| anyhow!("'{}' is not a number", value))
+}
+
+/// "value" or "value/mask"
+fn split_mask(value: &str, full: u64) -> Result<(u64, u64)> {
+    match value.split_once('/') {
+        Some((value, mask)) => Ok((parse_uint(value)?, parse_uint(mask)?)),
+        None => Ok((parse_uint(value)?, full)),
+    }
+}
+
+fn parse_uint_field(kind: OxmKind, value: &str) -> Result<OxmField> {
+    let width = kind.width().unwrap_or(8);
+    let max = if width >= 8 {
+        u64::MAX
+    } else {
+        (1u64 << (width * 8)) - 1
+    };
+    let (value, mask) = split_mask(value, max)?;
+    if value > max || mask > max {
+        bail!("value does not fit in {} bytes", width);
+    }
+    Ok(OxmField::uint_masked(kind, value, mask))
+}
+
+fn parse_ipv4(kind: OxmKind, value: &str) -> Result<OxmField> {
+    let (addr, mask) = value.split_once('/').unwrap_or((value, "32"));
+    let addr: Ipv4Addr = addr.parse()?;
+    match mask.parse::<u8>() {
+        Ok(prefix) if prefix <= 32 => Ok(OxmField::ipv4(kind, addr, prefix)),
+        _ => {
+            let mask: Ipv4Addr = mask.parse()?;
+            Ok(OxmField::masked(kind, &addr.octets(), &mask.octets()))
+        }
+    }
+}
+
+fn parse_ipv6(kind: OxmKind, value: &str) -> Result<OxmField> {
+    let (addr, mask) = value.split_once('/').unwrap_or((value, "128"));
+    let addr: Ipv6Addr = addr.parse()?;
+    match mask.parse::<u8>() {
+        Ok(prefix) if prefix <= 128 => Ok(OxmField::ipv6(kind, addr, prefix)),
+        _ => {
+            let mask: Ipv6Addr = mask.parse()?;
+            Ok(OxmField::masked(kind, &addr.octets(), &mask.octets()))
+        }
+    }
+}
+
+fn parse_mac(kind: OxmKind, value: &str) -> Result<OxmField> {
+    let (addr, mask) = value
+        .split_once('/')
+        .unwrap_or((value, "ff:ff:ff:ff:ff:ff"));
+    Ok(OxmField::masked(kind, &mac_bytes(addr)?, &mac_bytes(mask)?))
+}
+
+fn mac_bytes(value: &str) -> Result<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let parts: Vec<&str> = value.split(':').collect();
+    if parts.len() != 6 {
+        bail!("'{}' is not a MAC address", value);
+    }
+    for (byte, part) in mac.iter_mut().zip(parts) {
+        *byte = u8::from_str_radix(part, 16)
+            .map_err(|_| anyhow!("'{}' is not a MAC address", value))?;
+    }
+    Ok(mac)
+}
+
+fn format_mac(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// "+flag-flag" syntax into (value, mask)
+fn parse_flags(value: &str, names: &[&str]) -> Result<(u64, u64)> {
+    let (mut bits, mut mask) = (0u64, 0u64);
+    let mut rest = value;
+    while !rest.is_empty() {
+        let set = match rest.as_bytes()[0] {
+            b'+' => true,
+            b'-' => false,
+            _ => bail!("flags must be written as +flag or -flag"),
+        };
+        rest = &rest[1..];
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let name = &rest[..end];
+        let bit = names
+            .iter()
+            .position(|n| *n == name)
+            .ok_or_else(|| anyhow!("unknown flag '{}'", name))?;
+        mask |= 1 << bit;
+        if set {
+            bits |= 1 << bit;
+        }
+        rest = &rest[end..];
+    }
+    Ok((bits, mask))
+}
+
+fn format_flags(value: u64, mask: u64, names: &[&str]) -> String {
+    names
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(bit, name)| {
+            let sign = if value & (1 << bit) != 0 { '+' } else { '-' };
+            format!("{}{}", sign, name)
+        })
+        .collect()
+}
+
+fn u128_from(bytes: &[u8]) -> u128 {
+    bytes.iter().fold(0, |acc, b| acc << 8 | u128::from(*b))
+}
+
+/// Prefix length of a contiguous netmask
+fn prefix_len(mask: u128, width: u32) -> Option<u8> {
+    let ones = mask.count_ones();
+    let expected = if ones == 0 {
+        0
+    } else {
+        (u128::MAX >> (128 - width)) & !((1u128 << (width - ones)) - 1)
+    };
+    (mask == expected).then_some(ones as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(fields: &[(&str, &str)], actions: Vec<FlowAction>) -> FlowEntry {
+        FlowEntry {
+            table: 0,
+            priority: 100,
+            match_fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            actions,
+            cookie: None,
+            idle_timeout: 0,
+            hard_timeout: 0,
+        }
+    }
+
+    fn ports() -> PortMap {
+        PortMap::new([
+            ("vi100".to_string(), 3),
+            ("ovsbr0".to_string(), ofp_port::LOCAL),
+        ])
+    }
+
+    #[test]
+    fn test_match_encoding_follows_ovs_ofctl() {
+        let entry = flow(
+            &[
+                ("tcp", ""),
+                ("in_port", "vi100"),
+                ("tp_dst", "443"),
+                ("nw_src", "224.0.0.0/4"),
+                ("tcp_flags", "+syn-ack"),
+                ("ct_state", "+est+trk"),
+            ],
+            vec![FlowAction::Normal],
+        );
+        let flow_mod = to_flow_mod(&entry, &ports()).unwrap();
+        let m = &flow_mod.match_fields;
+
+        assert_eq!(m.fields[0], OxmField::uint(OxmKind::EthType, 0x0800));
+        assert_eq!(m.fields[1], OxmField::uint(OxmKind::IpProto, 6));
+        assert_eq!(m.get(OxmKind::InPort).unwrap().value_uint(), 3);
+        assert_eq!(m.get(OxmKind::TcpDst).unwrap().value_uint(), 443);
+        assert_eq!(
+            m.get(OxmKind::Ipv4Src).unwrap().mask_uint(),
+            Some(0xf000_0000)
+        );
+        let flags = m.get(OxmKind::TcpFlags).unwrap();
+        assert_eq!((flags.value_uint(), flags.mask_uint()), (0x02, Some(0x12)));
+        let ct = m.get(OxmKind::CtState).unwrap();
+        assert_eq!((ct.value_uint(), ct.mask_uint()), (0x22, Some(0x22)));
+
+        // udp makes tp_dst a UDP port; without a protocol it's an error
+        let udp = to_flow_mod(&flow(&[("udp", ""), ("tp_dst", "68")], vec![]), &ports()).unwrap();
+        assert!(udp.match_fields.get(OxmKind::UdpDst).is_some());
+        assert!(udp.instructions.is_empty());
+        assert!(to_flow_mod(&flow(&[("tp_dst", "68")], vec![]), &ports()).is_err());
+        assert!(to_flow_mod(&flow(&[("in_port", "nope")], vec![]), &ports()).is_err());
+    }
+
+    #[test]
+    fn test_canonical_round_trip() {
+        let entry = flow(
+            &[
+                ("ip", ""),
+                ("dl_src", "ff:ff:ff:ff:ff:ff"),
+                ("ip_frag", "yes"),
+                ("nw_dst", "240.0.0.0/4"),
+                ("tcp_flags", "0x000"),
+            ],
+            vec![
+                FlowAction::LoadRegister {
+                    register: 4,
+                    value: 443,
+                },
+                FlowAction::SetField {
+                    field: "nw_ttl".into(),
+                    value: "64".into(),
+                },
+                FlowAction::Resubmit { table: 10 },
+                FlowAction::Output {
+                    port: "ovsbr0".into(),
+                },
+                FlowAction::Controller { max_len: None },
+            ],
+        );
+        let canonical = canonicalize(&entry, &ports()).unwrap();
+        // tcp_flags implies tcp, so the ip shorthand becomes tcp
+        assert_eq!(
+            canonical.match_fields.get("tcp").map(String::as_str),
+            Some("")
+        );
+        assert!(!canonical.match_fields.contains_key("ip"));
+        assert_eq!(canonical.match_fields["ip_frag"], "yes");
+        assert_eq!(canonical.match_fields["nw_dst"], "240.0.0.0/4");
+        assert_eq!(canonical.match_fields["tcp_flags"], "0x000");
+        assert_eq!(canonical.match_fields["dl_src"], "ff:ff:ff:ff:ff:ff");
+        assert_eq!(canonical.cookie, Some(0));
+        assert_eq!(
+            canonical.actions[3],
+            FlowAction::Output {
+                port: "LOCAL".into()
+            }
+        );
+
+        // What the switch reports for the flow equals the canonical form
+        let flow_mod = to_flow_mod(&entry, &ports()).unwrap();
+        let stats = FlowStats {
+            table_id: flow_mod.table_id,
+            duration_sec: 0,
+            duration_nsec: 0,
+            priority: flow_mod.priority,
+            idle_timeout: 0,
+            hard_timeout: 0,
+            flags: 0,
+            cookie: 0,
+            packet_count: 0,
+            byte_count: 0,
+            match_fields: flow_mod.match_fields.clone(),
+            instructions: flow_mod.instructions.clone(),
+        };
+        assert_eq!(from_flow_stats(&stats, &ports()), canonical);
+        assert_eq!(canonicalize(&canonical, &ports()).unwrap(), canonical);
+
+        // A flow without actions drops
+        let drop = canonicalize(&flow(&[("arp", "")], vec![FlowAction::Drop]), &ports()).unwrap();
+        assert_eq!(drop.actions, vec![FlowAction::Drop]);
+    }
+}
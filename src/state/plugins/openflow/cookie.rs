@@ -0,0 +1,150 @@
+//! Namespaced flow cookies marking op-dbus ownership
+//!
+//! Layout: `0x0DB5` namespace (16 bits) | family (8) | tag (8) | id (32).
+//! The family says which generator produced a flow, the tag distinguishes
+//! policies within a family and the id identifies the flow itself, so every
+//! flow op-dbus installs can be found (and deleted) by cookie alone while
+//! flows from other controllers or admins never match the namespace.
+
+use super::FlowEntry;
+
+pub const NAMESPACE: u64 = 0x0db5 << 48;
+pub const NAMESPACE_MASK: u64 = 0xffff << 48;
+const FAMILY_MASK: u64 = 0xff << 40;
+
+/// What produced an owned flow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowFamily {
+    /// Flows written out in the bridge config
+    Static = 0,
+    Security = 1,
+    PatternHiding = 2,
+    Obfuscation = 3,
+    /// Flows generated from a flow policy for a discovered container
+    Policy = 4,
+    /// Tunnel-chain flows of the privacy plugin; the openflow diff leaves them alone
+    Privacy = 5,
+}
+
+impl FlowFamily {
+    const ALL: [FlowFamily; 6] = [
+        FlowFamily::Static,
+        FlowFamily::Security,
+        FlowFamily::PatternHiding,
+        FlowFamily::Obfuscation,
+        FlowFamily::Policy,
+        FlowFamily::Privacy,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FlowFamily::Static => "static",
+            FlowFamily::Security => "security",
+            FlowFamily::PatternHiding => "pattern-hiding",
+            FlowFamily::Obfuscation => "obfuscation",
+            FlowFamily::Policy => "policy",
+            FlowFamily::Privacy => "privacy",
+        }
+    }
+
+    /// (cookie, mask) selecting every flow of this family
+    pub fn cookie_match(self) -> (u64, u64) {
+        (make(self, 0, 0), NAMESPACE_MASK | FAMILY_MASK)
+    }
+}
+
+pub fn make(family: FlowFamily, tag: u8, id: u32) -> u64 {
+    NAMESPACE | (family as u64) << 40 | u64::from(tag) << 32 | u64::from(id)
+}
+
+pub fn is_owned(cookie: u64) -> bool {
+    cookie & NAMESPACE_MASK == NAMESPACE
+}
+
+pub fn family(cookie: u64) -> Option<FlowFamily> {
+    if !is_owned(cookie) {
+        return None;
+    }
+    let code = (cookie >> 40 & 0xff) as usize;
+    FlowFamily::ALL.get(code).copied()
+}
+
+/// Stable 8-bit tag for a policy or template name
+pub fn tag(name: &str) -> u8 {
+    let hash = fnv1a(name.as_bytes());
+    (hash ^ hash >> 8 ^ hash >> 16 ^ hash >> 24) as u8
+}
+
+/// Stable id for a flow without an explicit cookie, from its table, priority and match
+pub fn flow_id(flow: &FlowEntry) -> u32 {
+    let mut fields: Vec<_> = flow.match_fields.iter().collect();
+    fields.sort();
+    let mut key = format!("{}/{}", flow.table, flow.priority);
+    for (k, v) in fields {
+        key.push_str(&format!(",{}={}", k, v));
+    }
+    fnv1a(key.as_bytes())
+}
+
+/// Tag a flow as owned by `family`, keeping an existing id if it has one
+pub fn claim(flow: &mut FlowEntry, family: FlowFamily, tag: u8) {
+    let cookie = match flow.cookie {
+        Some(cookie) if is_owned(cookie) => cookie,
+        Some(cookie) => make(family, tag, cookie as u32),
+        None => make(family, tag, flow_id(flow)),
+    };
+    flow.cookie = Some(cookie);
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash: u32, b| {
+        (hash ^ u32::from(*b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_cookie_layout() {
+        let cookie = make(FlowFamily::Security, 0, 0xDEAD_0001);
+        assert_eq!(cookie, 0x0db5_0100_dead_0001);
+        assert!(is_owned(cookie));
+        assert_eq!(family(cookie), Some(FlowFamily::Security));
+        assert!(!is_owned(0xDEAD_0001));
+        assert_eq!(family(0), None);
+
+        let (value, mask) = FlowFamily::Security.cookie_match();
+        assert_eq!(cookie & mask, value);
+        assert_ne!(make(FlowFamily::Policy, 1, 1) & mask, value);
+    }
+
+    #[test]
+    fn test_claim_is_stable() {
+        let mut flow = FlowEntry {
+            table: 0,
+            priority: 10,
+            match_fields: HashMap::from([("ip".to_string(), String::new())]),
+            actions: vec![],
+            cookie: None,
+            idle_timeout: 0,
+            hard_timeout: 0,
+        };
+        let mut again = flow.clone();
+        claim(&mut flow, FlowFamily::Static, 0);
+        claim(&mut again, FlowFamily::Static, 0);
+        assert_eq!(flow.cookie, again.cookie);
+
+        // Claiming an owned flow again keeps its cookie
+        let cookie = flow.cookie;
+        claim(&mut flow, FlowFamily::Policy, tag("web"));
+        assert_eq!(flow.cookie, cookie);
+
+        // An explicit foreign cookie becomes the id
+        flow.cookie = Some(0xCAFE_0002);
+        claim(&mut flow, FlowFamily::PatternHiding, 0);
+        assert_eq!(flow.cookie, Some(make(FlowFamily::PatternHiding, 0, 0xCAFE_0002)));
+    }
+}
@@ -0,0 +1,375 @@
+//! Offline packet traces through a bridge's desired flows
+//!
+//! Flows are encoded exactly as they would be installed and run through
+//! `native::openflow_trace`, so the table-by-table path matches what the
+//! switch would do. Used by `op-dbus openflow trace` and by tests that need
+//! to reason about the generated security and obfuscation flows.
+
+use super::convert::{self, PortMap};
+use super::{BridgeFlowConfig, FlowAction, FlowEntry, OpenFlowConfig, OpenFlowPlugin};
+use crate::native::openflow::FlowMod;
+use crate::native::openflow_trace::{self, Packet};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// One table lookup on the packet's path
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceHop {
+    pub table: u8,
+    /// Resubmit/goto nesting level
+    pub depth: usize,
+    /// Matching flow, None on a table miss
+    pub flow: Option<FlowEntry>,
+    /// Flows that matched at the same priority; the switch may pick any of them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ties: Vec<FlowEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceReport {
+    pub bridge: String,
+    pub packet: String,
+    pub hops: Vec<TraceHop>,
+    /// Output ports by name, in order; empty when the packet is dropped
+    pub outputs: Vec<String>,
+    pub groups: Vec<u32>,
+    /// Evaluation hit the resubmit depth limit
+    pub truncated: bool,
+}
+
+impl TraceReport {
+    pub fn dropped(&self) -> bool {
+        self.outputs.is_empty() && self.groups.is_empty()
+    }
+
+    /// Cookies of the matched flows, in order
+    pub fn cookies(&self) -> Vec<u64> {
+        self.hops
+            .iter()
+            .filter_map(|hop| hop.flow.as_ref().and_then(|f| f.cookie))
+            .collect()
+    }
+}
+
+impl fmt::Display for TraceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Bridge: {}", self.bridge)?;
+        writeln!(f, "Packet: {}", self.packet)?;
+        for hop in &self.hops {
+            let indent = "    ".repeat(hop.depth);
+            match &hop.flow {
+                Some(flow) => {
+                    writeln!(f, "{}table {}: {}", indent, hop.table, describe_flow(flow))?
+                }
+                None => writeln!(f, "{}table {}: no match, drop", indent, hop.table)?,
+            }
+            for tie in &hop.ties {
+                writeln!(
+                    f,
+                    "{}    also matches at the same priority: {}",
+                    indent,
+                    describe_flow(tie)
+                )?;
+            }
+        }
+        if self.truncated {
+            writeln!(f, "Resubmit depth limit reached, evaluation stopped")?;
+        }
+        if self.dropped() {
+            write!(f, "Final: drop")
+        } else {
+            let mut targets: Vec<String> = self
+                .outputs
+                .iter()
+                .map(|p| format!("output:{}", p))
+                .collect();
+            targets.extend(self.groups.iter().map(|g| format!("group:{}", g)));
+            write!(f, "Final: {}", targets.join(", "))
+        }
+    }
+}
+
+/// Trace a packet through a bridge of a desired openflow config, generated flows included
+pub fn trace_config(
+    config: &OpenFlowConfig,
+    bridge: Option<&str>,
+    packet: &str,
+) -> Result<TraceReport> {
+    let mut config = config.clone();
+    OpenFlowPlugin::inject_generated_flows(&mut config);
+
+    let bridge_config = match bridge {
+        Some(name) => config
+            .bridges
+            .iter()
+            .find(|b| b.name == name)
+            .ok_or_else(|| anyhow!("Bridge {} is not in the openflow config", name))?,
+        None => match config.bridges.as_slice() {
+            [only] => only,
+            [] => bail!("The openflow config has no bridges"),
+            _ => bail!("The openflow config has several bridges, pick one with --bridge"),
+        },
+    };
+
+    let fields = packet_fields(packet)?;
+    let ports = offline_ports(bridge_config, fields.get("in_port").map(String::as_str));
+    trace_with_ports(&bridge_config.name, &bridge_config.flows, &ports, packet)
+}
+
+/// Trace a packet through a flow list; port names get numbers in order of appearance
+pub fn trace_flows(bridge: &str, flows: &[FlowEntry], packet: &str) -> Result<TraceReport> {
+    let bridge_config = BridgeFlowConfig {
+        name: bridge.to_string(),
+        flows: flows.to_vec(),
+        foreign_flows: None,
+        socket_ports: None,
+    };
+    let fields = packet_fields(packet)?;
+    let ports = offline_ports(&bridge_config, fields.get("in_port").map(String::as_str));
+    trace_with_ports(bridge, flows, &ports, packet)
+}
+
+fn trace_with_ports(
+    bridge: &str,
+    flows: &[FlowEntry],
+    ports: &PortMap,
+    packet: &str,
+) -> Result<TraceReport> {
+    let flow_mods = flows
+        .iter()
+        .enumerate()
+        .map(|(i, flow)| {
+            convert::to_flow_mod(flow, ports)
+                .with_context(|| format!("Flow {} ({}) cannot be encoded", i, describe_flow(flow)))
+        })
+        .collect::<Result<Vec<FlowMod>>>()?;
+
+    let fields = packet_fields(packet)?;
+    if let Some((key, value)) = fields.iter().find(|(_, v)| v.contains('/')) {
+        bail!(
+            "Packet fields must be exact values, not masks: {}={}",
+            key,
+            value
+        );
+    }
+    let packet_match = convert::to_match(&fields, ports)?;
+    let trace = openflow_trace::trace(&flow_mods, Packet::new(packet_match));
+
+    Ok(TraceReport {
+        bridge: bridge.to_string(),
+        packet: packet.to_string(),
+        hops: trace
+            .steps
+            .iter()
+            .map(|step| TraceHop {
+                table: step.table,
+                depth: step.depth,
+                flow: step.flow.map(|i| flows[i].clone()),
+                ties: step.ties.iter().map(|i| flows[*i].clone()).collect(),
+            })
+            .collect(),
+        outputs: trace.outputs.iter().map(|p| ports.name(*p)).collect(),
+        groups: trace.groups.clone(),
+        truncated: trace.truncated,
+    })
+}
+
+/// "in_port=vi100,tcp,tp_dst=443" as match fields
+fn packet_fields(spec: &str) -> Result<HashMap<String, String>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            Some(_) => Err(anyhow!("Malformed packet field '{}'", part)),
+            None => Ok((part.to_string(), String::new())),
+        })
+        .collect()
+}
+
+/// Port numbers without a switch: declared socket ports keep their ofport,
+/// other names are numbered in sorted order and the bridge itself is LOCAL
+fn offline_ports(bridge: &BridgeFlowConfig, packet_in_port: Option<&str>) -> PortMap {
+    let mut known: Vec<(String, u32)> = bridge
+        .socket_ports
+        .iter()
+        .flatten()
+        .filter_map(|p| p.ofport.map(|n| (p.name.clone(), u32::from(n))))
+        .collect();
+    known.push((
+        bridge.name.clone(),
+        crate::native::openflow::ofp_port::LOCAL,
+    ));
+
+    let mut names = BTreeSet::new();
+    for flow in &bridge.flows {
+        if let Some(port) = flow.match_fields.get("in_port") {
+            names.insert(port.clone());
+        }
+        for action in &flow.actions {
+            if let FlowAction::Output { port } = action {
+                names.insert(port.clone());
+            }
+        }
+    }
+    names.extend(packet_in_port.map(str::to_string));
+
+    let probe = PortMap::new(known.clone());
+    let mut next = known
+        .iter()
+        .map(|(_, n)| *n)
+        .filter(|n| *n < crate::native::openflow::ofp_port::MAX)
+        .max()
+        .unwrap_or(0)
+        + 1;
+    for name in names {
+        if probe.number(&name).is_err() {
+            known.push((name, next));
+            next += 1;
+        }
+    }
+    PortMap::new(known)
+}
+
+/// ovs-ofctl style one-liner for a flow
+fn describe_flow(flow: &FlowEntry) -> String {
+    let mut fields: Vec<String> = flow
+        .match_fields
+        .iter()
+        .map(|(k, v)| {
+            if v.is_empty() {
+                k.clone()
+            } else {
+                format!("{}={}", k, v)
+            }
+        })
+        .collect();
+    fields.sort();
+
+    let actions: Vec<String> = flow
+        .actions
+        .iter()
+        .map(|action| match action {
+            FlowAction::Output { port } => format!("output:{}", port),
+            FlowAction::LoadRegister { register, value } => {
+                format!("load:{}->reg{}", value, register)
+            }
+            FlowAction::Resubmit { table } => format!("resubmit(,{})", table),
+            FlowAction::SetField { field, value } => format!("set_field:{}->{}", value, field),
+            FlowAction::Drop => "drop".to_string(),
+            FlowAction::Normal => "NORMAL".to_string(),
+            FlowAction::Controller { max_len: Some(len) } => format!("CONTROLLER:{}", len),
+            FlowAction::Controller { max_len: None } => "CONTROLLER".to_string(),
+        })
+        .collect();
+
+    let mut text = format!("priority={}", flow.priority);
+    if let Some(cookie) = flow.cookie {
+        text.push_str(&format!(",cookie={:#x}", cookie));
+    }
+    if !fields.is_empty() {
+        text.push(',');
+        text.push_str(&fields.join(","));
+    }
+    format!("{} actions={}", text, actions.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(level: u8, flows: Vec<FlowEntry>) -> OpenFlowConfig {
+        OpenFlowConfig {
+            bridges: vec![BridgeFlowConfig {
+                name: "ovsbr0".to_string(),
+                flows,
+                foreign_flows: None,
+                socket_ports: None,
+            }],
+            controller_endpoint: None,
+            flow_policies: None,
+            auto_discover_containers: false,
+            enable_security_flows: true,
+            obfuscation_level: level,
+        }
+    }
+
+    fn flow(
+        table: u8,
+        priority: u16,
+        fields: &[(&str, &str)],
+        actions: Vec<FlowAction>,
+    ) -> FlowEntry {
+        FlowEntry {
+            table,
+            priority,
+            match_fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            actions,
+            cookie: None,
+            idle_timeout: 0,
+            hard_timeout: 0,
+        }
+    }
+
+    #[test]
+    fn test_security_flows_drop_scans() {
+        let config = config(1, vec![flow(0, 1, &[], vec![FlowAction::Normal])]);
+
+        let null_scan =
+            trace_config(&config, None, "in_port=vi100,tcp,tcp_flags=0x000,tp_dst=22").unwrap();
+        assert!(null_scan.dropped(), "{}", null_scan);
+        assert_eq!(null_scan.cookies(), vec![0x0db5_0100_dead_0001]);
+
+        let arp = trace_config(&config, Some("ovsbr0"), "in_port=vi100,arp").unwrap();
+        assert_eq!(arp.outputs, vec!["CONTROLLER"]);
+
+        assert!(trace_config(&config, Some("nope"), "arp").is_err());
+        assert!(trace_config(&config, None, "nw_src=10.0.0.0/8").is_err());
+    }
+
+    #[test]
+    fn test_trace_follows_resubmits() {
+        let flows = vec![
+            flow(
+                0,
+                100,
+                &[("in_port", "vi100")],
+                vec![
+                    FlowAction::LoadRegister {
+                        register: 1,
+                        value: 100,
+                    },
+                    FlowAction::Resubmit { table: 10 },
+                ],
+            ),
+            flow(
+                10,
+                50,
+                &[("reg1", "100"), ("ip", "")],
+                vec![FlowAction::Output {
+                    port: "vi200".to_string(),
+                }],
+            ),
+            flow(10, 50, &[("reg1", "100")], vec![FlowAction::Drop]),
+        ];
+
+        let report = trace_flows("ovsbr0", &flows, "in_port=vi100,ip,nw_dst=10.0.0.2").unwrap();
+        assert_eq!(report.hops.len(), 2);
+        assert_eq!(report.hops[1].depth, 1);
+        assert_eq!(report.outputs, vec!["vi200"]);
+        // Both table 10 flows match at priority 50
+        assert_eq!(report.hops[1].ties.len(), 1);
+        assert!(report
+            .to_string()
+            .contains("also matches at the same priority"));
+
+        let other = trace_flows("ovsbr0", &flows, "in_port=vi200,ip").unwrap();
+        assert!(other.dropped());
+        assert_eq!(other.hops[0].flow, None);
+    }
+}
@@ -1,10 +1,45 @@
-use crate::state::plugin::{StatePlugin, StateDiff, ApplyResult, PluginCapabilities, StateAction};
-use anyhow::Result;
+//! Privacy plugin - orchestrates the tunnel chain
+//!
+//! The chain is WireGuard gateway (clients arrive on `wg0`) -> XRay client
+//! container on the Proxmox bridge -> WARP tunnel (`warp0`) -> internet. The
+//! hops themselves are brought up by their own plugins (wireguard, lxc,
+//! openflow); this plugin wires them together:
+//! - a dedicated routing table whose default leaves through WARP, backed by an
+//!   unreachable route so nothing falls back to the main table when WARP is down
+//! - policy rules steering traffic from `wg0` and from the XRay container into it
+//! - the container's OVS port, and flows that only let it send from its own address
+//! - a health check proving that traffic really leaves through the WARP interface
+//!
+//! Routes and rules carry [`RTPROT_PRIVACY`] and flows the privacy cookie family,
+//! so the net and openflow plugins leave them alone. Forwarding and masquerading
+//! on the WARP interface remain with the sysctl and nft plugins.
+
+use crate::native::rtnetlink_links;
+use crate::native::rtnetlink_routes::{
+    self, Route, RouteSpec, RouteType, Rule, RuleSpec, TableNames, RTPROT_PRIVACY,
+};
+use crate::native::wireguard::WgClient;
+use crate::native::OvsdbClient;
+use crate::state::plugin::{
+    ApplyResult, Checkpoint, DiffMetadata, PluginCapabilities, StateAction, StateDiff, StatePlugin,
+};
+use crate::state::plugins::openflow::cookie::FlowFamily;
+use crate::state::plugins::openflow::{FlowAction, FlowEntry, OpenFlowPlugin};
+use crate::state::plugins::LxcPlugin;
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Handshakes older than this mean a WireGuard peer is idle or gone
+const HANDSHAKE_STALE_SECS: i64 = 180;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PrivacyConfig {
     /// Enable WireGuard gateway (system service)
     pub wireguard_gateway_enabled: bool,
@@ -24,9 +59,15 @@ pub struct PrivacyConfig {
     /// VPS XRay server address
     pub vps_xray_server: Option<String>,
 
-
     /// Proxmox-specific networking
     pub proxmox_bridge: String,
+
+    /// Routing table holding the chain routes
+    pub routing_table: u32,
+    /// Preference of the first chain rule; further rules follow it
+    pub rule_priority: u32,
+    /// Egress check through the WARP interface
+    pub health_check: HealthCheck,
 }
 
 impl Default for PrivacyConfig {
@@ -41,20 +82,782 @@ impl Default for PrivacyConfig {
             xray_socks_port: 1080,
             vps_xray_server: None,
             proxmox_bridge: "vmbr0".to_string(),
+            routing_table: 200,
+            rule_priority: 10000,
+            health_check: HealthCheck::default(),
         }
     }
 }
 
-pub struct PrivacyPlugin {
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthCheck {
+    pub enabled: bool,
+    /// host:port reached over TCP through the WARP interface, and looked up
+    /// with the chain's selectors to make sure they are routed there too
+    pub target: String,
+    pub timeout_secs: u64,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            target: "1.1.1.1:443".to_string(),
+            timeout_secs: 5,
+        }
+    }
+}
+
+/// Declared state: the chain config (query adds `hops` and `health`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PrivacyState {
+    #[serde(default)]
     config: PrivacyConfig,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum HopStatus {
+    Up,
+    Degraded,
+    Down,
+    Disabled,
+}
+
+/// Per-hop report in the queried state
+#[derive(Debug, Clone, Serialize)]
+struct Hop {
+    hop: &'static str,
+    status: HopStatus,
+    details: Value,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    problems: Vec<String>,
+}
+
+impl Hop {
+    fn new(hop: &'static str, status: HopStatus, details: Value) -> Self {
+        Self {
+            hop,
+            status,
+            details,
+            problems: Vec::new(),
+        }
+    }
+
+    fn disabled(hop: &'static str) -> Self {
+        Self::new(hop, HopStatus::Disabled, Value::Null)
+    }
+
+    fn problem(mut self, status: HopStatus, problem: String) -> Self {
+        // Keep the worst status seen
+        if self.status == HopStatus::Up || status == HopStatus::Down {
+            self.status = status;
+        }
+        self.problems.push(problem);
+        self
+    }
+}
+
+/// What the chain is built from, read from the live system
+#[derive(Debug, Clone, Default)]
+struct ChainFacts {
+    /// IPv4 address of the XRay container from its container config
+    xray_address: Option<Ipv4Addr>,
+    /// IPv4 networks on the WireGuard gateway interface
+    wireguard_networks: Vec<(Ipv4Addr, u8)>,
+    /// The Proxmox bridge is an OVS bridge
+    ovs_bridge: bool,
+    bridge_ports: Vec<String>,
+    /// The container's host-side interface exists
+    xray_link: bool,
+}
+
+/// Snapshot of everything the plugin installed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ChainSnapshot {
+    config: PrivacyConfig,
+    routes: Vec<RouteSpec>,
+    rules: Vec<RuleSpec>,
+    flows: BTreeMap<String, Vec<FlowEntry>>,
+}
+
+pub struct PrivacyPlugin {
+    config: RwLock<PrivacyConfig>,
+    openflow: OpenFlowPlugin,
+    ovsdb: OvsdbClient,
+}
+
 impl PrivacyPlugin {
     pub fn new(config: PrivacyConfig) -> Self {
-        Self { config }
+        Self {
+            config: RwLock::new(config),
+            openflow: OpenFlowPlugin::new(),
+            ovsdb: OvsdbClient::new(),
+        }
+    }
+
+    async fn gather_facts(&self, config: &PrivacyConfig) -> Result<ChainFacts> {
+        let mut facts = ChainFacts::default();
+
+        if config.xray_client_enabled {
+            let id = config.xray_client_container_id.to_string();
+            if let Ok(addresses) = LxcPlugin::new().container_config_addresses(&id).await {
+                facts.xray_address = addresses.into_iter().find_map(|addr| match addr {
+                    IpAddr::V4(v4) => Some(v4),
+                    IpAddr::V6(_) => None,
+                });
+            }
+            facts.xray_link = rtnetlink_links::get_link(&xray_port(config))
+                .await?
+                .is_some();
+        }
+
+        if config.wireguard_gateway_enabled
+            && rtnetlink_links::get_link(&config.wireguard_interface)
+                .await?
+                .is_some()
+        {
+            facts.wireguard_networks =
+                crate::native::rtnetlink_helpers::list_addresses(&config.wireguard_interface)
+                    .await?
+                    .into_iter()
+                    .filter_map(|(addr, prefix)| match addr {
+                        IpAddr::V4(v4) => Some(network(v4, prefix)),
+                        IpAddr::V6(_) => None,
+                    })
+                    .collect();
+        }
+
+        match self.ovsdb.list_bridges().await {
+            Ok(bridges) if bridges.contains(&config.proxmox_bridge) => {
+                facts.ovs_bridge = true;
+                facts.bridge_ports = self.ovsdb.list_bridge_ports(&config.proxmox_bridge).await?;
+            }
+            Ok(_) => {}
+            Err(e) => log::debug!(
+                "OVSDB unavailable, treating bridges as Linux bridges: {}",
+                e
+            ),
+        }
+
+        Ok(facts)
+    }
+
+    /// Chain flows currently installed, per reachable OVS bridge
+    async fn current_flows(&self) -> BTreeMap<String, Vec<FlowEntry>> {
+        let mut flows = BTreeMap::new();
+        for bridge in self.ovsdb.list_bridges().await.unwrap_or_default() {
+            match self
+                .openflow
+                .family_flows(&bridge, FlowFamily::Privacy)
+                .await
+            {
+                Ok(family) => {
+                    flows.insert(bridge, family);
+                }
+                Err(e) => log::warn!("Cannot query flows on {}: {:#}", bridge, e),
+            }
+        }
+        flows
+    }
+
+    /// Desired chain flows as the switch will report them
+    async fn wanted_flows(&self, config: &PrivacyConfig, facts: &ChainFacts) -> Vec<FlowEntry> {
+        let (true, Some(address)) = (flows_wanted(config, facts), facts.xray_address) else {
+            return Vec::new();
+        };
+        let mut flows = desired_flows(&xray_port(config), address);
+        for flow in &mut flows {
+            crate::state::plugins::openflow::cookie::claim(flow, FlowFamily::Privacy, 0);
+        }
+        self.openflow
+            .canonicalize_flows(&config.proxmox_bridge, &mut flows)
+            .await;
+        flows
+    }
+
+    async fn snapshot(&self) -> Result<ChainSnapshot> {
+        let tables = TableNames::default();
+        let (routes, rules) = chain_routing().await?;
+        Ok(ChainSnapshot {
+            config: self.config.read().await.clone(),
+            routes: routes.iter().map(|r| r.to_spec(&tables)).collect(),
+            rules: rules.iter().map(|r| r.to_spec(&tables)).collect(),
+            flows: self.current_flows().await,
+        })
+    }
+
+    async fn wireguard_hop(&self, config: &PrivacyConfig) -> Result<Hop> {
+        if !config.wireguard_gateway_enabled {
+            return Ok(Hop::disabled("wireguard"));
+        }
+        let interface = &config.wireguard_interface;
+        let Some(link) = rtnetlink_links::get_link(interface).await? else {
+            return Ok(Hop::new(
+                "wireguard",
+                HopStatus::Down,
+                json!({ "interface": interface }),
+            )
+            .problem(HopStatus::Down, format!("{} does not exist", interface)));
+        };
+
+        let mut hop = Hop::new(
+            "wireguard",
+            HopStatus::Up,
+            json!({ "interface": interface }),
+        );
+        if !link.up {
+            hop = hop.problem(HopStatus::Down, format!("{} is down", interface));
+        }
+        match read_handshakes(interface).await {
+            Ok(handshakes) => {
+                let now = chrono::Utc::now().timestamp();
+                let active = handshakes
+                    .iter()
+                    .filter(|h| h.is_some_and(|t| now - t <= HANDSHAKE_STALE_SECS))
+                    .count();
+                hop.details["peers"] = json!(handshakes.len());
+                hop.details["active_peers"] = json!(active);
+                if handshakes.is_empty() {
+                    hop = hop.problem(HopStatus::Degraded, format!("{} has no peers", interface));
+                }
+            }
+            Err(e) => {
+                hop = hop.problem(
+                    HopStatus::Degraded,
+                    format!("Cannot read WireGuard state of {}: {:#}", interface, e),
+                );
+            }
+        }
+        Ok(hop)
+    }
+
+    async fn xray_hop(&self, config: &PrivacyConfig, facts: &ChainFacts) -> Result<Hop> {
+        if !config.xray_client_enabled {
+            return Ok(Hop::disabled("xray"));
+        }
+        let id = config.xray_client_container_id.to_string();
+        let mut hop = Hop::new(
+            "xray",
+            HopStatus::Up,
+            json!({
+                "container": id,
+                "address": facts.xray_address,
+                "socks_port": config.xray_socks_port,
+                "server": config.vps_xray_server,
+            }),
+        );
+
+        let running = LxcPlugin::new()
+            .container_addresses()
+            .await
+            .ok()
+            .and_then(|containers| {
+                containers
+                    .into_iter()
+                    .find(|(info, _)| info.id == id)
+                    .and_then(|(info, _)| info.running)
+            });
+        if running == Some(false) || !facts.xray_link {
+            return Ok(hop.problem(HopStatus::Down, format!("Container {} is not running", id)));
+        }
+        if facts.ovs_bridge && !facts.bridge_ports.contains(&xray_port(config)) {
+            hop = hop.problem(
+                HopStatus::Degraded,
+                format!(
+                    "{} is not attached to {}",
+                    xray_port(config),
+                    config.proxmox_bridge
+                ),
+            );
+        }
+
+        let Some(address) = facts.xray_address else {
+            return Ok(hop.problem(
+                HopStatus::Degraded,
+                format!("Container {} has no static IPv4 address", id),
+            ));
+        };
+        let socks = tokio::time::timeout(
+            Duration::from_secs(2),
+            tokio::net::TcpStream::connect((address, config.xray_socks_port)),
+        )
+        .await;
+        if !matches!(socks, Ok(Ok(_))) {
+            hop = hop.problem(
+                HopStatus::Degraded,
+                format!(
+                    "SOCKS proxy {}:{} is not accepting connections",
+                    address, config.xray_socks_port
+                ),
+            );
+        }
+        Ok(hop)
+    }
+
+    async fn warp_hop(&self, config: &PrivacyConfig) -> Result<Hop> {
+        if !config.warp_tunnel_enabled {
+            return Ok(Hop::disabled("warp"));
+        }
+        let interface = &config.warp_interface;
+        let Some(link) = rtnetlink_links::get_link(interface).await? else {
+            return Ok(
+                Hop::new("warp", HopStatus::Down, json!({ "interface": interface }))
+                    .problem(HopStatus::Down, format!("{} does not exist", interface)),
+            );
+        };
+
+        let mut hop = Hop::new(
+            "warp",
+            HopStatus::Up,
+            json!({ "interface": interface, "kind": link.kind }),
+        );
+        if !link.up {
+            hop = hop.problem(HopStatus::Down, format!("{} is down", interface));
+        }
+        // Kernel WireGuard based WARP clients report handshakes; others only link state
+        if link.kind.as_deref() == Some("wireguard") {
+            let latest = read_handshakes(interface)
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .max();
+            let age = latest.map(|t| chrono::Utc::now().timestamp() - t);
+            hop.details["handshake_age_secs"] = json!(age);
+            if age.is_none_or(|age| age > HANDSHAKE_STALE_SECS) {
+                hop = hop.problem(
+                    HopStatus::Degraded,
+                    format!("No recent handshake on {}", interface),
+                );
+            }
+        }
+        Ok(hop)
+    }
+
+    async fn routing_hop(&self, config: &PrivacyConfig, facts: &ChainFacts) -> Result<Hop> {
+        let (routes, rules) = match desired_routing(config, facts) {
+            Ok(routing) => routing,
+            Err(e) => {
+                return Ok(Hop::new("routing", HopStatus::Down, Value::Null)
+                    .problem(HopStatus::Down, format!("{:#}", e)))
+            }
+        };
+        if routes.is_empty() && rules.is_empty() {
+            return Ok(Hop::disabled("routing"));
+        }
+
+        let (current_routes, current_rules) = chain_routing().await?;
+        let mut hop = Hop::new(
+            "routing",
+            HopStatus::Up,
+            json!({ "table": config.routing_table, "routes": routes.len(), "rules": rules.len() }),
+        );
+        for route in &routes {
+            if !current_routes.iter().any(|c| route.satisfied_by(c)) {
+                hop = hop.problem(HopStatus::Degraded, format!("Missing {}", route.key()));
+            }
+        }
+        for rule in &rules {
+            if !current_rules.iter().any(|c| rule.satisfied_by(c)) {
+                hop = hop.problem(HopStatus::Degraded, format!("Missing {}", rule.key()));
+            }
+        }
+        Ok(hop)
+    }
+
+    async fn flows_hop(&self, config: &PrivacyConfig, facts: &ChainFacts) -> Result<Hop> {
+        if !config.xray_client_enabled {
+            return Ok(Hop::disabled("flows"));
+        }
+        if !facts.ovs_bridge {
+            return Ok(Hop::new(
+                "flows",
+                HopStatus::Disabled,
+                json!({ "reason": format!("{} is not an OVS bridge", config.proxmox_bridge) }),
+            ));
+        }
+        let wanted = self.wanted_flows(config, facts).await;
+        let current = self
+            .openflow
+            .family_flows(&config.proxmox_bridge, FlowFamily::Privacy)
+            .await?;
+        let mut hop = Hop::new(
+            "flows",
+            HopStatus::Up,
+            json!({ "bridge": config.proxmox_bridge, "installed": current.len() }),
+        );
+        if wanted.is_empty() {
+            hop = hop.problem(
+                HopStatus::Degraded,
+                "Container port or address missing, no flows can be installed".to_string(),
+            );
+        } else if !same_flows(&wanted, &current) {
+            hop = hop.problem(HopStatus::Degraded, "Chain flows out of sync".to_string());
+        }
+        Ok(hop)
+    }
+
+    async fn health(&self, config: &PrivacyConfig, facts: &ChainFacts) -> Value {
+        if !config.warp_tunnel_enabled || !config.health_check.enabled {
+            return json!({ "ok": Value::Null, "checked": false });
+        }
+        match probe_egress(config, facts).await {
+            Ok(verified) => json!({
+                "ok": true,
+                "interface": config.warp_interface,
+                "target": config.health_check.target,
+                "routed": verified,
+            }),
+            Err(e) => json!({
+                "ok": false,
+                "interface": config.warp_interface,
+                "target": config.health_check.target,
+                "error": format!("{:#}", e),
+            }),
+        }
+    }
+
+    async fn apply_action(&self, action: &StateAction) -> Result<String> {
+        let tables = TableNames::default();
+        match action {
+            StateAction::Modify { resource, changes } if resource == "chain" => {
+                let config: PrivacyConfig = serde_json::from_value(changes["config"].clone())?;
+                *self.config.write().await = config;
+                Ok("Updated chain config".to_string())
+            }
+            StateAction::Create { resource, .. } if resource.starts_with("ovs/") => {
+                let (bridge, port) = parse_port_resource(resource)?;
+                self.ovsdb.add_port(bridge, port).await?;
+                Ok(format!("Attached {} to {}", port, bridge))
+            }
+            StateAction::Modify { resource, changes } if resource.starts_with("ovs/") => {
+                let bridge = resource
+                    .strip_prefix("ovs/")
+                    .and_then(|r| r.strip_suffix("/flows"))
+                    .ok_or_else(|| anyhow!("Malformed flows resource {}", resource))?;
+                let flows: Vec<FlowEntry> = serde_json::from_value(changes["flows"].clone())?;
+                self.openflow
+                    .replace_family_flows(bridge, FlowFamily::Privacy, &flows)
+                    .await?;
+                Ok(format!(
+                    "Installed {} chain flows on {}",
+                    flows.len(),
+                    bridge
+                ))
+            }
+            StateAction::Create { resource, config } if resource.starts_with("routing/route/") => {
+                let spec: RouteSpec = serde_json::from_value(config.clone())?;
+                let route = chain_route(&spec, &tables)?;
+                rtnetlink_routes::add_route(&route).await?;
+                Ok(format!("Added {}", route.key()))
+            }
+            StateAction::Create { resource, config } if resource.starts_with("routing/rule/") => {
+                let spec: RuleSpec = serde_json::from_value(config.clone())?;
+                let rule = chain_rule(&spec, &tables)?;
+                rtnetlink_routes::add_rule(&rule).await?;
+                Ok(format!("Added {}", rule.key()))
+            }
+            StateAction::Delete { resource } if resource.starts_with("routing/") => {
+                let key = resource
+                    .trim_start_matches("routing/route/")
+                    .trim_start_matches("routing/rule/");
+                let (routes, rules) = chain_routing().await?;
+                if let Some(route) = routes.iter().find(|r| r.key() == key) {
+                    rtnetlink_routes::delete_route(route).await?;
+                } else if let Some(rule) = rules.iter().find(|r| r.key() == key) {
+                    rtnetlink_routes::delete_rule(rule).await?;
+                }
+                Ok(format!("Removed {}", key))
+            }
+            other => bail!("Unsupported privacy action {:?}", other),
+        }
+    }
+}
+
+/// Host-side OVS port of the XRay container
+fn xray_port(config: &PrivacyConfig) -> String {
+    format!("vi{}", config.xray_client_container_id)
+}
+
+fn network(addr: Ipv4Addr, prefix: u8) -> (Ipv4Addr, u8) {
+    let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+    (Ipv4Addr::from(u32::from(addr) & mask), prefix)
+}
+
+fn flows_wanted(config: &PrivacyConfig, facts: &ChainFacts) -> bool {
+    config.xray_client_enabled
+        && facts.ovs_bridge
+        && facts.xray_link
+        && facts.xray_address.is_some()
+}
+
+fn same_flows(a: &[FlowEntry], b: &[FlowEntry]) -> bool {
+    a.len() == b.len() && a.iter().all(|f| b.contains(f))
+}
+
+fn parse_port_resource(resource: &str) -> Result<(&str, &str)> {
+    match resource.split('/').collect::<Vec<_>>().as_slice() {
+        ["ovs", bridge, "port", port] => Ok((bridge, port)),
+        _ => bail!("Malformed port resource {}", resource),
     }
 }
 
+fn chain_route(spec: &RouteSpec, tables: &TableNames) -> Result<Route> {
+    let mut route = Route::from_spec(spec, tables)?;
+    route.protocol = RTPROT_PRIVACY;
+    Ok(route)
+}
+
+fn chain_rule(spec: &RuleSpec, tables: &TableNames) -> Result<Rule> {
+    let mut rule = Rule::from_spec(spec, tables)?;
+    rule.protocol = RTPROT_PRIVACY;
+    Ok(rule)
+}
+
+/// Routes and rules installed by this plugin
+async fn chain_routing() -> Result<(Vec<Route>, Vec<Rule>)> {
+    let routes = rtnetlink_routes::list_routes()
+        .await?
+        .into_iter()
+        .filter(|r| r.protocol == RTPROT_PRIVACY)
+        .collect();
+    let rules = rtnetlink_routes::list_rules()
+        .await?
+        .into_iter()
+        .filter(|r| r.protocol == RTPROT_PRIVACY)
+        .collect();
+    Ok((routes, rules))
+}
+
+/// Last handshake of every peer of a WireGuard device
+async fn read_handshakes(interface: &str) -> Result<Vec<Option<i64>>> {
+    let name = interface.to_string();
+    let device =
+        tokio::task::spawn_blocking(move || WgClient::connect()?.get_device(&name)).await??;
+    Ok(device.peers.iter().map(|p| p.last_handshake).collect())
+}
+
+/// Selectors (input interface, source address) of the traffic the chain
+/// steers into WARP: the XRay container's address when XRay sits in front of
+/// WARP, otherwise a WireGuard client address
+fn egress_selectors(config: &PrivacyConfig, facts: &ChainFacts) -> Vec<(String, Ipv4Addr)> {
+    if !config.warp_tunnel_enabled {
+        return Vec::new();
+    }
+    if config.xray_client_enabled {
+        return facts
+            .xray_address
+            .map(|address| (config.proxmox_bridge.clone(), address))
+            .into_iter()
+            .collect();
+    }
+    if config.wireguard_gateway_enabled {
+        // The last host of each client network; the gateway's own address
+        // would be rejected as a martian source
+        return facts
+            .wireguard_networks
+            .iter()
+            .filter(|(_, prefix)| *prefix < 31)
+            .map(|(addr, prefix)| {
+                let broadcast = u32::from(*addr) | (u32::MAX >> prefix);
+                (
+                    config.wireguard_interface.clone(),
+                    Ipv4Addr::from(broadcast - 1),
+                )
+            })
+            .collect();
+    }
+    Vec::new()
+}
+
+/// Connect to the check target through the WARP interface to prove the tunnel
+/// carries traffic, then look up (RTM_GETROUTE) how the chain's own selectors
+/// are routed to the target, so a rule or route leak can't pass the check.
+/// Returns the selectors that were verified.
+async fn probe_egress(config: &PrivacyConfig, facts: &ChainFacts) -> Result<Vec<String>> {
+    let check = &config.health_check;
+    let interface = &config.warp_interface;
+    let target = tokio::net::lookup_host(&check.target)
+        .await
+        .with_context(|| format!("Cannot resolve {}", check.target))?
+        .find(|addr| addr.is_ipv4())
+        .ok_or_else(|| anyhow!("{} has no IPv4 address", check.target))?;
+
+    let socket = tokio::net::TcpSocket::new_v4()?;
+    socket
+        .bind_device(Some(interface.as_bytes()))
+        .with_context(|| format!("Cannot bind to {}", interface))?;
+    let stream = tokio::time::timeout(
+        Duration::from_secs(check.timeout_secs),
+        socket.connect(target),
+    )
+    .await
+    .map_err(|_| anyhow!("Connecting to {} through {} timed out", target, interface))?
+    .with_context(|| format!("Cannot reach {} through {}", target, interface))?;
+    drop(stream);
+
+    let mut verified = Vec::new();
+    for (iif, source) in egress_selectors(config, facts) {
+        let selector = format!("iif {} from {}", iif, source);
+        let route = rtnetlink_routes::lookup_route(target.ip(), Some(source.into()), Some(&iif))
+            .await
+            .with_context(|| format!("Route lookup for {} failed", selector))?;
+        if route.dev.as_deref() != Some(interface.as_str()) {
+            bail!(
+                "Traffic {} to {} leaves through {} (table {}), not {}",
+                selector,
+                target.ip(),
+                route.dev.as_deref().unwrap_or("nothing"),
+                route.table,
+                interface
+            );
+        }
+        verified.push(selector);
+    }
+    Ok(verified)
+}
+
+/// Routes and rules of the chain table for `config`; empty when no rule would
+/// use the table
+fn desired_routing(config: &PrivacyConfig, facts: &ChainFacts) -> Result<(Vec<Route>, Vec<Rule>)> {
+    let tables = TableNames::default();
+    let table = config.routing_table.to_string();
+    let wireguard = config.wireguard_gateway_enabled;
+    let xray = config.xray_client_enabled;
+    let warp = config.warp_tunnel_enabled;
+
+    let xray_address = || {
+        facts.xray_address.ok_or_else(|| {
+            anyhow!(
+                "XRay container {} has no static IPv4 address in its config",
+                config.xray_client_container_id
+            )
+        })
+    };
+    let rule = |priority: u32, from: Option<String>, iif: Option<String>| RuleSpec {
+        priority: Some(priority),
+        from,
+        to: None,
+        iif,
+        oif: None,
+        fwmark: None,
+        fwmask: None,
+        table: table.clone(),
+        ipv6: false,
+    };
+    let route = |destination: String, dev: Option<&str>, metric, route_type| RouteSpec {
+        destination,
+        gateway: None,
+        dev: dev.map(str::to_string),
+        metric,
+        table: Some(table.clone()),
+        scope: None,
+        source: None,
+        route_type,
+    };
+
+    let mut rules = Vec::new();
+    if wireguard && (xray || warp) {
+        rules.push(rule(
+            config.rule_priority,
+            None,
+            Some(config.wireguard_interface.clone()),
+        ));
+    }
+    if xray && warp {
+        rules.push(rule(
+            config.rule_priority + 1,
+            Some(format!("{}/32", xray_address()?)),
+            None,
+        ));
+    }
+    if rules.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+
+    let mut routes = Vec::new();
+    if warp {
+        routes.push(route(
+            "default".to_string(),
+            Some(&config.warp_interface),
+            None,
+            None,
+        ));
+    }
+    // Kill switch: with the tunnel gone, chain traffic is refused rather than
+    // falling through to the main table
+    routes.push(route(
+        "default".to_string(),
+        None,
+        Some(u32::MAX),
+        Some(RouteType::Unreachable),
+    ));
+    if wireguard && xray {
+        routes.push(route(
+            format!("{}/32", xray_address()?),
+            Some(&config.proxmox_bridge),
+            None,
+            None,
+        ));
+    }
+    if wireguard {
+        // Return path to the gateway's clients
+        for (addr, prefix) in &facts.wireguard_networks {
+            routes.push(route(
+                format!("{}/{}", addr, prefix),
+                Some(&config.wireguard_interface),
+                None,
+                None,
+            ));
+        }
+    }
+
+    Ok((
+        routes
+            .iter()
+            .map(|spec| chain_route(spec, &tables))
+            .collect::<Result<_>>()?,
+        rules
+            .iter()
+            .map(|spec| chain_rule(spec, &tables))
+            .collect::<Result<_>>()?,
+    ))
+}
+
+/// Flows pinning the XRay container's port to its own address. They sit below
+/// the security flows so those still apply; anything else the container sends
+/// (other addresses, IPv6) is dropped.
+fn desired_flows(port: &str, address: Ipv4Addr) -> Vec<FlowEntry> {
+    let flow = |priority: u16, fields: &[(&str, &str)], action: FlowAction| FlowEntry {
+        table: 0,
+        priority,
+        match_fields: fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>(),
+        actions: vec![action],
+        cookie: None,
+        idle_timeout: 0,
+        hard_timeout: 0,
+    };
+    let address = address.to_string();
+    vec![
+        flow(
+            30000,
+            &[("in_port", port), ("ip", ""), ("nw_src", &address)],
+            FlowAction::Normal,
+        ),
+        flow(
+            30000,
+            &[("in_port", port), ("arp", ""), ("arp_spa", &address)],
+            FlowAction::Normal,
+        ),
+        flow(29000, &[("in_port", port)], FlowAction::Drop),
+    ]
+}
+
 #[async_trait]
 impl StatePlugin for PrivacyPlugin {
     fn name(&self) -> &'static str {
@@ -67,67 +870,341 @@ impl StatePlugin for PrivacyPlugin {
 
     fn capabilities(&self) -> PluginCapabilities {
         PluginCapabilities {
-            supports_rollback: false,
-            supports_checkpoints: false,
+            supports_rollback: true,
+            supports_checkpoints: true,
             supports_verification: true,
             atomic_operations: false,
         }
     }
 
     async fn query_current_state(&self) -> Result<Value> {
-        // Basic state query - in full implementation this would check all components
-        Ok(serde_json::json!({
-            "config": self.config,
-            "status": "privacy_network_components_managed_by_individual_plugins"
+        let config = self.config.read().await.clone();
+        let facts = self.gather_facts(&config).await?;
+        let hops = vec![
+            self.wireguard_hop(&config).await?,
+            self.xray_hop(&config, &facts).await?,
+            self.warp_hop(&config).await?,
+            self.routing_hop(&config, &facts).await?,
+            self.flows_hop(&config, &facts).await?,
+        ];
+        let health = self.health(&config, &facts).await;
+        Ok(json!({
+            "config": config,
+            "hops": hops,
+            "health": health,
         }))
     }
 
     async fn calculate_diff(&self, current: &Value, desired: &Value) -> Result<StateDiff> {
-        // Basic diff calculation - full implementation would check component states
+        let desired_state: PrivacyState = serde_json::from_value(desired.clone())?;
+        let config = desired_state.config;
+        let facts = self.gather_facts(&config).await?;
+        let tables = TableNames::default();
         let mut actions = Vec::new();
 
-        // This is a coordinating plugin that delegates to individual component plugins
-        // The actual work is done by the respective plugins (netmaker, lxc for xray, etc.)
+        if current.get("config") != Some(&serde_json::to_value(&config)?) {
+            actions.push(StateAction::Modify {
+                resource: "chain".to_string(),
+                changes: json!({ "config": config }),
+            });
+        }
+
+        let port = xray_port(&config);
+        let mut facts_after = facts.clone();
+        if config.xray_client_enabled
+            && facts.ovs_bridge
+            && facts.xray_link
+            && !facts.bridge_ports.contains(&port)
+        {
+            actions.push(StateAction::Create {
+                resource: format!("ovs/{}/port/{}", config.proxmox_bridge, port),
+                config: json!({ "bridge": config.proxmox_bridge, "port": port }),
+            });
+            facts_after.bridge_ports.push(port);
+        }
+
+        let (routes, rules) = desired_routing(&config, &facts)?;
+        let (current_routes, current_rules) = chain_routing().await?;
+        for route in &routes {
+            if !current_routes.iter().any(|c| route.satisfied_by(c)) {
+                actions.push(StateAction::Create {
+                    resource: format!("routing/route/{}", route.key()),
+                    config: serde_json::to_value(route.to_spec(&tables))?,
+                });
+            }
+        }
+        for rule in &rules {
+            if !current_rules.iter().any(|c| rule.satisfied_by(c)) {
+                actions.push(StateAction::Create {
+                    resource: format!("routing/rule/{}", rule.key()),
+                    config: serde_json::to_value(rule.to_spec(&tables))?,
+                });
+            }
+        }
+
+        // Chain flows on the configured bridge, none anywhere else
+        let wanted = self.wanted_flows(&config, &facts_after).await;
+        let mut installed = self.current_flows().await;
+        if facts.ovs_bridge {
+            installed.entry(config.proxmox_bridge.clone()).or_default();
+        }
+        for (bridge, current_flows) in installed {
+            let desired_flows = if bridge == config.proxmox_bridge {
+                wanted.as_slice()
+            } else {
+                &[]
+            };
+            if !same_flows(desired_flows, &current_flows) {
+                actions.push(StateAction::Modify {
+                    resource: format!("ovs/{}/flows", bridge),
+                    changes: json!({ "flows": desired_flows }),
+                });
+            }
+        }
+
+        // Removals last, rules before the routes they point at. Routes replaced
+        // in place above share their key and must not be deleted.
+        for rule in &current_rules {
+            if !rules.iter().any(|r| r.satisfied_by(rule)) {
+                actions.push(StateAction::Delete {
+                    resource: format!("routing/rule/{}", rule.key()),
+                });
+            }
+        }
+        for route in &current_routes {
+            if !routes.iter().any(|r| r.key() == route.key()) {
+                actions.push(StateAction::Delete {
+                    resource: format!("routing/route/{}", route.key()),
+                });
+            }
+        }
 
         Ok(StateDiff {
             plugin: self.name().to_string(),
             actions,
-            metadata: crate::state::plugin::DiffMetadata {
-                timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs() as i64,
+            metadata: DiffMetadata {
+                timestamp: chrono::Utc::now().timestamp(),
                 current_hash: format!("{:x}", md5::compute(serde_json::to_string(current)?)),
                 desired_hash: format!("{:x}", md5::compute(serde_json::to_string(desired)?)),
-            }
+            },
         })
     }
 
-    async fn apply_state(&self, _diff: &StateDiff) -> Result<ApplyResult> {
-        // Privacy plugin coordinates but doesn't directly apply changes
-        // Individual component plugins handle their own state
+    async fn apply_state(&self, diff: &StateDiff) -> Result<ApplyResult> {
+        let mut changes_applied = Vec::new();
+        let mut errors = Vec::new();
+
+        for action in &diff.actions {
+            match self.apply_action(action).await {
+                Ok(change) => changes_applied.push(change),
+                Err(e) => errors.push(format!("{:#}", e)),
+            }
+        }
+
+        // Report only: rolling back on a failed check would reopen the leak the
+        // chain closes
+        let config = self.config.read().await.clone();
+        if errors.is_empty() && config.warp_tunnel_enabled && config.health_check.enabled {
+            let result = match self.gather_facts(&config).await {
+                Ok(facts) => probe_egress(&config, &facts).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                log::warn!("Privacy chain applied but the egress check failed: {:#}", e);
+            }
+        }
+
         Ok(ApplyResult {
-            success: true,
-            changes_applied: vec!["Privacy network coordination active".to_string()],
-            errors: vec![],
+            success: errors.is_empty(),
+            changes_applied,
+            errors,
             checkpoint: None,
         })
     }
 
     async fn verify_state(&self, desired: &Value) -> Result<bool> {
         let current = self.query_current_state().await?;
-        Ok(self.calculate_diff(&current, desired).await?.actions.is_empty())
+        if !self
+            .calculate_diff(&current, desired)
+            .await?
+            .actions
+            .is_empty()
+        {
+            return Ok(false);
+        }
+        Ok(current["health"]["ok"] != json!(false))
     }
 
-    async fn create_checkpoint(&self) -> Result<crate::state::plugin::Checkpoint> {
-        let state = self.query_current_state().await?;
-        Ok(crate::state::plugin::Checkpoint {
-            id: format!("privacy_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs()),
+    async fn create_checkpoint(&self) -> Result<Checkpoint> {
+        let now = chrono::Utc::now().timestamp();
+        Ok(Checkpoint {
+            id: format!("privacy_{}", now),
             plugin: self.name().to_string(),
-            timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs() as i64,
-            state_snapshot: state,
+            timestamp: now,
+            state_snapshot: serde_json::to_value(self.snapshot().await?)?,
             backend_checkpoint: None,
         })
     }
 
-    async fn rollback(&self, _checkpoint: &crate::state::plugin::Checkpoint) -> Result<()> {
-        Err(anyhow::anyhow!("Privacy plugin rollback not implemented - individual component plugins handle their own rollback"))
+    async fn rollback(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let snapshot: ChainSnapshot = serde_json::from_value(checkpoint.state_snapshot.clone())?;
+        let tables = TableNames::default();
+        let routes = snapshot
+            .routes
+            .iter()
+            .map(|spec| chain_route(spec, &tables))
+            .collect::<Result<Vec<_>>>()?;
+        let rules = snapshot
+            .rules
+            .iter()
+            .map(|spec| chain_rule(spec, &tables))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (current_routes, current_rules) = chain_routing().await?;
+        for rule in &current_rules {
+            if !rules.iter().any(|r| r.satisfied_by(rule)) {
+                rtnetlink_routes::delete_rule(rule).await?;
+            }
+        }
+        for route in &routes {
+            rtnetlink_routes::add_route(route).await?;
+        }
+        for route in &current_routes {
+            if !routes.iter().any(|r| r.key() == route.key()) {
+                rtnetlink_routes::delete_route(route).await?;
+            }
+        }
+        for rule in &rules {
+            if !current_rules.iter().any(|c| rule.satisfied_by(c)) {
+                rtnetlink_routes::add_rule(rule).await?;
+            }
+        }
+
+        for (bridge, current_flows) in self.current_flows().await {
+            let flows = snapshot.flows.get(&bridge).map_or(&[][..], Vec::as_slice);
+            if !same_flows(flows, &current_flows) {
+                self.openflow
+                    .replace_family_flows(&bridge, FlowFamily::Privacy, flows)
+                    .await?;
+            }
+        }
+
+        *self.config.write().await = snapshot.config;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> ChainFacts {
+        ChainFacts {
+            xray_address: Some(Ipv4Addr::new(10, 0, 0, 102)),
+            wireguard_networks: vec![network(Ipv4Addr::new(10, 8, 0, 1), 24)],
+            ovs_bridge: true,
+            bridge_ports: vec!["vi102".to_string()],
+            xray_link: true,
+        }
+    }
+
+    #[test]
+    fn test_full_chain_routing() {
+        let config = PrivacyConfig::default();
+        let (routes, rules) = desired_routing(&config, &facts()).unwrap();
+
+        assert!(routes
+            .iter()
+            .all(|r| r.table == 200 && r.protocol == RTPROT_PRIVACY));
+        let keys: Vec<String> = routes.iter().map(Route::key).collect();
+        assert_eq!(
+            keys,
+            [
+                "route:200:default",
+                "route:200:default:4294967295",
+                "route:200:10.0.0.102/32",
+                "route:200:10.8.0.0/24",
+            ]
+        );
+        assert_eq!(routes[0].dev.as_deref(), Some("warp0"));
+        assert_eq!(routes[1].route_type, RouteType::Unreachable);
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].iif.as_deref(), Some("wg0"));
+        assert_eq!(rules[0].priority, Some(10000));
+        assert_eq!(
+            rules[1].from,
+            Some((IpAddr::V4(Ipv4Addr::new(10, 0, 0, 102)), 32))
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_partial_chains() {
+        // Nothing to steer without a second hop
+        let config = PrivacyConfig {
+            xray_client_enabled: false,
+            warp_tunnel_enabled: false,
+            ..Default::default()
+        };
+        let (routes, rules) = desired_routing(&config, &facts()).unwrap();
+        assert!(routes.is_empty() && rules.is_empty());
+
+        // Without WARP the kill switch still keeps wg0 clients off the main table
+        let config = PrivacyConfig {
+            warp_tunnel_enabled: false,
+            ..Default::default()
+        };
+        let (routes, rules) = desired_routing(&config, &facts()).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert!(routes
+            .iter()
+            .any(|r| r.route_type == RouteType::Unreachable));
+        assert!(!routes.iter().any(|r| r.dev.as_deref() == Some("warp0")));
+
+        let no_address = ChainFacts {
+            xray_address: None,
+            ..facts()
+        };
+        assert!(desired_routing(&PrivacyConfig::default(), &no_address).is_err());
+    }
+
+    #[test]
+    fn test_egress_selectors() {
+        // XRay in front of WARP: the container's traffic arriving on the bridge
+        let config = PrivacyConfig::default();
+        assert_eq!(
+            egress_selectors(&config, &facts()),
+            vec![(config.proxmox_bridge.clone(), Ipv4Addr::new(10, 0, 0, 102))]
+        );
+
+        // WireGuard straight into WARP: a client address arriving on wg0
+        let config = PrivacyConfig {
+            xray_client_enabled: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            egress_selectors(&config, &facts()),
+            vec![(
+                config.wireguard_interface.clone(),
+                Ipv4Addr::new(10, 8, 0, 254)
+            )]
+        );
+
+        let config = PrivacyConfig {
+            warp_tunnel_enabled: false,
+            ..Default::default()
+        };
+        assert!(egress_selectors(&config, &facts()).is_empty());
+    }
+
+    #[test]
+    fn test_xray_flows() {
+        let flows = desired_flows("vi102", Ipv4Addr::new(10, 0, 0, 102));
+        assert_eq!(flows.len(), 3);
+        assert!(flows.iter().all(|f| f.table == 0 && f.priority < 31000));
+        assert_eq!(flows[0].match_fields["nw_src"], "10.0.0.102");
+        assert_eq!(flows[1].match_fields["arp_spa"], "10.0.0.102");
+        assert_eq!(flows[2].actions, vec![FlowAction::Drop]);
+        assert!(flows[2].priority < flows[0].priority);
+    }
+}
@@ -0,0 +1,605 @@
+//! sysctl plugin - kernel parameters through /proc/sys
+//!
+//! Design
+//! - Keys use sysctl notation ("net.ipv4.ip_forward", or "/" separated when
+//!   a component contains dots, like "net/ipv4/conf/eth0.100/rp_filter").
+//!   "*" and "?" in a component expand against what exists now, so
+//!   `net.ipv4.conf.*.rp_filter` covers every current interface.
+//! - Explicit keys win over glob expansions; two globs setting the same key
+//!   to different values are rejected.
+//! - Settings are persisted as written (globs included, systemd-sysctl
+//!   expands them at boot) to /etc/sysctl.d/90-op-dbus.conf.
+//! - Checkpoints record the keys an apply is about to write (or, without a
+//!   diff, the keys op-dbus persisted) plus the sysctl.d file, so rollback
+//!   leaves keys changed by anyone else alone.
+
+use crate::state::plugin::{
+    ApplyResult, Checkpoint, DiffMetadata, PluginCapabilities, StateAction, StateDiff, StatePlugin,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const PROC_SYS: &str = "/proc/sys";
+const PERSIST_FILE: &str = "/etc/sysctl.d/90-op-dbus.conf";
+const PERSIST_RESOURCE: &str = "sysctl.d";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SysctlConfig {
+    /// Key (or glob) to value; numbers and strings are both accepted
+    #[serde(default)]
+    pub settings: BTreeMap<String, Value>,
+    /// Write the settings to /etc/sysctl.d so they survive a reboot
+    #[serde(default = "default_persist")]
+    pub persist: bool,
+}
+
+fn default_persist() -> bool {
+    true
+}
+
+fn value_string(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::Number(n) => Ok(n.to_string()),
+        Value::String(s) => Ok(s.clone()),
+        Value::Bool(b) => Ok(u8::from(*b).to_string()),
+        _ => bail!("Value of {} must be a number or a string", key),
+    }
+}
+
+/// Multi-value entries print tab separated; compare them word by word
+fn normalize(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn is_glob(component: &str) -> bool {
+    component.contains(['*', '?'])
+}
+
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_match(&pattern[1..], name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+fn components(key: &str) -> Vec<&str> {
+    if key.contains('/') {
+        key.trim_matches('/').split('/').collect()
+    } else {
+        key.split('.').collect()
+    }
+}
+
+/// Key for a path below the sysctl root, "/" separated if a name has dots
+fn key_of(parts: &[String]) -> String {
+    if parts.iter().any(|p| p.contains('.')) {
+        parts.join("/")
+    } else {
+        parts.join(".")
+    }
+}
+
+/// Concrete keys for a key or glob, with their files
+fn expand(root: &Path, key: &str) -> Result<Vec<(String, PathBuf)>> {
+    let mut matches = vec![(Vec::<String>::new(), root.to_path_buf())];
+    for component in components(key) {
+        if component.is_empty() || component == ".." {
+            bail!("Invalid sysctl key '{}'", key);
+        }
+        let mut next = Vec::new();
+        for (parts, dir) in matches {
+            if is_glob(component) {
+                let Ok(entries) = std::fs::read_dir(&dir) else {
+                    continue;
+                };
+                let mut names: Vec<String> = entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.file_name().to_string_lossy().into_owned())
+                    .filter(|name| glob_match(component.as_bytes(), name.as_bytes()))
+                    .collect();
+                names.sort();
+                for name in names {
+                    let mut parts = parts.clone();
+                    parts.push(name.clone());
+                    next.push((parts, dir.join(name)));
+                }
+            } else {
+                let path = dir.join(component);
+                if path.exists() {
+                    let mut parts = parts.clone();
+                    parts.push(component.to_string());
+                    next.push((parts, path));
+                }
+            }
+        }
+        matches = next;
+    }
+    Ok(matches
+        .into_iter()
+        .filter(|(_, path)| path.is_file())
+        .map(|(parts, path)| (key_of(&parts), path))
+        .collect())
+}
+
+/// Desired settings resolved to concrete keys
+fn resolve(root: &Path, config: &SysctlConfig) -> Result<BTreeMap<String, (String, PathBuf)>> {
+    let mut explicit = BTreeMap::new();
+    let mut globbed: BTreeMap<String, (String, PathBuf, String)> = BTreeMap::new();
+    for (key, value) in &config.settings {
+        let value = value_string(key, value)?;
+        let expanded = expand(root, key)?;
+        if components(key).iter().any(|c| is_glob(c)) {
+            if expanded.is_empty() {
+                log::warn!("sysctl glob {} matches nothing", key);
+            }
+            for (concrete, path) in expanded {
+                if let Some((other, _, pattern)) = globbed.get(&concrete) {
+                    if normalize(other) != normalize(&value) {
+                        bail!(
+                            "{} and {} set {} to different values",
+                            pattern,
+                            key,
+                            concrete
+                        );
+                    }
+                }
+                globbed.insert(concrete, (value.clone(), path, key.clone()));
+            }
+        } else {
+            let (concrete, path) = expanded.into_iter().next().ok_or_else(|| {
+                anyhow!(
+                    "Unknown sysctl key {} (is the module providing it loaded?)",
+                    key
+                )
+            })?;
+            explicit.insert(concrete, (value, path));
+        }
+    }
+    for (concrete, (value, path, _)) in globbed {
+        explicit.entry(concrete).or_insert((value, path));
+    }
+    Ok(explicit)
+}
+
+fn read_value(path: &Path) -> Result<String> {
+    Ok(std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .trim_end()
+        .to_string())
+}
+
+fn write_value(path: &Path, value: &str) -> Result<()> {
+    std::fs::write(path, value).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Current values of concrete `keys`, skipping keys that don't exist
+fn capture<'a>(root: &Path, keys: impl IntoIterator<Item = &'a str>) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    for key in keys {
+        let Ok(expanded) = expand(root, key) else {
+            continue;
+        };
+        for (concrete, path) in expanded {
+            if let Ok(value) = read_value(&path) {
+                out.insert(concrete, value);
+            }
+        }
+    }
+    out
+}
+
+/// Write back every saved value that changed; returns how many were restored
+/// and an error per key that could not be
+fn restore(root: &Path, values: &BTreeMap<String, String>) -> (usize, Vec<String>) {
+    let mut restored = 0;
+    let mut errors = Vec::new();
+    for (key, old) in values {
+        // Keys of interfaces that are gone have nothing to restore
+        let Some((_, path)) = expand(root, key).ok().and_then(|e| e.into_iter().next()) else {
+            continue;
+        };
+        let result = read_value(&path).and_then(|current| {
+            if normalize(&current) == normalize(old) {
+                return Ok(false);
+            }
+            write_value(&path, old).map(|_| true)
+        });
+        match result {
+            Ok(true) => restored += 1,
+            Ok(false) => {}
+            Err(e) => errors.push(format!("{}: {:#}", key, e)),
+        }
+    }
+    (restored, errors)
+}
+
+fn render_persisted(config: &SysctlConfig) -> Result<String> {
+    let mut text =
+        String::from("# Managed by op-dbus (sysctl plugin); local edits are overwritten\n");
+    for (key, value) in &config.settings {
+        text.push_str(&format!("{} = {}\n", key, value_string(key, value)?));
+    }
+    Ok(text)
+}
+
+/// Keys and values of a sysctl.d file
+fn parse_persisted(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(['#', ';']))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let key = key.trim().trim_start_matches('-');
+            (key.to_string(), value.trim().to_string())
+        })
+        .collect()
+}
+
+pub struct SysctlPlugin;
+
+impl SysctlPlugin {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn root() -> &'static Path {
+        Path::new(PROC_SYS)
+    }
+
+    fn plan(config: &SysctlConfig) -> Result<Vec<StateAction>> {
+        let mut actions = Vec::new();
+        for (key, (value, path)) in resolve(Self::root(), config)? {
+            let current = read_value(&path)?;
+            if normalize(&current) != normalize(&value) {
+                actions.push(StateAction::Modify {
+                    resource: key,
+                    changes: json!({ "value": value, "previous": current }),
+                });
+            }
+        }
+
+        let persisted = std::fs::read_to_string(PERSIST_FILE).ok();
+        if config.persist {
+            let content = render_persisted(config)?;
+            if persisted.as_deref() != Some(content.as_str()) {
+                actions.push(StateAction::Modify {
+                    resource: PERSIST_RESOURCE.to_string(),
+                    changes: json!({ "path": PERSIST_FILE, "content": content }),
+                });
+            }
+        } else if persisted.is_some() {
+            actions.push(StateAction::Delete {
+                resource: PERSIST_RESOURCE.to_string(),
+            });
+        }
+        Ok(actions)
+    }
+
+    async fn checkpoint_keys(
+        &self,
+        keys: Vec<String>,
+        persisted: Option<String>,
+    ) -> Result<Checkpoint> {
+        let values = tokio::task::spawn_blocking(move || {
+            capture(Path::new(PROC_SYS), keys.iter().map(String::as_str))
+        })
+        .await?;
+        Ok(Checkpoint {
+            id: format!("sysctl-{}", chrono::Utc::now().timestamp()),
+            plugin: self.name().to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            state_snapshot: self.query_current_state().await?,
+            backend_checkpoint: Some(json!({
+                "values": values,
+                "persisted": persisted,
+            })),
+        })
+    }
+
+    fn write_persisted(content: Option<&str>) -> Result<()> {
+        match content {
+            Some(content) => {
+                std::fs::create_dir_all("/etc/sysctl.d")?;
+                let tmp = format!("{}.tmp", PERSIST_FILE);
+                std::fs::write(&tmp, content)?;
+                std::fs::rename(&tmp, PERSIST_FILE)?;
+            }
+            None => match std::fs::remove_file(PERSIST_FILE) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Default for SysctlPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl StatePlugin for SysctlPlugin {
+    fn name(&self) -> &str {
+        "sysctl"
+    }
+
+    fn version(&self) -> &str {
+        "1.0.0"
+    }
+
+    fn is_available(&self) -> bool {
+        Path::new(PROC_SYS).is_dir()
+    }
+
+    fn unavailable_reason(&self) -> String {
+        "/proc/sys is not mounted".to_string()
+    }
+
+    /// Live values of the keys op-dbus persisted
+    async fn query_current_state(&self) -> Result<Value> {
+        let persisted = std::fs::read_to_string(PERSIST_FILE).ok();
+        let mut settings = BTreeMap::new();
+        for key in parse_persisted(persisted.as_deref().unwrap_or_default()).keys() {
+            for (concrete, path) in expand(Self::root(), key)? {
+                if let Ok(value) = read_value(&path) {
+                    settings.insert(concrete, Value::String(value));
+                }
+            }
+        }
+        Ok(serde_json::to_value(SysctlConfig {
+            settings,
+            persist: persisted.is_some(),
+        })?)
+    }
+
+    async fn calculate_diff(&self, current: &Value, desired: &Value) -> Result<StateDiff> {
+        let config: SysctlConfig = serde_json::from_value(desired.clone())?;
+        let actions = Self::plan(&config)?;
+
+        Ok(StateDiff {
+            plugin: self.name().to_string(),
+            actions,
+            metadata: DiffMetadata {
+                timestamp: chrono::Utc::now().timestamp(),
+                current_hash: format!("{:x}", md5::compute(serde_json::to_string(current)?)),
+                desired_hash: format!("{:x}", md5::compute(serde_json::to_string(desired)?)),
+            },
+        })
+    }
+
+    async fn apply_state(&self, diff: &StateDiff) -> Result<ApplyResult> {
+        let mut changes_applied = Vec::new();
+        let mut errors = Vec::new();
+
+        for action in &diff.actions {
+            let result = match action {
+                StateAction::Modify { resource, changes } if resource == PERSIST_RESOURCE => {
+                    Self::write_persisted(changes["content"].as_str())
+                        .map(|_| format!("Wrote {}", PERSIST_FILE))
+                }
+                StateAction::Delete { resource } if resource == PERSIST_RESOURCE => {
+                    Self::write_persisted(None).map(|_| format!("Removed {}", PERSIST_FILE))
+                }
+                StateAction::Create { resource, config }
+                | StateAction::Modify {
+                    resource,
+                    changes: config,
+                } => {
+                    let value = config["value"]
+                        .as_str()
+                        .ok_or_else(|| anyhow!("{}: missing value", resource));
+                    value.and_then(|value| {
+                        let (_, path) = expand(Self::root(), resource)?
+                            .into_iter()
+                            .next()
+                            .ok_or_else(|| anyhow!("{} no longer exists", resource))?;
+                        write_value(&path, value)?;
+                        Ok(format!("{} = {}", resource, value))
+                    })
+                }
+                StateAction::Delete { resource } => Err(anyhow!(
+                    "{}: sysctl keys can't be deleted, set a value instead",
+                    resource
+                )),
+                StateAction::NoOp { .. } => continue,
+            };
+            match result {
+                Ok(change) => changes_applied.push(change),
+                Err(e) => errors.push(format!("{:#}", e)),
+            }
+        }
+
+        Ok(ApplyResult {
+            success: errors.is_empty(),
+            changes_applied,
+            errors,
+            checkpoint: None,
+        })
+    }
+
+    async fn verify_state(&self, desired: &Value) -> Result<bool> {
+        let config: SysctlConfig = serde_json::from_value(desired.clone())?;
+        Ok(Self::plan(&config)?.is_empty())
+    }
+
+    /// Checkpoint of the keys op-dbus persisted
+    async fn create_checkpoint(&self) -> Result<Checkpoint> {
+        let persisted = std::fs::read_to_string(PERSIST_FILE).ok();
+        let keys: Vec<String> = parse_persisted(persisted.as_deref().unwrap_or_default())
+            .into_keys()
+            .collect();
+        self.checkpoint_keys(keys, persisted).await
+    }
+
+    /// Checkpoint of the keys `diff` writes
+    async fn create_checkpoint_for(&self, diff: &StateDiff) -> Result<Checkpoint> {
+        let keys = diff
+            .actions
+            .iter()
+            .filter_map(|action| match action {
+                StateAction::Create { resource, .. } | StateAction::Modify { resource, .. }
+                    if resource != PERSIST_RESOURCE =>
+                {
+                    Some(resource.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        self.checkpoint_keys(keys, std::fs::read_to_string(PERSIST_FILE).ok())
+            .await
+    }
+
+    async fn rollback(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let saved = checkpoint
+            .backend_checkpoint
+            .as_ref()
+            .ok_or_else(|| anyhow!("Checkpoint {} has no sysctl values", checkpoint.id))?;
+        let values: BTreeMap<String, String> = serde_json::from_value(saved["values"].clone())?;
+        let persisted: Option<String> = serde_json::from_value(saved["persisted"].clone())?;
+
+        let (restored, mut errors) =
+            tokio::task::spawn_blocking(move || restore(Path::new(PROC_SYS), &values)).await?;
+        if let Err(e) = Self::write_persisted(persisted.as_deref()) {
+            errors.push(format!("{}: {:#}", PERSIST_FILE, e));
+        }
+
+        log::info!(
+            "Restored {} sysctl values from checkpoint {}",
+            restored,
+            checkpoint.id
+        );
+        if !errors.is_empty() {
+            bail!("Failed to restore {}", errors.join("; "));
+        }
+        Ok(())
+    }
+
+    fn owned_paths(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(PERSIST_FILE)]
+    }
+
+    fn capabilities(&self) -> PluginCapabilities {
+        PluginCapabilities {
+            supports_rollback: true,
+            supports_checkpoints: true,
+            supports_verification: true,
+            atomic_operations: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (path, value) in [
+            ("net/ipv4/ip_forward", "0"),
+            ("net/ipv4/conf/all/rp_filter", "2"),
+            ("net/ipv4/conf/eth0/rp_filter", "1"),
+            ("net/ipv4/conf/eth0.100/rp_filter", "1"),
+            ("net/ipv4/ip_local_port_range", "32768\t60999"),
+        ] {
+            let path = root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, format!("{}\n", value)).unwrap();
+        }
+        root
+    }
+
+    fn config(settings: Value) -> SysctlConfig {
+        serde_json::from_value(json!({ "settings": settings })).unwrap()
+    }
+
+    #[test]
+    fn test_globs_expand_and_explicit_keys_win() {
+        let root = tree();
+        let resolved = resolve(
+            root.path(),
+            &config(json!({
+                "net.ipv4.conf.*.rp_filter": 0,
+                "net.ipv4.conf.all.rp_filter": "2",
+                "net.ipv4.ip_forward": true,
+            })),
+        )
+        .unwrap();
+        let values: Vec<(&str, &str)> = resolved
+            .iter()
+            .map(|(k, (v, _))| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("net.ipv4.conf.all.rp_filter", "2"),
+                ("net.ipv4.conf.eth0.rp_filter", "0"),
+                ("net.ipv4.ip_forward", "1"),
+                ("net/ipv4/conf/eth0.100/rp_filter", "0"),
+            ]
+        );
+
+        assert!(resolve(
+            root.path(),
+            &config(json!({"net.bridge.bridge-nf-call-iptables": 1}))
+        )
+        .is_err());
+        assert!(resolve(
+            root.path(),
+            &config(json!({"net.ipv4.conf.*.rp_filter": 0, "net.ipv4.conf.eth?.rp_filter": 1}))
+        )
+        .is_err());
+        assert!(resolve(root.path(), &config(json!({"net.bridge.*": 1})))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_capture_and_restore_only_checkpointed_keys() {
+        let root = tree();
+        let values = capture(
+            root.path(),
+            [
+                "net.ipv4.ip_forward",
+                "net/ipv4/conf/eth0.100/rp_filter",
+                "net.ipv4.conf.gone.rp_filter",
+            ],
+        );
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["net/ipv4/conf/eth0.100/rp_filter"], "1");
+
+        // The apply wrote both keys; someone else changed rp_filter on eth0
+        std::fs::write(root.path().join("net/ipv4/ip_forward"), "1\n").unwrap();
+        std::fs::write(root.path().join("net/ipv4/conf/eth0.100/rp_filter"), "0\n").unwrap();
+        std::fs::write(root.path().join("net/ipv4/conf/eth0/rp_filter"), "0\n").unwrap();
+
+        let (restored, errors) = restore(root.path(), &values);
+        assert_eq!((restored, errors.len()), (2, 0));
+        let read = |key: &str| read_value(&root.path().join(key)).unwrap();
+        assert_eq!(read("net/ipv4/ip_forward"), "0");
+        assert_eq!(read("net/ipv4/conf/eth0.100/rp_filter"), "1");
+        assert_eq!(read("net/ipv4/conf/eth0/rp_filter"), "0");
+    }
+
+    #[test]
+    fn test_persisted_file_round_trip() {
+        let config = config(json!({"net.ipv4.ip_forward": 1, "net.ipv4.conf.*.rp_filter": "2"}));
+        let parsed = parse_persisted(&render_persisted(&config).unwrap());
+        assert_eq!(parsed["net.ipv4.ip_forward"], "1");
+        assert_eq!(parsed["net.ipv4.conf.*.rp_filter"], "2");
+        assert_eq!(
+            parse_persisted("; comment\n-kernel.foo=3\n")["kernel.foo"],
+            "3"
+        );
+    }
+}
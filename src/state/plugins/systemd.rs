@@ -115,7 +115,7 @@ impl SystemdStatePlugin {
     }
 
     /// Start a systemd unit
-    async fn start_unit(&self, unit_name: &str) -> Result<()> {
+    pub(crate) async fn start_unit(&self, unit_name: &str) -> Result<()> {
         let proxy = self.connect_systemd().await?;
 
         let _job: zbus::zvariant::OwnedObjectPath = proxy
@@ -128,7 +128,7 @@ impl SystemdStatePlugin {
     }
 
     /// Stop a systemd unit
-    async fn stop_unit(&self, unit_name: &str) -> Result<()> {
+    pub(crate) async fn stop_unit(&self, unit_name: &str) -> Result<()> {
         let proxy = self.connect_systemd().await?;
 
         let _job: zbus::zvariant::OwnedObjectPath = proxy
@@ -141,7 +141,7 @@ impl SystemdStatePlugin {
     }
 
     /// Enable a systemd unit
-    async fn enable_unit(&self, unit_name: &str) -> Result<()> {
+    pub(crate) async fn enable_unit(&self, unit_name: &str) -> Result<()> {
         let proxy = self.connect_systemd().await?;
 
         let _: (bool, Vec<(String, String, String)>) = proxy
@@ -154,7 +154,7 @@ impl SystemdStatePlugin {
     }
 
     /// Disable a systemd unit
-    async fn disable_unit(&self, unit_name: &str) -> Result<()> {
+    pub(crate) async fn disable_unit(&self, unit_name: &str) -> Result<()> {
         let proxy = self.connect_systemd().await?;
 
         let _: Vec<(String, String, String)> = proxy
@@ -250,6 +250,65 @@ impl SystemdStatePlugin {
         })
     }
 
+    /// Set persistent properties (resource limits etc.) on a unit, loading it if needed
+    pub(crate) async fn set_unit_properties(
+        &self,
+        unit_name: &str,
+        properties: Vec<(&str, zbus::zvariant::Value<'_>)>,
+    ) -> Result<()> {
+        let proxy = self.connect_systemd().await?;
+
+        let _: () = proxy
+            .call("SetUnitProperties", &(unit_name, false, properties))
+            .await
+            .context(format!("Failed to set properties of unit {}", unit_name))?;
+
+        log::info!("Set properties of systemd unit: {}", unit_name);
+        Ok(())
+    }
+
+    /// Read a numeric property of a unit (e.g. MemoryMax on org.freedesktop.systemd1.Service),
+    /// loading it if needed
+    pub(crate) async fn unit_property_u64(
+        &self,
+        unit_name: &str,
+        interface: &str,
+        property: &str,
+    ) -> Result<u64> {
+        let proxy = self.connect_systemd().await?;
+        let unit_path: zbus::zvariant::OwnedObjectPath = proxy
+            .call("LoadUnit", &(unit_name,))
+            .await
+            .context(format!("Failed to load unit {}", unit_name))?;
+
+        let conn = Connection::system().await?;
+        let unit_proxy = Proxy::new(
+            &conn,
+            "org.freedesktop.systemd1",
+            unit_path.to_string(),
+            interface.to_string(),
+        )
+        .await?;
+
+        unit_proxy
+            .get_property(property)
+            .await
+            .context(format!("Failed to read {} of unit {}", property, unit_name))
+    }
+
+    /// Restart a systemd unit, starting it if it is not running
+    pub async fn restart_unit(&self, unit_name: &str) -> Result<()> {
+        let proxy = self.connect_systemd().await?;
+
+        let _job: zbus::zvariant::OwnedObjectPath = proxy
+            .call("RestartUnit", &(unit_name, "replace"))
+            .await
+            .context(format!("Failed to restart unit {}", unit_name))?;
+
+        log::info!("Restarted systemd unit: {}", unit_name);
+        Ok(())
+    }
+
     /// Mask a systemd unit
     pub async fn mask_unit(&self, unit_name: &str) -> Result<()> {
         let proxy = self.connect_systemd().await?;
@@ -0,0 +1,706 @@
+//! wireguard plugin - WireGuard interfaces and peers over generic netlink
+//!
+//! Design
+//! - Interfaces are created through rtnetlink and tagged with the owner
+//!   alias; keys, listen port, fwmark and peers are set with the wireguard
+//!   generic netlink family ([`crate::native::wireguard`]). Addresses and
+//!   routes on the interface stay with the net plugin.
+//! - Private and preshared keys are only ever named by secret references
+//!   ([`crate::state::secret`]). They are resolved when diffing and applying,
+//!   and neither query output, diffs nor checkpoints contain key material.
+//! - Peers are updated in place (allowed IPs replaced, stale peers removed),
+//!   so a change to one peer doesn't reset the sessions of the others.
+//! - Omitted `private_key`, `listen_port` or `fwmark` leave the current value
+//!   alone; a peer without `endpoint` keeps whatever endpoint it roamed to.
+//! - Query reports every WireGuard interface with its public key and per-peer
+//!   handshake and transfer counters.
+
+use crate::native::rtnetlink_links::{self, KernelLink};
+use crate::native::wireguard::{AllowedIp, Device, Key, Peer, WgClient};
+use crate::state::plugin::{
+    ApplyResult, Checkpoint, DiffMetadata, PluginCapabilities, StateAction, StateDiff, StatePlugin,
+};
+use crate::state::secret;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+
+const KIND: &str = "wireguard";
+/// IFNAMSIZ without the terminating NUL
+const MAX_IFNAME: usize = 15;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct WireGuardConfig {
+    #[serde(default)]
+    pub interfaces: Vec<WgInterface>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct WgInterface {
+    pub name: String,
+    /// Secret reference to the base64 private key ("file:/etc/wireguard/wg0.key")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+    /// Public key of the interface (query only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listen_port: Option<u16>,
+    /// Mark on outgoing tunnel packets, for policy routing; 0 clears it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fwmark: Option<u32>,
+    #[serde(default)]
+    pub peers: Vec<WgPeer>,
+    /// Whether op-dbus created the interface (query only)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub owned: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct WgPeer {
+    pub public_key: String,
+    /// Secret reference to the base64 preshared key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preshared_key: Option<String>,
+    /// "host:port"; names are resolved when diffing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    /// Seconds between keepalives; 0 or omitted disables them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persistent_keepalive: Option<u16>,
+    /// Unix time of the latest handshake (query only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_handshake: Option<i64>,
+    /// Bytes received from the peer (query only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rx_bytes: Option<u64>,
+    /// Bytes sent to the peer (query only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_bytes: Option<u64>,
+}
+
+/// An interface declaration with its secrets read and endpoints resolved
+#[derive(Debug, Clone, PartialEq)]
+struct Resolved {
+    name: String,
+    private_key: Option<Key>,
+    listen_port: Option<u16>,
+    fwmark: Option<u32>,
+    peers: Vec<Peer>,
+}
+
+fn check_interface(iface: &WgInterface) -> Result<()> {
+    if iface.name.is_empty() || iface.name.len() > MAX_IFNAME || iface.name.contains('/') {
+        bail!("Invalid WireGuard interface name '{}'", iface.name);
+    }
+    if let Some(reference) = &iface.private_key {
+        secret::validate(reference).with_context(|| format!("{}: private_key", iface.name))?;
+    }
+    let mut seen = BTreeSet::new();
+    for peer in &iface.peers {
+        if !seen.insert(&peer.public_key) {
+            bail!("{}: peer {} is declared twice", iface.name, peer.public_key);
+        }
+        if let Some(reference) = &peer.preshared_key {
+            secret::validate(reference)
+                .with_context(|| format!("{}: preshared_key of {}", iface.name, peer.public_key))?;
+        }
+    }
+    Ok(())
+}
+
+fn read_key(reference: &str) -> Result<Key> {
+    secret::resolve(reference)?
+        .parse()
+        .with_context(|| format!("Secret {} is not a WireGuard key", reference))
+}
+
+async fn resolve_endpoint(endpoint: &str) -> Result<SocketAddr> {
+    if let Ok(address) = endpoint.parse() {
+        return Ok(address);
+    }
+    tokio::net::lookup_host(endpoint)
+        .await
+        .with_context(|| format!("Failed to resolve endpoint {}", endpoint))?
+        .next()
+        .ok_or_else(|| anyhow!("Endpoint {} has no addresses", endpoint))
+}
+
+async fn resolve(iface: &WgInterface) -> Result<Resolved> {
+    check_interface(iface)?;
+    let mut peers = Vec::new();
+    for peer in &iface.peers {
+        let public_key: Key = peer
+            .public_key
+            .parse()
+            .with_context(|| format!("{}: peer public key", iface.name))?;
+        let endpoint = match &peer.endpoint {
+            Some(endpoint) => Some(resolve_endpoint(endpoint).await?),
+            None => None,
+        };
+        peers.push(Peer {
+            public_key: Some(public_key),
+            preshared_key: peer.preshared_key.as_deref().map(read_key).transpose()?,
+            endpoint,
+            persistent_keepalive: peer.persistent_keepalive.unwrap_or(0),
+            allowed_ips: peer
+                .allowed_ips
+                .iter()
+                .map(|ip| ip.parse::<AllowedIp>().map(AllowedIp::network))
+                .collect::<Result<_>>()
+                .with_context(|| format!("{}: peer {}", iface.name, peer.public_key))?,
+            ..Default::default()
+        });
+    }
+    Ok(Resolved {
+        name: iface.name.clone(),
+        private_key: iface.private_key.as_deref().map(read_key).transpose()?,
+        listen_port: iface.listen_port,
+        fwmark: iface.fwmark,
+        peers,
+    })
+}
+
+/// The device to set, filling omitted fields from the current one
+fn desired_device(resolved: &Resolved, current: Option<&Device>) -> Device {
+    let peers = resolved
+        .peers
+        .iter()
+        .map(|peer| {
+            let mut peer = peer.clone();
+            let roamed = current
+                .and_then(|c| c.peers.iter().find(|p| p.public_key == peer.public_key))
+                .and_then(|p| p.endpoint);
+            peer.endpoint = peer.endpoint.or(roamed);
+            peer
+        })
+        .collect();
+    Device {
+        name: resolved.name.clone(),
+        private_key: resolved
+            .private_key
+            .or_else(|| current.and_then(|c| c.private_key)),
+        public_key: None,
+        listen_port: resolved
+            .listen_port
+            .or_else(|| current.map(|c| c.listen_port))
+            .unwrap_or(0),
+        fwmark: resolved
+            .fwmark
+            .or_else(|| current.map(|c| c.fwmark))
+            .unwrap_or(0),
+        peers,
+    }
+}
+
+/// A peer's configuration with statistics dropped and allowed IPs sorted
+fn canonical(peer: &Peer) -> Peer {
+    let mut allowed_ips: Vec<AllowedIp> = peer.allowed_ips.iter().map(|ip| ip.network()).collect();
+    allowed_ips.sort();
+    allowed_ips.dedup();
+    Peer {
+        public_key: peer.public_key,
+        preshared_key: peer.preshared_key,
+        endpoint: peer.endpoint,
+        persistent_keepalive: peer.persistent_keepalive,
+        allowed_ips,
+        ..Default::default()
+    }
+}
+
+/// Peers of `current` that `desired` doesn't list
+fn stale_peers(desired: &Device, current: &Device) -> Vec<Key> {
+    current
+        .peers
+        .iter()
+        .filter_map(|p| p.public_key)
+        .filter(|key| !desired.peers.iter().any(|p| p.public_key == Some(*key)))
+        .collect()
+}
+
+/// Whether `current` already is `desired`, ignoring the port the kernel
+/// picked when none was asked for
+fn in_sync(desired: &Device, current: &Device) -> bool {
+    let by_key = |device: &Device| -> BTreeMap<Option<Key>, Peer> {
+        device
+            .peers
+            .iter()
+            .map(|p| (p.public_key, canonical(p)))
+            .collect()
+    };
+    desired.private_key == current.private_key
+        && (desired.listen_port == 0 || desired.listen_port == current.listen_port)
+        && desired.fwmark == current.fwmark
+        && by_key(desired) == by_key(current)
+}
+
+fn report(link: &KernelLink, device: &Device) -> WgInterface {
+    WgInterface {
+        name: link.name.clone(),
+        private_key: None,
+        public_key: device.public_key.map(|k| k.to_string()),
+        listen_port: Some(device.listen_port),
+        fwmark: Some(device.fwmark).filter(|mark| *mark != 0),
+        peers: device
+            .peers
+            .iter()
+            .map(|peer| WgPeer {
+                public_key: peer.public_key.map(|k| k.to_string()).unwrap_or_default(),
+                preshared_key: None,
+                endpoint: peer.endpoint.map(|e| e.to_string()),
+                allowed_ips: peer.allowed_ips.iter().map(|ip| ip.to_string()).collect(),
+                persistent_keepalive: Some(peer.persistent_keepalive).filter(|s| *s != 0),
+                last_handshake: peer.last_handshake,
+                rx_bytes: Some(peer.rx_bytes),
+                tx_bytes: Some(peer.tx_bytes),
+            })
+            .collect(),
+        owned: link.is_owned(),
+    }
+}
+
+async fn read_device(name: &str) -> Result<Device> {
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || WgClient::connect()?.get_device(&name)).await?
+}
+
+async fn write_device(device: Device, remove: Vec<Key>) -> Result<()> {
+    tokio::task::spawn_blocking(move || WgClient::connect()?.set_device(&device, &remove)).await?
+}
+
+async fn wireguard_links() -> Result<Vec<KernelLink>> {
+    Ok(rtnetlink_links::list_links()
+        .await?
+        .into_iter()
+        .filter(|link| link.kind.as_deref() == Some(KIND))
+        .collect())
+}
+
+/// Whether the running kernel has WireGuard, loaded or loadable
+fn kernel_has_wireguard() -> bool {
+    if WgClient::connect().is_ok() {
+        return true;
+    }
+    let Ok(release) = std::fs::read_to_string("/proc/sys/kernel/osrelease") else {
+        return false;
+    };
+    std::fs::read_to_string(format!("/lib/modules/{}/modules.dep", release.trim()))
+        .is_ok_and(|deps| deps.contains("/wireguard.ko"))
+}
+
+pub struct WireGuardPlugin;
+
+impl WireGuardPlugin {
+    pub fn new() -> Self {
+        Self
+    }
+
+    async fn plan(config: &WireGuardConfig) -> Result<Vec<StateAction>> {
+        let links = rtnetlink_links::list_links().await?;
+        let mut actions = Vec::new();
+
+        for iface in &config.interfaces {
+            let resolved = resolve(iface).await?;
+            let declared = serde_json::to_value(iface)?;
+            let Some(link) = links.iter().find(|l| l.name == iface.name) else {
+                actions.push(StateAction::Create {
+                    resource: iface.name.clone(),
+                    config: json!({ "interface": declared }),
+                });
+                continue;
+            };
+            if link.kind.as_deref() != Some(KIND) {
+                bail!(
+                    "{} exists as a {} link, not a WireGuard interface",
+                    iface.name,
+                    link.kind.as_deref().unwrap_or("plain")
+                );
+            }
+
+            let current = read_device(&iface.name).await?;
+            let desired = desired_device(&resolved, Some(&current));
+            let stale = stale_peers(&desired, &current);
+            if !in_sync(&desired, &current) || !stale.is_empty() {
+                actions.push(StateAction::Modify {
+                    resource: iface.name.clone(),
+                    changes: json!({
+                        "interface": declared,
+                        "remove_peers": stale.iter().map(Key::to_string).collect::<Vec<_>>(),
+                    }),
+                });
+            }
+        }
+
+        for link in links {
+            let declared = config.interfaces.iter().any(|i| i.name == link.name);
+            if link.kind.as_deref() == Some(KIND) && link.is_owned() && !declared {
+                actions.push(StateAction::Delete {
+                    resource: link.name,
+                });
+            }
+        }
+        Ok(actions)
+    }
+
+    async fn configure(iface: &WgInterface, remove: Vec<Key>) -> Result<()> {
+        let resolved = resolve(iface).await?;
+        let current = read_device(&iface.name).await?;
+        let desired = desired_device(&resolved, Some(&current));
+        write_device(desired, remove).await
+    }
+
+    fn parse_remove(changes: &Value) -> Result<Vec<Key>> {
+        changes["remove_peers"]
+            .as_array()
+            .map(|keys| {
+                keys.iter()
+                    .map(|k| k.as_str().unwrap_or_default().parse())
+                    .collect()
+            })
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+}
+
+impl Default for WireGuardPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl StatePlugin for WireGuardPlugin {
+    fn name(&self) -> &str {
+        "wireguard"
+    }
+
+    fn version(&self) -> &str {
+        "1.0.0"
+    }
+
+    fn is_available(&self) -> bool {
+        kernel_has_wireguard()
+    }
+
+    fn unavailable_reason(&self) -> String {
+        "The kernel has no WireGuard support (wireguard module not found)".to_string()
+    }
+
+    async fn query_current_state(&self) -> Result<Value> {
+        let mut interfaces = Vec::new();
+        for link in wireguard_links().await? {
+            let device = read_device(&link.name).await?;
+            interfaces.push(report(&link, &device));
+        }
+        Ok(serde_json::to_value(WireGuardConfig { interfaces })?)
+    }
+
+    async fn calculate_diff(&self, current: &Value, desired: &Value) -> Result<StateDiff> {
+        let config: WireGuardConfig = serde_json::from_value(desired.clone())?;
+        let actions = Self::plan(&config).await?;
+
+        Ok(StateDiff {
+            plugin: self.name().to_string(),
+            actions,
+            metadata: DiffMetadata {
+                timestamp: chrono::Utc::now().timestamp(),
+                current_hash: format!("{:x}", md5::compute(serde_json::to_string(current)?)),
+                desired_hash: format!("{:x}", md5::compute(serde_json::to_string(desired)?)),
+            },
+        })
+    }
+
+    async fn apply_state(&self, diff: &StateDiff) -> Result<ApplyResult> {
+        let mut changes_applied = Vec::new();
+        let mut errors = Vec::new();
+
+        for action in &diff.actions {
+            let result = match action {
+                StateAction::Create { resource, config } => {
+                    async {
+                        let iface: WgInterface =
+                            serde_json::from_value(config["interface"].clone())?;
+                        if rtnetlink_links::get_link(resource).await?.is_none() {
+                            rtnetlink_links::create_wireguard_link(resource).await?;
+                        }
+                        Self::configure(&iface, Vec::new()).await?;
+                        Ok(format!("Created WireGuard interface {}", resource))
+                    }
+                    .await
+                }
+                StateAction::Modify { resource, changes } => {
+                    async {
+                        let iface: WgInterface =
+                            serde_json::from_value(changes["interface"].clone())?;
+                        let remove = Self::parse_remove(changes)?;
+                        Self::configure(&iface, remove).await?;
+                        Ok(format!("Configured WireGuard interface {}", resource))
+                    }
+                    .await
+                }
+                StateAction::Delete { resource } => rtnetlink_links::delete_link(resource)
+                    .await
+                    .map(|_| format!("Deleted WireGuard interface {}", resource)),
+                StateAction::NoOp { .. } => continue,
+            };
+            match result {
+                Ok(change) => changes_applied.push(change),
+                Err(e) => errors.push(format!("{:#}", e)),
+            }
+        }
+
+        Ok(ApplyResult {
+            success: errors.is_empty(),
+            changes_applied,
+            errors,
+            checkpoint: None,
+        })
+    }
+
+    async fn verify_state(&self, desired: &Value) -> Result<bool> {
+        let config: WireGuardConfig = serde_json::from_value(desired.clone())?;
+        Ok(Self::plan(&config).await?.is_empty())
+    }
+
+    /// Records configuration without keys; which peers had a preshared key
+    /// is kept so rollback can tell when one can't be restored
+    async fn create_checkpoint(&self) -> Result<Checkpoint> {
+        let mut interfaces = Vec::new();
+        let mut preshared = BTreeMap::new();
+        for link in wireguard_links().await? {
+            let device = read_device(&link.name).await?;
+            let mut iface = report(&link, &device);
+            for peer in &mut iface.peers {
+                peer.last_handshake = None;
+                peer.rx_bytes = None;
+                peer.tx_bytes = None;
+            }
+            let keyed: Vec<String> = device
+                .peers
+                .iter()
+                .filter(|p| p.preshared_key.is_some())
+                .filter_map(|p| p.public_key.map(|k| k.to_string()))
+                .collect();
+            preshared.insert(link.name.clone(), keyed);
+            interfaces.push(iface);
+        }
+
+        Ok(Checkpoint {
+            id: format!("wireguard-{}", chrono::Utc::now().timestamp()),
+            plugin: self.name().to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            state_snapshot: self.query_current_state().await?,
+            backend_checkpoint: Some(json!({
+                "interfaces": interfaces,
+                "preshared": preshared,
+            })),
+        })
+    }
+
+    /// Restores ports, marks and peers. Private keys stay as they are now, and
+    /// a removed interface can't come back since its key was never recorded.
+    async fn rollback(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let saved = checkpoint
+            .backend_checkpoint
+            .as_ref()
+            .ok_or_else(|| anyhow!("Checkpoint {} has no WireGuard state", checkpoint.id))?;
+        let interfaces: Vec<WgInterface> = serde_json::from_value(saved["interfaces"].clone())?;
+        let preshared: BTreeMap<String, Vec<String>> =
+            serde_json::from_value(saved["preshared"].clone()).unwrap_or_default();
+
+        for link in wireguard_links().await? {
+            if link.is_owned() && !interfaces.iter().any(|i| i.name == link.name) {
+                rtnetlink_links::delete_link(&link.name).await?;
+            }
+        }
+
+        for iface in &interfaces {
+            if rtnetlink_links::get_link(&iface.name).await?.is_none() {
+                log::warn!(
+                    "WireGuard interface {} was removed and can't be restored without its private key",
+                    iface.name
+                );
+                continue;
+            }
+            let current = read_device(&iface.name).await?;
+            let keyed = preshared.get(&iface.name).cloned().unwrap_or_default();
+
+            let mut peers = Vec::new();
+            for saved_peer in &iface.peers {
+                let public_key: Key = saved_peer.public_key.parse()?;
+                let now = current
+                    .peers
+                    .iter()
+                    .find(|p| p.public_key == Some(public_key));
+                let preshared_key = now.and_then(|p| p.preshared_key);
+                if preshared_key.is_none() && keyed.contains(&saved_peer.public_key) {
+                    log::warn!(
+                        "Peer {} on {} comes back without its preshared key",
+                        saved_peer.public_key,
+                        iface.name
+                    );
+                }
+                peers.push(Peer {
+                    public_key: Some(public_key),
+                    preshared_key,
+                    endpoint: saved_peer.endpoint.as_deref().map(str::parse).transpose()?,
+                    persistent_keepalive: saved_peer.persistent_keepalive.unwrap_or(0),
+                    allowed_ips: saved_peer
+                        .allowed_ips
+                        .iter()
+                        .map(|ip| ip.parse())
+                        .collect::<Result<_>>()?,
+                    ..Default::default()
+                });
+            }
+
+            let device = Device {
+                name: iface.name.clone(),
+                private_key: current.private_key,
+                public_key: None,
+                listen_port: iface.listen_port.unwrap_or(current.listen_port),
+                fwmark: iface.fwmark.unwrap_or(0),
+                peers,
+            };
+            let remove = stale_peers(&device, &current);
+            write_device(device, remove).await?;
+        }
+
+        log::info!(
+            "Restored {} WireGuard interfaces from checkpoint {}",
+            interfaces.len(),
+            checkpoint.id
+        );
+        Ok(())
+    }
+
+    fn capabilities(&self) -> PluginCapabilities {
+        PluginCapabilities {
+            supports_rollback: true,
+            supports_checkpoints: true,
+            supports_verification: true,
+            atomic_operations: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> Key {
+        Key([byte; 32])
+    }
+
+    fn peer(byte: u8, ips: &[&str]) -> Peer {
+        Peer {
+            public_key: Some(key(byte)),
+            allowed_ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_reads_secret_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("wg0.key");
+        std::fs::write(&key_file, format!("{}\n", key(1))).unwrap();
+
+        let iface: WgInterface = serde_json::from_value(json!({
+            "name": "wg0",
+            "private_key": format!("file:{}", key_file.display()),
+            "listen_port": 51820,
+            "peers": [{
+                "public_key": key(2).to_string(),
+                "endpoint": "192.0.2.1:51820",
+                "allowed_ips": ["10.8.0.7/24"],
+                "persistent_keepalive": 25
+            }]
+        }))
+        .unwrap();
+        let resolved = resolve(&iface).await.unwrap();
+        assert_eq!(resolved.private_key, Some(key(1)));
+        assert_eq!(resolved.peers[0].allowed_ips[0].to_string(), "10.8.0.0/24");
+
+        // A literal key is refused rather than stored in the state file
+        let mut literal = iface.clone();
+        literal.private_key = Some(key(1).to_string());
+        assert!(resolve(&literal).await.is_err());
+        let mut twice = iface.clone();
+        twice.peers.push(iface.peers[0].clone());
+        assert!(resolve(&twice).await.is_err());
+    }
+
+    #[test]
+    fn test_omitted_fields_keep_current_values() {
+        let current = Device {
+            name: "wg0".to_string(),
+            private_key: Some(key(1)),
+            public_key: Some(key(9)),
+            listen_port: 41999,
+            fwmark: 0x10,
+            peers: vec![Peer {
+                endpoint: Some("198.51.100.7:3456".parse().unwrap()),
+                rx_bytes: 1024,
+                last_handshake: Some(1_700_000_000),
+                ..peer(2, &["10.0.0.2/32", "10.0.1.0/24"])
+            }],
+        };
+        let resolved = Resolved {
+            name: "wg0".to_string(),
+            private_key: None,
+            listen_port: None,
+            fwmark: None,
+            peers: vec![peer(2, &["10.0.1.0/24", "10.0.0.2/32"])],
+        };
+        let desired = desired_device(&resolved, Some(&current));
+        assert!(in_sync(&desired, &current));
+        assert!(stale_peers(&desired, &current).is_empty());
+
+        let resolved = Resolved {
+            fwmark: Some(0),
+            peers: vec![peer(3, &["10.0.0.3/32"])],
+            ..resolved
+        };
+        let desired = desired_device(&resolved, Some(&current));
+        assert!(!in_sync(&desired, &current));
+        assert_eq!(stale_peers(&desired, &current), vec![key(2)]);
+    }
+
+    #[test]
+    fn test_report_never_contains_keys() {
+        let link = KernelLink {
+            index: 7,
+            name: "wg0".to_string(),
+            kind: Some(KIND.to_string()),
+            spec: None,
+            master: None,
+            alias: Some(rtnetlink_links::OWNER_ALIAS.to_string()),
+            mtu: Some(1420),
+            mac: None,
+            up: true,
+        };
+        let device = Device {
+            name: "wg0".to_string(),
+            private_key: Some(key(1)),
+            public_key: Some(key(9)),
+            listen_port: 51820,
+            fwmark: 0,
+            peers: vec![Peer {
+                preshared_key: Some(key(5)),
+                tx_bytes: 42,
+                ..peer(2, &["10.0.0.2/32"])
+            }],
+        };
+        let report = serde_json::to_string(&report(&link, &device)).unwrap();
+        assert!(!report.contains(&key(1).to_string()));
+        assert!(!report.contains(&key(5).to_string()));
+        assert!(report.contains(&key(9).to_string()));
+        assert!(report.contains("\"tx_bytes\":42"));
+        assert!(report.contains("\"owned\":true"));
+    }
+}
@@ -0,0 +1,81 @@
+//! Secret references - keep key material out of declared and reported state
+//!
+//! A reference names where a secret lives and is resolved only when it is
+//! needed:
+//! - `file:/etc/wireguard/wg0.key` - file contents, trailing whitespace trimmed
+//! - `env:WG0_KEY` - an environment variable of the daemon
+//! - `systemd-creds:wg0.key` - a credential passed to the unit with
+//!   LoadCredential=/LoadCredentialEncrypted=, read from $CREDENTIALS_DIRECTORY
+//!
+//! Plain values are rejected so a secret can't end up in a state file by accident.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::Path;
+
+/// Check that `reference` is well-formed without reading the secret
+pub fn validate(reference: &str) -> Result<()> {
+    let (scheme, target) = reference.split_once(':').ok_or_else(|| {
+        anyhow!("Secrets must be given as a reference (file:, env: or systemd-creds:)")
+    })?;
+    if target.is_empty() {
+        bail!("Secret reference '{}' names nothing", reference);
+    }
+    match scheme {
+        "file" if !Path::new(target).is_absolute() => {
+            bail!("Secret file '{}' must be an absolute path", target)
+        }
+        "systemd-creds" if target.contains('/') => {
+            bail!("Credential name '{}' must not contain '/'", target)
+        }
+        "file" | "env" | "systemd-creds" => Ok(()),
+        _ => bail!(
+            "Unknown secret reference scheme '{}' (expected file:, env: or systemd-creds:)",
+            scheme
+        ),
+    }
+}
+
+/// Read the secret a reference points to
+pub fn resolve(reference: &str) -> Result<String> {
+    validate(reference)?;
+    let (scheme, target) = reference.split_once(':').unwrap_or_default();
+    let value = match scheme {
+        "env" => std::env::var(target)
+            .with_context(|| format!("Secret variable {} is not set", target))?,
+        "systemd-creds" => {
+            let dir = std::env::var("CREDENTIALS_DIRECTORY").map_err(|_| {
+                anyhow!(
+                    "Credential {} requested but no $CREDENTIALS_DIRECTORY is set",
+                    target
+                )
+            })?;
+            std::fs::read_to_string(Path::new(&dir).join(target))
+                .with_context(|| format!("Failed to read credential {}", target))?
+        }
+        _ => std::fs::read_to_string(target)
+            .with_context(|| format!("Failed to read secret file {}", target))?,
+    };
+    Ok(value.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_references() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("wg0.key");
+        std::fs::write(&file, "secret\n").unwrap();
+        assert_eq!(
+            resolve(&format!("file:{}", file.display())).unwrap(),
+            "secret"
+        );
+
+        assert!(validate("c2VjcmV0").is_err());
+        assert!(validate("file:relative/key").is_err());
+        assert!(validate("vault:wg0").is_err());
+        assert!(validate("systemd-creds:../key").is_err());
+        assert!(resolve("env:OPDBUS_TEST_UNSET_SECRET").is_err());
+    }
+}
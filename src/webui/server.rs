@@ -3,13 +3,18 @@
 use anyhow::Result;
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
-    response::{Html, IntoResponse, Json},
+    http::{header, StatusCode},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        Html, IntoResponse, Json,
+    },
     routing::{delete, get, post},
     Router,
 };
+use futures::{Stream, StreamExt};
 use serde::Deserialize;
 use serde_json::Value;
+use std::convert::Infallible;
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 
@@ -53,9 +58,14 @@ pub async fn start_web_server(state_manager: Arc<StateManager>, config: WebConfi
         .route("/api/units", get(list_units))
         .route("/api/units/:name", get(get_unit))
         .route("/api/units/:name", post(apply_unit))
+        // Live network view (netlink monitor)
+        .route("/api/network/live", get(network_live))
+        .route("/api/network/events", get(network_events))
         // System-wide
         .route("/api/query", get(query_all))
         .route("/api/introspect", get(introspect_databases))
+        // Prometheus
+        .route("/metrics", get(metrics_handler))
         // UI
         .route("/", get(index_handler))
         .route("/containers", get(containers_page))
@@ -126,6 +136,36 @@ async fn query_all(State(state): State<AppState>) -> Result<Json<Value>, StatusC
     }
 }
 
+async fn metrics_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, crate::metrics::CONTENT_TYPE)],
+        crate::metrics::global().render(),
+    )
+}
+
+// Network handlers
+
+async fn network_live() -> Result<Json<Value>, StatusCode> {
+    let monitor = crate::native::rtnetlink_monitor::get().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let view = monitor.view().await;
+    serde_json::to_value(view)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Server-sent stream of netlink monitor events
+async fn network_events() -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let stream = crate::native::rtnetlink_monitor::events()
+        .await
+        .map(|event| {
+            Ok(SseEvent::default()
+                .event(event.event_type())
+                .json_data(event.to_json())
+                .unwrap_or_default())
+        });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
 // Container (PlugTree) handlers
 
 async fn list_containers(State(_state): State<AppState>) -> impl IntoResponse {